@@ -0,0 +1,146 @@
+//! Component for a Bluetooth Low Energy peripheral with a GATT server.
+//!
+//! This provides one Component, BleGattComponent, which creates the link
+//! layer, L2CAP and the GATT server on top of a BLE radio, and the syscall
+//! driver through which apps populate the GATT table and advertise.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let gatt = components::ble_gatt::BleGattComponent::new(
+//!     board_kernel,
+//!     capsules::ble::gatt_driver::DRIVER_NUM,
+//!     &base_peripherals.ble_radio,
+//!     mux_alarm,
+//!     [0x11, 0x22, 0x33, 0x44, 0x55, 0xc6],
+//! )
+//! .finalize(components::ble_gatt_component_helper!(
+//!     nrf52::ble_radio::Radio<'static>,
+//!     nrf52::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::ble::gatt::GattServer;
+use capsules::ble::gatt_driver::GattDriver;
+use capsules::ble::l2cap::{AttChannel, L2cap};
+use capsules::ble::link_layer::{AclLink, LinkLayer, ADDRESS_LEN};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ble_advertising::BleConnectionDriver;
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+/// Storage of the attribute values.
+static mut VALUES: [u8; 1024] = [0; 1024];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ble_gatt_component_helper {
+    ($R:ty, $A:ty $(,)?) => {{
+        use capsules::ble::gatt::GattServer;
+        use capsules::ble::gatt_driver::GattDriver;
+        use capsules::ble::l2cap::L2cap;
+        use capsules::ble::link_layer::LinkLayer;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<LinkLayer<'static, $R, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<L2cap<'static>> = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<GattServer<'static>> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<GattDriver<'static>> = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5)
+    };};
+}
+
+pub struct BleGattComponent<
+    R: 'static + BleConnectionDriver<'static>,
+    A: 'static + time::Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static R,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    address: [u8; ADDRESS_LEN],
+}
+
+impl<R: 'static + BleConnectionDriver<'static>, A: 'static + time::Alarm<'static>>
+    BleGattComponent<R, A>
+{
+    /// `address` is the random static device address, least significant
+    /// byte first. Its two most significant bits must be set.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static R,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        address: [u8; ADDRESS_LEN],
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            radio,
+            mux_alarm,
+            address,
+        }
+    }
+}
+
+impl<R: 'static + BleConnectionDriver<'static>, A: 'static + time::Alarm<'static>> Component
+    for BleGattComponent<R, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<LinkLayer<'static, R, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<L2cap<'static>>,
+        &'static mut MaybeUninit<GattServer<'static>>,
+        &'static mut MaybeUninit<GattDriver<'static>>,
+    );
+    type Output = &'static GattDriver<'static>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_init_half!(
+            s.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        virtual_alarm.setup();
+
+        let link_layer = static_init_half!(
+            s.1,
+            LinkLayer<'static, R, VirtualMuxAlarm<'static, A>>,
+            LinkLayer::new(self.radio, virtual_alarm, self.address)
+        );
+        self.radio.set_connection_client(link_layer);
+        virtual_alarm.set_alarm_client(link_layer);
+
+        let l2cap = static_init_half!(s.2, L2cap<'static>, L2cap::new(link_layer));
+        link_layer.set_client(l2cap);
+
+        let server = static_init_half!(
+            s.3,
+            GattServer<'static>,
+            GattServer::new(l2cap, &mut VALUES)
+        );
+        l2cap.set_client(server);
+
+        let gatt_driver = static_init_half!(
+            s.4,
+            GattDriver<'static>,
+            GattDriver::new(
+                server,
+                link_layer,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        server.set_client(gatt_driver);
+
+        gatt_driver
+    }
+}
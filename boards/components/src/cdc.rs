@@ -19,6 +19,13 @@
 //!     STRINGS)
 //! .finalize(components::usb_cdc_acm_component_helper!(nrf52::usbd::Usbd));
 //! ```
+//!
+//! The returned port implements `uart::ControlLines`, which gives the baud
+//! rate and the DTR and RTS lines set by the host.
+//!
+//! For several ports on one device, create each port on its own function of a
+//! composite device (see `usb_composite`), and pass that function as `usb`.
+//! Each call of the helper macro allocates a separate port.
 
 use core::mem::MaybeUninit;
 
@@ -0,0 +1,90 @@
+//! Component for a USB network adapter (CDC-NCM).
+//!
+//! This provides one Component, CdcNcmComponent, which creates the CDC-NCM
+//! class driver. It is an `EthernetAdapter`, on which `EthernetComponent`
+//! builds the IPv6/UDP stack.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 4] = &[
+//!     "XYZ Corp.",     // Manufacturer
+//!     "Tock Network",  // Product
+//!     "Serial No. 5",  // Serial number
+//!     "020000000002",  // MAC address of the host
+//! ];
+//! let ncm = components::cdc_ncm::CdcNcmComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     0x1209,
+//!     0x0003,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_cdc_ncm_component_helper!(nrf52::usbd::Usbd));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::cdc_ncm::{CdcNcm, NTB_BUF_LEN};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+static mut TX_NTB: [u8; NTB_BUF_LEN] = [0; NTB_BUF_LEN];
+static mut RX_NTB: [u8; NTB_BUF_LEN] = [0; NTB_BUF_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_cdc_ncm_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<capsules::usb::cdc_ncm::CdcNcm<'static, $U>> =
+            MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct CdcNcmComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 4],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> CdcNcmComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 4],
+    ) -> Self {
+        Self {
+            usb,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for CdcNcmComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<CdcNcm<'static, U>>;
+    type Output = &'static CdcNcm<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let ncm = static_init_half!(
+            s,
+            CdcNcm<'static, U>,
+            CdcNcm::new(
+                self.usb,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                &mut TX_NTB,
+                &mut RX_NTB,
+            )
+        );
+        self.usb.set_client(ncm);
+
+        ncm
+    }
+}
@@ -3,6 +3,9 @@
 //! This provides a component for using the CTAP driver. This allows for
 //! Client to Authenticator Protool Authentication
 //!
+//! The CTAPHID transport runs in the kernel: apps receive complete
+//! `CTAPHID_MSG` and `CTAPHID_CBOR` requests.
+//!
 //! Usage
 //! -----
 //! ```rust
@@ -15,45 +18,69 @@
 //!     let ctap_recv_buffer = static_init!([u8; 64], [0; 64]);
 //!
 //!     let (ctap, ctap_driver) = components::ctap::CtapComponent::new(
+//!         board_kernel,
+//!         capsules::ctap::DRIVER_NUM,
 //!         &earlgrey::usbdev::USB,
+//!         mux_alarm,
 //!         0x1337, // My important company
 //!         0x0DEC, // My device name
 //!         strings,
-//!         board_kernel,
 //!         ctap_send_buffer,
 //!         ctap_recv_buffer,
 //!     )
-//!     .finalize(components::usb_ctap_component_helper!(lowrisc::usbdev::Usb));
+//!     .finalize(components::usb_ctap_component_helper!(
+//!         lowrisc::usbdev::Usb,
+//!         earlgrey::timer::RvTimer
+//!     ));
 //!
 //!     ctap.enable();
 //!     ctap.attach();
 //! ```
 
 use core::mem::MaybeUninit;
+
+use capsules::ctap::{CtapDriver, CtapHidTransport};
+use capsules::usb::ctap::CtapHid;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil;
+use kernel::hil::time::{self, Alarm};
 use kernel::static_init_half;
 
+/// Storage of the CTAPHID messages, whose maximum length is 1024 bytes in
+/// CTAP2.
+static mut MESSAGE: [u8; 1024] = [0; 1024];
+
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! usb_ctap_component_helper {
-    ($U:ty $(,)?) => {{
+    ($U:ty, $A:ty $(,)?) => {{
+        use capsules::ctap::{CtapDriver, CtapHidTransport};
+        use capsules::usb::ctap::CtapHid;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
         use core::mem::MaybeUninit;
-        static mut BUF1: MaybeUninit<capsules::usb::ctap::CtapHid<'static, $U>> =
-            MaybeUninit::uninit();
-        static mut BUF2: MaybeUninit<
-            capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, $U>>,
+        static mut BUF1: MaybeUninit<CtapHid<'static, $U>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            CtapHidTransport<'static, CtapHid<'static, $U>, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            CtapDriver<'static, CtapHid<'static, $U>, VirtualMuxAlarm<'static, $A>>,
         > = MaybeUninit::uninit();
-        (&mut BUF1, &mut BUF2)
+        (&mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4)
     };};
 }
 
-pub struct CtapComponent<U: 'static + hil::usb::UsbController<'static>> {
+pub struct CtapComponent<
+    U: 'static + hil::usb::UsbController<'static>,
+    A: 'static + time::Alarm<'static>,
+> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
     usb: &'static U,
+    mux_alarm: &'static MuxAlarm<'static, A>,
     vendor_id: u16,
     product_id: u16,
     strings: &'static [&'static str; 3],
@@ -61,21 +88,25 @@ pub struct CtapComponent<U: 'static + hil::usb::UsbController<'static>> {
     recv_buffer: &'static mut [u8; 64],
 }
 
-impl<U: 'static + hil::usb::UsbController<'static>> CtapComponent<U> {
+impl<U: 'static + hil::usb::UsbController<'static>, A: 'static + time::Alarm<'static>>
+    CtapComponent<U, A>
+{
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
         usb: &'static U,
+        mux_alarm: &'static MuxAlarm<'static, A>,
         vendor_id: u16,
         product_id: u16,
         strings: &'static [&'static str; 3],
         send_buffer: &'static mut [u8; 64],
         recv_buffer: &'static mut [u8; 64],
-    ) -> CtapComponent<U> {
+    ) -> CtapComponent<U, A> {
         CtapComponent {
             board_kernel,
             driver_num,
             usb,
+            mux_alarm,
             vendor_id,
             product_id,
             strings,
@@ -85,45 +116,64 @@ impl<U: 'static + hil::usb::UsbController<'static>> CtapComponent<U> {
     }
 }
 
-impl<U: 'static + hil::usb::UsbController<'static>> Component for CtapComponent<U> {
+impl<U: 'static + hil::usb::UsbController<'static>, A: 'static + time::Alarm<'static>> Component
+    for CtapComponent<U, A>
+{
     type StaticInput = (
-        &'static mut MaybeUninit<capsules::usb::ctap::CtapHid<'static, U>>,
+        &'static mut MaybeUninit<CtapHid<'static, U>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
         &'static mut MaybeUninit<
-            capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, U>>,
+            CtapHidTransport<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<
+            CtapDriver<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
         >,
     );
     type Output = (
-        &'static capsules::usb::ctap::CtapHid<'static, U>,
-        &'static capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, U>>,
+        &'static CtapHid<'static, U>,
+        &'static CtapDriver<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
     );
 
     unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
         let ctap = static_init_half!(
             s.0,
-            capsules::usb::ctap::CtapHid<'static, U>,
-            capsules::usb::ctap::CtapHid::new(
-                self.usb,
-                self.vendor_id,
-                self.product_id,
-                self.strings
-            )
+            CtapHid<'static, U>,
+            CtapHid::new(self.usb, self.vendor_id, self.product_id, self.strings)
         );
         self.usb.set_client(ctap);
 
-        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
-
-        let ctap_driver = static_init_half!(
+        let virtual_alarm = static_init_half!(
             s.1,
-            capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, U>>,
-            capsules::ctap::CtapDriver::new(
-                Some(ctap),
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        virtual_alarm.setup();
+
+        let transport = static_init_half!(
+            s.2,
+            CtapHidTransport<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
+            CtapHidTransport::new(
+                ctap,
+                virtual_alarm,
                 self.send_buffer,
                 self.recv_buffer,
-                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+                &mut MESSAGE,
             )
         );
+        ctap.set_client(transport);
+        virtual_alarm.set_alarm_client(transport);
+
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
 
-        ctap.set_client(ctap_driver);
+        let ctap_driver = static_init_half!(
+            s.3,
+            CtapDriver<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
+            CtapDriver::new(
+                transport,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        transport.set_client(ctap_driver);
 
         (ctap, ctap_driver)
     }
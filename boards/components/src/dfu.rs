@@ -0,0 +1,122 @@
+//! Component for USB DFU support.
+//!
+//! This provides a component for using the Device Firmware Upgrade class
+//! driver, which lets `dfu-util` replace the apps of a board, and optionally
+//! write a new kernel image to a staging area for the bootloader.
+//!
+//! The storage should address the flash directly, e.g. a
+//! `NonvolatileToPages` on top of the flash controller, and the targets give
+//! the address and size of the regions within it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 4] = &[
+//!     "XYZ Corp.",      // Manufacturer
+//!     "Tock Board",     // Product
+//!     "serial0001",     // Serial number
+//!     "Apps",           // Name of alternate setting 0
+//! ];
+//! let apps = capsules::usb::dfu::DfuTarget {
+//!     storage: flash_storage,
+//!     start: &_sapps as *const u8 as usize,
+//!     size: &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+//! };
+//! let dfu = components::dfu::DfuComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     0x1209,
+//!     0x0001,
+//!     STRINGS,
+//!     apps,
+//!     None, // No kernel staging area
+//!     Some(&|| unsafe { cortexm4::scb::reset() }),
+//! )
+//! .finalize(components::usb_dfu_component_helper!(nrf52::usbd::Usbd));
+//!
+//! dfu.enable();
+//! dfu.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::dfu::{Dfu, DfuTarget};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+const BUFFER_LEN: usize = 512;
+
+static mut BUFFER: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+static mut FIRST_BLOCK: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_dfu_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<capsules::usb::dfu::Dfu<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct DfuComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str],
+    apps: DfuTarget<'static>,
+    staging: Option<DfuTarget<'static>>,
+    reset_function: Option<&'static (dyn Fn() + 'static)>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> DfuComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str],
+        apps: DfuTarget<'static>,
+        staging: Option<DfuTarget<'static>>,
+        reset_function: Option<&'static (dyn Fn() + 'static)>,
+    ) -> Self {
+        Self {
+            usb,
+            vendor_id,
+            product_id,
+            strings,
+            apps,
+            staging,
+            reset_function,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for DfuComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<Dfu<'static, U>>;
+    type Output = &'static Dfu<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let dfu = static_init_half!(
+            s,
+            Dfu<'static, U>,
+            Dfu::new(
+                self.usb,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.apps,
+                self.staging,
+                self.reset_function,
+                &mut BUFFER,
+                &mut FIRST_BLOCK,
+            )
+        );
+        self.usb.set_client(dfu);
+        self.apps.storage.set_client(dfu);
+        if let Some(staging) = self.staging {
+            staging.storage.set_client(dfu);
+        }
+
+        dfu
+    }
+}
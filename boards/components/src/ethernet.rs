@@ -0,0 +1,287 @@
+//! Component to initialize the IPv6/UDP stack over an Ethernet adapter.
+//!
+//! This provides one Component, EthernetComponent, which creates an
+//! `EthernetInterface` on an `EthernetAdapter` and builds on it the same
+//! stack as `UDPMuxComponent` does on 6LoWPAN: a MuxUdpSender and
+//! MuxUdpReceiver for UDP users (e.g. the userspace UDP driver, with
+//! `udp_driver_component_helper!(@sender EthernetInterface<'static>)`), an
+//! ICMPv6 Echo responder, and Neighbor Discovery, which configures the
+//! addresses of the interface and fills the neighbor cache used to pick the
+//! destination MAC address of outgoing frames.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (udp_send_mux, udp_recv_mux, port_table, ip_send_mux, icmp_recv_mux, eth, ip_receive) =
+//!        EthernetComponent::new(
+//!            ethmac0,
+//!            EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]),
+//!            local_ip_ifaces,
+//!            mux_alarm,
+//!        )
+//!        .finalize(components::ethernet_component_helper!(Alarm));
+//! ```
+
+use capsules::net::ethernet::frame::HEADER_LEN;
+use capsules::net::ethernet::{EthernetAddress, EthernetInterface};
+use capsules::net::icmpv6::icmpv6_echo::ICMP6EchoResponder;
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendUser, IP6Sender, MuxIP6Sender};
+use capsules::net::ipv6::nd::{NeighborDiscovery, ND_BUF_LEN};
+use capsules::net::ipv6::neighbor_cache::{NeighborCache, NeighborEntry};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_send::MuxUdpSender;
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ethernet::EthernetAdapter;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+// The stack requires several packet buffers:
+//
+//   1. TX_BUF: holds the frame being sent by the adapter.
+//   2. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   3. UDP_QUEUE_BUF / ECHO_QUEUE_BUF / ND_QUEUE_BUF: Hold the payload of a UDP, ICMPv6 Echo Reply
+//      or Neighbor Discovery packet while the IP sender is busy sending another packet.
+//   4. ECHO_BUF / ND_BUF: Hold the data of the ICMPv6 Echo Reply or NDP message being sent.
+//
+// Received frames are read directly from the adapter.
+//
+// Payloads are limited to the same length as on 6LoWPAN, so that the
+// userspace UDP driver can be used unchanged.
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+const IP_HDR_LEN: usize = 40;
+const TX_BUF_LEN: usize = HEADER_LEN + IP_HDR_LEN + MAX_PAYLOAD_LEN;
+
+static mut TX_BUF: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut UDP_QUEUE_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ECHO_QUEUE_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ECHO_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ND_QUEUE_BUF: [u8; ND_BUF_LEN] = [0; ND_BUF_LEN];
+static mut ND_BUF: [u8; ND_BUF_LEN] = [0; ND_BUF_LEN];
+
+const NEIGHBOR_CACHE_SIZE: usize = 8;
+static mut NEIGHBOR_CACHE_ENTRIES: [Option<NeighborEntry<EthernetAddress>>; NEIGHBOR_CACHE_SIZE] =
+    [None; NEIGHBOR_CACHE_SIZE];
+
+static mut USED_KERNEL_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
+    [None; MAX_NUM_BOUND_PORTS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ethernet_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::ethernet::EthernetAddress;
+        use capsules::net::ipv6::nd::NeighborDiscovery;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, $A>, EthernetAddress>,
+        > = MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct EthernetComponent<A: Alarm<'static> + 'static> {
+    adapter: &'static dyn EthernetAdapter<'static>,
+    mac_addr: EthernetAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> EthernetComponent<A> {
+    pub fn new(
+        adapter: &'static dyn EthernetAdapter<'static>,
+        mac_addr: EthernetAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            adapter,
+            mac_addr,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for EthernetComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>, EthernetAddress>,
+        >,
+    );
+    type Output = (
+        &'static MuxUdpSender<'static, EthernetInterface<'static>>,
+        &'static MuxUdpReceiver<'static>,
+        &'static UdpPortManager,
+        &'static MuxIP6Sender<'static>,
+        &'static MuxICMP6Receiver<'static>,
+        &'static EthernetInterface<'static>,
+        &'static IP6RecvStruct<'static>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::UDP(UDPHeader::new()),
+            payload: &mut UDP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let neighbor_cache = static_init!(
+            NeighborCache<EthernetAddress>,
+            NeighborCache::new(&mut NEIGHBOR_CACHE_ENTRIES)
+        );
+        let eth = static_init!(
+            EthernetInterface<'static>,
+            EthernetInterface::new(
+                self.adapter,
+                self.mac_addr,
+                ip6_dg,
+                &mut TX_BUF,
+                neighbor_cache,
+                ip_vis,
+            )
+        );
+        self.adapter.set_client(eth);
+
+        // UDP and ICMPv6 share the interface
+        let ip_send_mux = static_init!(MuxIP6Sender<'static>, MuxIP6Sender::new(eth));
+        eth.set_client(ip_send_mux);
+        // Neighbor Discovery replaces the source address once it has
+        // configured an address
+        ip_send_mux.set_addr(self.interface_list[0]);
+        let udp_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(ip_send_mux, &mut UDP_QUEUE_BUF)
+        );
+        udp_ip_send.setup();
+
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+        eth.set_rx_client(ip_receive);
+        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+        ip_receive.set_client(udp_recv_mux);
+        let icmp_recv_mux = static_init!(MuxICMP6Receiver<'static>, MuxICMP6Receiver::new());
+        ip_receive.set_icmp_client(icmp_recv_mux);
+
+        // Reply to ICMPv6 Echo Requests addressed to this node
+        let echo_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(ip_send_mux, &mut ECHO_QUEUE_BUF)
+        );
+        echo_ip_send.setup();
+        let echo_icmp_send = static_init!(
+            ICMP6SendStruct<'static, IP6SendUser<'static>>,
+            ICMP6SendStruct::new(echo_ip_send)
+        );
+        echo_ip_send.set_client(echo_icmp_send);
+        let echo_net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Any,
+                PortRange::NoPorts,
+                PortRange::NoPorts,
+                &create_cap
+            )
+        );
+        let echo_responder = static_init!(
+            ICMP6EchoResponder<'static>,
+            ICMP6EchoResponder::new(
+                echo_icmp_send,
+                self.interface_list,
+                &mut ECHO_BUF,
+                echo_net_cap
+            )
+        );
+        echo_icmp_send.set_client(echo_responder);
+        let echo_rcvr = static_init!(ICMP6Receiver<'static>, ICMP6Receiver::new());
+        echo_rcvr.set_client(echo_responder);
+        icmp_recv_mux.add_client(echo_rcvr);
+
+        // Neighbor Discovery
+        let nd_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        nd_virtual_alarm.setup();
+        let nd_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(ip_send_mux, &mut ND_QUEUE_BUF)
+        );
+        nd_ip_send.setup();
+        let nd_icmp_send = static_init!(
+            ICMP6SendStruct<'static, IP6SendUser<'static>>,
+            ICMP6SendStruct::new(nd_ip_send)
+        );
+        nd_ip_send.set_client(nd_icmp_send);
+        let nd = static_init_half!(
+            static_buffer.1,
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>, EthernetAddress>,
+            NeighborDiscovery::new(
+                nd_icmp_send,
+                nd_ip_send,
+                ip_send_mux,
+                neighbor_cache,
+                nd_virtual_alarm,
+                self.mac_addr,
+                &mut ND_BUF,
+                echo_net_cap
+            )
+        );
+        nd_icmp_send.set_client(nd);
+        nd_virtual_alarm.set_alarm_client(nd);
+        let nd_rcvr = static_init!(ICMP6Receiver<'static>, ICMP6Receiver::new());
+        nd_rcvr.set_client(nd);
+        icmp_recv_mux.add_client(nd_rcvr);
+        nd.start();
+
+        let udp_send_mux = static_init!(
+            MuxUdpSender<'static, EthernetInterface<'static>>,
+            MuxUdpSender::new(udp_ip_send)
+        );
+        udp_ip_send.set_client(udp_send_mux);
+
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let udp_port_table = static_init!(
+            UdpPortManager,
+            UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
+        );
+
+        (
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            ip_send_mux,
+            icmp_recv_mux,
+            eth,
+            ip_receive,
+        )
+    }
+}
@@ -0,0 +1,127 @@
+//! Component for an IEEE 802.15.4 packet sniffer.
+//!
+//! This provides one Component, Ieee802154SnifferComponent, which captures
+//! every frame received on an 802.15.4 channel and streams it over a UART as
+//! a PCAP capture. On the host, `tools/sniffer-pcap.py` turns the stream into
+//! a file or pipes it to Wireshark.
+//!
+//! The sniffer becomes the receive client of the radio, so it replaces the
+//! 802.15.4 stack of `Ieee802154Component` and must not be used together
+//! with it. The UART should be dedicated to the sniffer, e.g. a second UART
+//! of the board, as console output would corrupt the capture.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let sniffer = components::ieee802154_sniffer::Ieee802154SnifferComponent::new(
+//!        &nrf52840::ieee802154_radio::RADIO,
+//!        uart_mux,
+//!        mux_alarm,
+//!        26,
+//!    )
+//!    .finalize(components::ieee802154_sniffer_component_helper!(
+//!        nrf52840::ieee802154_radio::Radio,
+//!        nrf52840::rtc::Rtc<'static>,
+//!    ));
+//! ```
+
+use capsules::ieee802154::sniffer::{Sniffer, RECORD_HEADER_LEN, TAP_HEADER_LEN};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::radio::{self, Radio, RadioMonitor};
+use kernel::hil::time::{self, Alarm};
+use kernel::{static_init, static_init_half};
+
+// Room for 8 frames of the maximum size
+const QUEUE_LEN: usize = 8 * (RECORD_HEADER_LEN + TAP_HEADER_LEN + radio::MAX_FRAME_SIZE);
+const TX_LEN: usize = 256;
+
+static mut QUEUE_BUF: [u8; QUEUE_LEN] = [0; QUEUE_LEN];
+static mut TX_BUF: [u8; TX_LEN] = [0; TX_LEN];
+static mut RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0; radio::MAX_BUF_SIZE];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ieee802154_sniffer_component_helper {
+    ($R:ty, $A:ty $(,)?) => {{
+        use capsules::ieee802154::sniffer::Sniffer;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<Sniffer<'static, $R, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct Ieee802154SnifferComponent<
+    R: 'static + Radio + RadioMonitor,
+    A: 'static + Alarm<'static>,
+> {
+    radio: &'static R,
+    uart_mux: &'static MuxUart<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    channel: u8,
+}
+
+impl<R: 'static + Radio + RadioMonitor, A: 'static + Alarm<'static>>
+    Ieee802154SnifferComponent<R, A>
+{
+    pub fn new(
+        radio: &'static R,
+        uart_mux: &'static MuxUart<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        channel: u8,
+    ) -> Self {
+        Self {
+            radio,
+            uart_mux,
+            alarm_mux,
+            channel,
+        }
+    }
+}
+
+impl<R: 'static + Radio + RadioMonitor, A: 'static + Alarm<'static>> Component
+    for Ieee802154SnifferComponent<R, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Sniffer<'static, R, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Sniffer<'static, R, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let sniffer_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, false));
+        sniffer_uart.setup();
+
+        let sniffer_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        sniffer_alarm.setup();
+
+        let sniffer = static_init_half!(
+            static_buffer.1,
+            Sniffer<'static, R, VirtualMuxAlarm<'static, A>>,
+            Sniffer::new(
+                self.radio,
+                sniffer_uart,
+                sniffer_alarm,
+                &mut QUEUE_BUF,
+                &mut TX_BUF,
+            )
+        );
+        hil::uart::Transmit::set_transmit_client(sniffer_uart, sniffer);
+        time::Alarm::set_alarm_client(sniffer_alarm, sniffer);
+        self.radio.set_receive_client(sniffer, &mut RX_BUF);
+
+        sniffer.start(self.channel).unwrap(); // Unwrap fail = invalid channel
+
+        sniffer
+    }
+}
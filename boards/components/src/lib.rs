@@ -5,15 +5,20 @@ pub mod adc_microphone;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod app_flash_driver;
+pub mod ble_gatt;
 pub mod bus;
 pub mod button;
 pub mod cdc;
+pub mod cdc_ncm;
+pub mod coap;
 pub mod console;
 pub mod crc;
 pub mod ctap;
 pub mod debug_queue;
 pub mod debug_writer;
+pub mod dfu;
 pub mod digest;
+pub mod ethernet;
 pub mod flash;
 pub mod ft6x06;
 pub mod fxos8700;
@@ -24,6 +29,7 @@ pub mod hts221;
 pub mod humidity;
 pub mod i2c;
 pub mod ieee802154;
+pub mod ieee802154_sniffer;
 pub mod isl29035;
 pub mod l3gd20;
 pub mod led;
@@ -33,17 +39,21 @@ pub mod lsm303agr;
 pub mod lsm303dlhc;
 pub mod lsm6dsox;
 pub mod mlx90614;
+pub mod msc;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
+pub mod ping;
 pub mod process_console;
 pub mod process_printer;
 pub mod rng;
+pub mod rpl;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod serial_tunnel;
 pub mod sha;
 pub mod sht3x;
 pub mod si7021;
@@ -55,7 +65,10 @@ pub mod temperature_rp2040;
 pub mod temperature_stm;
 pub mod test;
 pub mod text_screen;
+pub mod thread_mle;
 pub mod tickv;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
+pub mod usb_composite;
+pub mod usb_hid;
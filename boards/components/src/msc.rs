@@ -0,0 +1,130 @@
+//! Component for USB Mass Storage support.
+//!
+//! This provides a component for using the Mass Storage Class driver. This
+//! lets a host access a `NonvolatileStorage` device as a USB disk, e.g. to
+//! copy logs off a board.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Data Logger",  // Product
+//!     "0123456789AB", // Serial number, at least 12 hex digits
+//! ];
+//! let msc = components::msc::MassStorageComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005b,
+//!     STRINGS,
+//!     flash_storage,
+//!     2048, // Number of 512 byte blocks
+//! )
+//! .finalize(components::usb_msc_component_helper!(nrf52::usbd::Usbd));
+//!
+//! msc.enable();
+//! msc.attach();
+//! ```
+//!
+//! To serve an SD card, put a `capsules::usb::msc::SDCardStorage` between the
+//! card and the disk, which tells the disk when a card is inserted:
+//!
+//! ```rust
+//! let sd_storage = static_init!(
+//!     capsules::usb::msc::SDCardStorage<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::usb::msc::SDCardStorage::new(sdcard)
+//! );
+//! sdcard.set_client(sd_storage);
+//! let msc = components::msc::MassStorageComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005b,
+//!     STRINGS,
+//!     sd_storage,
+//!     0, // No medium until the card is initialized
+//! )
+//! .finalize(components::usb_msc_component_helper!(nrf52::usbd::Usbd));
+//! sd_storage.set_medium_client(msc);
+//! sd_storage.start();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::msc::{MassStorage, BLOCK_SIZE};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::static_init_half;
+
+static mut BLOCK_BUF: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_msc_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<capsules::usb::msc::MassStorage<'static, $U>> =
+            MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct MassStorageComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    storage: &'static dyn NonvolatileStorage<'static>,
+    num_blocks: u32,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> MassStorageComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'static dyn NonvolatileStorage<'static>,
+        num_blocks: u32,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            storage,
+            num_blocks,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for MassStorageComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<MassStorage<'static, U>>;
+    type Output = &'static MassStorage<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let msc = static_init_half!(
+            s,
+            MassStorage<'static, U>,
+            MassStorage::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.storage,
+                &mut BLOCK_BUF,
+                self.num_blocks,
+            )
+        );
+        self.usb.set_client(msc);
+        self.storage.set_client(msc);
+
+        msc
+    }
+}
@@ -0,0 +1,138 @@
+//! Component to initialize the userland ping (ICMPv6 echo) driver.
+//!
+//! This provides one Component, PingComponent, which sends Echo Requests
+//! through the IPv6 send mux and receives Echo Replies from the ICMPv6
+//! receive mux exposed by UDPMuxComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let ping_driver = PingComponent::new(
+//!        board_kernel,
+//!        capsules::net::icmpv6::DRIVER_NUM,
+//!        ip_send_mux,
+//!        icmp_recv_mux,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::ping_component_helper!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules;
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
+use capsules::net::icmpv6::PingDriver;
+use capsules::net::ipv6::ipv6_send::{IP6SendUser, IP6Sender, MuxIP6Sender};
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::time::{self, Alarm};
+use kernel::{create_capability, static_init, static_init_half};
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+static mut QUEUE_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut DRIVER_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ping_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::icmpv6::PingDriver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<PingDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct PingComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    ip_send_mux: &'static MuxIP6Sender<'static>,
+    icmp_recv_mux: &'static MuxICMP6Receiver<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static>> PingComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        ip_send_mux: &'static MuxIP6Sender<'static>,
+        icmp_recv_mux: &'static MuxICMP6Receiver<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            ip_send_mux,
+            icmp_recv_mux,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for PingComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<PingDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static PingDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let ping_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        ping_alarm.setup();
+
+        let ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(self.ip_send_mux, &mut QUEUE_BUF)
+        );
+        ip_send.setup();
+        let icmp_send = static_init!(
+            ICMP6SendStruct<'static, IP6SendUser<'static>>,
+            ICMP6SendStruct::new(ip_send)
+        );
+        ip_send.set_client(icmp_send);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Any,
+                PortRange::NoPorts,
+                PortRange::NoPorts,
+                &create_cap
+            )
+        );
+
+        let ping_driver = static_init_half!(
+            static_buffer.1,
+            PingDriver<'static, VirtualMuxAlarm<'static, A>>,
+            PingDriver::new(
+                icmp_send,
+                ping_alarm,
+                &mut DRIVER_BUF,
+                net_cap,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        icmp_send.set_client(ping_driver);
+        time::Alarm::set_alarm_client(ping_alarm, ping_driver);
+
+        let ping_rcvr = static_init!(ICMP6Receiver<'static>, ICMP6Receiver::new());
+        ping_rcvr.set_client(ping_driver);
+        self.icmp_recv_mux.add_client(ping_rcvr);
+
+        ping_driver
+    }
+}
@@ -0,0 +1,187 @@
+//! Component to initialize RPL routing on the 6LoWPAN interface.
+//!
+//! This provides one Component, RplComponent, which attaches an RPL router
+//! to the IPv6 sender and receiver exposed by UDPMuxComponent, so that
+//! packets to destinations beyond the local link are routed through the
+//! DODAG, and received packets addressed to other nodes are forwarded.
+//!
+//! The addresses configured by Neighbor Discovery are local to the node in
+//! addition to `interface_list`, so packets for them are not forwarded.
+//!
+//! Pass `Some(dodag_id)` as `root` to make the board the root of a DODAG
+//! (e.g. a border router); `dodag_id` must be a global address of the
+//! board. Otherwise the board joins a DODAG it hears DIOs from.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let rpl = RplComponent::new(
+//!        ip_send,
+//!        ip_receive,
+//!        ip_send_mux,
+//!        icmp_recv_mux,
+//!        mux_alarm,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        nd,
+//!        None,
+//!    )
+//!    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules;
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6SendUser, IP6Sender, MuxIP6Sender};
+use capsules::net::ipv6::nd::InterfaceAddresses;
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::rpl::rpl::{Route, RPL_BUF_LEN};
+use capsules::net::rpl::Rpl;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::time::{self, Alarm};
+use kernel::{create_capability, static_init, static_init_half};
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+const NUM_ROUTES: usize = 16;
+
+static mut ROUTES: [Option<Route>; NUM_ROUTES] = [None; NUM_ROUTES];
+static mut CTRL_QUEUE_BUF: [u8; RPL_BUF_LEN] = [0; RPL_BUF_LEN];
+static mut CTRL_BUF: [u8; RPL_BUF_LEN] = [0; RPL_BUF_LEN];
+static mut FORWARD_QUEUE_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut FORWARD_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! rpl_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::rpl::Rpl;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<Rpl<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct RplComponent<A: Alarm<'static> + 'static> {
+    ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    ip_send_mux: &'static MuxIP6Sender<'static>,
+    icmp_recv_mux: &'static MuxICMP6Receiver<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    interface_addresses: &'static dyn InterfaceAddresses,
+    root: Option<IPAddr>,
+}
+
+impl<A: Alarm<'static>> RplComponent<A> {
+    pub fn new(
+        ip_send: &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        ip_send_mux: &'static MuxIP6Sender<'static>,
+        icmp_recv_mux: &'static MuxICMP6Receiver<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        interface_addresses: &'static dyn InterfaceAddresses,
+        root: Option<IPAddr>,
+    ) -> Self {
+        Self {
+            ip_send,
+            ip_receive,
+            ip_send_mux,
+            icmp_recv_mux,
+            alarm_mux,
+            src_mac_addr,
+            interface_list,
+            interface_addresses,
+            root,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for RplComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Rpl<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Rpl<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let rpl_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        rpl_alarm.setup();
+
+        // Control messages are sent from the link-local address
+        let ctrl_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(self.ip_send_mux, &mut CTRL_QUEUE_BUF)
+        );
+        ctrl_ip_send.setup();
+        ctrl_ip_send.set_addr(IPAddr::generate_from_mac(self.src_mac_addr));
+        let ctrl_icmp_send = static_init!(
+            ICMP6SendStruct<'static, IP6SendUser<'static>>,
+            ICMP6SendStruct::new(ctrl_ip_send)
+        );
+        ctrl_ip_send.set_client(ctrl_icmp_send);
+
+        let forward_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(self.ip_send_mux, &mut FORWARD_QUEUE_BUF)
+        );
+        forward_ip_send.setup();
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let rpl = static_init_half!(
+            static_buffer.1,
+            Rpl<'static, VirtualMuxAlarm<'static, A>>,
+            Rpl::new(
+                ctrl_icmp_send,
+                forward_ip_send,
+                self.ip_send_mux,
+                rpl_alarm,
+                self.src_mac_addr,
+                self.interface_list,
+                &mut ROUTES,
+                &mut CTRL_BUF,
+                &mut FORWARD_BUF,
+                net_cap,
+            )
+        );
+        ctrl_icmp_send.set_client(rpl);
+        time::Alarm::set_alarm_client(rpl_alarm, rpl);
+        rpl.set_interface_addresses(self.interface_addresses);
+
+        let rpl_rcvr = static_init!(ICMP6Receiver<'static>, ICMP6Receiver::new());
+        rpl_rcvr.set_client(rpl);
+        self.icmp_recv_mux.add_client(rpl_rcvr);
+
+        self.ip_send.set_router(rpl);
+        self.ip_receive.set_forwarder(rpl);
+
+        match self.root {
+            Some(dodag_id) => rpl.start_root(dodag_id),
+            None => rpl.start(),
+        }
+
+        rpl
+    }
+}
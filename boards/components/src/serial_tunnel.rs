@@ -0,0 +1,113 @@
+//! Component to carry IPv6 packets over a UART.
+//!
+//! This provides one Component, SerialTunnelComponent, which creates a
+//! virtual UART device on `uart_mux` and a `SerialTunnel` network interface
+//! on top of it, with an `IP6RecvStruct` receiving its packets. The tunnel
+//! implements `IP6Sender`, so a UDP or ICMPv6 stack can be built on it in
+//! place of the 6LoWPAN interface of `UDPMuxComponent`. On a Linux host,
+//! `tools/serial-tun.py` exposes the other end of the line as a TUN device.
+//!
+//! The tunnel uses about 6.5 kB of buffers, to send and receive packets of
+//! up to the IPv6 minimum MTU (1280 bytes).
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (tunnel, ip_receive) =
+//!        SerialTunnelComponent::new(uart_mux, Framing::Slip, local_ip_ifaces[0]).finalize(());
+//!    let ip_send_mux = static_init!(MuxIP6Sender<'static>, MuxIP6Sender::new(tunnel));
+//!    tunnel.set_client(ip_send_mux);
+//! ```
+
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::IP6RecvStruct;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::IpVisibilityCapability;
+use capsules::net::tunnel::serial_tunnel::MTU;
+use capsules::net::tunnel::{Framing, SerialTunnel};
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::{create_capability, static_init};
+
+const IP_HDR_LEN: usize = 40;
+// Large enough for any framing
+const MAX_FRAME_LEN: usize = Framing::Hdlc.max_frame_len(MTU);
+
+static mut DGRAM_BUF: [u8; MTU - IP_HDR_LEN] = [0; MTU - IP_HDR_LEN];
+static mut PACKET_BUF: [u8; MTU] = [0; MTU];
+static mut TX_BUF: [u8; MAX_FRAME_LEN] = [0; MAX_FRAME_LEN];
+static mut RX_BUF: [u8; MTU] = [0; MTU];
+static mut RX_BYTE: [u8; 1] = [0; 1];
+
+pub struct SerialTunnelComponent {
+    uart_mux: &'static MuxUart<'static>,
+    framing: Framing,
+    src_addr: IPAddr,
+}
+
+impl SerialTunnelComponent {
+    pub fn new(
+        uart_mux: &'static MuxUart<'static>,
+        framing: Framing,
+        src_addr: IPAddr,
+    ) -> SerialTunnelComponent {
+        SerialTunnelComponent {
+            uart_mux,
+            framing,
+            src_addr,
+        }
+    }
+}
+
+impl Component for SerialTunnelComponent {
+    type StaticInput = ();
+    type Output = (
+        &'static SerialTunnel<'static>,
+        &'static IP6RecvStruct<'static>,
+    );
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let tunnel_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
+        tunnel_uart.setup();
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::UDP(UDPHeader::new()),
+            payload: &mut DGRAM_BUF,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let tunnel = static_init!(
+            SerialTunnel<'static>,
+            SerialTunnel::new(
+                tunnel_uart,
+                self.framing,
+                ip6_dg,
+                &mut PACKET_BUF,
+                &mut TX_BUF,
+                &mut RX_BUF,
+                &mut RX_BYTE,
+                ip_vis,
+            )
+        );
+        hil::uart::Transmit::set_transmit_client(tunnel_uart, tunnel);
+        hil::uart::Receive::set_receive_client(tunnel_uart, tunnel);
+        tunnel.set_addr(self.src_addr);
+
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+        tunnel.set_rx_client(ip_receive);
+
+        tunnel.start().unwrap(); // Unwrap fail = the UART could not start receiving
+
+        (tunnel, ip_receive)
+    }
+}
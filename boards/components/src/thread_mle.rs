@@ -0,0 +1,216 @@
+//! Component to attach to a Thread network as a child with MLE.
+//!
+//! This provides one Component, MleComponent, which runs the Thread Mesh
+//! Link Establishment (MLE) attach process over the UDP stack exposed by
+//! UDPMuxComponent. MLE messages are sent from the link-local address of the
+//! board with a hop limit of 255, and are secured with a virtual AES-CCM
+//! instance on `aes_mux`.
+//!
+//! `rng` must be a synchronous random number source, e.g. a
+//! `capsules::rng::SynchronousRandom` wrapping the chip's entropy source.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let mle = MleComponent::new(
+//!        ip_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        aes_mux,
+//!        mux_alarm,
+//!        rng,
+//!        ext_addr,
+//!        LinkMode::ReceiverOnWhenIdle as u8 | LinkMode::FullNetworkDataRequired as u8,
+//!        THREAD_MASTER_KEY,
+//!    )
+//!    .finalize(components::thread_mle_component_helper!(
+//!        sam4l::ast::Ast,
+//!        sam4l::aes::Aes<'static>
+//!    ));
+//! ```
+
+use capsules;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::{IP6SendUser, IP6Sender, MuxIP6Sender};
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::thread::mle::{
+    Mle, MASTER_KEY_LEN, MAX_MLE_MSG_LEN, MLE_BUF_LEN, MLE_CRYPT_BUF_LEN, MLE_PORT,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128CBC, AES128ECB};
+use kernel::hil::time::{self, Alarm};
+use kernel::{create_capability, static_init, static_init_half};
+
+static mut IP_QUEUE_BUF: [u8; MAX_MLE_MSG_LEN] = [0; MAX_MLE_MSG_LEN];
+static mut CRYPT_BUF: [u8; MLE_CRYPT_BUF_LEN] = [0; MLE_CRYPT_BUF_LEN];
+static mut MLE_BUF: [u8; MLE_BUF_LEN] = [0; MLE_BUF_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! thread_mle_component_helper {
+    ($A:ty, $C:ty $(,)?) => {{
+        use capsules::net::thread::mle::Mle;
+        use capsules::virtual_aes_ccm::VirtualAES128CCM;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualAES128CCM<'static, $C>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            Mle<'static, VirtualMuxAlarm<'static, $A>, VirtualAES128CCM<'static, $C>>,
+        > = MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct MleComponent<
+    A: Alarm<'static> + 'static,
+    C: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+> {
+    ip_send_mux: &'static MuxIP6Sender<'static>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    aes_mux: &'static MuxAES128CCM<'static, C>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng: &'static dyn Random<'static>,
+    ext_addr: [u8; 8],
+    mode: u8,
+    master_key: [u8; MASTER_KEY_LEN],
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        C: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+    > MleComponent<A, C>
+{
+    pub fn new(
+        ip_send_mux: &'static MuxIP6Sender<'static>,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        aes_mux: &'static MuxAES128CCM<'static, C>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng: &'static dyn Random<'static>,
+        ext_addr: [u8; 8],
+        mode: u8,
+        master_key: [u8; MASTER_KEY_LEN],
+    ) -> Self {
+        Self {
+            ip_send_mux,
+            udp_recv_mux,
+            port_table,
+            aes_mux,
+            alarm_mux,
+            rng,
+            ext_addr,
+            mode,
+            master_key,
+        }
+    }
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        C: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+    > Component for MleComponent<A, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualAES128CCM<'static, C>>,
+        &'static mut MaybeUninit<
+            Mle<'static, VirtualMuxAlarm<'static, A>, VirtualAES128CCM<'static, C>>,
+        >,
+    );
+    type Output = &'static Mle<'static, VirtualMuxAlarm<'static, A>, VirtualAES128CCM<'static, C>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let mle_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        mle_alarm.setup();
+
+        let aes_ccm = static_init_half!(
+            static_buffer.1,
+            VirtualAES128CCM<'static, C>,
+            VirtualAES128CCM::new(self.aes_mux, &mut CRYPT_BUF)
+        );
+        aes_ccm.setup();
+
+        // MLE messages are sent from the link-local address, and receivers
+        // drop those that have been forwarded (hop limit below 255)
+        let ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(self.ip_send_mux, &mut IP_QUEUE_BUF)
+        );
+        ip_send.setup();
+        ip_send.set_addr(IPAddr::generate_from_mac(MacAddress::Long(self.ext_addr)));
+        ip_send.set_hop_limit(255);
+
+        let udp_send_mux = static_init!(
+            MuxUdpSender<'static, IP6SendUser<'static>>,
+            MuxUdpSender::new(ip_send)
+        );
+        ip_send.set_client(udp_send_mux);
+
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let udp_send = static_init!(
+            UDPSendStruct<'static, IP6SendUser<'static>>,
+            UDPSendStruct::new(udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Any,
+                PortRange::Port(MLE_PORT),
+                PortRange::Port(MLE_PORT),
+                &create_cap
+            )
+        );
+
+        let mle = static_init_half!(
+            static_buffer.2,
+            Mle<'static, VirtualMuxAlarm<'static, A>, VirtualAES128CCM<'static, C>>,
+            Mle::new(
+                udp_send,
+                udp_recv,
+                self.port_table,
+                aes_ccm,
+                mle_alarm,
+                self.rng,
+                self.ext_addr,
+                self.mode,
+                self.master_key,
+                &mut MLE_BUF,
+                net_cap,
+            )
+        );
+        udp_send.set_client(mle);
+        udp_recv.set_client(mle);
+        symmetric_encryption::AES128CCM::set_client(aes_ccm, mle);
+        time::Alarm::set_alarm_client(mle_alarm, mle);
+
+        mle.start().unwrap(); // Unwrap fail = the MLE port is already bound
+
+        mle
+    }
+}
@@ -3,6 +3,11 @@
 //! This provides one Component, UDPDriverComponent. This component initializes a userspace
 //! UDP driver that allows apps to use the UDP stack.
 //!
+//! The component works with any IPv6 interface. The helper macro takes the
+//! alarm type of a 6LoWPAN stack built by `UDPMuxComponent`, or, prefixed
+//! with `@sender`, the IPv6 sender of another interface (e.g.
+//! `EthernetInterface<'static>`).
+//!
 //! Usage
 //! -----
 //! ```rust
@@ -12,28 +17,27 @@
 //!        udp_recv_mux,
 //!        udp_port_table,
 //!        local_ip_ifaces,
-//!        PAYLOAD_LEN,
+//!        ip_receive.multicast_groups(),
 //!     )
 //!     .finalize();
 //! ```
 
 use capsules;
 use capsules::net::ipv6::ip_utils::IPAddr;
-use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::multicast::MulticastGroups;
 use capsules::net::network_capabilities::{
-    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
 use capsules::net::udp::udp_port_table::UdpPortManager;
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
-use capsules::virtual_alarm::VirtualMuxAlarm;
 use core::mem::MaybeUninit;
 use kernel;
 use kernel::capabilities;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
 use kernel::component::Component;
-use kernel::hil::time::Alarm;
 use kernel::{create_capability, static_init, static_init_half};
 
 const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
@@ -43,6 +47,12 @@ static mut DRIVER_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! udp_driver_component_helper {
+    (@sender $S:ty $(,)?) => {{
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<UDPSendStruct<'static, $S>> = MaybeUninit::uninit();
+        (&mut BUF0,)
+    };};
     ($A:ty $(,)?) => {{
         use capsules::net::udp::udp_send::UDPSendStruct;
         use core::mem::MaybeUninit;
@@ -59,27 +69,25 @@ macro_rules! udp_driver_component_helper {
     };};
 }
 
-pub struct UDPDriverComponent<A: Alarm<'static> + 'static> {
+pub struct UDPDriverComponent<S: IP6Sender<'static> + 'static> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
-    udp_send_mux:
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_send_mux: &'static MuxUdpSender<'static, S>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
     interface_list: &'static [IPAddr],
+    multicast_groups: &'static MulticastGroups,
 }
 
-impl<A: Alarm<'static>> UDPDriverComponent<A> {
+impl<S: IP6Sender<'static>> UDPDriverComponent<S> {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, S>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
         interface_list: &'static [IPAddr],
+        multicast_groups: &'static MulticastGroups,
     ) -> Self {
         Self {
             board_kernel,
@@ -88,19 +96,13 @@ impl<A: Alarm<'static>> UDPDriverComponent<A> {
             udp_recv_mux,
             port_table,
             interface_list,
+            multicast_groups,
         }
     }
 }
 
-impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
-    type StaticInput = (
-        &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
-        >,
-    );
+impl<S: IP6Sender<'static>> Component for UDPDriverComponent<S> {
+    type StaticInput = (&'static mut MaybeUninit<UDPSendStruct<'static, S>>,);
     type Output = &'static capsules::net::udp::UDPDriver<'static>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -113,10 +115,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         );
         let udp_send = static_init_half!(
             static_buffer.0,
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
+            UDPSendStruct<'static, S>,
             UDPSendStruct::new(self.udp_send_mux, udp_vis)
         );
 
@@ -131,6 +130,11 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
             NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
         );
 
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
         let udp_driver = static_init!(
             capsules::net::udp::UDPDriver<'static>,
             capsules::net::udp::UDPDriver::new(
@@ -142,6 +146,8 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
                 kernel::utilities::leasable_buffer::LeasableBuffer::new(&mut DRIVER_BUF),
                 &DRIVER_CAP,
                 net_cap,
+                ip_vis,
+                self.multicast_groups,
             )
         );
         udp_send.set_client(udp_driver);
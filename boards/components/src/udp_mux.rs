@@ -2,12 +2,20 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. It also answers
+//! ICMPv6 Echo Requests addressed to the node, and exposes the IPv6 send mux
+//! and ICMPv6 receive mux for other ICMPv6 users (e.g. the ping driver).
+//! Neighbor Discovery is started to configure addresses from Router
+//! Advertisements and to fill the neighbor cache used to pick the
+//! destination MAC address of outgoing frames. The IPv6 sender and receiver,
+//! and Neighbor Discovery with the addresses it configured, are exposed so
+//! that a routing protocol (e.g. RPL) can be attached.
 //!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_mux, udp_recv, port_table, ip_send_mux, icmp_recv_mux, ip_send, ip_receive, nd) =
+//!        UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
@@ -25,13 +33,21 @@
 
 use capsules;
 use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6_echo::ICMP6EchoResponder;
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6Receiver, MuxICMP6Receiver};
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
-use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::ipv6_send::{IP6SendUser, MuxIP6Sender};
+use capsules::net::ipv6::nd::{NeighborDiscovery, ND_BUF_LEN};
+use capsules::net::ipv6::neighbor_cache::{NeighborCache, NeighborEntry};
 use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
@@ -52,6 +68,10 @@ use kernel::{static_init, static_init_half};
 //   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
 //   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
 //   3. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   4. UDP_QUEUE_BUF / ECHO_QUEUE_BUF: Hold the payload of a UDP or ICMPv6 Echo Reply packet
+//      while the IP6_Sender is busy sending a packet of the other protocol.
+//   5. ECHO_BUF: Holds the data of the ICMPv6 Echo Reply being sent.
+//   6. ND_QUEUE_BUF / ND_BUF: The same for Neighbor Discovery messages.
 //
 //   Additionally, every capsule using the stack needs an additional buffer to craft packets for
 //   tx which can then be passed to the MuxUdpSender for tx.
@@ -61,6 +81,15 @@ static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
 
 pub const MAX_PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by userspace apps or capsules
 static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut UDP_QUEUE_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ECHO_QUEUE_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ECHO_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ND_QUEUE_BUF: [u8; ND_BUF_LEN] = [0; ND_BUF_LEN];
+static mut ND_BUF: [u8; ND_BUF_LEN] = [0; ND_BUF_LEN];
+
+const NEIGHBOR_CACHE_SIZE: usize = 8;
+static mut NEIGHBOR_CACHE_ENTRIES: [Option<NeighborEntry>; NEIGHBOR_CACHE_SIZE] =
+    [None; NEIGHBOR_CACHE_SIZE];
 
 // Rather than require a data structure with 65535 slots (number of UDP ports), we
 // use a structure that can hold up to 16 port bindings. Any given capsule can bind
@@ -106,8 +135,12 @@ macro_rules! udp_mux_component_helper {
                 >,
             >,
         > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<
+            capsules::net::ipv6::nd::NeighborDiscovery<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
         (
-            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5,
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
         )
     };};
 }
@@ -165,11 +198,18 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
                 capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
             >,
         >,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>>,
     );
     type Output = (
         &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        &'static MuxIP6Sender<'static>,
+        &'static MuxICMP6Receiver<'static>,
+        &'static IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        &'static IP6RecvStruct<'static>,
+        &'static NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>,
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -231,12 +271,9 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         };
         let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
 
-        // In current design, all udp senders share same IP sender, and the IP sender
-        // holds the destination mac address. This means all UDP senders must send to
-        // the same mac address...this works fine under the assumption
-        // of all packets being routed via a single gateway router, but doesn't work
-        // if multiple senders want to send to different addresses on a local network.
-        // This will be fixed once we have an ipv6_nd cache mapping IP addresses to dst macs
+        // All senders share the same IP sender, which picks the destination mac
+        // address from the neighbor cache. `dst_mac_addr` is only used for
+        // destinations the cache cannot resolve.
         let ip_send = static_init_half!(
             static_buffer.4,
             capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
@@ -252,21 +289,105 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             )
         );
         ipsender_virtual_alarm.set_alarm_client(ip_send);
+        udp_mac.set_transmit_client(ip_send);
+        let neighbor_cache = static_init!(
+            NeighborCache,
+            NeighborCache::new(&mut NEIGHBOR_CACHE_ENTRIES)
+        );
+        ip_send.set_neighbor_cache(neighbor_cache);
 
+        // UDP and ICMPv6 share the IP sender
+        let ip_send_mux = static_init!(MuxIP6Sender<'static>, MuxIP6Sender::new(ip_send));
+        ip_send.set_client(ip_send_mux);
         // Initially, set src IP of the sender to be the first IP in the Interface
-        // list. Userland apps can change this if they so choose.
+        // list. Neighbor Discovery replaces it once it has configured an address.
         // Notably, the src addr is the same regardless of if messages are sent from
         // userland or capsules.
-        ip_send.set_addr(self.interface_list[0]);
-        udp_mac.set_transmit_client(ip_send);
-
-        let ip_receive = static_init!(
-            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
-            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        ip_send_mux.set_addr(self.interface_list[0]);
+        let udp_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(ip_send_mux, &mut UDP_QUEUE_BUF)
         );
+        udp_ip_send.setup();
+
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
         sixlowpan_state.set_rx_client(ip_receive);
         let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
         ip_receive.set_client(udp_recv_mux);
+        let icmp_recv_mux = static_init!(MuxICMP6Receiver<'static>, MuxICMP6Receiver::new());
+        ip_receive.set_icmp_client(icmp_recv_mux);
+
+        // Reply to ICMPv6 Echo Requests addressed to this node
+        let echo_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(ip_send_mux, &mut ECHO_QUEUE_BUF)
+        );
+        echo_ip_send.setup();
+        let echo_icmp_send = static_init!(
+            ICMP6SendStruct<'static, IP6SendUser<'static>>,
+            ICMP6SendStruct::new(echo_ip_send)
+        );
+        echo_ip_send.set_client(echo_icmp_send);
+        let echo_net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Any,
+                PortRange::NoPorts,
+                PortRange::NoPorts,
+                &create_cap
+            )
+        );
+        let echo_responder = static_init!(
+            ICMP6EchoResponder<'static>,
+            ICMP6EchoResponder::new(
+                echo_icmp_send,
+                self.interface_list,
+                &mut ECHO_BUF,
+                echo_net_cap
+            )
+        );
+        echo_icmp_send.set_client(echo_responder);
+        let echo_rcvr = static_init!(ICMP6Receiver<'static>, ICMP6Receiver::new());
+        echo_rcvr.set_client(echo_responder);
+        icmp_recv_mux.add_client(echo_rcvr);
+
+        // Neighbor Discovery
+        let nd_virtual_alarm = static_init_half!(
+            static_buffer.6,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        nd_virtual_alarm.setup();
+        let nd_ip_send = static_init!(
+            IP6SendUser<'static>,
+            IP6SendUser::new(ip_send_mux, &mut ND_QUEUE_BUF)
+        );
+        nd_ip_send.setup();
+        let nd_icmp_send = static_init!(
+            ICMP6SendStruct<'static, IP6SendUser<'static>>,
+            ICMP6SendStruct::new(nd_ip_send)
+        );
+        nd_ip_send.set_client(nd_icmp_send);
+        let nd = static_init_half!(
+            static_buffer.7,
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>,
+            NeighborDiscovery::new(
+                nd_icmp_send,
+                nd_ip_send,
+                ip_send_mux,
+                neighbor_cache,
+                nd_virtual_alarm,
+                self.src_mac_addr,
+                &mut ND_BUF,
+                echo_net_cap
+            )
+        );
+        nd_icmp_send.set_client(nd);
+        nd_virtual_alarm.set_alarm_client(nd);
+        let nd_rcvr = static_init!(ICMP6Receiver<'static>, ICMP6Receiver::new());
+        nd_rcvr.set_client(nd);
+        icmp_recv_mux.add_client(nd_rcvr);
+        nd.start();
 
         let udp_send_mux = static_init_half!(
             static_buffer.5,
@@ -274,9 +395,9 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
                 'static,
                 capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
             >,
-            MuxUdpSender::new(ip_send)
+            MuxUdpSender::new(udp_ip_send)
         );
-        ip_send.set_client(udp_send_mux);
+        udp_ip_send.set_client(udp_send_mux);
 
         let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
         let udp_port_table = static_init!(
@@ -284,6 +405,15 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
         );
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        (
+            udp_send_mux,
+            udp_recv_mux,
+            udp_port_table,
+            ip_send_mux,
+            icmp_recv_mux,
+            ip_send,
+            ip_receive,
+            nd,
+        )
     }
 }
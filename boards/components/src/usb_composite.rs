@@ -0,0 +1,147 @@
+//! Components for composite USB devices.
+//!
+//! This provides two components. `UsbCompositeComponent` makes the USB
+//! controller a composite device, and `UsbCompositeFunctionComponent` adds a
+//! function to it. Each function is passed as the USB controller of one class
+//! component, e.g. `CdcAcmComponent`, so that a board can offer several USB
+//! classes at once.
+//!
+//! The functions must be created before `enable()` is called on the composite
+//! device, and their interfaces are numbered in the order they are created.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let composite = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x521f,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_composite_component_helper!(nrf52::usbd::Usbd));
+//!
+//! let cdc_function = components::usb_composite::UsbCompositeFunctionComponent::new(composite)
+//!     .finalize(components::usb_composite_function_component_helper!(
+//!         nrf52::usbd::Usbd
+//!     ));
+//! let cdc = components::cdc::CdcAcmComponent::new(
+//!     cdc_function,
+//!     capsules::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x521f,
+//!     STRINGS,
+//!     mux_alarm,
+//!     dynamic_deferred_caller,
+//!     None,
+//! )
+//! .finalize(components::usb_cdc_acm_component_helper!(
+//!     capsules::usb::composite::CompositeFunction<'static, nrf52::usbd::Usbd>,
+//!     nrf52::rtc::Rtc
+//! ));
+//!
+//! composite.enable();
+//! composite.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::composite::{CompositeDevice, CompositeFunction};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_composite_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::composite::CompositeDevice;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CompositeDevice<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! usb_composite_function_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::composite::CompositeFunction;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CompositeFunction<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct UsbCompositeComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCompositeComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCompositeComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<CompositeDevice<'static, U>>;
+    type Output = &'static CompositeDevice<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let composite = static_init_half!(
+            s,
+            CompositeDevice<'static, U>,
+            CompositeDevice::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+            )
+        );
+        self.usb.set_client(composite);
+
+        composite
+    }
+}
+
+pub struct UsbCompositeFunctionComponent<U: 'static + hil::usb::UsbController<'static>> {
+    composite: &'static CompositeDevice<'static, U>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCompositeFunctionComponent<U> {
+    pub fn new(composite: &'static CompositeDevice<'static, U>) -> Self {
+        Self { composite }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCompositeFunctionComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<CompositeFunction<'static, U>>;
+    type Output = &'static CompositeFunction<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let function = static_init_half!(
+            s,
+            CompositeFunction<'static, U>,
+            CompositeFunction::new(self.composite)
+        );
+        function.setup();
+
+        function
+    }
+}
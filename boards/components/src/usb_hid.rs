@@ -0,0 +1,121 @@
+//! Component for USB HID keyboard and mouse support.
+//!
+//! This provides a component for using the HID class driver and its syscall
+//! driver, which let apps send key and pointer reports to the host.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",     // Manufacturer
+//!     "Keyboard",      // Product
+//!     "Serial No. 5",  // Serial number
+//! ];
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     board_kernel,
+//!     capsules::usb::hid_driver::DRIVER_NUM,
+//!     &nrf52::usbd::USBD,
+//!     0x1209,
+//!     0x0002,
+//!     STRINGS,
+//!     capsules::usb::hid::BootDevice::Keyboard,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52::usbd::Usbd));
+//!
+//! hid.enable();
+//! hid.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::hid::{BootDevice, Hid, MAX_REPORT_LEN};
+use capsules::usb::hid_driver::UsbHidDriver;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init_half;
+
+static mut REPORT_BUF: [u8; MAX_REPORT_LEN] = [0; MAX_REPORT_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_hid_component_helper {
+    ($U:ty $(,)?) => {{
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<capsules::usb::hid::Hid<'static, $U>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::usb::hid_driver::UsbHidDriver<'static, $U>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UsbHidComponent<U: 'static + hil::usb::UsbController<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    usb: &'static U,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    device: BootDevice,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbHidComponent<U> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        usb: &'static U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        device: BootDevice,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            usb,
+            vendor_id,
+            product_id,
+            strings,
+            device,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbHidComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<Hid<'static, U>>,
+        &'static mut MaybeUninit<UsbHidDriver<'static, U>>,
+    );
+    type Output = (&'static Hid<'static, U>, &'static UsbHidDriver<'static, U>);
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let hid = static_init_half!(
+            s.0,
+            Hid<'static, U>,
+            Hid::new(
+                self.usb,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.device,
+            )
+        );
+        self.usb.set_client(hid);
+
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let hid_driver = static_init_half!(
+            s.1,
+            UsbHidDriver<'static, U>,
+            UsbHidDriver::new(
+                hid,
+                &mut REPORT_BUF,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            )
+        );
+        hid.set_client(hid_driver);
+
+        (hid, hid_driver)
+    }
+}
@@ -134,6 +134,14 @@ struct Imix {
     ipc: kernel::ipc::IPC<NUM_PROCS>,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    ping_driver: &'static capsules::net::icmpv6::PingDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    coap_driver: &'static capsules::net::coap::CoapDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     crc: &'static capsules::crc::CrcDriver<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -179,6 +187,8 @@ impl SyscallDriverLookup for Imix {
             capsules::crc::DRIVER_NUM => f(Some(self.crc)),
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
@@ -596,7 +606,16 @@ pub unsafe fn main() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
+    let (
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        ip_send_mux,
+        icmp_recv_mux,
+        ip_send,
+        ip_receive,
+        nd,
+    ) = components::udp_mux::UDPMuxComponent::new(
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
         DEFAULT_CTX_PREFIX,
@@ -608,6 +627,21 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
 
+    // Multi-hop routing. Pass the global address of the board as the DODAG ID
+    // instead of `None` to make it the root of a DODAG.
+    components::rpl::RplComponent::new(
+        ip_send,
+        ip_receive,
+        ip_send_mux,
+        icmp_recv_mux,
+        mux_alarm,
+        src_mac_from_serial_num,
+        local_ip_ifaces,
+        nd,
+        None,
+    )
+    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
         board_kernel,
@@ -616,9 +650,29 @@ pub unsafe fn main() {
         udp_recv_mux,
         udp_port_table,
         local_ip_ifaces,
+        ip_receive.multicast_groups(),
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    let ping_driver = components::ping::PingComponent::new(
+        board_kernel,
+        capsules::net::icmpv6::DRIVER_NUM,
+        ip_send_mux,
+        icmp_recv_mux,
+        mux_alarm,
+    )
+    .finalize(components::ping_component_helper!(sam4l::ast::Ast));
+
+    let coap_driver = components::coap::CoapComponent::new(
+        board_kernel,
+        capsules::net::coap::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+    )
+    .finalize(components::coap_component_helper!(sam4l::ast::Ast));
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
 
@@ -640,6 +694,8 @@ pub unsafe fn main() {
         ipc: kernel::ipc::IPC::new(board_kernel, kernel::ipc::DRIVER_NUM, &grant_cap),
         ninedof,
         udp_driver,
+        ping_driver,
+        coap_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
@@ -32,6 +32,7 @@ use kernel::debug;
 use kernel::hil::radio;
 use kernel::hil::time::{self, Alarm, ConvertTicks};
 use kernel::static_init;
+use kernel::utilities::leasable_buffer::LeasableBuffer;
 
 pub const SRC_ADDR: IPAddr = IPAddr([
     0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
@@ -166,7 +167,7 @@ pub unsafe fn run(
 impl<'a, A: time::Alarm<'a>> capsules::net::icmpv6::icmpv6_send::ICMP6SendClient
     for LowpanICMPTest<'a, A>
 {
-    fn send_done(&self, result: Result<(), ErrorCode>) {
+    fn send_done(&self, result: Result<(), ErrorCode>, _buf: LeasableBuffer<'static, u8>) {
         match result {
             Ok(()) => {
                 debug!("ICMP Echo Request Packet Sent!");
@@ -239,8 +240,12 @@ impl<'a, A: time::Alarm<'a>> LowpanICMPTest<'a, A> {
     fn send_next(&self) {
         let icmp_hdr = ICMP6Header::new(ICMP6Type::Type128); // Echo Request
         let _ = unsafe {
-            self.icmp_sender
-                .send(DST_ADDR, icmp_hdr, &mut ICMP_PAYLOAD, self.net_cap)
+            self.icmp_sender.send(
+                DST_ADDR,
+                icmp_hdr,
+                LeasableBuffer::new(&mut ICMP_PAYLOAD),
+                self.net_cap,
+            )
         };
     }
 }
@@ -460,7 +460,13 @@ impl<'a, A: time::Alarm<'a>> SixlowpanRxClient for LowpanTest<'a, A> {
 
 static mut ARRAY: [u8; 100] = [0x0; 100]; //used in introducing delay between frames
 impl<'a, A: time::Alarm<'a>> TxClient for LowpanTest<'a, A> {
-    fn send_done(&self, tx_buf: &'static mut [u8], _acked: bool, result: Result<(), ErrorCode>) {
+    fn send_done(
+        &self,
+        tx_buf: &'static mut [u8],
+        _acked: bool,
+        _retries: u8,
+        result: Result<(), ErrorCode>,
+    ) {
         match result {
             Ok(()) => {}
             _ => debug!("sendDone indicates error"),
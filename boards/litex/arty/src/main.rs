@@ -396,9 +396,6 @@ pub unsafe fn main() {
 
     // ---------- ETHERNET ----------
 
-    // Packet receive buffer
-    let ethmac0_rxbuf0 = static_init!([u8; 1522], [0; 1522]);
-
     // ETHMAC peripheral
     let ethmac0 = static_init!(
         litex_vexriscv::liteeth::LiteEth<socc::SoCRegisterFmt>,
@@ -412,7 +409,6 @@ pub unsafe fn main() {
             socc::ETHMAC_SLOT_SIZE,
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
-            ethmac0_rxbuf0,
         )
     );
 
@@ -5,6 +5,9 @@
 // https://github.com/rust-lang/rust/issues/62184.
 #![cfg_attr(not(doc), no_main)]
 
+use capsules::net::ethernet::EthernetAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::neighbor_cache::LinkAddress;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel::capabilities;
 use kernel::component::Component;
@@ -96,6 +99,9 @@ static mut PANIC_REFERENCES: LiteXSimPanicReferences = LiteXSimPanicReferences {
     process_printer: None,
 };
 
+/// MAC address of the simulated Ethernet interface (locally administered).
+const ETHMAC_ADDRESS: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::PanicFaultPolicy {};
 
@@ -128,6 +134,7 @@ struct LiteXSim {
         'static,
         capsules::virtual_uart::UartDevice<'static>,
     >,
+    udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         VirtualMuxAlarm<
@@ -168,6 +175,7 @@ impl SyscallDriverLookup for LiteXSim {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::low_level_debug::DRIVER_NUM => f(Some(self.lldb)),
+            capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -389,9 +397,6 @@ pub unsafe fn main() {
 
     // ---------- ETHERNET ----------
 
-    // Packet receive buffer
-    let ethmac0_rxbuf0 = static_init!([u8; 1522], [0; 1522]);
-
     // ETHMAC peripheral
     let ethmac0 = static_init!(
         litex_vexriscv::liteeth::LiteEth<socc::SoCRegisterFmt>,
@@ -405,13 +410,58 @@ pub unsafe fn main() {
             socc::ETHMAC_SLOT_SIZE,
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
-            ethmac0_rxbuf0,
         )
     );
 
     // Initialize the ETHMAC controller
     ethmac0.initialize();
 
+    // ---------- IPv6 / UDP OVER ETHERNET ----------
+
+    type LiteXAlarm = litex_vexriscv::timer::LiteXAlarm<
+        'static,
+        'static,
+        socc::SoCRegisterFmt,
+        socc::ClockFrequency,
+    >;
+
+    let local_ip_ifaces = static_init!(
+        [IPAddr; 2],
+        [
+            ETHMAC_ADDRESS.link_local(),
+            IPAddr([0xfd, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]),
+        ]
+    );
+
+    let (
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        _ip_send_mux,
+        _icmp_recv_mux,
+        _eth,
+        ip_receive,
+    ) = components::ethernet::EthernetComponent::new(
+        ethmac0,
+        ETHMAC_ADDRESS,
+        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(components::ethernet_component_helper!(LiteXAlarm));
+
+    let udp_driver = components::udp_driver::UDPDriverComponent::new(
+        board_kernel,
+        capsules::net::udp::driver::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        local_ip_ifaces,
+        ip_receive.multicast_groups(),
+    )
+    .finalize(components::udp_driver_component_helper!(
+        @sender capsules::net::ethernet::EthernetInterface<'static>
+    ));
+
     // --------- GPIO CONTROLLER ----------
     type GPIOPin = litex_vexriscv::gpio::LiteXGPIOPin<'static, 'static, socc::SoCRegisterFmt>;
 
@@ -612,6 +662,7 @@ pub unsafe fn main() {
         button_driver: button_driver,
         led_driver: led_driver,
         console: console,
+        udp_driver: udp_driver,
         alarm: alarm,
         lldb: lldb,
         ipc: kernel::ipc::IPC::new(
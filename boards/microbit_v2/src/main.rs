@@ -71,6 +71,12 @@ static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS]
 static mut CHIP: Option<&'static nrf52833::chip::NRF52<Nrf52833DefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText> = None;
 
+// Allocate 8 KiB (two flash pages) for the app log.
+#[allow(missing_docs)]
+mod app_log_storage {
+    kernel::storage_volume!(APP_LOG, 8);
+}
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
@@ -78,6 +84,13 @@ pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
 // debug mode requires more stack space
 // pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
+/// Lets the log driver look up the package names of apps.
+struct ProcessMgmtCap;
+unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+
+type AppLog =
+    capsules::log::Log<'static, capsules::virtual_flash::FlashUser<'static, nrf52833::nvmc::Nvmc>>;
+
 /// Supported drivers by the platform
 pub struct MicroBit {
     ble_radio: &'static capsules::ble_advertising_driver::BLE<
@@ -108,6 +121,12 @@ pub struct MicroBit {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52833::rtc::Rtc<'static>>,
     >,
     app_flash: &'static capsules::app_flash_driver::AppFlash<'static>,
+    log: &'static capsules::log_driver::LogDriver<
+        'static,
+        AppLog,
+        nrf52::rtc::Rtc<'static>,
+        ProcessMgmtCap,
+    >,
     sound_pressure: &'static capsules::sound_pressure::SoundPressureSensor<'static>,
 
     scheduler: &'static RoundRobinSched<'static>,
@@ -133,6 +152,7 @@ impl SyscallDriverLookup for MicroBit {
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::buzzer_driver::DRIVER_NUM => f(Some(self.buzzer)),
             capsules::app_flash_driver::DRIVER_NUM => f(Some(self.app_flash)),
+            capsules::log_driver::DRIVER_NUM => f(Some(self.log)),
             capsules::sound_pressure::DRIVER_NUM => f(Some(self.sound_pressure)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
@@ -522,6 +542,46 @@ pub unsafe fn main() {
         512
     ));
 
+    // App Log
+
+    let virtual_log_flash = components::flash::FlashUserComponent::new(mux_flash).finalize(
+        components::flash_user_component_helper!(nrf52833::nvmc::Nvmc),
+    );
+    let log_pagebuffer = static_init!(nrf52833::nvmc::NrfPage, nrf52833::nvmc::NrfPage::default());
+    let app_log = static_init!(
+        AppLog,
+        capsules::log::Log::new(
+            &app_log_storage::APP_LOG,
+            virtual_log_flash,
+            log_pagebuffer,
+            dynamic_deferred_caller,
+            true
+        )
+    );
+    kernel::hil::flash::HasClient::set_client(virtual_log_flash, app_log);
+    app_log.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(app_log)
+            .expect("no deferred call slot available for the app log"),
+    );
+
+    let log = static_init!(
+        capsules::log_driver::LogDriver<'static, AppLog, nrf52::rtc::Rtc<'static>, ProcessMgmtCap>,
+        capsules::log_driver::LogDriver::new(
+            app_log,
+            rtc,
+            &mut capsules::log_driver::BUFFER,
+            board_kernel.create_grant(
+                capsules::log_driver::DRIVER_NUM,
+                &memory_allocation_capability
+            ),
+            board_kernel,
+            ProcessMgmtCap,
+        )
+    );
+    kernel::hil::log::LogRead::set_read_client(app_log, log);
+    kernel::hil::log::LogWrite::set_append_client(app_log, log);
+
     //--------------------------------------------------------------------------
     // WIRELESS
     //--------------------------------------------------------------------------
@@ -610,6 +670,7 @@ pub unsafe fn main() {
         adc: adc_syscall,
         alarm,
         app_flash,
+        log,
         ipc: kernel::ipc::IPC::new(
             board_kernel,
             kernel::ipc::DRIVER_NUM,
@@ -628,6 +689,9 @@ pub unsafe fn main() {
 
     debug!("Initialization complete. Entering main loop.");
 
+    // Print the app log left over from previous boots to the console.
+    let _ = log.print_log();
+
     //--------------------------------------------------------------------------
     // PROCESSES AND MAIN LOOP
     //--------------------------------------------------------------------------
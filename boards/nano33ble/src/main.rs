@@ -147,6 +147,14 @@ pub struct Platform {
         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
     >,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    ping_driver: &'static capsules::net::icmpv6::PingDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
+    coap_driver: &'static capsules::net::coap::CoapDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
     scheduler: &'static RoundRobinSched<'static>,
     systick: cortexm4::systick::SysTick,
 }
@@ -169,6 +177,8 @@ impl SyscallDriverLookup for Platform {
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_radio)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -581,16 +591,17 @@ pub unsafe fn main() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_send_mux, icmp_recv_mux, _, ip_receive, _) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -600,9 +611,29 @@ pub unsafe fn main() {
         udp_recv_mux,
         udp_port_table,
         local_ip_ifaces,
+        ip_receive.multicast_groups(),
     )
     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
 
+    let ping_driver = components::ping::PingComponent::new(
+        board_kernel,
+        capsules::net::icmpv6::DRIVER_NUM,
+        ip_send_mux,
+        icmp_recv_mux,
+        mux_alarm,
+    )
+    .finalize(components::ping_component_helper!(nrf52840::rtc::Rtc));
+
+    let coap_driver = components::coap::CoapComponent::new(
+        board_kernel,
+        capsules::net::coap::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+    )
+    .finalize(components::coap_component_helper!(nrf52840::rtc::Rtc));
+
     //--------------------------------------------------------------------------
     // FINAL SETUP AND BOARD BOOT
     //--------------------------------------------------------------------------
@@ -628,6 +659,8 @@ pub unsafe fn main() {
         rng,
         alarm,
         udp_driver,
+        ping_driver,
+        coap_driver,
         ipc: kernel::ipc::IPC::new(
             board_kernel,
             kernel::ipc::DRIVER_NUM,
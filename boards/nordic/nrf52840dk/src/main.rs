@@ -190,6 +190,14 @@ pub struct Platform {
     >,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    ping_driver: &'static capsules::net::icmpv6::PingDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
+    coap_driver: &'static capsules::net::coap::CoapDriver<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
     i2c_master_slave: &'static capsules::i2c_master_slave_driver::I2CMasterSlaveDriver<'static>,
     spi_controller: &'static capsules::spi_controller::Spi<
         'static,
@@ -217,6 +225,8 @@ impl SyscallDriverLookup for Platform {
             capsules::analog_comparator::DRIVER_NUM => f(Some(self.analog_comparator)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             capsules::i2c_master_slave_driver::DRIVER_NUM => f(Some(self.i2c_master_slave)),
             capsules::spi_controller::DRIVER_NUM => f(Some(self.spi_controller)),
@@ -495,16 +505,17 @@ pub unsafe fn main() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_send_mux, icmp_recv_mux, _, ip_receive, _) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -514,9 +525,29 @@ pub unsafe fn main() {
         udp_recv_mux,
         udp_port_table,
         local_ip_ifaces,
+        ip_receive.multicast_groups(),
     )
     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
 
+    let ping_driver = components::ping::PingComponent::new(
+        board_kernel,
+        capsules::net::icmpv6::DRIVER_NUM,
+        ip_send_mux,
+        icmp_recv_mux,
+        mux_alarm,
+    )
+    .finalize(components::ping_component_helper!(nrf52840::rtc::Rtc));
+
+    let coap_driver = components::coap::CoapComponent::new(
+        board_kernel,
+        capsules::net::coap::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+    )
+    .finalize(components::coap_component_helper!(nrf52840::rtc::Rtc));
+
     let temp = components::temperature::TemperatureComponent::new(
         board_kernel,
         capsules::temperature::DRIVER_NUM,
@@ -649,15 +680,20 @@ pub unsafe fn main() {
     // let ctap_recv_buffer = static_init!([u8; 64], [0; 64]);
 
     // let (ctap, _ctap_driver) = components::ctap::CtapComponent::new(
+    //     board_kernel,
+    //     capsules::ctap::DRIVER_NUM,
     //     &peripherals.usbd,
+    //     mux_alarm,
     //     0x1915, // Nordic Semiconductor
     //     0x503a, // lowRISC generic FS USB
     //     strings,
-    //     board_kernel,
     //     ctap_send_buffer,
     //     ctap_recv_buffer,
     // )
-    // .finalize(components::usb_ctap_component_helper!(nrf52840::usbd::Usbd));
+    // .finalize(components::usb_ctap_component_helper!(
+    //     nrf52840::usbd::Usbd,
+    //     nrf52840::rtc::Rtc
+    // ));
 
     // ctap.enable();
     // ctap.attach();
@@ -680,6 +716,8 @@ pub unsafe fn main() {
         analog_comparator,
         nonvolatile_storage,
         udp_driver,
+        ping_driver,
+        coap_driver,
         ipc: kernel::ipc::IPC::new(
             board_kernel,
             kernel::ipc::DRIVER_NUM,
@@ -0,0 +1,337 @@
+//! Implements AES-GCM authenticated encryption (NIST SP 800-38D) on top of
+//! an AES-128 block cipher in ECB mode.
+//!
+//! The underlying AES implementation is only used to encrypt blocks: the
+//! hash subkey `H = E(K, 0^128)`, the pre-counter block `J0` that masks the
+//! tag and the counter blocks that form the key stream. Counter blocks are
+//! prepared in `crypt_buf` and encrypted in batches of as many blocks as fit
+//! in it. The key stream is then XORed into the message and GHASH is computed
+//! in software.
+//!
+//! ```text
+//! crypt_buf: [ 0^128 | J0 ]                    first batch: H and the tag mask
+//! crypt_buf: [ J0 + 2 | J0 + 3 | ... | J0 + n ] following batches: key stream
+//! ```
+//!
+//! Only 96 bit nonces and 128 bit tags are supported.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let crypt_buf = static_init!([u8; 64], [0; 64]);
+//! let gcm = static_init!(
+//!     capsules::aes_gcm::Aes128Gcm<'static, sam4l::aes::Aes<'static>>,
+//!     capsules::aes_gcm::Aes128Gcm::new(&sam4l::aes::AES, crypt_buf)
+//! );
+//! sam4l::aes::AES.set_client(gcm);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{
+    AEADClient, AEAD, AEAD_TAG_LENGTH, AES128, AES128ECB, AES128GCM, AES128_BLOCK_SIZE,
+    AES128_KEY_SIZE, GCM_NONCE_LENGTH,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The reduction constant of GF(2^128), `x^128 + x^7 + x^2 + x + 1` in the
+/// bit-reflected representation GCM uses.
+const R: u128 = 0xe1 << 120;
+
+/// Multiplication in GF(2^128), without branches on the operands.
+fn gf_mul(x: u128, y: u128) -> u128 {
+    let mut z = 0;
+    let mut v = y;
+    for i in (0..128).rev() {
+        z ^= v & 0u128.wrapping_sub((x >> i) & 1);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    z
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum GCMState {
+    Idle,
+    /// Computing the hash subkey and the tag mask.
+    Init,
+    /// Encrypting counter blocks for the key stream.
+    Crypt,
+}
+
+pub struct Aes128Gcm<'a, A: AES128<'a> + AES128ECB> {
+    aes: &'a A,
+    crypt_buf: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn AEADClient>,
+
+    state: Cell<GCMState>,
+    encrypting: Cell<bool>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; GCM_NONCE_LENGTH]>,
+
+    buf: TakeCell<'static, [u8]>,
+    /// `(a_off, m_off, m_len)` of the current operation.
+    pos: Cell<(usize, usize, usize)>,
+    /// Number of message bytes already encrypted or decrypted.
+    processed: Cell<usize>,
+    /// Number of message bytes covered by the current batch of key stream.
+    batch_len: Cell<usize>,
+    counter: Cell<u32>,
+
+    h: Cell<u128>,
+    ghash: Cell<u128>,
+    tag_mask: Cell<[u8; AES128_BLOCK_SIZE]>,
+}
+
+impl<'a, A: AES128<'a> + AES128ECB> Aes128Gcm<'a, A> {
+    /// `crypt_buf` holds the counter blocks. It must be a multiple of
+    /// `AES128_BLOCK_SIZE` long and hold at least two blocks; longer buffers
+    /// need fewer calls to the AES implementation.
+    pub fn new(aes: &'a A, crypt_buf: &'static mut [u8]) -> Aes128Gcm<'a, A> {
+        Aes128Gcm {
+            aes,
+            crypt_buf: TakeCell::new(crypt_buf),
+            client: OptionalCell::empty(),
+            state: Cell::new(GCMState::Idle),
+            encrypting: Cell::new(false),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            nonce: Cell::new([0; GCM_NONCE_LENGTH]),
+            buf: TakeCell::empty(),
+            pos: Cell::new((0, 0, 0)),
+            processed: Cell::new(0),
+            batch_len: Cell::new(0),
+            counter: Cell::new(0),
+            h: Cell::new(0),
+            ghash: Cell::new(0),
+            tag_mask: Cell::new([0; AES128_BLOCK_SIZE]),
+        }
+    }
+
+    /// Write the counter block `nonce || counter` to `block`.
+    fn counter_block(&self, block: &mut [u8], counter: u32) {
+        block[..GCM_NONCE_LENGTH].copy_from_slice(&self.nonce.get());
+        block[GCM_NONCE_LENGTH..AES128_BLOCK_SIZE].copy_from_slice(&counter.to_be_bytes());
+    }
+
+    /// Feed `data` into GHASH, zero-padding the last block.
+    fn ghash_update(&self, data: &[u8]) {
+        let h = self.h.get();
+        let mut y = self.ghash.get();
+        for chunk in data.chunks(AES128_BLOCK_SIZE) {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            y = gf_mul(y ^ u128::from_be_bytes(block), h);
+        }
+        self.ghash.set(y);
+    }
+
+    /// Encrypt the first `len` bytes of `crypt_buf` in ECB mode.
+    fn encrypt_blocks(&self, len: usize) -> Result<(), ErrorCode> {
+        if self.crypt_buf.is_none() {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.aes.set_mode_aes128ecb(true)?;
+        self.aes.set_key(&self.key.get())?;
+        let crypt_buf = self.crypt_buf.take().ok_or(ErrorCode::NOMEM)?;
+        self.aes.start_message();
+        match self.aes.crypt(None, crypt_buf, 0, len) {
+            None => Ok(()),
+            Some((res, _, crypt_buf)) => {
+                self.crypt_buf.replace(crypt_buf);
+                res.and(Err(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        if self.crypt_buf.map_or(0, |cbuf| cbuf.len()) < 2 * AES128_BLOCK_SIZE {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.crypt_buf.map(|cbuf| {
+            cbuf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0);
+            self.counter_block(&mut cbuf[AES128_BLOCK_SIZE..2 * AES128_BLOCK_SIZE], 1);
+        });
+        self.encrypt_blocks(2 * AES128_BLOCK_SIZE)?;
+        self.state.set(GCMState::Init);
+        Ok(())
+    }
+
+    /// Encrypt the next batch of counter blocks, or finish if the whole
+    /// message has been processed.
+    fn next_batch(&self) {
+        let (_, _, m_len) = self.pos.get();
+        let remaining = m_len - self.processed.get();
+        if remaining == 0 {
+            self.finish(Ok(()));
+            return;
+        }
+
+        let capacity = self.crypt_buf.map_or(0, |cbuf| cbuf.len()) / AES128_BLOCK_SIZE;
+        let blocks = core::cmp::min(
+            capacity,
+            (remaining + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE,
+        );
+        self.crypt_buf.map(|cbuf| {
+            for (i, block) in cbuf.chunks_mut(AES128_BLOCK_SIZE).take(blocks).enumerate() {
+                self.counter_block(block, self.counter.get().wrapping_add(i as u32));
+            }
+        });
+        self.counter
+            .set(self.counter.get().wrapping_add(blocks as u32));
+        self.batch_len
+            .set(core::cmp::min(remaining, blocks * AES128_BLOCK_SIZE));
+
+        let res = self.encrypt_blocks(blocks * AES128_BLOCK_SIZE);
+        if res.is_err() {
+            self.finish(res);
+        }
+    }
+
+    /// Compute the tag, write or check it, and return the buffer to the
+    /// client.
+    fn finish(&self, res: Result<(), ErrorCode>) {
+        let (a_off, m_off, m_len) = self.pos.get();
+        let tag_valid = res.is_ok()
+            && self.buf.map_or(false, |buf| {
+                let lengths = ((((m_off - a_off) as u64) * 8) as u128) << 64 | (m_len as u128 * 8);
+                let y = gf_mul(self.ghash.get() ^ lengths, self.h.get());
+                let mut tag = y.to_be_bytes();
+                tag.iter_mut()
+                    .zip(self.tag_mask.get().iter())
+                    .for_each(|(t, m)| *t ^= *m);
+
+                let tag_off = m_off + m_len;
+                if self.encrypting.get() {
+                    buf[tag_off..tag_off + AEAD_TAG_LENGTH].copy_from_slice(&tag);
+                    true
+                } else {
+                    // Compare in constant time.
+                    buf[tag_off..tag_off + AEAD_TAG_LENGTH]
+                        .iter()
+                        .zip(tag.iter())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b))
+                        == 0
+                }
+            });
+
+        self.h.set(0);
+        self.ghash.set(0);
+        self.tag_mask.set([0; AES128_BLOCK_SIZE]);
+        self.state.set(GCMState::Idle);
+        self.buf.take().map(|buf| {
+            self.client.map(move |client| {
+                client.crypt_done(buf, res, tag_valid);
+            });
+        });
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128ECB> AEAD<'a> for Aes128Gcm<'a, A> {
+    fn set_client(&'a self, client: &'a dyn AEADClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() != AES128_KEY_SIZE {
+            Err(ErrorCode::INVAL)
+        } else {
+            let mut new_key = [0u8; AES128_KEY_SIZE];
+            new_key.copy_from_slice(key);
+            self.key.set(new_key);
+            Ok(())
+        }
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        if nonce.len() != GCM_NONCE_LENGTH {
+            Err(ErrorCode::INVAL)
+        } else {
+            let mut new_nonce = [0u8; GCM_NONCE_LENGTH];
+            new_nonce.copy_from_slice(nonce);
+            self.nonce.set(new_nonce);
+            Ok(())
+        }
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != GCMState::Idle {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        let end = m_off
+            .checked_add(m_len)
+            .and_then(|tag_off| tag_off.checked_add(AEAD_TAG_LENGTH));
+        if !(a_off <= m_off && end.map_or(false, |end| end <= buf.len())) {
+            return Err((ErrorCode::INVAL, buf));
+        }
+
+        self.encrypting.set(encrypting);
+        self.pos.set((a_off, m_off, m_len));
+        self.processed.set(0);
+        self.counter.set(2);
+        self.ghash.set(0);
+        match self.start() {
+            Ok(()) => {
+                self.buf.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128ECB> AES128GCM<'a> for Aes128Gcm<'a, A> {}
+
+impl<'a, A: AES128<'a> + AES128ECB> symmetric_encryption::Client<'a> for Aes128Gcm<'a, A> {
+    fn crypt_done(&'a self, _: Option<&'static mut [u8]>, crypt_buf: &'static mut [u8]) {
+        match self.state.get() {
+            GCMState::Idle => {
+                self.crypt_buf.replace(crypt_buf);
+            }
+            GCMState::Init => {
+                let mut h = [0; AES128_BLOCK_SIZE];
+                let mut tag_mask = [0; AES128_BLOCK_SIZE];
+                h.copy_from_slice(&crypt_buf[..AES128_BLOCK_SIZE]);
+                tag_mask.copy_from_slice(&crypt_buf[AES128_BLOCK_SIZE..2 * AES128_BLOCK_SIZE]);
+                self.crypt_buf.replace(crypt_buf);
+                self.h.set(u128::from_be_bytes(h));
+                self.tag_mask.set(tag_mask);
+
+                let (a_off, m_off, _) = self.pos.get();
+                self.buf.map(|buf| self.ghash_update(&buf[a_off..m_off]));
+                self.state.set(GCMState::Crypt);
+                self.next_batch();
+            }
+            GCMState::Crypt => {
+                let (_, m_off, _) = self.pos.get();
+                let start = m_off + self.processed.get();
+                let len = self.batch_len.get();
+                self.buf.map(|buf| {
+                    let data = &mut buf[start..start + len];
+                    if !self.encrypting.get() {
+                        self.ghash_update(data);
+                    }
+                    data.iter_mut()
+                        .zip(crypt_buf.iter())
+                        .for_each(|(d, k)| *d ^= *k);
+                    if self.encrypting.get() {
+                        self.ghash_update(data);
+                    }
+                });
+                self.crypt_buf.replace(crypt_buf);
+                self.processed.set(self.processed.get() + len);
+                self.next_batch();
+            }
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! Software implementation of AES-128 (FIPS 197) in ECB, CBC and CTR modes.
+//!
+//! This capsule implements `hil::symmetric_encryption::AES128`, `AES128Ctr`,
+//! `AES128CBC` and `AES128ECB` entirely in software, so that the AES users
+//! (`symmetric_encryption::aes`, `virtual_aes_ccm`, `aes_gcm` and 802.15.4
+//! link security) can run on chips without an AES peripheral.
+//!
+//! The implementation does not use lookup tables and does not branch on
+//! secret data, so that its timing does not depend on the key or the data.
+//! The S-box is computed as the inverse in GF(2^8) followed by the affine
+//! transformation. All 16 bytes of the state are processed at once, packed
+//! into a `u128` with one byte per lane.
+//!
+//! Like `sha_software`, all operations complete asynchronously from a
+//! deferred call, and long buffers are processed a few blocks at a time,
+//! re-scheduling the deferred call in between.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let aes = static_init!(
+//!     capsules::aes_software::Aes128Software<'static>,
+//!     capsules::aes_software::Aes128Software::new(dynamic_deferred_caller)
+//! );
+//! aes.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(aes).unwrap(), // Unwrap fail = no deferred call slot available for AES
+//! );
+//! ```
+
+use core::cell::Cell;
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, Client, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Number of blocks processed per deferred call.
+const BLOCKS_PER_CALL: usize = 8;
+
+const ROUNDS: usize = 10;
+const ROUND_KEYS_SIZE: usize = (ROUNDS + 1) * AES128_BLOCK_SIZE;
+
+type Block = [u8; AES128_BLOCK_SIZE];
+
+/// Byte lanes of a `u128`.
+const LANES_01: u128 = 0x01010101_01010101_01010101_01010101;
+const LANES_7F: u128 = 0x7f7f7f7f_7f7f7f7f_7f7f7f7f_7f7f7f7f;
+
+/// Multiply every byte lane by `x` in GF(2^8).
+fn xtime_lanes(a: u128) -> u128 {
+    ((a & LANES_7F) << 1) ^ (((a >> 7) & LANES_01) * 0x1b)
+}
+
+/// Multiply the byte lanes of `a` and `b` in GF(2^8).
+fn mul_lanes(mut a: u128, b: u128) -> u128 {
+    let mut p = 0;
+    for i in 0..8 {
+        p ^= a & (((b >> i) & LANES_01) * 0xff);
+        a = xtime_lanes(a);
+    }
+    p
+}
+
+/// Invert every byte lane in GF(2^8), computed as `a^254` so that zero maps
+/// to zero.
+fn inv_lanes(a: u128) -> u128 {
+    let a2 = mul_lanes(a, a);
+    let a3 = mul_lanes(a2, a);
+    let a6 = mul_lanes(a3, a3);
+    let a12 = mul_lanes(a6, a6);
+    let a15 = mul_lanes(a12, a3);
+    let a30 = mul_lanes(a15, a15);
+    let a60 = mul_lanes(a30, a30);
+    let a120 = mul_lanes(a60, a60);
+    let a240 = mul_lanes(a120, a120);
+    let a252 = mul_lanes(a240, a12);
+    mul_lanes(a252, a2)
+}
+
+/// Rotate every byte lane left by `n` bits.
+fn rotl_lanes(a: u128, n: u32) -> u128 {
+    let low = LANES_01 * ((1 << n) - 1);
+    ((a << n) & !low) | ((a >> (8 - n)) & low)
+}
+
+fn sub_lanes(a: u128) -> u128 {
+    let b = inv_lanes(a);
+    b ^ rotl_lanes(b, 1)
+        ^ rotl_lanes(b, 2)
+        ^ rotl_lanes(b, 3)
+        ^ rotl_lanes(b, 4)
+        ^ (LANES_01 * 0x63)
+}
+
+fn inv_sub_lanes(a: u128) -> u128 {
+    inv_lanes(rotl_lanes(a, 1) ^ rotl_lanes(a, 3) ^ rotl_lanes(a, 6) ^ (LANES_01 * 0x05))
+}
+
+fn sub_bytes(s: &mut Block) {
+    *s = sub_lanes(u128::from_be_bytes(*s)).to_be_bytes();
+}
+
+fn inv_sub_bytes(s: &mut Block) {
+    *s = inv_sub_lanes(u128::from_be_bytes(*s)).to_be_bytes();
+}
+
+fn shift_rows(s: &mut Block) {
+    let t = *s;
+    for c in 0..4 {
+        for r in 0..4 {
+            s[4 * c + r] = t[4 * ((c + r) % 4) + r];
+        }
+    }
+}
+
+fn inv_shift_rows(s: &mut Block) {
+    let t = *s;
+    for c in 0..4 {
+        for r in 0..4 {
+            s[4 * ((c + r) % 4) + r] = t[4 * c + r];
+        }
+    }
+}
+
+/// The state with every byte multiplied by `x`, `x^2` and `x^3`.
+fn xtimes(s: &Block) -> (Block, Block, Block) {
+    let x1 = xtime_lanes(u128::from_be_bytes(*s));
+    let x2 = xtime_lanes(x1);
+    let x3 = xtime_lanes(x2);
+    (x1.to_be_bytes(), x2.to_be_bytes(), x3.to_be_bytes())
+}
+
+fn mix_columns(s: &mut Block) {
+    let (x, _, _) = xtimes(s);
+    let t = *s;
+    for c in 0..4 {
+        let i = 4 * c;
+        for r in 0..4 {
+            let (r1, r2, r3) = (i + (r + 1) % 4, i + (r + 2) % 4, i + (r + 3) % 4);
+            // 2 * a[r] + 3 * a[r + 1] + a[r + 2] + a[r + 3]
+            s[i + r] = x[i + r] ^ x[r1] ^ t[r1] ^ t[r2] ^ t[r3];
+        }
+    }
+}
+
+fn inv_mix_columns(s: &mut Block) {
+    let (x, x2, x3) = xtimes(s);
+    let t = *s;
+    // Multiples of each byte by 9, 11, 13 and 14.
+    let m9 = |i: usize| x3[i] ^ t[i];
+    let m11 = |i: usize| x3[i] ^ x[i] ^ t[i];
+    let m13 = |i: usize| x3[i] ^ x2[i] ^ t[i];
+    let m14 = |i: usize| x3[i] ^ x2[i] ^ x[i];
+    for c in 0..4 {
+        let i = 4 * c;
+        for r in 0..4 {
+            let (r1, r2, r3) = (i + (r + 1) % 4, i + (r + 2) % 4, i + (r + 3) % 4);
+            s[i + r] = m14(i + r) ^ m11(r1) ^ m13(r2) ^ m9(r3);
+        }
+    }
+}
+
+fn add_round_key(s: &mut Block, round_key: &[u8]) {
+    s.iter_mut()
+        .zip(round_key.iter())
+        .for_each(|(b, k)| *b ^= *k);
+}
+
+fn expand_key(key: &[u8; AES128_KEY_SIZE]) -> [u8; ROUND_KEYS_SIZE] {
+    let mut w = [0; ROUND_KEYS_SIZE];
+    w[..AES128_KEY_SIZE].copy_from_slice(key);
+    let mut rcon = 1u8;
+    for i in 4..4 * (ROUNDS + 1) {
+        let mut t = [w[4 * i - 4], w[4 * i - 3], w[4 * i - 2], w[4 * i - 1]];
+        if i % 4 == 0 {
+            let s = sub_lanes(u32::from_be_bytes(t) as u128) as u32;
+            t = s.rotate_left(8).to_be_bytes();
+            t[0] ^= rcon;
+            rcon = xtime_lanes(rcon as u128) as u8;
+        }
+        for j in 0..4 {
+            w[4 * i + j] = w[4 * i - 16 + j] ^ t[j];
+        }
+    }
+    w
+}
+
+fn encrypt_block(round_keys: &[u8; ROUND_KEYS_SIZE], s: &mut Block) {
+    add_round_key(s, &round_keys[..AES128_BLOCK_SIZE]);
+    for round in 1..=ROUNDS {
+        sub_bytes(s);
+        shift_rows(s);
+        if round != ROUNDS {
+            mix_columns(s);
+        }
+        add_round_key(s, &round_keys[round * AES128_BLOCK_SIZE..]);
+    }
+}
+
+fn decrypt_block(round_keys: &[u8; ROUND_KEYS_SIZE], s: &mut Block) {
+    add_round_key(s, &round_keys[ROUNDS * AES128_BLOCK_SIZE..]);
+    for round in (0..ROUNDS).rev() {
+        inv_shift_rows(s);
+        inv_sub_bytes(s);
+        add_round_key(s, &round_keys[round * AES128_BLOCK_SIZE..]);
+        if round != 0 {
+            inv_mix_columns(s);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Mode {
+    Ecb,
+    Cbc,
+    Ctr,
+}
+
+pub struct Aes128Software<'a> {
+    client: OptionalCell<&'a dyn Client<'a>>,
+    mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+    round_keys: Cell<[u8; ROUND_KEYS_SIZE]>,
+    iv: Cell<Block>,
+    /// The CBC chaining value or the CTR counter of the current message.
+    chain: Cell<Block>,
+
+    source: TakeCell<'static, [u8]>,
+    dest: TakeCell<'static, [u8]>,
+    /// `(start_index, stop_index)` of the current operation in `dest`.
+    range: Cell<(usize, usize)>,
+    /// Number of bytes already processed.
+    processed: Cell<usize>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> Aes128Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> Aes128Software<'a> {
+        Aes128Software {
+            client: OptionalCell::empty(),
+            mode: Cell::new(Mode::Ecb),
+            encrypting: Cell::new(true),
+            round_keys: Cell::new([0; ROUND_KEYS_SIZE]),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
+            chain: Cell::new([0; AES128_BLOCK_SIZE]),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            range: Cell::new((0, 0)),
+            processed: Cell::new(0),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn busy(&self) -> bool {
+        self.dest.is_some()
+    }
+
+    /// Encrypt or decrypt one block in the current mode.
+    fn crypt_block(&self, round_keys: &[u8; ROUND_KEYS_SIZE], block: &mut Block) {
+        let mut chain = self.chain.get();
+        match (self.mode.get(), self.encrypting.get()) {
+            (Mode::Ecb, true) => encrypt_block(round_keys, block),
+            (Mode::Ecb, false) => decrypt_block(round_keys, block),
+            (Mode::Cbc, true) => {
+                add_round_key(block, &chain);
+                encrypt_block(round_keys, block);
+                chain = *block;
+            }
+            (Mode::Cbc, false) => {
+                let next = *block;
+                decrypt_block(round_keys, block);
+                add_round_key(block, &chain);
+                chain = next;
+            }
+            (Mode::Ctr, _) => {
+                let mut key_stream = chain;
+                encrypt_block(round_keys, &mut key_stream);
+                add_round_key(block, &key_stream);
+                chain = u128::from_be_bytes(chain).wrapping_add(1).to_be_bytes();
+            }
+        }
+        self.chain.set(chain);
+    }
+
+    /// Process the next blocks of the current operation. Returns true once
+    /// the whole range has been processed.
+    fn crypt_step(&self, dest: &mut [u8]) -> bool {
+        let (start, stop) = self.range.get();
+        let processed = self.processed.get();
+        let len = core::cmp::min(
+            BLOCKS_PER_CALL * AES128_BLOCK_SIZE,
+            stop - start - processed,
+        );
+        let round_keys = self.round_keys.get();
+
+        for offset in (processed..processed + len).step_by(AES128_BLOCK_SIZE) {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            let from_source = self
+                .source
+                .map(|source| block.copy_from_slice(&source[offset..offset + AES128_BLOCK_SIZE]))
+                .is_some();
+            if !from_source {
+                block.copy_from_slice(&dest[start + offset..start + offset + AES128_BLOCK_SIZE]);
+            }
+            self.crypt_block(&round_keys, &mut block);
+            dest[start + offset..start + offset + AES128_BLOCK_SIZE].copy_from_slice(&block);
+        }
+
+        self.processed.set(processed + len);
+        processed + len == stop - start
+    }
+}
+
+impl<'a> AES128<'a> for Aes128Software<'a> {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn set_client(&'a self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() != AES128_KEY_SIZE {
+            Err(ErrorCode::INVAL)
+        } else if self.busy() {
+            Err(ErrorCode::BUSY)
+        } else {
+            let mut new_key = [0; AES128_KEY_SIZE];
+            new_key.copy_from_slice(key);
+            self.round_keys.set(expand_key(&new_key));
+            Ok(())
+        }
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> Result<(), ErrorCode> {
+        if iv.len() != AES128_BLOCK_SIZE {
+            Err(ErrorCode::INVAL)
+        } else if self.busy() {
+            Err(ErrorCode::BUSY)
+        } else {
+            let mut new_iv = [0; AES128_BLOCK_SIZE];
+            new_iv.copy_from_slice(iv);
+            self.iv.set(new_iv);
+            Ok(())
+        }
+    }
+
+    fn start_message(&self) {
+        if self.busy() {
+            return;
+        }
+        self.chain.set(self.iv.get());
+    }
+
+    fn crypt(
+        &self,
+        source: Option<&'static mut [u8]>,
+        dest: &'static mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(
+        Result<(), ErrorCode>,
+        Option<&'static mut [u8]>,
+        &'static mut [u8],
+    )> {
+        if self.busy() {
+            return Some((Err(ErrorCode::BUSY), source, dest));
+        }
+        let valid = start_index <= stop_index
+            && stop_index <= dest.len()
+            && (stop_index - start_index) % AES128_BLOCK_SIZE == 0
+            && source
+                .as_ref()
+                .map_or(true, |source| source.len() == stop_index - start_index);
+        if !valid {
+            return Some((Err(ErrorCode::INVAL), source, dest));
+        }
+
+        self.range.set((start_index, stop_index));
+        self.processed.set(0);
+        self.source.put(source);
+        self.dest.replace(dest);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        None
+    }
+}
+
+impl AES128Ctr for Aes128Software<'_> {
+    fn set_mode_aes128ctr(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Ctr);
+        self.encrypting.set(encrypting);
+        Ok(())
+    }
+}
+
+impl AES128CBC for Aes128Software<'_> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Cbc);
+        self.encrypting.set(encrypting);
+        Ok(())
+    }
+}
+
+impl AES128ECB for Aes128Software<'_> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Ecb);
+        self.encrypting.set(encrypting);
+        Ok(())
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for Aes128Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        let done = self.dest.map_or(false, |dest| self.crypt_step(dest));
+        if !done {
+            if self.busy() {
+                self.handle.map(|handle| self.deferred_caller.set(*handle));
+            }
+            return;
+        }
+
+        let source = self.source.take();
+        self.dest.take().map(|dest| {
+            self.client.map(move |client| {
+                client.crypt_done(source, dest);
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::aes::{CTXT_CBC, CTXT_CTR, CTXT_ECB, IV_CBC, IV_CTR, KEY, PTXT};
+    use crate::test::leak;
+    use kernel::dynamic_deferred_call::DynamicDeferredCallClientState;
+
+    const OFFSET: usize = AES128_BLOCK_SIZE;
+
+    struct TestClient {
+        source: TakeCell<'static, [u8]>,
+        dest: TakeCell<'static, [u8]>,
+        done: Cell<bool>,
+    }
+
+    impl<'a> Client<'a> for TestClient {
+        fn crypt_done(&'a self, source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+            self.source.put(source);
+            self.dest.replace(dest);
+            self.done.set(true);
+        }
+    }
+
+    fn setup() -> (
+        &'static Aes128Software<'static>,
+        &'static TestClient,
+        DeferredCallHandle,
+    ) {
+        let state = leak([DynamicDeferredCallClientState::default()]);
+        let deferred_caller = leak(DynamicDeferredCall::new(state));
+        let aes = leak(Aes128Software::new(deferred_caller));
+        let handle = deferred_caller.register(aes).unwrap();
+        aes.initialize_callback_handle(handle);
+        let client = leak(TestClient {
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            done: Cell::new(false),
+        });
+        aes.set_client(client);
+        (aes, client, handle)
+    }
+
+    /// Run one operation on `input`, either in place or from a separate
+    /// source buffer, and return the output.
+    fn run(
+        aes: &Aes128Software<'static>,
+        client: &TestClient,
+        handle: DeferredCallHandle,
+        input: &[u8],
+        use_source: bool,
+    ) -> [u8; 4 * AES128_BLOCK_SIZE] {
+        let dest = leak([0u8; 6 * AES128_BLOCK_SIZE]);
+        let source = if use_source {
+            let source = leak([0u8; 4 * AES128_BLOCK_SIZE]);
+            source.copy_from_slice(input);
+            Some(&mut source[..])
+        } else {
+            dest[OFFSET..OFFSET + input.len()].copy_from_slice(input);
+            None
+        };
+
+        client.done.set(false);
+        assert!(aes
+            .crypt(source, dest, OFFSET, OFFSET + input.len())
+            .is_none());
+        while !client.done.get() {
+            aes.call(handle);
+        }
+
+        assert_eq!(client.source.take().is_some(), use_source);
+        let dest = client.dest.take().unwrap();
+        assert_eq!(dest[..OFFSET], [0; OFFSET]);
+        assert!(dest[OFFSET + input.len()..].iter().all(|b| *b == 0));
+        let mut output = [0; 4 * AES128_BLOCK_SIZE];
+        output[..input.len()].copy_from_slice(&dest[OFFSET..OFFSET + input.len()]);
+        output
+    }
+
+    #[test]
+    fn test_fips197_block() {
+        let mut key = [0; AES128_KEY_SIZE];
+        key.iter_mut().enumerate().for_each(|(i, k)| *k = i as u8);
+        let mut block = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        let round_keys = expand_key(&key);
+        encrypt_block(&round_keys, &mut block);
+        assert_eq!(
+            block,
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a
+            ]
+        );
+        decrypt_block(&round_keys, &mut block);
+        assert_eq!(block[..4], [0x00, 0x11, 0x22, 0x33]);
+    }
+
+    #[test]
+    fn test_modes() {
+        let (aes, client, handle) = setup();
+        assert_eq!(aes.set_key(&KEY), Ok(()));
+
+        for use_source in [false, true] {
+            for encrypting in [true, false] {
+                let (input, expected) = if encrypting {
+                    (PTXT, CTXT_ECB)
+                } else {
+                    (CTXT_ECB, PTXT)
+                };
+                aes.set_mode_aes128ecb(encrypting).unwrap();
+                aes.start_message();
+                assert_eq!(run(aes, client, handle, &input, use_source), expected);
+
+                let (input, expected) = if encrypting {
+                    (PTXT, CTXT_CBC)
+                } else {
+                    (CTXT_CBC, PTXT)
+                };
+                aes.set_mode_aes128cbc(encrypting).unwrap();
+                aes.set_iv(&IV_CBC).unwrap();
+                aes.start_message();
+                assert_eq!(run(aes, client, handle, &input, use_source), expected);
+
+                let (input, expected) = if encrypting {
+                    (PTXT, CTXT_CTR)
+                } else {
+                    (CTXT_CTR, PTXT)
+                };
+                aes.set_mode_aes128ctr(encrypting).unwrap();
+                aes.set_iv(&IV_CTR).unwrap();
+                aes.start_message();
+                assert_eq!(run(aes, client, handle, &input, use_source), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_message_continues_across_calls() {
+        let (aes, client, handle) = setup();
+        assert_eq!(aes.set_key(&KEY), Ok(()));
+        aes.set_mode_aes128cbc(true).unwrap();
+        aes.set_iv(&IV_CBC).unwrap();
+        aes.start_message();
+
+        let half = 2 * AES128_BLOCK_SIZE;
+        let first = run(aes, client, handle, &PTXT[..half], false);
+        let second = run(aes, client, handle, &PTXT[half..], false);
+        assert_eq!(first[..half], CTXT_CBC[..half]);
+        assert_eq!(second[..half], CTXT_CBC[half..]);
+    }
+
+    #[test]
+    fn test_invalid_and_busy() {
+        let (aes, client, handle) = setup();
+        assert_eq!(aes.set_key(&KEY[..8]), Err(ErrorCode::INVAL));
+        assert_eq!(aes.set_iv(&IV_CBC[..8]), Err(ErrorCode::INVAL));
+
+        let res = aes.crypt(None, leak([0u8; 32]), 0, 20);
+        assert_eq!(res.map(|(res, _, _)| res), Some(Err(ErrorCode::INVAL)));
+        let res = aes.crypt(Some(leak([0u8; 32])), leak([0u8; 32]), 0, 16);
+        assert_eq!(res.map(|(res, _, _)| res), Some(Err(ErrorCode::INVAL)));
+
+        assert!(aes.crypt(None, leak([0u8; 32]), 0, 32).is_none());
+        let res = aes.crypt(None, leak([0u8; 32]), 0, 32);
+        assert_eq!(res.map(|(res, _, _)| res), Some(Err(ErrorCode::BUSY)));
+        assert_eq!(aes.set_key(&KEY), Err(ErrorCode::BUSY));
+        aes.call(handle);
+        assert!(client.done.get());
+    }
+}
@@ -0,0 +1,753 @@
+//! Generic Attribute Profile server of a Bluetooth Low Energy peripheral.
+//!
+//! The server holds an attribute table of primary services and their
+//! characteristics, and answers the attribute protocol requests of the
+//! connected client, which discover the table and read and write values.
+//! Characteristics that can notify get a client characteristic configuration
+//! descriptor, and `set_value` sends a notification when the client enabled
+//! them.
+//!
+//! Attribute values live in a buffer given to the server, and the table holds
+//! at most `MAX_ATTRIBUTES` attributes. Each service takes one attribute, and
+//! each characteristic two, or three if it can notify.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F] and [Vol 3, Part G]
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use super::l2cap::{AttChannel, AttChannelClient, ATT_MTU};
+
+pub const MAX_ATTRIBUTES: usize = 48;
+/// The maximum length of a characteristic value.
+pub const MAX_VALUE_LEN: usize = 64;
+
+/// Characteristic properties
+pub const PROPERTY_READ: u8 = 0x02;
+pub const PROPERTY_WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+pub const PROPERTY_WRITE: u8 = 0x08;
+pub const PROPERTY_NOTIFY: u8 = 0x10;
+
+// Attribute types
+const PRIMARY_SERVICE: u16 = 0x2800;
+const CHARACTERISTIC: u16 = 0x2803;
+const CLIENT_CHARACTERISTIC_CONFIGURATION: u16 = 0x2902;
+
+// Attribute protocol opcodes
+const ERROR_RSP: u8 = 0x01;
+const EXCHANGE_MTU_REQ: u8 = 0x02;
+const EXCHANGE_MTU_RSP: u8 = 0x03;
+const FIND_INFORMATION_REQ: u8 = 0x04;
+const FIND_INFORMATION_RSP: u8 = 0x05;
+const FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+const FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+const READ_BY_TYPE_REQ: u8 = 0x08;
+const READ_BY_TYPE_RSP: u8 = 0x09;
+const READ_REQ: u8 = 0x0a;
+const READ_RSP: u8 = 0x0b;
+const READ_BLOB_REQ: u8 = 0x0c;
+const READ_BLOB_RSP: u8 = 0x0d;
+const READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+const READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+const WRITE_REQ: u8 = 0x12;
+const WRITE_RSP: u8 = 0x13;
+const HANDLE_VALUE_NTF: u8 = 0x1b;
+const WRITE_CMD: u8 = 0x52;
+const COMMAND_FLAG: u8 = 0x40;
+
+// Attribute protocol error codes
+const INVALID_HANDLE: u8 = 0x01;
+const READ_NOT_PERMITTED: u8 = 0x02;
+const WRITE_NOT_PERMITTED: u8 = 0x03;
+const INVALID_PDU: u8 = 0x04;
+const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+const INVALID_OFFSET: u8 = 0x07;
+const ATTRIBUTE_NOT_FOUND: u8 = 0x0a;
+const INVALID_ATTRIBUTE_VALUE_LENGTH: u8 = 0x0d;
+const UNSUPPORTED_GROUP_TYPE: u8 = 0x10;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// Parse a little-endian UUID of 2 or 16 bytes.
+    pub fn from_slice(bytes: &[u8]) -> Option<Uuid> {
+        match bytes.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(bytes);
+                Some(Uuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Write the UUID to `buf` in little-endian, and return its length.
+    fn write_to(&self, buf: &mut [u8]) -> usize {
+        match self {
+            Uuid::Uuid16(uuid) => buf[..2].copy_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => buf[..16].copy_from_slice(uuid),
+        }
+        self.len()
+    }
+}
+
+pub trait GattServerClient {
+    fn connected(&self);
+    /// The connection was closed, with `reason` an HCI error code.
+    fn disconnected(&self, reason: u8);
+    /// The client wrote `value` to the characteristic value `handle`.
+    fn value_written(&self, handle: u16, value: &[u8]);
+}
+
+#[derive(Copy, Clone)]
+struct Attribute {
+    uuid: Uuid,
+    readable: bool,
+    writable: bool,
+    /// The attribute is the value of a characteristic.
+    characteristic: bool,
+    /// The value is followed by its client characteristic configuration.
+    notify: bool,
+    offset: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl Attribute {
+    const EMPTY: Attribute = Attribute {
+        uuid: Uuid::Uuid16(0),
+        readable: false,
+        writable: false,
+        characteristic: false,
+        notify: false,
+        offset: 0,
+        len: 0,
+        capacity: 0,
+    };
+}
+
+fn error(rsp: &mut [u8], opcode: u8, handle: u16, code: u8) -> usize {
+    rsp[0] = ERROR_RSP;
+    rsp[1] = opcode;
+    rsp[2..4].copy_from_slice(&handle.to_le_bytes());
+    rsp[4] = code;
+    5
+}
+
+pub struct GattServer<'a> {
+    att: &'a dyn AttChannel<'a>,
+    client: OptionalCell<&'a dyn GattServerClient>,
+    attributes: MapCell<[Attribute; MAX_ATTRIBUTES]>,
+    num_attributes: Cell<usize>,
+    values: TakeCell<'static, [u8]>,
+    values_len: Cell<usize>,
+    connected: Cell<bool>,
+    /// A response waiting for a notification to be sent.
+    pending: Cell<Option<([u8; ATT_MTU], usize)>>,
+}
+
+impl<'a> GattServer<'a> {
+    pub fn new(att: &'a dyn AttChannel<'a>, values: &'static mut [u8]) -> GattServer<'a> {
+        GattServer {
+            att,
+            client: OptionalCell::empty(),
+            attributes: MapCell::new([Attribute::EMPTY; MAX_ATTRIBUTES]),
+            num_attributes: Cell::new(0),
+            values: TakeCell::new(values),
+            values_len: Cell::new(0),
+            connected: Cell::new(false),
+            pending: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn GattServerClient) {
+        self.client.set(client);
+    }
+
+    /// Add a primary service, and return its handle. The characteristics
+    /// added next belong to it.
+    pub fn add_service(&self, uuid: Uuid) -> Result<u16, ErrorCode> {
+        let mut value = [0; 16];
+        let len = uuid.write_to(&mut value);
+        self.push(
+            Uuid::Uuid16(PRIMARY_SERVICE),
+            (true, false, false, false),
+            &value[..len],
+            len,
+        )
+    }
+
+    /// Add a characteristic to the last service, with room for `max_len`
+    /// bytes of value, and return the handle of its value.
+    pub fn add_characteristic(
+        &self,
+        uuid: Uuid,
+        properties: u8,
+        max_len: usize,
+    ) -> Result<u16, ErrorCode> {
+        if self.num_attributes.get() == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if max_len > MAX_VALUE_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.connected.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let value_handle = self.num_attributes.get() as u16 + 2;
+        let mut declaration = [0; 19];
+        declaration[0] = properties;
+        declaration[1..3].copy_from_slice(&value_handle.to_le_bytes());
+        let len = 3 + uuid.write_to(&mut declaration[3..]);
+
+        // Check that all the attributes fit before adding any of them.
+        let notify = properties & PROPERTY_NOTIFY != 0;
+        let (needed, needed_len) = if notify {
+            (3, len + max_len + 2)
+        } else {
+            (2, len + max_len)
+        };
+        if self.num_attributes.get() + needed > MAX_ATTRIBUTES
+            || self.values_len.get() + needed_len > self.values.map_or(0, |v| v.len())
+        {
+            return Err(ErrorCode::NOMEM);
+        }
+
+        self.push(
+            Uuid::Uuid16(CHARACTERISTIC),
+            (true, false, false, false),
+            &declaration[..len],
+            len,
+        )?;
+        let writable = properties & (PROPERTY_WRITE | PROPERTY_WRITE_WITHOUT_RESPONSE) != 0;
+        self.push(
+            uuid,
+            (properties & PROPERTY_READ != 0, writable, true, notify),
+            &[],
+            max_len,
+        )?;
+        if notify {
+            self.push(
+                Uuid::Uuid16(CLIENT_CHARACTERISTIC_CONFIGURATION),
+                (true, true, false, false),
+                &[0, 0],
+                2,
+            )?;
+        }
+        Ok(value_handle)
+    }
+
+    /// Set the value of a characteristic, and notify the client if it asked
+    /// for it. `handle` is the value handle returned by `add_characteristic`.
+    pub fn set_value(&self, handle: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        let attribute = self
+            .attribute(handle)
+            .filter(|attribute| attribute.characteristic)
+            .ok_or(ErrorCode::INVAL)?;
+        if value.len() > attribute.capacity {
+            return Err(ErrorCode::SIZE);
+        }
+        self.store(handle, value);
+        if attribute.notify && self.connected.get() && self.notifications_enabled(handle) {
+            let mut ntf = [0; ATT_MTU];
+            ntf[0] = HANDLE_VALUE_NTF;
+            ntf[1..3].copy_from_slice(&handle.to_le_bytes());
+            let len = cmp::min(value.len(), ATT_MTU - 3);
+            ntf[3..3 + len].copy_from_slice(&value[..len]);
+            self.att.send(&ntf[..3 + len])?;
+        }
+        Ok(())
+    }
+
+    /// Remove all services.
+    pub fn clear(&self) -> Result<(), ErrorCode> {
+        if self.connected.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.num_attributes.set(0);
+        self.values_len.set(0);
+        Ok(())
+    }
+
+    fn push(
+        &self,
+        uuid: Uuid,
+        (readable, writable, characteristic, notify): (bool, bool, bool, bool),
+        value: &[u8],
+        capacity: usize,
+    ) -> Result<u16, ErrorCode> {
+        if self.connected.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let index = self.num_attributes.get();
+        let offset = self.values_len.get();
+        if index >= MAX_ATTRIBUTES || offset + capacity > self.values.map_or(0, |v| v.len()) {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.attributes.map(|attributes| {
+            attributes[index] = Attribute {
+                uuid,
+                readable,
+                writable,
+                characteristic,
+                notify,
+                offset,
+                len: 0,
+                capacity,
+            };
+        });
+        self.num_attributes.set(index + 1);
+        self.values_len.set(offset + capacity);
+        let handle = index as u16 + 1;
+        self.store(handle, value);
+        Ok(handle)
+    }
+
+    fn attribute(&self, handle: u16) -> Option<Attribute> {
+        let index = (handle as usize).checked_sub(1)?;
+        if index >= self.num_attributes.get() {
+            return None;
+        }
+        self.attributes.map(|attributes| attributes[index])
+    }
+
+    fn store(&self, handle: u16, value: &[u8]) {
+        let index = handle as usize - 1;
+        self.attributes.map(|attributes| {
+            let attribute = &mut attributes[index];
+            attribute.len = value.len();
+            self.values.map(|values| {
+                values[attribute.offset..attribute.offset + value.len()].copy_from_slice(value);
+            });
+        });
+    }
+
+    /// Copy the value of `attribute` from `offset` to `buf`, and return the
+    /// number of bytes copied.
+    fn read(&self, attribute: &Attribute, offset: usize, buf: &mut [u8]) -> usize {
+        let len = cmp::min(attribute.len.saturating_sub(offset), buf.len());
+        self.values.map(|values| {
+            let start = attribute.offset + offset;
+            buf[..len].copy_from_slice(&values[start..start + len]);
+        });
+        len
+    }
+
+    fn notifications_enabled(&self, handle: u16) -> bool {
+        let mut config = [0; 2];
+        self.attribute(handle + 1).map_or(false, |cccd| {
+            self.read(&cccd, 0, &mut config) == 2 && config[0] & 1 != 0
+        })
+    }
+
+    /// The last handle of the group that starts at `handle`: the services
+    /// are groups, other attributes are on their own.
+    fn group_end(&self, handle: u16, attribute: &Attribute) -> u16 {
+        if attribute.uuid != Uuid::Uuid16(PRIMARY_SERVICE) {
+            return handle;
+        }
+        let num = self.num_attributes.get() as u16;
+        ((handle + 1)..=num)
+            .find(|&h| {
+                self.attribute(h)
+                    .map_or(false, |a| a.uuid == Uuid::Uuid16(PRIMARY_SERVICE))
+            })
+            .map_or(num, |next| next - 1)
+    }
+
+    /// The valid handles of a request for `start..=end`.
+    fn handle_range(&self, start: u16, end: u16) -> Option<core::ops::RangeInclusive<u16>> {
+        if start == 0 || start > end {
+            return None;
+        }
+        Some(start..=cmp::min(end, self.num_attributes.get() as u16))
+    }
+
+    /// Handle an attribute protocol request, write the response to `rsp`
+    /// and return its length, which is 0 if there is no response.
+    fn handle_request(&self, req: &[u8], rsp: &mut [u8; ATT_MTU]) -> usize {
+        let opcode = match req.first() {
+            Some(opcode) => *opcode,
+            None => return 0,
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([req[i], req[i + 1]]);
+        match opcode {
+            EXCHANGE_MTU_REQ if req.len() == 3 => {
+                rsp[0] = EXCHANGE_MTU_RSP;
+                rsp[1..3].copy_from_slice(&(ATT_MTU as u16).to_le_bytes());
+                3
+            }
+            FIND_INFORMATION_REQ if req.len() == 5 => {
+                let (start, end) = (u16_at(1), u16_at(3));
+                let range = match self.handle_range(start, end) {
+                    Some(range) => range,
+                    None => return error(rsp, opcode, start, INVALID_HANDLE),
+                };
+                let mut len = 2;
+                let mut uuid_len = 0;
+                for handle in range {
+                    let attribute = match self.attribute(handle) {
+                        Some(attribute) => attribute,
+                        None => break,
+                    };
+                    if uuid_len == 0 {
+                        uuid_len = attribute.uuid.len();
+                    }
+                    if attribute.uuid.len() != uuid_len || len + 2 + uuid_len > ATT_MTU {
+                        break;
+                    }
+                    rsp[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                    attribute.uuid.write_to(&mut rsp[len + 2..]);
+                    len += 2 + uuid_len;
+                }
+                if uuid_len == 0 {
+                    return error(rsp, opcode, start, ATTRIBUTE_NOT_FOUND);
+                }
+                rsp[0] = FIND_INFORMATION_RSP;
+                rsp[1] = if uuid_len == 2 { 1 } else { 2 };
+                len
+            }
+            FIND_BY_TYPE_VALUE_REQ if req.len() >= 7 => {
+                let (start, end) = (u16_at(1), u16_at(3));
+                let range = match self.handle_range(start, end) {
+                    Some(range) => range,
+                    None => return error(rsp, opcode, start, INVALID_HANDLE),
+                };
+                let (uuid, value) = (Uuid::Uuid16(u16_at(5)), &req[7..]);
+                let mut len = 1;
+                let mut buf = [0; MAX_VALUE_LEN];
+                for handle in range {
+                    let attribute = match self.attribute(handle) {
+                        Some(attribute) => attribute,
+                        None => break,
+                    };
+                    if attribute.uuid != uuid || self.read(&attribute, 0, &mut buf) != value.len() {
+                        continue;
+                    }
+                    if buf[..value.len()] != *value || len + 4 > ATT_MTU {
+                        continue;
+                    }
+                    let group_end = self.group_end(handle, &attribute);
+                    rsp[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                    rsp[len + 2..len + 4].copy_from_slice(&group_end.to_le_bytes());
+                    len += 4;
+                }
+                if len == 1 {
+                    return error(rsp, opcode, start, ATTRIBUTE_NOT_FOUND);
+                }
+                rsp[0] = FIND_BY_TYPE_VALUE_RSP;
+                len
+            }
+            READ_BY_TYPE_REQ | READ_BY_GROUP_TYPE_REQ if req.len() == 7 || req.len() == 21 => {
+                let (start, end) = (u16_at(1), u16_at(3));
+                let range = match self.handle_range(start, end) {
+                    Some(range) => range,
+                    None => return error(rsp, opcode, start, INVALID_HANDLE),
+                };
+                let uuid = match Uuid::from_slice(&req[5..]) {
+                    Some(uuid) => uuid,
+                    None => return error(rsp, opcode, start, INVALID_PDU),
+                };
+                let group = opcode == READ_BY_GROUP_TYPE_REQ;
+                if group && uuid != Uuid::Uuid16(PRIMARY_SERVICE) {
+                    return error(rsp, opcode, start, UNSUPPORTED_GROUP_TYPE);
+                }
+                // Each entry is the handle, the group end handle for groups,
+                // and the value. All entries have the length of the first.
+                let header_len = if group { 4 } else { 2 };
+                let mut len = 2;
+                let mut entry_len = 0;
+                for handle in range {
+                    let attribute = match self.attribute(handle) {
+                        Some(attribute) => attribute,
+                        None => break,
+                    };
+                    if attribute.uuid != uuid {
+                        continue;
+                    }
+                    if !attribute.readable {
+                        if entry_len == 0 {
+                            return error(rsp, opcode, handle, READ_NOT_PERMITTED);
+                        }
+                        break;
+                    }
+                    let value_len = cmp::min(attribute.len, ATT_MTU - 2 - header_len);
+                    if entry_len == 0 {
+                        entry_len = header_len + value_len;
+                    }
+                    if header_len + value_len != entry_len || len + entry_len > ATT_MTU {
+                        break;
+                    }
+                    rsp[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                    if group {
+                        let group_end = self.group_end(handle, &attribute);
+                        rsp[len + 2..len + 4].copy_from_slice(&group_end.to_le_bytes());
+                    }
+                    self.read(&attribute, 0, &mut rsp[len + header_len..len + entry_len]);
+                    len += entry_len;
+                }
+                if entry_len == 0 {
+                    return error(rsp, opcode, start, ATTRIBUTE_NOT_FOUND);
+                }
+                rsp[0] = if group {
+                    READ_BY_GROUP_TYPE_RSP
+                } else {
+                    READ_BY_TYPE_RSP
+                };
+                rsp[1] = entry_len as u8;
+                len
+            }
+            READ_REQ | READ_BLOB_REQ if req.len() == 3 || req.len() == 5 => {
+                let handle = u16_at(1);
+                let offset = if opcode == READ_BLOB_REQ && req.len() == 5 {
+                    u16_at(3) as usize
+                } else {
+                    0
+                };
+                let attribute = match self.attribute(handle) {
+                    Some(attribute) => attribute,
+                    None => return error(rsp, opcode, handle, INVALID_HANDLE),
+                };
+                if !attribute.readable {
+                    return error(rsp, opcode, handle, READ_NOT_PERMITTED);
+                }
+                if offset > attribute.len {
+                    return error(rsp, opcode, handle, INVALID_OFFSET);
+                }
+                rsp[0] = if opcode == READ_REQ {
+                    READ_RSP
+                } else {
+                    READ_BLOB_RSP
+                };
+                1 + self.read(&attribute, offset, &mut rsp[1..])
+            }
+            WRITE_REQ | WRITE_CMD if req.len() >= 3 => {
+                let handle = u16_at(1);
+                let value = &req[3..];
+                let checked = match self.attribute(handle) {
+                    None => Err(INVALID_HANDLE),
+                    Some(attribute) if !attribute.writable => Err(WRITE_NOT_PERMITTED),
+                    Some(attribute) if value.len() > attribute.capacity => {
+                        Err(INVALID_ATTRIBUTE_VALUE_LENGTH)
+                    }
+                    Some(attribute) => Ok(attribute),
+                };
+                match checked {
+                    Ok(attribute) => {
+                        self.store(handle, value);
+                        if attribute.characteristic {
+                            self.client
+                                .map(|client| client.value_written(handle, value));
+                        }
+                        if opcode == WRITE_CMD {
+                            return 0;
+                        }
+                        rsp[0] = WRITE_RSP;
+                        1
+                    }
+                    // Commands have no response, not even errors
+                    Err(_) if opcode == WRITE_CMD => 0,
+                    Err(code) => error(rsp, opcode, handle, code),
+                }
+            }
+            _ if opcode & COMMAND_FLAG != 0 => 0,
+            // Handle value confirmations, which we do not expect
+            0x1e => 0,
+            _ => error(rsp, opcode, 0, REQUEST_NOT_SUPPORTED),
+        }
+    }
+}
+
+impl AttChannelClient for GattServer<'_> {
+    fn connected(&self) {
+        self.connected.set(true);
+        self.pending.set(None);
+        // Notifications are configured per connection
+        for handle in 1..=self.num_attributes.get() as u16 {
+            if self.attribute(handle).map_or(false, |a| a.notify) {
+                self.store(handle + 1, &[0, 0]);
+            }
+        }
+        self.client.map(|client| client.connected());
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.connected.set(false);
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    fn received(&self, pdu: &[u8]) {
+        let mut rsp = [0; ATT_MTU];
+        let len = self.handle_request(pdu, &mut rsp);
+        if len > 0 && self.att.send(&rsp[..len]) == Err(ErrorCode::BUSY) {
+            self.pending.set(Some((rsp, len)));
+        }
+    }
+
+    fn send_done(&self) {
+        if let Some((rsp, len)) = self.pending.take() {
+            let _ = self.att.send(&rsp[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::leak;
+
+    struct FakeChannel {
+        sent: Cell<Option<([u8; ATT_MTU], usize)>>,
+    }
+
+    impl<'a> AttChannel<'a> for FakeChannel {
+        fn set_client(&self, _client: &'a dyn AttChannelClient) {}
+
+        fn send(&self, pdu: &[u8]) -> Result<(), ErrorCode> {
+            let mut buf = [0; ATT_MTU];
+            buf[..pdu.len()].copy_from_slice(pdu);
+            self.sent.set(Some((buf, pdu.len())));
+            Ok(())
+        }
+    }
+
+    fn request(server: &GattServer, channel: &FakeChannel, req: &[u8], rsp: &[u8]) {
+        server.received(req);
+        let (buf, len) = channel.sent.take().unwrap();
+        assert_eq!(&buf[..len], rsp);
+    }
+
+    #[test]
+    fn discover_read_write_and_notify() {
+        let channel = FakeChannel {
+            sent: Cell::new(None),
+        };
+        let values = leak([0; 256]);
+        let server = GattServer::new(&channel, values);
+
+        // Battery service with a battery level that notifies.
+        assert_eq!(server.add_service(Uuid::Uuid16(0x180f)), Ok(1));
+        let level = server
+            .add_characteristic(Uuid::Uuid16(0x2a19), PROPERTY_READ | PROPERTY_NOTIFY, 1)
+            .unwrap();
+        assert_eq!(level, 3);
+        server.set_value(level, &[87]).unwrap();
+        server.connected();
+
+        // Primary service discovery
+        request(
+            &server,
+            &channel,
+            &[READ_BY_GROUP_TYPE_REQ, 1, 0, 0xff, 0xff, 0x00, 0x28],
+            &[READ_BY_GROUP_TYPE_RSP, 6, 1, 0, 4, 0, 0x0f, 0x18],
+        );
+        // Characteristic discovery
+        request(
+            &server,
+            &channel,
+            &[READ_BY_TYPE_REQ, 1, 0, 4, 0, 0x03, 0x28],
+            &[READ_BY_TYPE_RSP, 7, 2, 0, 0x12, 3, 0, 0x19, 0x2a],
+        );
+        // Descriptor discovery
+        request(
+            &server,
+            &channel,
+            &[FIND_INFORMATION_REQ, 4, 0, 4, 0],
+            &[FIND_INFORMATION_RSP, 1, 4, 0, 0x02, 0x29],
+        );
+        request(&server, &channel, &[READ_REQ, 3, 0], &[READ_RSP, 87]);
+        request(
+            &server,
+            &channel,
+            &[WRITE_REQ, 3, 0, 1],
+            &[ERROR_RSP, WRITE_REQ, 3, 0, WRITE_NOT_PERMITTED],
+        );
+
+        // Notifications are sent once the client enables them.
+        server.set_value(level, &[86]).unwrap();
+        assert!(channel.sent.take().is_none());
+        request(&server, &channel, &[WRITE_REQ, 4, 0, 1, 0], &[WRITE_RSP]);
+        server.set_value(level, &[85]).unwrap();
+        let (buf, len) = channel.sent.take().unwrap();
+        assert_eq!(&buf[..len], &[HANDLE_VALUE_NTF, 3, 0, 85]);
+    }
+
+    #[test]
+    fn only_characteristic_values_can_be_set() {
+        let channel = FakeChannel {
+            sent: Cell::new(None),
+        };
+        let values = leak([0; 256]);
+        let server = GattServer::new(&channel, values);
+
+        assert_eq!(server.add_service(Uuid::Uuid16(0x180f)), Ok(1));
+        let level = server
+            .add_characteristic(Uuid::Uuid16(0x2a19), PROPERTY_READ | PROPERTY_NOTIFY, 1)
+            .unwrap();
+        assert_eq!(level, 3);
+
+        // The service and characteristic declarations and the client
+        // characteristic configuration belong to the server.
+        for handle in [1, 2, 4, 5] {
+            assert_eq!(server.set_value(handle, &[0]), Err(ErrorCode::INVAL));
+        }
+        assert_eq!(server.set_value(level, &[87]), Ok(()));
+        server.connected();
+        request(
+            &server,
+            &channel,
+            &[READ_BY_TYPE_REQ, 1, 0, 4, 0, 0x03, 0x28],
+            &[READ_BY_TYPE_RSP, 7, 2, 0, 0x12, 3, 0, 0x19, 0x2a],
+        );
+        request(&server, &channel, &[READ_REQ, 4, 0], &[READ_RSP, 0, 0]);
+    }
+
+    #[test]
+    fn characteristics_that_do_not_fit_are_not_added() {
+        let channel = FakeChannel {
+            sent: Cell::new(None),
+        };
+        // Room for the service, a 5-byte declaration and 8 bytes of value
+        let values = leak([0; 15]);
+        let server = GattServer::new(&channel, values);
+
+        assert_eq!(server.add_service(Uuid::Uuid16(0x180f)), Ok(1));
+        assert_eq!(
+            server.add_characteristic(Uuid::Uuid16(0x2a19), PROPERTY_READ, 9),
+            Err(ErrorCode::NOMEM)
+        );
+        // The declaration of the characteristic that did not fit is not left
+        // behind, and the next one gets its handles.
+        assert_eq!(
+            server.add_characteristic(Uuid::Uuid16(0x2a19), PROPERTY_READ, 8),
+            Ok(3)
+        );
+        assert_eq!(
+            server.add_characteristic(Uuid::Uuid16(0x2a1a), PROPERTY_READ, 0),
+            Err(ErrorCode::NOMEM)
+        );
+        server.connected();
+        request(
+            &server,
+            &channel,
+            &[READ_BY_TYPE_REQ, 1, 0, 0xff, 0xff, 0x03, 0x28],
+            &[READ_BY_TYPE_RSP, 7, 2, 0, 0x02, 3, 0, 0x19, 0x2a],
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Provides userspace with a Bluetooth Low Energy peripheral: apps populate
+//! the GATT table with services and characteristics, advertise, and serve
+//! the values of the characteristics to the connected central.
+//!
+//! The first app that uses the driver owns the peripheral.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let gatt = components::ble_gatt::BleGattComponent::new(
+//!     board_kernel,
+//!     capsules::ble::gatt_driver::DRIVER_NUM,
+//!     &nrf52840_peripherals.ble_radio,
+//!     mux_alarm,
+//!     [0x11, 0x22, 0x33, 0x44, 0x55, 0xc6],
+//! )
+//! .finalize(components::ble_gatt_component_helper!(
+//!     nrf52840::ble_radio::Radio,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Read-only allow
+//!
+//! - `0`: The UUID of a service or characteristic to add, 2 or 16 bytes,
+//!   little-endian.
+//! - `1`: The value of a characteristic, or the advertising data.
+//!
+//! ### Read-write allow
+//!
+//! - `0`: Receives the values written by the central.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Peripheral events. The callback signature is
+//!   `fn(event: u32, arg1: u32, arg2: u32)`, where `event` is `0` when a
+//!   central connected, `1` when it disconnected (`arg1` is the HCI reason),
+//!   and `2` when it wrote `arg2` bytes to the characteristic value with
+//!   handle `arg1`.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Add a primary service, returns its handle.
+//! - `2`: Add a characteristic to the last service, with properties `data1`
+//!   and a value of up to `data2` bytes. Returns the handle of the value.
+//! - `3`: Set the value of characteristic `data1`, which notifies the central
+//!   if it enabled notifications.
+//! - `4`: Advertise every `data1` ms until a central connects, and again after
+//!   it disconnects.
+//! - `5`: Stop advertising.
+//! - `6`: Disconnect.
+//! - `7`: Remove all services.
+
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use super::gatt::{GattServer, GattServerClient, Uuid, MAX_VALUE_LEN};
+use super::link_layer::{GapPeripheral, MAX_ADV_DATA};
+use crate::process_owner;
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BleGatt as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const UUID: usize = 0;
+    pub const VALUE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const WRITTEN: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Values of the first upcall argument.
+const EVENT_CONNECTED: usize = 0;
+const EVENT_DISCONNECTED: usize = 1;
+const EVENT_WRITTEN: usize = 2;
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct GattDriver<'a> {
+    server: &'a GattServer<'a>,
+    gap: &'a dyn GapPeripheral,
+    apps: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    owner: OptionalCell<ProcessId>,
+}
+
+impl<'a> GattDriver<'a> {
+    pub fn new(
+        server: &'a GattServer<'a>,
+        gap: &'a dyn GapPeripheral,
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> GattDriver<'a> {
+        GattDriver {
+            server,
+            gap,
+            apps: grant,
+            owner: OptionalCell::empty(),
+        }
+    }
+
+    /// Copy read-only buffer `allow_num` into `buf`, and return its length.
+    fn copy_buffer(
+        &self,
+        processid: ProcessId,
+        allow_num: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(allow_num)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            if data.len() > buf.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data.copy_to_slice(&mut buf[..data.len()]);
+                            Ok(data.len())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn uuid(&self, processid: ProcessId) -> Result<Uuid, ErrorCode> {
+        let mut uuid = [0; 16];
+        let len = self.copy_buffer(processid, ro_allow::UUID, &mut uuid)?;
+        Uuid::from_slice(&uuid[..len]).ok_or(ErrorCode::INVAL)
+    }
+
+    fn schedule_upcall(&self, event: usize, arg1: usize, arg2: usize) {
+        self.owner.map(|processid| {
+            let _ = self.apps.enter(*processid, |_, kernel_data| {
+                kernel_data.schedule_upcall(0, (event, arg1, arg2)).ok();
+            });
+        });
+    }
+}
+
+impl GattServerClient for GattDriver<'_> {
+    fn connected(&self) {
+        self.schedule_upcall(EVENT_CONNECTED, 0, 0);
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.schedule_upcall(EVENT_DISCONNECTED, reason as usize, 0);
+    }
+
+    fn value_written(&self, handle: u16, value: &[u8]) {
+        self.owner.map(|processid| {
+            let _ = self.apps.enter(*processid, |_, kernel_data| {
+                let len = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::WRITTEN)
+                    .and_then(|dest| {
+                        dest.mut_enter(|dest| {
+                            let len = cmp::min(dest.len(), value.len());
+                            dest[..len].copy_from_slice(&value[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0);
+                kernel_data
+                    .schedule_upcall(0, (EVENT_WRITTEN, handle as usize, len))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl SyscallDriver for GattDriver<'_> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+
+        // The attribute database and the connection belong to a single app
+        if let Err(e) = process_owner::claim(&self.owner, &self.apps, processid) {
+            return CommandReturn::failure(e);
+        }
+
+        match command_num {
+            1 => match self
+                .uuid(processid)
+                .and_then(|uuid| self.server.add_service(uuid))
+            {
+                Ok(handle) => CommandReturn::success_u32(handle as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+            2 => {
+                if data1 > u8::MAX as usize {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                match self
+                    .uuid(processid)
+                    .and_then(|uuid| self.server.add_characteristic(uuid, data1 as u8, data2))
+                {
+                    Ok(handle) => CommandReturn::success_u32(handle as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+            3 => {
+                let mut value = [0; MAX_VALUE_LEN];
+                self.copy_buffer(processid, ro_allow::VALUE, &mut value)
+                    .and_then(|len| self.server.set_value(data1 as u16, &value[..len]))
+                    .into()
+            }
+            4 => {
+                let mut adv_data = [0; MAX_ADV_DATA];
+                self.copy_buffer(processid, ro_allow::VALUE, &mut adv_data)
+                    .and_then(|len| self.gap.start_advertising(&adv_data[..len], data1 as u32))
+                    .into()
+            }
+            5 => {
+                self.gap.stop_advertising();
+                CommandReturn::success()
+            }
+            6 => self.gap.disconnect().into(),
+            7 => self.server.clear().into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,217 @@
+//! Logical Link Control and Adaptation Protocol of a Bluetooth Low Energy
+//! peripheral, limited to the fixed channels.
+//!
+//! L2CAP frames are reassembled from the data PDUs of the link layer, and
+//! fragmented into them. The attribute protocol channel is passed on to an
+//! `AttChannelClient`. The signaling channel rejects all commands, and the
+//! security manager channel answers pairing requests with "pairing not
+//! supported".
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A]
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::ErrorCode;
+
+use super::link_layer::{AclClient, AclLink, MAX_DATA_PAYLOAD};
+
+pub const ATT_CID: u16 = 0x0004;
+pub const SIGNALING_CID: u16 = 0x0005;
+pub const SMP_CID: u16 = 0x0006;
+
+/// The default ATT_MTU of LE, which is the only one supported.
+pub const ATT_MTU: usize = 23;
+
+const HEADER_LEN: usize = 4;
+/// The largest frame accepted, which fits any ATT PDU and signaling command
+/// of the fixed channels.
+const MAX_FRAME_LEN: usize = HEADER_LEN + 64;
+
+// Signaling commands
+const COMMAND_REJECT: u8 = 0x01;
+const CONNECTION_PARAMETER_UPDATE_RSP: u8 = 0x13;
+const REJECT_NOT_UNDERSTOOD: u16 = 0x0000;
+
+// Security manager commands
+const PAIRING_REQUEST: u8 = 0x01;
+const PAIRING_FAILED: u8 = 0x05;
+const PAIRING_NOT_SUPPORTED: u8 = 0x05;
+
+/// The attribute protocol channel.
+pub trait AttChannel<'a> {
+    fn set_client(&self, client: &'a dyn AttChannelClient);
+
+    /// Send an ATT PDU. Fails with `BUSY` until the previous one is sent, as
+    /// reported by `send_done`.
+    fn send(&self, pdu: &[u8]) -> Result<(), ErrorCode>;
+}
+
+pub trait AttChannelClient {
+    fn connected(&self);
+    /// The connection was closed, with `reason` an HCI error code.
+    fn disconnected(&self, reason: u8);
+    fn received(&self, pdu: &[u8]);
+    fn send_done(&self);
+}
+
+pub struct L2cap<'a> {
+    link: &'a dyn AclLink<'a>,
+    client: OptionalCell<&'a dyn AttChannelClient>,
+    rx: MapCell<[u8; MAX_FRAME_LEN]>,
+    /// The length of the frame being reassembled, 0 if none.
+    rx_len: Cell<usize>,
+    tx: MapCell<[u8; MAX_FRAME_LEN]>,
+    /// The length of the frame being sent, 0 if none.
+    tx_len: Cell<usize>,
+    tx_sent: Cell<usize>,
+    tx_att: Cell<bool>,
+}
+
+impl<'a> L2cap<'a> {
+    pub fn new(link: &'a dyn AclLink<'a>) -> L2cap<'a> {
+        L2cap {
+            link,
+            client: OptionalCell::empty(),
+            rx: MapCell::new([0; MAX_FRAME_LEN]),
+            rx_len: Cell::new(0),
+            tx: MapCell::new([0; MAX_FRAME_LEN]),
+            tx_len: Cell::new(0),
+            tx_sent: Cell::new(0),
+            tx_att: Cell::new(false),
+        }
+    }
+
+    fn send_frame(&self, cid: u16, payload: &[u8], att: bool) -> Result<(), ErrorCode> {
+        if self.tx_len.get() != 0 {
+            return Err(ErrorCode::BUSY);
+        }
+        let len = HEADER_LEN + payload.len();
+        if len > MAX_FRAME_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.tx.map(|tx| {
+            tx[0..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+            tx[2..4].copy_from_slice(&cid.to_le_bytes());
+            tx[HEADER_LEN..len].copy_from_slice(payload);
+        });
+        self.tx_len.set(len);
+        self.tx_sent.set(0);
+        self.tx_att.set(att);
+        self.send_fragment()
+    }
+
+    fn send_fragment(&self) -> Result<(), ErrorCode> {
+        let sent = self.tx_sent.get();
+        let end = cmp::min(sent + MAX_DATA_PAYLOAD, self.tx_len.get());
+        let result = self.tx.map_or(Err(ErrorCode::FAIL), |tx| {
+            self.link.send(sent == 0, &tx[sent..end])
+        });
+        match result {
+            Ok(()) => self.tx_sent.set(end),
+            Err(_) => self.tx_len.set(0),
+        }
+        result
+    }
+
+    fn dispatch(&self, cid: u16, payload: &[u8]) {
+        match cid {
+            ATT_CID => {
+                self.client.map(|client| client.received(payload));
+            }
+            SIGNALING_CID => {
+                // Only the responses to our own requests are not rejected,
+                // and we make none.
+                if payload.len() >= 4
+                    && payload[0] != COMMAND_REJECT
+                    && payload[0] != CONNECTION_PARAMETER_UPDATE_RSP
+                {
+                    let reason = REJECT_NOT_UNDERSTOOD.to_le_bytes();
+                    let reject = [COMMAND_REJECT, payload[1], 2, 0, reason[0], reason[1]];
+                    let _ = self.send_frame(SIGNALING_CID, &reject, false);
+                }
+            }
+            SMP_CID => {
+                if payload.first() == Some(&PAIRING_REQUEST) {
+                    let _ =
+                        self.send_frame(SMP_CID, &[PAIRING_FAILED, PAIRING_NOT_SUPPORTED], false);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn reset(&self) {
+        self.rx_len.set(0);
+        self.tx_len.set(0);
+    }
+}
+
+impl<'a> AttChannel<'a> for L2cap<'a> {
+    fn set_client(&self, client: &'a dyn AttChannelClient) {
+        self.client.set(client);
+    }
+
+    fn send(&self, pdu: &[u8]) -> Result<(), ErrorCode> {
+        if pdu.len() > ATT_MTU {
+            return Err(ErrorCode::SIZE);
+        }
+        self.send_frame(ATT_CID, pdu, true)
+    }
+}
+
+impl AclClient for L2cap<'_> {
+    fn connected(&self) {
+        self.reset();
+        self.client.map(|client| client.connected());
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.reset();
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    fn received(&self, start: bool, data: &[u8]) {
+        let offset = if start { 0 } else { self.rx_len.get() };
+        if offset == 0 && !start {
+            // The start of the frame was dropped
+            return;
+        }
+        let len = self.rx.map_or(0, |rx| {
+            if offset + data.len() > rx.len() {
+                // Too long, drop the frame
+                return 0;
+            }
+            rx[offset..offset + data.len()].copy_from_slice(data);
+            offset + data.len()
+        });
+        self.rx_len.set(len);
+        if len < HEADER_LEN {
+            return;
+        }
+
+        self.rx.map(|rx| {
+            let frame_len = HEADER_LEN + u16::from_le_bytes([rx[0], rx[1]]) as usize;
+            if len >= frame_len {
+                self.rx_len.set(0);
+                let cid = u16::from_le_bytes([rx[2], rx[3]]);
+                self.dispatch(cid, &rx[HEADER_LEN..frame_len]);
+            }
+        });
+    }
+
+    fn send_done(&self) {
+        if self.tx_len.get() == 0 {
+            return;
+        }
+        if self.tx_sent.get() < self.tx_len.get() {
+            let _ = self.send_fragment();
+        } else {
+            self.tx_len.set(0);
+            if self.tx_att.get() {
+                self.client.map(|client| client.send_done());
+            }
+        }
+    }
+}
@@ -0,0 +1,942 @@
+//! Link layer of a Bluetooth Low Energy peripheral.
+//!
+//! The link layer advertises with connectable undirected advertisements
+//! (`ADV_IND`) until a central connects with a `CONNECT_IND`. It then takes
+//! part in the connection events of the central until the connection is
+//! terminated or lost, after which it advertises again.
+//!
+//! Each connection event is a single exchange: the central sends a PDU and
+//! the link layer answers it. The link layer never sets the "more data" bit,
+//! which limits the throughput to one PDU per direction and connection
+//! interval, but lets connection events be timed with an alarm. The link
+//! layer listens at every connection event, i.e. it does not use the slave
+//! latency.
+//!
+//! The supported LL control procedures are connection update, channel map
+//! update, termination, feature exchange, version exchange and LE ping.
+//! Encryption requests are rejected, other requests are answered with
+//! `LL_UNKNOWN_RSP`.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B]
+
+use core::cell::Cell;
+use core::convert::TryInto;
+
+use kernel::hil::ble_advertising::{BleConnectionDriver, ConnectionClient, RadioChannel};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// Maximum payload of a data channel PDU (without data length extension).
+pub const MAX_DATA_PAYLOAD: usize = 27;
+/// Maximum length of the advertising data.
+pub const MAX_ADV_DATA: usize = 31;
+pub const ADDRESS_LEN: usize = 6;
+
+/// Reasons of a disconnection (HCI error codes).
+pub const CONNECTION_TIMEOUT: u8 = 0x08;
+pub const REMOTE_USER_TERMINATED: u8 = 0x13;
+pub const LOCAL_HOST_TERMINATED: u8 = 0x16;
+pub const UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1a;
+pub const INSTANT_PASSED: u8 = 0x28;
+pub const FAILED_TO_BE_ESTABLISHED: u8 = 0x3e;
+
+// Advertising channel PDU header
+const ADV_IND: u8 = 0x0;
+const CONNECT_IND: u8 = 0x5;
+const ADV_PDU_TYPE: u8 = 0x0f;
+const ADV_TXADD: u8 = 0x40;
+const ADV_RXADD: u8 = 0x80;
+const CONNECT_IND_LEN: usize = 34;
+const ADV_PDU_LEN: usize = 2 + ADDRESS_LEN + MAX_ADV_DATA;
+
+// Data channel PDU header
+const LLID: u8 = 0x03;
+const NESN: u8 = 0x04;
+const SN: u8 = 0x08;
+const LLID_CONTINUATION: u8 = 0x1;
+const LLID_START: u8 = 0x2;
+const LLID_CONTROL: u8 = 0x3;
+
+// LL control PDU opcodes
+const LL_CONNECTION_UPDATE_IND: u8 = 0x00;
+const LL_CHANNEL_MAP_IND: u8 = 0x01;
+const LL_TERMINATE_IND: u8 = 0x02;
+const LL_ENC_REQ: u8 = 0x03;
+const LL_UNKNOWN_RSP: u8 = 0x07;
+const LL_FEATURE_REQ: u8 = 0x08;
+const LL_FEATURE_RSP: u8 = 0x09;
+const LL_VERSION_IND: u8 = 0x0c;
+const LL_REJECT_IND: u8 = 0x0d;
+const LL_SLAVE_FEATURE_REQ: u8 = 0x0e;
+const LL_REJECT_EXT_IND: u8 = 0x11;
+const LL_PING_REQ: u8 = 0x12;
+const LL_PING_RSP: u8 = 0x13;
+
+/// LE Ping is the only optional feature of the link layer.
+const FEATURES: [u8; 8] = [0x10, 0, 0, 0, 0, 0, 0, 0];
+/// Bluetooth 4.1, company identifier for tests, sub-version 0.
+const VERSION: [u8; 5] = [0x07, 0xff, 0xff, 0x00, 0x00];
+
+// Timing, in microseconds
+const CONNECT_DELAY_US: u32 = 1250;
+const UNIT_US: u32 = 1250;
+const SUPERVISION_UNIT_US: u32 = 10_000;
+const SLAVE_SCA_PPM: u32 = 50;
+const MASTER_SCA_PPM: [u32; 8] = [500, 250, 150, 100, 75, 50, 30, 20];
+/// Time to turn on the receiver and to cover the resolution of the alarm.
+const LISTEN_MARGIN_US: u32 = 500;
+/// How long to wait for a request after starting an advertisement.
+const ADV_LISTEN_US: u32 = 1500;
+/// Maximum of the random delay added to the advertising interval.
+const ADV_DELAY_MAX_US: u32 = 10_000;
+const NUM_DATA_CHANNELS: u8 = 37;
+
+/// The data link to the L2CAP layer.
+pub trait AclLink<'a> {
+    fn set_client(&self, client: &'a dyn AclClient);
+
+    /// Queue a data PDU. `start` is whether `data` starts an L2CAP frame.
+    /// Fails with `BUSY` until the previous PDU is taken by the link layer,
+    /// as reported by `send_done`.
+    fn send(&self, start: bool, data: &[u8]) -> Result<(), ErrorCode>;
+}
+
+pub trait AclClient {
+    /// A central connected.
+    fn connected(&self);
+    /// The connection was closed, with `reason` an HCI error code.
+    fn disconnected(&self, reason: u8);
+    /// A data PDU was received. `start` is whether it starts an L2CAP frame.
+    fn received(&self, start: bool, data: &[u8]);
+    /// The PDU passed to `send` is being transmitted, another one can be
+    /// queued.
+    fn send_done(&self);
+}
+
+/// Peripheral role procedures of the Generic Access Profile.
+pub trait GapPeripheral {
+    /// Advertise `adv_data` (AD structures) every `interval_ms` until a
+    /// central connects, and again after each disconnection.
+    fn start_advertising(&self, adv_data: &[u8], interval_ms: u32) -> Result<(), ErrorCode>;
+    /// Stop advertising. A connection is kept.
+    fn stop_advertising(&self);
+    /// Terminate the connection.
+    fn disconnect(&self) -> Result<(), ErrorCode>;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Advertising,
+    /// A `CONNECT_IND` was received during the current exchange.
+    Connecting,
+    Connected,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Timer {
+    None,
+    AdvertisingEvent,
+    AdvertisingListenEnd,
+    ConnectionEvent,
+    ReceiveWindowEnd,
+}
+
+#[derive(Copy, Clone)]
+struct Pdu {
+    llid: u8,
+    len: u8,
+    payload: [u8; MAX_DATA_PAYLOAD],
+}
+
+impl Pdu {
+    const EMPTY: Pdu = Pdu {
+        llid: LLID_CONTINUATION,
+        len: 0,
+        payload: [0; MAX_DATA_PAYLOAD],
+    };
+
+    fn new(llid: u8, data: &[u8]) -> Pdu {
+        let mut pdu = Pdu::EMPTY;
+        pdu.llid = llid;
+        pdu.len = data.len() as u8;
+        pdu.payload[..data.len()].copy_from_slice(data);
+        pdu
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct ConnectionUpdate {
+    win_size: u8,
+    win_offset: u16,
+    interval: u16,
+    timeout: u16,
+    instant: u16,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Connection {
+    access_address: u32,
+    crc_init: u32,
+    /// In units of 1.25 ms.
+    interval: u16,
+    /// In units of 10 ms.
+    timeout: u16,
+    channel_map: [u8; 5],
+    hop: u8,
+    master_sca_ppm: u32,
+    unmapped_channel: u8,
+    channel: u8,
+    /// The counter of the next connection event.
+    event_counter: u16,
+    sn: bool,
+    nesn: bool,
+    established: bool,
+    version_sent: bool,
+    update: Option<ConnectionUpdate>,
+    channel_map_update: Option<([u8; 5], u16)>,
+}
+
+impl Connection {
+    fn interval_us(&self) -> u32 {
+        self.interval as u32 * UNIT_US
+    }
+}
+
+fn num_used_channels(channel_map: &[u8; 5]) -> usize {
+    (0..NUM_DATA_CHANNELS)
+        .filter(|&c| channel_used(channel_map, c))
+        .count()
+}
+
+fn channel_used(channel_map: &[u8; 5], channel: u8) -> bool {
+    channel_map[(channel / 8) as usize] & (1 << (channel % 8)) != 0
+}
+
+/// Channel selection algorithm #1: the data channel of `unmapped_channel`,
+/// remapped to a used channel if it is not in `channel_map`.
+fn select_channel(channel_map: &[u8; 5], unmapped_channel: u8) -> u8 {
+    if channel_used(channel_map, unmapped_channel) {
+        return unmapped_channel;
+    }
+    let num_used = num_used_channels(channel_map);
+    (0..NUM_DATA_CHANNELS)
+        .filter(|&c| channel_used(channel_map, c))
+        .nth(unmapped_channel as usize % num_used)
+        .unwrap_or(0)
+}
+
+/// Whether the connection event `instant` is before `event_counter`.
+fn instant_passed(instant: u16, event_counter: u16) -> bool {
+    let elapsed = event_counter.wrapping_sub(instant);
+    elapsed != 0 && elapsed < 32767
+}
+
+pub struct LinkLayer<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn AclClient>,
+    address: [u8; ADDRESS_LEN],
+    state: Cell<State>,
+    timer: Cell<Timer>,
+    advertising: Cell<bool>,
+    adv_pdu: Cell<[u8; ADV_PDU_LEN]>,
+    adv_interval_ms: Cell<u32>,
+    adv_channel: Cell<RadioChannel>,
+    connection: Cell<Connection>,
+    /// The last anchor point, or the end of the `CONNECT_IND`.
+    anchor: Cell<A::Ticks>,
+    /// The time from `anchor` to the next anchor point.
+    next_anchor_us: Cell<u32>,
+    /// The transmit window of the next anchor point.
+    window_us: Cell<u32>,
+    /// The anchor point of the current exchange.
+    received_at: OptionalCell<A::Ticks>,
+    /// The last packet with a valid CRC, for the supervision timeout.
+    last_rx: Cell<A::Ticks>,
+    /// The PDU sent in the last exchange, until it is acknowledged.
+    in_flight: Cell<Option<Pdu>>,
+    /// The next data PDU from the host.
+    queued: Cell<Option<Pdu>>,
+    /// The next LL control PDU, which goes before data.
+    control: Cell<Option<Pdu>>,
+    received: Cell<Option<Pdu>>,
+    send_done_pending: Cell<bool>,
+    disconnect_reason: OptionalCell<u8>,
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> LinkLayer<'a, R, A> {
+    /// `address` is the random static device address, least significant
+    /// byte first.
+    pub fn new(radio: &'a R, alarm: &'a A, address: [u8; ADDRESS_LEN]) -> LinkLayer<'a, R, A> {
+        LinkLayer {
+            radio,
+            alarm,
+            client: OptionalCell::empty(),
+            address,
+            state: Cell::new(State::Idle),
+            timer: Cell::new(Timer::None),
+            advertising: Cell::new(false),
+            adv_pdu: Cell::new([0; ADV_PDU_LEN]),
+            adv_interval_ms: Cell::new(100),
+            adv_channel: Cell::new(RadioChannel::AdvertisingChannel37),
+            connection: Cell::new(Connection::default()),
+            anchor: Cell::new(alarm.now()),
+            next_anchor_us: Cell::new(0),
+            window_us: Cell::new(0),
+            received_at: OptionalCell::empty(),
+            last_rx: Cell::new(alarm.now()),
+            in_flight: Cell::new(None),
+            queued: Cell::new(None),
+            control: Cell::new(None),
+            received: Cell::new(None),
+            send_done_pending: Cell::new(false),
+            disconnect_reason: OptionalCell::empty(),
+        }
+    }
+
+    pub fn address(&self) -> [u8; ADDRESS_LEN] {
+        self.address
+    }
+
+    fn update_connection<F: FnOnce(&mut Connection)>(&self, f: F) {
+        let mut connection = self.connection.get();
+        f(&mut connection);
+        self.connection.set(connection);
+    }
+
+    fn set_timer(&self, timer: Timer, reference: A::Ticks, dt_us: u32) {
+        self.timer.set(timer);
+        self.alarm
+            .set_alarm(reference, self.alarm.ticks_from_us(dt_us));
+    }
+
+    fn cancel_timer(&self) {
+        self.timer.set(Timer::None);
+        let _ = self.alarm.disarm();
+    }
+
+    fn advertise(&self) {
+        let pdu = self.adv_pdu.get();
+        let len = pdu[1] as usize + 2;
+        self.radio
+            .advertise_connectable(&pdu[..len], self.adv_channel.get());
+        self.set_timer(Timer::AdvertisingListenEnd, self.alarm.now(), ADV_LISTEN_US);
+    }
+
+    fn next_advertising_channel(&self) {
+        if !self.advertising.get() {
+            self.state.set(State::Idle);
+            return;
+        }
+        let next = match self.adv_channel.get() {
+            RadioChannel::AdvertisingChannel37 => RadioChannel::AdvertisingChannel38,
+            RadioChannel::AdvertisingChannel38 => RadioChannel::AdvertisingChannel39,
+            _ => {
+                // End of the advertising event. The next one is delayed by a
+                // pseudo-random 0 to 10 ms, to avoid repeated collisions.
+                let now = self.alarm.now();
+                let delay_us = self.alarm.ticks_to_us(now) % ADV_DELAY_MAX_US;
+                self.adv_channel.set(RadioChannel::AdvertisingChannel37);
+                self.set_timer(
+                    Timer::AdvertisingEvent,
+                    now,
+                    self.adv_interval_ms.get() * 1000 + delay_us,
+                );
+                return;
+            }
+        };
+        self.adv_channel.set(next);
+        self.advertise();
+    }
+
+    /// Handle a `CONNECT_IND` addressed to us, and return whether a
+    /// connection is created.
+    fn connect(&self, pdu: &[u8]) -> bool {
+        if pdu.len() < 2 + CONNECT_IND_LEN
+            || pdu[0] & ADV_PDU_TYPE != CONNECT_IND
+            || pdu[0] & ADV_RXADD == 0
+            || pdu[1] as usize != CONNECT_IND_LEN
+            || pdu[8..14] != self.address
+        {
+            return false;
+        }
+        let ll_data = &pdu[14..36];
+        let u16_at = |i: usize| u16::from_le_bytes([ll_data[i], ll_data[i + 1]]);
+        let channel_map: [u8; 5] = ll_data[16..21].try_into().unwrap_or([0; 5]);
+        let hop = ll_data[21] & 0x1f;
+        let interval = u16_at(10);
+        if interval == 0 || hop == 0 || num_used_channels(&channel_map) < 2 {
+            return false;
+        }
+
+        self.connection.set(Connection {
+            access_address: u32::from_le_bytes([ll_data[0], ll_data[1], ll_data[2], ll_data[3]]),
+            crc_init: u32::from_le_bytes([ll_data[4], ll_data[5], ll_data[6], 0]),
+            interval,
+            timeout: u16_at(14),
+            channel_map,
+            hop,
+            master_sca_ppm: MASTER_SCA_PPM[(ll_data[21] >> 5) as usize],
+            ..Connection::default()
+        });
+        let now = self.alarm.now();
+        self.anchor.set(now);
+        self.last_rx.set(now);
+        self.next_anchor_us
+            .set(CONNECT_DELAY_US + u16_at(8) as u32 * UNIT_US);
+        self.window_us.set(ll_data[7] as u32 * UNIT_US);
+        self.in_flight.set(None);
+        self.queued.set(None);
+        self.control.set(None);
+        self.received.set(None);
+        true
+    }
+
+    fn window_widening_us(&self) -> u32 {
+        let ppm = self.connection.get().master_sca_ppm + SLAVE_SCA_PPM;
+        (self.next_anchor_us.get() as u64 * ppm as u64 / 1_000_000) as u32 + 16
+    }
+
+    /// Apply the updates whose instant is the next connection event, select
+    /// its channel and set the alarm to listen for it.
+    fn schedule_connection_event(&self) {
+        let mut connection = self.connection.get();
+        let counter = connection.event_counter;
+
+        if let Some((channel_map, instant)) = connection.channel_map_update {
+            if instant == counter {
+                connection.channel_map = channel_map;
+                connection.channel_map_update = None;
+            } else if instant_passed(instant, counter) {
+                self.close(INSTANT_PASSED);
+                return;
+            }
+        }
+        if let Some(update) = connection.update {
+            if update.instant == counter {
+                // The old connection ends at the instant, and the transmit
+                // window of the new one starts `win_offset` after it.
+                self.next_anchor_us
+                    .set(self.next_anchor_us.get() + update.win_offset as u32 * UNIT_US);
+                self.window_us.set(update.win_size as u32 * UNIT_US);
+                connection.interval = update.interval;
+                connection.timeout = update.timeout;
+                connection.update = None;
+            } else if instant_passed(update.instant, counter) {
+                self.close(INSTANT_PASSED);
+                return;
+            }
+        }
+        connection.unmapped_channel =
+            (connection.unmapped_channel + connection.hop) % NUM_DATA_CHANNELS;
+        connection.channel = select_channel(&connection.channel_map, connection.unmapped_channel);
+        self.connection.set(connection);
+
+        // The connection is lost if the central is not heard for the
+        // supervision timeout, or for 6 intervals before it is established.
+        let timeout_us = if connection.established {
+            connection.timeout as u32 * SUPERVISION_UNIT_US
+        } else {
+            6 * connection.interval_us()
+        };
+        let since_rx = self.alarm.now().wrapping_sub(self.last_rx.get());
+        if self.alarm.ticks_to_us(since_rx) > timeout_us {
+            self.close(if connection.established {
+                CONNECTION_TIMEOUT
+            } else {
+                FAILED_TO_BE_ESTABLISHED
+            });
+            return;
+        }
+
+        let start_us = self
+            .next_anchor_us
+            .get()
+            .saturating_sub(self.window_widening_us() + LISTEN_MARGIN_US);
+        self.set_timer(Timer::ConnectionEvent, self.anchor.get(), start_us);
+    }
+
+    fn end_connection_event(&self) {
+        let connection = self.connection.get();
+        self.next_anchor_us
+            .set(self.next_anchor_us.get() + connection.interval_us());
+        self.update_connection(|c| c.event_counter = c.event_counter.wrapping_add(1));
+    }
+
+    fn queue_control(&self, data: &[u8]) {
+        self.control.set(Some(Pdu::new(LLID_CONTROL, data)));
+    }
+
+    fn handle_control(&self, pdu: &Pdu) {
+        let data = pdu.data();
+        if data.is_empty() {
+            return;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        match data[0] {
+            LL_CONNECTION_UPDATE_IND if data.len() >= 12 => {
+                let update = ConnectionUpdate {
+                    win_size: data[1],
+                    win_offset: u16_at(2),
+                    interval: u16_at(4),
+                    timeout: u16_at(8),
+                    instant: u16_at(10),
+                };
+                if update.interval != 0 {
+                    self.update_connection(|c| c.update = Some(update));
+                }
+            }
+            LL_CHANNEL_MAP_IND if data.len() >= 8 => {
+                let channel_map: [u8; 5] = data[1..6].try_into().unwrap_or([0; 5]);
+                if num_used_channels(&channel_map) >= 2 {
+                    let instant = u16_at(6);
+                    self.update_connection(|c| c.channel_map_update = Some((channel_map, instant)));
+                }
+            }
+            LL_TERMINATE_IND if data.len() >= 2 => self.disconnect_reason.set(data[1]),
+            LL_ENC_REQ => self.queue_control(&[LL_REJECT_IND, UNSUPPORTED_REMOTE_FEATURE]),
+            LL_FEATURE_REQ => {
+                let mut rsp = [LL_FEATURE_RSP; 9];
+                rsp[1..].copy_from_slice(&FEATURES);
+                self.queue_control(&rsp);
+            }
+            LL_VERSION_IND => {
+                if !self.connection.get().version_sent {
+                    let mut ind = [LL_VERSION_IND; 6];
+                    ind[1..].copy_from_slice(&VERSION);
+                    self.queue_control(&ind);
+                    self.update_connection(|c| c.version_sent = true);
+                }
+            }
+            LL_PING_REQ => self.queue_control(&[LL_PING_RSP]),
+            LL_UNKNOWN_RSP | LL_FEATURE_RSP | LL_REJECT_IND | LL_SLAVE_FEATURE_REQ
+            | LL_REJECT_EXT_IND | LL_PING_RSP => {}
+            opcode => self.queue_control(&[LL_UNKNOWN_RSP, opcode]),
+        }
+    }
+
+    /// Close the connection and go back to advertising, if enabled.
+    fn close(&self, reason: u8) {
+        self.cancel_timer();
+        self.state.set(State::Idle);
+        self.in_flight.set(None);
+        self.queued.set(None);
+        self.control.set(None);
+        self.received.set(None);
+        self.send_done_pending.set(false);
+        self.disconnect_reason.clear();
+        if self.advertising.get() {
+            self.state.set(State::Advertising);
+            self.adv_channel.set(RadioChannel::AdvertisingChannel37);
+            self.advertise();
+        }
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    /// Build the response to a data channel PDU.
+    fn respond(&self, pdu: &[u8], crc_ok: bool, response: &mut [u8]) -> Option<usize> {
+        let now = self.alarm.now();
+        // The anchor point is the start of the packet: preamble, access
+        // address, PDU and CRC, at 1 µs per bit.
+        let air_time_us = (pdu.len() as u32 + 8) * 8;
+        self.received_at
+            .set(now.wrapping_sub(self.alarm.ticks_from_us(air_time_us)));
+
+        let mut connection = self.connection.get();
+        if crc_ok && pdu.len() >= 2 {
+            self.last_rx.set(now);
+            connection.established = true;
+            if (pdu[0] & NESN != 0) != connection.sn {
+                // Our last PDU is acknowledged.
+                connection.sn = !connection.sn;
+                if let Some(sent) = self.in_flight.take() {
+                    if sent.llid == LLID_CONTROL && sent.data().first() == Some(&LL_TERMINATE_IND) {
+                        self.disconnect_reason.set(LOCAL_HOST_TERMINATED);
+                    }
+                }
+            }
+            if (pdu[0] & SN != 0) == connection.nesn {
+                // A new PDU, which is not a retransmission.
+                connection.nesn = !connection.nesn;
+                let len = core::cmp::min(pdu[1] as usize, MAX_DATA_PAYLOAD);
+                if len > 0 && pdu.len() >= 2 + len {
+                    self.received
+                        .set(Some(Pdu::new(pdu[0] & LLID, &pdu[2..2 + len])));
+                }
+            }
+        }
+        self.connection.set(connection);
+
+        let next = self.in_flight.get().unwrap_or_else(|| {
+            self.control.take().unwrap_or_else(|| {
+                self.queued.take().map_or(Pdu::EMPTY, |pdu| {
+                    self.send_done_pending.set(true);
+                    pdu
+                })
+            })
+        });
+        self.in_flight.set(Some(next));
+
+        let len = next.len as usize;
+        if response.len() < 2 + len {
+            return None;
+        }
+        let mut header = next.llid;
+        if connection.nesn {
+            header |= NESN;
+        }
+        if connection.sn {
+            header |= SN;
+        }
+        response[0] = header;
+        response[1] = next.len;
+        response[2..2 + len].copy_from_slice(next.data());
+        Some(2 + len)
+    }
+
+    fn connection_exchange_done(&self) {
+        self.cancel_timer();
+        if let Some(anchor) = self.received_at.take() {
+            self.anchor.set(anchor);
+            self.next_anchor_us.set(0);
+            self.window_us.set(0);
+        }
+        self.end_connection_event();
+
+        if let Some(pdu) = self.received.take() {
+            if pdu.llid == LLID_CONTROL {
+                self.handle_control(&pdu);
+            } else {
+                self.client
+                    .map(|client| client.received(pdu.llid == LLID_START, pdu.data()));
+            }
+        }
+        if let Some(reason) = self.disconnect_reason.take() {
+            self.close(reason);
+            return;
+        }
+        if self.send_done_pending.take() {
+            self.client.map(|client| client.send_done());
+        }
+        if self.state.get() == State::Connected {
+            self.schedule_connection_event();
+        }
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> AclLink<'a> for LinkLayer<'a, R, A> {
+    fn set_client(&self, client: &'a dyn AclClient) {
+        self.client.set(client);
+    }
+
+    fn send(&self, start: bool, data: &[u8]) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Connected {
+            return Err(ErrorCode::OFF);
+        }
+        if data.len() > MAX_DATA_PAYLOAD {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.queued.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let llid = if start { LLID_START } else { LLID_CONTINUATION };
+        self.queued.set(Some(Pdu::new(llid, data)));
+        Ok(())
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> GapPeripheral for LinkLayer<'a, R, A> {
+    fn start_advertising(&self, adv_data: &[u8], interval_ms: u32) -> Result<(), ErrorCode> {
+        if adv_data.len() > MAX_ADV_DATA {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut pdu = [0; ADV_PDU_LEN];
+        pdu[0] = ADV_IND | ADV_TXADD;
+        pdu[1] = (ADDRESS_LEN + adv_data.len()) as u8;
+        pdu[2..2 + ADDRESS_LEN].copy_from_slice(&self.address);
+        pdu[2 + ADDRESS_LEN..2 + ADDRESS_LEN + adv_data.len()].copy_from_slice(adv_data);
+        self.adv_pdu.set(pdu);
+        // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+        self.adv_interval_ms.set(interval_ms.max(20).min(10_240));
+        self.advertising.set(true);
+
+        if self.state.get() == State::Idle {
+            self.state.set(State::Advertising);
+            self.adv_channel.set(RadioChannel::AdvertisingChannel37);
+            self.advertise();
+        }
+        Ok(())
+    }
+
+    fn stop_advertising(&self) {
+        self.advertising.set(false);
+        // Otherwise, advertising stops at the end of the current exchange
+        if self.timer.get() == Timer::AdvertisingEvent {
+            self.cancel_timer();
+            self.state.set(State::Idle);
+        }
+    }
+
+    fn disconnect(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Connected {
+            return Err(ErrorCode::OFF);
+        }
+        self.queue_control(&[LL_TERMINATE_IND, REMOTE_USER_TERMINATED]);
+        Ok(())
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> ConnectionClient for LinkLayer<'a, R, A> {
+    fn packet_received(&self, pdu: &[u8], crc_ok: bool, response: &mut [u8]) -> Option<usize> {
+        match self.state.get() {
+            State::Advertising => {
+                if crc_ok && self.connect(pdu) {
+                    self.state.set(State::Connecting);
+                }
+                None
+            }
+            State::Connected => self.respond(pdu, crc_ok, response),
+            State::Idle | State::Connecting => None,
+        }
+    }
+
+    fn exchange_done(&self, _result: Result<(), ErrorCode>) {
+        match self.state.get() {
+            State::Advertising => {
+                self.cancel_timer();
+                self.next_advertising_channel();
+            }
+            State::Connecting => {
+                self.cancel_timer();
+                self.state.set(State::Connected);
+                self.client.map(|client| client.connected());
+                self.schedule_connection_event();
+            }
+            State::Connected => self.connection_exchange_done(),
+            State::Idle => (),
+        }
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: Alarm<'a>> AlarmClient for LinkLayer<'a, R, A> {
+    fn alarm(&self) {
+        match self.timer.get() {
+            Timer::AdvertisingEvent => {
+                self.timer.set(Timer::None);
+                self.advertise();
+            }
+            Timer::AdvertisingListenEnd => {
+                // If a request is being received, `exchange_done` follows.
+                if self.radio.stop_listening().is_ok() {
+                    self.timer.set(Timer::None);
+                    self.next_advertising_channel();
+                }
+            }
+            Timer::ConnectionEvent => {
+                let connection = self.connection.get();
+                let channel = RadioChannel::data_channel(connection.channel)
+                    .unwrap_or(RadioChannel::DataChannel0);
+                self.radio.listen_connection(
+                    channel,
+                    connection.access_address,
+                    connection.crc_init,
+                );
+                let end_us = self.next_anchor_us.get()
+                    + self.window_us.get()
+                    + self.window_widening_us()
+                    + LISTEN_MARGIN_US;
+                self.set_timer(Timer::ReceiveWindowEnd, self.anchor.get(), end_us);
+            }
+            Timer::ReceiveWindowEnd => {
+                if self.radio.stop_listening().is_ok() {
+                    // The central was not heard in this connection event.
+                    self.timer.set(Timer::None);
+                    self.end_connection_event();
+                    self.schedule_connection_event();
+                }
+            }
+            Timer::None => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::FakeAlarm;
+
+    /// A radio which records what it is asked to do. Tests play the central
+    /// by calling the `ConnectionClient`.
+    struct SimRadio {
+        advertised: Cell<Option<RadioChannel>>,
+        listening: Cell<Option<(RadioChannel, u32, u32)>>,
+    }
+
+    impl SimRadio {
+        fn new() -> Self {
+            Self {
+                advertised: Cell::new(None),
+                listening: Cell::new(None),
+            }
+        }
+    }
+
+    impl<'a> BleConnectionDriver<'a> for SimRadio {
+        fn set_connection_client(&self, _client: &'a dyn ConnectionClient) {}
+
+        fn advertise_connectable(&self, _pdu: &[u8], channel: RadioChannel) {
+            self.advertised.set(Some(channel));
+        }
+
+        fn listen_connection(&self, channel: RadioChannel, access_address: u32, crc_init: u32) {
+            self.listening
+                .set(Some((channel, access_address, crc_init)));
+        }
+
+        fn stop_listening(&self) -> Result<(), ErrorCode> {
+            self.listening.set(None);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Host {
+        connected: Cell<bool>,
+        disconnected: Cell<Option<u8>>,
+        received: Cell<usize>,
+    }
+
+    impl AclClient for Host {
+        fn connected(&self) {
+            self.connected.set(true);
+        }
+        fn disconnected(&self, reason: u8) {
+            self.disconnected.set(Some(reason));
+        }
+        fn received(&self, _start: bool, data: &[u8]) {
+            self.received.set(data.len());
+        }
+        fn send_done(&self) {}
+    }
+
+    const ADDRESS: [u8; 6] = [1, 2, 3, 4, 5, 0xc6];
+    const ACCESS_ADDRESS: u32 = 0x50654c33;
+
+    fn connect_ind(hop: u8) -> [u8; 36] {
+        let mut pdu = [0; 36];
+        pdu[0] = CONNECT_IND | ADV_TXADD | ADV_RXADD;
+        pdu[1] = CONNECT_IND_LEN as u8;
+        pdu[8..14].copy_from_slice(&ADDRESS);
+        pdu[14..18].copy_from_slice(&ACCESS_ADDRESS.to_le_bytes());
+        pdu[18..21].copy_from_slice(&[0x12, 0x34, 0x56]);
+        pdu[21] = 2; // WinSize
+        pdu[24] = 40; // Interval, 50 ms
+        pdu[28] = 100; // Timeout, 1 s
+        pdu[30..35].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x1f]);
+        pdu[35] = hop;
+        pdu
+    }
+
+    #[test]
+    fn channel_selection_remaps_unused_channels() {
+        let all = [0xff, 0xff, 0xff, 0xff, 0x1f];
+        assert_eq!(select_channel(&all, 12), 12);
+        // Only channels 1, 9 and 20 are used: 12 % 3 = 0 maps to channel 1.
+        let map = [0x02, 0x02, 0x10, 0x00, 0x00];
+        assert_eq!(select_channel(&map, 12), 1);
+        assert_eq!(select_channel(&map, 13), 9);
+        assert_eq!(select_channel(&map, 20), 20);
+    }
+
+    #[test]
+    fn connection_events_and_control_procedures() {
+        let radio = SimRadio::new();
+        let alarm = FakeAlarm::new();
+        alarm.set_now(1_000);
+        let host = Host::default();
+        let ll = LinkLayer::new(&radio, &alarm, ADDRESS);
+        alarm.set_alarm_client(&ll);
+        ll.set_client(&host);
+
+        ll.start_advertising(&[0x02, 0x01, 0x06], 100).unwrap();
+        assert_eq!(
+            radio.advertised.get(),
+            Some(RadioChannel::AdvertisingChannel37)
+        );
+        // Nobody answers on 37, a central connects on 38.
+        alarm.trigger();
+        assert_eq!(
+            radio.advertised.get(),
+            Some(RadioChannel::AdvertisingChannel38)
+        );
+        let mut response = [0; 64];
+        assert_eq!(
+            ll.packet_received(&connect_ind(7), true, &mut response),
+            None
+        );
+        ll.exchange_done(Ok(()));
+        assert!(host.connected.get());
+
+        // The first event is on channel 7, with the new access address.
+        alarm.trigger();
+        let (channel, access_address, crc_init) = radio.listening.get().unwrap();
+        assert_eq!(channel, RadioChannel::DataChannel7);
+        assert_eq!(access_address, ACCESS_ADDRESS);
+        assert_eq!(crc_init, 0x563412);
+
+        // The central asks for our version: we acknowledge it with an empty
+        // PDU, then send ours at the next event.
+        let version_ind = [LLID_CONTROL, 6, LL_VERSION_IND, 9, 0x59, 0, 0, 0];
+        let len = ll
+            .packet_received(&version_ind, true, &mut response)
+            .unwrap();
+        assert_eq!(&response[..len], &[LLID_CONTINUATION | NESN, 0]);
+        ll.exchange_done(Ok(()));
+
+        alarm.trigger();
+        assert_eq!(
+            radio.listening.get().unwrap().0,
+            RadioChannel::DataChannel14
+        );
+        let empty = [LLID_CONTINUATION | NESN | SN, 0];
+        let len = ll.packet_received(&empty, true, &mut response).unwrap();
+        assert_eq!(response[0], LLID_CONTROL | SN);
+        assert_eq!(&response[2..len], &[LL_VERSION_IND, 7, 0xff, 0xff, 0, 0]);
+        ll.exchange_done(Ok(()));
+
+        // A missed event moves on to the next channel.
+        alarm.trigger();
+        alarm.trigger();
+        alarm.trigger();
+        assert_eq!(
+            radio.listening.get().unwrap().0,
+            RadioChannel::DataChannel28
+        );
+
+        // Data is passed to the host, and the central terminates.
+        let data = [LLID_START, 3, 0xaa, 0xbb, 0xcc];
+        ll.packet_received(&data, true, &mut response).unwrap();
+        ll.exchange_done(Ok(()));
+        assert_eq!(host.received.get(), 3);
+        alarm.trigger();
+        let terminate = [
+            LLID_CONTROL | SN,
+            2,
+            LL_TERMINATE_IND,
+            REMOTE_USER_TERMINATED,
+        ];
+        ll.packet_received(&terminate, true, &mut response).unwrap();
+        ll.exchange_done(Ok(()));
+        assert_eq!(host.disconnected.get(), Some(REMOTE_USER_TERMINATED));
+        assert_eq!(
+            radio.advertised.get(),
+            Some(RadioChannel::AdvertisingChannel37)
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Bluetooth Low Energy peripheral stack.
+//!
+//! A connectable peripheral built from the following layers, each on top of
+//! the one below it:
+//!
+//! ```text
+//!           +----------------------------------------------+
+//!           | GattDriver: syscall driver of the GATT table |
+//!           +----------------------------------------------+
+//!           | GattServer: attribute table and ATT server   |
+//!           +----------------------------------------------+
+//!           | L2cap: fixed channels (ATT, signaling, SMP)  |
+//!           +----------------------------------------------+
+//!           | LinkLayer: advertising and connection events |
+//!           +----------------------------------------------+
+//!             hil::ble_advertising::BleConnectionDriver
+//!             hil::time::Alarm
+//! ```
+//!
+//! The stack supports one connection at a time, without security: pairing
+//! requests are answered with "pairing not supported".
+
+pub mod gatt;
+pub mod gatt_driver;
+pub mod l2cap;
+pub mod link_layer;
@@ -7,17 +7,29 @@
 //! driver but processes can request an advertising or scanning interval.
 //! Processes can also control the TX power used for their advertisements.
 //!
-//! Data payloads are limited to 31 bytes since the maximum advertising channel
-//! protocol data unit (PDU) is 37 bytes and includes a 6-byte header.
+//! Legacy advertisements (`ADV_IND`, `ADV_NONCONN_IND` and `ADV_SCAN_IND`)
+//! limit data payloads to 31 bytes since the maximum advertising channel
+//! protocol data unit (PDU) is 37 bytes and includes a 6-byte header. Scannable
+//! advertisements (`ADV_IND` and `ADV_SCAN_IND`) answer scan requests with a
+//! scan response of up to 31 bytes of data too. Nothing answers the connection
+//! requests of `ADV_IND`: connectable peripherals use `capsules::ble` instead.
+//!
+//! Extended advertisements (`ADV_EXT_IND`) carry up to 245 bytes of data: the
+//! `ADV_EXT_IND` sent on the advertising channels points to an `AUX_ADV_IND`
+//! on a data channel, which holds the data. Extended advertisements are
+//! neither connectable nor scannable.
 //!
 //! ### Allow system calls
 //!
-//! There is one ReadWrite and one ReadOnly allow buffers, both at index `0`.
+//! There are one ReadWrite and two ReadOnly allow buffers.
 //!
-//! * ReadOnly: Advertising data, containing the full _payload_ (i.e. excluding the header) the
-//!             process wishes to advertise.
-//! * ReadWrite: Passive scanning buffer, which is populated during BLE scans with complete (i.e.
-//!              including headers) advertising packets received on channels 37, 38 and 39.
+//! * ReadOnly 0: Advertising data, containing the full _payload_ (i.e. excluding the header) the
+//!               process wishes to advertise.
+//! * ReadOnly 1: Scan response data, sent in reply to the scan requests for a scannable
+//!               advertisement.
+//! * ReadWrite 0: Scanning buffer, which is populated during BLE scans with complete (i.e.
+//!                including headers) advertising packets received on channels 37, 38 and 39,
+//!                and with the scan responses during active scans.
 //!
 //! The possible return codes from the 'allow' system call indicate the following:
 //!
@@ -34,7 +46,9 @@
 //!  The `subscribe` is used to specify the specific operation, currently:
 //!
 //! * 0: provides a callback user-space when a device scanning for advertisements
-//!      and the callback is used to invoke user-space processes.
+//!      and the callback is used to invoke user-space processes. A scan that the
+//!      radio cannot perform stops, and the callback reports the error with a
+//!      length of 0.
 //!
 //! The possible return codes from the `allow` system call indicate the following:
 //!
@@ -47,9 +61,13 @@
 //! `command number` is used to specify the specific operation, currently
 //! the following commands are supported:
 //!
-//! * 0: start advertisement
+//! * 0: start advertisement, with the PDU type (`0` `ADV_IND`, `2` `ADV_NONCONN_IND`, `6`
+//!      `ADV_SCAN_IND` or `7` `ADV_EXT_IND`) and the advertising interval in ms
 //! * 1: stop advertisement or scanning
-//! * 5: start scanning
+//! * 2: configure the transmitted power
+//! * 5: start passive scanning
+//! * 6: start active scanning: scannable advertisements are answered with a scan request, and
+//!      the scan response is passed to the process like an advertisement
 //!
 //! The possible return codes from the `command` system call indicate the following:
 //!
@@ -100,6 +118,11 @@
 // This means that advertising events can collide. In this case, we just defer one of the
 // advertisements. Because we add a pseudo random pad to the timer interval each time (as required
 // by the Bluetooth specification) multiple collisions of the same processes are highly unlikely.
+//
+// Within an event, the timer of the process also paces the packets that do not immediately follow
+// each other: after a scannable advertisement, the radio keeps the channel for the scan request
+// and response, and the `AUX_ADV_IND` of an extended advertisement is sent at the time announced
+// in the `ADV_EXT_IND`.
 
 use core::cell::Cell;
 use core::cmp;
@@ -122,8 +145,9 @@ pub const DRIVER_NUM: usize = driver::NUM::BleAdvertising as usize;
 /// Ids for read-only allow buffers
 mod ro_allow {
     pub const ADV_DATA: usize = 0;
+    pub const SCAN_RESPONSE_DATA: usize = 1;
     /// The number of allow buffers the kernel stores for this grant
-    pub const COUNT: usize = 1;
+    pub const COUNT: usize = 2;
 }
 
 /// Ids for read-write allow buffers
@@ -133,20 +157,56 @@ mod rw_allow {
     pub const COUNT: usize = 1;
 }
 
-/// Advertisement Buffer
-pub static mut BUF: [u8; PACKET_LENGTH] = [0; PACKET_LENGTH];
+/// Advertisement Buffer, which holds a legacy advertisement followed by its
+/// scan response, or an extended advertising PDU
+pub static mut BUF: [u8; EXT_PACKET_LENGTH] = [0; EXT_PACKET_LENGTH];
 
 const PACKET_ADDR_LEN: usize = 6;
 const PACKET_LENGTH: usize = 39;
+const LEGACY_ADV_DATA_LEN: usize = PACKET_LENGTH - 2 - PACKET_ADDR_LEN;
 const ADV_HEADER_TXADD_OFFSET: usize = 6;
+const ADV_HEADER_PDU_TYPE_MASK: u8 = 0x0f;
+
+// BLUETOOTH SPECIFICATION Version 5.0 [Vol 6, Part B], section 2.3.4 Common Extended Advertising
+// Payload Format
+//
+// The payload starts with the length of the extended header and the advertising mode (always
+// non-connectable and non-scannable here), followed by the extended header: a flags byte, then the
+// fields present, in order.
+const EXT_PACKET_LENGTH: usize = 2 + 255;
+const EXT_HEADER_ADV_A: u8 = 1 << 0;
+const EXT_HEADER_ADI: u8 = 1 << 3;
+const EXT_HEADER_AUX_PTR: u8 = 1 << 4;
+/// Flags, ADI and AuxPtr
+const EXT_IND_HEADER_LEN: usize = 1 + 2 + 3;
+/// Flags, AdvA and ADI
+const AUX_ADV_IND_HEADER_LEN: usize = 1 + PACKET_ADDR_LEN + 2;
+const EXT_ADV_DATA_LEN: usize = EXT_PACKET_LENGTH - 3 - AUX_ADV_IND_HEADER_LEN;
+
+// The AUX_ADV_IND starts AUX_OFFSET_UNITS units of 300 µs after the start of the ADV_EXT_IND that
+// points to it, and up to one unit later. Both are started from the same reference time, and
+// take the same time to ramp up, so the AUX_ADV_IND is started a third of a unit into its window
+// to leave room for the latency of the timer.
+const AUX_PTR_OFFSET_UNITS_300_US: u32 = 1 << 7;
+const AUX_OFFSET_UNITS: u32 = 4;
+const AUX_DELAY_US: u32 = AUX_OFFSET_UNITS * 300 + 100;
 
 #[derive(PartialEq, Debug)]
 enum BLEState {
     Idle,
     ScanningIdle,
     Scanning(RadioChannel),
+    /// Waiting for the scan response to an advertisement received on the
+    /// channel.
+    ScanningPause(RadioChannel),
     AdvertisingIdle,
     Advertising(RadioChannel),
+    /// Done transmitting on the channel, and either answering scan requests
+    /// or waiting to send the `AUX_ADV_IND`.
+    AdvertisingPause(RadioChannel),
+    /// Transmitting the `AUX_ADV_IND` after the `ADV_EXT_IND` sent on the
+    /// channel.
+    AdvertisingAux(RadioChannel),
 }
 
 #[derive(Copy, Clone)]
@@ -177,11 +237,13 @@ const ADV_DIRECTED_IND: AdvPduType = 0b0001;
 const ADV_NONCONN_IND: AdvPduType = 0b0010;
 #[allow(dead_code)]
 const SCAN_REQ: AdvPduType = 0b0011;
-#[allow(dead_code)]
 const SCAN_RESP: AdvPduType = 0b0100;
 #[allow(dead_code)]
 const CONNECT_IND: AdvPduType = 0b0101;
 const ADV_SCAN_IND: AdvPduType = 0b0110;
+// BLUETOOTH SPECIFICATION Version 5.0 [Vol 6, Part B], section 2.3
+// Also the type of the AUX_ADV_IND, on the secondary advertising channel.
+const ADV_EXT_IND: AdvPduType = 0b0111;
 
 /// Process specific memory
 pub struct App {
@@ -193,6 +255,15 @@ pub struct App {
     pdu_type: AdvPduType,
     advertisement_interval_ms: u32,
     tx_power: u8,
+    /// Whether scans send scan requests.
+    active_scan: bool,
+    /// When the last advertisement was sent, in ticks.
+    tx_time: u32,
+    /// The data channel of the `AUX_ADV_IND` in the current event.
+    aux_channel: u8,
+    /// The Advertising Data Info of extended advertisements: the data ID in
+    /// the low 12 bits, and a set ID of 0.
+    adi: u16,
     /// The state of an app-specific pseudo random number.
     ///
     /// For example, it can be used for the pseudo-random `advDelay` parameter.
@@ -210,6 +281,10 @@ impl Default for App {
             process_status: Some(BLEState::Idle),
             tx_power: 0,
             advertisement_interval_ms: 200,
+            active_scan: false,
+            tx_time: 0,
+            aux_channel: 0,
+            adi: 0,
             // Just use any non-zero starting value by default
             random_nonce: 0xdeadbeef,
         }
@@ -258,41 +333,141 @@ impl App {
     {
         // Ensure we have an address set before advertisement
         self.generate_random_address(appid)?;
+        let kernel_tx = ble.kernel_tx.take().ok_or(ErrorCode::FAIL)?;
+        // The lengths of the advertisement and of its scan response, if any
+        let written = match self.pdu_type {
+            ADV_EXT_IND => Ok((self.write_ext_indication(kernel_tx), 0)),
+            ADV_IND | ADV_SCAN_IND => self
+                .write_legacy_pdu(kernel_data, ro_allow::ADV_DATA, self.pdu_type, kernel_tx)
+                .and_then(|len| {
+                    self.write_legacy_pdu(
+                        kernel_data,
+                        ro_allow::SCAN_RESPONSE_DATA,
+                        SCAN_RESP,
+                        &mut kernel_tx[len..],
+                    )
+                    .map(|response_len| (len, response_len))
+                }),
+            _ => self
+                .write_legacy_pdu(kernel_data, ro_allow::ADV_DATA, self.pdu_type, kernel_tx)
+                .map(|len| (len, 0)),
+        };
+        self.tx_time = ble.alarm.now().into_u32();
+        match written {
+            Ok((len, 0)) => ble.radio.transmit_advertisement(kernel_tx, len, channel),
+            Ok((len, response_len)) => {
+                ble.radio
+                    .transmit_scannable_advertisement(kernel_tx, len, response_len, channel)
+            }
+            Err(e) => {
+                ble.kernel_tx.replace(kernel_tx);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn send_aux_advertisement<'a, B, A>(
+        &mut self,
+        kernel_data: &GrantKernelData,
+        ble: &BLE<'a, B, A>,
+    ) -> Result<(), ErrorCode>
+    where
+        B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+        A: kernel::hil::time::Alarm<'a>,
+    {
+        let channel = RadioChannel::data_channel(self.aux_channel).ok_or(ErrorCode::INVAL)?;
+        let kernel_tx = ble.kernel_tx.take().ok_or(ErrorCode::FAIL)?;
+        match self.write_aux_advertisement(kernel_data, kernel_tx) {
+            Ok(len) => {
+                ble.radio.transmit_advertisement(kernel_tx, len, channel);
+                Ok(())
+            }
+            Err(e) => {
+                ble.kernel_tx.replace(kernel_tx);
+                Err(e)
+            }
+        }
+    }
+
+    // Write a legacy advertising channel PDU of type `pdu_type` to `buf`, with the address of the
+    // app and the data of read-only buffer `allow_num`, and return its length.
+    fn write_legacy_pdu(
+        &self,
+        kernel_data: &GrantKernelData,
+        allow_num: usize,
+        pdu_type: AdvPduType,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
         kernel_data
-            .get_readonly_processbuffer(ro_allow::ADV_DATA)
-            .and_then(|adv_data| {
-                adv_data.enter(|adv_data| {
-                    ble.kernel_tx
-                        .take()
-                        .map_or(Err(ErrorCode::FAIL), |kernel_tx| {
-                            let adv_data_len =
-                                cmp::min(kernel_tx.len() - PACKET_ADDR_LEN - 2, adv_data.len());
-                            let adv_data_corrected =
-                                adv_data.get_to(..adv_data_len).ok_or(ErrorCode::SIZE)?;
-                            let payload_len = adv_data_corrected.len() + PACKET_ADDR_LEN;
-                            {
-                                let (header, payload) = kernel_tx.split_at_mut(2);
-                                header[0] = self.pdu_type;
-                                match self.pdu_type {
-                                    ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
-                                        // Set TxAdd because AdvA field is going to be a "random"
-                                        // address
-                                        header[0] |= 1 << ADV_HEADER_TXADD_OFFSET;
-                                    }
-                                    _ => {}
-                                }
-                                // The LENGTH field is 6-bits wide, so make sure to truncate it
-                                header[1] = (payload_len & 0x3f) as u8;
+            .get_readonly_processbuffer(allow_num)
+            .and_then(|data| {
+                data.enter(|data| {
+                    let data_len = cmp::min(LEGACY_ADV_DATA_LEN, data.len());
+                    let len = 2 + PACKET_ADDR_LEN + data_len;
+                    let (header, payload) =
+                        buf.get_mut(..len).ok_or(ErrorCode::SIZE)?.split_at_mut(2);
+                    // Set TxAdd because AdvA field is going to be a "random" address
+                    header[0] = pdu_type | 1 << ADV_HEADER_TXADD_OFFSET;
+                    header[1] = (PACKET_ADDR_LEN + data_len) as u8;
 
-                                let (adva, data) = payload.split_at_mut(6);
-                                adva.copy_from_slice_or_err(&self.address)?;
-                                adv_data_corrected.copy_to_slice(&mut data[..adv_data_len]);
-                            }
-                            let total_len = cmp::min(PACKET_LENGTH, payload_len + 2);
-                            ble.radio
-                                .transmit_advertisement(kernel_tx, total_len, channel);
-                            Ok(())
-                        })
+                    let (adva, payload_data) = payload.split_at_mut(PACKET_ADDR_LEN);
+                    adva.copy_from_slice_or_err(&self.address)?;
+                    data.get_to(..data_len)
+                        .ok_or(ErrorCode::SIZE)?
+                        .copy_to_slice(payload_data);
+                    Ok(len)
+                })
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+
+    // Write the ADV_EXT_IND to `buf` and return its length. It only points to the AUX_ADV_IND,
+    // which holds the address and the data.
+    fn write_ext_indication(&self, buf: &mut [u8]) -> usize {
+        let aux_ptr =
+            (self.aux_channel as u32 & 0x3f) | AUX_PTR_OFFSET_UNITS_300_US | AUX_OFFSET_UNITS << 8;
+        let aux_ptr = aux_ptr.to_le_bytes();
+        let adi = self.adi.to_le_bytes();
+        let pdu = [
+            ADV_EXT_IND,
+            1 + EXT_IND_HEADER_LEN as u8,
+            EXT_IND_HEADER_LEN as u8,
+            EXT_HEADER_ADI | EXT_HEADER_AUX_PTR,
+            adi[0],
+            adi[1],
+            aux_ptr[0],
+            aux_ptr[1],
+            aux_ptr[2],
+        ];
+        buf[..pdu.len()].copy_from_slice(&pdu);
+        pdu.len()
+    }
+
+    // Write the AUX_ADV_IND to `buf`, with the advertising data, and return its length.
+    fn write_aux_advertisement(
+        &self,
+        kernel_data: &GrantKernelData,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let header_len = 3 + AUX_ADV_IND_HEADER_LEN;
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::ADV_DATA)
+            .and_then(|data| {
+                data.enter(|data| {
+                    let data_len = cmp::min(EXT_ADV_DATA_LEN, data.len());
+                    let len = header_len + data_len;
+                    let pdu = buf.get_mut(..len).ok_or(ErrorCode::SIZE)?;
+                    pdu[0] = ADV_EXT_IND | 1 << ADV_HEADER_TXADD_OFFSET;
+                    pdu[1] = (len - 2) as u8;
+                    pdu[2] = AUX_ADV_IND_HEADER_LEN as u8;
+                    pdu[3] = EXT_HEADER_ADV_A | EXT_HEADER_ADI;
+                    pdu[4..10].copy_from_slice(&self.address);
+                    pdu[10..12].copy_from_slice(&self.adi.to_le_bytes());
+                    data.get_to(..data_len)
+                        .ok_or(ErrorCode::SIZE)?
+                        .copy_to_slice(&mut pdu[header_len..]);
+                    Ok(len)
                 })
             })
             .unwrap_or(Err(ErrorCode::FAIL))
@@ -319,6 +494,20 @@ impl App {
         let period_ms = (self.advertisement_interval_ms + nonce) * F::frequency() / 1000;
         self.alarm_data.expiration = Expiration::Enabled(now, period_ms);
     }
+
+    // Set the alarm for the next packet of the current event, `us` microseconds after `reference`.
+    fn set_event_alarm<F: Frequency>(&mut self, reference: u32, us: u32) {
+        let dt = (us as u64 * F::frequency() as u64 / 1_000_000) as u32;
+        self.alarm_data.expiration = Expiration::Enabled(reference, dt);
+    }
+}
+
+fn next_advertising_channel(channel: RadioChannel) -> Option<RadioChannel> {
+    match channel {
+        RadioChannel::AdvertisingChannel37 => Some(RadioChannel::AdvertisingChannel38),
+        RadioChannel::AdvertisingChannel38 => Some(RadioChannel::AdvertisingChannel39),
+        _ => None,
+    }
 }
 
 pub struct BLE<'a, B, A>
@@ -398,6 +587,104 @@ where
                 .set_alarm(A::Ticks::from(next_ref), A::Ticks::from(next_dt));
         }
     }
+
+    fn advertise(
+        &self,
+        appid: kernel::ProcessId,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        channel: RadioChannel,
+    ) {
+        app.process_status = Some(BLEState::Advertising(channel));
+        self.sending_app.set(appid);
+        let _ = self.radio.set_tx_power(app.tx_power);
+        if app
+            .send_advertisement(appid, kernel_data, self, channel)
+            .is_err()
+        {
+            self.end_advertising_event(app);
+        }
+    }
+
+    fn advertise_aux(
+        &self,
+        appid: kernel::ProcessId,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        channel: RadioChannel,
+    ) {
+        app.process_status = Some(BLEState::AdvertisingAux(channel));
+        self.sending_app.set(appid);
+        if app.send_aux_advertisement(kernel_data, self).is_err() {
+            self.advertising_channel_done(appid, app, kernel_data, channel);
+        }
+    }
+
+    // Move on to the next advertising channel of the event, if any.
+    fn advertising_channel_done(
+        &self,
+        appid: kernel::ProcessId,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        channel: RadioChannel,
+    ) {
+        match next_advertising_channel(channel) {
+            Some(next) => self.advertise(appid, app, kernel_data, next),
+            None => self.end_advertising_event(app),
+        }
+    }
+
+    fn end_advertising_event(&self, app: &mut App) {
+        self.busy.set(false);
+        app.process_status = Some(BLEState::AdvertisingIdle);
+        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+    }
+
+    fn scan(
+        &self,
+        appid: kernel::ProcessId,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        channel: RadioChannel,
+    ) {
+        app.process_status = Some(BLEState::Scanning(channel));
+        self.receiving_app.set(appid);
+        let _ = self.radio.set_tx_power(app.tx_power);
+        if app.active_scan {
+            let _ = app.generate_random_address(appid);
+            let result = self
+                .radio
+                .receive_advertisement_active(channel, &app.address);
+            if result.is_err() {
+                // The radio can't scan actively, so the scan is over
+                self.busy.set(false);
+                app.process_status = Some(BLEState::Idle);
+                kernel_data
+                    .schedule_upcall(0, (kernel::errorcode::into_statuscode(result), 0, 0))
+                    .ok();
+            }
+        } else {
+            self.radio.receive_advertisement(channel);
+        }
+    }
+
+    // Move on to the next advertising channel of the scan, if any.
+    fn scanning_channel_done(
+        &self,
+        appid: kernel::ProcessId,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        channel: RadioChannel,
+    ) {
+        match next_advertising_channel(channel) {
+            Some(next) => self.scan(appid, app, kernel_data, next),
+            None => {
+                self.busy.set(false);
+                app.process_status = Some(BLEState::ScanningIdle);
+                app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+            }
+        }
+    }
 }
 
 // Timer alarm
@@ -427,6 +714,25 @@ where
                 let t0 = A::Ticks::from(reference);
                 let expired = !now.within_range(t0, exp);
                 if expired {
+                    // The app is using the radio, and the next packet of its event is due
+                    match app.process_status {
+                        Some(BLEState::AdvertisingPause(channel)) => {
+                            app.alarm_data.expiration = Expiration::Disabled;
+                            if app.pdu_type == ADV_EXT_IND {
+                                self.advertise_aux(appid, app, kernel_data, channel);
+                            } else {
+                                self.advertising_channel_done(appid, app, kernel_data, channel);
+                            }
+                            return;
+                        }
+                        Some(BLEState::ScanningPause(channel)) => {
+                            app.alarm_data.expiration = Expiration::Disabled;
+                            self.scanning_channel_done(appid, app, kernel_data, channel);
+                            return;
+                        }
+                        _ => (),
+                    }
+
                     if self.busy.get() {
                         // The radio is currently busy, so we won't be able to start the
                         // operation at the appropriate time. Instead, reschedule the
@@ -442,25 +748,17 @@ where
                     match app.process_status {
                         Some(BLEState::AdvertisingIdle) => {
                             self.busy.set(true);
-                            app.process_status =
-                                Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37));
-                            self.sending_app.set(appid);
-                            let _ = self.radio.set_tx_power(app.tx_power);
-                            let _ = app.send_advertisement(
+                            app.aux_channel = (app.random_nonce() % 37) as u8;
+                            self.advertise(
                                 appid,
+                                app,
                                 kernel_data,
-                                &self,
                                 RadioChannel::AdvertisingChannel37,
                             );
                         }
                         Some(BLEState::ScanningIdle) => {
                             self.busy.set(true);
-                            app.process_status =
-                                Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37));
-                            self.receiving_app.set(appid);
-                            let _ = self.radio.set_tx_power(app.tx_power);
-                            self.radio
-                                .receive_advertisement(RadioChannel::AdvertisingChannel37);
+                            self.scan(appid, app, kernel_data, RadioChannel::AdvertisingChannel37);
                         }
                         _ => debug!("app: {:?} \t invalid state {:?}", appid, app.process_status),
                     }
@@ -514,25 +812,20 @@ where
                 }
 
                 match app.process_status {
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37)) => {
-                        app.process_status =
-                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38));
-                        self.receiving_app.set(*appid);
-                        let _ = self.radio.set_tx_power(app.tx_power);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel38);
-                    }
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38)) => {
-                        app.process_status =
-                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39));
-                        self.receiving_app.set(*appid);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel39);
-                    }
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
-                        self.busy.set(false);
-                        app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+                    Some(BLEState::Scanning(channel)) => {
+                        let scannable = result == Ok(())
+                            && len >= 2
+                            && matches!(buf[0] & ADV_HEADER_PDU_TYPE_MASK, ADV_IND | ADV_SCAN_IND);
+                        if app.active_scan && scannable {
+                            // The radio sends a scan request and receives the response
+                            app.process_status = Some(BLEState::ScanningPause(channel));
+                            app.set_event_alarm::<A::Frequency>(
+                                self.alarm.now().into_u32(),
+                                ble_advertising::SCAN_RESPONSE_WINDOW_US,
+                            );
+                        } else {
+                            self.scanning_channel_done(*appid, app, kernel_data, channel);
+                        }
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -556,35 +849,25 @@ where
         self.sending_app.map(|appid| {
             let _ = self.app.enter(*appid, |app, kernel_data| {
                 match app.process_status {
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37)) => {
-                        app.process_status =
-                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel38));
-                        self.sending_app.set(*appid);
-                        let _ = self.radio.set_tx_power(app.tx_power);
-                        let _ = app.send_advertisement(
-                            *appid,
-                            kernel_data,
-                            &self,
-                            RadioChannel::AdvertisingChannel38,
-                        );
-                    }
-
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel38)) => {
-                        app.process_status =
-                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39));
-                        self.sending_app.set(*appid);
-                        let _ = app.send_advertisement(
-                            *appid,
-                            kernel_data,
-                            &self,
-                            RadioChannel::AdvertisingChannel39,
-                        );
-                    }
-
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39)) => {
-                        self.busy.set(false);
-                        app.process_status = Some(BLEState::AdvertisingIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+                    Some(BLEState::Advertising(channel)) => match app.pdu_type {
+                        // The radio answers scan requests in the meantime
+                        ADV_IND | ADV_SCAN_IND => {
+                            app.process_status = Some(BLEState::AdvertisingPause(channel));
+                            app.set_event_alarm::<A::Frequency>(
+                                self.alarm.now().into_u32(),
+                                ble_advertising::SCAN_RESPONSE_WINDOW_US,
+                            );
+                        }
+                        // Wait for the offset announced in the AuxPtr
+                        ADV_EXT_IND => {
+                            app.process_status = Some(BLEState::AdvertisingPause(channel));
+                            let tx_time = app.tx_time;
+                            app.set_event_alarm::<A::Frequency>(tx_time, AUX_DELAY_US);
+                        }
+                        _ => self.advertising_channel_done(*appid, app, kernel_data, channel),
+                    },
+                    Some(BLEState::AdvertisingAux(channel)) => {
+                        self.advertising_channel_done(*appid, app, kernel_data, channel)
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -616,10 +899,11 @@ where
                         if let Some(BLEState::Idle) = app.process_status {
                             let pdu_type = data as AdvPduType;
                             match pdu_type {
-                                ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
+                                ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND | ADV_EXT_IND => {
                                     app.pdu_type = pdu_type;
                                     app.process_status = Some(BLEState::AdvertisingIdle);
                                     app.random_nonce = self.alarm.now().into_u32();
+                                    app.adi = (app.random_nonce() & 0x0fff) as u16;
                                     app.advertisement_interval_ms = cmp::max(20, interval as u32);
                                     app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                                     Ok(())
@@ -686,11 +970,12 @@ where
                     .unwrap_or_else(|err| err.into())
             }
 
-            // Passive scanning mode
-            5 => {
+            // Passive or active scanning mode
+            5 | 6 => {
                 self.app
                     .enter(appid, |app, _| {
                         if let Some(BLEState::Idle) = app.process_status {
+                            app.active_scan = command_num == 6;
                             app.process_status = Some(BLEState::ScanningIdle);
                             app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                             Ok(())
@@ -720,3 +1005,54 @@ where
         self.app.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ext_indication_points_to_aux_packet() {
+        let mut app = App::default();
+        app.aux_channel = 17;
+        app.adi = 0x0abc;
+        let mut buf = [0; EXT_PACKET_LENGTH];
+        let len = app.write_ext_indication(&mut buf);
+
+        assert_eq!(len, 2 + 1 + EXT_IND_HEADER_LEN);
+        assert_eq!(buf[0] & ADV_HEADER_PDU_TYPE_MASK, ADV_EXT_IND);
+        assert_eq!(buf[1] as usize, len - 2);
+        // Non-connectable and non-scannable
+        assert_eq!(buf[2], EXT_IND_HEADER_LEN as u8);
+        assert_eq!(buf[3], EXT_HEADER_ADI | EXT_HEADER_AUX_PTR);
+        assert_eq!(&buf[4..6], &[0xbc, 0x0a]);
+
+        let aux_ptr = u32::from_le_bytes([buf[6], buf[7], buf[8], 0]);
+        assert_eq!(aux_ptr & 0x3f, 17);
+        assert_eq!(
+            aux_ptr & AUX_PTR_OFFSET_UNITS_300_US,
+            AUX_PTR_OFFSET_UNITS_300_US
+        );
+        assert_eq!((aux_ptr >> 8) & 0x1fff, AUX_OFFSET_UNITS);
+        // LE 1M
+        assert_eq!(aux_ptr >> 21, 0);
+        assert!(
+            AUX_DELAY_US >= AUX_OFFSET_UNITS * 300 && AUX_DELAY_US < (AUX_OFFSET_UNITS + 1) * 300
+        );
+    }
+
+    #[test]
+    fn advertising_channels_in_order() {
+        assert_eq!(
+            next_advertising_channel(RadioChannel::AdvertisingChannel37),
+            Some(RadioChannel::AdvertisingChannel38)
+        );
+        assert_eq!(
+            next_advertising_channel(RadioChannel::AdvertisingChannel38),
+            Some(RadioChannel::AdvertisingChannel39)
+        );
+        assert_eq!(
+            next_advertising_channel(RadioChannel::AdvertisingChannel39),
+            None
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Software implementation of the ChaCha20-Poly1305 AEAD (RFC 8439).
+//!
+//! This capsule implements `hil::symmetric_encryption::ChaCha20Poly1305`
+//! entirely in software. Like `sha_software`, all operations complete
+//! asynchronously from a deferred call, and long messages are processed a few
+//! blocks at a time, re-scheduling the deferred call in between, so that a
+//! large buffer does not block the rest of the kernel.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let chacha = static_init!(
+//!     capsules::chacha20_poly1305::ChaCha20Poly1305Software<'static>,
+//!     capsules::chacha20_poly1305::ChaCha20Poly1305Software::new(dynamic_deferred_caller)
+//! );
+//! chacha.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(chacha).unwrap(), // Unwrap fail = no deferred call slot available for ChaCha20-Poly1305
+//! );
+//! ```
+
+use core::cell::Cell;
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::symmetric_encryption::{
+    AEADClient, ChaCha20Poly1305, AEAD, AEAD_TAG_LENGTH, CHACHA20_POLY1305_KEY_SIZE,
+    CHACHA20_POLY1305_NONCE_LENGTH,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Number of message bytes processed per deferred call. Must be a multiple
+/// of `CHACHA20_BLOCK_SIZE`.
+const BYTES_PER_CALL: usize = 256;
+
+const CHACHA20_BLOCK_SIZE: usize = 64;
+const POLY1305_BLOCK_SIZE: usize = 16;
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function.
+fn chacha20_block(
+    key: &[u8; CHACHA20_POLY1305_KEY_SIZE],
+    counter: u32,
+    nonce: &[u8; CHACHA20_POLY1305_NONCE_LENGTH],
+) -> [u8; CHACHA20_BLOCK_SIZE] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for i in 0..8 {
+        input[4 + i] = le32(&key[4 * i..]);
+    }
+    input[12] = counter;
+    for i in 0..3 {
+        input[13 + i] = le32(&nonce[4 * i..]);
+    }
+
+    let mut s = input;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+
+    let mut out = [0; CHACHA20_BLOCK_SIZE];
+    for i in 0..16 {
+        out[4 * i..4 * i + 4].copy_from_slice(&s[i].wrapping_add(input[i]).to_le_bytes());
+    }
+    out
+}
+
+/// Poly1305 with 26 bit limbs.
+#[derive(Clone, Copy, Default)]
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+}
+
+impl Poly1305 {
+    fn new(key: &[u8]) -> Poly1305 {
+        Poly1305 {
+            r: [
+                le32(&key[0..]) & 0x3ffffff,
+                (le32(&key[3..]) >> 2) & 0x3ffff03,
+                (le32(&key[6..]) >> 4) & 0x3ffc0ff,
+                (le32(&key[9..]) >> 6) & 0x3f03fff,
+                (le32(&key[12..]) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [
+                le32(&key[16..]),
+                le32(&key[20..]),
+                le32(&key[24..]),
+                le32(&key[28..]),
+            ],
+        }
+    }
+
+    /// Process `data`, zero-padding it to a multiple of the block size as the
+    /// AEAD construction requires.
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(POLY1305_BLOCK_SIZE) {
+            let mut block = [0; POLY1305_BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.block(&block);
+        }
+    }
+
+    fn block(&mut self, m: &[u8; POLY1305_BLOCK_SIZE]) {
+        let [r0, r1, r2, r3, r4] = self.r;
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+        let h = &mut self.h;
+
+        h[0] += le32(&m[0..]) & 0x3ffffff;
+        h[1] += (le32(&m[3..]) >> 2) & 0x3ffffff;
+        h[2] += (le32(&m[6..]) >> 4) & 0x3ffffff;
+        h[3] += (le32(&m[9..]) >> 6) & 0x3ffffff;
+        h[4] += (le32(&m[12..]) >> 8) | (1 << 24);
+
+        let mul = |a: u32, b: u32| a as u64 * b as u64;
+        let d0 = mul(h[0], r0) + mul(h[1], s4) + mul(h[2], s3) + mul(h[3], s2) + mul(h[4], s1);
+        let mut d1 = mul(h[0], r1) + mul(h[1], r0) + mul(h[2], s4) + mul(h[3], s3) + mul(h[4], s2);
+        let mut d2 = mul(h[0], r2) + mul(h[1], r1) + mul(h[2], r0) + mul(h[3], s4) + mul(h[4], s3);
+        let mut d3 = mul(h[0], r3) + mul(h[1], r2) + mul(h[2], r1) + mul(h[3], r0) + mul(h[4], s4);
+        let mut d4 = mul(h[0], r4) + mul(h[1], r3) + mul(h[2], r2) + mul(h[3], r1) + mul(h[4], r0);
+
+        h[0] = d0 as u32 & 0x3ffffff;
+        d1 += d0 >> 26;
+        h[1] = d1 as u32 & 0x3ffffff;
+        d2 += d1 >> 26;
+        h[2] = d2 as u32 & 0x3ffffff;
+        d3 += d2 >> 26;
+        h[3] = d3 as u32 & 0x3ffffff;
+        d4 += d3 >> 26;
+        h[4] = d4 as u32 & 0x3ffffff;
+        h[0] += (d4 >> 26) as u32 * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+    }
+
+    fn finish(&self) -> [u8; AEAD_TAG_LENGTH] {
+        let mut h = self.h;
+
+        // Fully carry h.
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 26;
+            h[i - 1] &= 0x3ffffff;
+        }
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= 0x3ffffff;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+
+        // Compute h - p and select it if h >= p.
+        let mut g = [0u32; 5];
+        let mut carry = 5;
+        for i in 0..5 {
+            let t = h[i] + carry;
+            carry = t >> 26;
+            g[i] = t & 0x3ffffff;
+        }
+        g[4] = g[4].wrapping_add(carry << 26).wrapping_sub(1 << 26);
+        let mask = (g[4] >> 31).wrapping_sub(1);
+        for i in 0..5 {
+            h[i] = (h[i] & !mask) | (g[i] & mask);
+        }
+
+        // h = (h + pad) mod 2^128
+        let words = [
+            h[0] | (h[1] << 26),
+            (h[1] >> 6) | (h[2] << 20),
+            (h[2] >> 12) | (h[3] << 14),
+            (h[3] >> 18) | (h[4] << 8),
+        ];
+        let mut tag = [0; AEAD_TAG_LENGTH];
+        let mut f = 0u64;
+        for i in 0..4 {
+            f = words[i] as u64 + self.pad[i] as u64 + (f >> 32);
+            tag[4 * i..4 * i + 4].copy_from_slice(&(f as u32).to_le_bytes());
+        }
+        tag
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// An operation was started and the one-time Poly1305 key has to be
+    /// derived.
+    Start,
+    /// Encrypting or decrypting the message.
+    Crypt,
+}
+
+pub struct ChaCha20Poly1305Software<'a> {
+    client: OptionalCell<&'a dyn AEADClient>,
+    state: Cell<State>,
+    encrypting: Cell<bool>,
+    key: Cell<[u8; CHACHA20_POLY1305_KEY_SIZE]>,
+    nonce: Cell<[u8; CHACHA20_POLY1305_NONCE_LENGTH]>,
+
+    buf: TakeCell<'static, [u8]>,
+    /// `(a_off, m_off, m_len)` of the current operation.
+    pos: Cell<(usize, usize, usize)>,
+    /// Number of message bytes already encrypted or decrypted.
+    processed: Cell<usize>,
+    poly: Cell<Poly1305>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> ChaCha20Poly1305Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> ChaCha20Poly1305Software<'a> {
+        ChaCha20Poly1305Software {
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            encrypting: Cell::new(false),
+            key: Cell::new([0; CHACHA20_POLY1305_KEY_SIZE]),
+            nonce: Cell::new([0; CHACHA20_POLY1305_NONCE_LENGTH]),
+            buf: TakeCell::empty(),
+            pos: Cell::new((0, 0, 0)),
+            processed: Cell::new(0),
+            poly: Cell::new(Poly1305::default()),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Derive the Poly1305 key and authenticate the associated data.
+    fn start(&self, buf: &[u8]) {
+        let (a_off, m_off, _) = self.pos.get();
+        let poly_key = chacha20_block(&self.key.get(), 0, &self.nonce.get());
+        let mut poly = Poly1305::new(&poly_key[..32]);
+        poly.update_padded(&buf[a_off..m_off]);
+        self.poly.set(poly);
+    }
+
+    /// Encrypt or decrypt the next part of the message. Returns true once the
+    /// whole message has been processed.
+    fn crypt_step(&self, buf: &mut [u8]) -> bool {
+        let (_, m_off, m_len) = self.pos.get();
+        let processed = self.processed.get();
+        let len = core::cmp::min(BYTES_PER_CALL, m_len - processed);
+        let data = &mut buf[m_off + processed..m_off + processed + len];
+
+        let mut poly = self.poly.get();
+        if !self.encrypting.get() {
+            poly.update_padded(data);
+        }
+        let key = self.key.get();
+        let nonce = self.nonce.get();
+        for (i, chunk) in data.chunks_mut(CHACHA20_BLOCK_SIZE).enumerate() {
+            let counter = 1 + ((processed / CHACHA20_BLOCK_SIZE) + i) as u32;
+            let key_stream = chacha20_block(&key, counter, &nonce);
+            chunk
+                .iter_mut()
+                .zip(key_stream.iter())
+                .for_each(|(d, k)| *d ^= *k);
+        }
+        if self.encrypting.get() {
+            poly.update_padded(data);
+        }
+        self.poly.set(poly);
+
+        self.processed.set(processed + len);
+        processed + len == m_len
+    }
+
+    /// Compute the tag and write or check it.
+    fn finish_tag(&self, buf: &mut [u8]) -> bool {
+        let (a_off, m_off, m_len) = self.pos.get();
+        let mut poly = self.poly.get();
+        let mut lengths = [0; POLY1305_BLOCK_SIZE];
+        lengths[..8].copy_from_slice(&((m_off - a_off) as u64).to_le_bytes());
+        lengths[8..].copy_from_slice(&(m_len as u64).to_le_bytes());
+        poly.block(&lengths);
+        let tag = poly.finish();
+        self.poly.set(Poly1305::default());
+
+        let tag_off = m_off + m_len;
+        if self.encrypting.get() {
+            buf[tag_off..tag_off + AEAD_TAG_LENGTH].copy_from_slice(&tag);
+            true
+        } else {
+            // Compare in constant time.
+            buf[tag_off..tag_off + AEAD_TAG_LENGTH]
+                .iter()
+                .zip(tag.iter())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+        }
+    }
+}
+
+impl<'a> AEAD<'a> for ChaCha20Poly1305Software<'a> {
+    fn set_client(&'a self, client: &'a dyn AEADClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() != CHACHA20_POLY1305_KEY_SIZE {
+            Err(ErrorCode::INVAL)
+        } else {
+            let mut new_key = [0u8; CHACHA20_POLY1305_KEY_SIZE];
+            new_key.copy_from_slice(key);
+            self.key.set(new_key);
+            Ok(())
+        }
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        if nonce.len() != CHACHA20_POLY1305_NONCE_LENGTH {
+            Err(ErrorCode::INVAL)
+        } else {
+            let mut new_nonce = [0u8; CHACHA20_POLY1305_NONCE_LENGTH];
+            new_nonce.copy_from_slice(nonce);
+            self.nonce.set(new_nonce);
+            Ok(())
+        }
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        let end = m_off
+            .checked_add(m_len)
+            .and_then(|tag_off| tag_off.checked_add(AEAD_TAG_LENGTH));
+        if !(a_off <= m_off && end.map_or(false, |end| end <= buf.len())) {
+            return Err((ErrorCode::INVAL, buf));
+        }
+
+        self.encrypting.set(encrypting);
+        self.pos.set((a_off, m_off, m_len));
+        self.processed.set(0);
+        self.buf.replace(buf);
+        self.state.set(State::Start);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+}
+
+impl<'a> ChaCha20Poly1305<'a> for ChaCha20Poly1305Software<'a> {}
+
+impl<'a> DynamicDeferredCallClient for ChaCha20Poly1305Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        let done = self.buf.map_or(true, |buf| {
+            if self.state.get() == State::Start {
+                self.start(buf);
+                self.state.set(State::Crypt);
+            }
+            let (_, _, m_len) = self.pos.get();
+            self.processed.get() == m_len || self.crypt_step(buf)
+        });
+
+        if !done {
+            self.handle.map(|handle| self.deferred_caller.set(*handle));
+            return;
+        }
+
+        let tag_valid = self.buf.map_or(false, |buf| self.finish_tag(buf));
+        self.state.set(State::Idle);
+        self.buf.take().map(|buf| {
+            self.client.map(move |client| {
+                client.crypt_done(buf, Ok(()), tag_valid);
+            });
+        });
+    }
+}
@@ -1,164 +1,632 @@
-//! Provides userspace with access CTAP devices over any transport
-//! layer (USB HID, BLE, NFC). Currently only USB HID is supported.
+//! Provides userspace with a FIDO authenticator transport: the CTAPHID
+//! protocol over USB HID, the only transport supported so far.
+//!
+//! The kernel implements the CTAPHID transport layer. `CtapHidTransport`
+//! allocates channels (`CTAPHID_INIT`), answers `CTAPHID_PING` and
+//! `CTAPHID_LOCK`, reassembles the requests split across several HID
+//! reports, fragments the responses, and sends keep-alives while a
+//! `CTAPHID_CBOR` request is processed. `CtapDriver` passes the complete
+//! `CTAPHID_MSG` (U2F) and `CTAPHID_CBOR` requests to the app, which only
+//! deals with the messages themselves.
+//!
+//! One transaction is in progress at a time: the other channels are told
+//! the authenticator is busy until it is answered.
 //!
 //! Setup
 //! -----
 //!
-//! You need a device that provides the `hil::usb::UsbController` and
-//! `hil::usb_hid::UsbHid` trait.
+//! You need a device that provides the `hil::usb::UsbController` trait, and
+//! an alarm for the keep-alives and timeouts.
 //!
 //! ```rust
 //!     let ctap_send_buffer = static_init!([u8; 64], [0; 64]);
 //!     let ctap_recv_buffer = static_init!([u8; 64], [0; 64]);
 //!
 //!     let (ctap, ctap_driver) = components::ctap::CtapComponent::new(
+//!         board_kernel,
+//!         capsules::ctap::DRIVER_NUM,
 //!         &earlgrey::usbdev::USB,
+//!         mux_alarm,
 //!         0x1337, // My important company
 //!         0x0DEC, // My device name
 //!         strings,
-//!         board_kernel,
 //!         ctap_send_buffer,
 //!         ctap_recv_buffer,
 //!     )
-//!     .finalize(components::usb_ctap_component_helper!(lowrisc::usbdev::Usb));
+//!     .finalize(components::usb_ctap_component_helper!(
+//!         lowrisc::usbdev::Usb,
+//!         earlgrey::timer::RvTimer
+//!     ));
 //!
 //!     ctap.enable();
 //!     ctap.attach();
 //! ```
 //!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Read-only allow
+//!
+//! - `0`: The response to the request being processed.
+//!
+//! ### Read-write allow
+//!
+//! - `0`: Receives the requests.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Transport events. The callback signature is
+//!   `fn(event: u32, arg1: u32, arg2: u32)`, where `event` is `0` when a
+//!   request of `arg2` bytes was received with command `arg1`
+//!   (`CTAPHID_MSG` or `CTAPHID_CBOR`), `1` when the response was sent, and
+//!   `2` when the host cancelled the request.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Start receiving requests.
+//! - `2`: Send the first `data1` bytes of the response buffer.
+//! - `3`: Set the status of the keep-alives to `data1`: `1` while processing,
+//!   `2` while waiting for the user presence.
+//!
+//! FIDO Client to Authenticator Protocol v2.0, section 8.1
+//! USB Human Interface Device (USB HID)
 
 use core::cell::Cell;
-use core::marker::PhantomData;
+use core::cmp;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, ConvertTicks};
 use kernel::hil::usb_hid;
-use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::{ErrorCode, ProcessId};
 
+use crate::process_owner;
+
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::CtapHid as usize;
 
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const SEND: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
 /// Ids for read-write allow buffers
 mod rw_allow {
     pub const RECV: usize = 0;
-    pub const SEND: usize = 1;
     /// The number of allow buffers the kernel stores for this grant
-    pub const COUNT: usize = 2;
+    pub const COUNT: usize = 1;
 }
 
-pub struct App {
-    can_receive: Cell<bool>,
+// CTAPHID commands
+pub const CTAPHID_PING: u8 = 0x01;
+pub const CTAPHID_MSG: u8 = 0x03;
+pub const CTAPHID_LOCK: u8 = 0x04;
+pub const CTAPHID_INIT: u8 = 0x06;
+pub const CTAPHID_CBOR: u8 = 0x10;
+pub const CTAPHID_CANCEL: u8 = 0x11;
+pub const CTAPHID_KEEPALIVE: u8 = 0x3b;
+pub const CTAPHID_ERROR: u8 = 0x3f;
+
+// CTAPHID_ERROR codes
+pub const ERR_INVALID_CMD: u8 = 0x01;
+pub const ERR_INVALID_PAR: u8 = 0x02;
+pub const ERR_INVALID_LEN: u8 = 0x03;
+pub const ERR_INVALID_SEQ: u8 = 0x04;
+pub const ERR_MSG_TIMEOUT: u8 = 0x05;
+pub const ERR_CHANNEL_BUSY: u8 = 0x06;
+pub const ERR_INVALID_CHANNEL: u8 = 0x0b;
+pub const ERR_OTHER: u8 = 0x7f;
+
+// CTAPHID_KEEPALIVE statuses
+pub const STATUS_PROCESSING: u8 = 1;
+pub const STATUS_UPNEEDED: u8 = 2;
+
+/// The CTAP2 status of a cancelled `CTAPHID_CBOR` request.
+const CTAP2_ERR_KEEPALIVE_CANCEL: u8 = 0x2d;
+
+pub const BROADCAST_CID: u32 = 0xffff_ffff;
+pub const PACKET_LEN: usize = 64;
+const INIT_HEADER_LEN: usize = 7;
+const CONT_HEADER_LEN: usize = 5;
+const INIT_FLAG: u8 = 0x80;
+
+const NONCE_LEN: usize = 8;
+const PROTOCOL_VERSION: u8 = 2;
+const DEVICE_VERSION: [u8; 3] = [2, 0, 0];
+const CAPABILITY_CBOR: u8 = 0x04;
+/// Nonce, channel, protocol and device versions, and capabilities.
+const INIT_RESPONSE_LEN: usize = NONCE_LEN + 4 + 1 + 3 + 1;
+
+/// Period of the keep-alives, and unit of the timeouts.
+const TICK_MS: u32 = 100;
+/// How long the host may pause between the packets of a request.
+const MSG_TIMEOUT_TICKS: u8 = 5;
+const MAX_LOCK_SECONDS: u8 = 10;
+
+/// The side of the transport that processes the requests.
+pub trait CtapClient {
+    /// A `CTAPHID_MSG` or `CTAPHID_CBOR` request was received. It must be
+    /// answered with `CtapHidTransport::send_response`, but not from within
+    /// this call. Fails if the request cannot be processed, in which case the
+    /// host receives an error.
+    fn message_received(&self, cmd: u8, message: &[u8]) -> Result<(), ErrorCode>;
+
+    /// The host cancelled the request, or started over: it must not be
+    /// answered anymore.
+    fn message_cancelled(&self);
+
+    /// The response was sent.
+    fn response_sent(&self);
 }
 
-impl Default for App {
-    fn default() -> App {
-        App {
-            can_receive: Cell::new(false),
-        }
+/// A response that fits in one packet, sent between the packets of a
+/// message.
+#[derive(Copy, Clone)]
+struct Reply {
+    cid: u32,
+    cmd: u8,
+    len: usize,
+    payload: [u8; INIT_RESPONSE_LEN],
+}
+
+impl Reply {
+    fn new(cid: u32, cmd: u8, payload: &[u8]) -> Reply {
+        let mut reply = Reply {
+            cid,
+            cmd,
+            len: payload.len(),
+            payload: [0; INIT_RESPONSE_LEN],
+        };
+        reply.payload[..payload.len()].copy_from_slice(payload);
+        reply
+    }
+
+    fn error(cid: u32, code: u8) -> Reply {
+        Reply::new(cid, CTAPHID_ERROR, &[code])
+    }
+
+    fn write(&self, packet: &mut [u8; PACKET_LEN]) {
+        write_init_header(packet, self.cid, self.cmd, self.len);
+        packet[INIT_HEADER_LEN..INIT_HEADER_LEN + self.len]
+            .copy_from_slice(&self.payload[..self.len]);
     }
 }
 
-pub struct CtapDriver<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> {
-    usb: Option<&'a U>,
+fn write_init_header(packet: &mut [u8; PACKET_LEN], cid: u32, cmd: u8, len: usize) {
+    packet.iter_mut().for_each(|byte| *byte = 0);
+    packet[0..4].copy_from_slice(&cid.to_be_bytes());
+    packet[4] = cmd | INIT_FLAG;
+    packet[5..7].copy_from_slice(&(len as u16).to_be_bytes());
+}
 
-    app: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
-    appid: OptionalCell<ProcessId>,
-    phantom: PhantomData<&'a U>,
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Reassembling a request from its continuation packets.
+    Receiving {
+        cmd: u8,
+        len: usize,
+        received: usize,
+        seq: u8,
+    },
+    /// The client is processing the request.
+    Processing {
+        cmd: u8,
+    },
+    /// Fragmenting a response.
+    Sending {
+        cmd: u8,
+        len: usize,
+        sent: usize,
+        packets: u8,
+        from_client: bool,
+    },
+}
+
+/// The CTAPHID transport layer, over the 64-byte reports of a USB HID
+/// interface.
+pub struct CtapHidTransport<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> {
+    usb: &'a U,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn CtapClient>,
 
     send_buffer: TakeCell<'static, [u8; 64]>,
     recv_buffer: TakeCell<'static, [u8; 64]>,
+    /// The request being reassembled, or the response being fragmented.
+    message: TakeCell<'static, [u8]>,
+
+    listening: Cell<bool>,
+    state: Cell<State>,
+    /// The channel of the transaction, unless `state` is `Idle`.
+    cid: Cell<u32>,
+    next_cid: Cell<u32>,
+    reply: OptionalCell<Reply>,
+    keepalive_status: Cell<u8>,
+    /// Ticks since the last packet of the request being received.
+    idle_ticks: Cell<u8>,
+    /// The channel that locked the transport, and the remaining ticks.
+    lock: OptionalCell<(u32, u16)>,
 }
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> CtapDriver<'a, U> {
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> CtapHidTransport<'a, U, A> {
+    /// `message` bounds the length of the requests and responses, 1024
+    /// bytes by default in CTAP2.
     pub fn new(
-        usb: Option<&'a U>,
+        usb: &'a U,
+        alarm: &'a A,
         send_buffer: &'static mut [u8; 64],
         recv_buffer: &'static mut [u8; 64],
-        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
-    ) -> CtapDriver<'a, U> {
-        CtapDriver {
-            usb: usb,
-            app: grant,
-            appid: OptionalCell::empty(),
-            phantom: PhantomData,
+        message: &'static mut [u8],
+    ) -> CtapHidTransport<'a, U, A> {
+        CtapHidTransport {
+            usb,
+            alarm,
+            client: OptionalCell::empty(),
             send_buffer: TakeCell::new(send_buffer),
             recv_buffer: TakeCell::new(recv_buffer),
+            message: TakeCell::new(message),
+            listening: Cell::new(false),
+            state: Cell::new(State::Idle),
+            cid: Cell::new(0),
+            next_cid: Cell::new(1),
+            reply: OptionalCell::empty(),
+            keepalive_status: Cell::new(STATUS_PROCESSING),
+            idle_ticks: Cell::new(0),
+            lock: OptionalCell::empty(),
         }
     }
-}
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> usb_hid::UsbHid<'a, [u8; 64]> for CtapDriver<'a, U> {
-    fn send_buffer(
-        &'a self,
-        send: &'static mut [u8; 64],
-    ) -> Result<usize, (ErrorCode, &'static mut [u8; 64])> {
-        if let Some(usb) = self.usb {
-            usb.send_buffer(send)
-        } else {
-            Err((ErrorCode::NOSUPPORT, send))
+    pub fn set_client(&self, client: &'a dyn CtapClient) {
+        self.client.set(client);
+    }
+
+    /// Start receiving requests.
+    pub fn listen(&self) {
+        self.listening.set(true);
+        self.receive();
+    }
+
+    /// Answer the request being processed.
+    pub fn send_response(&self, response: &ReadableProcessSlice) -> Result<(), ErrorCode> {
+        let cmd = match self.state.get() {
+            State::Processing { cmd } => cmd,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        self.message
+            .map_or(Err(ErrorCode::FAIL), |message| {
+                let len = response.len();
+                response.copy_to_slice_or_err(message.get_mut(..len).ok_or(ErrorCode::SIZE)?)?;
+                Ok(len)
+            })
+            .map(|len| {
+                self.start_sending(cmd, len, true);
+                self.send_next();
+                self.update_timer();
+            })
+    }
+
+    /// Set the status sent in the keep-alives while the request is processed.
+    pub fn set_keepalive_status(&self, status: u8) -> Result<(), ErrorCode> {
+        match status {
+            STATUS_PROCESSING | STATUS_UPNEEDED => {
+                self.keepalive_status.set(status);
+                Ok(())
+            }
+            _ => Err(ErrorCode::INVAL),
         }
     }
 
-    fn send_cancel(&'a self) -> Result<&'static mut [u8; 64], ErrorCode> {
-        if let Some(usb) = self.usb {
-            usb.send_cancel()
-        } else {
-            Err(ErrorCode::NOSUPPORT)
+    fn receive(&self) {
+        self.recv_buffer.take().map(|buf| {
+            if let Err((_, buf)) = self.usb.receive_buffer(buf) {
+                self.recv_buffer.replace(buf);
+            }
+        });
+    }
+
+    fn handle_packet(&self, packet: &[u8; PACKET_LEN]) {
+        let cid = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+        if cid == 0 {
+            self.queue_reply(Reply::error(cid, ERR_INVALID_CHANNEL));
+            return;
+        }
+        if packet[4] & INIT_FLAG == 0 {
+            self.handle_continuation(cid, packet[4], &packet[CONT_HEADER_LEN..]);
+            return;
+        }
+
+        let cmd = packet[4] & !INIT_FLAG;
+        let len = u16::from_be_bytes([packet[5], packet[6]]) as usize;
+        let data = &packet[INIT_HEADER_LEN..];
+        if cmd == CTAPHID_INIT {
+            self.handle_init(cid, len, data);
+            return;
+        }
+        if cid == BROADCAST_CID {
+            self.queue_reply(Reply::error(cid, ERR_INVALID_CHANNEL));
+            return;
+        }
+        if self.lock.map_or(false, |(owner, _)| *owner != cid) {
+            self.queue_reply(Reply::error(cid, ERR_CHANNEL_BUSY));
+            return;
+        }
+        if cmd == CTAPHID_CANCEL {
+            self.handle_cancel(cid);
+            return;
+        }
+
+        match self.state.get() {
+            State::Idle => (),
+            State::Receiving { .. } if self.cid.get() == cid => {
+                self.state.set(State::Idle);
+                self.queue_reply(Reply::error(cid, ERR_INVALID_SEQ));
+                return;
+            }
+            _ => {
+                self.queue_reply(Reply::error(cid, ERR_CHANNEL_BUSY));
+                return;
+            }
+        }
+        match cmd {
+            CTAPHID_PING | CTAPHID_MSG | CTAPHID_CBOR | CTAPHID_LOCK => (),
+            _ => {
+                self.queue_reply(Reply::error(cid, ERR_INVALID_CMD));
+                return;
+            }
+        }
+
+        let received = self.message.map_or(None, |message| {
+            let received = cmp::min(len, data.len());
+            message.get_mut(..len).map(|message| {
+                message[..received].copy_from_slice(&data[..received]);
+                received
+            })
+        });
+        match received {
+            None => self.queue_reply(Reply::error(cid, ERR_INVALID_LEN)),
+            Some(received) => {
+                self.cid.set(cid);
+                if received == len {
+                    self.dispatch(cid, cmd, len);
+                } else {
+                    self.idle_ticks.set(0);
+                    self.state.set(State::Receiving {
+                        cmd,
+                        len,
+                        received,
+                        seq: 0,
+                    });
+                }
+            }
         }
     }
 
-    fn receive_buffer(
-        &'a self,
-        recv: &'static mut [u8; 64],
-    ) -> Result<(), (ErrorCode, &'static mut [u8; 64])> {
-        if let Some(usb) = self.usb {
-            usb.receive_buffer(recv)
+    // Continuation packets that are not part of the request being received
+    // are ignored.
+    fn handle_continuation(&self, cid: u32, seq: u8, data: &[u8]) {
+        if let State::Receiving {
+            cmd,
+            len,
+            received,
+            seq: expected,
+        } = self.state.get()
+        {
+            if cid != self.cid.get() {
+                return;
+            }
+            if seq != expected {
+                self.state.set(State::Idle);
+                self.queue_reply(Reply::error(cid, ERR_INVALID_SEQ));
+                return;
+            }
+            let n = cmp::min(len - received, data.len());
+            self.message
+                .map(|message| message[received..received + n].copy_from_slice(&data[..n]));
+            if received + n == len {
+                self.dispatch(cid, cmd, len);
+            } else {
+                self.idle_ticks.set(0);
+                self.state.set(State::Receiving {
+                    cmd,
+                    len,
+                    received: received + n,
+                    seq: seq + 1,
+                });
+            }
+        }
+    }
+
+    // On the broadcast channel, allocate a channel. On another channel,
+    // abort its transaction.
+    fn handle_init(&self, cid: u32, len: usize, data: &[u8]) {
+        if len != NONCE_LEN {
+            self.queue_reply(Reply::error(cid, ERR_INVALID_LEN));
+            return;
+        }
+        let channel = if cid == BROADCAST_CID {
+            let channel = self.next_cid.get();
+            let next = channel.wrapping_add(1);
+            self.next_cid.set(if next == 0 || next == BROADCAST_CID {
+                1
+            } else {
+                next
+            });
+            channel
         } else {
-            Err((ErrorCode::NODEVICE, recv))
+            if self.state.get() != State::Idle && self.cid.get() == cid {
+                self.abort();
+            }
+            cid
+        };
+
+        let mut response = [0; INIT_RESPONSE_LEN];
+        response[..NONCE_LEN].copy_from_slice(&data[..NONCE_LEN]);
+        response[NONCE_LEN..NONCE_LEN + 4].copy_from_slice(&channel.to_be_bytes());
+        response[NONCE_LEN + 4] = PROTOCOL_VERSION;
+        response[NONCE_LEN + 5..NONCE_LEN + 8].copy_from_slice(&DEVICE_VERSION);
+        response[NONCE_LEN + 8] = CAPABILITY_CBOR;
+        self.queue_reply(Reply::new(cid, CTAPHID_INIT, &response));
+    }
+
+    // Only `CTAPHID_CBOR` requests can be cancelled. Cancelling is not
+    // answered, but the request is, with a CTAP2 error.
+    fn handle_cancel(&self, cid: u32) {
+        if self.cid.get() == cid && self.state.get() == (State::Processing { cmd: CTAPHID_CBOR }) {
+            self.message
+                .map(|message| message[0] = CTAP2_ERR_KEEPALIVE_CANCEL);
+            self.start_sending(CTAPHID_CBOR, 1, false);
+            self.client.map(|client| client.message_cancelled());
+        }
+    }
+
+    fn abort(&self) {
+        let processing = matches!(self.state.get(), State::Processing { .. });
+        self.state.set(State::Idle);
+        if processing {
+            self.client.map(|client| client.message_cancelled());
+        }
+    }
+
+    fn dispatch(&self, cid: u32, cmd: u8, len: usize) {
+        match cmd {
+            CTAPHID_PING => self.start_sending(cmd, len, false),
+            CTAPHID_LOCK => {
+                self.state.set(State::Idle);
+                let seconds = self.message.map_or(0, |message| message[0]);
+                if len != 1 || seconds > MAX_LOCK_SECONDS {
+                    self.queue_reply(Reply::error(cid, ERR_INVALID_PAR));
+                } else {
+                    if seconds == 0 {
+                        self.lock.clear();
+                    } else {
+                        let ticks = seconds as u16 * (1000 / TICK_MS) as u16;
+                        self.lock.set((cid, ticks));
+                    }
+                    self.queue_reply(Reply::new(cid, CTAPHID_LOCK, &[]));
+                }
+            }
+            _ => {
+                self.state.set(State::Processing { cmd });
+                self.keepalive_status.set(STATUS_PROCESSING);
+                let result = self.message.map_or(Err(ErrorCode::FAIL), |message| {
+                    self.client.map_or(Err(ErrorCode::OFF), |client| {
+                        client.message_received(cmd, &message[..len])
+                    })
+                });
+                if result.is_err() {
+                    self.state.set(State::Idle);
+                    self.queue_reply(Reply::error(cid, ERR_OTHER));
+                }
+            }
         }
     }
 
-    fn receive_cancel(&'a self) -> Result<&'static mut [u8; 64], ErrorCode> {
-        if let Some(usb) = self.usb {
-            usb.receive_cancel()
+    fn start_sending(&self, cmd: u8, len: usize, from_client: bool) {
+        self.state.set(State::Sending {
+            cmd,
+            len,
+            sent: 0,
+            packets: 0,
+            from_client,
+        });
+    }
+
+    // Replies that do not fit in the queue are dropped: the host retries.
+    fn queue_reply(&self, reply: Reply) {
+        if self.reply.is_none() {
+            self.reply.set(reply);
+        }
+    }
+
+    // Send the pending reply, or else the next packet of the response, if
+    // no packet is being sent.
+    fn send_next(&self) {
+        let packet = match self.send_buffer.take() {
+            Some(packet) => packet,
+            None => return,
+        };
+        let queued = if let Some(reply) = self.reply.take() {
+            reply.write(packet);
+            true
+        } else if let State::Sending {
+            cmd,
+            len,
+            sent,
+            packets,
+            from_client,
+        } = self.state.get()
+        {
+            if packets > 0 && sent == len {
+                false
+            } else {
+                let header_len = if packets == 0 {
+                    write_init_header(packet, self.cid.get(), cmd, len);
+                    INIT_HEADER_LEN
+                } else {
+                    packet.iter_mut().for_each(|byte| *byte = 0);
+                    packet[0..4].copy_from_slice(&self.cid.get().to_be_bytes());
+                    packet[4] = packets - 1;
+                    CONT_HEADER_LEN
+                };
+                let n = cmp::min(len - sent, PACKET_LEN - header_len);
+                self.message.map(|message| {
+                    packet[header_len..header_len + n].copy_from_slice(&message[sent..sent + n])
+                });
+                self.state.set(State::Sending {
+                    cmd,
+                    len,
+                    sent: sent + n,
+                    packets: packets + 1,
+                    from_client,
+                });
+                true
+            }
+        } else {
+            false
+        };
+
+        if queued {
+            if let Err((_, packet)) = self.usb.send_buffer(packet) {
+                self.send_buffer.replace(packet);
+            }
         } else {
-            Err(ErrorCode::NOSUPPORT)
+            self.send_buffer.replace(packet);
+        }
+    }
+
+    fn update_timer(&self) {
+        let needed = self.lock.is_some()
+            || matches!(
+                self.state.get(),
+                State::Receiving { .. } | State::Processing { .. }
+            );
+        if needed && !self.alarm.is_armed() {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
         }
     }
 }
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> usb_hid::Client<'a, [u8; 64]> for CtapDriver<'a, U> {
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> usb_hid::Client<'a, [u8; 64]>
+    for CtapHidTransport<'a, U, A>
+{
     fn packet_received(
         &'a self,
         _result: Result<(), ErrorCode>,
         buffer: &'static mut [u8; 64],
         _endpoint: usize,
     ) {
-        self.appid.map(|id| {
-            self.app
-                .enter(*id, |app, kernel_data| {
-                    let _ = kernel_data
-                        .get_readwrite_processbuffer(rw_allow::RECV)
-                        .and_then(|recv| {
-                            recv.mut_enter(|dest| {
-                                dest.copy_from_slice(buffer);
-                            })
-                        });
-
-                    kernel_data.schedule_upcall(0, (0, 0, 0)).ok();
-                    app.can_receive.set(false);
-                })
-                .map_err(|err| {
-                    if err == kernel::process::Error::NoSuchApp
-                        || err == kernel::process::Error::InactiveApp
-                    {}
-                })
-        });
-
+        self.handle_packet(buffer);
         self.recv_buffer.replace(buffer);
+        self.receive();
+        self.send_next();
+        self.update_timer();
     }
 
     fn packet_transmitted(
@@ -167,214 +635,182 @@ impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> usb_hid::Client<'a, [u8; 64]> for Cta
         buffer: &'static mut [u8; 64],
         _endpoint: usize,
     ) {
+        self.send_buffer.replace(buffer);
+        // Only one packet is sent at a time, so this was the last one of
+        // the response if it is fully sent.
+        if let State::Sending {
+            len,
+            sent,
+            packets,
+            from_client,
+            ..
+        } = self.state.get()
+        {
+            if packets > 0 && sent == len {
+                self.state.set(State::Idle);
+                if from_client {
+                    self.client.map(|client| client.response_sent());
+                }
+            }
+        }
+        self.send_next();
+    }
+
+    fn can_receive(&'a self) -> bool {
+        self.listening.get()
+    }
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> time::AlarmClient
+    for CtapHidTransport<'a, U, A>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Receiving { .. } => {
+                let ticks = self.idle_ticks.get() + 1;
+                self.idle_ticks.set(ticks);
+                if ticks >= MSG_TIMEOUT_TICKS {
+                    self.state.set(State::Idle);
+                    self.queue_reply(Reply::error(self.cid.get(), ERR_MSG_TIMEOUT));
+                }
+            }
+            State::Processing { cmd: CTAPHID_CBOR } => self.queue_reply(Reply::new(
+                self.cid.get(),
+                CTAPHID_KEEPALIVE,
+                &[self.keepalive_status.get()],
+            )),
+            _ => (),
+        }
+        self.lock.take().map(|(cid, ticks)| {
+            if ticks > 1 {
+                self.lock.set((cid, ticks - 1));
+            }
+        });
+        self.send_next();
+        self.update_timer();
+    }
+}
+
+/// Values of the first upcall argument.
+const EVENT_RECEIVED: usize = 0;
+const EVENT_SENT: usize = 1;
+const EVENT_CANCELLED: usize = 2;
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct CtapDriver<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> {
+    transport: &'a CtapHidTransport<'a, U, A>,
+    app: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    appid: OptionalCell<ProcessId>,
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> CtapDriver<'a, U, A> {
+    pub fn new(
+        transport: &'a CtapHidTransport<'a, U, A>,
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> CtapDriver<'a, U, A> {
+        CtapDriver {
+            transport,
+            app: grant,
+            appid: OptionalCell::empty(),
+        }
+    }
+
+    fn schedule_upcall(&self, event: usize, arg1: usize, arg2: usize) {
         self.appid.map(|id| {
+            let _ = self.app.enter(*id, |_, kernel_data| {
+                kernel_data.schedule_upcall(0, (event, arg1, arg2)).ok();
+            });
+        });
+    }
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> CtapClient for CtapDriver<'a, U, A> {
+    fn message_received(&self, cmd: u8, message: &[u8]) -> Result<(), ErrorCode> {
+        self.appid.map_or(Err(ErrorCode::OFF), |id| {
             self.app
-                .enter(*id, |_app, kernel_data| {
-                    kernel_data.schedule_upcall(0, (1, 0, 0)).ok();
-                })
-                .map_err(|err| {
-                    if err == kernel::process::Error::NoSuchApp
-                        || err == kernel::process::Error::InactiveApp
-                    {}
+                .enter(*id, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::RECV)
+                        .and_then(|recv| {
+                            recv.mut_enter(|dest| {
+                                dest.get_to(..message.len())
+                                    .ok_or(ErrorCode::SIZE)
+                                    .map(|dest| dest.copy_from_slice(message))
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE))?;
+                    kernel_data
+                        .schedule_upcall(0, (EVENT_RECEIVED, cmd as usize, message.len()))
+                        .map_err(|_| ErrorCode::FAIL)
                 })
-        });
+                .unwrap_or_else(|err| Err(err.into()))
+        })
+    }
 
-        // Save our send buffer so we can use it later
-        self.send_buffer.replace(buffer);
+    fn message_cancelled(&self) {
+        self.schedule_upcall(EVENT_CANCELLED, 0, 0);
     }
 
-    fn can_receive(&'a self) -> bool {
-        self.appid
-            .map(|id| {
-                self.app
-                    .enter(*id, |app, _| app.can_receive.get())
-                    .unwrap_or(false)
-            })
-            .unwrap_or(false)
+    fn response_sent(&self) {
+        self.schedule_upcall(EVENT_SENT, 0, 0);
     }
 }
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> SyscallDriver for CtapDriver<'a, U> {
-    // Subscribe to CtapDriver events.
-    //
-    // ### `subscribe_num`
-    //
-    // - `0`: Subscribe to interrupts from Ctap events.
-    //        The callback signature is `fn(direction: u32)`
-    //        `fn(0)` indicates a packet was recieved
-    //        `fn(1)` indicates a packet was transmitted
-
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: time::Alarm<'a>> SyscallDriver
+    for CtapDriver<'a, U, A>
+{
     fn command(
         &self,
         command_num: usize,
-        _data1: usize,
+        data1: usize,
         _data2: usize,
         appid: ProcessId,
     ) -> CommandReturn {
-        let can_access = self.appid.map_or(true, |owning_app| {
-            if owning_app == &appid {
-                // We own the Ctap device
-                true
-            } else {
-                false
-            }
-        });
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
 
-        if !can_access {
-            return CommandReturn::failure(ErrorCode::BUSY);
+        // Only one app can hold the credentials that answer the host
+        if let Err(e) = process_owner::claim(&self.appid, &self.app, appid) {
+            return CommandReturn::failure(e);
         }
 
         match command_num {
-            // Send data
-            0 => self
+            // Start receiving requests
+            1 => {
+                self.transport.listen();
+                CommandReturn::success()
+            }
+            // Send the response
+            2 => self
                 .app
                 .enter(appid, |_, kernel_data| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        kernel_data
-                            .get_readwrite_processbuffer(rw_allow::SEND)
-                            .and_then(|send| {
-                                send.enter(|data| {
-                                    self.send_buffer.take().map_or(
-                                        CommandReturn::failure(ErrorCode::RESERVE),
-                                        |buf| {
-                                            // Copy the data into the static buffer
-                                            data.copy_to_slice(buf);
-
-                                            let _ = usb.send_buffer(buf);
-                                            CommandReturn::success()
-                                        },
-                                    )
-                                })
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::SEND)
+                        .and_then(|send| {
+                            send.enter(|data| match data.get_to(..data1) {
+                                Some(response) => self.transport.send_response(response),
+                                None => Err(ErrorCode::SIZE),
                             })
-                            .unwrap_or(CommandReturn::failure(ErrorCode::RESERVE))
-                    } else {
-                        CommandReturn::failure(ErrorCode::NOSUPPORT)
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Allow receive
-            1 => self
-                .app
-                .enter(appid, |app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        app.can_receive.set(true);
-                        if let Some(buf) = self.recv_buffer.take() {
-                            match usb.receive_buffer(buf) {
-                                Ok(_) => CommandReturn::success(),
-                                Err((err, buffer)) => {
-                                    self.recv_buffer.replace(buffer);
-                                    CommandReturn::failure(err)
-                                }
-                            }
-                        } else {
-                            CommandReturn::failure(ErrorCode::BUSY)
-                        }
-                    } else {
-                        CommandReturn::failure(ErrorCode::NOSUPPORT)
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Cancel send
-            2 => self
-                .app
-                .enter(appid, |_app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        match usb.receive_cancel() {
-                            Ok(buf) => {
-                                self.recv_buffer.replace(buf);
-                                CommandReturn::success()
-                            }
-                            Err(err) => CommandReturn::failure(err),
-                        }
-                    } else {
-                        CommandReturn::failure(ErrorCode::NOSUPPORT)
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Cancel receive
-            3 => self
-                .app
-                .enter(appid, |_app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        match usb.receive_cancel() {
-                            Ok(buf) => {
-                                self.recv_buffer.replace(buf);
-                                CommandReturn::success()
-                            }
-                            Err(err) => CommandReturn::failure(err),
-                        }
-                    } else {
-                        CommandReturn::failure(ErrorCode::NOSUPPORT)
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Send or receive
-            // This command has two parts.
-            //    Part 1: Receive
-            //            This will allow receives, the same as the Allow
-            //            receive command above. If data is ready to receive
-            //            the `packet_received()` callback will be called.
-            //            When this happens the client callback will be
-            //            scheduled and no send event will occur.
-            //    Part 2: Send
-            //            If no receive occurs we will be left in a start where
-            //            future recieves will be allowed. This is the same
-            //            outcome as calling the Allow receive command.
-            //            As well as that we will then send the data in the
-            //            send buffer.
-            4 => self
-                .app
-                .enter(appid, |app, kernel_data| {
-                    if let Some(usb) = self.usb {
-                        if app.can_receive.get() {
-                            // We are already receiving
-                            CommandReturn::failure(ErrorCode::BUSY)
-                        } else {
-                            app.can_receive.set(true);
-                            if let Some(buf) = self.recv_buffer.take() {
-                                match usb.receive_buffer(buf) {
-                                    Ok(_) => CommandReturn::success(),
-                                    Err((err, buffer)) => {
-                                        self.recv_buffer.replace(buffer);
-                                        return CommandReturn::failure(err);
-                                    }
-                                }
-                            } else {
-                                return CommandReturn::failure(ErrorCode::BUSY);
-                            };
-
-                            if !app.can_receive.get() {
-                                // The call to receive_buffer() collected a pending packet.
-                                CommandReturn::failure(ErrorCode::BUSY)
-                            } else {
-                                kernel_data
-                                    .get_readwrite_processbuffer(rw_allow::SEND)
-                                    .and_then(|send| {
-                                        send.enter(|data| {
-                                            self.send_buffer.take().map_or(
-                                                CommandReturn::failure(ErrorCode::RESERVE),
-                                                |buf| {
-                                                    // Copy the data into the static buffer
-                                                    data.copy_to_slice(buf);
-
-                                                    let _ = usb.send_buffer(buf);
-                                                    CommandReturn::success()
-                                                },
-                                            )
-                                        })
-                                    })
-                                    .unwrap_or(CommandReturn::failure(ErrorCode::RESERVE))
-                            }
-                        }
-                    } else {
-                        CommandReturn::failure(ErrorCode::NOSUPPORT)
-                    }
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE))
+                        .into()
                 })
                 .unwrap_or_else(|err| err.into()),
+            // Set the keep-alive status
+            3 => self.transport.set_keepalive_status(data1 as u8).into(),
 
             // default
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
@@ -385,3 +821,279 @@ impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> SyscallDriver for CtapDriver<'a, U> {
         self.app.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{leak, FakeAlarm};
+    use kernel::hil::time::Alarm;
+    use kernel::hil::usb_hid::{Client, UsbHid};
+
+    /// A HID interface which holds on to the buffers it is given. Tests play
+    /// the host by filling the receive buffer, and by completing the sends.
+    struct FakeHid {
+        send: TakeCell<'static, [u8; 64]>,
+        recv: TakeCell<'static, [u8; 64]>,
+    }
+
+    impl<'a> UsbHid<'a, [u8; 64]> for FakeHid {
+        fn send_buffer(
+            &'a self,
+            send: &'static mut [u8; 64],
+        ) -> Result<usize, (ErrorCode, &'static mut [u8; 64])> {
+            self.send.replace(send);
+            Ok(PACKET_LEN)
+        }
+
+        fn send_cancel(&'a self) -> Result<&'static mut [u8; 64], ErrorCode> {
+            self.send.take().ok_or(ErrorCode::BUSY)
+        }
+
+        fn receive_buffer(
+            &'a self,
+            recv: &'static mut [u8; 64],
+        ) -> Result<(), (ErrorCode, &'static mut [u8; 64])> {
+            self.recv.replace(recv);
+            Ok(())
+        }
+
+        fn receive_cancel(&'a self) -> Result<&'static mut [u8; 64], ErrorCode> {
+            self.recv.take().ok_or(ErrorCode::BUSY)
+        }
+    }
+
+    #[derive(Default)]
+    struct Authenticator {
+        received: Cell<Option<(u8, usize)>>,
+        cancelled: Cell<bool>,
+        sent: Cell<bool>,
+    }
+
+    impl CtapClient for Authenticator {
+        fn message_received(&self, cmd: u8, message: &[u8]) -> Result<(), ErrorCode> {
+            self.received.set(Some((cmd, message.len())));
+            Ok(())
+        }
+
+        fn message_cancelled(&self) {
+            self.cancelled.set(true);
+        }
+
+        fn response_sent(&self) {
+            self.sent.set(true);
+        }
+    }
+
+    type Transport = CtapHidTransport<'static, FakeHid, FakeAlarm<'static>>;
+
+    fn setup() -> (
+        &'static Transport,
+        &'static FakeHid,
+        &'static FakeAlarm<'static>,
+    ) {
+        let usb = leak(FakeHid {
+            send: TakeCell::empty(),
+            recv: TakeCell::empty(),
+        });
+        let alarm = leak(FakeAlarm::new());
+        let transport = leak(CtapHidTransport::new(
+            usb,
+            alarm,
+            leak([0; 64]),
+            leak([0; 64]),
+            leak([0; 1024]),
+        ));
+        alarm.set_alarm_client(transport);
+        transport.listen();
+        (transport, usb, alarm)
+    }
+
+    fn host_send(transport: &'static Transport, usb: &FakeHid, packet: &[u8]) {
+        let buf = usb.recv.take().unwrap();
+        buf.iter_mut().for_each(|byte| *byte = 0);
+        buf[..packet.len()].copy_from_slice(packet);
+        transport.packet_received(Ok(()), buf, 1);
+    }
+
+    fn host_receive(transport: &'static Transport, usb: &FakeHid) -> [u8; 64] {
+        let buf = usb.send.take().expect("no packet sent");
+        let packet = *buf;
+        transport.packet_transmitted(Ok(()), buf, 1);
+        packet
+    }
+
+    fn init_packet(cid: u32, cmd: u8, len: u16, data: &[u8]) -> [u8; 64] {
+        let mut packet = [0; 64];
+        packet[0..4].copy_from_slice(&cid.to_be_bytes());
+        packet[4] = cmd | INIT_FLAG;
+        packet[5..7].copy_from_slice(&len.to_be_bytes());
+        packet[7..7 + data.len()].copy_from_slice(data);
+        packet
+    }
+
+    fn cont_packet(cid: u32, seq: u8, data: &[u8]) -> [u8; 64] {
+        let mut packet = [0; 64];
+        packet[0..4].copy_from_slice(&cid.to_be_bytes());
+        packet[4] = seq;
+        packet[5..5 + data.len()].copy_from_slice(data);
+        packet
+    }
+
+    fn allocate_channel(transport: &'static Transport, usb: &FakeHid) -> u32 {
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        host_send(
+            transport,
+            usb,
+            &init_packet(BROADCAST_CID, CTAPHID_INIT, 8, &nonce),
+        );
+        let response = host_receive(transport, usb);
+        assert_eq!(&response[0..4], &BROADCAST_CID.to_be_bytes());
+        assert_eq!(response[4], CTAPHID_INIT | INIT_FLAG);
+        assert_eq!(&response[5..7], &(INIT_RESPONSE_LEN as u16).to_be_bytes());
+        assert_eq!(&response[7..15], &nonce);
+        u32::from_be_bytes([response[15], response[16], response[17], response[18]])
+    }
+
+    #[test]
+    fn init_allocates_channels() {
+        let (transport, usb, _) = setup();
+        let cid = allocate_channel(transport, usb);
+        assert_ne!(cid, 0);
+        assert_ne!(cid, BROADCAST_CID);
+        assert_ne!(allocate_channel(transport, usb), cid);
+
+        // Only INIT is allowed on the broadcast channel
+        host_send(
+            transport,
+            usb,
+            &init_packet(BROADCAST_CID, CTAPHID_PING, 1, &[0]),
+        );
+        let response = host_receive(transport, usb);
+        assert_eq!(response[4], CTAPHID_ERROR | INIT_FLAG);
+        assert_eq!(response[7], ERR_INVALID_CHANNEL);
+    }
+
+    #[test]
+    fn ping_is_reassembled_and_echoed() {
+        let (transport, usb, _) = setup();
+        let cid = allocate_channel(transport, usb);
+        let mut message = [0; 100];
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+
+        host_send(
+            transport,
+            usb,
+            &init_packet(cid, CTAPHID_PING, 100, &message[..57]),
+        );
+        assert!(usb.send.is_none());
+        host_send(transport, usb, &cont_packet(cid, 0, &message[57..]));
+
+        let first = host_receive(transport, usb);
+        assert_eq!(&first[0..4], &cid.to_be_bytes());
+        assert_eq!(first[4], CTAPHID_PING | INIT_FLAG);
+        assert_eq!(&first[5..7], &[0, 100]);
+        assert_eq!(&first[7..], &message[..57]);
+        let second = host_receive(transport, usb);
+        assert_eq!(&second[0..4], &cid.to_be_bytes());
+        assert_eq!(second[4], 0);
+        assert_eq!(&second[5..48], &message[57..]);
+        assert!(usb.send.is_none());
+    }
+
+    #[test]
+    fn continuation_out_of_sequence_is_rejected() {
+        let (transport, usb, _) = setup();
+        let cid = allocate_channel(transport, usb);
+        host_send(
+            transport,
+            usb,
+            &init_packet(cid, CTAPHID_PING, 100, &[0; 57]),
+        );
+        host_send(transport, usb, &cont_packet(cid, 1, &[0; 43]));
+
+        let response = host_receive(transport, usb);
+        assert_eq!(response[4], CTAPHID_ERROR | INIT_FLAG);
+        assert_eq!(response[7], ERR_INVALID_SEQ);
+    }
+
+    #[test]
+    fn cbor_request_is_kept_alive_and_answered() {
+        let (transport, usb, alarm) = setup();
+        let authenticator = leak(Authenticator::default());
+        transport.set_client(authenticator);
+        let cid = allocate_channel(transport, usb);
+        let other = allocate_channel(transport, usb);
+
+        host_send(transport, usb, &init_packet(cid, CTAPHID_CBOR, 1, &[0x04]));
+        assert_eq!(authenticator.received.get(), Some((CTAPHID_CBOR, 1)));
+        assert!(alarm.is_armed());
+
+        transport.set_keepalive_status(STATUS_UPNEEDED).unwrap();
+        alarm.trigger();
+        let keepalive = host_receive(transport, usb);
+        assert_eq!(keepalive[4], CTAPHID_KEEPALIVE | INIT_FLAG);
+        assert_eq!(keepalive[7], STATUS_UPNEEDED);
+
+        // Other channels wait for the transaction to complete
+        host_send(transport, usb, &init_packet(other, CTAPHID_PING, 0, &[]));
+        let busy = host_receive(transport, usb);
+        assert_eq!(&busy[0..4], &other.to_be_bytes());
+        assert_eq!(busy[7], ERR_CHANNEL_BUSY);
+
+        let response: &[u8] = &[0x00, 0xa1, 0x01, 0x02];
+        transport.send_response(response.into()).unwrap();
+        let packet = host_receive(transport, usb);
+        assert_eq!(packet[4], CTAPHID_CBOR | INIT_FLAG);
+        assert_eq!(&packet[5..11], &[0, 4, 0x00, 0xa1, 0x01, 0x02]);
+        assert!(authenticator.sent.get());
+        assert!(!authenticator.cancelled.get());
+    }
+
+    #[test]
+    fn cbor_request_can_be_cancelled() {
+        let (transport, usb, _) = setup();
+        let authenticator = leak(Authenticator::default());
+        transport.set_client(authenticator);
+        let cid = allocate_channel(transport, usb);
+
+        host_send(transport, usb, &init_packet(cid, CTAPHID_CBOR, 1, &[0x01]));
+        host_send(transport, usb, &init_packet(cid, CTAPHID_CANCEL, 0, &[]));
+        assert!(authenticator.cancelled.get());
+
+        let response = host_receive(transport, usb);
+        assert_eq!(response[4], CTAPHID_CBOR | INIT_FLAG);
+        assert_eq!(&response[5..8], &[0, 1, CTAP2_ERR_KEEPALIVE_CANCEL]);
+        assert!(!authenticator.sent.get());
+
+        let response: &[u8] = &[0x00];
+        assert_eq!(
+            transport.send_response(response.into()),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn lock_excludes_other_channels() {
+        let (transport, usb, alarm) = setup();
+        let cid = allocate_channel(transport, usb);
+        let other = allocate_channel(transport, usb);
+
+        host_send(transport, usb, &init_packet(cid, CTAPHID_LOCK, 1, &[1]));
+        let locked = host_receive(transport, usb);
+        assert_eq!(locked[4], CTAPHID_LOCK | INIT_FLAG);
+
+        host_send(transport, usb, &init_packet(other, CTAPHID_PING, 0, &[]));
+        assert_eq!(host_receive(transport, usb)[7], ERR_CHANNEL_BUSY);
+
+        // The lock expires after a second
+        for _ in 0..10 {
+            alarm.trigger();
+        }
+        assert!(!alarm.is_armed());
+        host_send(transport, usb, &init_packet(other, CTAPHID_PING, 0, &[]));
+        assert_eq!(host_receive(transport, usb)[4], CTAPHID_PING | INIT_FLAG);
+    }
+}
@@ -28,11 +28,15 @@ pub enum NUM {
     I2cMaster             = 0x20003,
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
+    UsbHid                = 0x20007,
 
     // Radio
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Ping                  = 0x30003,
+    Coap                  = 0x30004,
+    BleGatt               = 0x30005,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -41,11 +45,14 @@ pub enum NUM {
     CtapHid               = 0x40004,
     Sha                   = 0x40005,
     Aes                   = 0x40006,
+    SignatureVerify       = 0x40007,
+    Aead                  = 0x40008,
 
     // Storage
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    Log                   = 0x50003,
 
     // Sensors
     Temperature           = 0x60000,
@@ -0,0 +1,618 @@
+//! Unslotted CSMA-CA MAC layer with acknowledgements and retransmissions.
+//!
+//! `CsmaMac` implements the channel access and retransmission procedures of
+//! IEEE 802.15.4-2015 (sections 6.2.5.1 and 6.7.4.3) in software, so that
+//! they behave the same regardless of the radio underneath. Before each
+//! transmission attempt, it waits a random number of backoff periods between
+//! 0 and 2^BE - 1, and hands the frame to the radio, which performs a single
+//! clear channel assessment (see `kernel::hil::radio::RadioChannelAccess`).
+//! If the channel is busy, BE is incremented up to `max_be` and the frame is
+//! tried again, up to `max_csma_backoffs` times before failing with `BUSY`.
+//!
+//! Frames that request an acknowledgement and are not broadcast are
+//! retransmitted up to `max_frame_retries` times until an acknowledgement
+//! with their sequence number is received within the ACK wait duration.
+//! Acknowledgements reported by the radio itself (e.g. the RF233 in extended
+//! operating mode) are used as well. If no acknowledgement is received, the
+//! transmission fails with `NOACK`. In both cases, `send_done` reports the
+//! number of retransmissions of the frame.
+//!
+//! `CsmaMac` does not send acknowledgements for received frames, which
+//! remains up to the radio.
+//!
+//! Usage
+//! -----
+//! `CsmaMac` can be used as the backend for a `capsules::ieee802154::framer::Framer`
+//! in place of `AwakeMac`:
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! type CsmaDevice = capsules::ieee802154::csma::CsmaMac<'static, Radio, VirtualMuxAlarm<'static, Rtc>>;
+//!
+//! let csma_mac = static_init!(CsmaDevice, CsmaMac::new(radio, csma_alarm));
+//! csma_alarm.set_alarm_client(csma_mac);
+//! radio.set_transmit_client(csma_mac);
+//! radio.set_receive_client(csma_mac, &mut RADIO_RX_BUF);
+//! csma_mac.set_backoff_exponents(3, 5);
+//! csma_mac.set_max_frame_retries(3);
+//! csma_mac.initialize(&mut MAC_BUF);
+//!
+//! let mac_device = static_init!(
+//!     capsules::ieee802154::framer::Framer<'static, CsmaDevice, AesCcm>,
+//!     capsules::ieee802154::framer::Framer::new(csma_mac, aes_ccm));
+//! csma_mac.set_transmit_client(mac_device);
+//! csma_mac.set_receive_client(mac_device);
+//! csma_mac.set_config_client(mac_device);
+//! ```
+
+use crate::ieee802154::mac::{Mac, TxClient};
+use crate::net::ieee802154::{FrameType, Header, MacAddress};
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::hil::radio::{self, RadioChannelAccess};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Duration of a backoff period, 20 symbols of the 2.4 GHz O-QPSK PHY.
+pub const UNIT_BACKOFF_PERIOD_US: u32 = 320;
+
+// Defaults of the MAC PIB attributes
+const DEFAULT_MIN_BE: u8 = 3;
+const DEFAULT_MAX_BE: u8 = 5;
+const DEFAULT_MAX_CSMA_BACKOFFS: u8 = 4;
+const DEFAULT_MAX_FRAME_RETRIES: u8 = 3;
+// macAckWaitDuration, 54 symbols
+const DEFAULT_ACK_WAIT_US: u32 = 864;
+
+const BROADCAST_ADDRESS: u16 = 0xffff;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum CsmaState {
+    Idle,
+    Backoff,
+    Transmitting,
+    AckWait,
+}
+
+/// Returns the sequence number of `frame` (starting at the frame control
+/// field) if it is an acknowledgement.
+fn ack_seq(frame: &[u8]) -> Option<u8> {
+    if frame.len() >= 3
+        && FrameType::from_fcf(u16::from_le_bytes([frame[0], frame[1]]))
+            == Some(FrameType::Acknowledgement)
+    {
+        Some(frame[2])
+    } else {
+        None
+    }
+}
+
+pub struct CsmaMac<'a, R: radio::Radio + RadioChannelAccess, A: Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    tx_client: OptionalCell<&'static dyn TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    state: Cell<CsmaState>,
+
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// Sequence number of the frame being sent, if it must be acknowledged
+    tx_ack_seq: Cell<Option<u8>>,
+    backoffs: Cell<u8>,
+    be: Cell<u8>,
+    retries: Cell<u8>,
+
+    min_be: Cell<u8>,
+    max_be: Cell<u8>,
+    max_csma_backoffs: Cell<u8>,
+    max_frame_retries: Cell<u8>,
+    ack_wait_us: Cell<u32>,
+    random: Cell<u32>,
+}
+
+impl<'a, R: radio::Radio + RadioChannelAccess, A: Alarm<'a>> CsmaMac<'a, R, A> {
+    pub fn new(radio: &'a R, alarm: &'a A) -> CsmaMac<'a, R, A> {
+        CsmaMac {
+            radio: radio,
+            alarm: alarm,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            state: Cell::new(CsmaState::Idle),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_ack_seq: Cell::new(None),
+            backoffs: Cell::new(0),
+            be: Cell::new(0),
+            retries: Cell::new(0),
+            min_be: Cell::new(DEFAULT_MIN_BE),
+            max_be: Cell::new(DEFAULT_MAX_BE),
+            max_csma_backoffs: Cell::new(DEFAULT_MAX_CSMA_BACKOFFS),
+            max_frame_retries: Cell::new(DEFAULT_MAX_FRAME_RETRIES),
+            ack_wait_us: Cell::new(DEFAULT_ACK_WAIT_US),
+            random: Cell::new(1),
+        }
+    }
+
+    /// Sets the minimum and maximum backoff exponents (macMinBE and
+    /// macMaxBE). `min_be` must not be larger than `max_be`, which must be
+    /// at most 8.
+    pub fn set_backoff_exponents(&self, min_be: u8, max_be: u8) -> Result<(), ErrorCode> {
+        if min_be > max_be || max_be > 8 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.min_be.set(min_be);
+        self.max_be.set(max_be);
+        Ok(())
+    }
+
+    /// Sets the number of times the channel is found busy before a
+    /// transmission fails (macMaxCSMABackoffs).
+    pub fn set_max_csma_backoffs(&self, max_csma_backoffs: u8) {
+        self.max_csma_backoffs.set(max_csma_backoffs);
+    }
+
+    /// Sets the number of retransmissions of unacknowledged frames
+    /// (macMaxFrameRetries).
+    pub fn set_max_frame_retries(&self, max_frame_retries: u8) {
+        self.max_frame_retries.set(max_frame_retries);
+    }
+
+    /// Sets how long to wait for an acknowledgement after a frame is sent.
+    pub fn set_ack_wait_us(&self, ack_wait_us: u32) {
+        self.ack_wait_us.set(ack_wait_us);
+    }
+
+    // Xorshift, as only the low bits are used for backoffs
+    fn random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    fn start_backoff(&self) {
+        let periods = self.random() & ((1 << self.be.get()) - 1);
+        self.state.set(CsmaState::Backoff);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_us(periods * UNIT_BACKOFF_PERIOD_US),
+        );
+    }
+
+    fn start_attempt(&self) {
+        self.backoffs.set(0);
+        self.be.set(self.min_be.get());
+        self.start_backoff();
+    }
+
+    fn transmit_frame(&self) {
+        self.tx_buf.take().map(|buf| {
+            self.state.set(CsmaState::Transmitting);
+            if let Err((ecode, buf)) = self.radio.transmit(buf, self.tx_len.get()) {
+                self.call_tx_client(buf, false, Err(ecode));
+            }
+        });
+    }
+
+    fn no_ack(&self) {
+        if self.retries.get() < self.max_frame_retries.get() {
+            self.retries.set(self.retries.get() + 1);
+            self.start_attempt();
+        } else {
+            self.tx_buf
+                .take()
+                .map(|buf| self.call_tx_client(buf, false, Err(ErrorCode::NOACK)));
+        }
+    }
+
+    fn call_tx_client(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+        self.state.set(CsmaState::Idle);
+        let retries = self.retries.get();
+        self.tx_client.map(move |c| {
+            c.send_done(buf, acked, retries, result);
+        });
+    }
+}
+
+impl<'a, R: radio::Radio + RadioChannelAccess, A: Alarm<'a>> Mac for CsmaMac<'a, R, A> {
+    fn initialize(&self, _mac_buf: &'static mut [u8]) -> Result<(), ErrorCode> {
+        // Seed the backoffs with the unique address of the node, so that
+        // nodes do not back off in lockstep
+        let seed = self
+            .radio
+            .get_address_long()
+            .chunks(4)
+            .fold(self.alarm.now().into_u32(), |seed, chunk| {
+                seed ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])
+            });
+        self.random.set(seed | 1);
+        self.radio.set_single_attempt(true);
+        Ok(())
+    }
+
+    fn is_on(&self) -> bool {
+        self.radio.is_on()
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.radio.set_config_client(client)
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.radio.set_address(addr)
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.radio.set_address_long(addr)
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.radio.set_pan(id)
+    }
+
+    fn get_address(&self) -> u16 {
+        self.radio.get_address()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.radio.get_address_long()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.radio.get_pan()
+    }
+
+    fn config_commit(&self) {
+        self.radio.config_commit()
+    }
+
+    fn set_transmit_client(&self, client: &'static dyn TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.radio.set_receive_buffer(buffer);
+    }
+
+    fn transmit(
+        &self,
+        full_mac_frame: &'static mut [u8],
+        frame_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != CsmaState::Idle {
+            return Err((ErrorCode::BUSY, full_mac_frame));
+        } else if radio::PSDU_OFFSET + frame_len >= full_mac_frame.len() {
+            return Err((ErrorCode::SIZE, full_mac_frame));
+        }
+
+        let ack_seq = match Header::decode(&full_mac_frame[radio::PSDU_OFFSET..], false).done() {
+            Some((_, (header, _))) => match header.dst_addr {
+                Some(MacAddress::Short(BROADCAST_ADDRESS)) | None => None,
+                Some(_) if header.ack_requested => header.seq,
+                Some(_) => None,
+            },
+            None => return Err((ErrorCode::FAIL, full_mac_frame)),
+        };
+
+        self.tx_buf.replace(full_mac_frame);
+        self.tx_len.set(frame_len);
+        self.tx_ack_seq.set(ack_seq);
+        self.retries.set(0);
+        self.start_attempt();
+        Ok(())
+    }
+}
+
+impl<'a, R: radio::Radio + RadioChannelAccess, A: Alarm<'a>> time::AlarmClient
+    for CsmaMac<'a, R, A>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            CsmaState::Backoff => self.transmit_frame(),
+            CsmaState::AckWait => self.no_ack(),
+            CsmaState::Idle | CsmaState::Transmitting => {}
+        }
+    }
+}
+
+impl<'a, R: radio::Radio + RadioChannelAccess, A: Alarm<'a>> radio::TxClient for CsmaMac<'a, R, A> {
+    fn send_done(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+        if self.state.get() != CsmaState::Transmitting {
+            return;
+        }
+        match result {
+            Err(ErrorCode::BUSY) => {
+                // The channel is busy; back off longer and try again
+                self.backoffs.set(self.backoffs.get() + 1);
+                if self.backoffs.get() > self.max_csma_backoffs.get() {
+                    self.call_tx_client(buf, false, Err(ErrorCode::BUSY));
+                } else {
+                    self.be.set(min(self.be.get() + 1, self.max_be.get()));
+                    self.tx_buf.replace(buf);
+                    self.start_backoff();
+                }
+            }
+            Ok(()) if self.tx_ack_seq.get().is_some() && !acked => {
+                self.tx_buf.replace(buf);
+                self.state.set(CsmaState::AckWait);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_us(self.ack_wait_us.get()),
+                );
+            }
+            _ => self.call_tx_client(buf, acked, result),
+        }
+    }
+}
+
+impl<'a, R: radio::Radio + RadioChannelAccess, A: Alarm<'a>> radio::RxClient for CsmaMac<'a, R, A> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        crc_valid: bool,
+        result: Result<(), ErrorCode>,
+    ) {
+        let frame = &buf[radio::PSDU_OFFSET..min(radio::PSDU_OFFSET + frame_len, buf.len())];
+        if let Some(seq) = ack_seq(frame) {
+            // Acknowledgements are consumed here and never passed up
+            self.radio.set_receive_buffer(buf);
+            if self.state.get() == CsmaState::AckWait
+                && crc_valid
+                && self.tx_ack_seq.get() == Some(seq)
+            {
+                let _ = self.alarm.disarm();
+                self.tx_buf
+                    .take()
+                    .map(|tx_buf| self.call_tx_client(tx_buf, true, Ok(())));
+            }
+            return;
+        }
+
+        // Filter frames by destination, as the radio may be in promiscuous
+        // mode
+        let addr_match = match Header::decode(frame, false).done() {
+            Some((_, (header, _))) => match header.dst_addr {
+                Some(MacAddress::Short(addr)) => {
+                    addr == self.radio.get_address() || addr == BROADCAST_ADDRESS
+                }
+                Some(MacAddress::Long(long_addr)) => long_addr == self.radio.get_address_long(),
+                None => false,
+            },
+            None => false,
+        };
+
+        if addr_match {
+            self.rx_client.map(move |c| {
+                c.receive(buf, frame_len, crc_valid, result);
+            });
+        } else {
+            self.radio.set_receive_buffer(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{leak, FakeAlarm};
+    use kernel::hil::time::AlarmClient;
+
+    #[test]
+    fn ack_frames() {
+        // Frame control of an ACK frame, then its sequence number
+        assert_eq!(ack_seq(&[0x02, 0x00, 0x2a]), Some(0x2a));
+        // Data frame
+        assert_eq!(ack_seq(&[0x41, 0xcc, 0x2a]), None);
+        assert_eq!(ack_seq(&[0x02, 0x00]), None);
+    }
+
+    /// A radio that holds on to the frame being transmitted, until the test
+    /// completes the transmission.
+    struct FakeRadio {
+        tx_buf: TakeCell<'static, [u8]>,
+        transmissions: Cell<usize>,
+    }
+
+    impl radio::RadioConfig for FakeRadio {
+        fn initialize(
+            &self,
+            _spi_buf: &'static mut [u8],
+            _reg_write: &'static mut [u8],
+            _reg_read: &'static mut [u8],
+        ) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn reset(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn start(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn stop(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn is_on(&self) -> bool {
+            true
+        }
+        fn busy(&self) -> bool {
+            self.tx_buf.is_some()
+        }
+        fn set_power_client(&self, _client: &'static dyn radio::PowerClient) {}
+        fn config_commit(&self) {}
+        fn set_config_client(&self, _client: &'static dyn radio::ConfigClient) {}
+        fn get_address(&self) -> u16 {
+            0x0001
+        }
+        fn get_address_long(&self) -> [u8; 8] {
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        }
+        fn get_pan(&self) -> u16 {
+            0xabcd
+        }
+        fn get_tx_power(&self) -> i8 {
+            0
+        }
+        fn get_channel(&self) -> u8 {
+            26
+        }
+        fn set_address(&self, _addr: u16) {}
+        fn set_address_long(&self, _addr: [u8; 8]) {}
+        fn set_pan(&self, _id: u16) {}
+        fn set_tx_power(&self, _power: i8) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn set_channel(&self, _chan: u8) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+    }
+
+    impl radio::RadioData for FakeRadio {
+        fn set_transmit_client(&self, _client: &'static dyn radio::TxClient) {}
+        fn set_receive_client(
+            &self,
+            _client: &'static dyn radio::RxClient,
+            _receive_buffer: &'static mut [u8],
+        ) {
+        }
+        fn set_receive_buffer(&self, _receive_buffer: &'static mut [u8]) {}
+        fn transmit(
+            &self,
+            spi_buf: &'static mut [u8],
+            _frame_len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.transmissions.set(self.transmissions.get() + 1);
+            self.tx_buf.replace(spi_buf);
+            Ok(())
+        }
+    }
+
+    impl RadioChannelAccess for FakeRadio {
+        fn set_single_attempt(&self, _enable: bool) {}
+    }
+
+    struct FakeClient {
+        done: Cell<Option<(bool, u8, Result<(), ErrorCode>)>>,
+    }
+
+    impl TxClient for FakeClient {
+        fn send_done(
+            &self,
+            _buf: &'static mut [u8],
+            acked: bool,
+            retries: u8,
+            result: Result<(), ErrorCode>,
+        ) {
+            self.done.set(Some((acked, retries, result)));
+        }
+    }
+
+    type TestMac = CsmaMac<'static, FakeRadio, FakeAlarm<'static>>;
+
+    fn setup() -> (&'static TestMac, &'static FakeRadio, &'static FakeClient) {
+        let radio = leak(FakeRadio {
+            tx_buf: TakeCell::empty(),
+            transmissions: Cell::new(0),
+        });
+        // The alarm never fires by itself; the tests call `alarm` on the MAC
+        let mac = leak(CsmaMac::new(&*radio, leak(FakeAlarm::new())));
+        let client = leak(FakeClient {
+            done: Cell::new(None),
+        });
+        mac.set_transmit_client(client);
+        mac.initialize(leak([0; 1])).unwrap();
+        (mac, radio, client)
+    }
+
+    /// Sends a data frame with sequence number 7 from 0x0001 to 0x0002,
+    /// which requests an acknowledgement.
+    fn send_frame(mac: &TestMac) {
+        let frame = leak([0; radio::MAX_BUF_SIZE]);
+        let header = [0x61, 0x88, 7, 0xcd, 0xab, 0x02, 0x00, 0x01, 0x00];
+        frame[radio::PSDU_OFFSET..radio::PSDU_OFFSET + header.len()].copy_from_slice(&header);
+        assert!(mac.transmit(frame, header.len() + 2).is_ok());
+    }
+
+    /// Ends the backoff, and completes the transmission the radio started.
+    fn attempt(mac: &TestMac, radio: &FakeRadio, acked: bool, result: Result<(), ErrorCode>) {
+        let transmissions = radio.transmissions.get();
+        mac.alarm();
+        assert_eq!(radio.transmissions.get(), transmissions + 1);
+        let buf = radio.tx_buf.take().unwrap();
+        radio::TxClient::send_done(mac, buf, acked, result);
+    }
+
+    #[test]
+    fn busy_channel_exhausts_backoffs() {
+        let (mac, radio, client) = setup();
+        mac.set_max_csma_backoffs(2);
+        send_frame(mac);
+
+        // The first attempt and 2 more backoffs find the channel busy
+        for _ in 0..2 {
+            attempt(mac, radio, false, Err(ErrorCode::BUSY));
+            assert!(client.done.get().is_none());
+        }
+        attempt(mac, radio, false, Err(ErrorCode::BUSY));
+        assert_eq!(client.done.get(), Some((false, 0, Err(ErrorCode::BUSY))));
+
+        // Nothing is left to transmit
+        mac.alarm();
+        assert_eq!(radio.transmissions.get(), 3);
+    }
+
+    #[test]
+    fn busy_channel_then_clear() {
+        let (mac, radio, client) = setup();
+        send_frame(mac);
+
+        attempt(mac, radio, false, Err(ErrorCode::BUSY));
+        // The radio reports the acknowledgement itself
+        attempt(mac, radio, true, Ok(()));
+        assert_eq!(client.done.get(), Some((true, 0, Ok(()))));
+    }
+
+    #[test]
+    fn unacknowledged_frames_are_retried() {
+        let (mac, radio, client) = setup();
+        mac.set_max_frame_retries(2);
+        send_frame(mac);
+
+        // The first transmission and 2 retries time out waiting for the
+        // acknowledgement
+        for _ in 0..3 {
+            attempt(mac, radio, false, Ok(()));
+            assert!(client.done.get().is_none());
+            mac.alarm();
+        }
+        assert_eq!(client.done.get(), Some((false, 2, Err(ErrorCode::NOACK))));
+        assert_eq!(radio.transmissions.get(), 3);
+    }
+
+    #[test]
+    fn retries_are_reported_with_the_acknowledgement() {
+        let (mac, radio, client) = setup();
+        send_frame(mac);
+
+        attempt(mac, radio, false, Ok(()));
+        mac.alarm();
+        // The channel is busy before the retransmission, which does not
+        // count as a retry
+        attempt(mac, radio, false, Err(ErrorCode::BUSY));
+        attempt(mac, radio, false, Ok(()));
+
+        // ACK frame with sequence number 7
+        let ack = leak([0; radio::MAX_BUF_SIZE]);
+        ack[radio::PSDU_OFFSET..radio::PSDU_OFFSET + 3].copy_from_slice(&[0x02, 0x00, 7]);
+        radio::RxClient::receive(mac, ack, 5, true, Ok(()));
+        assert_eq!(client.done.get(), Some((true, 1, Ok(()))));
+    }
+}
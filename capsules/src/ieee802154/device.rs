@@ -86,9 +86,17 @@ pub trait TxClient {
     /// - `spi_buf`: The buffer used to contain the transmitted frame is
     /// returned to the client here.
     /// - `acked`: Whether the transmission was acknowledged.
+    /// - `retries`: The number of times the frame was retransmitted because
+    /// it was not acknowledged.
     /// - `result`: This is `Ok(())` if the frame was transmitted,
     /// otherwise an error occured in the transmission pipeline.
-    fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>);
+    fn send_done(
+        &self,
+        spi_buf: &'static mut [u8],
+        acked: bool,
+        retries: u8,
+        result: Result<(), ErrorCode>,
+    );
 }
 
 /// Trait to be implemented by users of the IEEE 802.15.4 device that wish to
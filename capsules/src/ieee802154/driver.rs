@@ -547,7 +547,9 @@ impl SyscallDriver for RadioDriver<'_> {
     // ### `subscribe_num`
     //
     // - `0`: Setup callback for when frame is received.
-    // - `1`: Setup callback for when frame is transmitted.
+    // - `1`: Setup callback for when frame is transmitted. The callback
+    //   receives the result, whether the frame was acknowledged, and the
+    //   number of times it was retransmitted.
 
     /// IEEE 802.15.4 MAC device control.
     ///
@@ -896,7 +898,13 @@ impl SyscallDriver for RadioDriver<'_> {
 }
 
 impl device::TxClient for RadioDriver<'_> {
-    fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+    fn send_done(
+        &self,
+        spi_buf: &'static mut [u8],
+        acked: bool,
+        retries: u8,
+        result: Result<(), ErrorCode>,
+    ) {
         self.kernel_tx.replace(spi_buf);
         self.current_app.take().map(|appid| {
             let _ = self.apps.enter(appid, |_app, upcalls| {
@@ -906,7 +914,7 @@ impl device::TxClient for RadioDriver<'_> {
                         (
                             kernel::errorcode::into_statuscode(result),
                             acked as usize,
-                            0,
+                            retries as usize,
                         ),
                     )
                     .ok();
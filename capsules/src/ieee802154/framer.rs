@@ -72,7 +72,7 @@
 //
 
 use crate::ieee802154::device::{MacDevice, RxClient, TxClient};
-use crate::ieee802154::mac::Mac;
+use crate::ieee802154::mac::{self, Mac};
 use crate::net::ieee802154::{
     FrameType, FrameVersion, Header, KeyId, MacAddress, PanID, Security, SecurityLevel,
 };
@@ -786,11 +786,17 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
     }
 }
 
-impl<'a, M: Mac, A: AES128CCM<'a>> radio::TxClient for Framer<'a, M, A> {
-    fn send_done(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+impl<'a, M: Mac, A: AES128CCM<'a>> mac::TxClient for Framer<'a, M, A> {
+    fn send_done(
+        &self,
+        buf: &'static mut [u8],
+        acked: bool,
+        retries: u8,
+        result: Result<(), ErrorCode>,
+    ) {
         self.data_sequence.set(self.data_sequence.get() + 1);
         self.tx_client.map(move |client| {
-            client.send_done(buf, acked, result);
+            client.send_done(buf, acked, retries, result);
         });
     }
 }
@@ -839,7 +845,7 @@ impl<'a, M: Mac, A: AES128CCM<'a>> radio::ConfigClient for Framer<'a, M, A> {
         let _ = self.step_transmit_state().map_err(|(ecode, buf)| {
             // Return the buffer to the transmit client
             self.tx_client.map(move |client| {
-                client.send_done(buf, false, Err(ecode));
+                client.send_done(buf, false, 0, Err(ecode));
             });
         });
     }
@@ -868,7 +874,7 @@ impl<'a, M: Mac, A: AES128CCM<'a>> CCMClient for Framer<'a, M, A> {
                     if let Err((ecode, buf)) = res2 {
                         // Abort the transmission process. Return the buffer to the client.
                         self.tx_client.map(move |client| {
-                            client.send_done(buf, false, Err(ecode));
+                            client.send_done(buf, false, 0, Err(ecode));
                         });
                     }
                     None
@@ -916,7 +922,7 @@ impl<'a, M: Mac, A: AES128CCM<'a>> CCMClient for Framer<'a, M, A> {
             let _ = self.step_transmit_state().map_err(|(ecode, buf)| {
                 // Return the buffer to the client.
                 self.tx_client.map(move |client| {
-                    client.send_done(buf, false, Err(ecode));
+                    client.send_done(buf, false, 0, Err(ecode));
                 });
             });
         } else if rx_waiting {
@@ -7,7 +7,8 @@
 //!
 //! AwakeMac provides a default implementation of such a layer, maintaining
 //! the underlying kernel::hil::radio::Radio powered at all times and passing
-//! through each frame for transmission.
+//! through each frame for transmission. CsmaMac (csma.rs) additionally
+//! performs CSMA-CA and retransmissions in software.
 
 use crate::net::ieee802154::{Header, MacAddress};
 use kernel::debug;
@@ -15,6 +16,21 @@ use kernel::hil::radio;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
+/// Trait to be implemented by the client of a Mac layer that transmits
+/// frames, usually a MacDevice.
+pub trait TxClient {
+    /// Returns the frame buffer when a transmission completes or fails.
+    /// `retries` is the number of times the frame was retransmitted because
+    /// it was not acknowledged.
+    fn send_done(
+        &self,
+        buf: &'static mut [u8],
+        acked: bool,
+        retries: u8,
+        result: Result<(), ErrorCode>,
+    );
+}
+
 pub trait Mac {
     /// Initializes the layer; may require a buffer to temporarily retaining frames to be
     /// transmitted
@@ -23,7 +39,7 @@ pub trait Mac {
     /// Sets the notified client for configuration changes
     fn set_config_client(&self, client: &'static dyn radio::ConfigClient);
     /// Sets the notified client for transmission completions
-    fn set_transmit_client(&self, client: &'static dyn radio::TxClient);
+    fn set_transmit_client(&self, client: &'static dyn TxClient);
     /// Sets the notified client for frame receptions
     fn set_receive_client(&self, client: &'static dyn radio::RxClient);
     /// Sets the buffer for packet reception
@@ -70,7 +86,7 @@ pub trait Mac {
 pub struct AwakeMac<'a, R: radio::Radio> {
     radio: &'a R,
 
-    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    tx_client: OptionalCell<&'static dyn TxClient>,
     rx_client: OptionalCell<&'static dyn radio::RxClient>,
 }
 
@@ -126,7 +142,7 @@ impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
         self.radio.config_commit()
     }
 
-    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+    fn set_transmit_client(&self, client: &'static dyn TxClient) {
         self.tx_client.set(client);
     }
 
@@ -150,7 +166,7 @@ impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
 impl<R: radio::Radio> radio::TxClient for AwakeMac<'_, R> {
     fn send_done(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
         self.tx_client.map(move |c| {
-            c.send_done(buf, acked, result);
+            c.send_done(buf, acked, 0, result);
         });
     }
 }
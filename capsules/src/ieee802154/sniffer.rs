@@ -0,0 +1,319 @@
+//! IEEE 802.15.4 packet sniffer.
+//!
+//! `Sniffer` puts a radio in promiscuous mode on a single channel and streams
+//! every frame it receives over a UART as a PCAP capture, which can be opened
+//! with Wireshark. Each frame is preceded by an IEEE 802.15.4 TAP header
+//! (link type 283) carrying the channel, the RSSI and the LQI of the frame.
+//! Timestamps are measured from the moment the sniffer is started.
+//!
+//! The sniffer takes over the receive client of the radio, so it cannot be
+//! used together with the 802.15.4 MAC stack, and it should be given a UART
+//! of its own, as anything else written to it would corrupt the capture.
+//! Frames are buffered in a ring buffer while the UART is busy; frames that
+//! do not fit are dropped and counted. Frames with an invalid CRC are not
+//! captured, and the FCS of captured frames is not included.
+//!
+//! On the host, `tools/sniffer-pcap.py` reads the stream from the serial port
+//! and writes it to a file, or pipes it to Wireshark.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let sniffer = static_init!(
+//!     capsules::ieee802154::sniffer::Sniffer<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::sniffer::Sniffer::new(
+//!         radio,
+//!         uart_device,
+//!         sniffer_alarm,
+//!         &mut QUEUE_BUF,
+//!         &mut TX_BUF,
+//!     )
+//! );
+//! uart_device.set_transmit_client(sniffer);
+//! sniffer_alarm.set_alarm_client(sniffer);
+//! radio.set_receive_client(sniffer, &mut RX_BUF);
+//! sniffer.start(26).unwrap();
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+
+use kernel::collections::queue::Queue;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::hil::radio::{self, Radio, RadioMonitor};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::hil::uart;
+use kernel::utilities::cells::{MapCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Length of the PCAP global header.
+pub const PCAP_HEADER_LEN: usize = 24;
+/// Length of the PCAP record header in front of each frame.
+pub const RECORD_HEADER_LEN: usize = 16;
+/// Length of the IEEE 802.15.4 TAP header in front of each frame.
+pub const TAP_HEADER_LEN: usize = 36;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 256;
+const LINKTYPE_IEEE802_15_4_TAP: u32 = 283;
+
+// TAP TLV types
+const TLV_FCS_TYPE: u16 = 0;
+const TLV_RSS: u16 = 1;
+const TLV_CHANNEL_ASSIGNMENT: u16 = 3;
+const TLV_LQI: u16 = 10;
+
+/// Writes the PCAP global header to `buf`, which must hold at least
+/// `PCAP_HEADER_LEN` bytes.
+pub fn encode_pcap_header(buf: &mut [u8]) {
+    buf[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+    buf[4..6].copy_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+    buf[6..8].copy_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+    // Time zone offset and timestamp accuracy
+    buf[8..16].copy_from_slice(&[0; 8]);
+    buf[16..20].copy_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+    buf[20..24].copy_from_slice(&LINKTYPE_IEEE802_15_4_TAP.to_le_bytes());
+}
+
+/// Writes the PCAP record header and the TAP header of a frame of
+/// `frame_len` bytes (without FCS) received at `timestamp_us` to `buf`,
+/// which must hold at least `RECORD_HEADER_LEN + TAP_HEADER_LEN` bytes.
+pub fn encode_record_header(
+    buf: &mut [u8],
+    timestamp_us: u64,
+    frame_len: usize,
+    channel: u8,
+    rssi: i8,
+    lqi: u8,
+) {
+    let captured_len = ((TAP_HEADER_LEN + frame_len) as u32).to_le_bytes();
+    buf[0..4].copy_from_slice(&((timestamp_us / 1_000_000) as u32).to_le_bytes());
+    buf[4..8].copy_from_slice(&((timestamp_us % 1_000_000) as u32).to_le_bytes());
+    buf[8..12].copy_from_slice(&captured_len);
+    buf[12..16].copy_from_slice(&captured_len);
+
+    let tap = &mut buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + TAP_HEADER_LEN];
+    // Version and reserved byte, then the length of the header with its TLVs
+    tap[0..2].copy_from_slice(&[0, 0]);
+    tap[2..4].copy_from_slice(&(TAP_HEADER_LEN as u16).to_le_bytes());
+    // Each TLV is padded to a multiple of 4 bytes
+    let mut tlv = |offset: usize, tlv_type: u16, value: &[u8]| {
+        tap[offset..offset + 2].copy_from_slice(&tlv_type.to_le_bytes());
+        tap[offset + 2..offset + 4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        tap[offset + 4..offset + 8].copy_from_slice(&[0; 4]);
+        tap[offset + 4..offset + 4 + value.len()].copy_from_slice(value);
+    };
+    // The FCS is not included in captured frames
+    tlv(4, TLV_FCS_TYPE, &[0]);
+    tlv(12, TLV_RSS, &(rssi as f32).to_le_bytes());
+    let channel = (channel as u16).to_le_bytes();
+    // Channel page 0
+    tlv(20, TLV_CHANNEL_ASSIGNMENT, &[channel[0], channel[1], 0]);
+    tlv(28, TLV_LQI, &[lqi]);
+}
+
+pub struct Sniffer<'a, R: Radio + RadioMonitor, A: Alarm<'a>> {
+    radio: &'a R,
+    uart: &'a dyn uart::Transmit<'a>,
+    alarm: &'a A,
+    /// Holds the capture stream until it is sent over the UART
+    queue: MapCell<RingBuffer<'static, u8>>,
+    tx_buf: TakeCell<'static, [u8]>,
+    channel: Cell<u8>,
+    running: Cell<bool>,
+    last_ticks: Cell<A::Ticks>,
+    elapsed_ticks: Cell<u64>,
+    dropped: Cell<usize>,
+}
+
+impl<'a, R: Radio + RadioMonitor, A: Alarm<'a>> Sniffer<'a, R, A> {
+    /// `queue_buf` buffers the capture stream, and should hold a few frames
+    /// of up to `RECORD_HEADER_LEN + TAP_HEADER_LEN + radio::MAX_FRAME_SIZE`
+    /// bytes. `tx_buf` holds the data being sent over the UART.
+    pub fn new(
+        radio: &'a R,
+        uart: &'a dyn uart::Transmit<'a>,
+        alarm: &'a A,
+        queue_buf: &'static mut [u8],
+        tx_buf: &'static mut [u8],
+    ) -> Sniffer<'a, R, A> {
+        Sniffer {
+            radio: radio,
+            uart: uart,
+            alarm: alarm,
+            queue: MapCell::new(RingBuffer::new(queue_buf)),
+            tx_buf: TakeCell::new(tx_buf),
+            channel: Cell::new(0),
+            running: Cell::new(false),
+            last_ticks: Cell::new(A::Ticks::from(0)),
+            elapsed_ticks: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Starts capturing frames on `channel`. Each call starts a new capture
+    /// stream, beginning with a PCAP header.
+    pub fn start(&self, channel: u8) -> Result<(), ErrorCode> {
+        self.radio.set_channel(channel)?;
+        self.radio.set_promiscuous_mode(true)?;
+        if self.radio.is_on() {
+            self.radio.config_commit();
+        } else {
+            self.radio.start()?;
+        }
+        self.channel.set(channel);
+        self.last_ticks.set(self.alarm.now());
+        self.elapsed_ticks.set(0);
+        self.dropped.set(0);
+        self.alarm
+            .set_alarm(self.last_ticks.get(), A::Ticks::half_max_value());
+
+        let mut header = [0; PCAP_HEADER_LEN];
+        encode_pcap_header(&mut header);
+        self.queue.map(|queue| {
+            queue.empty();
+            header.iter().for_each(|byte| {
+                queue.enqueue(*byte);
+            });
+        });
+        self.running.set(true);
+        self.send_queued();
+        Ok(())
+    }
+
+    /// Stops capturing frames. Frames that are already queued are still sent.
+    pub fn stop(&self) -> Result<(), ErrorCode> {
+        self.running.set(false);
+        let _ = self.alarm.disarm();
+        self.radio.set_promiscuous_mode(false)?;
+        self.radio.stop()
+    }
+
+    /// Returns the number of frames that were dropped since the capture
+    /// started because the UART could not keep up.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped.get()
+    }
+
+    /// Returns the time since the capture started, in microseconds. Must be
+    /// called at least once every `Ticks::half_max_value()` ticks.
+    fn elapsed_us(&self) -> u64 {
+        let now = self.alarm.now();
+        let ticks = now.wrapping_sub(self.last_ticks.get()).into_u32() as u64;
+        self.last_ticks.set(now);
+        self.elapsed_ticks.set(self.elapsed_ticks.get() + ticks);
+        self.elapsed_ticks.get() * 1_000_000 / A::Frequency::frequency() as u64
+    }
+
+    fn capture(&self, frame: &[u8]) {
+        let timestamp_us = self.elapsed_us();
+        let (rssi, lqi) = self.radio.rx_signal_quality();
+        let mut header = [0; RECORD_HEADER_LEN + TAP_HEADER_LEN];
+        encode_record_header(
+            &mut header,
+            timestamp_us,
+            frame.len(),
+            self.channel.get(),
+            rssi,
+            lqi,
+        );
+        self.queue.map(|queue| {
+            if queue.available_len() < header.len() + frame.len() {
+                self.dropped.set(self.dropped.get() + 1);
+                return;
+            }
+            header.iter().chain(frame.iter()).for_each(|byte| {
+                queue.enqueue(*byte);
+            });
+        });
+    }
+
+    /// Sends as much of the queued stream as fits in `tx_buf`, unless the
+    /// UART is still busy.
+    fn send_queued(&self) {
+        self.tx_buf.take().map(|tx_buf| {
+            let len = self.queue.map_or(0, |queue| {
+                let len = min(queue.len(), tx_buf.len());
+                for byte in tx_buf[..len].iter_mut() {
+                    *byte = queue.dequeue().unwrap_or(0);
+                }
+                len
+            });
+            if len == 0 {
+                self.tx_buf.replace(tx_buf);
+            } else if let Err((_, tx_buf)) = self.uart.transmit_buffer(tx_buf, len) {
+                self.tx_buf.replace(tx_buf);
+            }
+        });
+    }
+}
+
+impl<'a, R: Radio + RadioMonitor, A: Alarm<'a>> radio::RxClient for Sniffer<'a, R, A> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        crc_valid: bool,
+        result: Result<(), ErrorCode>,
+    ) {
+        if self.running.get() && crc_valid && result == Ok(()) {
+            let frame_len = min(frame_len, buf.len() - radio::PSDU_OFFSET);
+            self.capture(&buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len]);
+        }
+        self.radio.set_receive_buffer(buf);
+        self.send_queued();
+    }
+}
+
+impl<'a, R: Radio + RadioMonitor, A: Alarm<'a>> time::AlarmClient for Sniffer<'a, R, A> {
+    fn alarm(&self) {
+        // Keeps the timestamps from wrapping around when no frames are
+        // received for a long time
+        if self.running.get() {
+            self.elapsed_us();
+            self.alarm
+                .set_alarm(self.last_ticks.get(), A::Ticks::half_max_value());
+        }
+    }
+}
+
+impl<'a, R: Radio + RadioMonitor, A: Alarm<'a>> uart::TransmitClient for Sniffer<'a, R, A> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buf.replace(tx_buffer);
+        self.send_queued();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_header() {
+        let mut buf = [0xff; RECORD_HEADER_LEN + TAP_HEADER_LEN];
+        encode_record_header(&mut buf, 3_000_042, 10, 26, -60, 200);
+        let captured_len = (TAP_HEADER_LEN as u32 + 10).to_le_bytes();
+        assert_eq!(buf[0..4], 3u32.to_le_bytes());
+        assert_eq!(buf[4..8], 42u32.to_le_bytes());
+        assert_eq!(buf[8..12], captured_len);
+        assert_eq!(buf[12..16], captured_len);
+
+        let tap = &buf[RECORD_HEADER_LEN..];
+        assert_eq!(tap[0..4], [0, 0, TAP_HEADER_LEN as u8, 0]);
+        assert_eq!(tap[4..12], [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(tap[12..16], [1, 0, 4, 0]);
+        assert_eq!(tap[16..20], (-60.0f32).to_le_bytes());
+        assert_eq!(tap[20..28], [3, 0, 3, 0, 26, 0, 0, 0]);
+        assert_eq!(tap[28..36], [10, 0, 1, 0, 200, 0, 0, 0]);
+    }
+}
@@ -47,9 +47,15 @@ pub struct MuxMac<'a> {
 }
 
 impl device::TxClient for MuxMac<'_> {
-    fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+    fn send_done(
+        &self,
+        spi_buf: &'static mut [u8],
+        acked: bool,
+        retries: u8,
+        result: Result<(), ErrorCode>,
+    ) {
         self.inflight.take().map(move |user| {
-            user.send_done(spi_buf, acked, result);
+            user.send_done(spi_buf, acked, retries, result);
         });
         self.do_next_op_async();
     }
@@ -112,7 +118,7 @@ impl<'a> MuxMac<'a> {
                     self.inflight.set(node);
                 }
                 Err((ecode, buf)) => {
-                    node.send_done(buf, false, Err(ecode));
+                    node.send_done(buf, false, 0, Err(ecode));
                 }
             }
         }
@@ -209,10 +215,16 @@ impl<'a> MacUser<'a> {
 }
 
 impl MacUser<'_> {
-    fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+    fn send_done(
+        &self,
+        spi_buf: &'static mut [u8],
+        acked: bool,
+        retries: u8,
+        result: Result<(), ErrorCode>,
+    ) {
         self.tx_client
             .get()
-            .map(move |client| client.send_done(spi_buf, acked, result));
+            .map(move |client| client.send_done(spi_buf, acked, retries, result));
     }
 
     fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
@@ -78,7 +78,7 @@
 // Date: Nov 21 2017
 //
 
-use crate::ieee802154::mac::Mac;
+use crate::ieee802154::mac::{Mac, TxClient};
 use crate::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress, PanID};
 use core::cell::Cell;
 use kernel::hil::radio;
@@ -148,7 +148,7 @@ pub struct XMac<'a, R: radio::Radio, A: Alarm<'a>> {
     radio: &'a R,
     alarm: &'a A,
     rng: &'a dyn Rng<'a>,
-    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    tx_client: OptionalCell<&'static dyn TxClient>,
     rx_client: OptionalCell<&'static dyn radio::RxClient>,
     state: Cell<XMacState>,
     delay_sleep: Cell<bool>,
@@ -293,7 +293,7 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
         self.state.set(XMacState::AWAKE);
         self.sleep();
         self.tx_client.map(move |c| {
-            c.send_done(buf, acked, result);
+            c.send_done(buf, acked, 0, result);
         });
     }
 
@@ -392,7 +392,7 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
         self.radio.config_commit()
     }
 
-    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+    fn set_transmit_client(&self, client: &'static dyn TxClient) {
         self.tx_client.set(client);
     }
 
@@ -9,16 +9,20 @@ pub mod net;
 
 pub mod adc;
 pub mod adc_microphone;
+pub mod aes_gcm;
+pub mod aes_software;
 pub mod alarm;
 pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
 pub mod app_flash_driver;
+pub mod ble;
 pub mod ble_advertising_driver;
 pub mod bus;
 pub mod button;
 pub mod buzzer_driver;
+pub mod chacha20_poly1305;
 pub mod console;
 pub mod crc;
 pub mod ctap;
@@ -42,6 +46,7 @@ pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
 pub mod log;
+pub mod log_driver;
 pub mod low_level_debug;
 pub mod lps25hb;
 pub mod lsm303agr;
@@ -60,6 +65,8 @@ pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
 pub mod process_console;
+pub mod process_owner;
+pub mod public_key_crypto;
 pub mod proximity;
 pub mod read_only_state;
 pub mod rf233;
@@ -69,6 +76,7 @@ pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
 pub mod sha;
+pub mod sha_software;
 pub mod sht3x;
 pub mod si7021;
 pub mod sip_hash;
@@ -86,6 +94,7 @@ pub mod touch;
 pub mod tsl2561;
 pub mod usb;
 pub mod virtual_adc;
+pub mod virtual_aead;
 pub mod virtual_aes_ccm;
 pub mod virtual_alarm;
 pub mod virtual_digest;
@@ -0,0 +1,900 @@
+//! Provides userspace access to a persistent log.
+//!
+//! This capsule sits on top of any implementation of `hil::log::LogRead` and
+//! `hil::log::LogWrite` (such as `capsules::log::Log`) and exposes it to
+//! applications. Applications can append records to the log and iterate
+//! through it. Every application has its own read cursor, so applications
+//! reading the log do not disturb each other. Cursors are the entry IDs
+//! ("cookies") of the underlying log, and an application can save a cookie
+//! and later seek back to it.
+//!
+//! Each record written through this driver starts with a small header that
+//! the kernel fills in. The header always contains a sequence number, and can
+//! optionally contain the package name of the application that appended the
+//! record (from its TBF header) and a timestamp taken from a
+//! `hil::time::Time` source. Because these fields are written by the kernel,
+//! an application cannot put a name other than its own into a record header.
+//! Package names are not authenticated, however: two applications built with
+//! the same package name cannot be told apart in the log.
+//!
+//! Sequence numbers increase by one with every record. Before the first
+//! operation after boot, the driver walks the log and continues from the
+//! sequence number of the newest record. A reader can therefore detect
+//! records that were removed or rewritten: the sequence numbers of
+//! consecutive records always differ by exactly one. Only the oldest records
+//! may be missing, when a circular log wraps around.
+//!
+//! Applications cannot erase the log. The board (or another kernel client
+//! holding a reference to the driver) can call `LogDriver::erase()`; sequence
+//! numbers continue across the erase until the next reboot.
+//!
+//! The board can also print the whole log to the kernel console with
+//! `LogDriver::print_log()`. Each record is printed on its own line with its
+//! cookie, sequence number, app name, timestamp and payload. The output goes
+//! through `debug!()`, so a long log can overflow the debug buffer and be
+//! truncated.
+//!
+//! Record format
+//! -------------
+//!
+//! ```text
+//! +-------+----------------+----------+-----------+-----------------+---------+
+//! | flags | sequence (u32) | name len | name      | timestamp (u32) | payload |
+//! +-------+----------------+----------+-----------+-----------------+---------+
+//!   1 byte  always present   present if flags       present if flags
+//!                            bit 0 is set           bit 1 is set
+//! ```
+//!
+//! The name is at most `MAX_APP_NAME_SIZE` bytes long; longer package names
+//! are truncated. All multi-byte fields are little endian. Records are
+//! returned to applications exactly as they are stored, including the header.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{capabilities, static_init};
+//!
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//!
+//! let log_driver = static_init!(
+//!     capsules::log_driver::LogDriver<
+//!         'static,
+//!         capsules::log::Log<'static, sam4l::flashcalw::FLASHCALW>,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules::log_driver::LogDriver::new(
+//!         log,
+//!         virtual_alarm,
+//!         &mut capsules::log_driver::BUFFER,
+//!         board_kernel.create_grant(capsules::log_driver::DRIVER_NUM, &grant_cap),
+//!         board_kernel,
+//!         ProcessMgmtCap,
+//!     )
+//! );
+//! log.set_read_client(log_driver);
+//! log.set_append_client(log_driver);
+//!
+//! // Optionally print the log left over from previous boots.
+//! let _ = log_driver.print_log();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt;
+use core::str;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::hil::time::{Frequency, Ticks, Time};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{debug, ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Log as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const APPEND: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const READ_DONE: usize = 0;
+    pub const APPEND_DONE: usize = 1;
+    pub const SYNC_DONE: usize = 2;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 3;
+}
+
+/// Record header flag: the record contains the name of the appending app.
+pub const FLAG_APP_ID: u8 = 0x01;
+/// Record header flag: the record contains a timestamp.
+pub const FLAG_TIMESTAMP: u8 = 0x02;
+
+/// Maximum number of bytes of the app name stored in a record header.
+pub const MAX_APP_NAME_SIZE: usize = 32;
+
+/// Size of the largest possible record header.
+pub const MAX_HEADER_SIZE: usize = 10 + MAX_APP_NAME_SIZE;
+
+/// Default buffer for records. Must be large enough for the largest record
+/// (header included) that will be written or read.
+pub static mut BUFFER: [u8; 256] = [0; 256];
+
+/// The header the kernel writes in front of every record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordHeader<'b> {
+    pub flags: u8,
+    pub sequence: u32,
+    /// Package name of the app, only stored if `flags` contains
+    /// `FLAG_APP_ID`. Truncated to `MAX_APP_NAME_SIZE` bytes when encoded.
+    pub app_name: &'b [u8],
+    /// Only stored if `flags` contains `FLAG_TIMESTAMP`.
+    pub timestamp: u32,
+}
+
+impl<'b> RecordHeader<'b> {
+    /// Write the header to the start of `buffer` and return its length.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        let name = &self.app_name[..cmp::min(self.app_name.len(), MAX_APP_NAME_SIZE)];
+        let mut length = 5;
+        if self.flags & FLAG_APP_ID != 0 {
+            length += 1 + name.len();
+        }
+        if self.flags & FLAG_TIMESTAMP != 0 {
+            length += 4;
+        }
+        if length > buffer.len() {
+            return Err(ErrorCode::SIZE);
+        }
+
+        buffer[0] = self.flags;
+        buffer[1..5].copy_from_slice(&self.sequence.to_le_bytes());
+        let mut offset = 5;
+        if self.flags & FLAG_APP_ID != 0 {
+            buffer[offset] = name.len() as u8;
+            buffer[offset + 1..offset + 1 + name.len()].copy_from_slice(name);
+            offset += 1 + name.len();
+        }
+        if self.flags & FLAG_TIMESTAMP != 0 {
+            buffer[offset..offset + 4].copy_from_slice(&self.timestamp.to_le_bytes());
+        }
+        Ok(length)
+    }
+
+    /// Parse the header at the start of `record`. Returns the header and its
+    /// length, or `None` if `record` does not start with a valid header.
+    pub fn decode(record: &'b [u8]) -> Option<(RecordHeader<'b>, usize)> {
+        let read_u32 = |offset: usize| -> Option<u32> {
+            let bytes = record.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        let flags = *record.get(0)?;
+        if flags & !(FLAG_APP_ID | FLAG_TIMESTAMP) != 0 {
+            return None;
+        }
+        let sequence = read_u32(1)?;
+        let mut length = 5;
+        let mut app_name: &[u8] = &[];
+        if flags & FLAG_APP_ID != 0 {
+            let name_len = *record.get(length)? as usize;
+            if name_len > MAX_APP_NAME_SIZE {
+                return None;
+            }
+            app_name = record.get(length + 1..length + 1 + name_len)?;
+            length += 1 + name_len;
+        }
+        let mut timestamp = 0;
+        if flags & FLAG_TIMESTAMP != 0 {
+            timestamp = read_u32(length)?;
+            length += 4;
+        }
+        Some((
+            RecordHeader {
+                flags,
+                sequence,
+                app_name,
+                timestamp,
+            },
+            length,
+        ))
+    }
+}
+
+/// Formats a record for the kernel console.
+struct RecordPrinter<'b> {
+    entry: usize,
+    record: &'b [u8],
+}
+
+impl fmt::Display for RecordPrinter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (header, header_len) = match RecordHeader::decode(self.record) {
+            Some(decoded) => decoded,
+            None => {
+                return write!(
+                    f,
+                    "{:#x}: invalid header ({} bytes)",
+                    self.entry,
+                    self.record.len()
+                )
+            }
+        };
+        write!(f, "{:#x}: #{}", self.entry, header.sequence)?;
+        if header.flags & FLAG_APP_ID != 0 {
+            write!(f, " {}", str::from_utf8(header.app_name).unwrap_or("?"))?;
+        }
+        if header.flags & FLAG_TIMESTAMP != 0 {
+            write!(f, " @{}", header.timestamp)?;
+        }
+        let payload = &self.record[header_len..];
+        match str::from_utf8(payload) {
+            Ok(text) => write!(f, " {:?}", text),
+            Err(_) => {
+                write!(f, " ")?;
+                for byte in payload {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns whether `id` is a valid seek position in a log whose oldest entry
+/// is `start` and whose next entry will be `end`.
+fn in_log(id: usize, start: usize, end: usize) -> bool {
+    id >= start && id <= end
+}
+
+/// Returns `cursor` if it still points into the log, otherwise the oldest
+/// entry in the log. Cursors fall out of the log when a circular log wraps.
+fn resolve_cursor(cursor: Option<usize>, start: usize, end: usize) -> usize {
+    match cursor {
+        Some(id) if in_log(id, start, end) => id,
+        _ => start,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogOperation {
+    Read,
+    Append { length: usize, flags: u8 },
+    Sync,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Seeking the underlying log to the app's cursor before a read.
+    Seek,
+    Read,
+    Append,
+    Sync,
+    /// Erasing the log on behalf of the kernel.
+    Erase,
+    /// Walking the log to recover the next sequence number.
+    Scan,
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: Option<LogOperation>,
+    cursor: Option<usize>,
+}
+
+pub struct LogDriver<'a, L: LogRead<'a> + LogWrite<'a>, T: Time, C: ProcessManagementCapability> {
+    log: &'a L,
+    time: &'a T,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    buffer: TakeCell<'static, [u8]>,
+    current_app: OptionalCell<ProcessId>,
+    state: Cell<State>,
+    /// Entry ID of the record currently being read.
+    read_entry: Cell<usize>,
+    /// Sequence number of the next appended record.
+    sequence: Cell<u32>,
+    /// Whether `sequence` has been recovered from the log since boot.
+    recovered: Cell<bool>,
+    /// Whether the records are printed to the console while scanning.
+    printing: Cell<bool>,
+    /// Used to look up the package names of apps.
+    kernel: &'static Kernel,
+    capability: C,
+}
+
+impl<
+        'a,
+        L: LogRead<'a, EntryID = usize> + LogWrite<'a>,
+        T: Time,
+        C: ProcessManagementCapability,
+    > LogDriver<'a, L, T, C>
+{
+    pub fn new(
+        log: &'a L,
+        time: &'a T,
+        buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        kernel: &'static Kernel,
+        capability: C,
+    ) -> LogDriver<'a, L, T, C> {
+        LogDriver {
+            log,
+            time,
+            apps: grant,
+            buffer: TakeCell::new(buffer),
+            current_app: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            read_entry: Cell::new(0),
+            sequence: Cell::new(0),
+            recovered: Cell::new(false),
+            printing: Cell::new(false),
+            kernel,
+            capability,
+        }
+    }
+
+    /// Erase the log. This is not exposed to applications; it is up to the
+    /// board to decide when the log may be erased. Returns `BUSY` if an
+    /// operation is in progress.
+    pub fn erase(&self) -> Result<(), ErrorCode> {
+        if self.current_app.is_some() || self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.state.set(State::Erase);
+        self.log.erase().map_err(|e| {
+            self.state.set(State::Idle);
+            e
+        })
+    }
+
+    /// Print every record in the log to the kernel console. Returns `BUSY`
+    /// if an operation is in progress.
+    pub fn print_log(&self) -> Result<(), ErrorCode> {
+        if self.current_app.is_some() || self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        debug!(
+            "Log {:#x}..{:#x}:",
+            self.log.log_start(),
+            self.log.log_end()
+        );
+        self.printing.set(true);
+        self.scan();
+        Ok(())
+    }
+
+    fn valid_cursor(&self, cursor: Option<usize>) -> usize {
+        resolve_cursor(cursor, self.log.log_start(), self.log.log_end())
+    }
+
+    /// Either start the operation right away or queue it for the app if the
+    /// log is in use.
+    fn enqueue(&self, operation: LogOperation, appid: ProcessId) -> Result<(), ErrorCode> {
+        if self.current_app.is_none() && self.state.get() == State::Idle && self.recovered.get() {
+            self.current_app.set(appid);
+            let res = self.start(operation, appid);
+            if res.is_err() {
+                self.current_app.clear();
+                self.state.set(State::Idle);
+            }
+            res
+        } else {
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending.is_some() {
+                        Err(ErrorCode::NOMEM)
+                    } else {
+                        app.pending = Some(operation);
+                        Ok(())
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+                .map(|()| {
+                    if self.state.get() == State::Idle && !self.recovered.get() {
+                        self.scan();
+                    }
+                })
+        }
+    }
+
+    /// Start walking the log from its oldest record to recover the sequence
+    /// number of the newest one. Queued operations start once this is done.
+    fn scan(&self) {
+        self.state.set(State::Scan);
+        self.sequence.set(0);
+        if self.log.next_read_entry_id() == self.log.log_start() {
+            self.scan_next();
+        } else if self.log.seek(self.log.log_start()).is_err() {
+            self.scan_done();
+        }
+    }
+
+    fn scan_next(&self) {
+        // Reading fails once the end of the log is reached.
+        if self.read().is_err() {
+            self.scan_done();
+        }
+    }
+
+    fn scan_done(&self) {
+        if self.printing.take() {
+            debug!("End of log.");
+        }
+        self.recovered.set(true);
+        self.state.set(State::Idle);
+        self.check_queue();
+    }
+
+    fn start(&self, operation: LogOperation, appid: ProcessId) -> Result<(), ErrorCode> {
+        match operation {
+            LogOperation::Read => {
+                let cursor = self
+                    .apps
+                    .enter(appid, |app, _| self.valid_cursor(app.cursor))?;
+                if cursor == self.log.next_read_entry_id() {
+                    self.state.set(State::Read);
+                    self.read()
+                } else {
+                    self.state.set(State::Seek);
+                    self.log.seek(cursor)
+                }
+            }
+            LogOperation::Append { length, flags } => self.append(appid, length, flags),
+            LogOperation::Sync => {
+                self.state.set(State::Sync);
+                self.log.sync()
+            }
+        }
+    }
+
+    fn read(&self) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::RESERVE)?;
+        let length = buffer.len();
+        self.read_entry.set(self.log.next_read_entry_id());
+        self.log.read(buffer, length).map_err(|(ecode, buffer)| {
+            self.buffer.replace(buffer);
+            ecode
+        })
+    }
+
+    /// Build the record header followed by the data the app allowed, then
+    /// append it to the log.
+    fn append(&self, appid: ProcessId, length: usize, flags: u8) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::RESERVE)?;
+
+        let app_name = self.kernel.process_map_or_external(
+            "",
+            appid,
+            |process| process.get_process_name(),
+            &self.capability,
+        );
+        let header = RecordHeader {
+            flags,
+            sequence: self.sequence.get(),
+            app_name: app_name.as_bytes(),
+            timestamp: self.time.now().into_u32(),
+        };
+        let header_len = match header.encode(buffer) {
+            Ok(header_len) => header_len,
+            Err(e) => {
+                self.buffer.replace(buffer);
+                return Err(e);
+            }
+        };
+
+        let copied = self
+            .apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::APPEND)
+                    .and_then(|append| {
+                        append.enter(|data| {
+                            if length > data.len() || header_len + length > buffer.len() {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data[..length]
+                                    .copy_to_slice(&mut buffer[header_len..header_len + length]);
+                                Ok(())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = copied {
+            self.buffer.replace(buffer);
+            return Err(e);
+        }
+
+        self.state.set(State::Append);
+        self.log
+            .append(buffer, header_len + length)
+            .map_err(|(ecode, buffer)| {
+                self.buffer.replace(buffer);
+                ecode
+            })
+    }
+
+    /// Signal the current app that its operation finished and start the next
+    /// queued operation, if any.
+    fn finish(&self, upcall_num: usize, result: Result<(), ErrorCode>, arg1: usize, arg2: usize) {
+        self.state.set(State::Idle);
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall_num, (into_statuscode(result), arg1, arg2))
+                    .ok();
+            });
+        });
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            let appid = cntr.processid();
+            let operation = cntr.enter(|app, _| app.pending.take());
+            if let Some(operation) = operation {
+                self.current_app.set(appid);
+                if let Err(e) = self.start(operation, appid) {
+                    // Report the failure and move on to the next app.
+                    let upcall_num = match operation {
+                        LogOperation::Read => upcall::READ_DONE,
+                        LogOperation::Append { .. } => upcall::APPEND_DONE,
+                        LogOperation::Sync => upcall::SYNC_DONE,
+                    };
+                    self.state.set(State::Idle);
+                    self.current_app.clear();
+                    let _ = self.apps.enter(appid, |_, kernel_data| {
+                        kernel_data
+                            .schedule_upcall(upcall_num, (into_statuscode(Err(e)), 0, 0))
+                            .ok();
+                    });
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        L: LogRead<'a, EntryID = usize> + LogWrite<'a>,
+        T: Time,
+        C: ProcessManagementCapability,
+    > LogReadClient for LogDriver<'a, L, T, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, error: Result<(), ErrorCode>) {
+        if self.state.get() == State::Scan {
+            if error.is_ok() {
+                let record = &buffer[..length];
+                if let Some((header, _)) = RecordHeader::decode(record) {
+                    self.sequence.set(header.sequence.wrapping_add(1));
+                }
+                if self.printing.get() {
+                    debug!(
+                        "{}",
+                        RecordPrinter {
+                            entry: self.read_entry.get(),
+                            record,
+                        }
+                    );
+                }
+            }
+            self.buffer.replace(buffer);
+            if error.is_ok() {
+                self.scan_next();
+            } else {
+                self.scan_done();
+            }
+            return;
+        }
+
+        let entry = self.read_entry.get();
+        let next = self.log.next_read_entry_id();
+        self.current_app.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, kernel_data| {
+                app.cursor = Some(next);
+                if error.is_ok() {
+                    let _ = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::READ)
+                        .and_then(|read| {
+                            read.mut_enter(|app_buffer| {
+                                let read_len = cmp::min(app_buffer.len(), length);
+                                app_buffer[..read_len].copy_from_slice(&buffer[..read_len]);
+                            })
+                        });
+                }
+            });
+        });
+        self.buffer.replace(buffer);
+        self.finish(upcall::READ_DONE, error, length, entry);
+    }
+
+    fn seek_done(&self, error: Result<(), ErrorCode>) {
+        match self.state.get() {
+            State::Seek => self.state.set(State::Read),
+            State::Scan => {
+                match error {
+                    Ok(()) => self.scan_next(),
+                    Err(_) => self.scan_done(),
+                }
+                return;
+            }
+            _ => return,
+        }
+        match error.and_then(|()| self.read()) {
+            Ok(()) => {}
+            Err(e) => self.finish(upcall::READ_DONE, Err(e), 0, 0),
+        }
+    }
+}
+
+impl<
+        'a,
+        L: LogRead<'a, EntryID = usize> + LogWrite<'a>,
+        T: Time,
+        C: ProcessManagementCapability,
+    > LogWriteClient for LogDriver<'a, L, T, C>
+{
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+        if error.is_ok() {
+            self.sequence.set(self.sequence.get().wrapping_add(1));
+        }
+        self.finish(upcall::APPEND_DONE, error, length, records_lost as usize);
+    }
+
+    fn sync_done(&self, error: Result<(), ErrorCode>) {
+        self.finish(upcall::SYNC_DONE, error, 0, 0);
+    }
+
+    fn erase_done(&self, _error: Result<(), ErrorCode>) {
+        // Erasing is only started by the kernel, so there is no app to notify.
+        // The log is empty now, so there is nothing left to recover the
+        // sequence number from: keep counting from the current one.
+        self.recovered.set(true);
+        self.state.set(State::Idle);
+        self.check_queue();
+    }
+}
+
+/// Provide an interface for userland.
+impl<
+        'a,
+        L: LogRead<'a, EntryID = usize> + LogWrite<'a>,
+        T: Time,
+        C: ProcessManagementCapability,
+    > SyscallDriver for LogDriver<'a, L, T, C>
+{
+    /// Command interface.
+    ///
+    /// Entry IDs ("cookies") are returned and accepted as 32 bit values.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Read the next record at the app's cursor into the read buffer.
+    ///        Upcall 0 is called with the status, the record length and the
+    ///        cookie of the record.
+    /// - `2`: Seek the app's cursor to the cookie in `data1`.
+    /// - `3`: Return the cookie of the oldest record in the log.
+    /// - `4`: Return the cookie the next appended record will get.
+    /// - `5`: Return the app's cursor.
+    /// - `6`: Append `data1` bytes of the append buffer, with the header
+    ///        fields selected by the flags in `data2`. Upcall 1 is called with
+    ///        the status, the record length and whether old records were lost.
+    /// - `7`: Sync the log to storage. Upcall 2 is called when done.
+    /// - `9`: Return the approximate capacity of the log in bytes.
+    /// - `10`: Return the frequency of the timestamps in Hz.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let res = match command_num {
+            0 => Ok(()),
+            1 => self.enqueue(LogOperation::Read, appid),
+            2 => {
+                if !in_log(data1, self.log.log_start(), self.log.log_end()) {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    self.apps
+                        .enter(appid, |app, _| {
+                            app.cursor = Some(data1);
+                        })
+                        .map_err(ErrorCode::from)
+                }
+            }
+            3 => return CommandReturn::success_u32(self.log.log_start() as u32),
+            4 => return CommandReturn::success_u32(self.log.log_end() as u32),
+            5 => {
+                return self
+                    .apps
+                    .enter(appid, |app, _| {
+                        CommandReturn::success_u32(self.valid_cursor(app.cursor) as u32)
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            6 => {
+                let flags = data2 as u8;
+                if flags & !(FLAG_APP_ID | FLAG_TIMESTAMP) != 0 {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    self.enqueue(
+                        LogOperation::Append {
+                            length: data1,
+                            flags,
+                        },
+                        appid,
+                    )
+                }
+            }
+            7 => self.enqueue(LogOperation::Sync, appid),
+            9 => return CommandReturn::success_u32(self.log.get_size() as u32),
+            10 => return CommandReturn::success_u32(T::Frequency::frequency()),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let header = RecordHeader {
+            flags: FLAG_APP_ID | FLAG_TIMESTAMP,
+            sequence: 0x01020304,
+            app_name: b"blink",
+            timestamp: 0xa0b0c0d0,
+        };
+        let mut buf = [0; MAX_HEADER_SIZE];
+        let len = header.encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[
+                0x03, 0x04, 0x03, 0x02, 0x01, 5, b'b', b'l', b'i', b'n', b'k', 0xd0, 0xc0, 0xb0,
+                0xa0
+            ]
+        );
+        assert_eq!(RecordHeader::decode(&buf[..len]), Some((header, len)));
+    }
+
+    #[test]
+    fn header_without_optional_fields() {
+        let header = RecordHeader {
+            flags: 0,
+            sequence: 7,
+            app_name: b"ignored",
+            timestamp: 1234,
+        };
+        let mut buf = [0xff; 8];
+        assert_eq!(header.encode(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], &[0, 7, 0, 0, 0]);
+
+        let (decoded, len) = RecordHeader::decode(&buf).unwrap();
+        assert_eq!(len, 5);
+        assert_eq!(decoded.sequence, 7);
+        assert_eq!(decoded.app_name, b"");
+        assert_eq!(decoded.timestamp, 0);
+    }
+
+    #[test]
+    fn header_truncates_long_names() {
+        let name = [b'x'; MAX_APP_NAME_SIZE + 10];
+        let header = RecordHeader {
+            flags: FLAG_APP_ID | FLAG_TIMESTAMP,
+            sequence: 0,
+            app_name: &name,
+            timestamp: 0,
+        };
+        let mut buf = [0; MAX_HEADER_SIZE];
+        assert_eq!(header.encode(&mut buf), Ok(MAX_HEADER_SIZE));
+        let (decoded, _) = RecordHeader::decode(&buf).unwrap();
+        assert_eq!(decoded.app_name, &name[..MAX_APP_NAME_SIZE]);
+    }
+
+    #[test]
+    fn header_does_not_fit() {
+        let header = RecordHeader {
+            flags: FLAG_TIMESTAMP,
+            sequence: 0,
+            app_name: b"",
+            timestamp: 0,
+        };
+        let mut buf = [0; 8];
+        assert_eq!(header.encode(&mut buf), Err(ErrorCode::SIZE));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_headers() {
+        // Unknown flag.
+        assert_eq!(RecordHeader::decode(&[0x04, 0, 0, 0, 0]), None);
+        // Truncated sequence number.
+        assert_eq!(RecordHeader::decode(&[0x00, 0, 0, 0]), None);
+        // Name longer than the record.
+        assert_eq!(RecordHeader::decode(&[0x01, 0, 0, 0, 0, 3, b'a']), None);
+        // Name longer than any name the kernel writes.
+        let mut record = [0; 6 + MAX_APP_NAME_SIZE + 1];
+        record[0] = FLAG_APP_ID;
+        record[5] = MAX_APP_NAME_SIZE as u8 + 1;
+        assert_eq!(RecordHeader::decode(&record), None);
+        // Truncated timestamp.
+        assert_eq!(RecordHeader::decode(&[0x02, 0, 0, 0, 0, 1, 2]), None);
+        assert_eq!(RecordHeader::decode(&[]), None);
+    }
+
+    #[test]
+    fn cursor_in_log_is_kept() {
+        assert_eq!(resolve_cursor(Some(100), 50, 200), 100);
+        assert_eq!(resolve_cursor(Some(50), 50, 200), 50);
+        // The end of the log is where the next record will be.
+        assert_eq!(resolve_cursor(Some(200), 50, 200), 200);
+    }
+
+    #[test]
+    fn cursor_outside_log_restarts_at_oldest_entry() {
+        assert_eq!(resolve_cursor(None, 50, 200), 50);
+        // The log wrapped around and overwrote the record at the cursor.
+        assert_eq!(resolve_cursor(Some(20), 50, 200), 50);
+        // The log was erased after the cursor was saved.
+        assert_eq!(resolve_cursor(Some(300), 0, 0), 0);
+    }
+
+    #[test]
+    fn seek_bounds() {
+        assert!(in_log(50, 50, 200));
+        assert!(in_log(200, 50, 200));
+        assert!(!in_log(49, 50, 200));
+        assert!(!in_log(201, 50, 200));
+    }
+}
@@ -0,0 +1,579 @@
+//! Encoding and decoding of CoAP messages (RFC 7252, section 3) and of the
+//! Block1 and Block2 options of block-wise transfers (RFC 7959).
+//!
+//! A message is a 4 byte header, a token of up to 8 bytes, a sequence of
+//! options sorted by option number and an optional payload that follows a
+//! `0xff` marker. Option numbers are delta encoded, so options have to be
+//! encoded in increasing order: each of the option encoders takes the
+//! number of the option encoded before it (`0` for the first one).
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// The default CoAP port.
+pub const COAP_PORT: u16 = 5683;
+
+pub const MAX_TOKEN_LEN: usize = 8;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// Message codes, in the `c.dd` notation of the RFC encoded as `c << 5 | dd`
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+
+    // Requests
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    // Responses
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const CONTINUE: u8 = 0x5f;
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+    pub fn is_request(code: u8) -> bool {
+        code != EMPTY && code >> 5 == 0
+    }
+
+    pub fn is_response(code: u8) -> bool {
+        let class = code >> 5;
+        class >= 2 && class <= 5
+    }
+}
+
+/// Option numbers
+pub mod option {
+    pub const URI_HOST: u16 = 3;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const SIZE1: u16 = 60;
+
+    /// Critical options must be understood by the receiver of a message,
+    /// which rejects the message otherwise.
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 == 1
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> MessageType {
+        match bits & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// The token correlating requests and responses.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Token {
+    len: u8,
+    bytes: [u8; MAX_TOKEN_LEN],
+}
+
+impl Token {
+    pub const EMPTY: Token = Token {
+        len: 0,
+        bytes: [0; MAX_TOKEN_LEN],
+    };
+
+    /// Returns `None` if `bytes` is longer than 8 bytes.
+    pub fn new(bytes: &[u8]) -> Option<Token> {
+        if bytes.len() > MAX_TOKEN_LEN {
+            return None;
+        }
+        let mut token = Token::EMPTY;
+        token.len = bytes.len() as u8;
+        token.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(token)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CoapHeader {
+    pub mtype: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Token,
+}
+
+impl CoapHeader {
+    pub fn new(mtype: MessageType, code: u8, message_id: u16, token: Token) -> CoapHeader {
+        CoapHeader {
+            mtype,
+            code,
+            message_id,
+            token,
+        }
+    }
+
+    pub fn get_hdr_size(&self) -> usize {
+        4 + self.token.len as usize
+    }
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, offset + self.get_hdr_size());
+        let mut off = offset;
+        let first = VERSION << 6 | (self.mtype as u8) << 4 | self.token.len;
+        off = enc_consume!(buf, off; encode_u8, first);
+        off = enc_consume!(buf, off; encode_u8, self.code);
+        off = enc_consume!(buf, off; encode_u16, self.message_id);
+        off = enc_consume!(buf, off; encode_bytes, self.token.as_slice());
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<CoapHeader> {
+        let off = 0;
+        let (off, first) = dec_try!(buf, off; decode_u8);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        let (off, message_id) = dec_try!(buf, off; decode_u16);
+        stream_cond!(first >> 6 == VERSION);
+        let token_len = (first & 0xf) as usize;
+        stream_cond!(token_len <= MAX_TOKEN_LEN);
+        stream_len_cond!(buf, off + token_len);
+        // An empty message is only the header
+        stream_cond!(code != code::EMPTY || (token_len == 0 && buf.len() == off));
+        let token = stream_from_option!(Token::new(&buf[off..off + token_len]));
+        stream_done!(
+            off + token_len,
+            CoapHeader {
+                mtype: MessageType::from_bits(first >> 4),
+                code,
+                message_id,
+                token,
+            }
+        );
+    }
+}
+
+fn option_nibble(value: usize) -> (u8, usize) {
+    if value < 13 {
+        (value as u8, 0)
+    } else if value < 269 {
+        (13, 1)
+    } else {
+        (14, 2)
+    }
+}
+
+fn encode_option_ext(buf: &mut [u8], offset: usize, value: usize) -> SResult<usize> {
+    let off = match option_nibble(value) {
+        (13, _) => enc_consume!(buf, offset; encode_u8, (value - 13) as u8),
+        (14, _) => enc_consume!(buf, offset; encode_u16, (value - 269) as u16),
+        _ => offset,
+    };
+    stream_done!(off, off);
+}
+
+/// Encodes the option `number` with `value`, after an option numbered
+/// `prev`.
+pub fn encode_option(
+    buf: &mut [u8],
+    offset: usize,
+    prev: u16,
+    number: u16,
+    value: &[u8],
+) -> SResult<usize> {
+    stream_cond!(number >= prev && value.len() < 65805);
+    let delta = (number - prev) as usize;
+    let (delta_nibble, delta_ext) = option_nibble(delta);
+    let (len_nibble, len_ext) = option_nibble(value.len());
+    stream_len_cond!(buf, offset + 1 + delta_ext + len_ext + value.len());
+    let mut off = offset;
+    off = enc_consume!(buf, off; encode_u8, delta_nibble << 4 | len_nibble);
+    let (off, _) = enc_try!(encode_option_ext(buf, off, delta), 0);
+    let (off, _) = enc_try!(encode_option_ext(buf, off, value.len()), 0);
+    let off = enc_consume!(buf, off; encode_bytes, value);
+    stream_done!(off, off);
+}
+
+/// Encodes an option with an unsigned integer value in as few bytes as
+/// possible.
+pub fn encode_uint_option(
+    buf: &mut [u8],
+    offset: usize,
+    prev: u16,
+    number: u16,
+    value: u32,
+) -> SResult<usize> {
+    let bytes = value.to_be_bytes();
+    let skip = (value.leading_zeros() / 8) as usize;
+    encode_option(buf, offset, prev, number, &bytes[skip..])
+}
+
+/// Encodes a path such as `sensors/temp` as one Uri-Path option per
+/// segment. Empty segments are skipped.
+pub fn encode_uri_path(buf: &mut [u8], offset: usize, prev: u16, path: &[u8]) -> SResult<usize> {
+    let mut off = offset;
+    let mut prev = prev;
+    for segment in path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+        let (next, _) = enc_try!(encode_option(buf, off, prev, option::URI_PATH, segment), 0);
+        off = next;
+        prev = option::URI_PATH;
+    }
+    stream_done!(off, off);
+}
+
+/// Encodes the payload marker and `payload`, if it is not empty.
+pub fn encode_payload(buf: &mut [u8], offset: usize, payload: &[u8]) -> SResult<usize> {
+    if payload.is_empty() {
+        stream_done!(offset, offset);
+    }
+    stream_len_cond!(buf, offset + 1 + payload.len());
+    let mut off = offset;
+    off = enc_consume!(buf, off; encode_u8, PAYLOAD_MARKER);
+    off = enc_consume!(buf, off; encode_bytes, payload);
+    stream_done!(off, off);
+}
+
+/// Decodes an unsigned integer option value.
+pub fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+}
+
+/// The value of a Block1 or Block2 option.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Block {
+    /// The number of the block within the body
+    pub num: u32,
+    /// Whether more blocks follow
+    pub more: bool,
+    /// The size exponent: blocks are `2^(szx + 4)` bytes long
+    pub szx: u8,
+}
+
+impl Block {
+    pub const MAX_SZX: u8 = 6;
+
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// The offset of the block within the body.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    pub fn to_uint(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    /// Returns `None` for the reserved size exponent 7 and for values that
+    /// do not fit in 3 bytes.
+    pub fn from_uint(value: u32) -> Option<Block> {
+        let szx = (value & 0x7) as u8;
+        if szx > Block::MAX_SZX || value > 0xff_ffff {
+            return None;
+        }
+        Some(Block {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx,
+        })
+    }
+}
+
+/// A decoded message. The options and the payload borrow the buffer the
+/// message was decoded from.
+pub struct CoapMessage<'b> {
+    pub header: CoapHeader,
+    options: &'b [u8],
+    pub payload: &'b [u8],
+}
+
+impl<'b> CoapMessage<'b> {
+    /// Decodes `buf` and checks that its options are well-formed. Returns
+    /// `None` for malformed messages, which are silently ignored.
+    pub fn decode(buf: &'b [u8]) -> Option<CoapMessage<'b>> {
+        let (off, header) = CoapHeader::decode(buf).done()?;
+        let rest = &buf[off..];
+        let mut options = CoapOptions::new(rest);
+        while options.next().is_some() {}
+        if options.malformed {
+            return None;
+        }
+        let options_len = rest.len() - options.buf.len();
+        let payload = match options.buf.split_first() {
+            // A marker followed by an empty payload is a format error
+            Some((&PAYLOAD_MARKER, payload)) if !payload.is_empty() => payload,
+            None => &[],
+            _ => return None,
+        };
+        Some(CoapMessage {
+            header,
+            options: &rest[..options_len],
+            payload,
+        })
+    }
+
+    pub fn options(&self) -> CoapOptions<'b> {
+        CoapOptions::new(self.options)
+    }
+
+    /// The value of the first option numbered `number`.
+    pub fn option(&self, number: u16) -> Option<&'b [u8]> {
+        self.options()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value)
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(decode_uint)
+    }
+
+    /// The value of the Block1 or Block2 option. Malformed values are
+    /// treated as absent.
+    pub fn block(&self, number: u16) -> Option<Block> {
+        self.uint_option(number).and_then(Block::from_uint)
+    }
+
+    /// Whether the message carries a critical option that is not in
+    /// `known`.
+    pub fn has_unknown_critical(&self, known: &[u16]) -> bool {
+        self.options()
+            .any(|(n, _)| option::is_critical(n) && !known.contains(&n))
+    }
+
+    /// Whether the Uri-Path options of the message spell `path`, given in
+    /// the form `encode_uri_path` accepts.
+    pub fn path_matches(&self, path: &[u8]) -> bool {
+        let mut expected = path.split(|b| *b == b'/').filter(|s| !s.is_empty());
+        let mut segments = self
+            .options()
+            .filter(|(n, _)| *n == option::URI_PATH)
+            .map(|(_, value)| value);
+        loop {
+            match (expected.next(), segments.next()) {
+                (None, None) => return true,
+                (Some(e), Some(s)) if e == s => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Iterates over the options of a message, yielding the number and the
+/// value of each option. Iteration stops at the payload marker.
+pub struct CoapOptions<'b> {
+    buf: &'b [u8],
+    number: u16,
+    malformed: bool,
+}
+
+impl<'b> CoapOptions<'b> {
+    pub fn new(buf: &'b [u8]) -> CoapOptions<'b> {
+        CoapOptions {
+            buf,
+            number: 0,
+            malformed: false,
+        }
+    }
+
+    fn decode_ext(&mut self, nibble: u8) -> Option<usize> {
+        match nibble {
+            13 => {
+                let (off, ext) = decode_u8(self.buf).done()?;
+                self.buf = &self.buf[off..];
+                Some(ext as usize + 13)
+            }
+            14 => {
+                let (off, ext) = decode_u16(self.buf).done()?;
+                self.buf = &self.buf[off..];
+                Some(ext as usize + 269)
+            }
+            15 => None,
+            n => Some(n as usize),
+        }
+    }
+
+    fn decode_option(&mut self) -> Option<(u16, &'b [u8])> {
+        let first = self.buf[0];
+        self.buf = &self.buf[1..];
+        let delta = self.decode_ext(first >> 4)?;
+        let len = self.decode_ext(first & 0xf)?;
+        if len > self.buf.len() || self.number as usize + delta > u16::MAX as usize {
+            return None;
+        }
+        self.number += delta as u16;
+        let (value, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some((self.number, value))
+    }
+}
+
+impl<'b> Iterator for CoapOptions<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'b [u8])> {
+        match self.buf.first() {
+            None | Some(&PAYLOAD_MARKER) => return None,
+            Some(_) => {}
+        }
+        let option = self.decode_option();
+        if option.is_none() {
+            self.malformed = true;
+            self.buf = &[];
+        }
+        option
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let token = Token::new(&[0xde, 0xad]).unwrap();
+        let header = CoapHeader::new(MessageType::Confirmable, code::GET, 0x1234, token);
+        let mut buf = [0; 64];
+        let (off, _) = header.encode(&mut buf, 0).done().unwrap();
+        let (off, _) = encode_uri_path(&mut buf, off, 0, b"/sensors/temp")
+            .done()
+            .unwrap();
+        let block = Block {
+            num: 3,
+            more: false,
+            szx: 2,
+        };
+        let (off, _) = encode_uint_option(
+            &mut buf,
+            off,
+            option::URI_PATH,
+            option::BLOCK2,
+            block.to_uint(),
+        )
+        .done()
+        .unwrap();
+        let (off, _) = encode_payload(&mut buf, off, b"hi").done().unwrap();
+        assert_eq!(
+            buf[..off],
+            [
+                0x42, 0x01, 0x12, 0x34, 0xde, 0xad, // header and token
+                0xb7, b's', b'e', b'n', b's', b'o', b'r', b's', // Uri-Path
+                0x04, b't', b'e', b'm', b'p', // Uri-Path
+                0xc1, 0x32, // Block2
+                0xff, b'h', b'i', // payload
+            ]
+        );
+
+        let message = CoapMessage::decode(&buf[..off]).unwrap();
+        assert_eq!(message.header.mtype, MessageType::Confirmable);
+        assert_eq!(message.header.code, code::GET);
+        assert_eq!(message.header.message_id, 0x1234);
+        assert_eq!(message.header.token, token);
+        assert!(message.path_matches(b"sensors/temp"));
+        assert!(!message.path_matches(b"sensors"));
+        assert!(!message.path_matches(b"sensors/temp/x"));
+        assert_eq!(message.block(option::BLOCK2), Some(block));
+        assert_eq!(message.block(option::BLOCK1), None);
+        assert!(!message.has_unknown_critical(&[option::URI_PATH, option::BLOCK2]));
+        assert!(message.has_unknown_critical(&[option::URI_PATH]));
+        assert_eq!(message.payload, b"hi");
+    }
+
+    #[test]
+    fn extended_options() {
+        let mut buf = [0; 330];
+        let value = [0xaa; 300];
+        let (off, _) = encode_option(&mut buf, 0, 0, option::URI_PATH, b"0123456789abcdef")
+            .done()
+            .unwrap();
+        // 16 byte value: 1 byte extended length
+        assert_eq!(buf[..2], [0xbd, 3]);
+        let (end, _) = encode_option(&mut buf, off, option::URI_PATH, option::SIZE1 + 300, &value)
+            .done()
+            .unwrap();
+        // Delta 349 and length 300: 2 byte extended delta and length
+        assert_eq!(buf[off..off + 5], [0xee, 0, 80, 0, 31]);
+
+        let mut options = CoapOptions::new(&buf[..end]);
+        assert_eq!(options.next().unwrap().0, option::URI_PATH);
+        let (number, decoded) = options.next().unwrap();
+        assert_eq!(number, option::SIZE1 + 300);
+        assert_eq!(decoded, &value[..]);
+        assert!(options.next().is_none());
+    }
+
+    #[test]
+    fn uint_options() {
+        let mut buf = [0; 8];
+        let (off, _) = encode_uint_option(&mut buf, 0, 0, option::CONTENT_FORMAT, 0)
+            .done()
+            .unwrap();
+        assert_eq!(buf[..off], [0xc0]);
+        let (off, _) = encode_uint_option(&mut buf, 0, 0, option::CONTENT_FORMAT, 60)
+            .done()
+            .unwrap();
+        assert_eq!(buf[..off], [0xc1, 60]);
+        assert_eq!(decode_uint(&[]), Some(0));
+        assert_eq!(decode_uint(&[1, 0]), Some(256));
+        assert_eq!(decode_uint(&[1, 0, 0, 0, 0]), None);
+
+        let block = Block::from_uint(0x1234e).unwrap();
+        assert_eq!(block.num, 0x1234);
+        assert!(block.more);
+        assert_eq!(block.size(), 1024);
+        assert_eq!(block.offset(), 0x1234 * 1024);
+        assert_eq!(Block::from_uint(0x17), None);
+    }
+
+    #[test]
+    fn malformed_messages() {
+        // Empty ACK
+        let message = CoapMessage::decode(&[0x60, 0x00, 0x12, 0x34]).unwrap();
+        assert_eq!(message.header.mtype, MessageType::Acknowledgement);
+        assert_eq!(message.header.code, code::EMPTY);
+        // Wrong version
+        assert!(CoapMessage::decode(&[0xa0, 0x00, 0x12, 0x34]).is_none());
+        // Token longer than 8 bytes
+        assert!(CoapMessage::decode(&[0x49, 0x01, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).is_none());
+        // Empty message with a token
+        assert!(CoapMessage::decode(&[0x61, 0x00, 0x12, 0x34, 0x01]).is_none());
+        // Payload marker without payload
+        assert!(CoapMessage::decode(&[0x40, 0x01, 0x12, 0x34, 0xff]).is_none());
+        // Reserved length nibble
+        assert!(CoapMessage::decode(&[0x40, 0x01, 0x12, 0x34, 0xbf, 0x00]).is_none());
+        // Truncated option value
+        assert!(CoapMessage::decode(&[0x40, 0x01, 0x12, 0x34, 0xb2, b'a']).is_none());
+    }
+}
@@ -0,0 +1,1361 @@
+//! CoAP userspace interface.
+//!
+//! Lets processes act as CoAP (RFC 7252) clients and servers without
+//! carrying their own CoAP implementation. The driver is bound to the CoAP
+//! port and takes care of encoding and decoding messages, retransmitting
+//! confirmable requests, matching responses to requests by token and
+//! splitting bodies that do not fit in one message into blocks (RFC 7959).
+//!
+//! As a client, a process allows the destination address, the path of the
+//! resource and the request body, and sends a request. A request body that
+//! does not fit in one message is sent block by block (Block1), and the
+//! blocks of a block-wise response (Block2) are requested until the whole
+//! response body has been copied into the process's response buffer. One
+//! request is outstanding at a time; requests of other processes are
+//! queued.
+//!
+//! As a server, a process registers resources by path. Requests for a
+//! registered resource are delivered to the process together with the
+//! request body, which is reassembled first if it was sent block-wise. The
+//! process responds with a code and the full representation of the
+//! resource, and the driver sends the block of it the client asked for.
+//! Responses to confirmable requests are piggybacked on the
+//! acknowledgement, so a process has to respond within
+//! `SERVER_TIMEOUT_MS`; after that, the driver responds with 5.03 (Service
+//! Unavailable). One request is delivered at a time, requests that arrive
+//! in the meantime are dropped and retransmitted by their client. Requests
+//! for paths that are not registered are answered with 4.04 (Not Found).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let coap = static_init!(
+//!     capsules::net::coap::CoapDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::net::coap::CoapDriver::new(
+//!         udp_send,
+//!         udp_recv,
+//!         udp_port_table,
+//!         coap_alarm,
+//!         client_buf,
+//!         server_buf,
+//!         net_cap,
+//!         board_kernel.create_grant(capsules::net::coap::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! udp_send.set_client(coap);
+//! udp_recv.set_client(coap);
+//! coap_alarm.set_alarm_client(coap);
+//! coap.start().unwrap();
+//! ```
+
+use crate::net::coap::coap::{code, option, Block, CoapHeader, CoapMessage, MessageType, Token};
+use crate::net::coap::coap::{encode_uint_option, encode_uri_path, COAP_PORT, MAX_TOKEN_LEN};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_port_table::UdpPortManager;
+use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const DEST: usize = 0;
+    pub const PATH: usize = 1;
+    pub const REQUEST: usize = 2;
+    pub const RESPONSE: usize = 3;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 4;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const RESPONSE: usize = 0;
+    pub const REQUEST: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const RESPONSE: usize = 0;
+    pub const REQUEST: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// How long a process has to respond to a request.
+pub const SERVER_TIMEOUT_MS: u32 = 1000;
+
+/// How long a client waits for the response to a non-confirmable request,
+/// or for a separate response once its request has been acknowledged.
+pub const RESPONSE_TIMEOUT_MS: u32 = 10000;
+
+/// Maximum length of the path of a resource, such as `sensors/temp`.
+pub const MAX_PATH_LEN: usize = 32;
+
+/// The number of resources each process can register.
+pub const MAX_RESOURCES: usize = 4;
+
+/// Transmission parameters (RFC 7252, section 4.8)
+const ACK_TIMEOUT_MS: u32 = 2000;
+const MAX_RETRANSMIT: u8 = 4;
+
+const TOKEN_LEN: usize = 4;
+
+/// Space needed in a response besides the body: the header, the token, the
+/// Content-Format, Block2 and Block1 options and the payload marker.
+const RESPONSE_OVERHEAD: usize = 4 + MAX_TOKEN_LEN + 3 + 4 + 4 + 1;
+
+/// Critical options a request may carry.
+const KNOWN_OPTIONS: [u16; 6] = [
+    option::URI_HOST,
+    option::URI_PORT,
+    option::URI_PATH,
+    option::ACCEPT,
+    option::BLOCK2,
+    option::BLOCK1,
+];
+
+/// Space needed in a request besides the body. Each Uri-Path segment is at
+/// least one byte long and its option header at most two bytes long.
+fn request_overhead(path_len: usize) -> usize {
+    4 + TOKEN_LEN + 2 * path_len + 1 + 4 + 4 + 1
+}
+
+/// The largest block size exponent for blocks that fit in `room` bytes.
+fn block_szx(room: usize) -> Option<u8> {
+    (0..=Block::MAX_SZX).rev().find(|szx| 16 << szx <= room)
+}
+
+fn encode_body(
+    buf: &mut [u8],
+    offset: usize,
+    body: &ReadableProcessSlice,
+) -> Result<usize, ErrorCode> {
+    if body.len() == 0 {
+        return Ok(offset);
+    }
+    let end = offset + 1 + body.len();
+    if end > buf.len() {
+        return Err(ErrorCode::SIZE);
+    }
+    buf[offset] = 0xff;
+    body.copy_to_slice(&mut buf[offset + 1..end]);
+    Ok(end)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct Peer {
+    addr: IPAddr,
+    port: u16,
+}
+
+/// A resource path, with empty segments removed.
+#[derive(Copy, Clone)]
+struct Path {
+    len: usize,
+    bytes: [u8; MAX_PATH_LEN],
+}
+
+impl Path {
+    fn new(raw: &[u8]) -> Path {
+        let mut path = Path {
+            len: 0,
+            bytes: [0; MAX_PATH_LEN],
+        };
+        for segment in raw.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            if path.len > 0 {
+                path.bytes[path.len] = b'/';
+                path.len += 1;
+            }
+            path.bytes[path.len..path.len + segment.len()].copy_from_slice(segment);
+            path.len += segment.len();
+        }
+        path
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+fn read_path(kernel_data: &GrantKernelData) -> Result<Path, ErrorCode> {
+    kernel_data
+        .get_readonly_processbuffer(ro_allow::PATH)
+        .and_then(|path| {
+            path.enter(|path| {
+                let mut raw = [0; MAX_PATH_LEN];
+                if path.len() > MAX_PATH_LEN {
+                    return Err(ErrorCode::SIZE);
+                }
+                path.copy_to_slice(&mut raw[..path.len()]);
+                Ok(Path::new(&raw[..path.len()]))
+            })
+        })
+        .unwrap_or(Ok(Path::new(&[])))
+}
+
+#[derive(Copy, Clone)]
+struct Request {
+    code: u8,
+    confirmable: bool,
+    port: u16,
+}
+
+#[derive(Default)]
+pub struct App {
+    request: Option<Request>,
+    resources: [Option<Path>; MAX_RESOURCES],
+}
+
+/// The request of a process that is being sent.
+#[derive(Copy, Clone)]
+struct Exchange {
+    appid: ProcessId,
+    peer: Peer,
+    request: Request,
+    token: Token,
+    message_id: u16,
+    body_len: usize,
+    /// The block of the request body the current message carries, if the
+    /// body is sent block-wise
+    block1: Option<Block>,
+    /// The block of the response body the current message asks for
+    block2: Option<Block>,
+    /// The length of the response body received so far
+    received: usize,
+    /// Whether the current message has been acknowledged; always true for
+    /// non-confirmable requests
+    acked: bool,
+    retransmissions: u8,
+    timeout_ms: u32,
+}
+
+/// A response of the server, either from a process or from the driver.
+#[derive(Copy, Clone)]
+struct Response {
+    peer: Peer,
+    /// Type and message ID of the request
+    request_type: MessageType,
+    message_id: u16,
+    token: Token,
+    code: u8,
+    content_format: Option<u16>,
+    /// Acknowledges a block of the request body
+    block1: Option<Block>,
+    /// The process whose response buffer holds the body, and the block of
+    /// the body the client asked for
+    body: Option<(ProcessId, Option<Block>)>,
+}
+
+impl Response {
+    fn new(peer: Peer, request: &CoapHeader, multicast: bool) -> Response {
+        Response {
+            peer,
+            // Responses to multicast requests are never piggybacked
+            request_type: if multicast {
+                MessageType::NonConfirmable
+            } else {
+                request.mtype
+            },
+            message_id: request.message_id,
+            token: request.token,
+            code: code::EMPTY,
+            content_format: None,
+            block1: None,
+            body: None,
+        }
+    }
+}
+
+/// A request that has been delivered to a process.
+#[derive(Copy, Clone)]
+struct Incoming {
+    appid: ProcessId,
+    block2: Option<Block>,
+    multicast: bool,
+    response: Response,
+}
+
+/// A request body that is being received block-wise.
+#[derive(Copy, Clone)]
+struct Upload {
+    peer: Peer,
+    appid: ProcessId,
+    resource: usize,
+    next: Block,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Buffer {
+    Client,
+    Server,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ServerTx {
+    Idle,
+    /// Encode and send `response`
+    Build,
+    /// Send the last response again, for a duplicate request
+    Resend,
+}
+
+pub struct CoapDriver<'a, A: time::Alarm<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    udp_receiver: &'a UDPReceiver<'a>,
+    port_table: &'static UdpPortManager,
+    alarm: &'a A,
+    net_cap: &'static NetworkCapability,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    client_buf: MapCell<LeasableBuffer<'static, u8>>,
+    client_buf_len: usize,
+    server_buf: MapCell<LeasableBuffer<'static, u8>>,
+    server_buf_len: usize,
+    /// The buffer the UDP sender holds.
+    in_flight: OptionalCell<Buffer>,
+    message_id: Cell<u16>,
+    token_counter: Cell<u16>,
+
+    // Client state
+    exchange: OptionalCell<Exchange>,
+    /// Whether the current message of `exchange` has to be sent.
+    client_tx: Cell<bool>,
+    /// Empty acknowledgement to send for a separate response.
+    client_ack: OptionalCell<(Peer, u16)>,
+    client_timer: OptionalCell<(A::Ticks, A::Ticks)>,
+
+    // Server state
+    incoming: OptionalCell<Incoming>,
+    upload: OptionalCell<Upload>,
+    response: OptionalCell<Response>,
+    server_tx: Cell<ServerTx>,
+    /// The peer and the message ID of the request the response in the
+    /// server buffer acknowledges.
+    last_response: OptionalCell<(Peer, u16)>,
+    server_dest: Cell<Peer>,
+    server_len: Cell<usize>,
+    server_timer: OptionalCell<(A::Ticks, A::Ticks)>,
+}
+
+impl<'a, A: time::Alarm<'a>> CoapDriver<'a, A> {
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        udp_receiver: &'a UDPReceiver<'a>,
+        port_table: &'static UdpPortManager,
+        alarm: &'a A,
+        client_buf: &'static mut [u8],
+        server_buf: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> CoapDriver<'a, A> {
+        let client_buf_len = client_buf.len();
+        let server_buf_len = server_buf.len();
+        CoapDriver {
+            udp_sender: udp_sender,
+            udp_receiver: udp_receiver,
+            port_table: port_table,
+            alarm: alarm,
+            net_cap: net_cap,
+            apps: grant,
+            client_buf: MapCell::new(LeasableBuffer::new(client_buf)),
+            client_buf_len: client_buf_len,
+            server_buf: MapCell::new(LeasableBuffer::new(server_buf)),
+            server_buf_len: server_buf_len,
+            in_flight: OptionalCell::empty(),
+            message_id: Cell::new(0),
+            token_counter: Cell::new(0),
+            exchange: OptionalCell::empty(),
+            client_tx: Cell::new(false),
+            client_ack: OptionalCell::empty(),
+            client_timer: OptionalCell::empty(),
+            incoming: OptionalCell::empty(),
+            upload: OptionalCell::empty(),
+            response: OptionalCell::empty(),
+            server_tx: Cell::new(ServerTx::Idle),
+            last_response: OptionalCell::empty(),
+            server_dest: Cell::new(Peer {
+                addr: IPAddr::new(),
+                port: 0,
+            }),
+            server_len: Cell::new(0),
+            server_timer: OptionalCell::empty(),
+        }
+    }
+
+    /// Binds the CoAP port.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.udp_receiver.is_bound() {
+            return Err(ErrorCode::ALREADY);
+        }
+        let socket = self
+            .port_table
+            .create_socket()
+            .map_err(|_| ErrorCode::NOMEM)?;
+        let (send_binding, recv_binding) = self
+            .port_table
+            .bind(socket, COAP_PORT, self.net_cap)
+            .map_err(|_| ErrorCode::BUSY)?;
+        self.udp_sender.set_binding(send_binding);
+        self.udp_receiver.set_binding(recv_binding);
+
+        // Start from a different message ID and token after each reboot
+        let now = self.alarm.now().into_u32();
+        self.message_id.set(now as u16);
+        self.token_counter.set((now >> 16) as u16);
+        Ok(())
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let message_id = self.message_id.get().wrapping_add(1);
+        self.message_id.set(message_id);
+        message_id
+    }
+
+    fn next_token(&self) -> Token {
+        let counter = self.token_counter.get().wrapping_add(1);
+        self.token_counter.set(counter);
+        let now = self.alarm.now().into_u32() as u16;
+        let mut bytes = [0; TOKEN_LEN];
+        bytes[..2].copy_from_slice(&counter.to_be_bytes());
+        bytes[2..].copy_from_slice(&now.to_be_bytes());
+        Token::new(&bytes).unwrap_or(Token::EMPTY)
+    }
+
+    fn set_timer(&self, timer: &OptionalCell<(A::Ticks, A::Ticks)>, ms: u32) {
+        timer.set((self.alarm.now(), self.alarm.ticks_from_ms(ms)));
+        self.arm();
+    }
+
+    fn expired(&self, timer: &OptionalCell<(A::Ticks, A::Ticks)>) -> bool {
+        timer.map_or(false, |&mut (reference, dt)| {
+            self.alarm.now().wrapping_sub(reference) >= dt
+        })
+    }
+
+    /// Sets the alarm for the earlier of the client and the server timer.
+    fn arm(&self) {
+        let now = self.alarm.now();
+        let remaining = |timer: &OptionalCell<(A::Ticks, A::Ticks)>| {
+            timer.map(|&mut (reference, dt)| {
+                let elapsed = now.wrapping_sub(reference);
+                if elapsed >= dt {
+                    A::Ticks::from(0)
+                } else {
+                    dt.wrapping_sub(elapsed)
+                }
+            })
+        };
+        let next = match (remaining(&self.client_timer), remaining(&self.server_timer)) {
+            (Some(client), Some(server)) => Some(cmp::min(client, server)),
+            (client, server) => client.or(server),
+        };
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Sends the messages that are waiting for a buffer, one at a time.
+    fn transmit(&self) {
+        while self.in_flight.is_none() {
+            if let Some((peer, message_id)) = self.client_ack.take() {
+                let _ = self.send(Buffer::Client, peer, |buf| {
+                    CoapHeader::new(
+                        MessageType::Acknowledgement,
+                        code::EMPTY,
+                        message_id,
+                        Token::EMPTY,
+                    )
+                    .encode(buf, 0)
+                    .done()
+                    .map(|(off, _)| off)
+                    .ok_or(ErrorCode::SIZE)
+                });
+            } else if self.client_tx.replace(false) {
+                if let Some(ex) = self.exchange.extract() {
+                    let result =
+                        self.send(Buffer::Client, ex.peer, |buf| self.encode_request(&ex, buf));
+                    if let Err(e) = result {
+                        self.finish(Err(e), 0, 0);
+                    }
+                }
+            } else if self.server_tx.get() != ServerTx::Idle {
+                match (self.server_tx.replace(ServerTx::Idle), self.response.take()) {
+                    (ServerTx::Build, Some(response)) => {
+                        let result = self.send(Buffer::Server, response.peer, |buf| {
+                            self.encode_response(&response, buf)
+                        });
+                        if result.is_ok() && response.request_type == MessageType::Confirmable {
+                            self.last_response.set((response.peer, response.message_id));
+                        } else {
+                            self.last_response.clear();
+                        }
+                    }
+                    (ServerTx::Resend, _) => {
+                        let len = self.server_len.get();
+                        let _ = self.send(Buffer::Server, self.server_dest.get(), |_| Ok(len));
+                    }
+                    _ => {}
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    /// Encodes a message into `which` and sends it to `peer`. Only errors
+    /// of `encode` are returned, messages the UDP stack fails to send are
+    /// handled like lost messages.
+    fn send<F>(&self, which: Buffer, peer: Peer, encode: F) -> Result<(), ErrorCode>
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize, ErrorCode>,
+    {
+        let cell = match which {
+            Buffer::Client => &self.client_buf,
+            Buffer::Server => &self.server_buf,
+        };
+        let mut buf = cell.take().ok_or(ErrorCode::BUSY)?;
+        buf.reset();
+        let len = match encode(&mut buf[..]) {
+            Ok(len) => len,
+            Err(e) => {
+                cell.replace(buf);
+                return Err(e);
+            }
+        };
+        if which == Buffer::Server {
+            self.server_dest.set(peer);
+            self.server_len.set(len);
+        }
+        buf.slice(0..len);
+        match self
+            .udp_sender
+            .send_to(peer.addr, peer.port, buf, self.net_cap)
+        {
+            Ok(()) => self.in_flight.set(which),
+            Err(mut buf) => {
+                buf.reset();
+                cell.replace(buf);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies `data` to `offset` in a read-write buffer of `appid`.
+    fn copy_to_process(
+        &self,
+        appid: ProcessId,
+        allow_num: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(allow_num)
+                    .and_then(|buf| {
+                        buf.mut_enter(|buf| {
+                            if offset + data.len() > buf.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            buf[offset..offset + data.len()].copy_from_slice(data);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(if data.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(ErrorCode::SIZE)
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    // Client
+
+    fn enqueue(&self, request: Request, appid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() || self.exchange.map_or(false, |ex| ex.appid == appid) {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    app.request = Some(request);
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        if self.exchange.is_none() {
+            // Errors of the request that was just queued are returned
+            // synchronously
+            let result = self.start_request(appid);
+            if result.is_err() {
+                self.start_next();
+            }
+            result
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Starts sending the queued request of `appid`.
+    fn start_request(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        let ex = self
+            .apps
+            .enter(appid, |app, kernel_data| {
+                let request = app.request.take().ok_or(ErrorCode::FAIL)?;
+
+                let addr = kernel_data
+                    .get_readonly_processbuffer(ro_allow::DEST)
+                    .and_then(|dest| {
+                        dest.enter(|dest| {
+                            let mut addr = IPAddr::new();
+                            if dest.len() != addr.0.len() {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            dest.copy_to_slice(&mut addr.0);
+                            Ok(addr)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                let path = read_path(kernel_data)?;
+                let body_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::REQUEST)
+                    .and_then(|body| body.enter(|body| body.len()))
+                    .unwrap_or(0);
+
+                // Bodies that do not fit in one message are sent block-wise
+                let room = self
+                    .client_buf_len
+                    .saturating_sub(request_overhead(path.len));
+                let szx = block_szx(room).ok_or(ErrorCode::SIZE)?;
+                let block1 = if body_len > room {
+                    Some(Block {
+                        num: 0,
+                        more: true,
+                        szx,
+                    })
+                } else {
+                    None
+                };
+                // Multicast requests cannot be acknowledged, nor continued
+                if addr.is_multicast() && (request.confirmable || block1.is_some()) {
+                    return Err(ErrorCode::INVAL);
+                }
+
+                Ok(Exchange {
+                    appid,
+                    peer: Peer {
+                        addr,
+                        port: request.port,
+                    },
+                    request,
+                    token: self.next_token(),
+                    message_id: 0,
+                    body_len,
+                    block1,
+                    block2: None,
+                    received: 0,
+                    acked: false,
+                    retransmissions: 0,
+                    timeout_ms: 0,
+                })
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.send_request(ex);
+        Ok(())
+    }
+
+    /// Sends the next message of an exchange.
+    fn send_request(&self, mut ex: Exchange) {
+        ex.message_id = self.next_message_id();
+        ex.acked = !ex.request.confirmable;
+        ex.retransmissions = 0;
+        ex.timeout_ms = if ex.request.confirmable {
+            // Randomized between ACK_TIMEOUT and 1.5 times that
+            ACK_TIMEOUT_MS + self.alarm.now().into_u32() % (ACK_TIMEOUT_MS / 2)
+        } else {
+            RESPONSE_TIMEOUT_MS
+        };
+        self.exchange.set(ex);
+        self.set_timer(&self.client_timer, ex.timeout_ms);
+        self.client_tx.set(true);
+        self.transmit();
+    }
+
+    fn encode_request(&self, ex: &Exchange, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(ex.appid, |_, kernel_data| {
+                let path = read_path(kernel_data)?;
+                let mtype = if ex.request.confirmable {
+                    MessageType::Confirmable
+                } else {
+                    MessageType::NonConfirmable
+                };
+                let header = CoapHeader::new(mtype, ex.request.code, ex.message_id, ex.token);
+                let (off, _) = header.encode(buf, 0).done().ok_or(ErrorCode::SIZE)?;
+                let (off, _) = encode_uri_path(buf, off, 0, path.as_slice())
+                    .done()
+                    .ok_or(ErrorCode::SIZE)?;
+
+                if let Some(block2) = ex.block2 {
+                    // Requests for the following blocks of the response
+                    // carry no body
+                    let (off, _) = encode_uint_option(
+                        buf,
+                        off,
+                        option::URI_PATH,
+                        option::BLOCK2,
+                        block2.to_uint(),
+                    )
+                    .done()
+                    .ok_or(ErrorCode::SIZE)?;
+                    return Ok(off);
+                }
+
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::REQUEST)
+                    .and_then(|body| {
+                        body.enter(|body| {
+                            let body = &body[..cmp::min(ex.body_len, body.len())];
+                            match ex.block1 {
+                                Some(block1) => {
+                                    let start = cmp::min(block1.offset(), body.len());
+                                    let end = cmp::min(start + block1.size(), body.len());
+                                    let block1 = Block {
+                                        more: end < ex.body_len,
+                                        ..block1
+                                    };
+                                    let (off, _) = encode_uint_option(
+                                        buf,
+                                        off,
+                                        option::URI_PATH,
+                                        option::BLOCK1,
+                                        block1.to_uint(),
+                                    )
+                                    .done()
+                                    .ok_or(ErrorCode::SIZE)?;
+                                    encode_body(buf, off, &body[start..end])
+                                }
+                                None => encode_body(buf, off, body),
+                            }
+                        })
+                    })
+                    .unwrap_or(Ok(off))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn client_timeout(&self) {
+        if let Some(mut ex) = self.exchange.extract() {
+            if !ex.acked && ex.retransmissions < MAX_RETRANSMIT {
+                ex.retransmissions += 1;
+                ex.timeout_ms *= 2;
+                self.exchange.set(ex);
+                self.set_timer(&self.client_timer, ex.timeout_ms);
+                self.client_tx.set(true);
+                self.transmit();
+            } else {
+                self.finish(Err(ErrorCode::NOACK), 0, 0);
+            }
+        }
+    }
+
+    /// Handles acknowledgements, resets and responses.
+    fn receive_response(&self, peer: Peer, message: &CoapMessage) {
+        let header = message.header;
+        if header.mtype == MessageType::Confirmable && code::is_response(header.code) {
+            // Acknowledge separate responses, including duplicates of ones
+            // that have already been handled
+            self.client_ack.set((peer, header.message_id));
+            self.transmit();
+        }
+
+        let mut ex = match self.exchange.extract() {
+            Some(ex) => ex,
+            None => return,
+        };
+        // Any node may respond to a multicast request
+        if peer != ex.peer && !ex.peer.addr.is_multicast() {
+            return;
+        }
+        match header.mtype {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                if header.message_id != ex.message_id {
+                    return;
+                }
+                if header.mtype == MessageType::Reset {
+                    self.finish(Err(ErrorCode::FAIL), 0, 0);
+                    return;
+                }
+                ex.acked = true;
+                self.exchange.set(ex);
+                if header.code == code::EMPTY {
+                    // The response will be sent separately
+                    self.set_timer(&self.client_timer, RESPONSE_TIMEOUT_MS);
+                    return;
+                }
+            }
+            _ => {}
+        }
+        if code::is_response(header.code) && header.token == ex.token {
+            self.handle_response(ex, message);
+        }
+    }
+
+    fn handle_response(&self, mut ex: Exchange, message: &CoapMessage) {
+        let code = message.header.code;
+        if message.has_unknown_critical(&[option::BLOCK2, option::BLOCK1]) {
+            self.finish(Err(ErrorCode::FAIL), code, 0);
+            return;
+        }
+
+        if let Some(sent) = ex.block1 {
+            if code == code::CONTINUE {
+                let end = sent.offset() + sent.size();
+                if end >= ex.body_len {
+                    self.finish(Err(ErrorCode::FAIL), code, 0);
+                    return;
+                }
+                // The server may ask for smaller blocks
+                let szx = message
+                    .block(option::BLOCK1)
+                    .map_or(sent.szx, |block1| cmp::min(block1.szx, sent.szx));
+                ex.block1 = Some(Block {
+                    num: (end >> (szx + 4)) as u32,
+                    more: true,
+                    szx,
+                });
+                self.send_request(ex);
+                return;
+            }
+            // The request body is complete, or the server gave up on it
+            ex.block1 = None;
+        }
+
+        let block2 = message.block(option::BLOCK2);
+        let offset = block2.map_or(0, |block2| block2.offset());
+        if offset != ex.received && offset != 0 {
+            self.finish(Err(ErrorCode::FAIL), code, ex.received);
+            return;
+        }
+        let len = offset + message.payload.len();
+        if let Err(e) = self.copy_to_process(ex.appid, rw_allow::RESPONSE, offset, message.payload)
+        {
+            self.finish(Err(e), code, len);
+            return;
+        }
+        match block2 {
+            Some(block2) if block2.more => {
+                ex.received = len;
+                ex.block2 = Some(Block {
+                    num: block2.num + 1,
+                    more: false,
+                    szx: block2.szx,
+                });
+                self.send_request(ex);
+            }
+            _ => self.finish(Ok(()), code, len),
+        }
+    }
+
+    /// Completes the outstanding request.
+    fn finish(&self, result: Result<(), ErrorCode>, code: u8, len: usize) {
+        self.client_timer.clear();
+        self.client_tx.set(false);
+        self.exchange.take().map(|ex| {
+            self.notify_response(ex.appid, result, code, len);
+        });
+        self.start_next();
+    }
+
+    /// Starts the next queued request, if any. Errors are reported to the
+    /// owning process through its upcall.
+    fn start_next(&self) {
+        while self.exchange.is_none() {
+            let next = self.apps.iter().find_map(|app| {
+                let appid = app.processid();
+                app.enter(|app, _| app.request.map(|_| appid))
+            });
+            match next {
+                Some(appid) => {
+                    if let Err(e) = self.start_request(appid) {
+                        self.notify_response(appid, Err(e), 0, 0);
+                    }
+                }
+                None => return,
+            }
+        }
+    }
+
+    fn notify_response(
+        &self,
+        appid: ProcessId,
+        result: Result<(), ErrorCode>,
+        code: u8,
+        len: usize,
+    ) {
+        let _ = self.apps.enter(appid, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(
+                    upcall::RESPONSE,
+                    (into_statuscode(result), code as usize, len),
+                )
+                .ok();
+        });
+    }
+
+    // Server
+
+    fn register(&self, appid: ProcessId) -> Result<usize, ErrorCode> {
+        let path = self
+            .apps
+            .enter(appid, |_, kernel_data| read_path(kernel_data))
+            .unwrap_or_else(|err| Err(err.into()))?;
+        let registered = self.apps.iter().any(|app| {
+            app.enter(|app, _| {
+                app.resources
+                    .iter()
+                    .flatten()
+                    .any(|resource| resource.as_slice() == path.as_slice())
+            })
+        });
+        if registered {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let slot = app
+                    .resources
+                    .iter()
+                    .position(|resource| resource.is_none())
+                    .ok_or(ErrorCode::NOMEM)?;
+                app.resources[slot] = Some(path);
+                Ok(slot)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn unregister(&self, appid: ProcessId, resource: usize) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| {
+                match app.resources.get_mut(resource).and_then(|slot| slot.take()) {
+                    Some(_) => Ok(()),
+                    None => Err(ErrorCode::INVAL),
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn find_resource(&self, message: &CoapMessage) -> Option<(ProcessId, usize)> {
+        self.apps.iter().find_map(|app| {
+            let appid = app.processid();
+            app.enter(|app, _| {
+                app.resources.iter().position(|resource| {
+                    resource.map_or(false, |path| message.path_matches(path.as_slice()))
+                })
+            })
+            .map(|resource| (appid, resource))
+        })
+    }
+
+    fn queue_response(&self, response: Response) {
+        self.response.set(response);
+        self.server_tx.set(ServerTx::Build);
+        self.transmit();
+    }
+
+    fn receive_request(&self, peer: Peer, multicast: bool, message: &CoapMessage) {
+        let header = message.header;
+        if header.mtype != MessageType::Confirmable && header.mtype != MessageType::NonConfirmable {
+            return;
+        }
+        let duplicate = |(p, message_id): (Peer, u16)| p == peer && message_id == header.message_id;
+        if self.incoming.map_or(false, |incoming| {
+            duplicate((incoming.response.peer, incoming.response.message_id))
+        }) {
+            return;
+        }
+        if header.mtype == MessageType::Confirmable
+            && self.last_response.map_or(false, |last| duplicate(*last))
+        {
+            // The response was lost
+            if self.server_tx.get() == ServerTx::Idle && self.response.is_none() {
+                self.server_tx.set(ServerTx::Resend);
+                self.transmit();
+            }
+            return;
+        }
+        // One request at a time
+        if self.incoming.is_some() || self.response.is_some() {
+            return;
+        }
+
+        let mut response = Response::new(peer, &header, multicast);
+        // Errors are not reported to multicast requests
+        let reply = move |code| {
+            if !multicast {
+                self.queue_response(Response { code, ..response });
+            }
+        };
+        if message.has_unknown_critical(&KNOWN_OPTIONS) {
+            reply(code::BAD_OPTION);
+            return;
+        }
+        let (appid, resource) = match self.find_resource(message) {
+            Some(found) => found,
+            None => {
+                reply(code::NOT_FOUND);
+                return;
+            }
+        };
+
+        let offset = match message.block(option::BLOCK1) {
+            Some(block1) => {
+                let expected = self
+                    .upload
+                    .take()
+                    .filter(|upload| {
+                        upload.peer == peer
+                            && upload.appid == appid
+                            && upload.resource == resource
+                            && upload.next.szx == block1.szx
+                    })
+                    .map_or(0, |upload| upload.next.num);
+                if block1.num != expected {
+                    reply(code::REQUEST_ENTITY_INCOMPLETE);
+                    return;
+                }
+                if block1.more && message.payload.len() != block1.size() {
+                    reply(code::BAD_REQUEST);
+                    return;
+                }
+                response.block1 = Some(block1);
+                block1.offset()
+            }
+            None => 0,
+        };
+        let reply = move |code| {
+            if !multicast {
+                self.queue_response(Response { code, ..response });
+            }
+        };
+        if self
+            .copy_to_process(appid, rw_allow::REQUEST, offset, message.payload)
+            .is_err()
+        {
+            reply(code::REQUEST_ENTITY_TOO_LARGE);
+            return;
+        }
+        if let Some(block1) = response.block1 {
+            if block1.more {
+                self.upload.set(Upload {
+                    peer,
+                    appid,
+                    resource,
+                    next: Block {
+                        num: block1.num + 1,
+                        more: false,
+                        szx: block1.szx,
+                    },
+                });
+                reply(code::CONTINUE);
+                return;
+            }
+        }
+
+        self.incoming.set(Incoming {
+            appid,
+            block2: message.block(option::BLOCK2),
+            multicast,
+            response,
+        });
+        self.set_timer(&self.server_timer, SERVER_TIMEOUT_MS);
+        let len = offset + message.payload.len();
+        let _ = self.apps.enter(appid, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall::REQUEST, (resource, header.code as usize, len))
+                .ok();
+        });
+    }
+
+    fn respond(
+        &self,
+        appid: ProcessId,
+        code: u8,
+        content_format: Option<u16>,
+    ) -> Result<(), ErrorCode> {
+        if !code::is_response(code) {
+            return Err(ErrorCode::INVAL);
+        }
+        let incoming = match self.incoming.extract() {
+            Some(incoming) if incoming.appid == appid => incoming,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        self.incoming.clear();
+        self.server_timer.clear();
+        self.queue_response(Response {
+            code,
+            content_format,
+            body: Some((appid, incoming.block2)),
+            ..incoming.response
+        });
+        Ok(())
+    }
+
+    fn server_timeout(&self) {
+        self.incoming.take().map(|incoming| {
+            if !incoming.multicast {
+                self.queue_response(Response {
+                    code: code::SERVICE_UNAVAILABLE,
+                    ..incoming.response
+                });
+            }
+        });
+    }
+
+    fn encode_response(&self, response: &Response, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let empty: &[u8] = &[];
+        match response.body {
+            Some((appid, _)) => self
+                .apps
+                .enter(appid, |_, kernel_data| {
+                    let mut result = Err(ErrorCode::FAIL);
+                    let entered = kernel_data
+                        .get_readonly_processbuffer(ro_allow::RESPONSE)
+                        .and_then(|body| {
+                            body.enter(|body| {
+                                result = self.encode_response_body(response, buf, body);
+                            })
+                        });
+                    match entered {
+                        Ok(()) => result,
+                        Err(_) => self.encode_response_body(response, buf, empty.into()),
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            None => self.encode_response_body(response, buf, empty.into()),
+        }
+    }
+
+    fn encode_response_body(
+        &self,
+        response: &Response,
+        buf: &mut [u8],
+        body: &ReadableProcessSlice,
+    ) -> Result<usize, ErrorCode> {
+        let mut code = response.code;
+        let mut block2 = None;
+        let mut range = 0..body.len();
+        if let Some((_, requested)) = response.body {
+            let room = self.server_buf_len.saturating_sub(RESPONSE_OVERHEAD);
+            let szx = block_szx(room).ok_or(ErrorCode::SIZE)?;
+            if requested.is_some() || body.len() > room {
+                let requested = requested.unwrap_or(Block {
+                    num: 0,
+                    more: false,
+                    szx,
+                });
+                let szx = cmp::min(requested.szx, szx);
+                let start = requested.offset();
+                let block = Block {
+                    num: (start >> (szx + 4)) as u32,
+                    more: false,
+                    szx,
+                };
+                if start > 0 && start >= body.len() {
+                    code = code::BAD_OPTION;
+                    range = 0..0;
+                } else {
+                    let end = cmp::min(start + block.size(), body.len());
+                    block2 = Some(Block {
+                        more: end < body.len(),
+                        ..block
+                    });
+                    range = start..end;
+                }
+            }
+        }
+
+        let (mtype, message_id) = match response.request_type {
+            MessageType::Confirmable => (MessageType::Acknowledgement, response.message_id),
+            _ => (MessageType::NonConfirmable, self.next_message_id()),
+        };
+        let header = CoapHeader::new(mtype, code, message_id, response.token);
+        let (mut off, _) = header.encode(buf, 0).done().ok_or(ErrorCode::SIZE)?;
+        let mut prev = 0;
+        let options = [
+            (
+                option::CONTENT_FORMAT,
+                response.content_format.map(|cf| cf as u32),
+            ),
+            (option::BLOCK2, block2.map(|block| block.to_uint())),
+            (option::BLOCK1, response.block1.map(|block| block.to_uint())),
+        ];
+        for (number, value) in options.iter() {
+            if let Some(value) = value {
+                off = encode_uint_option(buf, off, prev, *number, *value)
+                    .done()
+                    .ok_or(ErrorCode::SIZE)?
+                    .0;
+                prev = *number;
+            }
+        }
+        encode_body(buf, off, &body[range])
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for CoapDriver<'a, A> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, mut dgram: LeasableBuffer<'static, u8>) {
+        // Failed sends are handled like lost messages
+        dgram.reset();
+        match self.in_flight.take() {
+            Some(Buffer::Client) => self.client_buf.replace(dgram),
+            _ => self.server_buf.replace(dgram),
+        };
+        self.transmit();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for CoapDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let message = match CoapMessage::decode(payload) {
+            Some(message) => message,
+            None => return,
+        };
+        let peer = Peer {
+            addr: src_addr,
+            port: src_port,
+        };
+        if code::is_request(message.header.code) {
+            self.receive_request(peer, dst_addr.is_multicast(), &message);
+        } else {
+            self.receive_response(peer, &message);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for CoapDriver<'a, A> {
+    fn alarm(&self) {
+        if self.expired(&self.client_timer) {
+            self.client_timer.clear();
+            self.client_timeout();
+        }
+        if self.expired(&self.server_timer) {
+            self.server_timer.clear();
+            self.server_timeout();
+        }
+        self.arm();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> SyscallDriver for CoapDriver<'a, A> {
+    /// Control the CoAP driver.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send a request to the address in read-only buffer `0` for the
+    ///        path in read-only buffer `1`, with the body in read-only buffer
+    ///        `2`. Bits 0-7 of `data1` are the method code, and bit 8 is set
+    ///        for a non-confirmable request. `data2` is the destination port,
+    ///        `0` for the default CoAP port.
+    /// - `2`: Register the resource whose path is in read-only buffer `1`.
+    ///        Returns the resource id.
+    /// - `3`: Unregister resource `data1`.
+    /// - `4`: Respond to the request delivered to the process. `data1` is
+    ///        the response code and `data2` the Content-Format, or a value
+    ///        above 65535 for none. The body is in read-only buffer `3`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let res = match command_num {
+            0 => Ok(()),
+            1 => {
+                let method = data1 as u8;
+                if !code::is_request(method) {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    let port = match data2 {
+                        0 => COAP_PORT,
+                        port => port as u16,
+                    };
+                    self.enqueue(
+                        Request {
+                            code: method,
+                            confirmable: data1 & (1 << 8) == 0,
+                            port,
+                        },
+                        appid,
+                    )
+                }
+            }
+            2 => {
+                return match self.register(appid) {
+                    Ok(resource) => CommandReturn::success_u32(resource as u32),
+                    Err(e) => CommandReturn::failure(e),
+                };
+            }
+            3 => self.unregister(appid, data1),
+            4 => {
+                let content_format = if data2 <= u16::MAX as usize {
+                    Some(data2 as u16)
+                } else {
+                    None
+                };
+                self.respond(appid, data1 as u8, content_format)
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,188 @@
+//! Ethernet II framing of IPv6 packets (RFC 2464).
+//!
+//! Frames start with a 14 byte header holding the destination and source
+//! MAC addresses and the EtherType of the payload. The frame check sequence
+//! is added and checked by the adapter.
+//!
+//! IPv6 multicast packets are sent to the MAC address 33:33 followed by the
+//! last four bytes of the group address, link-local addresses are formed
+//! from the MAC address with a modified EUI-64 interface identifier, and
+//! link-layer address options of Neighbor Discovery hold the MAC address in
+//! 8 bytes.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::neighbor_cache::LinkAddress;
+
+pub use kernel::hil::ethernet::HEADER_LEN;
+
+/// The EtherType of IPv6 packets.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// The shortest frame, without the frame check sequence. Shorter frames are
+/// padded with zeros.
+pub const MIN_FRAME_LEN: usize = 60;
+
+/// The longest payload of a frame.
+pub const MTU: usize = 1500;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EthernetAddress(pub [u8; 6]);
+
+impl EthernetAddress {
+    pub const BROADCAST: EthernetAddress = EthernetAddress([0xff; 6]);
+
+    /// Whether the address is a group address (including broadcast).
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+impl LinkAddress for EthernetAddress {
+    fn multicast(group: IPAddr) -> EthernetAddress {
+        let mut addr = [0x33, 0x33, 0, 0, 0, 0];
+        addr[2..6].copy_from_slice(&group.0[12..16]);
+        EthernetAddress(addr)
+    }
+
+    /// Only interface identifiers formed from a MAC address (with `ff:fe`
+    /// in the middle) map back to one.
+    fn from_link_local(addr: IPAddr) -> Option<EthernetAddress> {
+        let iid = &addr.0[8..16];
+        if iid[3] != 0xff || iid[4] != 0xfe {
+            return None;
+        }
+        Some(EthernetAddress([
+            iid[0] ^ 0x02,
+            iid[1],
+            iid[2],
+            iid[5],
+            iid[6],
+            iid[7],
+        ]))
+    }
+
+    fn link_local(&self) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.set_unicast_link_local();
+        addr.0[8..16].copy_from_slice(&[
+            self.0[0] ^ 0x02,
+            self.0[1],
+            self.0[2],
+            0xff,
+            0xfe,
+            self.0[3],
+            self.0[4],
+            self.0[5],
+        ]);
+        addr
+    }
+
+    fn encode_option(&self, buf: &mut [u8], option_type: u8) -> usize {
+        if buf.len() < 8 {
+            return 0;
+        }
+        buf[0] = option_type;
+        buf[1] = 1;
+        buf[2..8].copy_from_slice(&self.0);
+        8
+    }
+
+    fn decode_option(option: &[u8]) -> Option<EthernetAddress> {
+        if option.len() != 8 {
+            return None;
+        }
+        let mut addr = [0; 6];
+        addr.copy_from_slice(&option[2..8]);
+        Some(EthernetAddress(addr))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub dst: EthernetAddress,
+    pub src: EthernetAddress,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    /// Writes the header at the start of `buf`. Returns the header length,
+    /// or `None` if `buf` is too short.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let header = buf.get_mut(..HEADER_LEN)?;
+        header[0..6].copy_from_slice(&self.dst.0);
+        header[6..12].copy_from_slice(&self.src.0);
+        header[12..14].copy_from_slice(&self.ethertype.to_be_bytes());
+        Some(HEADER_LEN)
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<EthernetHeader> {
+        let header = buf.get(..HEADER_LEN)?;
+        let mut dst = [0; 6];
+        let mut src = [0; 6];
+        dst.copy_from_slice(&header[0..6]);
+        src.copy_from_slice(&header[6..12]);
+        Some(EthernetHeader {
+            dst: EthernetAddress(dst),
+            src: EthernetAddress(src),
+            ethertype: u16::from_be_bytes([header[12], header[13]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: EthernetAddress = EthernetAddress([0x02, 0x00, 0x5e, 0x10, 0x20, 0x30]);
+
+    #[test]
+    fn header_round_trip() {
+        let header = EthernetHeader {
+            dst: EthernetAddress::BROADCAST,
+            src: MAC,
+            ethertype: ETHERTYPE_IPV6,
+        };
+        let mut buf = [0; 16];
+        assert_eq!(header.encode(&mut buf), Some(HEADER_LEN));
+        assert_eq!(
+            &buf[..HEADER_LEN],
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x00, 0x5e, 0x10, 0x20, 0x30, 0x86, 0xdd]
+        );
+        assert_eq!(EthernetHeader::decode(&buf), Some(header));
+        assert_eq!(EthernetHeader::decode(&buf[..13]), None);
+        assert_eq!(header.encode(&mut buf[..13]), None);
+    }
+
+    #[test]
+    fn ipv6_mapping() {
+        // fe80::ff:fe10:2030
+        let link_local = MAC.link_local();
+        assert!(link_local.is_unicast_link_local());
+        assert_eq!(
+            &link_local.0[8..16],
+            &[0x00, 0x00, 0x5e, 0xff, 0xfe, 0x10, 0x20, 0x30]
+        );
+        assert_eq!(EthernetAddress::from_link_local(link_local), Some(MAC));
+
+        // Interface identifiers that are not derived from a MAC address
+        let mut other = link_local;
+        other.0[11] = 0x12;
+        assert_eq!(EthernetAddress::from_link_local(other), None);
+
+        // ff02::1:ff10:2030
+        let group = link_local.solicited_node();
+        let mac = EthernetAddress::multicast(group);
+        assert_eq!(mac.0, [0x33, 0x33, 0xff, 0x10, 0x20, 0x30]);
+        assert!(mac.is_multicast());
+        assert!(!MAC.is_multicast());
+
+        let mut option = [0; 8];
+        assert_eq!(MAC.encode_option(&mut option, 1), 8);
+        assert_eq!(option, [1, 1, 0x02, 0x00, 0x5e, 0x10, 0x20, 0x30]);
+        assert_eq!(EthernetAddress::decode_option(&option), Some(MAC));
+        assert_eq!(
+            EthernetAddress::decode_option(&[1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+    }
+}
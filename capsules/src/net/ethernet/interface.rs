@@ -0,0 +1,236 @@
+//! IPv6 network interface over Ethernet.
+//!
+//! `EthernetInterface` sends and receives IPv6 packets in Ethernet frames
+//! through an `EthernetAdapter`. Like `SerialTunnel`, it is an alternative
+//! to the 6LoWPAN path: it implements `IP6Sender`, so it can be used under
+//! `MuxIP6Sender` in place of `IP6SendStruct`, and passes received packets
+//! to a `SixlowpanRxClient` such as `IP6RecvStruct`.
+//!
+//! The destination MAC address of each packet is chosen by a
+//! `NeighborCache<EthernetAddress>`, which Neighbor Discovery fills from
+//! received messages. Multicast destinations map to their 33:33 MAC address,
+//! and link-local destinations with an interface identifier formed from a
+//! MAC address map to that address. Packets to any other destination that
+//! is not in the cache fail with `FAIL`, as unknown neighbors are not
+//! resolved with Neighbor Solicitations.
+//!
+//! Received frames are accepted if they are sent to the MAC address of the
+//! interface, to the broadcast address or to an IPv6 multicast MAC address.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let eth = static_init!(
+//!     capsules::net::ethernet::EthernetInterface<'static>,
+//!     capsules::net::ethernet::EthernetInterface::new(
+//!         ethmac,
+//!         EthernetAddress(MAC_ADDR),
+//!         ip6_packet,
+//!         &mut TX_BUF,
+//!         neighbor_cache,
+//!         ip_vis,
+//!     )
+//! );
+//! ethmac.set_client(eth);
+//! eth.set_rx_client(ip_receive);
+//! ```
+
+use crate::net::ethernet::frame::{
+    EthernetAddress, EthernetHeader, ETHERTYPE_IPV6, HEADER_LEN, MIN_FRAME_LEN,
+};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_cache::NeighborCache;
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+
+use core::cell::Cell;
+
+use kernel::hil::ethernet::{EthernetAdapter, EthernetAdapterClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// The length of the fixed IPv6 header.
+const IP6_HDR_LEN: usize = 40;
+
+pub struct EthernetInterface<'a> {
+    adapter: &'a dyn EthernetAdapter<'a>,
+    mac_addr: EthernetAddress,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    src_addr: Cell<IPAddr>,
+    hop_limit: Cell<u8>,
+    tx_buf: TakeCell<'static, [u8]>,
+    neighbor_cache: &'a NeighborCache<EthernetAddress>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    rx_client: OptionalCell<&'a dyn SixlowpanRxClient>,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a> EthernetInterface<'a> {
+    /// `tx_buf` holds the frames being sent, and limits the size of sent
+    /// packets to `tx_buf.len() - HEADER_LEN` bytes. It must hold at least
+    /// `MIN_FRAME_LEN` bytes.
+    pub fn new(
+        adapter: &'a dyn EthernetAdapter<'a>,
+        mac_addr: EthernetAddress,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        tx_buf: &'static mut [u8],
+        neighbor_cache: &'a NeighborCache<EthernetAddress>,
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> EthernetInterface<'a> {
+        EthernetInterface {
+            adapter: adapter,
+            mac_addr: mac_addr,
+            ip6_packet: TakeCell::new(ip6_packet),
+            src_addr: Cell::new(IPAddr::new()),
+            hop_limit: Cell::new(IP6Header::default().get_hop_limit()),
+            tx_buf: TakeCell::new(tx_buf),
+            neighbor_cache: neighbor_cache,
+            client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            ip_vis: ip_vis,
+        }
+    }
+
+    /// Sets the client that received packets are passed to.
+    pub fn set_rx_client(&self, rx_client: &'a dyn SixlowpanRxClient) {
+        self.rx_client.set(rx_client);
+    }
+
+    pub fn mac_address(&self) -> EthernetAddress {
+        self.mac_addr
+    }
+
+    /// Builds the frame in `tx_buf`, and returns its length.
+    fn encode_frame(
+        &self,
+        tx_buf: &mut [u8],
+        dst_mac_addr: EthernetAddress,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+    ) -> Result<usize, ErrorCode> {
+        let ip6_packet = self.ip6_packet.take().ok_or(ErrorCode::NOMEM)?;
+        let result = if payload.len() > ip6_packet.payload.payload.len() {
+            Err(ErrorCode::SIZE)
+        } else {
+            ip6_packet.header = IP6Header::default();
+            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.set_hop_limit(self.hop_limit.get());
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_payload(transport_header, payload);
+            ip6_packet.set_transport_checksum();
+            let len = HEADER_LEN + ip6_packet.get_total_len() as usize;
+            let header = EthernetHeader {
+                dst: dst_mac_addr,
+                src: self.mac_addr,
+                ethertype: ETHERTYPE_IPV6,
+            };
+            if len > tx_buf.len() || tx_buf.len() < MIN_FRAME_LEN {
+                Err(ErrorCode::SIZE)
+            } else {
+                header.encode(tx_buf).ok_or(ErrorCode::SIZE).and_then(|_| {
+                    ip6_packet
+                        .encode(&mut tx_buf[HEADER_LEN..])
+                        .done()
+                        .map_or(Err(ErrorCode::FAIL), |_| Ok(len))
+                })
+            }
+        };
+        self.ip6_packet.replace(ip6_packet);
+        result.map(|len| {
+            // Pad short frames
+            for b in tx_buf[len..MIN_FRAME_LEN.max(len)].iter_mut() {
+                *b = 0;
+            }
+            MIN_FRAME_LEN.max(len)
+        })
+    }
+}
+
+impl<'a> IP6Sender<'a> for EthernetInterface<'a> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_hop_limit(&self, hop_limit: u8) {
+        self.hop_limit.set(hop_limit);
+    }
+
+    fn set_gateway(&self, _gateway: MacAddress) {
+        // The next hop is chosen by the neighbor cache
+    }
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return Err(ErrorCode::FAIL);
+        }
+        // No link-layer address is known for the next hop
+        let dst_mac_addr = self.neighbor_cache.next_hop(dst).ok_or(ErrorCode::FAIL)?;
+        let tx_buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+        match self.encode_frame(tx_buf, dst_mac_addr, dst, transport_header, payload) {
+            Ok(len) => self.adapter.transmit(tx_buf, len).map_err(|(e, tx_buf)| {
+                self.tx_buf.replace(tx_buf);
+                e
+            }),
+            Err(e) => {
+                self.tx_buf.replace(tx_buf);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a> EthernetAdapterClient for EthernetInterface<'a> {
+    fn tx_done(&self, result: Result<(), ErrorCode>, frame: &'static mut [u8]) {
+        self.tx_buf.replace(frame);
+        self.client.map(|client| client.send_done(result));
+    }
+
+    fn rx_frame(&self, frame: &[u8]) {
+        let header = match EthernetHeader::decode(frame) {
+            Some(header) => header,
+            None => return,
+        };
+        if header.ethertype != ETHERTYPE_IPV6
+            || !(header.dst == self.mac_addr
+                || header.dst == EthernetAddress::BROADCAST
+                || header.dst.0[0..2] == [0x33, 0x33])
+        {
+            return;
+        }
+        // Short frames are padded, so the packet length is taken from the
+        // IPv6 header
+        let packet = &frame[HEADER_LEN..];
+        if packet.len() < IP6_HDR_LEN {
+            return;
+        }
+        let len = IP6_HDR_LEN + u16::from_be_bytes([packet[4], packet[5]]) as usize;
+        if len > packet.len() {
+            return;
+        }
+        self.rx_client
+            .map(|client| client.receive(&packet[..len], len, Ok(())));
+    }
+}
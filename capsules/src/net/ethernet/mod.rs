@@ -0,0 +1,5 @@
+pub mod frame;
+pub mod interface;
+
+pub use self::frame::EthernetAddress;
+pub use self::interface::EthernetInterface;
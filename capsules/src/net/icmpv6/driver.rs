@@ -0,0 +1,384 @@
+//! ICMPv6 echo (ping) userspace interface.
+//!
+//! Lets processes send ICMPv6 Echo Requests and be notified when the
+//! matching Echo Reply arrives, together with the round-trip time measured
+//! with an alarm. The destination address and the echo data are passed in
+//! read-only allow buffers. If a read-write buffer is shared, the data of
+//! the reply is copied into it.
+//!
+//! One request is outstanding at a time; requests from other processes are
+//! queued and sent once the current one has completed, either because a
+//! reply arrived or because its timeout expired.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let ping = static_init!(
+//!     capsules::net::icmpv6::PingDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::net::icmpv6::PingDriver::new(
+//!         icmp_send,
+//!         ping_alarm,
+//!         ping_buf,
+//!         net_cap,
+//!         board_kernel.create_grant(capsules::net::icmpv6::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! icmp_send.set_client(ping);
+//! icmp_rcvr.set_client(ping);
+//! ping_alarm.set_alarm_client(ping);
+//! ```
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::IP6Header;
+use crate::net::network_capabilities::NetworkCapability;
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ping as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const DEST: usize = 0;
+    pub const DATA: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const REPLY: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Timeout used when a process passes 0 as the timeout.
+pub const DEFAULT_TIMEOUT_MS: u32 = 5000;
+
+#[derive(Clone, Copy)]
+struct Request {
+    seqno: u16,
+    timeout_ms: u32,
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: Option<Request>,
+}
+
+pub struct PingDriver<'a, A: time::Alarm<'a>> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    alarm: &'a A,
+    buffer: MapCell<LeasableBuffer<'static, u8>>,
+    net_cap: &'static NetworkCapability,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// Process whose request is outstanding.
+    current_app: OptionalCell<ProcessId>,
+    dest: Cell<IPAddr>,
+    ident: Cell<u16>,
+    seqno: Cell<u16>,
+    sent_at: Cell<A::Ticks>,
+    /// Whether the ICMPv6 sender still holds the buffer.
+    sending: Cell<bool>,
+}
+
+impl<'a, A: time::Alarm<'a>> PingDriver<'a, A> {
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> PingDriver<'a, A> {
+        PingDriver {
+            icmp_sender: icmp_sender,
+            alarm: alarm,
+            buffer: MapCell::new(LeasableBuffer::new(buffer)),
+            net_cap: net_cap,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            dest: Cell::new(IPAddr::new()),
+            ident: Cell::new(0),
+            seqno: Cell::new(0),
+            sent_at: Cell::new(A::Ticks::from(0)),
+            sending: Cell::new(false),
+        }
+    }
+
+    fn enqueue(&self, request: Request, appid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending.is_some() || self.current_app.contains(&appid) {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    app.pending = Some(request);
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        if self.current_app.is_none() && !self.sending.get() {
+            // Errors of the request that was just queued are returned
+            // synchronously
+            let result = self.start(appid);
+            if result.is_err() {
+                self.start_next();
+            }
+            result
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sends the pending request of `appid`.
+    fn start(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        // Copy the request out of the grant, which must not stay entered while
+        // the request is sent.
+        let (request, dest, buf) = self
+            .apps
+            .enter(appid, |app, kernel_data| {
+                let request = app.pending.take().ok_or(ErrorCode::FAIL)?;
+
+                let dest = kernel_data
+                    .get_readonly_processbuffer(ro_allow::DEST)
+                    .and_then(|dest| {
+                        dest.enter(|dest| {
+                            let mut addr = IPAddr::new();
+                            if dest.len() != addr.0.len() {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            dest.copy_to_slice(&mut addr.0);
+                            Ok(addr)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+                let mut buf = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+                buf.reset();
+                let copied = kernel_data
+                    .get_readonly_processbuffer(ro_allow::DATA)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            if data.len() > buf.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data.copy_to_slice(&mut buf[..data.len()]);
+                            Ok(data.len())
+                        })
+                    })
+                    .unwrap_or(Ok(0));
+                let len = match copied {
+                    Ok(len) => len,
+                    Err(e) => {
+                        self.buffer.replace(buf);
+                        return Err(e);
+                    }
+                };
+                buf.slice(0..len);
+                Ok((request, dest, buf))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        // The identifier tells the requests of different processes apart
+        let ident = appid.id() as u16;
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type128);
+        icmp_header.set_options(ICMP6HeaderOptions::Type128 {
+            id: ident,
+            seqno: request.seqno,
+        });
+
+        self.current_app.set(appid);
+        self.dest.set(dest);
+        self.ident.set(ident);
+        self.seqno.set(request.seqno);
+        self.sending.set(true);
+        let now = self.alarm.now();
+        self.sent_at.set(now);
+        self.alarm
+            .set_alarm(now, self.alarm.ticks_from_ms(request.timeout_ms));
+
+        match self.icmp_sender.send(dest, icmp_header, buf, self.net_cap) {
+            Ok(()) => Ok(()),
+            Err((e, buf)) => {
+                self.buffer.replace(buf);
+                self.sending.set(false);
+                self.current_app.clear();
+                let _ = self.alarm.disarm();
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts the next queued request, if any. Errors are reported to the
+    /// owning process through its upcall.
+    fn start_next(&self) {
+        while self.current_app.is_none() && !self.sending.get() {
+            let next = self.apps.iter().find_map(|app| {
+                let appid = app.processid();
+                app.enter(|app, _| app.pending.map(|request| (appid, request.seqno)))
+            });
+            match next {
+                Some((appid, seqno)) => {
+                    if let Err(e) = self.start(appid) {
+                        self.notify(appid, Err(e), seqno, 0);
+                    }
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Completes the outstanding request.
+    fn finish(&self, result: Result<(), ErrorCode>, rtt_us: u32) {
+        let _ = self.alarm.disarm();
+        self.current_app.take().map(|appid| {
+            self.notify(appid, result, self.seqno.get(), rtt_us);
+        });
+        self.start_next();
+    }
+
+    fn notify(&self, appid: ProcessId, result: Result<(), ErrorCode>, seqno: u16, rtt_us: u32) {
+        let _ = self.apps.enter(appid, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(
+                    upcall::DONE,
+                    (into_statuscode(result), seqno as usize, rtt_us as usize),
+                )
+                .ok();
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6SendClient for PingDriver<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>, buf: LeasableBuffer<'static, u8>) {
+        self.buffer.replace(buf);
+        self.sending.set(false);
+        if result.is_err() && self.current_app.is_some() {
+            self.finish(result, 0);
+        } else {
+            // The request may have timed out before the send completed
+            self.start_next();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6RecvClient for PingDriver<'a, A> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        let appid = match self.current_app.extract() {
+            Some(appid) => appid,
+            None => return,
+        };
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type129 { id, seqno }
+                if id == self.ident.get() && seqno == self.seqno.get() => {}
+            _ => return,
+        }
+        // Replies to a multicast request come from the individual nodes
+        let dest = self.dest.get();
+        if !dest.is_multicast() && ip_header.get_src_addr() != dest {
+            return;
+        }
+
+        let elapsed = self.alarm.now().wrapping_sub(self.sent_at.get());
+        let rtt_us = self.alarm.ticks_to_us(elapsed);
+        let _ = self.apps.enter(appid, |_, kernel_data| {
+            let _ = kernel_data
+                .get_readwrite_processbuffer(rw_allow::REPLY)
+                .and_then(|reply| {
+                    reply.mut_enter(|reply| {
+                        let len = cmp::min(reply.len(), payload.len());
+                        reply[..len].copy_from_slice(&payload[..len]);
+                    })
+                });
+        });
+        self.finish(Ok(()), rtt_us);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for PingDriver<'a, A> {
+    fn alarm(&self) {
+        if self.current_app.is_some() {
+            self.finish(Err(ErrorCode::NOACK), 0);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> SyscallDriver for PingDriver<'a, A> {
+    /// Control the ping driver.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send an Echo Request to the address in read-only buffer `0`,
+    ///        with the data in read-only buffer `1` (if any). `data1` is the
+    ///        sequence number and `data2` the timeout in milliseconds (`0`
+    ///        selects the default of 5 seconds). Returns BUSY if the process
+    ///        already has a request outstanding.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let res = match command_num {
+            0 => Ok(()),
+            1 => {
+                let timeout_ms = match data2 {
+                    0 => DEFAULT_TIMEOUT_MS,
+                    ms => ms as u32,
+                };
+                self.enqueue(
+                    Request {
+                        seqno: data1 as u16,
+                        timeout_ms,
+                    },
+                    appid,
+                )
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
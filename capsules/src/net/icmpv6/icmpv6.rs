@@ -4,9 +4,10 @@
 //!
 //! - Author: Conor McAvity <cmcavity@stanford.edu>
 
+use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::stream::SResult;
-use crate::net::stream::{decode_u16, decode_u32, decode_u8};
-use crate::net::stream::{encode_u16, encode_u32, encode_u8};
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
 
 /// A struct representing an ICMPv6 header.
 #[derive(Copy, Clone)]
@@ -23,6 +24,19 @@ pub enum ICMP6HeaderOptions {
     Type3 { unused: u32 },
     Type128 { id: u16, seqno: u16 },
     Type129 { id: u16, seqno: u16 },
+    Type133 { reserved: u32 },
+    Type134 {
+        cur_hop_limit: u8,
+        flags: u8,
+        router_lifetime: u16,
+        reachable_time: u32,
+        retrans_timer: u32,
+    },
+    Type135 { reserved: u32, target: IPAddr },
+    Type136 { flags: u32, target: IPAddr },
+    // The RPL message body follows the checksum directly, and is carried in
+    // the payload
+    Type155,
 }
 
 #[derive(Copy, Clone)]
@@ -31,8 +45,17 @@ pub enum ICMP6Type {
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type133, // Router Solicitation
+    Type134, // Router Advertisement
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
+    Type155, // RPL Control Message
 }
 
+/// The size of the largest ICMPv6 header, that of Neighbor Solicitations and
+/// Advertisements.
+pub const ICMP6_MAX_HDR_LEN: usize = 24;
+
 impl ICMP6Header {
     pub fn new(icmp_type: ICMP6Type) -> ICMP6Header {
         let options = match icmp_type {
@@ -40,6 +63,23 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { reserved: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: 0,
+                flags: 0,
+                router_lifetime: 0,
+                reachable_time: 0,
+                retrans_timer: 0,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 {
+                reserved: 0,
+                target: IPAddr::new(),
+            },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 {
+                flags: 0,
+                target: IPAddr::new(),
+            },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155,
         };
 
         ICMP6Header {
@@ -51,12 +91,7 @@ impl ICMP6Header {
     }
 
     pub fn set_type(&mut self, icmp_type: ICMP6Type) {
-        match icmp_type {
-            ICMP6Type::Type1 => self.set_options(ICMP6HeaderOptions::Type1 { unused: 0 }),
-            ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
-            ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
-            ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
-        }
+        self.set_options(Self::new(icmp_type).options);
     }
 
     pub fn set_code(&mut self, code: u8) {
@@ -81,6 +116,11 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type133 { .. } => ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type155 => ICMP6Type::Type155,
         }
     }
 
@@ -90,6 +130,11 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type133 => 133,
+            ICMP6Type::Type134 => 134,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
+            ICMP6Type::Type155 => 155,
         }
     }
 
@@ -110,7 +155,12 @@ impl ICMP6Header {
     }
 
     pub fn get_hdr_size(&self) -> usize {
-        8
+        match self.options {
+            ICMP6HeaderOptions::Type134 { .. } => 16,
+            ICMP6HeaderOptions::Type135 { .. } | ICMP6HeaderOptions::Type136 { .. } => 24,
+            ICMP6HeaderOptions::Type155 => 4,
+            _ => 8,
+        }
     }
 
     /// Serializes an `ICMP6Header` into a buffer.
@@ -140,6 +190,34 @@ impl ICMP6Header {
                 off = enc_consume!(buf, off; encode_u16, id);
                 off = enc_consume!(buf, off; encode_u16, seqno);
             }
+            ICMP6HeaderOptions::Type133 { reserved } => {
+                off = enc_consume!(buf, off; encode_u32, reserved);
+            }
+            ICMP6HeaderOptions::Type134 {
+                cur_hop_limit,
+                flags,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, cur_hop_limit);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, router_lifetime);
+                off = enc_consume!(buf, off; encode_u32, reachable_time);
+                off = enc_consume!(buf, off; encode_u32, retrans_timer);
+            }
+            ICMP6HeaderOptions::Type135 {
+                reserved: value,
+                target,
+            }
+            | ICMP6HeaderOptions::Type136 {
+                flags: value,
+                target,
+            } => {
+                off = enc_consume!(buf, off; encode_u32, value);
+                off = enc_consume!(buf, off; encode_bytes, &target.0);
+            }
+            ICMP6HeaderOptions::Type155 => {}
         }
 
         stream_done!(off, off);
@@ -163,6 +241,11 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            133 => ICMP6Type::Type133,
+            134 => ICMP6Type::Type134,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
+            155 => ICMP6Type::Type155,
             _ => return SResult::Error(()),
         };
 
@@ -171,34 +254,63 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
+        icmp_header.set_cksum(cksum);
 
-        match icmp_type {
+        let (off, options) = match icmp_type {
             ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type1 { unused })
             }
             ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type3 { unused })
             }
             ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                (off, ICMP6HeaderOptions::Type128 { id, seqno })
             }
             ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                (off, ICMP6HeaderOptions::Type129 { id, seqno })
             }
-        }
+            ICMP6Type::Type133 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type133 { reserved })
+            }
+            ICMP6Type::Type134 => {
+                let (off, cur_hop_limit) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, router_lifetime) = dec_try!(buf, off; decode_u16);
+                let (off, reachable_time) = dec_try!(buf, off; decode_u32);
+                let (off, retrans_timer) = dec_try!(buf, off; decode_u32);
+                (
+                    off,
+                    ICMP6HeaderOptions::Type134 {
+                        cur_hop_limit,
+                        flags,
+                        router_lifetime,
+                        reachable_time,
+                        retrans_timer,
+                    },
+                )
+            }
+            ICMP6Type::Type135 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                let mut target = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+                (off, ICMP6HeaderOptions::Type135 { reserved, target })
+            }
+            ICMP6Type::Type136 => {
+                let (off, flags) = dec_try!(buf, off; decode_u32);
+                let mut target = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+                (off, ICMP6HeaderOptions::Type136 { flags, target })
+            }
+            ICMP6Type::Type155 => (off, ICMP6HeaderOptions::Type155),
+        };
+        icmp_header.set_options(options);
 
         stream_done!(off, icmp_header);
     }
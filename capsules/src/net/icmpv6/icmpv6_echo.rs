@@ -0,0 +1,99 @@
+//! Replies to ICMPv6 Echo Requests (RFC 4443, section 4) addressed to this
+//! node.
+//!
+//! The `ICMP6EchoResponder` is a client of an `ICMP6Receiver`. When an Echo
+//! Request arrives whose destination is one of the node's interface
+//! addresses or a multicast address, it sends an Echo Reply carrying the same
+//! identifier, sequence number and data back to the sender. Only one reply
+//! is in flight at a time; requests received while a reply is being sent, or
+//! whose data does not fit into the responder's buffer, are dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let echo = static_init!(
+//!     ICMP6EchoResponder<'static>,
+//!     ICMP6EchoResponder::new(icmp_send, interface_list, echo_buf, net_cap)
+//! );
+//! icmp_send.set_client(echo);
+//! icmp_rcvr.set_client(echo);
+//! ```
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::IP6Header;
+use crate::net::network_capabilities::NetworkCapability;
+
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+pub struct ICMP6EchoResponder<'a> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    interface_list: &'static [IPAddr],
+    buffer: MapCell<LeasableBuffer<'static, u8>>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a> ICMP6EchoResponder<'a> {
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        interface_list: &'static [IPAddr],
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ICMP6EchoResponder<'a> {
+        ICMP6EchoResponder {
+            icmp_sender: icmp_sender,
+            interface_list: interface_list,
+            buffer: MapCell::new(LeasableBuffer::new(buffer)),
+            net_cap: net_cap,
+        }
+    }
+
+    fn is_local(&self, addr: IPAddr) -> bool {
+        addr.is_multicast() || self.interface_list.iter().any(|iface| *iface == addr)
+    }
+}
+
+impl<'a> ICMP6RecvClient for ICMP6EchoResponder<'a> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        let (id, seqno) = match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => (id, seqno),
+            _ => return,
+        };
+        let src_addr = ip_header.get_src_addr();
+        if !self.is_local(ip_header.get_dst_addr())
+            || src_addr.is_unspecified()
+            || src_addr.is_multicast()
+        {
+            return;
+        }
+
+        // If the buffer is missing a reply is still in flight, so the request
+        // is dropped
+        self.buffer.take().map(|mut buf| {
+            buf.reset();
+            if payload.len() > buf.len() {
+                self.buffer.replace(buf);
+                return;
+            }
+            buf[..payload.len()].copy_from_slice(payload);
+            buf.slice(0..payload.len());
+
+            let mut reply = ICMP6Header::new(ICMP6Type::Type129);
+            reply.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+            if let Err((_, buf)) = self.icmp_sender.send(src_addr, reply, buf, self.net_cap) {
+                self.buffer.replace(buf);
+            }
+        });
+    }
+}
+
+impl<'a> ICMP6SendClient for ICMP6EchoResponder<'a> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, buf: LeasableBuffer<'static, u8>) {
+        self.buffer.replace(buf);
+    }
+}
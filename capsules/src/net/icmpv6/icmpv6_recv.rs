@@ -0,0 +1,87 @@
+//! This file contains the definition and implementation of the ICMPv6
+//! reception interface. The `MuxICMP6Receiver` is set as the ICMPv6 client of
+//! the IPv6 receiver, decodes the ICMPv6 header of every received packet and
+//! passes the packet to all `ICMP6Receiver`s. As with UDP, there is no
+//! queueing: received packets are dispatched immediately, and each client is
+//! expected to filter the message types it is interested in.
+
+use crate::net::icmpv6::ICMP6Header;
+use crate::net::ipv6::ip_utils::ip6_nh;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::IP6Header;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::utilities::cells::OptionalCell;
+
+/// A trait for a client of an `ICMP6Receiver`.
+pub trait ICMP6RecvClient {
+    /// Called for every ICMPv6 packet with a valid checksum.
+    ///
+    /// # Arguments
+    ///
+    /// `ip_header` - The IPv6 header of the received packet
+    /// `icmp_header` - The decoded ICMPv6 header
+    /// `payload` - The ICMPv6 payload following the header
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]);
+}
+
+pub struct MuxICMP6Receiver<'a> {
+    rcvr_list: List<'a, ICMP6Receiver<'a>>,
+}
+
+impl<'a> MuxICMP6Receiver<'a> {
+    pub fn new() -> MuxICMP6Receiver<'a> {
+        MuxICMP6Receiver {
+            rcvr_list: List::new(),
+        }
+    }
+
+    pub fn add_client(&self, rcvr: &'a ICMP6Receiver<'a>) {
+        self.rcvr_list.push_tail(rcvr);
+    }
+}
+
+impl<'a> IP6RecvClient for MuxICMP6Receiver<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        match ICMP6Header::decode(payload).done() {
+            Some((offset, mut icmp_header)) => {
+                icmp_header.set_len(payload.len() as u16);
+                for rcvr in self.rcvr_list.iter() {
+                    rcvr.client.map(|client| {
+                        client.receive(ip_header, icmp_header, &payload[offset..]);
+                    });
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// A receiver registered with a `MuxICMP6Receiver`, which passes received
+/// ICMPv6 packets up to its client.
+pub struct ICMP6Receiver<'a> {
+    client: OptionalCell<&'a dyn ICMP6RecvClient>,
+    next: ListLink<'a, ICMP6Receiver<'a>>,
+}
+
+impl<'a> ListNode<'a, ICMP6Receiver<'a>> for ICMP6Receiver<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, ICMP6Receiver<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> ICMP6Receiver<'a> {
+    pub fn new() -> ICMP6Receiver<'a> {
+        ICMP6Receiver {
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ICMP6RecvClient) {
+        self.client.set(client);
+    }
+}
@@ -13,15 +13,15 @@ use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
 use crate::net::ipv6::TransportHeader;
 use crate::net::network_capabilities::NetworkCapability;
 
-use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::utilities::leasable_buffer::LeasableBuffer;
 use kernel::ErrorCode;
 
 /// A trait for a client of an `ICMP6Sender`.
 pub trait ICMP6SendClient {
     /// A client callback invoked after an ICMP6Sender has completed sending
-    /// a requested packet.
-    fn send_done(&self, result: Result<(), ErrorCode>);
+    /// a requested packet. The payload buffer passed to `send` is returned.
+    fn send_done(&self, result: Result<(), ErrorCode>, buf: LeasableBuffer<'static, u8>);
 }
 
 /// A trait that defines an interface for sending ICMPv6 packets.
@@ -41,26 +41,30 @@ pub trait ICMP6Sender<'a> {
     ///
     /// `dest` - The destination IP address
     /// `icmp_header` - The ICMPv6 header to be sent
-    /// `buf` - The byte array containing the ICMPv6 payload
+    /// `buf` - The buffer containing the ICMPv6 payload; its active slice
+    /// is sent
     ///
     /// # Return Value
     ///
     /// This function returns a code reporting either success or any
-    /// synchronous errors. Note that any asynchronous errors are returned
-    /// via the callback.
+    /// synchronous errors, in which case the buffer is returned. Note that
+    /// any asynchronous errors are returned via the callback.
     fn send(
         &self,
         dest: IPAddr,
         icmp_header: ICMP6Header,
-        buf: &'static mut [u8],
+        buf: LeasableBuffer<'static, u8>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), ErrorCode>;
+    ) -> Result<(), (ErrorCode, LeasableBuffer<'static, u8>)>;
 }
 
 /// A struct that implements the `ICMP6Sender` trait.
 pub struct ICMP6SendStruct<'a, T: IP6Sender<'a>> {
     ip_send_struct: &'a T,
     client: OptionalCell<&'a dyn ICMP6SendClient>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    // Result of a send that the IP layer completed before `send_to` returned
+    sync_result: OptionalCell<Result<(), ErrorCode>>,
 }
 
 impl<'a, T: IP6Sender<'a>> ICMP6SendStruct<'a, T> {
@@ -68,6 +72,8 @@ impl<'a, T: IP6Sender<'a>> ICMP6SendStruct<'a, T> {
         ICMP6SendStruct {
             ip_send_struct: ip_send_struct,
             client: OptionalCell::empty(),
+            tx_buffer: MapCell::empty(),
+            sync_result: OptionalCell::empty(),
         }
     }
 }
@@ -81,14 +87,32 @@ impl<'a, T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
         &self,
         dest: IPAddr,
         mut icmp_header: ICMP6Header,
-        buf: &'static mut [u8],
+        buf: LeasableBuffer<'static, u8>,
         net_cap: &'static NetworkCapability,
-    ) -> Result<(), ErrorCode> {
+    ) -> Result<(), (ErrorCode, LeasableBuffer<'static, u8>)> {
+        if self.tx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, buf));
+        }
         let total_len = buf.len() + icmp_header.get_hdr_size();
         icmp_header.set_len(total_len as u16);
         let transport_header = TransportHeader::ICMP(icmp_header);
-        self.ip_send_struct
-            .send_to(dest, transport_header, &LeasableBuffer::new(buf), net_cap)
+        match self
+            .ip_send_struct
+            .send_to(dest, transport_header, &buf, net_cap)
+        {
+            Ok(()) => {
+                match self.sync_result.take() {
+                    Some(result) => {
+                        self.client.map(|client| client.send_done(result, buf));
+                    }
+                    None => {
+                        self.tx_buffer.replace(buf);
+                    }
+                }
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
     }
 }
 
@@ -96,6 +120,12 @@ impl<'a, T: IP6Sender<'a>> IP6SendClient for ICMP6SendStruct<'a, T> {
     /// Forwards callback received from the `IP6Sender` to the
     /// `ICMP6SendClient`.
     fn send_done(&self, result: Result<(), ErrorCode>) {
-        self.client.map(|client| client.send_done(result));
+        match self.tx_buffer.take() {
+            Some(buf) => {
+                self.client.map(|client| client.send_done(result, buf));
+            }
+            // Completed synchronously, `send` returns the buffer
+            None => self.sync_result.set(result),
+        }
     }
 }
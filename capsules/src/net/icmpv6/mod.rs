@@ -1,8 +1,15 @@
+pub mod driver;
+pub mod icmpv6_echo;
+pub mod icmpv6_recv;
 pub mod icmpv6_send;
 
+pub use self::driver::PingDriver;
+pub use self::driver::DRIVER_NUM;
+
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
 // module paths (e.g. `capsules::net::icmpv6::icmpv6::ICMP6Header`)
 mod icmpv6;
 pub use icmpv6::ICMP6Header;
 pub use icmpv6::ICMP6HeaderOptions;
 pub use icmpv6::ICMP6Type;
+pub use icmpv6::ICMP6_MAX_HDR_LEN;
@@ -2,7 +2,7 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::{ICMP6Header, ICMP6_MAX_HDR_LEN};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::UDPHeader;
@@ -74,6 +74,27 @@ impl IPAddr {
         ip_addr
     }
 
+    /// Returns the 802.15.4 address that the interface identifier of this
+    /// address is derived from; the inverse of `generate_from_mac`.
+    pub fn link_local_mac(&self) -> MacAddress {
+        if self.0[8..14] == [0x00, 0x00, 0x00, 0xff, 0xfe, 0x00] {
+            MacAddress::Short((self.0[14] as u16) << 8 | self.0[15] as u16)
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..16]);
+            long_addr[0] ^= 0b00000010;
+            MacAddress::Long(long_addr)
+        }
+    }
+
+    /// Returns the solicited-node multicast address of this address
+    /// (ff02::1:ffXX:XXXX), to which Neighbor Solicitations are sent
+    pub fn solicited_node(&self) -> IPAddr {
+        let mut addr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0]);
+        addr.0[13..16].copy_from_slice(&self.0[13..16]);
+        addr
+    }
+
     pub fn is_unspecified(&self) -> bool {
         self.0.iter().all(|&b| b == 0)
     }
@@ -190,22 +211,13 @@ pub fn compute_icmp_checksum(
     // add ipv6 pseudo-header
     sum += compute_ipv6_ph_sum(ipv6_header);
 
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
-
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
-    }
+    // add the header, with the checksum field set to zero
+    let mut header = *icmp_header;
+    header.set_cksum(0);
+    let mut header_buf = [0; ICMP6_MAX_HDR_LEN];
+    let header_len = header.get_hdr_size();
+    let _ = header.encode(&mut header_buf, 0);
+    sum += compute_sum(&header_buf, header_len as u16);
 
     // add icmp payload
     let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
@@ -215,7 +227,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -241,7 +253,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -253,10 +265,121 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd trailing byte is padded with a zero byte
+        let lsb = if i + 1 < (len as usize) {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
 
     sum
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::icmpv6::{ICMP6HeaderOptions, ICMP6Type};
+    use crate::net::ipv6::ICMP_HDR_LEN;
+    use kernel::ErrorCode;
+
+    // Echo Request from fe80::1 to fe80::2 with identifier 0x1234, sequence
+    // number 1 and the data "abcde"
+    const ECHO_REQUEST: [u8; 13] = [
+        0x80, 0x00, 0x46, 0xb7, 0x12, 0x34, 0x00, 0x01, b'a', b'b', b'c', b'd', b'e',
+    ];
+
+    fn ip6_header() -> IP6Header {
+        let mut header = IP6Header::default();
+        header.src_addr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        header.dst_addr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        header.set_next_header(ip6_nh::ICMP);
+        header.set_payload_len(ECHO_REQUEST.len() as u16);
+        header
+    }
+
+    #[test]
+    fn icmp_checksum_of_received_packet() {
+        let ip6_header = ip6_header();
+        let (_, mut icmp_header) = ICMP6Header::decode(&ECHO_REQUEST).done().unwrap();
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => assert_eq!((id, seqno), (0x1234, 1)),
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(icmp_header.get_cksum(), 0x46b7);
+
+        icmp_header.set_len(ECHO_REQUEST.len() as u16);
+        let cksum = compute_icmp_checksum(&ip6_header, &icmp_header, &ECHO_REQUEST[ICMP_HDR_LEN..]);
+        assert_eq!(cksum, 0x46b7);
+        assert_eq!(ip6_header.check_transport_checksum(&ECHO_REQUEST), Ok(()));
+
+        let mut corrupted = ECHO_REQUEST;
+        corrupted[9] ^= 1;
+        assert_eq!(
+            ip6_header.check_transport_checksum(&corrupted),
+            Err(ErrorCode::FAIL)
+        );
+    }
+
+    #[test]
+    fn icmp_encode_with_checksum() {
+        let ip6_header = ip6_header();
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type128);
+        icmp_header.set_options(ICMP6HeaderOptions::Type128 {
+            id: 0x1234,
+            seqno: 1,
+        });
+        icmp_header.set_len(ECHO_REQUEST.len() as u16);
+        let cksum = compute_icmp_checksum(&ip6_header, &icmp_header, &ECHO_REQUEST[ICMP_HDR_LEN..]);
+        icmp_header.set_cksum(cksum);
+
+        let mut buf = [0; ICMP_HDR_LEN];
+        let (off, _) = icmp_header.encode(&mut buf, 0).done().unwrap();
+        assert_eq!(off, ICMP_HDR_LEN);
+        assert_eq!(buf, ECHO_REQUEST[..ICMP_HDR_LEN]);
+    }
+
+    #[test]
+    fn neighbor_solicitation_round_trip() {
+        let target = IPAddr([
+            0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0x12, 0x34,
+        ]);
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type135);
+        icmp_header.set_options(ICMP6HeaderOptions::Type135 {
+            reserved: 0,
+            target,
+        });
+        assert_eq!(icmp_header.get_hdr_size(), 24);
+
+        let mut buf = [0; ICMP6_MAX_HDR_LEN];
+        let (off, _) = icmp_header.encode(&mut buf, 0).done().unwrap();
+        assert_eq!(off, 24);
+        assert_eq!(buf[0], 135);
+        assert_eq!(buf[8..24], target.0);
+
+        let (off, decoded) = ICMP6Header::decode(&buf).done().unwrap();
+        assert_eq!(off, 24);
+        match decoded.get_options() {
+            ICMP6HeaderOptions::Type135 { target: t, .. } => assert!(t == target),
+            _ => panic!("wrong type"),
+        }
+    }
+
+    #[test]
+    fn neighbor_addresses() {
+        let short = IPAddr::generate_from_mac(MacAddress::Short(0x1234));
+        assert!(short.link_local_mac() == MacAddress::Short(0x1234));
+        let long_mac = MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]);
+        let long = IPAddr::generate_from_mac(long_mac);
+        assert!(long.link_local_mac() == long_mac);
+
+        let solicited = short.solicited_node();
+        assert_eq!(
+            solicited.0,
+            [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0x12, 0x34]
+        );
+        assert!(solicited.is_multicast());
+    }
+}
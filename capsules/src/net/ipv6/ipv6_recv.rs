@@ -1,3 +1,5 @@
+use crate::net::ipv6::ip_utils::ip6_nh;
+use crate::net::ipv6::multicast::MulticastGroups;
 use crate::net::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 
@@ -22,8 +24,12 @@ use kernel::ErrorCode;
 - The udp_mac MacUser has a single receive client, which is the `sixlowpan_state` struct
 - `sixlowpan_state` has a single rx_client, which in our case is a single struct that
   implements the `ip_receive ` trait.
-- the `ip_receive` implementing struct (`IP6RecvStruct`) has a single client, which is
-  udp_recv, a `UDPReceive` struct.
+- the `ip_receive` implementing struct (`IP6RecvStruct`) has two clients: ICMPv6
+  packets are passed to the ICMPv6 receive mux (`MuxICMP6Receiver`), and all other
+  packets to udp_recv, a `UDPReceive` struct. If a forwarder (e.g. RPL) is set,
+  it is offered every packet first, and packets it takes are not delivered
+  locally. Non-ICMPv6 packets sent to a multicast group are dropped unless
+  the interface is a member of the group (see `multicast.rs`).
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
 */
@@ -32,6 +38,15 @@ pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
 }
 
+/// Implemented by routing protocols to forward received packets that are
+/// not addressed to this node.
+pub trait IP6Forwarder {
+    /// Returns `true` if the packet is not addressed to this node, in which
+    /// case it has been forwarded or dropped, and must not be delivered
+    /// locally.
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> bool;
+}
+
 /// Currently only one implementation of this trait should exist,
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
@@ -39,24 +54,51 @@ pub trait IP6RecvClient {
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Sets the client that receives ICMPv6 packets. ICMPv6 packets are not
+    /// passed to the client set with `set_client`.
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Sets the forwarder that is offered every packet before it is passed
+    /// to a client.
+    fn set_forwarder(&self, forwarder: &'a dyn IP6Forwarder);
 }
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    icmp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    forwarder: OptionalCell<&'a dyn IP6Forwarder>,
+    multicast_groups: MulticastGroups,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient) {
         self.client.set(client);
     }
+
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
+
+    fn set_forwarder(&self, forwarder: &'a dyn IP6Forwarder) {
+        self.forwarder.set(forwarder);
+    }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            forwarder: OptionalCell::empty(),
+            multicast_groups: MulticastGroups::new(),
         }
     }
+
+    /// Returns the multicast groups this interface is a member of.
+    pub fn multicast_groups(&self) -> &MulticastGroups {
+        &self.multicast_groups
+    }
 }
 
 impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
@@ -75,8 +117,23 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
                 // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                let forwarded = self.forwarder.map_or(false, |forwarder| {
+                    forwarder.forward(ip6_header, &buf[offset..len])
+                });
+                if forwarded {
+                    return;
+                }
+
+                let client = if ip6_header.get_next_header() == ip6_nh::ICMP {
+                    &self.icmp_client
+                } else {
+                    let dst_addr = ip6_header.get_dst_addr();
+                    if dst_addr.is_multicast() && !self.multicast_groups.is_member(dst_addr) {
+                        return; // Not a member of the group
+                    }
+                    &self.client
+                };
+                client.map(|client| client.receive(ip6_header, &buf[offset..len]));
             }
             None => {
                 debug!("failed to decode ipv6 header");
@@ -5,7 +5,13 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN, and a virtualization layer
+//! ([MuxIP6Sender](struct.MuxIP6Sender.html)) that allows several transport
+//! layers (e.g. UDP and ICMPv6) to share a single `IP6Sender`.
+//!
+//! Packets to destinations that are not neighbors are sent to the next hop
+//! chosen by an [IP6Router](trait.IP6Router.html), such as RPL, if one is
+//! set.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -19,12 +25,14 @@
 use crate::ieee802154::device::{MacDevice, TxClient};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::neighbor_cache::NeighborCache;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
 
 use core::cell::Cell;
 
+use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::debug;
 use kernel::hil::time::{self, ConvertTicks};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
@@ -39,6 +47,14 @@ pub trait IP6SendClient {
     fn send_done(&self, result: Result<(), ErrorCode>);
 }
 
+/// This trait is implemented by routing protocols to choose the next hop of
+/// packets sent to destinations that are not on the local link.
+pub trait IP6Router {
+    /// Returns the (link-local) address of the neighbor that packets to
+    /// `dst` are sent to, or `None` if there is no route to `dst`.
+    fn next_hop(&self, dst: IPAddr) -> Option<IPAddr>;
+}
+
 /// This trait provides a basic IPv6 sending interface. It exposes basic
 /// configuration information for the IPv6 layer (setting the source address,
 /// setting the gateway MAC address), as well as a way to send an IPv6
@@ -60,8 +76,16 @@ pub trait IP6Sender<'a> {
     /// from this instance of `IP6Sender`
     fn set_addr(&self, src_addr: IPAddr);
 
+    /// This method sets the hop limit of packets sent from the `IP6Sender`
+    /// instance, e.g. to forward a packet with a decremented hop limit.
+    ///
+    /// # Arguments
+    /// `hop_limit` - Hop limit of subsequent packets
+    fn set_hop_limit(&self, hop_limit: u8);
+
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
+    /// instance. It is used for destinations that cannot be resolved through
+    /// a neighbor cache.
     ///
     /// # Arguments
     /// `gateway` - MAC address to send the constructed packet to
@@ -99,12 +123,14 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     // successful reception on receivers with slow copies out of the radio buffer
     // (imix)
     src_addr: Cell<IPAddr>,
+    hop_limit: Cell<u8>,
     gateway: Cell<MacAddress>,
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    neighbor_cache: OptionalCell<&'a NeighborCache>,
+    router: OptionalCell<&'a dyn IP6Router>,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
 }
@@ -118,6 +144,10 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.src_addr.set(src_addr);
     }
 
+    fn set_hop_limit(&self, hop_limit: u8) {
+        self.hop_limit.set(hop_limit);
+    }
+
     fn set_gateway(&self, gateway: MacAddress) {
         self.gateway.set(gateway);
     }
@@ -137,12 +167,23 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return Err(ErrorCode::FAIL);
         }
-        let _ = self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
+        // Only packets that leave the link are routed
+        let next_hop = if dst.is_multicast() || dst.is_unicast_link_local() {
+            dst
+        } else {
+            self.router
+                .map(|router| router.next_hop(dst))
+                .flatten()
+                .unwrap_or(dst)
+        };
+        let dst_mac_addr = self
+            .neighbor_cache
+            .map(|cache| cache.next_hop(next_hop))
+            .flatten()
+            .unwrap_or(self.gateway.get());
+        let _ = self
+            .sixlowpan
+            .init(self.src_mac_addr, dst_mac_addr, self.radio.get_pan(), None);
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
         ret
@@ -164,17 +205,32 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             ip6_packet: TakeCell::new(ip6_packet),
             alarm: alarm,
             src_addr: Cell::new(IPAddr::new()),
+            hop_limit: Cell::new(IP6Header::default().get_hop_limit()),
             gateway: Cell::new(dst_mac_addr),
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            neighbor_cache: OptionalCell::empty(),
+            router: OptionalCell::empty(),
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
         }
     }
 
+    /// Sets the neighbor cache used to find the destination MAC address of
+    /// each packet. Without one, all packets are sent to the gateway.
+    pub fn set_neighbor_cache(&self, neighbor_cache: &'a NeighborCache) {
+        self.neighbor_cache.set(neighbor_cache);
+    }
+
+    /// Sets the routing protocol that chooses the next hop of packets to
+    /// destinations off the local link. Without one, such packets are sent
+    /// to the default router of the neighbor cache.
+    pub fn set_router(&self, router: &'a dyn IP6Router) {
+        self.router.set(router);
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
@@ -188,6 +244,7 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             |ip6_packet| {
                 ip6_packet.header = IP6Header::default();
                 ip6_packet.header.src_addr = self.src_addr.get();
+                ip6_packet.header.set_hop_limit(self.hop_limit.get());
                 ip6_packet.header.dst_addr = dst_addr;
                 ip6_packet.set_payload(transport_header, payload);
                 ip6_packet.set_transport_checksum();
@@ -218,7 +275,10 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
                             } else {
                                 match self.radio.transmit(frame) {
                                     Ok(()) => (Ok(()), false),
-                                    Err((ecode, _buf)) => (Err(ecode), false),
+                                    Err((ecode, buf)) => {
+                                        self.tx_buf.replace(buf);
+                                        (Err(ecode), false)
+                                    }
                                 }
                             }
                         }
@@ -259,7 +319,13 @@ impl<'a, A: time::Alarm<'a>> time::AlarmClient for IP6SendStruct<'a, A> {
 }
 
 impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
-    fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+    fn send_done(
+        &self,
+        tx_buf: &'static mut [u8],
+        acked: bool,
+        _retries: u8,
+        result: Result<(), ErrorCode>,
+    ) {
         self.tx_buf.replace(tx_buf);
         if result != Ok(()) {
             debug!("Send Failed: {:?}, acked: {}", result, acked);
@@ -280,3 +346,200 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         }
     }
 }
+
+/// Virtualizes an `IP6Sender` so it can be shared by several users, each of
+/// which is an `IP6SendUser`. Only one packet is handed to the underlying
+/// sender at a time. A user that sends while another packet is in flight has
+/// its payload copied into its own buffer, and the packet is sent once the
+/// sender becomes free. Each user can have at most one packet queued.
+pub struct MuxIP6Sender<'a> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    users: List<'a, IP6SendUser<'a>>,
+    inflight: OptionalCell<&'a IP6SendUser<'a>>,
+    src_addr: Cell<IPAddr>,
+}
+
+impl<'a> MuxIP6Sender<'a> {
+    pub fn new(ip_sender: &'a dyn IP6Sender<'a>) -> MuxIP6Sender<'a> {
+        MuxIP6Sender {
+            ip_sender: ip_sender,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+            src_addr: Cell::new(IPAddr::new()),
+        }
+    }
+
+    /// Sets the source address of packets from users that have not set
+    /// their own.
+    pub fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    /// Passes a packet of `user` to the underlying sender.
+    fn send(
+        &self,
+        user: &'a IP6SendUser<'a>,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        // Set before sending, as the underlying sender may report completion
+        // synchronously
+        self.inflight.set(user);
+        self.ip_sender
+            .set_addr(user.src_addr.unwrap_or(self.src_addr.get()));
+        self.ip_sender.set_hop_limit(
+            user.hop_limit
+                .unwrap_or(IP6Header::default().get_hop_limit()),
+        );
+        let result = self
+            .ip_sender
+            .send_to(dst, transport_header, payload, net_cap);
+        if result.is_err() {
+            self.inflight.clear();
+        }
+        result
+    }
+
+    fn add_user(&self, user: &'a IP6SendUser<'a>) {
+        self.users.push_tail(user);
+    }
+
+    /// Sends queued packets until one is accepted by the underlying sender
+    /// or none are left.
+    fn send_next(&self) {
+        while self.inflight.is_none() {
+            let next = self.users.iter().find(|user| user.pending.is_some());
+            let user = match next {
+                Some(user) => user,
+                None => return,
+            };
+            let result = user.pending.take().map_or(Err(ErrorCode::FAIL), |pending| {
+                let (dst, transport_header, len, net_cap) = pending;
+                user.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+                    let mut payload = LeasableBuffer::new(buffer);
+                    payload.slice(0..len);
+                    let result = self.send(user, dst, transport_header, &payload, net_cap);
+                    user.buffer.replace(payload.take());
+                    result
+                })
+            });
+            if result.is_err() {
+                user.client.map(|client| client.send_done(result));
+            }
+        }
+    }
+}
+
+impl<'a> IP6SendClient for MuxIP6Sender<'a> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        self.inflight.take().map(|user| {
+            user.client.map(|client| client.send_done(result));
+        });
+        self.send_next();
+    }
+}
+
+/// A user of a `MuxIP6Sender`, which implements `IP6Sender`. The gateway is
+/// shared by all users of the same mux. The source address can be set per
+/// user, and otherwise is the one set on the mux. The hop limit is also set
+/// per user.
+pub struct IP6SendUser<'a> {
+    mux: &'a MuxIP6Sender<'a>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    src_addr: OptionalCell<IPAddr>,
+    hop_limit: OptionalCell<u8>,
+    buffer: TakeCell<'static, [u8]>,
+    pending: OptionalCell<(IPAddr, TransportHeader, usize, &'static NetworkCapability)>,
+    next: ListLink<'a, IP6SendUser<'a>>,
+}
+
+impl<'a> ListNode<'a, IP6SendUser<'a>> for IP6SendUser<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6SendUser<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> IP6SendUser<'a> {
+    /// `buffer` holds the payload of a packet that has to wait for the
+    /// underlying sender, so it limits the payload size of queued packets.
+    pub fn new(mux: &'a MuxIP6Sender<'a>, buffer: &'static mut [u8]) -> IP6SendUser<'a> {
+        IP6SendUser {
+            mux: mux,
+            client: OptionalCell::empty(),
+            src_addr: OptionalCell::empty(),
+            hop_limit: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            pending: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Registers this user with its mux. Must be called once, after the
+    /// user has been placed at its final (static) address.
+    pub fn setup(&'a self) {
+        self.mux.add_user(self);
+    }
+
+    fn is_inflight(&self) -> bool {
+        self.mux
+            .inflight
+            .map_or(false, |user| core::ptr::eq(*user, self))
+    }
+}
+
+impl<'a> IP6Sender<'a> for IP6SendUser<'a> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_hop_limit(&self, hop_limit: u8) {
+        self.hop_limit.set(hop_limit);
+    }
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        self.mux.ip_sender.set_gateway(gateway);
+    }
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {
+        // The header is built by the underlying sender for every packet, and
+        // cannot be changed through a shared user.
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if self.pending.is_some() || self.is_inflight() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.mux.inflight.is_none() {
+            // `send_to` only borrows `self`, so look up the registered user
+            let user = self
+                .mux
+                .users
+                .iter()
+                .find(|user| core::ptr::eq(*user, self))
+                .ok_or(ErrorCode::OFF)?;
+            return self.mux.send(user, dst, transport_header, payload, net_cap);
+        }
+        let len = payload.len();
+        self.buffer.map_or(Err(ErrorCode::NOMEM), |buffer| {
+            if len > buffer.len() {
+                return Err(ErrorCode::SIZE);
+            }
+            buffer[..len].copy_from_slice(&payload[..len]);
+            Ok(())
+        })?;
+        self.pending.set((dst, transport_header, len, net_cap));
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Multicast group membership of an IPv6 interface.
+//!
+//! The receive path of an interface (`IP6RecvStruct`) owns one
+//! `MulticastGroups` table. Datagrams sent to a multicast group are only
+//! passed up to the transport layer if the interface is a member of the group.
+//! Memberships are reference counted, so that several users (e.g. processes
+//! using the UDP driver) can join the same group, and the interface leaves the
+//! group only once all of them have left it.
+//!
+//! The interface is always a member of the all-nodes groups (ff01::1 and
+//! ff02::1, RFC 4291 section 2.8), which cannot be joined or left.
+//!
+//! ICMPv6 messages are not filtered by this table: Neighbor Discovery and RPL
+//! listen on well-known groups and check the destination address themselves.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+
+/// The maximum number of groups an interface can join, not counting the
+/// all-nodes groups.
+pub const MAX_GROUPS: usize = 4;
+
+const INTERFACE_LOCAL_ALL_NODES: IPAddr =
+    IPAddr([0xff, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+const LINK_LOCAL_ALL_NODES: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+#[derive(Copy, Clone)]
+struct Membership {
+    group: IPAddr,
+    users: usize,
+}
+
+pub struct MulticastGroups {
+    groups: [Cell<Option<Membership>>; MAX_GROUPS],
+}
+
+impl MulticastGroups {
+    pub fn new() -> MulticastGroups {
+        MulticastGroups {
+            groups: Default::default(),
+        }
+    }
+
+    fn is_permanent(group: IPAddr) -> bool {
+        group == INTERFACE_LOCAL_ALL_NODES || group == LINK_LOCAL_ALL_NODES
+    }
+
+    fn find(&self, group: IPAddr) -> Option<&Cell<Option<Membership>>> {
+        self.groups
+            .iter()
+            .find(|slot| slot.get().map_or(false, |m| m.group == group))
+    }
+
+    /// Adds a user to `group`, joining it if this is its first user.
+    /// Returns INVAL if `group` is not a multicast address, and NOMEM if the
+    /// interface is already a member of `MAX_GROUPS` groups.
+    pub fn join(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        if !group.is_multicast() {
+            return Err(ErrorCode::INVAL);
+        }
+        if Self::is_permanent(group) {
+            return Ok(());
+        }
+        if let Some(slot) = self.find(group) {
+            slot.set(slot.get().map(|m| Membership {
+                group: m.group,
+                users: m.users + 1,
+            }));
+            return Ok(());
+        }
+        let free = self
+            .groups
+            .iter()
+            .find(|slot| slot.get().is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        free.set(Some(Membership { group, users: 1 }));
+        Ok(())
+    }
+
+    /// Removes a user from `group`, leaving it if this was its last user.
+    /// Returns INVAL if the interface is not a member of `group`.
+    pub fn leave(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        if Self::is_permanent(group) {
+            return Ok(());
+        }
+        let slot = self.find(group).ok_or(ErrorCode::INVAL)?;
+        slot.set(slot.get().and_then(|m| {
+            if m.users > 1 {
+                Some(Membership {
+                    group: m.group,
+                    users: m.users - 1,
+                })
+            } else {
+                None
+            }
+        }));
+        Ok(())
+    }
+
+    /// Returns true if the interface is a member of `group`.
+    pub fn is_member(&self, group: IPAddr) -> bool {
+        Self::is_permanent(group) || self.find(group).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(id: u8) -> IPAddr {
+        IPAddr([0xff, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, id])
+    }
+
+    #[test]
+    fn membership_is_reference_counted() {
+        let groups = MulticastGroups::new();
+        assert!(!groups.is_member(group(1)));
+        assert_eq!(groups.join(group(1)), Ok(()));
+        assert_eq!(groups.join(group(1)), Ok(()));
+        assert!(groups.is_member(group(1)));
+        assert_eq!(groups.leave(group(1)), Ok(()));
+        assert!(groups.is_member(group(1)));
+        assert_eq!(groups.leave(group(1)), Ok(()));
+        assert!(!groups.is_member(group(1)));
+        assert_eq!(groups.leave(group(1)), Err(ErrorCode::INVAL));
+    }
+
+    #[test]
+    fn join_limits() {
+        let groups = MulticastGroups::new();
+        let mut unicast = group(1);
+        unicast.set_unicast_link_local();
+        assert_eq!(groups.join(unicast), Err(ErrorCode::INVAL));
+
+        for id in 0..MAX_GROUPS as u8 {
+            assert_eq!(groups.join(group(id)), Ok(()));
+        }
+        assert_eq!(groups.join(group(0xff)), Err(ErrorCode::NOMEM));
+        // The all-nodes groups do not take a slot
+        assert!(groups.is_member(LINK_LOCAL_ALL_NODES));
+        assert_eq!(groups.join(LINK_LOCAL_ALL_NODES), Ok(()));
+        assert_eq!(groups.leave(LINK_LOCAL_ALL_NODES), Ok(()));
+        assert!(groups.is_member(LINK_LOCAL_ALL_NODES));
+
+        assert_eq!(groups.leave(group(2)), Ok(()));
+        assert_eq!(groups.join(group(0xff)), Ok(()));
+    }
+}
@@ -0,0 +1,591 @@
+//! IPv6 Neighbor Discovery (RFC 4861) and stateless address
+//! autoconfiguration (RFC 4862) over 6LoWPAN or Ethernet.
+//!
+//! `NeighborDiscovery` manages the addresses of the interface:
+//!
+//! - On `start`, it forms a link-local address from the node's MAC address
+//!   and verifies it with duplicate address detection (DAD): a Neighbor
+//!   Solicitation for the tentative address is sent from the unspecified
+//!   address, and if no other node defends the address within
+//!   `RETRANS_TIMER_MS` the address becomes preferred.
+//! - Once the link-local address is preferred, Router Solicitations are sent
+//!   to the all-routers address until a Router Advertisement arrives.
+//! - Router Advertisements set the default router of the neighbor cache, and
+//!   each Prefix Information option with the autonomous flag forms a new
+//!   address from the prefix and the node's interface identifier, which
+//!   again goes through DAD.
+//! - Neighbor Solicitations for one of the node's addresses are answered
+//!   with a Neighbor Advertisement, and the link-layer address options of
+//!   all received messages update the neighbor cache used by the 6LoWPAN
+//!   or Ethernet sender.
+//!
+//! The link-layer address type of the interface, and so the format of the
+//! link-layer address options, is given by the `LinkAddress` type
+//! parameter, which defaults to 802.15.4 addresses.
+//!
+//! The most recently configured address is used as the default source
+//! address of the IPv6 send mux, so once a global address is configured it
+//! is used for all traffic.
+//!
+//! Known limitations: lifetimes of addresses and routers are not tracked,
+//! only one router is remembered, and the 6LoWPAN-specific registration
+//! mechanisms of RFC 6775 are not implemented. Address resolution is
+//! passive: link-layer addresses are only learned from received messages and
+//! from link-local addresses, and no Neighbor Solicitations are sent to
+//! resolve unknown neighbors.
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6Sender, MuxIP6Sender};
+use crate::net::ipv6::neighbor_cache::{LinkAddress, NeighborCache};
+use crate::net::ipv6::IP6Header;
+use crate::net::network_capabilities::NetworkCapability;
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// The maximum number of addresses of the interface.
+pub const MAX_ADDRESSES: usize = 4;
+/// Time to wait for a defending Neighbor Advertisement during DAD.
+pub const RETRANS_TIMER_MS: u32 = 1000;
+/// Number of Router Solicitations sent if no advertisement arrives.
+pub const MAX_RTR_SOLICITATIONS: u8 = 3;
+/// Time between Router Solicitations.
+pub const RTR_SOLICITATION_INTERVAL_MS: u32 = 4000;
+/// Delay before DAD of the link-local address starts after `start`.
+pub const START_DELAY_MS: u32 = 1000;
+/// The length of the buffer needed for the options of sent messages.
+pub const ND_BUF_LEN: usize = 16;
+
+pub const ALL_NODES_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+pub const ALL_ROUTERS_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+// Option types (RFC 4861, section 4.6)
+const OPT_SOURCE_LL_ADDR: u8 = 1;
+const OPT_TARGET_LL_ADDR: u8 = 2;
+const OPT_PREFIX_INFO: u8 = 3;
+
+// Neighbor Advertisement flags
+const NA_FLAG_SOLICITED: u32 = 0x4000_0000;
+const NA_FLAG_OVERRIDE: u32 = 0x2000_0000;
+
+// Prefix Information flags
+const PIO_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// NDP messages must be sent with, and are only accepted with, this hop
+/// limit, which guarantees that they were not forwarded by a router.
+const ND_HOP_LIMIT: u8 = 255;
+
+/// A trait for clients interested in the addresses of the interface.
+pub trait NeighborDiscoveryClient {
+    /// `addr` passed duplicate address detection and is now in use.
+    fn address_configured(&self, addr: IPAddr);
+
+    /// Another node uses `addr`, so it was not configured.
+    fn address_duplicated(&self, addr: IPAddr);
+}
+
+/// The addresses the interface currently answers to, for layers that must
+/// tell packets for the node from packets for other nodes.
+pub trait InterfaceAddresses {
+    /// Returns whether `addr` is a preferred address of the interface.
+    fn is_local(&self, addr: IPAddr) -> bool;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum AddrState {
+    Tentative,
+    Preferred,
+    Duplicate,
+}
+
+#[derive(Copy, Clone)]
+struct AddrEntry {
+    addr: IPAddr,
+    state: AddrState,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Message {
+    NeighborAdvertisement,
+    DadSolicitation,
+    RouterSolicitation,
+}
+
+pub struct NeighborDiscovery<'a, A: time::Alarm<'a>, L: LinkAddress + 'static = MacAddress> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    ip_send_mux: &'a MuxIP6Sender<'a>,
+    neighbor_cache: &'a NeighborCache<L>,
+    alarm: &'a A,
+    mac_addr: L,
+    net_cap: &'static NetworkCapability,
+    client: OptionalCell<&'a dyn NeighborDiscoveryClient>,
+    addresses: [Cell<Option<AddrEntry>>; MAX_ADDRESSES],
+    buffer: MapCell<LeasableBuffer<'static, u8>>,
+    in_flight: OptionalCell<Message>,
+    /// Address whose DAD solicitation was sent.
+    dad_index: OptionalCell<usize>,
+    /// Whether the DAD solicitation has been sent and the timer is running.
+    dad_waiting: Cell<bool>,
+    /// Neighbor Advertisement to send: destination, target and whether it
+    /// answers a solicitation.
+    na_pending: OptionalCell<(IPAddr, IPAddr, bool)>,
+    rs_pending: Cell<bool>,
+    rs_remaining: Cell<u8>,
+}
+
+impl<'a, A: time::Alarm<'a>, L: LinkAddress> NeighborDiscovery<'a, A, L> {
+    /// `ip_sender` must be the IPv6 sender used by `icmp_sender`; its source
+    /// address is set for each message.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        ip_sender: &'a dyn IP6Sender<'a>,
+        ip_send_mux: &'a MuxIP6Sender<'a>,
+        neighbor_cache: &'a NeighborCache<L>,
+        alarm: &'a A,
+        mac_addr: L,
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> NeighborDiscovery<'a, A, L> {
+        NeighborDiscovery {
+            icmp_sender: icmp_sender,
+            ip_sender: ip_sender,
+            ip_send_mux: ip_send_mux,
+            neighbor_cache: neighbor_cache,
+            alarm: alarm,
+            mac_addr: mac_addr,
+            net_cap: net_cap,
+            client: OptionalCell::empty(),
+            addresses: Default::default(),
+            buffer: MapCell::new(LeasableBuffer::new(buffer)),
+            in_flight: OptionalCell::empty(),
+            dad_index: OptionalCell::empty(),
+            dad_waiting: Cell::new(false),
+            na_pending: OptionalCell::empty(),
+            rs_pending: Cell::new(false),
+            rs_remaining: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn NeighborDiscoveryClient) {
+        self.client.set(client);
+    }
+
+    /// Starts configuring the link-local address.
+    pub fn start(&self) {
+        self.add_address(self.mac_addr.link_local());
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(START_DELAY_MS));
+    }
+
+    /// Returns the `index`th preferred address of the interface.
+    pub fn get_address(&self, index: usize) -> Option<IPAddr> {
+        self.addresses
+            .iter()
+            .filter_map(|entry| entry.get())
+            .filter(|entry| entry.state == AddrState::Preferred)
+            .map(|entry| entry.addr)
+            .nth(index)
+    }
+
+    fn find_address(&self, addr: IPAddr) -> Option<usize> {
+        self.addresses
+            .iter()
+            .position(|entry| entry.get().map_or(false, |entry| entry.addr == addr))
+    }
+
+    fn state_of(&self, index: usize) -> Option<AddrState> {
+        self.addresses[index].get().map(|entry| entry.state)
+    }
+
+    fn set_state(&self, index: usize, state: AddrState) {
+        if let Some(mut entry) = self.addresses[index].get() {
+            entry.state = state;
+            self.addresses[index].set(Some(entry));
+        }
+    }
+
+    /// Adds a tentative address, which is verified with DAD.
+    fn add_address(&self, addr: IPAddr) {
+        if self.find_address(addr).is_some() {
+            return;
+        }
+        // Duplicate addresses are only kept to avoid retrying them
+        let slot = self
+            .addresses
+            .iter()
+            .position(|entry| entry.get().is_none())
+            .or_else(|| {
+                self.addresses.iter().position(|entry| {
+                    entry
+                        .get()
+                        .map_or(false, |entry| entry.state == AddrState::Duplicate)
+                })
+            });
+        slot.map(|i| {
+            self.addresses[i].set(Some(AddrEntry {
+                addr,
+                state: AddrState::Tentative,
+            }))
+        });
+    }
+
+    fn link_local_addr(&self) -> Option<IPAddr> {
+        self.addresses
+            .iter()
+            .filter_map(|entry| entry.get())
+            .find(|entry| entry.state == AddrState::Preferred && entry.addr.is_unicast_link_local())
+            .map(|entry| entry.addr)
+    }
+
+    fn dad_succeeded(&self, index: usize) {
+        let addr = match self.addresses[index].get() {
+            Some(entry) if entry.state == AddrState::Tentative => entry.addr,
+            _ => return,
+        };
+        self.set_state(index, AddrState::Preferred);
+        self.ip_send_mux.set_addr(addr);
+        if addr.is_unicast_link_local() {
+            // Look for routers
+            self.rs_pending.set(true);
+            self.rs_remaining.set(MAX_RTR_SOLICITATIONS - 1);
+        }
+        self.client.map(|client| client.address_configured(addr));
+    }
+
+    fn dad_failed(&self, index: usize) {
+        let addr = match self.addresses[index].get() {
+            Some(entry) if entry.state == AddrState::Tentative => entry.addr,
+            _ => return,
+        };
+        self.set_state(index, AddrState::Duplicate);
+        if self.dad_index.contains(&index) {
+            self.dad_index.clear();
+            self.dad_waiting.set(false);
+        }
+        self.client.map(|client| client.address_duplicated(addr));
+    }
+
+    /// Sends the most urgent pending message, if the sender is free.
+    fn send_next(&self) {
+        if self.in_flight.is_some() {
+            return;
+        }
+        let dad_index = if self.dad_index.is_none() {
+            (0..MAX_ADDRESSES).find(|i| self.state_of(*i) == Some(AddrState::Tentative))
+        } else {
+            None
+        };
+
+        let (message, dst, header) = if let Some((dst, target, solicited)) = self.na_pending.take()
+        {
+            let mut flags = NA_FLAG_OVERRIDE;
+            if solicited {
+                flags |= NA_FLAG_SOLICITED;
+            }
+            let mut header = ICMP6Header::new(ICMP6Type::Type136);
+            header.set_options(ICMP6HeaderOptions::Type136 { flags, target });
+            (Message::NeighborAdvertisement, dst, header)
+        } else if let Some(index) = dad_index {
+            let target = match self.addresses[index].get() {
+                Some(entry) => entry.addr,
+                None => return,
+            };
+            self.dad_index.set(index);
+            let mut header = ICMP6Header::new(ICMP6Type::Type135);
+            header.set_options(ICMP6HeaderOptions::Type135 {
+                reserved: 0,
+                target,
+            });
+            (Message::DadSolicitation, target.solicited_node(), header)
+        } else if self.rs_pending.get() && self.link_local_addr().is_some() {
+            self.rs_pending.set(false);
+            (
+                Message::RouterSolicitation,
+                ALL_ROUTERS_ADDR,
+                ICMP6Header::new(ICMP6Type::Type133),
+            )
+        } else {
+            return;
+        };
+
+        // DAD solicitations are sent from the unspecified address, and must
+        // not carry a link-layer address option
+        let src_addr = match message {
+            Message::DadSolicitation => IPAddr::new(),
+            _ => self.link_local_addr().unwrap_or(IPAddr::new()),
+        };
+        self.ip_sender.set_addr(src_addr);
+
+        let result = self.buffer.take().map_or(Err(ErrorCode::BUSY), |mut buf| {
+            buf.reset();
+            let len = match message {
+                Message::NeighborAdvertisement => self
+                    .mac_addr
+                    .encode_option(&mut buf[..], OPT_TARGET_LL_ADDR),
+                Message::RouterSolicitation => self
+                    .mac_addr
+                    .encode_option(&mut buf[..], OPT_SOURCE_LL_ADDR),
+                Message::DadSolicitation => 0,
+            };
+            buf.slice(0..len);
+            self.in_flight.set(message);
+            match self.icmp_sender.send(dst, header, buf, self.net_cap) {
+                Ok(()) => Ok(()),
+                Err((e, buf)) => {
+                    self.buffer.replace(buf);
+                    self.in_flight.clear();
+                    Err(e)
+                }
+            }
+        });
+        if result.is_err() {
+            // Treat the message as lost and carry on, so that DAD and router
+            // solicitation still finish
+            self.sent(message);
+        }
+    }
+
+    /// Starts the timer that follows a sent message.
+    fn sent(&self, message: Message) {
+        match message {
+            Message::DadSolicitation => {
+                if self.dad_index.is_some() {
+                    self.dad_waiting.set(true);
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RETRANS_TIMER_MS));
+                }
+            }
+            Message::RouterSolicitation => {
+                if self.rs_remaining.get() > 0 && !self.dad_waiting.get() {
+                    self.alarm.set_alarm(
+                        self.alarm.now(),
+                        self.alarm.ticks_from_ms(RTR_SOLICITATION_INTERVAL_MS),
+                    );
+                }
+            }
+            Message::NeighborAdvertisement => {}
+        }
+    }
+
+    fn receive_router_advertisement(
+        &self,
+        ip_header: &IP6Header,
+        router_lifetime: u16,
+        options: &[u8],
+    ) {
+        let src_addr = ip_header.get_src_addr();
+        if !src_addr.is_unicast_link_local() {
+            return;
+        }
+        self.rs_pending.set(false);
+        self.rs_remaining.set(0);
+
+        for (option_type, option) in NdOptions::new(options) {
+            match option_type {
+                OPT_SOURCE_LL_ADDR => {
+                    L::decode_option(option)
+                        .map(|mac_addr| self.neighbor_cache.update(src_addr, mac_addr));
+                }
+                OPT_PREFIX_INFO => self.receive_prefix_info(option),
+                _ => {}
+            }
+        }
+
+        if router_lifetime > 0 {
+            self.neighbor_cache.set_default_router(Some(src_addr));
+        } else if self.neighbor_cache.default_router() == Some(src_addr) {
+            self.neighbor_cache.set_default_router(None);
+        }
+        self.send_next();
+    }
+
+    /// Forms an address from a Prefix Information option (RFC 4862,
+    /// section 5.5.3).
+    fn receive_prefix_info(&self, option: &[u8]) {
+        if option.len() != 32 {
+            return;
+        }
+        let prefix_len = option[2];
+        let flags = option[3];
+        let valid_lifetime = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+        let preferred_lifetime = u32::from_be_bytes([option[8], option[9], option[10], option[11]]);
+        let mut prefix = IPAddr::new();
+        prefix.0.copy_from_slice(&option[16..32]);
+
+        // The interface identifier is 64 bits long, so only /64 prefixes can
+        // be used
+        if flags & PIO_FLAG_AUTONOMOUS == 0
+            || prefix_len != 64
+            || prefix.is_unicast_link_local()
+            || valid_lifetime == 0
+            || preferred_lifetime > valid_lifetime
+        {
+            return;
+        }
+        let mut addr = self.mac_addr.link_local();
+        addr.set_prefix(&prefix.0, prefix_len);
+        self.add_address(addr);
+    }
+
+    fn receive_neighbor_solicitation(&self, ip_header: &IP6Header, target: IPAddr, options: &[u8]) {
+        let src_addr = ip_header.get_src_addr();
+        let dst_addr = ip_header.get_dst_addr();
+        if target.is_multicast() {
+            return;
+        }
+        // A solicitation from the unspecified address is part of DAD, and
+        // must be sent to the solicited-node address
+        if src_addr.is_unspecified() && dst_addr != target.solicited_node() {
+            return;
+        }
+        let index = match self.find_address(target) {
+            Some(index) => index,
+            None => return,
+        };
+        match self.state_of(index) {
+            Some(AddrState::Tentative) => {
+                // Another node is performing DAD for the same address
+                if src_addr.is_unspecified() {
+                    self.dad_failed(index);
+                }
+                return;
+            }
+            Some(AddrState::Preferred) => {}
+            _ => return,
+        }
+
+        if src_addr.is_unspecified() {
+            self.na_pending.set((ALL_NODES_ADDR, target, false));
+        } else {
+            for (option_type, option) in NdOptions::new(options) {
+                if option_type == OPT_SOURCE_LL_ADDR {
+                    L::decode_option(option)
+                        .map(|mac_addr| self.neighbor_cache.update(src_addr, mac_addr));
+                }
+            }
+            self.na_pending.set((src_addr, target, true));
+        }
+        self.send_next();
+    }
+
+    fn receive_neighbor_advertisement(&self, target: IPAddr, options: &[u8]) {
+        if target.is_multicast() {
+            return;
+        }
+        if let Some(index) = self.find_address(target) {
+            // Another node defends an address that is tentative here
+            self.dad_failed(index);
+            return;
+        }
+        for (option_type, option) in NdOptions::new(options) {
+            if option_type == OPT_TARGET_LL_ADDR {
+                L::decode_option(option)
+                    .map(|mac_addr| self.neighbor_cache.update(target, mac_addr));
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, L: LinkAddress> InterfaceAddresses for NeighborDiscovery<'a, A, L> {
+    fn is_local(&self, addr: IPAddr) -> bool {
+        self.find_address(addr)
+            .map_or(false, |i| self.state_of(i) == Some(AddrState::Preferred))
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, L: LinkAddress> ICMP6RecvClient for NeighborDiscovery<'a, A, L> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        if ip_header.get_hop_limit() != ND_HOP_LIMIT || icmp_header.get_code() != 0 {
+            return;
+        }
+        if !NdOptions::new(payload).is_valid() {
+            return;
+        }
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => self.receive_router_advertisement(&ip_header, router_lifetime, payload),
+            ICMP6HeaderOptions::Type135 { target, .. } => {
+                self.receive_neighbor_solicitation(&ip_header, target, payload)
+            }
+            ICMP6HeaderOptions::Type136 { target, .. } => {
+                self.receive_neighbor_advertisement(target, payload)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, L: LinkAddress> ICMP6SendClient for NeighborDiscovery<'a, A, L> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, buf: LeasableBuffer<'static, u8>) {
+        self.buffer.replace(buf);
+        self.in_flight.take().map(|message| self.sent(message));
+        self.send_next();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, L: LinkAddress> time::AlarmClient for NeighborDiscovery<'a, A, L> {
+    fn alarm(&self) {
+        if self.dad_waiting.get() {
+            self.dad_waiting.set(false);
+            self.dad_index.take().map(|index| self.dad_succeeded(index));
+        } else if self.rs_remaining.get() > 0 {
+            self.rs_remaining.set(self.rs_remaining.get() - 1);
+            self.rs_pending.set(true);
+        }
+        self.send_next();
+    }
+}
+
+/// Iterates over the options of an NDP message, yielding the type and the
+/// whole option (including the type and length bytes).
+struct NdOptions<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> NdOptions<'b> {
+    fn new(buf: &'b [u8]) -> NdOptions<'b> {
+        NdOptions { buf }
+    }
+
+    /// Messages with an option of length zero, or one that is longer than
+    /// the message, must be discarded.
+    fn is_valid(&self) -> bool {
+        let mut buf = self.buf;
+        while !buf.is_empty() {
+            if buf.len() < 2 || buf[1] == 0 || buf[1] as usize * 8 > buf.len() {
+                return false;
+            }
+            buf = &buf[buf[1] as usize * 8..];
+        }
+        true
+    }
+}
+
+impl<'b> Iterator for NdOptions<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'b [u8])> {
+        if self.buf.len() < 2 || self.buf[1] == 0 {
+            return None;
+        }
+        let len = self.buf[1] as usize * 8;
+        if len > self.buf.len() {
+            return None;
+        }
+        let (option, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some((option[0], option))
+    }
+}
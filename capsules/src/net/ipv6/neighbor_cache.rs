@@ -0,0 +1,217 @@
+//! Neighbor cache mapping IPv6 addresses to link-layer addresses.
+//!
+//! The cache is filled by Neighbor Discovery (`nd.rs`) from the link-layer
+//! address options of received Router Advertisements, Neighbor Solicitations
+//! and Neighbor Advertisements, and is used by the sender of the interface
+//! (6LoWPAN or Ethernet) to choose the destination MAC address of outgoing
+//! frames. It also records the default router learned from Router
+//! Advertisements.
+//!
+//! The cache is generic over the `LinkAddress` of the interface, and defaults
+//! to 802.15.4 addresses.
+//!
+//! A fixed size table is used. When it is full, the least recently used
+//! entry that is not the default router is replaced.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+
+use core::cell::Cell;
+
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// The broadcast short address, used for IPv6 multicast destinations.
+pub const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+/// The link-layer address of a network interface, as used by Neighbor
+/// Discovery and the neighbor cache.
+pub trait LinkAddress: Copy + PartialEq {
+    /// Returns the address that packets to the multicast address `group`
+    /// are sent to.
+    fn multicast(group: IPAddr) -> Self;
+
+    /// Returns the address that the interface identifier of the link-local
+    /// address `addr` is derived from, if any.
+    fn from_link_local(addr: IPAddr) -> Option<Self>;
+
+    /// Returns the link-local address formed from this address by stateless
+    /// address autoconfiguration.
+    fn link_local(&self) -> IPAddr;
+
+    /// Encodes a Source or Target Link-Layer Address option of type
+    /// `option_type` for this address into `buf`, and returns its length,
+    /// or 0 if it does not fit.
+    fn encode_option(&self, buf: &mut [u8], option_type: u8) -> usize;
+
+    /// Decodes a Source or Target Link-Layer Address option.
+    fn decode_option(option: &[u8]) -> Option<Self>;
+}
+
+impl LinkAddress for MacAddress {
+    fn multicast(_group: IPAddr) -> MacAddress {
+        BROADCAST_MAC_ADDR
+    }
+
+    /// Link-local addresses are derived from the MAC address of the node
+    /// (RFC 4944, section 6).
+    fn from_link_local(addr: IPAddr) -> Option<MacAddress> {
+        Some(addr.link_local_mac())
+    }
+
+    fn link_local(&self) -> IPAddr {
+        IPAddr::generate_from_mac(*self)
+    }
+
+    /// The option carries a short or extended address, padded to 8 or 16
+    /// bytes (RFC 4944, section 8).
+    fn encode_option(&self, buf: &mut [u8], option_type: u8) -> usize {
+        let len = match self {
+            MacAddress::Short(_) => 8,
+            MacAddress::Long(_) => 16,
+        };
+        if buf.len() < len {
+            return 0;
+        }
+        for b in buf[..len].iter_mut() {
+            *b = 0;
+        }
+        buf[0] = option_type;
+        buf[1] = (len / 8) as u8;
+        match self {
+            MacAddress::Short(short_addr) => buf[2..4].copy_from_slice(&short_addr.to_be_bytes()),
+            MacAddress::Long(long_addr) => buf[2..10].copy_from_slice(long_addr),
+        }
+        len
+    }
+
+    fn decode_option(option: &[u8]) -> Option<MacAddress> {
+        match option.len() {
+            8 => Some(MacAddress::Short(u16::from_be_bytes([
+                option[2], option[3],
+            ]))),
+            16 => {
+                let mut long_addr = [0; 8];
+                long_addr.copy_from_slice(&option[2..10]);
+                Some(MacAddress::Long(long_addr))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct NeighborEntry<L: LinkAddress = MacAddress> {
+    ip_addr: IPAddr,
+    mac_addr: L,
+    last_used: u32,
+}
+
+pub struct NeighborCache<L: LinkAddress + 'static = MacAddress> {
+    entries: TakeCell<'static, [Option<NeighborEntry<L>>]>,
+    default_router: OptionalCell<IPAddr>,
+    // Incremented on every access to order entries by last use
+    clock: Cell<u32>,
+}
+
+impl<L: LinkAddress> NeighborCache<L> {
+    pub fn new(entries: &'static mut [Option<NeighborEntry<L>>]) -> NeighborCache<L> {
+        NeighborCache {
+            entries: TakeCell::new(entries),
+            default_router: OptionalCell::empty(),
+            clock: Cell::new(0),
+        }
+    }
+
+    fn tick(&self) -> u32 {
+        let now = self.clock.get().wrapping_add(1);
+        self.clock.set(now);
+        now
+    }
+
+    /// Adds or updates the link-layer address of `ip_addr`.
+    pub fn update(&self, ip_addr: IPAddr, mac_addr: L) {
+        let now = self.tick();
+        let router = self.default_router.extract();
+        self.entries.map(|entries| {
+            let existing = entries
+                .iter()
+                .position(|entry| entry.map_or(false, |entry| entry.ip_addr == ip_addr));
+            let free = || entries.iter().position(|entry| entry.is_none());
+            let least_recently_used = || {
+                entries
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, entry)| entry.map(|entry| (i, entry)))
+                    .filter(|(_, entry)| router != Some(entry.ip_addr))
+                    .max_by_key(|(_, entry)| now.wrapping_sub(entry.last_used))
+                    .map(|(i, _)| i)
+            };
+            if let Some(i) = existing.or_else(free).or_else(least_recently_used) {
+                entries[i] = Some(NeighborEntry {
+                    ip_addr,
+                    mac_addr,
+                    last_used: now,
+                });
+            }
+        });
+    }
+
+    /// Returns the link-layer address of `ip_addr`, if known.
+    pub fn lookup(&self, ip_addr: IPAddr) -> Option<L> {
+        let now = self.tick();
+        self.entries
+            .map(|entries| {
+                entries.iter_mut().find_map(|entry| match entry {
+                    Some(entry) if entry.ip_addr == ip_addr => {
+                        entry.last_used = now;
+                        Some(entry.mac_addr)
+                    }
+                    _ => None,
+                })
+            })
+            .flatten()
+    }
+
+    pub fn remove(&self, ip_addr: IPAddr) {
+        self.entries.map(|entries| {
+            for entry in entries.iter_mut() {
+                if entry.map_or(false, |entry| entry.ip_addr == ip_addr) {
+                    *entry = None;
+                }
+            }
+        });
+        if self.default_router.contains(&ip_addr) {
+            self.default_router.clear();
+        }
+    }
+
+    /// Sets the router that packets to destinations off the link are sent
+    /// to. `None` removes the default router.
+    pub fn set_default_router(&self, router: Option<IPAddr>) {
+        self.default_router.insert(router);
+    }
+
+    pub fn default_router(&self) -> Option<IPAddr> {
+        self.default_router.extract()
+    }
+
+    /// Returns the link-layer address to send a packet for `dst` to.
+    /// Multicast destinations map to `LinkAddress::multicast`. Other
+    /// destinations are looked up in the cache; link-local addresses that
+    /// are not cached are assumed to be derived from the MAC address of the
+    /// neighbor. Any other destination is sent to the default router.
+    pub fn next_hop(&self, dst: IPAddr) -> Option<L> {
+        if dst.is_multicast() {
+            return Some(L::multicast(dst));
+        }
+        self.lookup(dst)
+            .or_else(|| {
+                if dst.is_unicast_link_local() {
+                    L::from_link_local(dst)
+                } else {
+                    None
+                }
+            })
+            .or_else(|| self.default_router().and_then(|router| self.lookup(router)))
+    }
+}
@@ -0,0 +1,495 @@
+//! Encoding and decoding of RPL control messages (RFC 6550, section 6).
+//!
+//! RPL control messages are ICMPv6 messages of type 155. The ICMPv6 code
+//! selects the message, whose base object directly follows the ICMPv6
+//! checksum and is followed by options. Only the messages and options used
+//! by the storing mode implementation in `rpl.rs` are supported, and secure
+//! variants of the messages are not.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+/// ICMPv6 codes of RPL control messages
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+/// RPL control message option types
+pub mod rpl_option {
+    pub const PAD1: u8 = 0x00;
+    pub const PADN: u8 = 0x01;
+    pub const DODAG_CONFIG: u8 = 0x04;
+    pub const TARGET: u8 = 0x05;
+    pub const TRANSIT_INFO: u8 = 0x06;
+    pub const PREFIX_INFO: u8 = 0x08;
+}
+
+/// The multicast address DIOs and DISs are sent to.
+pub const ALL_RPL_NODES_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Mode of Operation: storing mode without multicast support
+pub const MOP_STORING: u8 = 2;
+
+pub const INFINITE_RANK: u16 = 0xffff;
+
+const DIO_GROUNDED: u8 = 0x80;
+const DAO_ACK_REQUESTED: u8 = 0x80;
+const DAO_DODAG_ID_PRESENT: u8 = 0x40;
+const PIO_AUTONOMOUS: u8 = 0x40;
+
+/// The base object of a DODAG Information Object.
+#[derive(Copy, Clone)]
+pub struct Dio {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    pub mode_of_operation: u8,
+    pub preference: u8,
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+}
+
+impl Dio {
+    pub const LEN: usize = 24;
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, offset + Self::LEN);
+        let mut flags = (self.mode_of_operation & 0x7) << 3 | (self.preference & 0x7);
+        if self.grounded {
+            flags |= DIO_GROUNDED;
+        }
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, self.version);
+        off = enc_consume!(buf, off; encode_u16, self.rank);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, self.dtsn);
+        // Flags and reserved
+        off = enc_consume!(buf, off; encode_u16, 0);
+        off = enc_consume!(buf, off; encode_bytes, &self.dodag_id.0);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Dio> {
+        stream_len_cond!(buf, Self::LEN);
+        let off = 0;
+        let (off, instance_id) = dec_try!(buf, off; decode_u8);
+        let (off, version) = dec_try!(buf, off; decode_u8);
+        let (off, rank) = dec_try!(buf, off; decode_u16);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, dtsn) = dec_try!(buf, off; decode_u8);
+        let (off, _) = dec_try!(buf, off; decode_u16);
+        let mut dodag_id = IPAddr::new();
+        let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+        stream_done!(
+            off,
+            Dio {
+                instance_id,
+                version,
+                rank,
+                grounded: flags & DIO_GROUNDED != 0,
+                mode_of_operation: (flags >> 3) & 0x7,
+                preference: flags & 0x7,
+                dtsn,
+                dodag_id,
+            }
+        );
+    }
+}
+
+/// The DODAG Configuration option, which distributes the parameters of the
+/// DODAG.
+#[derive(Copy, Clone)]
+pub struct DodagConfig {
+    pub dio_interval_doublings: u8,
+    pub dio_interval_min: u8,
+    pub dio_redundancy: u8,
+    pub max_rank_increase: u16,
+    pub min_hop_rank_increase: u16,
+    pub objective_code_point: u16,
+    pub default_lifetime: u8,
+    pub lifetime_unit: u16,
+}
+
+impl DodagConfig {
+    pub const LEN: usize = 16;
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, offset + Self::LEN);
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, rpl_option::DODAG_CONFIG);
+        off = enc_consume!(buf, off; encode_u8, (Self::LEN - 2) as u8);
+        // Flags, authentication disabled
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u8, self.dio_interval_doublings);
+        off = enc_consume!(buf, off; encode_u8, self.dio_interval_min);
+        off = enc_consume!(buf, off; encode_u8, self.dio_redundancy);
+        off = enc_consume!(buf, off; encode_u16, self.max_rank_increase);
+        off = enc_consume!(buf, off; encode_u16, self.min_hop_rank_increase);
+        off = enc_consume!(buf, off; encode_u16, self.objective_code_point);
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u8, self.default_lifetime);
+        off = enc_consume!(buf, off; encode_u16, self.lifetime_unit);
+        stream_done!(off, off);
+    }
+
+    /// Decodes the body of the option, following the type and length.
+    pub fn decode(buf: &[u8]) -> SResult<DodagConfig> {
+        stream_len_cond!(buf, Self::LEN - 2);
+        let off = 1;
+        let (off, dio_interval_doublings) = dec_try!(buf, off; decode_u8);
+        let (off, dio_interval_min) = dec_try!(buf, off; decode_u8);
+        let (off, dio_redundancy) = dec_try!(buf, off; decode_u8);
+        let (off, max_rank_increase) = dec_try!(buf, off; decode_u16);
+        let (off, min_hop_rank_increase) = dec_try!(buf, off; decode_u16);
+        let (off, objective_code_point) = dec_try!(buf, off; decode_u16);
+        let (off, _) = dec_try!(buf, off; decode_u8);
+        let (off, default_lifetime) = dec_try!(buf, off; decode_u8);
+        let (off, lifetime_unit) = dec_try!(buf, off; decode_u16);
+        stream_done!(
+            off,
+            DodagConfig {
+                dio_interval_doublings,
+                dio_interval_min,
+                dio_redundancy,
+                max_rank_increase,
+                min_hop_rank_increase,
+                objective_code_point,
+                default_lifetime,
+                lifetime_unit,
+            }
+        );
+    }
+}
+
+/// The Prefix Information option, which has the same layout as the NDP
+/// option (RFC 4861, section 4.6.2).
+#[derive(Copy, Clone)]
+pub struct PrefixInfo {
+    pub prefix_len: u8,
+    pub autonomous: bool,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+    pub prefix: IPAddr,
+}
+
+impl PrefixInfo {
+    pub const LEN: usize = 32;
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, offset + Self::LEN);
+        let flags = if self.autonomous { PIO_AUTONOMOUS } else { 0 };
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, rpl_option::PREFIX_INFO);
+        off = enc_consume!(buf, off; encode_u8, (Self::LEN - 2) as u8);
+        off = enc_consume!(buf, off; encode_u8, self.prefix_len);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u32, self.valid_lifetime);
+        off = enc_consume!(buf, off; encode_u32, self.preferred_lifetime);
+        off = enc_consume!(buf, off; encode_u32, 0);
+        off = enc_consume!(buf, off; encode_bytes, &self.prefix.0);
+        stream_done!(off, off);
+    }
+
+    /// Decodes the body of the option, following the type and length.
+    pub fn decode(buf: &[u8]) -> SResult<PrefixInfo> {
+        stream_len_cond!(buf, Self::LEN - 2);
+        let off = 0;
+        let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+        let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+        let (off, _) = dec_try!(buf, off; decode_u32);
+        let mut prefix = IPAddr::new();
+        let off = dec_consume!(buf, off; decode_bytes, &mut prefix.0);
+        stream_done!(
+            off,
+            PrefixInfo {
+                prefix_len,
+                autonomous: flags & PIO_AUTONOMOUS != 0,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            }
+        );
+    }
+}
+
+/// The base object of a Destination Advertisement Object.
+#[derive(Copy, Clone)]
+pub struct Dao {
+    pub instance_id: u8,
+    pub ack_requested: bool,
+    pub sequence: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl Dao {
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut flags = 0;
+        if self.ack_requested {
+            flags |= DAO_ACK_REQUESTED;
+        }
+        if self.dodag_id.is_some() {
+            flags |= DAO_DODAG_ID_PRESENT;
+        }
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u8, self.sequence);
+        if let Some(dodag_id) = self.dodag_id {
+            off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+        }
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Dao> {
+        let off = 0;
+        let (off, instance_id) = dec_try!(buf, off; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, _) = dec_try!(buf, off; decode_u8);
+        let (off, sequence) = dec_try!(buf, off; decode_u8);
+        let (off, dodag_id) = if flags & DAO_DODAG_ID_PRESENT != 0 {
+            let mut dodag_id = IPAddr::new();
+            let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+            (off, Some(dodag_id))
+        } else {
+            (off, None)
+        };
+        stream_done!(
+            off,
+            Dao {
+                instance_id,
+                ack_requested: flags & DAO_ACK_REQUESTED != 0,
+                sequence,
+                dodag_id,
+            }
+        );
+    }
+}
+
+/// The base object of a DAO acknowledgement. The DODAG ID is never included.
+#[derive(Copy, Clone)]
+pub struct DaoAck {
+    pub instance_id: u8,
+    pub sequence: u8,
+    pub status: u8,
+}
+
+impl DaoAck {
+    pub const LEN: usize = 4;
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u8, self.sequence);
+        off = enc_consume!(buf, off; encode_u8, self.status);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<DaoAck> {
+        let off = 0;
+        let (off, instance_id) = dec_try!(buf, off; decode_u8);
+        let (off, _) = dec_try!(buf, off; decode_u8);
+        let (off, sequence) = dec_try!(buf, off; decode_u8);
+        let (off, status) = dec_try!(buf, off; decode_u8);
+        stream_done!(
+            off,
+            DaoAck {
+                instance_id,
+                sequence,
+                status,
+            }
+        );
+    }
+}
+
+/// Length of an RPL Target option for a full (/128) address.
+pub const TARGET_LEN: usize = 20;
+
+/// Encodes an RPL Target option advertising the address `target`.
+pub fn encode_target(buf: &mut [u8], offset: usize, target: IPAddr) -> SResult<usize> {
+    let mut off = offset;
+    off = enc_consume!(buf, off; encode_u8, rpl_option::TARGET);
+    off = enc_consume!(buf, off; encode_u8, (TARGET_LEN - 2) as u8);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    off = enc_consume!(buf, off; encode_u8, 128);
+    off = enc_consume!(buf, off; encode_bytes, &target.0);
+    stream_done!(off, off);
+}
+
+/// Decodes the body of an RPL Target option. Targets are padded with zeroes
+/// to a full address.
+pub fn decode_target(buf: &[u8]) -> SResult<(u8, IPAddr)> {
+    let off = 1;
+    let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+    let prefix_bytes = (prefix_len as usize + 7) / 8;
+    stream_cond!(prefix_len <= 128);
+    stream_len_cond!(buf, off + prefix_bytes);
+    let mut target = IPAddr::new();
+    target.0[..prefix_bytes].copy_from_slice(&buf[off..off + prefix_bytes]);
+    stream_done!(off + prefix_bytes, (prefix_len, target));
+}
+
+/// Length of a Transit Information option in storing mode.
+pub const TRANSIT_INFO_LEN: usize = 6;
+
+/// Encodes a Transit Information option. A `path_lifetime` of zero
+/// withdraws the preceding targets (a No-Path DAO).
+pub fn encode_transit_info(
+    buf: &mut [u8],
+    offset: usize,
+    path_sequence: u8,
+    path_lifetime: u8,
+) -> SResult<usize> {
+    let mut off = offset;
+    off = enc_consume!(buf, off; encode_u8, rpl_option::TRANSIT_INFO);
+    off = enc_consume!(buf, off; encode_u8, (TRANSIT_INFO_LEN - 2) as u8);
+    // No external flag, no path control
+    off = enc_consume!(buf, off; encode_u8, 0);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    off = enc_consume!(buf, off; encode_u8, path_sequence);
+    off = enc_consume!(buf, off; encode_u8, path_lifetime);
+    stream_done!(off, off);
+}
+
+/// Decodes the path lifetime from the body of a Transit Information option.
+pub fn decode_transit_info(buf: &[u8]) -> SResult<u8> {
+    stream_len_cond!(buf, 4);
+    stream_done!(4, buf[3]);
+}
+
+/// Iterates over the options of an RPL control message, yielding the type
+/// and the body of each option. Padding options are skipped.
+pub struct RplOptions<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> RplOptions<'b> {
+    pub fn new(buf: &'b [u8]) -> RplOptions<'b> {
+        RplOptions { buf }
+    }
+}
+
+impl<'b> Iterator for RplOptions<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'b [u8])> {
+        loop {
+            match self.buf.first() {
+                None => return None,
+                Some(&rpl_option::PAD1) => {
+                    self.buf = &self.buf[1..];
+                    continue;
+                }
+                Some(_) => {}
+            }
+            if self.buf.len() < 2 || self.buf.len() < 2 + self.buf[1] as usize {
+                // Truncated option
+                self.buf = &[];
+                return None;
+            }
+            let option_type = self.buf[0];
+            let (option, rest) = self.buf.split_at(2 + self.buf[1] as usize);
+            self.buf = rest;
+            if option_type != rpl_option::PADN {
+                return Some((option_type, &option[2..]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DODAG_ID: IPAddr = IPAddr([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 1]);
+
+    #[test]
+    fn dio_round_trip() {
+        let dio = Dio {
+            instance_id: 1,
+            version: 240,
+            rank: 256,
+            grounded: true,
+            mode_of_operation: MOP_STORING,
+            preference: 0,
+            dtsn: 7,
+            dodag_id: DODAG_ID,
+        };
+        let mut buf = [0; Dio::LEN + PrefixInfo::LEN];
+        let (off, _) = dio.encode(&mut buf, 0).done().unwrap();
+        assert_eq!(off, Dio::LEN);
+        assert_eq!(buf[..6], [1, 240, 0x01, 0x00, 0x90, 7]);
+
+        let pio = PrefixInfo {
+            prefix_len: 64,
+            autonomous: true,
+            valid_lifetime: 0xffffffff,
+            preferred_lifetime: 0xffffffff,
+            prefix: DODAG_ID,
+        };
+        let (off, _) = pio.encode(&mut buf, off).done().unwrap();
+        assert_eq!(off, buf.len());
+
+        let (off, decoded) = Dio::decode(&buf).done().unwrap();
+        assert_eq!(off, Dio::LEN);
+        assert_eq!(decoded.rank, 256);
+        assert_eq!(decoded.mode_of_operation, MOP_STORING);
+        assert!(decoded.grounded);
+        assert!(decoded.dodag_id == DODAG_ID);
+
+        let mut options = RplOptions::new(&buf[off..]);
+        let (option_type, body) = options.next().unwrap();
+        assert_eq!(option_type, rpl_option::PREFIX_INFO);
+        let (_, decoded) = PrefixInfo::decode(body).done().unwrap();
+        assert_eq!(decoded.prefix_len, 64);
+        assert!(decoded.autonomous);
+        assert!(decoded.prefix == DODAG_ID);
+        assert!(options.next().is_none());
+    }
+
+    #[test]
+    fn dao_with_padding() {
+        // DAO without DODAG ID, a Pad1, a target and a transit information
+        // option
+        let mut buf = [0; 4 + 1 + TARGET_LEN + TRANSIT_INFO_LEN];
+        let dao = Dao {
+            instance_id: 1,
+            ack_requested: true,
+            sequence: 3,
+            dodag_id: None,
+        };
+        let (off, _) = dao.encode(&mut buf, 0).done().unwrap();
+        buf[off] = rpl_option::PAD1;
+        let (off, _) = encode_target(&mut buf, off + 1, DODAG_ID).done().unwrap();
+        let (off, _) = encode_transit_info(&mut buf, off, 0, 30).done().unwrap();
+        assert_eq!(off, buf.len());
+
+        let (off, decoded) = Dao::decode(&buf).done().unwrap();
+        assert_eq!(off, 4);
+        assert!(decoded.ack_requested);
+        assert_eq!(decoded.sequence, 3);
+        assert!(decoded.dodag_id.is_none());
+
+        let mut options = RplOptions::new(&buf[off..]);
+        let (option_type, body) = options.next().unwrap();
+        assert_eq!(option_type, rpl_option::TARGET);
+        let (_, (prefix_len, target)) = decode_target(body).done().unwrap();
+        assert_eq!(prefix_len, 128);
+        assert!(target == DODAG_ID);
+        let (option_type, body) = options.next().unwrap();
+        assert_eq!(option_type, rpl_option::TRANSIT_INFO);
+        assert_eq!(decode_transit_info(body).done().unwrap().1, 30);
+        assert!(options.next().is_none());
+    }
+}
@@ -0,0 +1,7 @@
+//! RPL, the IPv6 Routing Protocol for Low-Power and Lossy Networks
+//! (RFC 6550), which builds multi-hop routes over 6LoWPAN.
+
+pub mod messages;
+pub mod rpl;
+
+pub use self::rpl::Rpl;
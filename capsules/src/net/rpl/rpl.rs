@@ -0,0 +1,861 @@
+//! An RPL router (RFC 6550) in storing mode.
+//!
+//! `Rpl` joins a single DODAG, or acts as its root, and provides routes
+//! for packets that leave the local link:
+//!
+//! - DIOs are sent to all RPL nodes, paced by a trickle timer (RFC 6206).
+//!   A node that is not part of a DODAG sends DISs to solicit DIOs.
+//! - A received DIO makes its sender the preferred parent if that lowers
+//!   the rank of the node. Ranks are computed with Objective Function Zero
+//!   (RFC 6552), with the default step of rank.
+//! - DAOs advertise the global address of the node, and the targets of its
+//!   own routing table, to the preferred parent, which stores a route to
+//!   each target through the child (storing mode). The root has routes to
+//!   all nodes of the DODAG.
+//! - `Rpl` is the `IP6Router` of the IPv6 sender: packets are sent along
+//!   the downward route to their destination if there is one, and to the
+//!   preferred parent otherwise.
+//! - `Rpl` is the `IP6Forwarder` of the IPv6 receiver, and re-sends packets
+//!   that are not addressed to the node with a decremented hop limit.
+//!
+//! If a DIO carries a Prefix Information option with the autonomous flag,
+//! the node forms its global address from the prefix and its MAC address.
+//! Packets for the addresses configured by Neighbor Discovery are not
+//! forwarded either, once it is passed to `set_interface_addresses`.
+//!
+//! Time is kept in ticks of `TICK_MS`, so the timer fires periodically
+//! while RPL is running.
+//!
+//! Known limitations: only one parent is tracked, so a node detaches when
+//! its parent advertises an infinite rank, rather than switching to a
+//! backup parent. Parents that disappear silently are not detected. The RPL
+//! hop-by-hop option (RFC 6553) is not added to forwarded packets, and only
+//! UDP and ICMPv6 packets are forwarded.
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6Forwarder;
+use crate::net::ipv6::ipv6_send::{IP6Router, IP6Sender, MuxIP6Sender};
+use crate::net::ipv6::nd::InterfaceAddresses;
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::rpl::messages::{
+    decode_target, decode_transit_info, encode_target, encode_transit_info, rpl_code, rpl_option,
+    Dao, DaoAck, Dio, DodagConfig, PrefixInfo, RplOptions, ALL_RPL_NODES_ADDR, INFINITE_RANK,
+    MOP_STORING, TARGET_LEN, TRANSIT_INFO_LEN,
+};
+use crate::net::udp::UDPHeader;
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// Length of the timer tick all RPL timers are counted in.
+pub const TICK_MS: u32 = 500;
+/// The length of the buffer needed for control messages.
+pub const RPL_BUF_LEN: usize = 128;
+
+const DIS_INTERVAL_TICKS: u32 = 20;
+/// Delay of a DAO after a change of the routes, to aggregate changes.
+const DAO_DELAY_TICKS: u32 = 2;
+
+const RPL_INSTANCE_ID: u8 = 0;
+const ROOT_RANK: u16 = 256;
+/// Objective Function Zero
+const OCP_OF0: u16 = 0;
+const OF0_DEFAULT_STEP_OF_RANK: u16 = 3;
+
+/// The DODAG parameters announced by a root.
+const DEFAULT_CONFIG: DodagConfig = DodagConfig {
+    dio_interval_doublings: 8,
+    // 2^12 ms
+    dio_interval_min: 12,
+    dio_redundancy: 10,
+    max_rank_increase: 7 * 256,
+    min_hop_rank_increase: 256,
+    objective_code_point: OCP_OF0,
+    default_lifetime: 30,
+    lifetime_unit: 60,
+};
+
+/// A downward route, learned from a DAO.
+#[derive(Copy, Clone)]
+pub struct Route {
+    target: IPAddr,
+    next_hop: IPAddr,
+    lifetime_ticks: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Dodag {
+    instance_id: u8,
+    version: u8,
+    grounded: bool,
+    dodag_id: IPAddr,
+    config: DodagConfig,
+    prefix: Option<PrefixInfo>,
+}
+
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    dtsn: u8,
+}
+
+pub struct Rpl<'a, A: time::Alarm<'a>> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    forward_sender: &'a dyn IP6Sender<'a>,
+    ip_send_mux: &'a MuxIP6Sender<'a>,
+    alarm: &'a A,
+    mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    addresses: OptionalCell<&'a dyn InterfaceAddresses>,
+    net_cap: &'static NetworkCapability,
+    routes: TakeCell<'static, [Option<Route>]>,
+    buffer: MapCell<LeasableBuffer<'static, u8>>,
+    forward_buffer: TakeCell<'static, [u8]>,
+    in_flight: Cell<bool>,
+
+    root: Cell<bool>,
+    dodag: OptionalCell<Dodag>,
+    parent: OptionalCell<Parent>,
+    rank: Cell<u16>,
+    global_addr: OptionalCell<IPAddr>,
+    dtsn: Cell<u8>,
+    dao_sequence: Cell<u8>,
+    path_sequence: Cell<u8>,
+
+    // Trickle timer state, in ticks
+    trickle_interval: Cell<u32>,
+    trickle_elapsed: Cell<u32>,
+    trickle_send_at: Cell<u32>,
+    trickle_counter: Cell<u8>,
+    dao_countdown: Cell<u32>,
+    dis_countdown: Cell<u32>,
+    rng_state: Cell<u32>,
+
+    // Pending control messages
+    dio_pending: OptionalCell<IPAddr>,
+    dis_pending: Cell<bool>,
+    dao_pending: Cell<bool>,
+    dao_ack_pending: OptionalCell<(IPAddr, u8)>,
+}
+
+impl<'a, A: time::Alarm<'a>> Rpl<'a, A> {
+    /// `forward_sender` is used to re-send forwarded packets, and must not
+    /// be used by anything else, as its source address is changed for each
+    /// packet.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        forward_sender: &'a dyn IP6Sender<'a>,
+        ip_send_mux: &'a MuxIP6Sender<'a>,
+        alarm: &'a A,
+        mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        routes: &'static mut [Option<Route>],
+        buffer: &'static mut [u8],
+        forward_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> Rpl<'a, A> {
+        // Seed the trickle timer jitter with the MAC address, so that
+        // neighbors do not send at the same time
+        let seed = match mac_addr {
+            MacAddress::Short(addr) => addr as u32,
+            MacAddress::Long(addr) => u32::from_be_bytes([addr[4], addr[5], addr[6], addr[7]]),
+        };
+        Rpl {
+            icmp_sender: icmp_sender,
+            forward_sender: forward_sender,
+            ip_send_mux: ip_send_mux,
+            alarm: alarm,
+            mac_addr: mac_addr,
+            interface_list: interface_list,
+            addresses: OptionalCell::empty(),
+            net_cap: net_cap,
+            routes: TakeCell::new(routes),
+            buffer: MapCell::new(LeasableBuffer::new(buffer)),
+            forward_buffer: TakeCell::new(forward_buffer),
+            in_flight: Cell::new(false),
+            root: Cell::new(false),
+            dodag: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            rank: Cell::new(INFINITE_RANK),
+            global_addr: OptionalCell::empty(),
+            dtsn: Cell::new(0),
+            dao_sequence: Cell::new(0),
+            path_sequence: Cell::new(0),
+            trickle_interval: Cell::new(1),
+            trickle_elapsed: Cell::new(0),
+            trickle_send_at: Cell::new(0),
+            trickle_counter: Cell::new(0),
+            dao_countdown: Cell::new(0),
+            dis_countdown: Cell::new(0),
+            rng_state: Cell::new(seed | 1),
+            dio_pending: OptionalCell::empty(),
+            dis_pending: Cell::new(false),
+            dao_pending: Cell::new(false),
+            dao_ack_pending: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the addresses configured on the interface at runtime, e.g. by
+    /// `NeighborDiscovery`, which are local in addition to the interface list.
+    pub fn set_interface_addresses(&self, addresses: &'a dyn InterfaceAddresses) {
+        self.addresses.set(addresses);
+    }
+
+    /// Starts RPL as a router, which looks for a DODAG to join.
+    pub fn start(&self) {
+        self.dis_pending.set(true);
+        self.dis_countdown.set(DIS_INTERVAL_TICKS);
+        self.start_timer();
+        self.send_next();
+    }
+
+    /// Starts RPL as the root of a new grounded DODAG. `dodag_id` must be a
+    /// global address of the root; its /64 prefix is announced for address
+    /// autoconfiguration.
+    pub fn start_root(&self, dodag_id: IPAddr) {
+        let mut prefix = IPAddr::new();
+        prefix.set_prefix(&dodag_id.0, 64);
+        self.root.set(true);
+        self.dodag.set(Dodag {
+            instance_id: RPL_INSTANCE_ID,
+            version: 0,
+            grounded: true,
+            dodag_id,
+            config: DEFAULT_CONFIG,
+            prefix: Some(PrefixInfo {
+                prefix_len: 64,
+                autonomous: true,
+                valid_lifetime: 0xffffffff,
+                preferred_lifetime: 0xffffffff,
+                prefix,
+            }),
+        });
+        self.rank.set(ROOT_RANK);
+        self.set_global_addr(dodag_id);
+        self.reset_trickle();
+        self.start_timer();
+    }
+
+    /// Returns the rank of the node, which is `INFINITE_RANK` if it is not
+    /// part of a DODAG.
+    pub fn get_rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    /// Returns the preferred parent of the node.
+    pub fn get_parent(&self) -> Option<IPAddr> {
+        self.parent.map(|parent| parent.addr)
+    }
+
+    fn start_timer(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(TICK_MS));
+    }
+
+    fn joined(&self) -> bool {
+        self.root.get() || self.parent.is_some()
+    }
+
+    fn is_local(&self, addr: IPAddr) -> bool {
+        addr == IPAddr::generate_from_mac(self.mac_addr)
+            || self.global_addr.contains(&addr)
+            || self.interface_list.iter().any(|iface| *iface == addr)
+            || self
+                .addresses
+                .map_or(false, |addresses| addresses.is_local(addr))
+    }
+
+    fn set_global_addr(&self, addr: IPAddr) {
+        if !self.global_addr.contains(&addr) {
+            self.global_addr.set(addr);
+            self.ip_send_mux.set_addr(addr);
+        }
+    }
+
+    fn random(&self) -> u32 {
+        // xorshift32
+        let mut x = self.rng_state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state.set(x);
+        x
+    }
+
+    fn update_dodag<F: FnOnce(&mut Dodag)>(&self, f: F) {
+        if let Some(mut dodag) = self.dodag.extract() {
+            f(&mut dodag);
+            self.dodag.set(dodag);
+        }
+    }
+
+    fn config(&self) -> DodagConfig {
+        self.dodag.map_or(DEFAULT_CONFIG, |dodag| dodag.config)
+    }
+
+    fn ms_to_ticks(ms: u32) -> u32 {
+        core::cmp::max(ms / TICK_MS, 1)
+    }
+
+    /// The lifetime of routes advertised in DAOs, in ticks.
+    fn route_lifetime_ticks(&self, path_lifetime: u8) -> u32 {
+        let config = self.config();
+        (path_lifetime as u32 * config.lifetime_unit as u32).saturating_mul(1000 / TICK_MS)
+    }
+
+    /// Computes the rank of the node through a parent with `parent_rank`
+    /// with Objective Function Zero.
+    fn rank_through(&self, parent_rank: u16) -> u16 {
+        let increase = OF0_DEFAULT_STEP_OF_RANK.saturating_mul(self.config().min_hop_rank_increase);
+        let rank = parent_rank.saturating_add(increase);
+        if rank == 0 {
+            INFINITE_RANK
+        } else {
+            rank
+        }
+    }
+
+    fn reset_trickle(&self) {
+        let config = self.config();
+        self.trickle_interval
+            .set(Self::ms_to_ticks(1 << config.dio_interval_min.min(31)));
+        self.start_trickle_interval();
+    }
+
+    fn start_trickle_interval(&self) {
+        let interval = self.trickle_interval.get();
+        let half = interval / 2;
+        self.trickle_elapsed.set(0);
+        self.trickle_counter.set(0);
+        self.trickle_send_at
+            .set(half + self.random() % core::cmp::max(interval - half, 1));
+    }
+
+    fn trickle_tick(&self) {
+        let elapsed = self.trickle_elapsed.get() + 1;
+        self.trickle_elapsed.set(elapsed);
+        let config = self.config();
+        if elapsed == self.trickle_send_at.get()
+            && (config.dio_redundancy == 0 || self.trickle_counter.get() < config.dio_redundancy)
+        {
+            self.dio_pending.set(ALL_RPL_NODES_ADDR);
+        }
+        if elapsed >= self.trickle_interval.get() {
+            let imax = Self::ms_to_ticks(
+                1 << (config.dio_interval_min as u32 + config.dio_interval_doublings as u32)
+                    .min(31),
+            );
+            let interval = self.trickle_interval.get().saturating_mul(2).min(imax);
+            self.trickle_interval.set(interval);
+            self.start_trickle_interval();
+        }
+    }
+
+    fn schedule_dao(&self) {
+        let countdown = self.dao_countdown.get();
+        if countdown == 0 || countdown > DAO_DELAY_TICKS {
+            self.dao_countdown.set(DAO_DELAY_TICKS);
+        }
+    }
+
+    fn join(&self, src_addr: IPAddr, dio: &Dio) {
+        self.dodag.set(Dodag {
+            instance_id: dio.instance_id,
+            version: dio.version,
+            grounded: dio.grounded,
+            dodag_id: dio.dodag_id,
+            config: self.config(),
+            prefix: None,
+        });
+        self.set_parent(src_addr, dio);
+    }
+
+    fn set_parent(&self, src_addr: IPAddr, dio: &Dio) {
+        self.parent.set(Parent {
+            addr: src_addr,
+            dtsn: dio.dtsn,
+        });
+        self.rank.set(self.rank_through(dio.rank));
+        self.reset_trickle();
+        self.schedule_dao();
+    }
+
+    /// Leaves the DODAG after losing the parent, and announces an infinite
+    /// rank so that children look for another parent.
+    fn detach(&self) {
+        self.parent.clear();
+        self.rank.set(INFINITE_RANK);
+        self.dao_countdown.set(0);
+        self.routes.map(|routes| {
+            for route in routes.iter_mut() {
+                *route = None;
+            }
+        });
+        self.dio_pending.set(ALL_RPL_NODES_ADDR);
+        self.dis_countdown.set(DIS_INTERVAL_TICKS);
+    }
+
+    fn receive_dio(&self, src_addr: IPAddr, dio: Dio, options: &[u8]) {
+        if self.root.get()
+            || dio.mode_of_operation != MOP_STORING
+            || !src_addr.is_unicast_link_local()
+        {
+            return;
+        }
+        let from_parent = self.parent.map_or(false, |parent| parent.addr == src_addr);
+        if dio.rank == INFINITE_RANK {
+            if from_parent {
+                self.detach();
+            }
+            return;
+        }
+
+        let same_dodag = self.dodag.map_or(false, |dodag| {
+            dodag.instance_id == dio.instance_id && dodag.dodag_id == dio.dodag_id
+        });
+        let newer_version = self.dodag.map_or(false, |dodag| {
+            (dio.version.wrapping_sub(dodag.version) as i8) > 0
+        });
+
+        if !self.joined() || (same_dodag && newer_version) {
+            // Join the DODAG, or move to its new version (global repair)
+            self.join(src_addr, &dio);
+        } else if !same_dodag
+            || self
+                .dodag
+                .map_or(true, |dodag| dodag.version != dio.version)
+        {
+            return;
+        } else if from_parent {
+            let dtsn_changed = self.parent.map_or(false, |parent| parent.dtsn != dio.dtsn);
+            self.parent.set(Parent {
+                addr: src_addr,
+                dtsn: dio.dtsn,
+            });
+            self.rank.set(self.rank_through(dio.rank));
+            if dtsn_changed {
+                // The parent asks for its downward routes to be refreshed
+                self.schedule_dao();
+            }
+            self.trickle_counter
+                .set(self.trickle_counter.get().saturating_add(1));
+        } else if self
+            .rank_through(dio.rank)
+            .saturating_add(self.config().min_hop_rank_increase)
+            <= self.rank.get()
+        {
+            // The sender is a better parent
+            self.set_parent(src_addr, &dio);
+        } else {
+            self.trickle_counter
+                .set(self.trickle_counter.get().saturating_add(1));
+        }
+
+        if !self.parent.map_or(false, |parent| parent.addr == src_addr) {
+            return;
+        }
+        // Take the DODAG parameters from the parent
+        for (option_type, body) in RplOptions::new(options) {
+            match option_type {
+                rpl_option::DODAG_CONFIG => {
+                    DodagConfig::decode(body).done().map(|(_, config)| {
+                        self.update_dodag(|dodag| dodag.config = config);
+                    });
+                }
+                rpl_option::PREFIX_INFO => {
+                    PrefixInfo::decode(body).done().map(|(_, prefix_info)| {
+                        self.receive_prefix_info(prefix_info);
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn receive_prefix_info(&self, prefix_info: PrefixInfo) {
+        self.update_dodag(|dodag| dodag.prefix = Some(prefix_info));
+        // The interface identifier is 64 bits long, so only /64 prefixes can
+        // be used
+        if !prefix_info.autonomous
+            || prefix_info.prefix_len != 64
+            || prefix_info.valid_lifetime == 0
+        {
+            return;
+        }
+        let mut addr = IPAddr::generate_from_mac(self.mac_addr);
+        addr.set_prefix(&prefix_info.prefix.0, 64);
+        if !self.global_addr.contains(&addr) {
+            self.set_global_addr(addr);
+            self.schedule_dao();
+        }
+    }
+
+    fn receive_dis(&self, src_addr: IPAddr, dst_addr: IPAddr) {
+        if !self.joined() {
+            return;
+        }
+        if dst_addr.is_multicast() {
+            self.reset_trickle();
+        } else if !self.dio_pending.is_some() {
+            self.dio_pending.set(src_addr);
+        }
+    }
+
+    fn receive_dao(&self, src_addr: IPAddr, dao: Dao, options: &[u8]) {
+        let same_dodag = self.dodag.map_or(false, |dodag| {
+            dodag.instance_id == dao.instance_id
+                && dao
+                    .dodag_id
+                    .map_or(true, |dodag_id| dodag_id == dodag.dodag_id)
+        });
+        if !self.joined() || !same_dodag || !src_addr.is_unicast_link_local() {
+            return;
+        }
+
+        // Each Transit Information option applies to the targets preceding
+        // it
+        let mut targets = [IPAddr::new(); 4];
+        let mut num_targets = 0;
+        for (option_type, body) in RplOptions::new(options) {
+            match option_type {
+                rpl_option::TARGET => {
+                    if let Some((_, (128, target))) = decode_target(body).done() {
+                        if num_targets < targets.len() && !self.is_local(target) {
+                            targets[num_targets] = target;
+                            num_targets += 1;
+                        }
+                    }
+                }
+                rpl_option::TRANSIT_INFO => {
+                    if let Some((_, path_lifetime)) = decode_transit_info(body).done() {
+                        for target in targets[..num_targets].iter() {
+                            if path_lifetime == 0 {
+                                self.remove_route(*target, src_addr);
+                            } else {
+                                self.add_route(
+                                    *target,
+                                    src_addr,
+                                    self.route_lifetime_ticks(path_lifetime),
+                                );
+                            }
+                        }
+                    }
+                    num_targets = 0;
+                }
+                _ => {}
+            }
+        }
+
+        if dao.ack_requested {
+            self.dao_ack_pending.set((src_addr, dao.sequence));
+        }
+        if !self.root.get() {
+            // Propagate the routes towards the root
+            self.schedule_dao();
+        }
+    }
+
+    fn add_route(&self, target: IPAddr, next_hop: IPAddr, lifetime_ticks: u32) {
+        self.routes.map(|routes| {
+            let existing = routes
+                .iter()
+                .position(|route| route.map_or(false, |route| route.target == target));
+            let slot = existing.or_else(|| routes.iter().position(|route| route.is_none()));
+            if let Some(i) = slot {
+                routes[i] = Some(Route {
+                    target,
+                    next_hop,
+                    lifetime_ticks,
+                });
+            }
+        });
+    }
+
+    fn remove_route(&self, target: IPAddr, next_hop: IPAddr) {
+        self.routes.map(|routes| {
+            for route in routes.iter_mut() {
+                if route.map_or(false, |route| {
+                    route.target == target && route.next_hop == next_hop
+                }) {
+                    *route = None;
+                }
+            }
+        });
+    }
+
+    fn age_routes(&self) {
+        self.routes.map(|routes| {
+            for route in routes.iter_mut() {
+                if let Some(entry) = route {
+                    entry.lifetime_ticks = entry.lifetime_ticks.saturating_sub(1);
+                    if entry.lifetime_ticks == 0 {
+                        *route = None;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Encodes the body of a DIO into `buf`, returning its length.
+    fn encode_dio(&self, buf: &mut [u8]) -> Option<usize> {
+        let dodag = self.dodag.extract()?;
+        let dio = Dio {
+            instance_id: dodag.instance_id,
+            version: dodag.version,
+            rank: self.rank.get(),
+            grounded: dodag.grounded,
+            mode_of_operation: MOP_STORING,
+            preference: 0,
+            dtsn: self.dtsn.get(),
+            dodag_id: dodag.dodag_id,
+        };
+        let (off, _) = dio.encode(buf, 0).done()?;
+        let (mut off, _) = dodag.config.encode(buf, off).done()?;
+        if let Some(prefix_info) = dodag.prefix {
+            off = prefix_info.encode(buf, off).done()?.0;
+        }
+        Some(off)
+    }
+
+    /// Encodes the body of a DAO advertising the global address of the node
+    /// and the targets of its routes into `buf`, returning its length.
+    fn encode_dao(&self, buf: &mut [u8]) -> Option<usize> {
+        let dodag = self.dodag.extract()?;
+        self.dao_sequence
+            .set(self.dao_sequence.get().wrapping_add(1));
+        let dao = Dao {
+            instance_id: dodag.instance_id,
+            ack_requested: false,
+            sequence: self.dao_sequence.get(),
+            dodag_id: Some(dodag.dodag_id),
+        };
+        let (mut off, _) = dao.encode(buf, 0).done()?;
+        let targets_end = buf.len().checked_sub(TRANSIT_INFO_LEN)?;
+        let mut num_targets = 0;
+        if let Some(global_addr) = self.global_addr.extract() {
+            off = encode_target(&mut buf[..targets_end], off, global_addr)
+                .done()?
+                .0;
+            num_targets += 1;
+        }
+        self.routes.map(|routes| {
+            for route in routes.iter().filter_map(|route| *route) {
+                if off + TARGET_LEN > targets_end {
+                    break;
+                }
+                if let Some((new_off, _)) = encode_target(buf, off, route.target).done() {
+                    off = new_off;
+                    num_targets += 1;
+                }
+            }
+        });
+        if num_targets == 0 {
+            return None;
+        }
+        self.path_sequence
+            .set(self.path_sequence.get().wrapping_add(1));
+        let (off, _) = encode_transit_info(
+            buf,
+            off,
+            self.path_sequence.get(),
+            dodag.config.default_lifetime,
+        )
+        .done()?;
+        Some(off)
+    }
+
+    /// Sends the most urgent pending control message, if the sender is
+    /// free.
+    fn send_next(&self) {
+        if self.in_flight.get() {
+            return;
+        }
+        self.buffer.take().map(|mut buf| {
+            buf.reset();
+            let message = if let Some((dst, sequence)) = self.dao_ack_pending.take() {
+                self.dodag.extract().and_then(|dodag| {
+                    let ack = DaoAck {
+                        instance_id: dodag.instance_id,
+                        sequence,
+                        status: 0,
+                    };
+                    let len = ack.encode(&mut buf[..], 0).done()?.0;
+                    Some((dst, rpl_code::DAO_ACK, len))
+                })
+            } else if let Some(dst) = self.dio_pending.take() {
+                self.encode_dio(&mut buf[..])
+                    .map(|len| (dst, rpl_code::DIO, len))
+            } else if self.dao_pending.get() {
+                self.dao_pending.set(false);
+                let parent = self.parent.map(|parent| parent.addr);
+                parent.and_then(|parent| {
+                    self.encode_dao(&mut buf[..])
+                        .map(|len| (parent, rpl_code::DAO, len))
+                })
+            } else if self.dis_pending.get() {
+                self.dis_pending.set(false);
+                // Flags and reserved
+                buf[0] = 0;
+                buf[1] = 0;
+                Some((ALL_RPL_NODES_ADDR, rpl_code::DIS, 2))
+            } else {
+                None
+            };
+
+            match message {
+                Some((dst, code, len)) => {
+                    buf.slice(0..len);
+                    let mut header = ICMP6Header::new(ICMP6Type::Type155);
+                    header.set_code(code);
+                    self.in_flight.set(true);
+                    if let Err((_, buf)) = self.icmp_sender.send(dst, header, buf, self.net_cap) {
+                        // The message is dropped
+                        self.in_flight.set(false);
+                        self.buffer.replace(buf);
+                    }
+                }
+                None => {
+                    self.buffer.replace(buf);
+                }
+            }
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6RecvClient for Rpl<'a, A> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type155 => {}
+            _ => return,
+        }
+        let src_addr = ip_header.get_src_addr();
+        match icmp_header.get_code() {
+            rpl_code::DIS => self.receive_dis(src_addr, ip_header.get_dst_addr()),
+            rpl_code::DIO => {
+                if let Some((off, dio)) = Dio::decode(payload).done() {
+                    self.receive_dio(src_addr, dio, &payload[off..]);
+                }
+            }
+            rpl_code::DAO => {
+                if let Some((off, dao)) = Dao::decode(payload).done() {
+                    self.receive_dao(src_addr, dao, &payload[off..]);
+                }
+            }
+            // DAO-ACKs are not requested, so they are ignored
+            _ => return,
+        }
+        self.send_next();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6SendClient for Rpl<'a, A> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, buf: LeasableBuffer<'static, u8>) {
+        self.buffer.replace(buf);
+        self.in_flight.set(false);
+        self.send_next();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for Rpl<'a, A> {
+    fn alarm(&self) {
+        self.start_timer();
+        if self.joined() {
+            self.trickle_tick();
+            self.age_routes();
+        } else {
+            let countdown = self.dis_countdown.get().saturating_sub(1);
+            if countdown == 0 {
+                self.dis_pending.set(true);
+                self.dis_countdown.set(DIS_INTERVAL_TICKS);
+            } else {
+                self.dis_countdown.set(countdown);
+            }
+        }
+        if self.parent.is_some() && self.dao_countdown.get() > 0 {
+            let countdown = self.dao_countdown.get() - 1;
+            if countdown == 0 {
+                self.dao_pending.set(true);
+                // Refresh the routes before they expire at the parent
+                let lifetime = self.route_lifetime_ticks(self.config().default_lifetime);
+                self.dao_countdown.set(core::cmp::max(lifetime / 2, 1));
+            } else {
+                self.dao_countdown.set(countdown);
+            }
+        }
+        self.send_next();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Router for Rpl<'a, A> {
+    fn next_hop(&self, dst: IPAddr) -> Option<IPAddr> {
+        let route = self
+            .routes
+            .map(|routes| {
+                routes
+                    .iter()
+                    .filter_map(|route| *route)
+                    .find(|route| route.target == dst)
+                    .map(|route| route.next_hop)
+            })
+            .flatten();
+        route.or_else(|| self.parent.map(|parent| parent.addr))
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Forwarder for Rpl<'a, A> {
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> bool {
+        let dst = header.get_dst_addr();
+        // Packets on the local link, and all packets while the node is not
+        // part of a DODAG, are delivered as before
+        if !self.joined() || dst.is_multicast() || dst.is_unicast_link_local() || self.is_local(dst)
+        {
+            return false;
+        }
+        let hop_limit = header.get_hop_limit();
+        if hop_limit <= 1 || self.next_hop(dst).is_none() {
+            // Dropped
+            return true;
+        }
+
+        let transport = match header.get_next_header() {
+            ip6_nh::UDP => UDPHeader::decode(payload)
+                .done()
+                .map(|(off, udp_header)| (TransportHeader::UDP(udp_header), off)),
+            ip6_nh::ICMP => ICMP6Header::decode(payload)
+                .done()
+                .map(|(off, icmp_header)| (TransportHeader::ICMP(icmp_header), off)),
+            _ => None,
+        };
+        let (transport_header, off) = match transport {
+            Some(transport) => transport,
+            None => return true,
+        };
+        let data = &payload[off..];
+        self.forward_buffer.take().map(|buffer| {
+            if data.len() <= buffer.len() {
+                buffer[..data.len()].copy_from_slice(data);
+                let mut data_buf = LeasableBuffer::new(buffer);
+                data_buf.slice(0..data.len());
+                self.forward_sender.set_addr(header.get_src_addr());
+                self.forward_sender.set_hop_limit(hop_limit - 1);
+                // The packet is dropped if the sender is busy
+                let _ = self
+                    .forward_sender
+                    .send_to(dst, transport_header, &data_buf, self.net_cap);
+                self.forward_buffer.replace(data_buf.take());
+            } else {
+                self.forward_buffer.replace(buffer);
+            }
+        });
+        true
+    }
+}
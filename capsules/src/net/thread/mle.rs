@@ -0,0 +1,1174 @@
+//! Mesh Link Establishment (MLE) for attaching to a Thread network as a
+//! child, as outlined in Chapter 4 of the Thread 1.1.1 Specification.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! `Mle` performs this handshake for an end device. The Parent Request is
+//! first sent to routers only, and then to routers and router-eligible end
+//! devices if no router responds in time. Among the Parent Responses that
+//! answer the challenge of the request, the parent with the best link
+//! quality, parent priority and number of good links is selected. If no
+//! parent responds, or the selected parent does not answer the Child ID
+//! Request, the handshake is restarted after `ATTACH_RETRY_DELAY_MS`.
+//!
+//! MLE messages are sent between link-local addresses, from and to UDP
+//! port 19788. They are secured with AES-CCM, with a 4 byte MIC, under the
+//! MLE key. The MLE key and the MAC key are derived from the master key
+//! and the key sequence with HMAC-SHA256 (Section 7.1.4). A secured message
+//! is laid out as follows:
+//!
+//! ```text
+//! [ 0 | Aux security header | Command type | TLVs ... | MIC ]
+//!       \_ authenticated _/   \____ encrypted _____/
+//! ```
+//!
+//! The aux security header is that of IEEE 802.15.4, with security level 5
+//! and key identifier mode 2, where the key source is the key sequence.
+//! The source and destination IPv6 addresses are authenticated as well,
+//! and the nonce is formed from the extended address of the sender, which
+//! is derived from the interface identifier of its link-local address.
+//!
+//! Known limitations: the network data of the Child ID Response is not
+//! stored, and the child does not keep its parent alive with Child Update
+//! Requests. Only one message is encrypted or decrypted at a time, and
+//! messages received in the meantime are dropped. The frame counter of the
+//! MAC layer is not known to `Mle`, so the Child ID Request reports 0.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::nd::ALL_ROUTERS_ADDR;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u32, encode_u8};
+use crate::net::thread::tlv::{MulticastResponder, Tlv, TlvType};
+use crate::net::udp::udp_port_table::UdpPortManager;
+use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::sha_software::{Algorithm, HashState};
+
+use core::cell::Cell;
+
+use kernel::hil::rng;
+use kernel::hil::symmetric_encryption::{
+    CCMClient, AES128CCM, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_NONCE_LENGTH,
+};
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// The UDP port MLE messages are sent from and to.
+pub const MLE_PORT: u16 = 19788;
+/// The length of the Thread master key.
+pub const MASTER_KEY_LEN: usize = 16;
+/// The longest MLE message (UDP payload) that is sent or received.
+pub const MAX_MLE_MSG_LEN: usize = 200;
+/// The length of the buffer messages are prepared in.
+pub const MLE_BUF_LEN: usize = PAYLOAD_OFF + MAX_MLE_MSG_LEN;
+/// The length of the intermediate buffer the AES-CCM user of `Mle` needs.
+pub const MLE_CRYPT_BUF_LEN: usize = 3 * AES128_BLOCK_SIZE + MLE_BUF_LEN;
+
+/// MLE command types
+pub mod command {
+    pub const LINK_REQUEST: u8 = 0;
+    pub const LINK_ACCEPT: u8 = 1;
+    pub const LINK_ACCEPT_AND_REQUEST: u8 = 2;
+    pub const LINK_REJECT: u8 = 3;
+    pub const ADVERTISEMENT: u8 = 4;
+    pub const DATA_REQUEST: u8 = 7;
+    pub const DATA_RESPONSE: u8 = 8;
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+    pub const CHILD_UPDATE_REQUEST: u8 = 13;
+    pub const CHILD_UPDATE_RESPONSE: u8 = 14;
+}
+
+const THREAD_VERSION: u16 = 2;
+/// The timeout the child asks its parent to keep it for, in seconds.
+const CHILD_TIMEOUT_S: u32 = 240;
+
+const START_DELAY_MS: u32 = 1000;
+const PARENT_REQUEST_ROUTER_TIMEOUT_MS: u32 = 750;
+const PARENT_REQUEST_REED_TIMEOUT_MS: u32 = 1250;
+const CHILD_ID_RESPONSE_TIMEOUT_MS: u32 = 1250;
+const ATTACH_RETRY_DELAY_MS: u32 = 10000;
+
+const SECURITY_SUITE_SECURED: u8 = 0;
+/// Security level 5 (ENC-MIC-32) and key identifier mode 2
+const SECURITY_CONTROL: u8 = 0x15;
+const SECURITY_LEVEL: u8 = 5;
+const AUX_HEADER_LEN: usize = 10;
+const MIC_LEN: usize = 4;
+
+// Layout of the message buffer. The source and destination addresses are
+// directly followed by the aux security header, so that together they form
+// the authenticated data, and then by the command type and TLVs, which are
+// encrypted. The UDP payload starts with the security suite, right before
+// the aux security header, which overwrites the last byte of the
+// destination address once the message has been encrypted.
+const SRC_ADDR_OFF: usize = 0;
+const DST_ADDR_OFF: usize = 16;
+const AUX_HEADER_OFF: usize = 32;
+const PAYLOAD_OFF: usize = AUX_HEADER_OFF - 1;
+const COMMAND_OFF: usize = AUX_HEADER_OFF + AUX_HEADER_LEN;
+
+/// Derives the MLE key and the MAC key for `key_sequence` from the master
+/// key. Returns `(mle_key, mac_key)`.
+pub fn derive_keys(
+    master_key: &[u8; MASTER_KEY_LEN],
+    key_sequence: u32,
+) -> ([u8; AES128_KEY_SIZE], [u8; AES128_KEY_SIZE]) {
+    let mut data = [0; 10];
+    data[..4].copy_from_slice(&key_sequence.to_be_bytes());
+    data[4..].copy_from_slice(b"Thread");
+    let mut hash = [0; 32];
+    hmac_sha256(master_key, &data, &mut hash);
+
+    let mut mle_key = [0; AES128_KEY_SIZE];
+    let mut mac_key = [0; AES128_KEY_SIZE];
+    mle_key.copy_from_slice(&hash[..AES128_KEY_SIZE]);
+    mac_key.copy_from_slice(&hash[AES128_KEY_SIZE..]);
+    (mle_key, mac_key)
+}
+
+/// HMAC-SHA256 (RFC 2104) with a key of at most one block.
+fn hmac_sha256(key: &[u8], data: &[u8], out: &mut [u8; 32]) {
+    let mut pad = [0x36; 64];
+    pad.iter_mut().zip(key.iter()).for_each(|(p, k)| *p ^= k);
+    let mut inner = HashState::new(Algorithm::Sha256);
+    inner.update(&pad);
+    inner.update(data);
+    let mut inner_hash = [0; 32];
+    inner.finish(&mut inner_hash);
+
+    pad.iter_mut().for_each(|p| *p ^= 0x36 ^ 0x5c);
+    let mut outer = HashState::new(Algorithm::Sha256);
+    outer.update(&pad);
+    outer.update(&inner_hash);
+    outer.finish(out);
+}
+
+fn key_index(key_sequence: u32) -> u8 {
+    (key_sequence & 0x7f) as u8 + 1
+}
+
+/// The nonce of a message sent by the device with extended address
+/// `ext_addr`.
+fn nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; CCM_NONCE_LENGTH] {
+    let mut nonce = [0; CCM_NONCE_LENGTH];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = SECURITY_LEVEL;
+    nonce
+}
+
+/// Writes the authenticated data of a message from `src` to `dst`: the
+/// addresses and the aux security header.
+fn encode_auth_data(
+    buf: &mut [u8],
+    src: IPAddr,
+    dst: IPAddr,
+    frame_counter: u32,
+    key_sequence: u32,
+) -> SResult {
+    let mut off = enc_consume!(buf; encode_bytes, &src.0);
+    off = enc_consume!(buf, off; encode_bytes, &dst.0);
+    off = enc_consume!(buf, off; encode_u8, SECURITY_CONTROL);
+    off = enc_consume!(buf, off; encode_bytes, &frame_counter.to_le_bytes());
+    off = enc_consume!(buf, off; encode_u32, key_sequence);
+    off = enc_consume!(buf, off; encode_u8, key_index(key_sequence));
+    stream_done!(off)
+}
+
+/// Decodes the security suite and aux security header of a received
+/// message. Returns the frame counter and key sequence.
+fn decode_security_header(buf: &[u8]) -> SResult<(u32, u32)> {
+    let (off, security_suite) = dec_try!(buf; decode_u8);
+    stream_cond!(security_suite == SECURITY_SUITE_SECURED);
+    let (off, security_control) = dec_try!(buf, off; decode_u8);
+    stream_cond!(security_control == SECURITY_CONTROL);
+    let mut frame_counter = [0; 4];
+    let off = dec_consume!(buf, off; decode_bytes, &mut frame_counter);
+    let (off, key_sequence) = dec_try!(buf, off; decode_u32);
+    let (off, index) = dec_try!(buf, off; decode_u8);
+    stream_cond!(index == key_index(key_sequence));
+    stream_done!(off, (u32::from_le_bytes(frame_counter), key_sequence))
+}
+
+/// Prepares a message from `src` to `dst` for encryption in `buf`: writes
+/// the authenticated data, and the command type and TLVs with `encode`.
+/// Returns the length of the command type and TLVs.
+fn prepare_message<F: FnOnce(&mut [u8]) -> SResult>(
+    buf: &mut [u8],
+    src: IPAddr,
+    dst: IPAddr,
+    frame_counter: u32,
+    key_sequence: u32,
+    encode: F,
+) -> Option<usize> {
+    if buf.len() < MLE_BUF_LEN {
+        return None;
+    }
+    encode_auth_data(
+        &mut buf[SRC_ADDR_OFF..COMMAND_OFF],
+        src,
+        dst,
+        frame_counter,
+        key_sequence,
+    )
+    .done()?;
+    encode(&mut buf[COMMAND_OFF..MLE_BUF_LEN - MIC_LEN])
+        .done()
+        .map(|(len, _)| len)
+}
+
+/// Copies a message received from `src` at `dst` into `buf` for
+/// decryption. Returns the frame counter, key sequence, and the length of
+/// the encrypted command type and TLVs.
+fn load_message(
+    buf: &mut [u8],
+    src: IPAddr,
+    dst: IPAddr,
+    payload: &[u8],
+) -> Option<(u32, u32, usize)> {
+    let (_, (frame_counter, key_sequence)) = decode_security_header(payload).done()?;
+    if payload.len() < 1 + AUX_HEADER_LEN + 1 + MIC_LEN
+        || payload.len() > MAX_MLE_MSG_LEN
+        || buf.len() < MLE_BUF_LEN
+    {
+        return None;
+    }
+    buf[PAYLOAD_OFF..PAYLOAD_OFF + payload.len()].copy_from_slice(payload);
+    buf[SRC_ADDR_OFF..DST_ADDR_OFF].copy_from_slice(&src.0);
+    buf[DST_ADDR_OFF..AUX_HEADER_OFF].copy_from_slice(&dst.0);
+    let len = payload.len() - (1 + AUX_HEADER_LEN) - MIC_LEN;
+    Some((frame_counter, key_sequence, len))
+}
+
+fn encode_parent_request(buf: &mut [u8], mode: u8, challenge: [u8; 8], scan_mask: u8) -> SResult {
+    let mut off = enc_consume!(buf; encode_u8, command::PARENT_REQUEST);
+    off = enc_consume!(buf, off; Tlv::Mode(mode); encode);
+    off = enc_consume!(buf, off; Tlv::Challenge(challenge); encode);
+    off = enc_consume!(buf, off; Tlv::ScanMask(scan_mask); encode);
+    off = enc_consume!(buf, off; Tlv::Version(THREAD_VERSION); encode);
+    stream_done!(off)
+}
+
+fn encode_child_id_request(
+    buf: &mut [u8],
+    mode: u8,
+    response: [u8; 8],
+    mle_frame_counter: u32,
+) -> SResult {
+    let requested_tlvs = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+    let mut off = enc_consume!(buf; encode_u8, command::CHILD_ID_REQUEST);
+    off = enc_consume!(buf, off; Tlv::Response(response); encode);
+    off = enc_consume!(buf, off; Tlv::LinkLayerFrameCounter(0); encode);
+    off = enc_consume!(buf, off; Tlv::MleFrameCounter(mle_frame_counter); encode);
+    off = enc_consume!(buf, off; Tlv::Mode(mode); encode);
+    off = enc_consume!(buf, off; Tlv::Timeout(CHILD_TIMEOUT_S); encode);
+    off = enc_consume!(buf, off; Tlv::Version(THREAD_VERSION); encode);
+    off = enc_consume!(buf, off; Tlv::TlvRequest(&requested_tlvs); encode);
+    stream_done!(off)
+}
+
+/// Iterates over the TLVs of a message. TLVs of types that `Tlv` does not
+/// implement are skipped.
+struct MleTlvs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> MleTlvs<'a> {
+    fn new(buf: &'a [u8]) -> MleTlvs<'a> {
+        MleTlvs { buf: buf }
+    }
+}
+
+impl<'a> Iterator for MleTlvs<'a> {
+    type Item = Tlv<'a>;
+
+    fn next(&mut self) -> Option<Tlv<'a>> {
+        while self.buf.len() >= 2 {
+            let len = 2 + self.buf[1] as usize;
+            if len > self.buf.len() {
+                break;
+            }
+            let (tlv, rest) = self.buf.split_at(len);
+            self.buf = rest;
+            if let SResult::Done(_, tlv) = Tlv::decode(tlv) {
+                return Some(tlv);
+            }
+        }
+        self.buf = &[];
+        None
+    }
+}
+
+/// The contents of a Parent Response that are needed to select a parent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ParentResponse {
+    rloc16: u16,
+    partition_id: u32,
+    response: [u8; 8],
+    challenge: [u8; 8],
+    mle_frame_counter: u32,
+    link_margin: u8,
+    parent_priority: u8,
+    link_quality_3: u8,
+}
+
+impl ParentResponse {
+    fn decode(tlvs: &[u8]) -> Option<ParentResponse> {
+        let mut rloc16 = None;
+        let mut partition_id = None;
+        let mut response = None;
+        let mut challenge = None;
+        let mut link_frame_counter = None;
+        let mut mle_frame_counter = None;
+        let mut link_margin = None;
+        let mut connectivity = None;
+        for tlv in MleTlvs::new(tlvs) {
+            match tlv {
+                Tlv::SourceAddress(addr) => rloc16 = Some(addr),
+                Tlv::LeaderData {
+                    partition_id: id, ..
+                } => partition_id = Some(id),
+                Tlv::Response(bytes) => response = Some(bytes),
+                Tlv::Challenge(bytes) => challenge = Some(bytes),
+                Tlv::LinkLayerFrameCounter(counter) => link_frame_counter = Some(counter),
+                Tlv::MleFrameCounter(counter) => mle_frame_counter = Some(counter),
+                Tlv::LinkMargin(margin) => link_margin = Some(margin),
+                Tlv::Connectivity {
+                    parent_priority,
+                    link_quality_3,
+                    ..
+                } => connectivity = Some((parent_priority, link_quality_3)),
+                _ => {}
+            }
+        }
+        let (parent_priority, link_quality_3) = connectivity?;
+        Some(ParentResponse {
+            rloc16: rloc16?,
+            partition_id: partition_id?,
+            response: response?,
+            challenge: challenge?,
+            // The MLE frame counter is only included if it differs from
+            // the link-layer frame counter
+            mle_frame_counter: mle_frame_counter.or(link_frame_counter)?,
+            link_margin: link_margin?,
+            parent_priority: parent_priority,
+            link_quality_3: link_quality_3,
+        })
+    }
+
+    /// Parents are ordered by the quality of the link to them, then by
+    /// the priority they advertise, and then by their number of links of
+    /// quality 3.
+    fn preference(&self) -> (u8, i8, u8) {
+        let link_quality = match self.link_margin {
+            m if m > 20 => 3,
+            m if m > 10 => 2,
+            m if m > 2 => 1,
+            _ => 0,
+        };
+        // The priority is a signed 2 bit value in the top bits
+        let priority = (self.parent_priority as i8) >> 6;
+        (link_quality, priority, self.link_quality_3)
+    }
+}
+
+/// The contents of a Child ID Response.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ChildIdResponse {
+    parent_rloc16: u16,
+    rloc16: u16,
+    partition_id: u32,
+    leader_router_id: u8,
+}
+
+impl ChildIdResponse {
+    fn decode(tlvs: &[u8]) -> Option<ChildIdResponse> {
+        let mut parent_rloc16 = None;
+        let mut rloc16 = None;
+        let mut leader_data = None;
+        for tlv in MleTlvs::new(tlvs) {
+            match tlv {
+                Tlv::SourceAddress(addr) => parent_rloc16 = Some(addr),
+                Tlv::Address16(addr) => rloc16 = Some(addr),
+                Tlv::LeaderData {
+                    partition_id,
+                    leader_router_id,
+                    ..
+                } => leader_data = Some((partition_id, leader_router_id)),
+                _ => {}
+            }
+        }
+        let (partition_id, leader_router_id) = leader_data?;
+        Some(ChildIdResponse {
+            parent_rloc16: parent_rloc16?,
+            rloc16: rloc16?,
+            partition_id: partition_id,
+            leader_router_id: leader_router_id,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Stopped,
+    /// Waiting to start the attach process.
+    Detached,
+    /// A Parent Request has been sent to routers.
+    ParentRequestRouters,
+    /// A Parent Request has been sent to routers and REEDs.
+    ParentRequestAll,
+    /// A Child ID Request has been sent to the selected parent.
+    ChildIdRequest,
+    Attached,
+}
+
+/// What the current AES-CCM operation is for.
+#[derive(Copy, Clone)]
+enum Crypt {
+    Idle,
+    /// Encrypting a message to `dst`, with `len` bytes of command type and
+    /// TLVs.
+    Send {
+        dst: IPAddr,
+        len: usize,
+    },
+    /// Decrypting a message received from `src`.
+    Receive {
+        src: IPAddr,
+        frame_counter: u32,
+        key_sequence: u32,
+        len: usize,
+    },
+}
+
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    response: ParentResponse,
+}
+
+pub trait MleClient {
+    /// Called when the device has attached to `parent`, which assigned it
+    /// the short address `rloc16`.
+    fn attached(&self, rloc16: u16, parent: IPAddr);
+}
+
+pub struct Mle<'a, A: time::Alarm<'a>, C: AES128CCM<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    udp_receiver: &'a UDPReceiver<'a>,
+    port_table: &'static UdpPortManager,
+    aes_ccm: &'a C,
+    alarm: &'a A,
+    rng: &'a dyn rng::Random<'a>,
+    client: OptionalCell<&'a dyn MleClient>,
+
+    ext_addr: [u8; 8],
+    src_addr: IPAddr,
+    mode: u8,
+    master_key: [u8; MASTER_KEY_LEN],
+    key_sequence: Cell<u32>,
+    frame_counter: Cell<u32>,
+
+    state: Cell<State>,
+    challenge: Cell<[u8; 8]>,
+    candidate: OptionalCell<Parent>,
+    parent: OptionalCell<Parent>,
+    rloc16: OptionalCell<u16>,
+
+    buffer: TakeCell<'static, [u8]>,
+    crypt: Cell<Crypt>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>, C: AES128CCM<'a>> Mle<'a, A, C> {
+    /// `ext_addr` is the extended MAC address of the device, and `mode`
+    /// the value of the Mode TLV it sends. `buffer` must be at least
+    /// `MLE_BUF_LEN` bytes long.
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        udp_receiver: &'a UDPReceiver<'a>,
+        port_table: &'static UdpPortManager,
+        aes_ccm: &'a C,
+        alarm: &'a A,
+        rng: &'a dyn rng::Random<'a>,
+        ext_addr: [u8; 8],
+        mode: u8,
+        master_key: [u8; MASTER_KEY_LEN],
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> Mle<'a, A, C> {
+        Mle {
+            udp_sender: udp_sender,
+            udp_receiver: udp_receiver,
+            port_table: port_table,
+            aes_ccm: aes_ccm,
+            alarm: alarm,
+            rng: rng,
+            client: OptionalCell::empty(),
+            ext_addr: ext_addr,
+            src_addr: IPAddr::generate_from_mac(MacAddress::Long(ext_addr)),
+            mode: mode,
+            master_key: master_key,
+            key_sequence: Cell::new(0),
+            frame_counter: Cell::new(0),
+            state: Cell::new(State::Stopped),
+            challenge: Cell::new([0; 8]),
+            candidate: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            rloc16: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            crypt: Cell::new(Crypt::Idle),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MleClient) {
+        self.client.set(client);
+    }
+
+    /// Binds the MLE port and starts attaching to a parent.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if !self.udp_receiver.is_bound() {
+            let socket = self
+                .port_table
+                .create_socket()
+                .map_err(|_| ErrorCode::NOMEM)?;
+            let (send_binding, recv_binding) = self
+                .port_table
+                .bind(socket, MLE_PORT, self.net_cap)
+                .map_err(|_| ErrorCode::BUSY)?;
+            self.udp_sender.set_binding(send_binding);
+            self.udp_receiver.set_binding(recv_binding);
+        }
+        self.detach(START_DELAY_MS);
+        Ok(())
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.state.get() == State::Attached
+    }
+
+    /// The short address assigned by the parent, once attached.
+    pub fn get_rloc16(&self) -> Option<u16> {
+        self.rloc16.extract()
+    }
+
+    /// The link-local address of the parent, once attached.
+    pub fn get_parent(&self) -> Option<IPAddr> {
+        self.parent.extract().map(|parent| parent.addr)
+    }
+
+    /// The current key sequence, which selects the MAC key of the network
+    /// (see `derive_keys`).
+    pub fn get_key_sequence(&self) -> u32 {
+        self.key_sequence.get()
+    }
+
+    /// Forgets the parent, and restarts the attach process in `delay_ms`.
+    fn detach(&self, delay_ms: u32) {
+        self.state.set(State::Detached);
+        self.candidate.clear();
+        self.parent.clear();
+        self.rloc16.clear();
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(delay_ms));
+    }
+
+    fn send_parent_request(&self, to_reeds: bool) {
+        let mut challenge = [0; 8];
+        challenge[..4].copy_from_slice(&self.rng.random().to_be_bytes());
+        challenge[4..].copy_from_slice(&self.rng.random().to_be_bytes());
+        self.challenge.set(challenge);
+
+        let (scan_mask, state, timeout_ms) = if to_reeds {
+            (
+                MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8,
+                State::ParentRequestAll,
+                PARENT_REQUEST_REED_TIMEOUT_MS,
+            )
+        } else {
+            (
+                MulticastResponder::Router as u8,
+                State::ParentRequestRouters,
+                PARENT_REQUEST_ROUTER_TIMEOUT_MS,
+            )
+        };
+        // A failed send is handled like a lost message
+        let mode = self.mode;
+        let _ = self.send(ALL_ROUTERS_ADDR, |buf| {
+            encode_parent_request(buf, mode, challenge, scan_mask)
+        });
+        self.state.set(state);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(timeout_ms));
+    }
+
+    /// Sends a Child ID Request to the best parent that has responded, if
+    /// any. Returns false if no parent has responded.
+    fn send_child_id_request(&self) -> bool {
+        match self.candidate.take() {
+            Some(candidate) => {
+                let mode = self.mode;
+                let frame_counter = self.frame_counter.get();
+                let _ = self.send(candidate.addr, |buf| {
+                    encode_child_id_request(buf, mode, candidate.response.challenge, frame_counter)
+                });
+                self.parent.set(candidate);
+                self.state.set(State::ChildIdRequest);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(CHILD_ID_RESPONSE_TIMEOUT_MS),
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Encodes a message to `dst` with `encode`, and starts encrypting it.
+    /// The message is sent once it has been encrypted.
+    fn send<F: FnOnce(&mut [u8]) -> SResult>(
+        &self,
+        dst: IPAddr,
+        encode: F,
+    ) -> Result<(), ErrorCode> {
+        let buf = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let frame_counter = self.frame_counter.get();
+        let key_sequence = self.key_sequence.get();
+        let len =
+            match prepare_message(buf, self.src_addr, dst, frame_counter, key_sequence, encode) {
+                Some(len) => len,
+                None => {
+                    self.buffer.replace(buf);
+                    return Err(ErrorCode::SIZE);
+                }
+            };
+        self.frame_counter.set(frame_counter.wrapping_add(1));
+
+        let (mle_key, _) = derive_keys(&self.master_key, key_sequence);
+        self.crypt.set(Crypt::Send { dst: dst, len: len });
+        self.start_crypt(
+            buf,
+            &mle_key,
+            &nonce(&self.ext_addr, frame_counter),
+            len,
+            true,
+        )
+    }
+
+    fn start_crypt(
+        &self,
+        buf: &'static mut [u8],
+        key: &[u8; AES128_KEY_SIZE],
+        nonce: &[u8; CCM_NONCE_LENGTH],
+        len: usize,
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        let res = self
+            .aes_ccm
+            .set_key(key)
+            .and_then(|()| self.aes_ccm.set_nonce(nonce));
+        if let Err(e) = res {
+            self.crypt.set(Crypt::Idle);
+            self.buffer.replace(buf);
+            return Err(e);
+        }
+        self.aes_ccm
+            .crypt(
+                buf,
+                SRC_ADDR_OFF,
+                COMMAND_OFF,
+                len,
+                MIC_LEN,
+                true,
+                encrypting,
+            )
+            .map_err(|(e, buf)| {
+                self.crypt.set(Crypt::Idle);
+                self.buffer.replace(buf);
+                e
+            })
+    }
+
+    /// Handles an authenticated message.
+    fn receive_message(&self, src: IPAddr, frame_counter: u32, key_sequence: u32, msg: &[u8]) {
+        let (command, tlvs) = match msg.split_first() {
+            Some((command, tlvs)) => (*command, tlvs),
+            None => return,
+        };
+        match (command, self.state.get()) {
+            (command::PARENT_RESPONSE, State::ParentRequestRouters)
+            | (command::PARENT_RESPONSE, State::ParentRequestAll) => {
+                let response = match ParentResponse::decode(tlvs) {
+                    Some(response) if response.response == self.challenge.get() => response,
+                    _ => return,
+                };
+                self.update_key_sequence(key_sequence);
+                let better = self.candidate.map_or(true, |candidate| {
+                    response.preference() > candidate.response.preference()
+                });
+                if better {
+                    self.candidate.set(Parent {
+                        addr: src,
+                        response: response,
+                    });
+                }
+            }
+            (command::CHILD_ID_RESPONSE, State::ChildIdRequest) => {
+                let parent = match self.parent.extract() {
+                    Some(parent) if parent.addr == src => parent,
+                    _ => return,
+                };
+                if frame_counter < parent.response.mle_frame_counter {
+                    return;
+                }
+                let response = match ChildIdResponse::decode(tlvs) {
+                    Some(response) if response.parent_rloc16 == parent.response.rloc16 => response,
+                    _ => return,
+                };
+                self.update_key_sequence(key_sequence);
+                let mut parent = parent;
+                parent.response.mle_frame_counter = frame_counter.wrapping_add(1);
+                parent.response.partition_id = response.partition_id;
+                self.parent.set(parent);
+                self.rloc16.set(response.rloc16);
+                self.state.set(State::Attached);
+                let _ = self.alarm.disarm();
+                self.client
+                    .map(|client| client.attached(response.rloc16, parent.addr));
+            }
+            _ => {}
+        }
+    }
+
+    /// Switches to a newer key sequence used by an authenticated message.
+    fn update_key_sequence(&self, key_sequence: u32) {
+        if key_sequence > self.key_sequence.get() {
+            self.key_sequence.set(key_sequence);
+            self.frame_counter.set(0);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, C: AES128CCM<'a>> time::AlarmClient for Mle<'a, A, C> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Detached => self.send_parent_request(false),
+            State::ParentRequestRouters => {
+                if !self.send_child_id_request() {
+                    self.send_parent_request(true);
+                }
+            }
+            State::ParentRequestAll => {
+                if !self.send_child_id_request() {
+                    self.detach(ATTACH_RETRY_DELAY_MS);
+                }
+            }
+            State::ChildIdRequest => self.detach(ATTACH_RETRY_DELAY_MS),
+            State::Stopped | State::Attached => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, C: AES128CCM<'a>> CCMClient for Mle<'a, A, C> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        match self.crypt.replace(Crypt::Idle) {
+            Crypt::Send { dst, len } => {
+                if res != Ok(()) {
+                    self.buffer.replace(buf);
+                    return;
+                }
+                buf[PAYLOAD_OFF] = SECURITY_SUITE_SECURED;
+                let mut dgram = LeasableBuffer::new(buf);
+                dgram.slice(PAYLOAD_OFF..COMMAND_OFF + len + MIC_LEN);
+                if let Err(dgram) = self.udp_sender.send_to(dst, MLE_PORT, dgram, self.net_cap) {
+                    self.buffer.replace(dgram.take());
+                }
+            }
+            Crypt::Receive {
+                src,
+                frame_counter,
+                key_sequence,
+                len,
+            } => {
+                if res == Ok(()) && tag_is_valid {
+                    self.receive_message(
+                        src,
+                        frame_counter,
+                        key_sequence,
+                        &buf[COMMAND_OFF..COMMAND_OFF + len],
+                    );
+                }
+                self.buffer.replace(buf);
+            }
+            Crypt::Idle => {
+                self.buffer.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, C: AES128CCM<'a>> UDPSendClient for Mle<'a, A, C> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, dgram: LeasableBuffer<'static, u8>) {
+        self.buffer.replace(dgram.take());
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, C: AES128CCM<'a>> UDPRecvClient for Mle<'a, A, C> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        if src_port != MLE_PORT || !src_addr.is_unicast_link_local() {
+            return;
+        }
+        let ext_addr = match src_addr.link_local_mac() {
+            MacAddress::Long(ext_addr) => ext_addr,
+            MacAddress::Short(_) => return,
+        };
+        // Messages that arrive while another one is being processed are
+        // dropped
+        let buf = match self.buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        match load_message(buf, src_addr, dst_addr, payload) {
+            Some((frame_counter, key_sequence, len)) => {
+                let (mle_key, _) = derive_keys(&self.master_key, key_sequence);
+                self.crypt.set(Crypt::Receive {
+                    src: src_addr,
+                    frame_counter: frame_counter,
+                    key_sequence: key_sequence,
+                    len: len,
+                });
+                let _ =
+                    self.start_crypt(buf, &mle_key, &nonce(&ext_addr, frame_counter), len, false);
+            }
+            None => {
+                self.buffer.replace(buf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes_software::Aes128Software;
+    use crate::net::ipv6::nd::ALL_ROUTERS_ADDR;
+    use crate::test::leak;
+    use crate::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+    use kernel::dynamic_deferred_call::{
+        DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+        DynamicDeferredCallClientState,
+    };
+    use kernel::hil::symmetric_encryption::AES128;
+
+    // The messages below were secured with an independent AES-CCM
+    // implementation, with the MLE key derived from MASTER_KEY for key
+    // sequence 0.
+    const MASTER_KEY: [u8; MASTER_KEY_LEN] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+    const CHILD_EXT_ADDR: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+    const PARENT_EXT_ADDR: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+    const MODE: u8 = 0x0c;
+    const CHALLENGE: [u8; 8] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+    const PARENT_CHALLENGE: [u8; 8] = [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7];
+
+    /// Parent Request to routers, with frame counter 0.
+    const PARENT_REQUEST: [u8; 36] = [
+        0x00, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x0f, 0x9b, 0xbc, 0xa4,
+        0x53, 0xa3, 0x33, 0x77, 0x6b, 0x61, 0x4d, 0x69, 0x63, 0x4c, 0x34, 0x67, 0xa0, 0xe9, 0x37,
+        0x54, 0x9d, 0xe2, 0xa0, 0x1f, 0x8c,
+    ];
+    /// Parent Response from RLOC16 0x0400, with frame counter 42.
+    const PARENT_RESPONSE: [u8; 81] = [
+        0x00, 0x15, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x1c, 0xc6, 0x21, 0x8d,
+        0x30, 0xc6, 0x22, 0x26, 0x94, 0x3f, 0x32, 0xda, 0xac, 0x31, 0x6e, 0x3b, 0x86, 0xfa, 0x3a,
+        0x26, 0xaf, 0x4d, 0x8b, 0xe9, 0x58, 0x8f, 0x46, 0x5b, 0xba, 0xe4, 0xe7, 0x7c, 0xbb, 0x60,
+        0xf1, 0xff, 0x68, 0x25, 0xde, 0x1a, 0xdf, 0xfc, 0x94, 0x6a, 0x76, 0xe2, 0xed, 0x86, 0xd2,
+        0x43, 0x69, 0xdd, 0x98, 0xd5, 0x64, 0x9b, 0xdd, 0x88, 0xde, 0x91, 0x5f, 0x20, 0x3a, 0x66,
+        0x40, 0xe8, 0xf2, 0x64, 0x71, 0xb0,
+    ];
+    /// Child ID Request answering the Parent Response, with frame counter 1.
+    const CHILD_ID_REQUEST: [u8; 55] = [
+        0x00, 0x15, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x6b, 0x9a, 0x5f, 0x23,
+        0x67, 0x57, 0xd4, 0xe6, 0xd3, 0x52, 0x5c, 0x2f, 0x96, 0xb4, 0xb1, 0x17, 0x5d, 0x84, 0x69,
+        0xb8, 0xae, 0x45, 0x6e, 0xa4, 0xf5, 0xc6, 0x3b, 0x39, 0x36, 0x5f, 0x3e, 0xce, 0x65, 0x8e,
+        0x03, 0xb3, 0x37, 0x60, 0x35, 0x9a, 0x29, 0xaf, 0x85, 0x32,
+    ];
+    /// Child ID Response assigning RLOC16 0x0401, with frame counter 43.
+    const CHILD_ID_RESPONSE: [u8; 42] = [
+        0x00, 0x15, 0x2b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xf0, 0xc5, 0xf2, 0x6c,
+        0x87, 0x31, 0x06, 0xab, 0x5e, 0x3b, 0xec, 0x2c, 0x1f, 0xd2, 0x73, 0xd4, 0x7f, 0x80, 0xb2,
+        0x49, 0xf7, 0x71, 0x56, 0x0c, 0xee, 0xba, 0x8d, 0x2b, 0x17, 0x91, 0xb8,
+    ];
+
+    type Ccm = VirtualAES128CCM<'static, Aes128Software<'static>>;
+
+    struct TestClient {
+        buf: TakeCell<'static, [u8]>,
+        tag_is_valid: Cell<bool>,
+    }
+
+    impl CCMClient for TestClient {
+        fn crypt_done(
+            &self,
+            buf: &'static mut [u8],
+            res: Result<(), ErrorCode>,
+            tag_is_valid: bool,
+        ) {
+            assert_eq!(res, Ok(()));
+            self.tag_is_valid.set(tag_is_valid);
+            self.buf.replace(buf);
+        }
+    }
+
+    struct TestCcm {
+        aes: &'static Aes128Software<'static>,
+        aes_handle: DeferredCallHandle,
+        mux: &'static MuxAES128CCM<'static, Aes128Software<'static>>,
+        mux_handle: DeferredCallHandle,
+        ccm: &'static Ccm,
+        client: &'static TestClient,
+    }
+
+    impl TestCcm {
+        fn new() -> TestCcm {
+            let state = leak([
+                DynamicDeferredCallClientState::default(),
+                DynamicDeferredCallClientState::default(),
+            ]);
+            let deferred_caller = leak(DynamicDeferredCall::new(state));
+            let aes = leak(Aes128Software::new(deferred_caller));
+            let aes_handle = deferred_caller.register(aes).unwrap();
+            aes.initialize_callback_handle(aes_handle);
+            let mux = leak(MuxAES128CCM::new(aes, deferred_caller));
+            let mux_handle = deferred_caller.register(mux).unwrap();
+            mux.initialize_callback_handle(mux_handle);
+            AES128::set_client(aes, mux);
+            let ccm = leak(VirtualAES128CCM::new(mux, leak([0; MLE_CRYPT_BUF_LEN])));
+            ccm.setup();
+            let client = leak(TestClient {
+                buf: TakeCell::empty(),
+                tag_is_valid: Cell::new(false),
+            });
+            AES128CCM::set_client(ccm, client);
+            TestCcm {
+                aes,
+                aes_handle,
+                mux,
+                mux_handle,
+                ccm,
+                client,
+            }
+        }
+
+        /// Encrypts or decrypts the prepared message of `len` bytes in
+        /// `buf`, as `Mle` does. Returns whether the MIC is valid.
+        fn crypt(
+            &self,
+            buf: &'static mut [u8],
+            ext_addr: &[u8; 8],
+            frame_counter: u32,
+            len: usize,
+            encrypting: bool,
+        ) -> (&'static mut [u8], bool) {
+            let (mle_key, _) = derive_keys(&MASTER_KEY, 0);
+            assert_eq!(AES128CCM::set_key(self.ccm, &mle_key), Ok(()));
+            assert_eq!(
+                AES128CCM::set_nonce(self.ccm, &nonce(ext_addr, frame_counter)),
+                Ok(())
+            );
+            assert!(AES128CCM::crypt(
+                self.ccm,
+                buf,
+                SRC_ADDR_OFF,
+                COMMAND_OFF,
+                len,
+                MIC_LEN,
+                true,
+                encrypting
+            )
+            .is_ok());
+            for _ in 0..1000 {
+                if self.client.buf.is_some() {
+                    break;
+                }
+                self.mux.call(self.mux_handle);
+                self.aes.call(self.aes_handle);
+            }
+            (
+                self.client.buf.take().expect("crypt did not finish"),
+                self.client.tag_is_valid.get(),
+            )
+        }
+
+        fn seal(
+            &self,
+            src_ext_addr: &[u8; 8],
+            dst: IPAddr,
+            frame_counter: u32,
+            encode: impl FnOnce(&mut [u8]) -> SResult,
+        ) -> ([u8; MLE_BUF_LEN], usize) {
+            let src = IPAddr::generate_from_mac(MacAddress::Long(*src_ext_addr));
+            let buf = leak([0; MLE_BUF_LEN]);
+            let len = prepare_message(buf, src, dst, frame_counter, 0, encode).unwrap();
+            let (buf, tag_is_valid) = self.crypt(buf, src_ext_addr, frame_counter, len, true);
+            assert!(tag_is_valid);
+            buf[PAYLOAD_OFF] = SECURITY_SUITE_SECURED;
+            let mut out = [0; MLE_BUF_LEN];
+            out.copy_from_slice(buf);
+            (out, COMMAND_OFF + len + MIC_LEN)
+        }
+
+        /// Returns the decrypted command type and TLVs of `payload`, if it
+        /// is authentic.
+        fn open(
+            &self,
+            src_ext_addr: &[u8; 8],
+            dst: IPAddr,
+            payload: &[u8],
+        ) -> Option<([u8; MLE_BUF_LEN], usize)> {
+            let src = IPAddr::generate_from_mac(MacAddress::Long(*src_ext_addr));
+            let buf = leak([0; MLE_BUF_LEN]);
+            let (frame_counter, key_sequence, len) = load_message(buf, src, dst, payload)?;
+            assert_eq!(key_sequence, 0);
+            let (buf, tag_is_valid) = self.crypt(buf, src_ext_addr, frame_counter, len, false);
+            let mut out = [0; MLE_BUF_LEN];
+            out.copy_from_slice(buf);
+            if tag_is_valid {
+                Some((out, len))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn link_local(ext_addr: &[u8; 8]) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long(*ext_addr))
+    }
+
+    #[test]
+    fn key_derivation() {
+        let (mle_key, mac_key) = derive_keys(&MASTER_KEY, 0);
+        assert_eq!(
+            mle_key,
+            [
+                0x54, 0x45, 0xf4, 0x15, 0x8f, 0xd7, 0x59, 0x12, 0x17, 0x58, 0x09, 0xf8, 0xb5, 0x7a,
+                0x66, 0xa4
+            ]
+        );
+        assert_eq!(
+            mac_key,
+            [
+                0xde, 0x89, 0xc5, 0x3a, 0xf3, 0x82, 0xb4, 0x21, 0xe0, 0xfd, 0xe5, 0xa9, 0xba, 0xe3,
+                0xbe, 0xf0
+            ]
+        );
+        let (mle_key, _) = derive_keys(&MASTER_KEY, 1);
+        assert_eq!(
+            mle_key,
+            [
+                0x8f, 0x4c, 0xd1, 0xa2, 0x7d, 0x95, 0xc0, 0x7d, 0x12, 0xdb, 0x89, 0x74, 0xbd, 0x61,
+                0x5c, 0x13
+            ]
+        );
+    }
+
+    #[test]
+    fn child_attach_messages() {
+        let ccm = TestCcm::new();
+        let child = link_local(&CHILD_EXT_ADDR);
+        let parent = link_local(&PARENT_EXT_ADDR);
+
+        let (buf, end) = ccm.seal(&CHILD_EXT_ADDR, ALL_ROUTERS_ADDR, 0, |buf| {
+            encode_parent_request(buf, MODE, CHALLENGE, MulticastResponder::Router as u8)
+        });
+        assert_eq!(buf[PAYLOAD_OFF..end], PARENT_REQUEST);
+
+        let (buf, len) = ccm.open(&PARENT_EXT_ADDR, child, &PARENT_RESPONSE).unwrap();
+        assert_eq!(buf[COMMAND_OFF], command::PARENT_RESPONSE);
+        let response = ParentResponse::decode(&buf[COMMAND_OFF + 1..COMMAND_OFF + len]).unwrap();
+        assert_eq!(
+            response,
+            ParentResponse {
+                rloc16: 0x0400,
+                partition_id: 0x12345678,
+                response: CHALLENGE,
+                challenge: PARENT_CHALLENGE,
+                mle_frame_counter: 42,
+                link_margin: 30,
+                parent_priority: 0x40,
+                link_quality_3: 2,
+            }
+        );
+        assert_eq!(response.preference(), (3, 1, 2));
+
+        let (buf, end) = ccm.seal(&CHILD_EXT_ADDR, parent, 1, |buf| {
+            encode_child_id_request(buf, MODE, response.challenge, 1)
+        });
+        assert_eq!(buf[PAYLOAD_OFF..end], CHILD_ID_REQUEST);
+
+        let (buf, len) = ccm
+            .open(&PARENT_EXT_ADDR, child, &CHILD_ID_RESPONSE)
+            .unwrap();
+        assert_eq!(buf[COMMAND_OFF], command::CHILD_ID_RESPONSE);
+        assert_eq!(
+            ChildIdResponse::decode(&buf[COMMAND_OFF + 1..COMMAND_OFF + len]),
+            Some(ChildIdResponse {
+                parent_rloc16: 0x0400,
+                rloc16: 0x0401,
+                partition_id: 0x12345678,
+                leader_router_id: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_forged_messages() {
+        let ccm = TestCcm::new();
+        let child = link_local(&CHILD_EXT_ADDR);
+
+        let mut forged = PARENT_RESPONSE;
+        forged[20] ^= 1;
+        assert!(ccm.open(&PARENT_EXT_ADDR, child, &forged).is_none());
+
+        // The addresses are authenticated
+        let other = link_local(&[0x02, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(ccm
+            .open(&PARENT_EXT_ADDR, other, &PARENT_RESPONSE)
+            .is_none());
+
+        // Unsecured messages and mismatched key indices are not accepted
+        let mut unsecured = PARENT_RESPONSE;
+        unsecured[0] = 255;
+        assert!(decode_security_header(&unsecured).is_err());
+        let mut bad_index = PARENT_RESPONSE;
+        bad_index[10] = 2;
+        assert!(decode_security_header(&bad_index).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! Framing of packets on a serial line.
+//!
+//! Two framings are supported:
+//!
+//! - SLIP (RFC 1055): frames are delimited by END (0xc0) bytes, and END and
+//!   ESC (0xdb) bytes in the packet are escaped.
+//! - HDLC-like framing (RFC 1662): frames are delimited by flag (0x7e)
+//!   bytes, flag, escape (0x7d), XON (0x11) and XOFF (0x13) bytes in the
+//!   packet are escaped, and each frame ends with a 16 bit frame check
+//!   sequence (FCS). Frames with an invalid FCS are dropped. This is the
+//!   framing used by e.g. the OpenThread spinel protocol.
+//!
+//! Frames are encoded into a buffer with `Framing::encode`, and decoded one
+//! byte at a time with a `Decoder`.
+
+use core::cell::Cell;
+
+mod slip {
+    pub const END: u8 = 0xc0;
+    pub const ESC: u8 = 0xdb;
+    pub const ESC_END: u8 = 0xdc;
+    pub const ESC_ESC: u8 = 0xdd;
+}
+
+mod hdlc {
+    pub const FLAG: u8 = 0x7e;
+    pub const ESC: u8 = 0x7d;
+    pub const XOR: u8 = 0x20;
+    pub const XON: u8 = 0x11;
+    pub const XOFF: u8 = 0x13;
+    pub const FCS_INIT: u16 = 0xffff;
+    /// The FCS computed over a frame including its FCS
+    pub const FCS_GOOD: u16 = 0xf0b8;
+    pub const FCS_LEN: usize = 2;
+}
+
+/// Updates a CRC-16/X.25 frame check sequence with one byte.
+fn fcs_update(fcs: u16, byte: u8) -> u16 {
+    let mut fcs = fcs ^ byte as u16;
+    for _ in 0..8 {
+        fcs = if fcs & 1 != 0 {
+            (fcs >> 1) ^ 0x8408
+        } else {
+            fcs >> 1
+        };
+    }
+    fcs
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Framing {
+    Slip,
+    Hdlc,
+}
+
+impl Framing {
+    /// Returns the length of the longest frame of a packet of `len` bytes.
+    pub const fn max_frame_len(&self, len: usize) -> usize {
+        match self {
+            Framing::Slip => 2 * len + 2,
+            Framing::Hdlc => 2 * (len + hdlc::FCS_LEN) + 2,
+        }
+    }
+
+    fn delimiter(&self) -> u8 {
+        match self {
+            Framing::Slip => slip::END,
+            Framing::Hdlc => hdlc::FLAG,
+        }
+    }
+
+    fn escape(&self) -> u8 {
+        match self {
+            Framing::Slip => slip::ESC,
+            Framing::Hdlc => hdlc::ESC,
+        }
+    }
+
+    /// Writes `byte`, escaped if needed, at `frame[off..]`. Returns the
+    /// offset after it.
+    fn put(&self, frame: &mut [u8], off: usize, byte: u8) -> Option<usize> {
+        let escaped = match self {
+            Framing::Slip => match byte {
+                slip::END => Some(slip::ESC_END),
+                slip::ESC => Some(slip::ESC_ESC),
+                _ => None,
+            },
+            Framing::Hdlc => match byte {
+                hdlc::FLAG | hdlc::ESC | hdlc::XON | hdlc::XOFF => Some(byte ^ hdlc::XOR),
+                _ => None,
+            },
+        };
+        match escaped {
+            Some(escaped) => {
+                frame
+                    .get_mut(off..off + 2)?
+                    .copy_from_slice(&[self.escape(), escaped]);
+                Some(off + 2)
+            }
+            None => {
+                *frame.get_mut(off)? = byte;
+                Some(off + 1)
+            }
+        }
+    }
+
+    /// Frames `packet` into `frame`. Returns the length of the frame, or
+    /// `None` if it does not fit.
+    pub fn encode(&self, packet: &[u8], frame: &mut [u8]) -> Option<usize> {
+        // A leading delimiter flushes any noise the receiver has seen
+        *frame.get_mut(0)? = self.delimiter();
+        let mut off = 1;
+        let mut fcs = hdlc::FCS_INIT;
+        for &byte in packet {
+            off = self.put(frame, off, byte)?;
+            fcs = fcs_update(fcs, byte);
+        }
+        if *self == Framing::Hdlc {
+            for &byte in (!fcs).to_le_bytes().iter() {
+                off = self.put(frame, off, byte)?;
+            }
+        }
+        *frame.get_mut(off)? = self.delimiter();
+        Some(off + 1)
+    }
+}
+
+/// Reassembles frames received one byte at a time into a buffer.
+pub struct Decoder {
+    framing: Framing,
+    len: Cell<usize>,
+    escaped: Cell<bool>,
+    /// Set when the current frame is invalid, and is dropped at its end
+    discard: Cell<bool>,
+    fcs: Cell<u16>,
+}
+
+impl Decoder {
+    pub fn new(framing: Framing) -> Decoder {
+        Decoder {
+            framing: framing,
+            len: Cell::new(0),
+            escaped: Cell::new(false),
+            discard: Cell::new(false),
+            fcs: Cell::new(hdlc::FCS_INIT),
+        }
+    }
+
+    fn reset(&self) {
+        self.len.set(0);
+        self.escaped.set(false);
+        self.discard.set(false);
+        self.fcs.set(hdlc::FCS_INIT);
+    }
+
+    /// Ends the current frame. Returns the length of the packet in it, if
+    /// the frame is valid and not empty.
+    fn end(&self) -> Option<usize> {
+        let len = self.len.get();
+        let valid = !self.discard.get() && !self.escaped.get();
+        let fcs = self.fcs.get();
+        self.reset();
+        if !valid {
+            return None;
+        }
+        match self.framing {
+            Framing::Slip if len > 0 => Some(len),
+            Framing::Hdlc if len > hdlc::FCS_LEN && fcs == hdlc::FCS_GOOD => {
+                Some(len - hdlc::FCS_LEN)
+            }
+            _ => None,
+        }
+    }
+
+    /// Processes a received byte, storing the packet in `buf`. Returns the
+    /// length of the packet when a valid frame ends. Frames that do not
+    /// fit in `buf` are dropped.
+    pub fn push(&self, byte: u8, buf: &mut [u8]) -> Option<usize> {
+        if byte == self.framing.delimiter() {
+            return self.end();
+        }
+        if self.discard.get() {
+            return None;
+        }
+        let byte = if self.escaped.get() {
+            self.escaped.set(false);
+            match self.framing {
+                Framing::Slip => match byte {
+                    slip::ESC_END => slip::END,
+                    slip::ESC_ESC => slip::ESC,
+                    _ => {
+                        // Protocol violation
+                        self.discard.set(true);
+                        return None;
+                    }
+                },
+                Framing::Hdlc => byte ^ hdlc::XOR,
+            }
+        } else if byte == self.framing.escape() {
+            self.escaped.set(true);
+            return None;
+        } else {
+            byte
+        };
+        let len = self.len.get();
+        match buf.get_mut(len) {
+            Some(slot) => {
+                *slot = byte;
+                self.len.set(len + 1);
+                self.fcs.set(fcs_update(self.fcs.get(), byte));
+            }
+            None => self.discard.set(true),
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: [u8; 8] = [0x60, 0xc0, 0xdb, 0x7e, 0x7d, 0x11, 0x13, 0x00];
+
+    fn decode_all(decoder: &Decoder, frame: &[u8], buf: &mut [u8]) -> Option<usize> {
+        let mut result = None;
+        for &byte in frame {
+            if let Some(len) = decoder.push(byte, buf) {
+                assert!(result.is_none());
+                result = Some(len);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn slip_round_trip() {
+        let mut frame = [0; 32];
+        let len = Framing::Slip.encode(&PACKET, &mut frame).unwrap();
+        assert_eq!(
+            &frame[..len],
+            &[0xc0, 0x60, 0xdb, 0xdc, 0xdb, 0xdd, 0x7e, 0x7d, 0x11, 0x13, 0x00, 0xc0]
+        );
+        assert!(len <= Framing::Slip.max_frame_len(PACKET.len()));
+
+        let decoder = Decoder::new(Framing::Slip);
+        let mut buf = [0; 16];
+        assert_eq!(decode_all(&decoder, &frame[..len], &mut buf), Some(8));
+        assert_eq!(&buf[..8], &PACKET);
+    }
+
+    #[test]
+    fn hdlc_round_trip() {
+        let mut frame = [0; 32];
+        let len = Framing::Hdlc.encode(b"123456789", &mut frame).unwrap();
+        // The FCS of "123456789" is 0x906e
+        assert_eq!(&frame[len - 3..len], &[0x6e, 0x90, 0x7e]);
+
+        let len = Framing::Hdlc.encode(&PACKET, &mut frame).unwrap();
+        assert_eq!(
+            &frame[1..12],
+            &[0x60, 0xc0, 0xdb, 0x7d, 0x5e, 0x7d, 0x5d, 0x7d, 0x31, 0x7d, 0x33]
+        );
+        assert!(len <= Framing::Hdlc.max_frame_len(PACKET.len()));
+
+        let decoder = Decoder::new(Framing::Hdlc);
+        let mut buf = [0; 16];
+        assert_eq!(decode_all(&decoder, &frame[..len], &mut buf), Some(8));
+        assert_eq!(&buf[..8], &PACKET);
+
+        // A corrupted frame is dropped, and the next one is received
+        frame[2] ^= 1;
+        assert_eq!(decode_all(&decoder, &frame[..len], &mut buf), None);
+        frame[2] ^= 1;
+        assert_eq!(decode_all(&decoder, &frame[..len], &mut buf), Some(8));
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let mut frame = [0; 32];
+        let len = Framing::Slip.encode(&PACKET, &mut frame).unwrap();
+        let decoder = Decoder::new(Framing::Slip);
+
+        // Too long for the buffer
+        let mut buf = [0; 4];
+        assert_eq!(decode_all(&decoder, &frame[..len], &mut buf), None);
+
+        // Invalid escape sequence
+        let mut buf = [0; 16];
+        assert_eq!(
+            decode_all(&decoder, &[0xc0, 0x60, 0xdb, 0x01, 0xc0], &mut buf),
+            None
+        );
+        // Empty frames between delimiters are ignored
+        assert_eq!(
+            decode_all(&decoder, &[0xc0, 0xc0, 0x60, 0xc0], &mut buf),
+            Some(1)
+        );
+
+        // Frames do not fit in a too short output buffer
+        assert_eq!(Framing::Slip.encode(&PACKET, &mut frame[..10]), None);
+        assert_eq!(Framing::Hdlc.encode(&PACKET, &mut frame[..2]), None);
+    }
+}
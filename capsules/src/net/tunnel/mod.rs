@@ -0,0 +1,5 @@
+pub mod framing;
+pub mod serial_tunnel;
+
+pub use self::framing::Framing;
+pub use self::serial_tunnel::SerialTunnel;
@@ -0,0 +1,250 @@
+//! IPv6 network interface over a serial line.
+//!
+//! `SerialTunnel` carries IPv6 packets over a UART, framed with SLIP or
+//! HDLC-like framing (see `framing.rs`). It is an alternative to the 6LoWPAN
+//! path: it implements `IP6Sender`, so it can be used under `MuxUdpSender`
+//! or `MuxIP6Sender` in place of `IP6SendStruct`, and passes received
+//! packets to a `SixlowpanRxClient` such as `IP6RecvStruct`. The link is
+//! point-to-point, so there are no link-layer addresses, and packets to all
+//! destinations are sent to the other end of the line.
+//!
+//! On a Linux host, `tools/serial-tun.py` exposes the other end of the line
+//! as a TUN device.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let tunnel = static_init!(
+//!     capsules::net::tunnel::SerialTunnel<'static>,
+//!     capsules::net::tunnel::SerialTunnel::new(
+//!         uart_device,
+//!         Framing::Slip,
+//!         ip6_packet,
+//!         &mut PACKET_BUF,
+//!         &mut TX_BUF,
+//!         &mut RX_BUF,
+//!         &mut RX_BYTE,
+//!         ip_vis,
+//!     )
+//! );
+//! uart_device.set_transmit_client(tunnel);
+//! uart_device.set_receive_client(tunnel);
+//! tunnel.set_rx_client(ip_receive);
+//! tunnel.start();
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use crate::net::tunnel::framing::{Decoder, Framing};
+
+use core::cell::Cell;
+
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// The MTU of the interface, the minimum MTU of IPv6 links (RFC 8200).
+pub const MTU: usize = 1280;
+
+pub struct SerialTunnel<'a> {
+    uart: &'a dyn uart::UartData<'a>,
+    framing: Framing,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    src_addr: Cell<IPAddr>,
+    hop_limit: Cell<u8>,
+    /// Holds the packet being sent before it is framed
+    packet_buf: TakeCell<'static, [u8]>,
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_byte: TakeCell<'static, [u8]>,
+    decoder: Decoder,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    rx_client: OptionalCell<&'a dyn SixlowpanRxClient>,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a> SerialTunnel<'a> {
+    /// `packet_buf` and `rx_buf` limit the size of sent and received
+    /// packets, and should hold at least `MTU` bytes. `tx_buf` should hold
+    /// `framing.max_frame_len(packet_buf.len())` bytes, so that any packet
+    /// can be framed. `rx_byte` holds one received byte.
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        framing: Framing,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        packet_buf: &'static mut [u8],
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+        rx_byte: &'static mut [u8],
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> SerialTunnel<'a> {
+        SerialTunnel {
+            uart: uart,
+            framing: framing,
+            ip6_packet: TakeCell::new(ip6_packet),
+            src_addr: Cell::new(IPAddr::new()),
+            hop_limit: Cell::new(IP6Header::default().get_hop_limit()),
+            packet_buf: TakeCell::new(packet_buf),
+            tx_buf: TakeCell::new(tx_buf),
+            rx_buf: TakeCell::new(rx_buf),
+            rx_byte: TakeCell::new(rx_byte),
+            decoder: Decoder::new(framing),
+            client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            ip_vis: ip_vis,
+        }
+    }
+
+    /// Sets the client that received packets are passed to.
+    pub fn set_rx_client(&self, rx_client: &'a dyn SixlowpanRxClient) {
+        self.rx_client.set(rx_client);
+    }
+
+    /// Starts receiving packets.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        self.rx_byte
+            .take()
+            .map_or(Err(ErrorCode::ALREADY), |rx_byte| self.receive(rx_byte))
+    }
+
+    fn receive(&self, rx_byte: &'static mut [u8]) -> Result<(), ErrorCode> {
+        self.uart
+            .receive_buffer(rx_byte, 1)
+            .map_err(|(e, rx_byte)| {
+                self.rx_byte.replace(rx_byte);
+                e
+            })
+    }
+
+    /// Builds the packet in `packet_buf`, and returns its length.
+    fn encode_packet(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+    ) -> Result<usize, ErrorCode> {
+        let ip6_packet = self.ip6_packet.take().ok_or(ErrorCode::NOMEM)?;
+        let result = if payload.len() > ip6_packet.payload.payload.len() {
+            Err(ErrorCode::SIZE)
+        } else {
+            ip6_packet.header = IP6Header::default();
+            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.set_hop_limit(self.hop_limit.get());
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_payload(transport_header, payload);
+            ip6_packet.set_transport_checksum();
+            let len = ip6_packet.get_total_len() as usize;
+            self.packet_buf.map_or(Err(ErrorCode::NOMEM), |packet_buf| {
+                if len > packet_buf.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                ip6_packet
+                    .encode(packet_buf)
+                    .done()
+                    .map_or(Err(ErrorCode::FAIL), |_| Ok(len))
+            })
+        };
+        self.ip6_packet.replace(ip6_packet);
+        result
+    }
+}
+
+impl<'a> IP6Sender<'a> for SerialTunnel<'a> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_hop_limit(&self, hop_limit: u8) {
+        self.hop_limit.set(hop_limit);
+    }
+
+    fn set_gateway(&self, _gateway: MacAddress) {
+        // A serial line has no link-layer addresses
+    }
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return Err(ErrorCode::FAIL);
+        }
+        let tx_buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+        let frame_len = self
+            .encode_packet(dst, transport_header, payload)
+            .and_then(|len| {
+                self.packet_buf.map_or(Err(ErrorCode::NOMEM), |packet_buf| {
+                    self.framing
+                        .encode(&packet_buf[..len], tx_buf)
+                        .ok_or(ErrorCode::SIZE)
+                })
+            });
+        match frame_len {
+            Ok(frame_len) => self
+                .uart
+                .transmit_buffer(tx_buf, frame_len)
+                .map_err(|(e, tx_buf)| {
+                    self.tx_buf.replace(tx_buf);
+                    e
+                }),
+            Err(e) => {
+                self.tx_buf.replace(tx_buf);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a> uart::TransmitClient for SerialTunnel<'a> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buf.replace(tx_buffer);
+        self.client.map(|client| client.send_done(rval));
+    }
+}
+
+impl<'a> uart::ReceiveClient for SerialTunnel<'a> {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        if rval == Ok(()) && rx_len == 1 {
+            let byte = rx_buffer[0];
+            self.rx_buf.take().map(|rx_buf| {
+                if let Some(len) = self.decoder.push(byte, rx_buf) {
+                    self.rx_client
+                        .map(|client| client.receive(rx_buf, len, Ok(())));
+                }
+                self.rx_buf.replace(rx_buf);
+            });
+        }
+        let _ = self.receive(rx_buffer);
+    }
+}
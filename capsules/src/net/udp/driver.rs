@@ -5,9 +5,17 @@
 //! and bind to UDP ports for receiving packets.
 //! Also exposes a list of interface addresses to the application (currently
 //! hard-coded).
+//!
+//! Processes can join IPv6 multicast groups, if the network capability of the
+//! driver permits communicating with the group address. A process bound to a
+//! port receives the datagrams sent to that port of the groups it has joined.
+//! The driver holds one membership of the interface for each group joined by
+//! at least one process. Memberships of processes that have exited are
+//! released the next time any process joins or leaves a group.
 
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::network_capabilities::NetworkCapability;
+use crate::net::ipv6::multicast::{self, MulticastGroups};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::stream::encode_u16;
 use crate::net::stream::encode_u8;
 use crate::net::stream::SResult;
@@ -50,6 +58,9 @@ mod rw_allow {
     pub const COUNT: usize = 3;
 }
 
+/// The maximum number of multicast groups each process can join.
+pub const MAX_APP_GROUPS: usize = 2;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct UDPEndpoint {
     addr: IPAddr,
@@ -89,6 +100,13 @@ impl UDPEndpoint {
 pub struct App {
     pending_tx: Option<[UDPEndpoint; 2]>,
     bound_port: Option<UDPEndpoint>,
+    groups: [Option<IPAddr>; MAX_APP_GROUPS],
+}
+
+impl App {
+    fn is_member(&self, group: IPAddr) -> bool {
+        self.groups.iter().any(|g| *g == Some(group))
+    }
 }
 
 #[allow(dead_code)]
@@ -120,6 +138,14 @@ pub struct UDPDriver<'a> {
     driver_send_cap: &'static dyn UdpDriverCapability,
 
     net_cap: &'static NetworkCapability,
+
+    ip_vis: &'static IpVisibilityCapability,
+
+    /// Multicast groups of the interface
+    multicast_groups: &'a MulticastGroups,
+
+    /// Multicast groups the driver has joined on behalf of processes
+    joined_groups: [Cell<Option<IPAddr>>; multicast::MAX_GROUPS],
 }
 
 impl<'a> UDPDriver<'a> {
@@ -137,6 +163,8 @@ impl<'a> UDPDriver<'a> {
         kernel_buffer: LeasableBuffer<'static, u8>,
         driver_send_cap: &'static dyn UdpDriverCapability,
         net_cap: &'static NetworkCapability,
+        ip_vis: &'static IpVisibilityCapability,
+        multicast_groups: &'a MulticastGroups,
     ) -> UDPDriver<'a> {
         UDPDriver {
             sender: sender,
@@ -148,6 +176,9 @@ impl<'a> UDPDriver<'a> {
             kernel_buffer: MapCell::new(kernel_buffer),
             driver_send_cap: driver_send_cap,
             net_cap: net_cap,
+            ip_vis: ip_vis,
+            multicast_groups: multicast_groups,
+            joined_groups: Default::default(),
         }
     }
 
@@ -277,6 +308,95 @@ impl<'a> UDPDriver<'a> {
         })
     }
 
+    /// Reads the multicast group address from the config buffer of `appid`.
+    fn get_group_addr(&self, appid: ProcessId) -> Result<IPAddr, ErrorCode> {
+        self.apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::CFG)
+                    .and_then(|cfg| {
+                        cfg.enter(|cfg| {
+                            if cfg.len() < size_of::<IPAddr>() {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            let mut group = IPAddr::new();
+                            cfg[..size_of::<IPAddr>()].copy_to_slice(&mut group.0);
+                            Ok(group)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Leaves the groups that no process is a member of anymore.
+    fn release_unused_groups(&self) {
+        for joined in self.joined_groups.iter() {
+            if let Some(group) = joined.get() {
+                let mut used = false;
+                for app in self.apps.iter() {
+                    app.enter(|app, _| used |= app.is_member(group));
+                }
+                if !used {
+                    let _ = self.multicast_groups.leave(group);
+                    joined.set(None);
+                }
+            }
+        }
+    }
+
+    fn join_group(&self, appid: ProcessId, group: IPAddr) -> Result<(), ErrorCode> {
+        if !group.is_multicast() || !self.net_cap.remote_addr_valid(group, self.ip_vis) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                if app.is_member(group) {
+                    Err(ErrorCode::ALREADY)
+                } else if app.groups.iter().all(|g| g.is_some()) {
+                    Err(ErrorCode::NOMEM)
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.release_unused_groups();
+        if !self.joined_groups.iter().any(|g| g.get() == Some(group)) {
+            let free = self
+                .joined_groups
+                .iter()
+                .find(|g| g.get().is_none())
+                .ok_or(ErrorCode::NOMEM)?;
+            self.multicast_groups.join(group)?;
+            free.set(Some(group));
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                app.groups.iter_mut().find(|g| g.is_none()).map(|g| {
+                    *g = Some(group);
+                });
+            })
+            .map_err(ErrorCode::from)
+    }
+
+    fn leave_group(&self, appid: ProcessId, group: IPAddr) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| {
+                app.groups
+                    .iter_mut()
+                    .find(|g| **g == Some(group))
+                    .map_or(Err(ErrorCode::INVAL), |g| {
+                        *g = None;
+                        Ok(())
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.release_unused_groups();
+        Ok(())
+    }
+
     #[inline]
     fn parse_ip_port_pair(&self, buf: &[u8]) -> Option<UDPEndpoint> {
         if buf.len() != size_of::<UDPEndpoint>() {
@@ -377,6 +497,13 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///        /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
     ///        This represents the size of the payload buffer in the kernel. Apps can use this
     ///        syscall to ensure they do not attempt to send too-large messages.
+    /// - `5`: Join the multicast group whose address is in the first 16 bytes of the
+    ///        config buffer. Returns INVAL if the address is not a multicast address or
+    ///        the network capability of the driver does not permit it, ALREADY if the
+    ///        process is already a member of the group, and NOMEM if the process or the
+    ///        interface cannot join more groups.
+    /// - `6`: Leave the multicast group whose address is in the first 16 bytes of the
+    ///        config buffer. Returns INVAL if the process is not a member of the group.
 
     fn command(
         &self,
@@ -551,6 +678,20 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                 }
             }
             4 => CommandReturn::success_u32(self.max_tx_pyld_len as u32),
+            5 => match self
+                .get_group_addr(appid)
+                .and_then(|group| self.join_group(appid, group))
+            {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            },
+            6 => match self
+                .get_group_addr(appid)
+                .and_then(|group| self.leave_group(appid, group))
+            {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            },
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -590,7 +731,10 @@ impl<'a> UDPRecvClient for UDPDriver<'a> {
             if app.bound_port.is_some() {
                 let mut for_me = false;
                 app.bound_port.as_ref().map(|requested_addr| {
-                    if requested_addr.addr == dst_addr && requested_addr.port == dst_port {
+                    if requested_addr.port == dst_port
+                        && (requested_addr.addr == dst_addr
+                            || (dst_addr.is_multicast() && app.is_member(dst_addr)))
+                    {
                         for_me = true;
                     }
                 });
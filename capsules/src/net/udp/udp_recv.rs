@@ -5,7 +5,7 @@
 //! by the UDP userspace driver, which must correctly check bindings of kernel apps to ensure
 //! correctness when dispatching received packets to the appropriate client.
 
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use crate::net::ipv6::ipv6_recv::IP6RecvClient;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::driver::UDPDriver;
@@ -40,6 +40,9 @@ impl<'a> MuxUdpReceiver<'a> {
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
@@ -48,10 +51,14 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                     debug!("[UDP_RECV] Error: Received UDP length too long");
                     return;
                 }
+                // Multicast datagrams are delivered to every receiver bound
+                // to the port, unicast datagrams only to the first one
+                let multicast = ip_header.get_dst_addr().is_multicast();
                 for rcvr in self.rcvr_list.iter() {
                     match rcvr.binding.take() {
                         Some(binding) => {
-                            if binding.get_port() == dst_port {
+                            let bound = binding.get_port() == dst_port;
+                            if bound {
                                 rcvr.client.map(|client| {
                                     client.receive(
                                         ip_header.get_src_addr(),
@@ -61,15 +68,17 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                                         &payload[offset..],
                                     );
                                 });
-                                rcvr.binding.replace(binding);
-                                break;
                             }
                             rcvr.binding.replace(binding);
+                            if bound && !multicast {
+                                break;
+                            }
                         }
                         // The UDPReceiver used by the driver will not have a binding
                         None => match self.driver.take() {
                             Some(driver) => {
-                                if driver.is_bound(dst_port) {
+                                let bound = driver.is_bound(dst_port);
+                                if bound {
                                     driver.receive(
                                         ip_header.get_src_addr(),
                                         ip_header.get_dst_addr(),
@@ -77,10 +86,11 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                                         udp_header.get_dst_port(),
                                         &payload[offset..],
                                     );
-                                    self.driver.replace(driver);
-                                    break;
                                 }
                                 self.driver.replace(driver);
+                                if bound && !multicast {
+                                    break;
+                                }
                             }
                             None => {}
                         },
@@ -0,0 +1,26 @@
+//! Helper for capsules that serve a single process at a time.
+//!
+//! Such a capsule keeps the `ProcessId` of its owner in an `OptionalCell` and
+//! calls [`claim`] before every command. The first process to call it becomes
+//! the owner, and keeps the capsule until it exits or restarts.
+
+use kernel::grant::{AllowRoSize, AllowRwSize, Grant, UpcallSize};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Makes `processid` the owner, unless another process still alive in `apps`
+/// already owns the capsule, in which case `BUSY` is returned.
+pub fn claim<T: Default, Upcalls: UpcallSize, AllowROs: AllowRoSize, AllowRWs: AllowRwSize>(
+    owner: &OptionalCell<ProcessId>,
+    apps: &Grant<T, Upcalls, AllowROs, AllowRWs>,
+    processid: ProcessId,
+) -> Result<(), ErrorCode> {
+    let available = owner.map_or(true, |owner| {
+        *owner == processid || apps.enter(*owner, |_, _| {}).is_err()
+    });
+    if !available {
+        return Err(ErrorCode::BUSY);
+    }
+    owner.set(processid);
+    Ok(())
+}
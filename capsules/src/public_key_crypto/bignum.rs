@@ -0,0 +1,262 @@
+//! 256 bit modular arithmetic used by the curve implementations.
+//!
+//! Numbers are stored as eight 32 bit limbs, least significant limb first.
+//! Multiplication uses Montgomery's method, so values that are multiplied
+//! have to be converted into the Montgomery domain of their modulus with
+//! `to_mont()` first and converted back with `from_mont()`. Addition and
+//! subtraction work the same in both domains.
+//!
+//! These functions are only used to verify signatures, where all inputs are
+//! public, so they make no attempt to run in constant time.
+
+/// A 256 bit number, least significant limb first.
+pub(crate) type Limbs = [u32; 8];
+
+pub(crate) const ZERO: Limbs = [0; 8];
+pub(crate) const ONE: Limbs = [1, 0, 0, 0, 0, 0, 0, 0];
+
+/// An odd modulus together with the constants needed for Montgomery
+/// multiplication with `R = 2^256`.
+pub(crate) struct Modulus {
+    pub(crate) m: Limbs,
+    /// `-m^-1 mod 2^32`
+    pub(crate) m0inv: u32,
+    /// `R^2 mod m`
+    pub(crate) r2: Limbs,
+}
+
+/// The field prime of NIST P-256.
+pub(crate) const P256_P: Modulus = Modulus {
+    m: [
+        0xffffffff, 0xffffffff, 0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0x00000001,
+        0xffffffff,
+    ],
+    m0inv: 0x00000001,
+    r2: [
+        0x00000003, 0x00000000, 0xffffffff, 0xfffffffb, 0xfffffffe, 0xffffffff, 0xfffffffd,
+        0x00000004,
+    ],
+};
+
+/// The group order of NIST P-256.
+pub(crate) const P256_N: Modulus = Modulus {
+    m: [
+        0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad, 0xffffffff, 0xffffffff, 0x00000000,
+        0xffffffff,
+    ],
+    m0inv: 0xee00bc4f,
+    r2: [
+        0xbe79eea2, 0x83244c95, 0x49bd6fa6, 0x4699799c, 0x2b6bec59, 0x2845b239, 0xf3d95620,
+        0x66e12d94,
+    ],
+};
+
+/// The field prime of Curve25519, `2^255 - 19`.
+pub(crate) const ED25519_P: Modulus = Modulus {
+    m: [
+        0xffffffed, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff,
+        0x7fffffff,
+    ],
+    m0inv: 0x286bca1b,
+    r2: [
+        0x000005a4, 0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000,
+        0x00000000,
+    ],
+};
+
+/// The order of the Ed25519 base point, `2^252 +
+/// 27742317777372353535851937790883648493`.
+pub(crate) const ED25519_L: Modulus = Modulus {
+    m: [
+        0x5cf5d3ed, 0x5812631a, 0xa2f79cd6, 0x14def9de, 0x00000000, 0x00000000, 0x00000000,
+        0x10000000,
+    ],
+    m0inv: 0x12547e1b,
+    r2: [
+        0x449c0f01, 0xa40611e3, 0x68859347, 0xd00e1ba7, 0x17f5be65, 0xceec73d2, 0x7c309a3d,
+        0x0399411b,
+    ],
+};
+
+pub(crate) fn from_be_bytes(bytes: &[u8]) -> Limbs {
+    let mut r = ZERO;
+    for (i, b) in bytes.iter().rev().take(32).enumerate() {
+        r[i / 4] |= (*b as u32) << (8 * (i % 4));
+    }
+    r
+}
+
+pub(crate) fn from_le_bytes(bytes: &[u8]) -> Limbs {
+    let mut r = ZERO;
+    for (i, b) in bytes.iter().take(32).enumerate() {
+        r[i / 4] |= (*b as u32) << (8 * (i % 4));
+    }
+    r
+}
+
+pub(crate) fn to_le_bytes(a: &Limbs) -> [u8; 32] {
+    let mut r = [0; 32];
+    for (i, limb) in a.iter().enumerate() {
+        r[i * 4..i * 4 + 4].copy_from_slice(&limb.to_le_bytes());
+    }
+    r
+}
+
+pub(crate) fn is_zero(a: &Limbs) -> bool {
+    a.iter().all(|limb| *limb == 0)
+}
+
+pub(crate) fn bit(a: &Limbs, n: usize) -> bool {
+    (a[n / 32] >> (n % 32)) & 1 == 1
+}
+
+/// Returns true if `a < b`.
+pub(crate) fn less_than(a: &Limbs, b: &Limbs) -> bool {
+    for i in (0..8).rev() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+/// `a + b`, returning the carry.
+fn add(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+    let mut r = ZERO;
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let s = a[i] as u64 + b[i] as u64 + carry;
+        r[i] = s as u32;
+        carry = s >> 32;
+    }
+    (r, carry != 0)
+}
+
+/// `a - b`, returning the borrow.
+fn sub(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+    let mut r = ZERO;
+    let mut borrow = 0i64;
+    for i in 0..8 {
+        let d = a[i] as i64 - b[i] as i64 + borrow;
+        r[i] = d as u32;
+        borrow = d >> 32;
+    }
+    (r, borrow != 0)
+}
+
+impl Modulus {
+    /// `a + b mod m` for `a, b < m`.
+    pub(crate) fn add(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (s, carry) = add(a, b);
+        if carry || !less_than(&s, &self.m) {
+            sub(&s, &self.m).0
+        } else {
+            s
+        }
+    }
+
+    /// `a - b mod m` for `a, b < m`.
+    pub(crate) fn sub(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (d, borrow) = sub(a, b);
+        if borrow {
+            add(&d, &self.m).0
+        } else {
+            d
+        }
+    }
+
+    /// `-a mod m` for `a < m`.
+    pub(crate) fn neg(&self, a: &Limbs) -> Limbs {
+        self.sub(&ZERO, a)
+    }
+
+    /// Reduce any 256 bit number modulo `m`.
+    pub(crate) fn reduce(&self, a: &Limbs) -> Limbs {
+        self.from_mont(&self.to_mont(a))
+    }
+
+    /// Montgomery multiplication, `a * b * R^-1 mod m`. Requires
+    /// `a * b < m * R`, which holds if one argument is less than `m`.
+    pub(crate) fn mul(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let mut t = [0u32; 10];
+        for i in 0..8 {
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let s = t[j] as u64 + a[j] as u64 * b[i] as u64 + carry;
+                t[j] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[8] as u64 + carry;
+            t[8] = s as u32;
+            t[9] = (s >> 32) as u32;
+
+            let q = t[0].wrapping_mul(self.m0inv);
+            let s = t[0] as u64 + q as u64 * self.m[0] as u64;
+            let mut carry = s >> 32;
+            for j in 1..8 {
+                let s = t[j] as u64 + q as u64 * self.m[j] as u64 + carry;
+                t[j - 1] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[8] as u64 + carry;
+            t[7] = s as u32;
+            t[8] = t[9] + (s >> 32) as u32;
+            t[9] = 0;
+        }
+
+        let mut r = ZERO;
+        r.copy_from_slice(&t[..8]);
+        if t[8] != 0 || !less_than(&r, &self.m) {
+            sub(&r, &self.m).0
+        } else {
+            r
+        }
+    }
+
+    pub(crate) fn square(&self, a: &Limbs) -> Limbs {
+        self.mul(a, a)
+    }
+
+    /// Convert `a` into the Montgomery domain, reducing it modulo `m`.
+    pub(crate) fn to_mont(&self, a: &Limbs) -> Limbs {
+        self.mul(a, &self.r2)
+    }
+
+    /// Convert `a` out of the Montgomery domain.
+    pub(crate) fn from_mont(&self, a: &Limbs) -> Limbs {
+        self.mul(a, &ONE)
+    }
+
+    /// `1` in the Montgomery domain.
+    pub(crate) fn one(&self) -> Limbs {
+        self.to_mont(&ONE)
+    }
+
+    /// `a^e` for `a` in the Montgomery domain and a plain exponent.
+    pub(crate) fn pow(&self, a: &Limbs, e: &Limbs) -> Limbs {
+        let mut r = self.one();
+        for n in (0..256).rev() {
+            r = self.square(&r);
+            if bit(e, n) {
+                r = self.mul(&r, a);
+            }
+        }
+        r
+    }
+
+    /// `a^-1` for `a` in the Montgomery domain, using Fermat's little
+    /// theorem. `m` must be prime.
+    pub(crate) fn inv(&self, a: &Limbs) -> Limbs {
+        let e = sub(&self.m, &[2, 0, 0, 0, 0, 0, 0, 0]).0;
+        self.pow(a, &e)
+    }
+
+    /// `a^((m - 5) / 8)` for `a` in the Montgomery domain.
+    pub(crate) fn pow_m_minus_5_over_8(&self, a: &Limbs) -> Limbs {
+        let mut e = sub(&self.m, &[5, 0, 0, 0, 0, 0, 0, 0]).0;
+        for i in 0..8 {
+            e[i] = (e[i] >> 3) | e.get(i + 1).map_or(0, |next| next << 29);
+        }
+        self.pow(a, &e)
+    }
+}
@@ -0,0 +1,259 @@
+//! Arithmetic on the Ed25519 curve for signature verification (RFC 8032).
+//!
+//! Points are kept in extended twisted Edwards coordinates with the
+//! coordinates in the Montgomery domain of the field prime.
+
+use super::bignum::{self, Limbs, ED25519_L, ED25519_P};
+use crate::sha_software::{Algorithm, HashState};
+
+/// The curve constant `d = -121665 / 121666`.
+const D: Limbs = [
+    0x135978a3, 0x75eb4dca, 0x4141d8ab, 0x00700a4d, 0x7779e898, 0x8cc74079, 0x2b6ffe73, 0x52036cee,
+];
+
+/// X coordinate of the base point.
+const BX: Limbs = [
+    0x8f25d51a, 0xc9562d60, 0x9525a7b2, 0x692cc760, 0xfdd6dc5c, 0xc0a4e231, 0xcd6e53fe, 0x216936d3,
+];
+
+/// Y coordinate of the base point.
+const BY: Limbs = [
+    0x66666658, 0x66666666, 0x66666666, 0x66666666, 0x66666666, 0x66666666, 0x66666666, 0x66666666,
+];
+
+/// A square root of -1 modulo p.
+const SQRT_M1: Limbs = [
+    0x4a0ea0b0, 0xc4ee1b27, 0xad2fe478, 0x2f431806, 0x3dfbd7a7, 0x2b4d0099, 0x4fc1df0b, 0x2b832480,
+];
+
+/// A point in extended coordinates, `x = X/Z`, `y = Y/Z`, `x * y = T/Z`.
+#[derive(Clone, Copy)]
+pub(crate) struct Point {
+    x: Limbs,
+    y: Limbs,
+    z: Limbs,
+    t: Limbs,
+}
+
+/// A decoded public key.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct PublicKey {
+    encoded: [u8; 32],
+    x: Limbs,
+    y: Limbs,
+}
+
+impl PublicKey {
+    /// Decode a 32 byte public key. Returns `None` if it isn't a valid point.
+    pub(crate) fn from_bytes(key: &[u8]) -> Option<PublicKey> {
+        if key.len() != 32 {
+            return None;
+        }
+        let mut encoded = [0; 32];
+        encoded.copy_from_slice(key);
+        let (x, y) = decode(&encoded)?;
+        Some(PublicKey { encoded, x, y })
+    }
+
+    fn to_point(&self) -> Point {
+        Point::from_affine(&self.x, &self.y)
+    }
+}
+
+/// Decode a point, returning its plain affine coordinates.
+fn decode(encoded: &[u8; 32]) -> Option<(Limbs, Limbs)> {
+    let p = &ED25519_P;
+    let sign = encoded[31] >> 7;
+    let mut y_bytes = *encoded;
+    y_bytes[31] &= 0x7f;
+    let y = bignum::from_le_bytes(&y_bytes);
+    if !bignum::less_than(&y, &p.m) {
+        return None;
+    }
+
+    // x^2 = (y^2 - 1) / (d * y^2 + 1)
+    let one = p.one();
+    let ym = p.to_mont(&y);
+    let y2 = p.square(&ym);
+    let u = p.sub(&y2, &one);
+    let v = p.add(&p.mul(&p.to_mont(&D), &y2), &one);
+
+    // x = u * v^3 * (u * v^7)^((p - 5) / 8)
+    let v3 = p.mul(&p.square(&v), &v);
+    let v7 = p.mul(&p.square(&v3), &v);
+    let mut x = p.mul(&p.mul(&u, &v3), &p.pow_m_minus_5_over_8(&p.mul(&u, &v7)));
+
+    let vx2 = p.mul(&v, &p.square(&x));
+    if vx2 != u {
+        if vx2 == p.neg(&u) {
+            x = p.mul(&x, &p.to_mont(&SQRT_M1));
+        } else {
+            return None;
+        }
+    }
+
+    let mut x = p.from_mont(&x);
+    if bignum::is_zero(&x) && sign == 1 {
+        return None;
+    }
+    if (x[0] & 1) as u8 != sign {
+        x = p.neg(&x);
+    }
+    Some((x, y))
+}
+
+impl Point {
+    fn identity() -> Point {
+        let p = &ED25519_P;
+        Point {
+            x: bignum::ZERO,
+            y: p.one(),
+            z: p.one(),
+            t: bignum::ZERO,
+        }
+    }
+
+    fn from_affine(x: &Limbs, y: &Limbs) -> Point {
+        let p = &ED25519_P;
+        let x = p.to_mont(x);
+        let y = p.to_mont(y);
+        Point {
+            x,
+            y,
+            z: p.one(),
+            t: p.mul(&x, &y),
+        }
+    }
+
+    fn base() -> Point {
+        Point::from_affine(&BX, &BY)
+    }
+
+    fn neg(&self) -> Point {
+        let p = &ED25519_P;
+        Point {
+            x: p.neg(&self.x),
+            y: self.y,
+            z: self.z,
+            t: p.neg(&self.t),
+        }
+    }
+
+    /// Unified point addition for `a = -1` ("add-2008-hwcd-3"), which also
+    /// works for doubling and the identity.
+    fn add(&self, other: &Point, d2: &Limbs) -> Point {
+        let p = &ED25519_P;
+        let a = p.mul(&p.sub(&self.y, &self.x), &p.sub(&other.y, &other.x));
+        let b = p.mul(&p.add(&self.y, &self.x), &p.add(&other.y, &other.x));
+        let c = p.mul(&p.mul(&self.t, d2), &other.t);
+        let zz = p.mul(&self.z, &other.z);
+        let d = p.add(&zz, &zz);
+        let e = p.sub(&b, &a);
+        let f = p.sub(&d, &c);
+        let g = p.add(&d, &c);
+        let h = p.add(&b, &a);
+        Point {
+            x: p.mul(&e, &f),
+            y: p.mul(&g, &h),
+            z: p.mul(&f, &g),
+            t: p.mul(&e, &h),
+        }
+    }
+
+    fn encode(&self) -> [u8; 32] {
+        let p = &ED25519_P;
+        let zinv = p.inv(&self.z);
+        let x = p.from_mont(&p.mul(&self.x, &zinv));
+        let y = p.from_mont(&p.mul(&self.y, &zinv));
+        let mut encoded = bignum::to_le_bytes(&y);
+        encoded[31] |= ((x[0] & 1) as u8) << 7;
+        encoded
+    }
+}
+
+/// State of an Ed25519 verification, computing `S * B - k * A` with
+/// Shamir's trick one bit at a time and comparing it with `R`.
+#[derive(Clone, Copy)]
+pub(crate) struct Verification {
+    acc: Point,
+    b: Point,
+    neg_a: Point,
+    b_neg_a: Point,
+    d2: Limbs,
+    s: Limbs,
+    k: Limbs,
+    r: [u8; 32],
+    /// Number of bits still to process.
+    bits: usize,
+}
+
+impl Verification {
+    /// Start verifying `signature` of `message`. Returns `None` if the
+    /// signature is malformed and thus invalid.
+    pub(crate) fn new(key: &PublicKey, message: &[u8], signature: &[u8]) -> Option<Verification> {
+        if signature.len() != 64 {
+            return None;
+        }
+        let mut r = [0; 32];
+        r.copy_from_slice(&signature[..32]);
+        let s = bignum::from_le_bytes(&signature[32..]);
+        if !bignum::less_than(&s, &ED25519_L.m) {
+            return None;
+        }
+
+        // k = SHA-512(R || A || M) mod L
+        let mut hash = HashState::new(Algorithm::Sha512);
+        hash.update(&r);
+        hash.update(&key.encoded);
+        hash.update(message);
+        let mut digest = [0; 64];
+        hash.finish(&mut digest);
+        let l = &ED25519_L;
+        let lo = bignum::from_le_bytes(&digest[..32]);
+        let hi = bignum::from_le_bytes(&digest[32..]);
+        // hi * R^2 * R^-1 = hi * 2^256 mod L
+        let k = l.add(&l.reduce(&lo), &l.mul(&hi, &l.r2));
+
+        let p = &ED25519_P;
+        let d = p.to_mont(&D);
+        let d2 = p.add(&d, &d);
+        let b = Point::base();
+        let neg_a = key.to_point().neg();
+        Some(Verification {
+            acc: Point::identity(),
+            b,
+            neg_a,
+            b_neg_a: b.add(&neg_a, &d2),
+            d2,
+            s,
+            k,
+            r,
+            bits: 256,
+        })
+    }
+
+    /// Process up to `count` bits of the scalars. Returns true once all bits
+    /// have been processed.
+    pub(crate) fn step(&mut self, count: usize) -> bool {
+        for _ in 0..core::cmp::min(count, self.bits) {
+            self.bits -= 1;
+            self.acc = self.acc.add(&self.acc, &self.d2);
+            match (
+                bignum::bit(&self.s, self.bits),
+                bignum::bit(&self.k, self.bits),
+            ) {
+                (true, true) => self.acc = self.acc.add(&self.b_neg_a, &self.d2),
+                (true, false) => self.acc = self.acc.add(&self.b, &self.d2),
+                (false, true) => self.acc = self.acc.add(&self.neg_a, &self.d2),
+                (false, false) => {}
+            }
+        }
+        self.bits == 0
+    }
+
+    /// Whether the signature is valid. Only meaningful once `step()` returned
+    /// true.
+    pub(crate) fn result(&self) -> bool {
+        self.acc.encode() == self.r
+    }
+}
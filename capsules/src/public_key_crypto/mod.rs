@@ -0,0 +1,8 @@
+//! Public key cryptography.
+
+mod bignum;
+mod ed25519;
+mod p256;
+
+pub mod signature;
+pub mod software;
@@ -0,0 +1,258 @@
+//! Arithmetic on the NIST P-256 curve for ECDSA signature verification.
+//!
+//! Points are kept in Jacobian coordinates with the coordinates in the
+//! Montgomery domain of the field prime.
+
+use super::bignum::{self, Limbs, P256_N, P256_P};
+
+/// The curve coefficient `b`.
+const B: Limbs = [
+    0x27d2604b, 0x3bce3c3e, 0xcc53b0f6, 0x651d06b0, 0x769886bc, 0xb3ebbd55, 0xaa3a93e7, 0x5ac635d8,
+];
+
+/// X coordinate of the base point.
+const GX: Limbs = [
+    0xd898c296, 0xf4a13945, 0x2deb33a0, 0x77037d81, 0x63a440f2, 0xf8bce6e5, 0xe12c4247, 0x6b17d1f2,
+];
+
+/// Y coordinate of the base point.
+const GY: Limbs = [
+    0x37bf51f5, 0xcbb64068, 0x6b315ece, 0x2bce3357, 0x7c0f9e16, 0x8ee7eb4a, 0xfe1a7f9b, 0x4fe342e2,
+];
+
+/// A point in Jacobian coordinates. The point at infinity has `z == 0`.
+#[derive(Clone, Copy)]
+pub(crate) struct Point {
+    x: Limbs,
+    y: Limbs,
+    z: Limbs,
+}
+
+/// An affine point, with plain (not Montgomery) coordinates.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct PublicKey {
+    x: Limbs,
+    y: Limbs,
+}
+
+impl PublicKey {
+    /// Parse an uncompressed SEC1 point and check that it is on the curve.
+    pub(crate) fn from_bytes(key: &[u8]) -> Option<PublicKey> {
+        let coordinates = match key.len() {
+            65 if key[0] == 0x04 => &key[1..],
+            64 => key,
+            _ => return None,
+        };
+        let x = bignum::from_be_bytes(&coordinates[..32]);
+        let y = bignum::from_be_bytes(&coordinates[32..]);
+        if !bignum::less_than(&x, &P256_P.m) || !bignum::less_than(&y, &P256_P.m) {
+            return None;
+        }
+
+        // Check y^2 == x^3 - 3x + b.
+        let p = &P256_P;
+        let xm = p.to_mont(&x);
+        let ym = p.to_mont(&y);
+        let x3 = p.mul(&p.square(&xm), &xm);
+        let three_x = p.add(&p.add(&xm, &xm), &xm);
+        let rhs = p.add(&p.sub(&x3, &three_x), &p.to_mont(&B));
+        if p.square(&ym) != rhs {
+            return None;
+        }
+        Some(PublicKey { x, y })
+    }
+
+    fn to_point(&self) -> Point {
+        Point {
+            x: P256_P.to_mont(&self.x),
+            y: P256_P.to_mont(&self.y),
+            z: P256_P.one(),
+        }
+    }
+}
+
+impl Point {
+    pub(crate) fn infinity() -> Point {
+        Point {
+            x: P256_P.one(),
+            y: P256_P.one(),
+            z: bignum::ZERO,
+        }
+    }
+
+    fn generator() -> Point {
+        Point {
+            x: P256_P.to_mont(&GX),
+            y: P256_P.to_mont(&GY),
+            z: P256_P.one(),
+        }
+    }
+
+    fn is_infinity(&self) -> bool {
+        bignum::is_zero(&self.z)
+    }
+
+    /// Point doubling for `a = -3` ("dbl-2001-b").
+    pub(crate) fn double(&self) -> Point {
+        let p = &P256_P;
+        let delta = p.square(&self.z);
+        let gamma = p.square(&self.y);
+        let beta = p.mul(&self.x, &gamma);
+        let t = p.mul(&p.sub(&self.x, &delta), &p.add(&self.x, &delta));
+        let alpha = p.add(&p.add(&t, &t), &t);
+        let beta2 = p.add(&beta, &beta);
+        let beta4 = p.add(&beta2, &beta2);
+        let beta8 = p.add(&beta4, &beta4);
+        let x3 = p.sub(&p.square(&alpha), &beta8);
+        let yz = p.add(&self.y, &self.z);
+        let z3 = p.sub(&p.sub(&p.square(&yz), &gamma), &delta);
+        let gamma2 = p.square(&gamma);
+        let gamma2_2 = p.add(&gamma2, &gamma2);
+        let gamma2_4 = p.add(&gamma2_2, &gamma2_2);
+        let gamma2_8 = p.add(&gamma2_4, &gamma2_4);
+        let y3 = p.sub(&p.mul(&alpha, &p.sub(&beta4, &x3)), &gamma2_8);
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    /// General point addition ("add-2007-bl").
+    pub(crate) fn add(&self, other: &Point) -> Point {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+
+        let p = &P256_P;
+        let z1z1 = p.square(&self.z);
+        let z2z2 = p.square(&other.z);
+        let u1 = p.mul(&self.x, &z2z2);
+        let u2 = p.mul(&other.x, &z1z1);
+        let s1 = p.mul(&p.mul(&self.y, &other.z), &z2z2);
+        let s2 = p.mul(&p.mul(&other.y, &self.z), &z1z1);
+        let h = p.sub(&u2, &u1);
+        let s_diff = p.sub(&s2, &s1);
+
+        if bignum::is_zero(&h) {
+            return if bignum::is_zero(&s_diff) {
+                self.double()
+            } else {
+                Point::infinity()
+            };
+        }
+
+        let h2 = p.add(&h, &h);
+        let i = p.square(&h2);
+        let j = p.mul(&h, &i);
+        let r = p.add(&s_diff, &s_diff);
+        let v = p.mul(&u1, &i);
+        let x3 = p.sub(&p.sub(&p.square(&r), &j), &p.add(&v, &v));
+        let s1j = p.mul(&s1, &j);
+        let y3 = p.sub(&p.mul(&r, &p.sub(&v, &x3)), &p.add(&s1j, &s1j));
+        let zz = p.add(&self.z, &other.z);
+        let z3 = p.mul(&p.sub(&p.sub(&p.square(&zz), &z1z1), &z2z2), &h);
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    /// The plain affine x coordinate, or `None` for the point at infinity.
+    fn affine_x(&self) -> Option<Limbs> {
+        if self.is_infinity() {
+            return None;
+        }
+        let p = &P256_P;
+        let zinv = p.inv(&self.z);
+        Some(p.from_mont(&p.mul(&self.x, &p.square(&zinv))))
+    }
+}
+
+/// State of an ECDSA verification, computing `u1 * G + u2 * Q` with Shamir's
+/// trick one bit at a time.
+#[derive(Clone, Copy)]
+pub(crate) struct Verification {
+    acc: Point,
+    g: Point,
+    q: Point,
+    gq: Point,
+    u1: Limbs,
+    u2: Limbs,
+    r: Limbs,
+    /// Number of bits still to process.
+    bits: usize,
+}
+
+impl Verification {
+    /// Start verifying the signature `r || s` of the hash `hash`. Returns
+    /// `None` if the signature is malformed and thus invalid.
+    pub(crate) fn new(key: &PublicKey, hash: &[u8], signature: &[u8]) -> Option<Verification> {
+        if signature.len() != 64 {
+            return None;
+        }
+        let n = &P256_N;
+        let r = bignum::from_be_bytes(&signature[..32]);
+        let s = bignum::from_be_bytes(&signature[32..]);
+        if bignum::is_zero(&r)
+            || bignum::is_zero(&s)
+            || !bignum::less_than(&r, &n.m)
+            || !bignum::less_than(&s, &n.m)
+        {
+            return None;
+        }
+
+        // Use the leftmost 256 bits of the hash.
+        let e = bignum::from_be_bytes(&hash[..core::cmp::min(hash.len(), 32)]);
+        let w = n.inv(&n.to_mont(&s));
+        // Multiplying a plain value with a Montgomery value gives a plain
+        // result.
+        let u1 = n.mul(&e, &w);
+        let u2 = n.mul(&r, &w);
+
+        let g = Point::generator();
+        let q = key.to_point();
+        Some(Verification {
+            acc: Point::infinity(),
+            g,
+            q,
+            gq: g.add(&q),
+            u1,
+            u2,
+            r,
+            bits: 256,
+        })
+    }
+
+    /// Process up to `count` bits of the scalars. Returns true once all bits
+    /// have been processed.
+    pub(crate) fn step(&mut self, count: usize) -> bool {
+        for _ in 0..core::cmp::min(count, self.bits) {
+            self.bits -= 1;
+            self.acc = self.acc.double();
+            match (
+                bignum::bit(&self.u1, self.bits),
+                bignum::bit(&self.u2, self.bits),
+            ) {
+                (true, true) => self.acc = self.acc.add(&self.gq),
+                (true, false) => self.acc = self.acc.add(&self.g),
+                (false, true) => self.acc = self.acc.add(&self.q),
+                (false, false) => {}
+            }
+        }
+        self.bits == 0
+    }
+
+    /// Whether the signature is valid. Only meaningful once `step()` returned
+    /// true.
+    pub(crate) fn result(&self) -> bool {
+        match self.acc.affine_x() {
+            Some(x) => P256_N.reduce(&x) == self.r,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Provides userspace access to signature verification.
+//!
+//! This capsule sits on top of any implementation of
+//! `hil::public_key_crypto::SignatureVerify` (such as
+//! `capsules::public_key_crypto::software::SignatureVerifySoftware`) and lets
+//! applications import public keys and verify signatures with them.
+//!
+//! Imported keys belong to the application that imported them: other
+//! applications can neither use nor remove them. Keys of applications that
+//! have exited are released when the key store runs full.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let signature_driver = static_init!(
+//!     capsules::public_key_crypto::signature::SignatureDriver<
+//!         'static,
+//!         capsules::public_key_crypto::software::SignatureVerifySoftware<'static, 4>,
+//!         4,
+//!     >,
+//!     capsules::public_key_crypto::signature::SignatureDriver::new(
+//!         verifier,
+//!         &mut capsules::public_key_crypto::signature::MESSAGE_BUFFER,
+//!         &mut capsules::public_key_crypto::signature::SIGNATURE_BUFFER,
+//!         board_kernel.create_grant(
+//!             capsules::public_key_crypto::signature::DRIVER_NUM,
+//!             &grant_cap
+//!         ),
+//!     )
+//! );
+//! verifier.set_verify_client(signature_driver);
+//! ```
+
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::public_key_crypto::{
+    ClientVerify, KeyHandle, SignatureAlgorithm, SignatureVerify,
+};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SignatureVerify as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const KEY: usize = 0;
+    pub const MESSAGE: usize = 1;
+    pub const SIGNATURE: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 0;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const VERIFY_DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Length of the longest supported public key.
+const MAX_KEY_LEN: usize = 65;
+
+/// Default buffer for messages. Limits the length of the messages that can be
+/// verified.
+pub static mut MESSAGE_BUFFER: [u8; 256] = [0; 256];
+
+/// Default buffer for signatures.
+pub static mut SIGNATURE_BUFFER: [u8; 64] = [0; 64];
+
+#[derive(Clone, Copy)]
+struct Verification {
+    key: KeyHandle,
+    length: usize,
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: Option<Verification>,
+}
+
+pub struct SignatureDriver<'a, V: SignatureVerify<'a>, const N: usize> {
+    verifier: &'a V,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The keys imported through this driver and the apps they belong to.
+    owners: MapCell<[Option<(KeyHandle, ProcessId)>; N]>,
+    current_app: OptionalCell<ProcessId>,
+    message_buffer: TakeCell<'static, [u8]>,
+    signature_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, V: SignatureVerify<'a>, const N: usize> SignatureDriver<'a, V, N> {
+    pub fn new(
+        verifier: &'a V,
+        message_buffer: &'static mut [u8],
+        signature_buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> SignatureDriver<'a, V, N> {
+        SignatureDriver {
+            verifier,
+            apps: grant,
+            owners: MapCell::new([None; N]),
+            current_app: OptionalCell::empty(),
+            message_buffer: TakeCell::new(message_buffer),
+            signature_buffer: TakeCell::new(signature_buffer),
+        }
+    }
+
+    fn owns_key(&self, key: KeyHandle, appid: ProcessId) -> bool {
+        self.owners.map_or(false, |owners| {
+            owners.iter().any(|owner| *owner == Some((key, appid)))
+        })
+    }
+
+    fn has_free_slot(&self) -> bool {
+        self.owners
+            .map_or(false, |owners| owners.iter().any(|owner| owner.is_none()))
+    }
+
+    /// Remove the keys of apps that no longer exist. Returns true if any key
+    /// was removed.
+    fn reclaim_keys(&self) -> bool {
+        self.owners.map_or(false, |owners| {
+            let mut reclaimed = false;
+            for owner in owners.iter_mut() {
+                if let Some((key, appid)) = *owner {
+                    if self.apps.enter(appid, |_, _| {}).is_err()
+                        && self.verifier.remove_key(key).is_ok()
+                    {
+                        *owner = None;
+                        reclaimed = true;
+                    }
+                }
+            }
+            reclaimed
+        })
+    }
+
+    /// Copy the key the app allowed and import it.
+    fn import_key(&self, algorithm: usize, appid: ProcessId) -> Result<KeyHandle, ErrorCode> {
+        let algorithm = match algorithm {
+            0 => SignatureAlgorithm::EcdsaP256,
+            1 => SignatureAlgorithm::Ed25519,
+            _ => return Err(ErrorCode::NOSUPPORT),
+        };
+
+        let mut key = [0; MAX_KEY_LEN];
+        let key_len = self
+            .apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::KEY)
+                    .and_then(|key_buffer| {
+                        key_buffer.enter(|data| {
+                            if data.len() > MAX_KEY_LEN {
+                                Err(ErrorCode::INVAL)
+                            } else {
+                                data.copy_to_slice(&mut key[..data.len()]);
+                                Ok(data.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        if !self.has_free_slot() {
+            self.reclaim_keys();
+            if !self.has_free_slot() {
+                return Err(ErrorCode::NOMEM);
+            }
+        }
+
+        let handle = match self.verifier.import_key(algorithm, &key[..key_len]) {
+            Err(ErrorCode::NOMEM) if self.reclaim_keys() => {
+                self.verifier.import_key(algorithm, &key[..key_len])
+            }
+            res => res,
+        }?;
+
+        self.owners.map(|owners| {
+            if let Some(owner) = owners.iter_mut().find(|owner| owner.is_none()) {
+                *owner = Some((handle, appid));
+            }
+        });
+        Ok(handle)
+    }
+
+    fn remove_key(&self, key: KeyHandle, appid: ProcessId) -> Result<(), ErrorCode> {
+        if !self.owns_key(key, appid) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.verifier.remove_key(key)?;
+        self.owners.map(|owners| {
+            for owner in owners.iter_mut() {
+                if *owner == Some((key, appid)) {
+                    *owner = None;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Either start the verification right away or queue it for the app if
+    /// the verifier is in use.
+    fn enqueue(&self, verification: Verification, appid: ProcessId) -> Result<(), ErrorCode> {
+        if self.current_app.is_none() {
+            self.current_app.set(appid);
+            let res = self.start(verification, appid);
+            if res.is_err() {
+                self.current_app.clear();
+            }
+            res
+        } else {
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending.is_some() {
+                        Err(ErrorCode::NOMEM)
+                    } else {
+                        app.pending = Some(verification);
+                        Ok(())
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        }
+    }
+
+    /// Copy the message and signature the app allowed and start verifying.
+    fn start(&self, verification: Verification, appid: ProcessId) -> Result<(), ErrorCode> {
+        if !self.owns_key(verification.key, appid) {
+            return Err(ErrorCode::INVAL);
+        }
+        let message = self.message_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        let signature = match self.signature_buffer.take() {
+            Some(signature) => signature,
+            None => {
+                self.message_buffer.replace(message);
+                return Err(ErrorCode::RESERVE);
+            }
+        };
+
+        let copied = self
+            .apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::MESSAGE)
+                    .and_then(|message_buffer| {
+                        message_buffer.enter(|data| {
+                            if verification.length > cmp::min(data.len(), message.len()) {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data[..verification.length]
+                                    .copy_to_slice(&mut message[..verification.length]);
+                                Ok(())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SIGNATURE)
+                    .and_then(|signature_buffer| {
+                        signature_buffer.enter(|data| {
+                            if data.len() != signature.len() {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data.copy_to_slice(signature);
+                                Ok(())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = copied {
+            self.message_buffer.replace(message);
+            self.signature_buffer.replace(signature);
+            return Err(e);
+        }
+
+        let mut message = LeasableBuffer::new(message);
+        message.slice(..verification.length);
+        self.verifier
+            .verify(verification.key, message, signature)
+            .map_err(|(ecode, message, signature)| {
+                self.message_buffer.replace(message);
+                self.signature_buffer.replace(signature);
+                ecode
+            })
+    }
+
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            let appid = cntr.processid();
+            let verification = cntr.enter(|app, _| app.pending.take());
+            if let Some(verification) = verification {
+                self.current_app.set(appid);
+                if let Err(e) = self.start(verification, appid) {
+                    // Report the failure and move on to the next app.
+                    self.current_app.clear();
+                    let _ = self.apps.enter(appid, |_, kernel_data| {
+                        kernel_data
+                            .schedule_upcall(upcall::VERIFY_DONE, (into_statuscode(Err(e)), 0, 0))
+                            .ok();
+                    });
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, V: SignatureVerify<'a>, const N: usize> ClientVerify for SignatureDriver<'a, V, N> {
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) {
+        self.message_buffer.replace(message);
+        self.signature_buffer.replace(signature);
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |_, kernel_data| {
+                let valid = result == Ok(true);
+                kernel_data
+                    .schedule_upcall(
+                        upcall::VERIFY_DONE,
+                        (into_statuscode(result.map(|_| ())), valid as usize, 0),
+                    )
+                    .ok();
+            });
+        });
+        self.check_queue();
+    }
+}
+
+/// Provide an interface for userland.
+impl<'a, V: SignatureVerify<'a>, const N: usize> SyscallDriver for SignatureDriver<'a, V, N> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Import the public key in the key buffer for the algorithm in
+    ///        `data1` (`0` for ECDSA P-256, `1` for Ed25519). Returns the
+    ///        handle of the key.
+    /// - `2`: Remove the key with the handle in `data1`.
+    /// - `3`: Verify the signature in the signature buffer over the first
+    ///        `data2` bytes of the message buffer with the key in `data1`.
+    ///        Upcall 0 is called with the status and `1` if the signature is
+    ///        valid, `0` otherwise.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let res = match command_num {
+            0 => Ok(()),
+            1 => {
+                return match self.import_key(data1, appid) {
+                    Ok(handle) => CommandReturn::success_u32(handle.0 as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+            2 => self.remove_key(KeyHandle(data1), appid),
+            3 => {
+                if !self.owns_key(KeyHandle(data1), appid) {
+                    Err(ErrorCode::INVAL)
+                } else {
+                    self.enqueue(
+                        Verification {
+                            key: KeyHandle(data1),
+                            length: data2,
+                        },
+                        appid,
+                    )
+                }
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,237 @@
+//! Software implementation of signature verification.
+//!
+//! This capsule implements `hil::public_key_crypto::SignatureVerify` entirely
+//! in software for ECDSA with the NIST P-256 curve and for Ed25519, so that
+//! signatures can be checked on chips without a public key accelerator.
+//!
+//! Verifying a signature takes a few hundred thousand field multiplications,
+//! far too long to do in one go. The work is therefore done from a deferred
+//! call that processes a few bits of the scalars at a time and then
+//! re-schedules itself, so the rest of the kernel keeps running while a
+//! verification is in progress.
+//!
+//! Up to `N` public keys can be imported at the same time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let verifier = static_init!(
+//!     capsules::public_key_crypto::software::SignatureVerifySoftware<'static, 4>,
+//!     capsules::public_key_crypto::software::SignatureVerifySoftware::new(
+//!         dynamic_deferred_caller
+//!     )
+//! );
+//! verifier.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(verifier).unwrap(), // Unwrap fail = no deferred call slot available for the verifier
+//! );
+//! ```
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::public_key_crypto::{
+    ClientVerify, KeyHandle, PublicKeyManager, SignatureAlgorithm, SignatureVerify,
+};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+use super::{ed25519, p256};
+
+/// Number of scalar bits processed per deferred call.
+const BITS_PER_CALL: usize = 16;
+
+#[derive(Clone, Copy)]
+enum Key {
+    EcdsaP256(p256::PublicKey),
+    Ed25519(ed25519::PublicKey),
+}
+
+impl Key {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            Key::EcdsaP256(_) => SignatureAlgorithm::EcdsaP256,
+            Key::Ed25519(_) => SignatureAlgorithm::Ed25519,
+        }
+    }
+}
+
+enum Job {
+    EcdsaP256(p256::Verification),
+    Ed25519(ed25519::Verification),
+}
+
+pub struct SignatureVerifySoftware<'a, const N: usize> {
+    keys: MapCell<[Option<Key>; N]>,
+    client: OptionalCell<&'a dyn ClientVerify>,
+
+    /// Index of the key used by the ongoing verification.
+    active_key: OptionalCell<usize>,
+    job: MapCell<Job>,
+    message: MapCell<LeasableBuffer<'static, u8>>,
+    signature: TakeCell<'static, [u8]>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, const N: usize> SignatureVerifySoftware<'a, N> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> SignatureVerifySoftware<'a, N> {
+        SignatureVerifySoftware {
+            keys: MapCell::new([None; N]),
+            client: OptionalCell::empty(),
+            active_key: OptionalCell::empty(),
+            job: MapCell::empty(),
+            message: MapCell::empty(),
+            signature: TakeCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn get_key(&self, handle: KeyHandle) -> Option<Key> {
+        self.keys
+            .map(|keys| keys.get(handle.0).copied().flatten())
+            .flatten()
+    }
+
+    /// Parse the signature and prepare the computation. Returns `None` if the
+    /// signature is malformed, which means it is invalid.
+    fn start_job(&self) -> Option<Job> {
+        let key = self.get_key(KeyHandle(self.active_key.extract()?))?;
+        let message = self.message.take()?;
+        let job = self.signature.map(|signature| match key {
+            Key::EcdsaP256(key) => {
+                p256::Verification::new(&key, &message[..], signature).map(Job::EcdsaP256)
+            }
+            Key::Ed25519(key) => {
+                ed25519::Verification::new(&key, &message[..], signature).map(Job::Ed25519)
+            }
+        });
+        self.message.put(message);
+        job.flatten()
+    }
+
+    fn finish(&self, result: Result<bool, ErrorCode>) {
+        self.job.take();
+        self.active_key.clear();
+        let message = self.message.take();
+        let signature = self.signature.take();
+        if let (Some(message), Some(signature)) = (message, signature) {
+            self.client.map(|client| {
+                client.verification_done(result, message.take(), signature);
+            });
+        }
+    }
+}
+
+impl<'a, const N: usize> PublicKeyManager for SignatureVerifySoftware<'a, N> {
+    fn import_key(
+        &self,
+        algorithm: SignatureAlgorithm,
+        key: &[u8],
+    ) -> Result<KeyHandle, ErrorCode> {
+        let key = match algorithm {
+            SignatureAlgorithm::EcdsaP256 => p256::PublicKey::from_bytes(key).map(Key::EcdsaP256),
+            SignatureAlgorithm::Ed25519 => ed25519::PublicKey::from_bytes(key).map(Key::Ed25519),
+        }
+        .ok_or(ErrorCode::INVAL)?;
+
+        self.keys
+            .map(|keys| {
+                let index = keys
+                    .iter()
+                    .position(|slot| slot.is_none())
+                    .ok_or(ErrorCode::NOMEM)?;
+                keys[index] = Some(key);
+                Ok(KeyHandle(index))
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+
+    fn remove_key(&self, handle: KeyHandle) -> Result<(), ErrorCode> {
+        if self.active_key.contains(&handle.0) {
+            return Err(ErrorCode::BUSY);
+        }
+        self.keys
+            .map(|keys| match keys.get_mut(handle.0) {
+                Some(slot) if slot.is_some() => {
+                    *slot = None;
+                    Ok(())
+                }
+                _ => Err(ErrorCode::INVAL),
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))
+    }
+
+    fn key_algorithm(&self, handle: KeyHandle) -> Result<SignatureAlgorithm, ErrorCode> {
+        self.get_key(handle)
+            .map(|key| key.algorithm())
+            .ok_or(ErrorCode::INVAL)
+    }
+}
+
+impl<'a, const N: usize> SignatureVerify<'a> for SignatureVerifySoftware<'a, N> {
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify) {
+        self.client.set(client);
+    }
+
+    fn verify(
+        &self,
+        key: KeyHandle,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8], &'static mut [u8])> {
+        if self.active_key.is_some() {
+            return Err((ErrorCode::BUSY, message.take(), signature));
+        }
+        let algorithm = match self.key_algorithm(key) {
+            Ok(algorithm) => algorithm,
+            Err(e) => return Err((e, message.take(), signature)),
+        };
+        if signature.len() != algorithm.signature_len() {
+            return Err((ErrorCode::SIZE, message.take(), signature));
+        }
+
+        self.active_key.set(key.0);
+        self.message.put(message);
+        self.signature.replace(signature);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+}
+
+impl<'a, const N: usize> DynamicDeferredCallClient for SignatureVerifySoftware<'a, N> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.job.is_none() {
+            match self.start_job() {
+                Some(job) => self.job.put(job),
+                None => {
+                    self.finish(Ok(false));
+                    return;
+                }
+            }
+        }
+
+        let done = self.job.map_or(true, |job| match job {
+            Job::EcdsaP256(verification) => verification.step(BITS_PER_CALL),
+            Job::Ed25519(verification) => verification.step(BITS_PER_CALL),
+        });
+        if done {
+            let valid = self.job.map_or(false, |job| match job {
+                Job::EcdsaP256(verification) => verification.result(),
+                Job::Ed25519(verification) => verification.result(),
+            });
+            self.finish(Ok(valid));
+        } else {
+            self.handle.map(|handle| self.deferred_caller.set(*handle));
+        }
+    }
+}
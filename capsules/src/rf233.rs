@@ -790,7 +790,7 @@ impl<'a, S: spi::SpiMasterDevice> spi::SpiMasterClient for RF233<'a, S> {
                 if status == ExternalState::RX_AACK_ON as u8 {
                     let return_code = if (result & TRX_TRAC_MASK) == TRX_TRAC_CHANNEL_ACCESS_FAILURE
                     {
-                        Err(ErrorCode::FAIL)
+                        Err(ErrorCode::BUSY)
                     } else {
                         Ok(())
                     };
@@ -1379,3 +1379,10 @@ impl<S: spi::SpiMasterDevice> radio::RadioData for RF233<'_, S> {
         Ok(())
     }
 }
+
+impl<S: spi::SpiMasterDevice> radio::RadioChannelAccess for RF233<'_, S> {
+    fn set_single_attempt(&self, _enable: bool) {
+        // XAH_CTRL_0 disables CSMA and frame retries, so the radio always
+        // makes a single attempt after its initial random backoff
+    }
+}
@@ -0,0 +1,755 @@
+//! Software implementation of the SHA-2 family and HMAC.
+//!
+//! This capsule implements the `hil::digest` traits entirely in software, so
+//! that the digest capsules (`virtual_digest`, `sha`, `hmac`) can be used on
+//! chips that do not have a hash accelerator. It supports SHA-224, SHA-256,
+//! SHA-384 and SHA-512, as well as HMAC with SHA-256, SHA-384 and SHA-512.
+//!
+//! All operations complete asynchronously: the work is done from a deferred
+//! call and the client is called back from there. Long inputs are hashed a
+//! few blocks at a time, re-scheduling the deferred call in between, so that
+//! hashing a large buffer does not block the rest of the kernel.
+//!
+//! `L` is the length of the digest buffer. It has to be large enough for the
+//! output of the selected mode: selecting a mode whose output does not fit in
+//! `L` bytes fails with `NOSUPPORT`. Digests shorter than `L` are written to
+//! the start of the buffer and the rest of the buffer is zeroed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let sha = static_init!(
+//!     capsules::sha_software::ShaSoftware<'static, 32>,
+//!     capsules::sha_software::ShaSoftware::new(dynamic_deferred_caller)
+//! );
+//! sha.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(sha).unwrap(), // Unwrap fail = no deferred call slot available for SHA
+//! );
+//!
+//! let mux_digest = components::digest::DigestMuxComponent::new(sha).finalize(
+//!     components::digest_mux_component_helper!(capsules::sha_software::ShaSoftware<'static, 32>, 32),
+//! );
+//! ```
+
+use core::cell::Cell;
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::digest;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// Number of bytes hashed per deferred call.
+const BYTES_PER_CALL: usize = 512;
+
+const SHA256_BLOCK_SIZE: usize = 64;
+const SHA512_BLOCK_SIZE: usize = 128;
+
+const SHA224_INIT: [u32; 8] = [
+    0xc1059ed8, 0x367cd507, 0x3070dd17, 0xf70e5939, 0xffc00b31, 0x68581511, 0x64f98fa7,
+    0xbefa4fa4,
+];
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+    0x5be0cd19,
+];
+
+const SHA384_INIT: [u64; 8] = [
+    0xcbbb9d5dc1059ed8,
+    0x629a292a367cd507,
+    0x9159015a3070dd17,
+    0x152fecd8f70e5939,
+    0x67332667ffc00b31,
+    0x8eb44a8768581511,
+    0xdb0c2e0d64f98fa7,
+    0x47b5481dbefa4fa4,
+];
+
+const SHA512_INIT: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+    0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+    0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+    0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+    0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+    0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+    0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+    0xc67178f2,
+];
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Algorithm {
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Length of the digest in bytes.
+    fn output_len(&self) -> usize {
+        match self {
+            Algorithm::Sha224 => 28,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha384 => 48,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        match self {
+            Algorithm::Sha224 | Algorithm::Sha256 => SHA256_BLOCK_SIZE,
+            Algorithm::Sha384 | Algorithm::Sha512 => SHA512_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Running state of a SHA-2 computation.
+///
+/// SHA-224 and SHA-256 only use the lower 32 bits of each word of `h`.
+pub(crate) struct HashState {
+    algorithm: Algorithm,
+    h: [u64; 8],
+    block: [u8; SHA512_BLOCK_SIZE],
+    block_len: usize,
+    total_len: u128,
+}
+
+impl HashState {
+    pub(crate) fn new(algorithm: Algorithm) -> HashState {
+        let mut h = [0; 8];
+        match algorithm {
+            Algorithm::Sha224 => {
+                for (h, init) in h.iter_mut().zip(SHA224_INIT.iter()) {
+                    *h = *init as u64;
+                }
+            }
+            Algorithm::Sha256 => {
+                for (h, init) in h.iter_mut().zip(SHA256_INIT.iter()) {
+                    *h = *init as u64;
+                }
+            }
+            Algorithm::Sha384 => h = SHA384_INIT,
+            Algorithm::Sha512 => h = SHA512_INIT,
+        }
+        HashState {
+            algorithm,
+            h,
+            block: [0; SHA512_BLOCK_SIZE],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        let block_size = self.algorithm.block_size();
+        self.total_len += data.len() as u128;
+
+        while !data.is_empty() {
+            let count = core::cmp::min(block_size - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + count].copy_from_slice(&data[..count]);
+            self.block_len += count;
+            data = &data[count..];
+
+            if self.block_len == block_size {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pad the message, process the final block(s) and write the digest to
+    /// `out`, which must be at least `output_len()` bytes long.
+    pub(crate) fn finish(&mut self, out: &mut [u8]) {
+        let block_size = self.algorithm.block_size();
+        // The message length is appended as a 64 bit value for SHA-224/256
+        // and as a 128 bit value for SHA-384/512.
+        let length_size = block_size / 8;
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > block_size - length_size {
+            for b in self.block[self.block_len..block_size].iter_mut() {
+                *b = 0;
+            }
+            self.compress();
+            self.block_len = 0;
+        }
+        for b in self.block[self.block_len..block_size - length_size].iter_mut() {
+            *b = 0;
+        }
+        let len_bytes = bit_len.to_be_bytes();
+        self.block[block_size - length_size..block_size]
+            .copy_from_slice(&len_bytes[len_bytes.len() - length_size..]);
+        self.compress();
+
+        let output_len = self.algorithm.output_len();
+        if block_size == SHA256_BLOCK_SIZE {
+            for (i, h) in self.h.iter().enumerate() {
+                let bytes = (*h as u32).to_be_bytes();
+                let start = i * 4;
+                if start < output_len {
+                    out[start..start + 4].copy_from_slice(&bytes);
+                }
+            }
+        } else {
+            for (i, h) in self.h.iter().enumerate() {
+                let bytes = h.to_be_bytes();
+                let start = i * 8;
+                if start < output_len {
+                    out[start..start + 8].copy_from_slice(&bytes);
+                }
+            }
+        }
+    }
+
+    fn compress(&mut self) {
+        if self.algorithm.block_size() == SHA256_BLOCK_SIZE {
+            self.compress256();
+        } else {
+            self.compress512();
+        }
+    }
+
+    fn compress256(&mut self) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                self.block[i * 4],
+                self.block[i * 4 + 1],
+                self.block[i * 4 + 2],
+                self.block[i * 4 + 3],
+            ]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = [0u32; 8];
+        for (v, h) in v.iter_mut().zip(self.h.iter()) {
+            *v = *h as u32;
+        }
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            v[7] = v[6];
+            v[6] = v[5];
+            v[5] = v[4];
+            v[4] = v[3].wrapping_add(t1);
+            v[3] = v[2];
+            v[2] = v[1];
+            v[1] = v[0];
+            v[0] = t1.wrapping_add(t2);
+        }
+
+        for (h, v) in self.h.iter_mut().zip(v.iter()) {
+            *h = (*h as u32).wrapping_add(*v) as u64;
+        }
+    }
+
+    fn compress512(&mut self) {
+        let mut w = [0u64; 80];
+        for i in 0..16 {
+            let mut word = [0; 8];
+            word.copy_from_slice(&self.block[i * 8..i * 8 + 8]);
+            w[i] = u64::from_be_bytes(word);
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = self.h;
+        for i in 0..80 {
+            let s1 = v[4].rotate_right(14) ^ v[4].rotate_right(18) ^ v[4].rotate_right(41);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA512_K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(28) ^ v[0].rotate_right(34) ^ v[0].rotate_right(39);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            v[7] = v[6];
+            v[6] = v[5];
+            v[5] = v[4];
+            v[4] = v[3].wrapping_add(t1);
+            v[3] = v[2];
+            v[2] = v[1];
+            v[1] = v[0];
+            v[0] = t1.wrapping_add(t2);
+        }
+
+        for (h, v) in self.h.iter_mut().zip(v.iter()) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    /// Overwrite the state, so no message data is left in memory.
+    fn wipe(&mut self) {
+        self.h = [0; 8];
+        self.block = [0; SHA512_BLOCK_SIZE];
+        self.block_len = 0;
+        self.total_len = 0;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    AddData,
+    Run,
+    Verify,
+}
+
+pub struct ShaSoftware<'a, const L: usize> {
+    client: OptionalCell<&'a dyn digest::Client<'a, L>>,
+    data_client: OptionalCell<&'a dyn digest::ClientData<'a, L>>,
+    hash_client: OptionalCell<&'a dyn digest::ClientHash<'a, L>>,
+    verify_client: OptionalCell<&'a dyn digest::ClientVerify<'a, L>>,
+
+    state: Cell<State>,
+    algorithm: Cell<Option<Algorithm>>,
+    hash: MapCell<HashState>,
+
+    /// Block sized key for HMAC, only valid if `hmac` is set.
+    hmac_key: MapCell<[u8; SHA512_BLOCK_SIZE]>,
+    hmac: Cell<bool>,
+
+    data: Cell<Option<LeasableBuffer<'static, u8>>>,
+    data_index: Cell<usize>,
+    digest: TakeCell<'static, [u8; L]>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, const L: usize> ShaSoftware<'a, L> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> ShaSoftware<'a, L> {
+        ShaSoftware {
+            client: OptionalCell::empty(),
+            data_client: OptionalCell::empty(),
+            hash_client: OptionalCell::empty(),
+            verify_client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            algorithm: Cell::new(None),
+            hash: MapCell::new(HashState::new(Algorithm::Sha256)),
+            hmac_key: MapCell::new([0; SHA512_BLOCK_SIZE]),
+            hmac: Cell::new(false),
+            data: Cell::new(None),
+            data_index: Cell::new(0),
+            digest: TakeCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Initializes the handle used for deferred calls.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn schedule(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    fn set_mode(&self, algorithm: Algorithm, key: Option<&[u8]>) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if algorithm.output_len() > L {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        self.algorithm.set(Some(algorithm));
+        self.hmac.set(key.is_some());
+        if let Some(key) = key {
+            let block_size = algorithm.block_size();
+            self.hmac_key.map(|hmac_key| {
+                *hmac_key = [0; SHA512_BLOCK_SIZE];
+                if key.len() > block_size {
+                    // Keys longer than a block are hashed first.
+                    let mut key_hash = HashState::new(algorithm);
+                    key_hash.update(key);
+                    key_hash.finish(hmac_key);
+                    key_hash.wipe();
+                } else {
+                    hmac_key[..key.len()].copy_from_slice(key);
+                }
+            });
+        }
+        self.restart();
+        Ok(())
+    }
+
+    /// Reset the hash state to the start of a new message in the current
+    /// mode.
+    fn restart(&self) {
+        let algorithm = self.algorithm.get().unwrap_or(Algorithm::Sha256);
+        self.hash.map(|hash| {
+            *hash = HashState::new(algorithm);
+            if self.hmac.get() {
+                self.hmac_key.map(|key| {
+                    let mut pad = [0x36; SHA512_BLOCK_SIZE];
+                    for (p, k) in pad.iter_mut().zip(key.iter()) {
+                        *p ^= *k;
+                    }
+                    hash.update(&pad[..algorithm.block_size()]);
+                });
+            }
+        });
+    }
+
+    /// Finish the current message and write the digest (or HMAC) to `out`.
+    fn finish(&self, out: &mut [u8; SHA512_BLOCK_SIZE / 2]) {
+        let algorithm = self.algorithm.get().unwrap_or(Algorithm::Sha256);
+        self.hash.map(|hash| {
+            hash.finish(out);
+            if self.hmac.get() {
+                *hash = HashState::new(algorithm);
+                self.hmac_key.map(|key| {
+                    let mut pad = [0x5c; SHA512_BLOCK_SIZE];
+                    for (p, k) in pad.iter_mut().zip(key.iter()) {
+                        *p ^= *k;
+                    }
+                    hash.update(&pad[..algorithm.block_size()]);
+                });
+                hash.update(&out[..algorithm.output_len()]);
+                hash.finish(out);
+            }
+        });
+        self.restart();
+    }
+
+    fn ensure_mode(&self) -> Result<(), ErrorCode> {
+        if self.algorithm.get().is_none() {
+            // Default to SHA-256 if no mode was selected.
+            self.set_mode(Algorithm::Sha256, None)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn process_data(&self) {
+        let buffer = match self.data.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let index = self.data_index.get();
+        let end = core::cmp::min(index + BYTES_PER_CALL, buffer.len());
+        self.hash.map(|hash| hash.update(&buffer[index..end]));
+
+        if end < buffer.len() {
+            self.data_index.set(end);
+            self.data.set(Some(buffer));
+            self.schedule();
+        } else {
+            self.state.set(State::Idle);
+            let data = buffer.take();
+            if self.data_client.is_some() {
+                self.data_client
+                    .map(move |client| client.add_data_done(Ok(()), data));
+            } else {
+                self.client
+                    .map(move |client| client.add_data_done(Ok(()), data));
+            }
+        }
+    }
+
+    fn compute(&self) {
+        let algorithm = self.algorithm.get().unwrap_or(Algorithm::Sha256);
+        let output_len = algorithm.output_len();
+        let mut out = [0; SHA512_BLOCK_SIZE / 2];
+        self.finish(&mut out);
+
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        self.digest.take().map(|digest| match state {
+            State::Verify => {
+                // Compare in constant time.
+                let diff = digest[..output_len]
+                    .iter()
+                    .zip(out[..output_len].iter())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b));
+                if self.verify_client.is_some() {
+                    self.verify_client
+                        .map(move |client| client.verification_done(Ok(diff == 0), digest));
+                } else {
+                    self.client
+                        .map(move |client| client.verification_done(Ok(diff == 0), digest));
+                }
+            }
+            _ => {
+                digest[..output_len].copy_from_slice(&out[..output_len]);
+                for b in digest[output_len..].iter_mut() {
+                    *b = 0;
+                }
+                if self.hash_client.is_some() {
+                    self.hash_client
+                        .map(move |client| client.hash_done(Ok(()), digest));
+                } else {
+                    self.client
+                        .map(move |client| client.hash_done(Ok(()), digest));
+                }
+            }
+        });
+    }
+}
+
+impl<'a, const L: usize> digest::DigestData<'a, L> for ShaSoftware<'a, L> {
+    fn set_data_client(&'a self, client: &'a dyn digest::ClientData<'a, L>) {
+        self.data_client.set(client);
+    }
+
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<usize, (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, data.take()));
+        }
+        if let Err(e) = self.ensure_mode() {
+            return Err((e, data.take()));
+        }
+
+        let len = data.len();
+        self.state.set(State::AddData);
+        self.data_index.set(0);
+        self.data.set(Some(data));
+        self.schedule();
+        Ok(len)
+    }
+
+    fn clear_data(&self) {
+        self.hash.map(|hash| hash.wipe());
+        self.hmac_key.map(|key| *key = [0; SHA512_BLOCK_SIZE]);
+        self.hmac.set(false);
+        self.algorithm.set(None);
+    }
+}
+
+impl<'a, const L: usize> digest::DigestHash<'a, L> for ShaSoftware<'a, L> {
+    fn set_hash_client(&'a self, client: &'a dyn digest::ClientHash<'a, L>) {
+        self.hash_client.set(client);
+    }
+
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, digest));
+        }
+        if let Err(e) = self.ensure_mode() {
+            return Err((e, digest));
+        }
+
+        self.state.set(State::Run);
+        self.digest.replace(digest);
+        self.schedule();
+        Ok(())
+    }
+}
+
+impl<'a, const L: usize> digest::DigestVerify<'a, L> for ShaSoftware<'a, L> {
+    fn set_verify_client(&'a self, client: &'a dyn digest::ClientVerify<'a, L>) {
+        self.verify_client.set(client);
+    }
+
+    fn verify(
+        &'a self,
+        compare: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, compare));
+        }
+        if let Err(e) = self.ensure_mode() {
+            return Err((e, compare));
+        }
+
+        self.state.set(State::Verify);
+        self.digest.replace(compare);
+        self.schedule();
+        Ok(())
+    }
+}
+
+impl<'a, const L: usize> digest::Digest<'a, L> for ShaSoftware<'a, L> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<'a, L>) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, const L: usize> DynamicDeferredCallClient for ShaSoftware<'a, L> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        match self.state.get() {
+            State::Idle => {}
+            State::AddData => self.process_data(),
+            State::Run | State::Verify => self.compute(),
+        }
+    }
+}
+
+impl<const L: usize> digest::Sha224 for ShaSoftware<'_, L> {
+    fn set_mode_sha224(&self) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha224, None)
+    }
+}
+
+impl<const L: usize> digest::Sha256 for ShaSoftware<'_, L> {
+    fn set_mode_sha256(&self) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha256, None)
+    }
+}
+
+impl<const L: usize> digest::Sha384 for ShaSoftware<'_, L> {
+    fn set_mode_sha384(&self) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha384, None)
+    }
+}
+
+impl<const L: usize> digest::Sha512 for ShaSoftware<'_, L> {
+    fn set_mode_sha512(&self) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha512, None)
+    }
+}
+
+impl<const L: usize> digest::HMACSha256 for ShaSoftware<'_, L> {
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha256, Some(key))
+    }
+}
+
+impl<const L: usize> digest::HMACSha384 for ShaSoftware<'_, L> {
+    fn set_mode_hmacsha384(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha384, Some(key))
+    }
+}
+
+impl<const L: usize> digest::HMACSha512 for ShaSoftware<'_, L> {
+    fn set_mode_hmacsha512(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        self.set_mode(Algorithm::Sha512, Some(key))
+    }
+}
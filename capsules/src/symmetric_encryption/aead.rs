@@ -0,0 +1,394 @@
+//! Authenticated encryption with associated data (AEAD).
+//!
+//! Provides userspace access to AES-128-GCM and ChaCha20-Poly1305 through
+//! any implementations of `hil::symmetric_encryption::AES128GCM` and
+//! `hil::symmetric_encryption::ChaCha20Poly1305`, for example
+//! `capsules::aes_gcm::Aes128Gcm` and
+//! `capsules::chacha20_poly1305::ChaCha20Poly1305Software`, possibly shared
+//! through `capsules::virtual_aead`.
+//!
+//! The key, nonce and associated data are passed in read-only allow buffers.
+//! The message is passed in the read-write data buffer and is replaced with
+//! the result: when encrypting, the ciphertext followed by the 16 byte tag;
+//! when decrypting, the plaintext, which is only written back if the tag is
+//! valid.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let aead = static_init!(
+//!     capsules::symmetric_encryption::aead::AeadDriver<'static, GCM, CHACHA>,
+//!     capsules::symmetric_encryption::aead::AeadDriver::new(
+//!         gcm_client,
+//!         chacha_client,
+//!         &mut capsules::symmetric_encryption::aead::BUF,
+//!         board_kernel.create_grant(
+//!             capsules::symmetric_encryption::aead::DRIVER_NUM,
+//!             &grant_cap
+//!         ),
+//!     )
+//! );
+//! gcm_client.set_client(aead);
+//! chacha_client.set_client(aead);
+//! ```
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Aead as usize;
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::symmetric_encryption::{
+    AEADClient, ChaCha20Poly1305, AEAD, AEAD_TAG_LENGTH, AES128GCM,
+};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const KEY: usize = 0;
+    pub const NONCE: usize = 1;
+    pub const AAD: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const CRYPT_DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// The longest key accepted from an app.
+const MAX_KEY_LENGTH: usize = 32;
+/// The longest nonce accepted from an app.
+const MAX_NONCE_LENGTH: usize = 16;
+
+/// Default buffer for operations. Must be large enough for the associated
+/// data, the message and the tag of the largest operation.
+pub static mut BUF: [u8; 512] = [0; 512];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Algorithm {
+    Aes128Gcm,
+    ChaCha20Poly1305,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Operation {
+    algorithm: Algorithm,
+    encrypting: bool,
+    /// Length of the message, without the tag.
+    length: usize,
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: Option<Operation>,
+}
+
+pub struct AeadDriver<'a, G: AES128GCM<'a>, C: ChaCha20Poly1305<'a>> {
+    gcm: &'a G,
+    chacha: &'a C,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    current_app: OptionalCell<ProcessId>,
+    /// The current operation and the length of its associated data.
+    current: Cell<Option<(Operation, usize)>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, G: AES128GCM<'a>, C: ChaCha20Poly1305<'a>> AeadDriver<'a, G, C> {
+    pub fn new(
+        gcm: &'a G,
+        chacha: &'a C,
+        buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> AeadDriver<'a, G, C> {
+        AeadDriver {
+            gcm,
+            chacha,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            current: Cell::new(None),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    fn aead(&self, algorithm: Algorithm) -> &'a dyn AEAD<'a> {
+        match algorithm {
+            Algorithm::Aes128Gcm => self.gcm,
+            Algorithm::ChaCha20Poly1305 => self.chacha,
+        }
+    }
+
+    /// Either start the operation right away or queue it for the app if an
+    /// operation is in progress.
+    fn enqueue(&self, operation: Operation, appid: ProcessId) -> Result<(), ErrorCode> {
+        if self.current_app.is_none() {
+            self.current_app.set(appid);
+            let res = self.start(operation, appid);
+            if res.is_err() {
+                self.current_app.clear();
+            }
+            res
+        } else {
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending.is_some() {
+                        Err(ErrorCode::NOMEM)
+                    } else {
+                        app.pending = Some(operation);
+                        Ok(())
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        }
+    }
+
+    /// Load the key and nonce the app allowed, copy its associated data and
+    /// message into the kernel buffer and start the operation.
+    fn start(&self, operation: Operation, appid: ProcessId) -> Result<(), ErrorCode> {
+        let aead = self.aead(operation.algorithm);
+        let buffer = self.buffer.take().ok_or(ErrorCode::RESERVE)?;
+
+        let copied = self
+            .apps
+            .enter(appid, |_, kernel_data| {
+                let mut key = [0; MAX_KEY_LENGTH];
+                let key_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::KEY)
+                    .and_then(|key_buf| {
+                        key_buf.enter(|data| {
+                            if data.len() > MAX_KEY_LENGTH {
+                                Err(ErrorCode::INVAL)
+                            } else {
+                                data.copy_to_slice(&mut key[..data.len()]);
+                                Ok(data.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                aead.set_key(&key[..key_len])?;
+
+                let mut nonce = [0; MAX_NONCE_LENGTH];
+                let nonce_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::NONCE)
+                    .and_then(|nonce_buf| {
+                        nonce_buf.enter(|data| {
+                            if data.len() > MAX_NONCE_LENGTH {
+                                Err(ErrorCode::INVAL)
+                            } else {
+                                data.copy_to_slice(&mut nonce[..data.len()]);
+                                Ok(data.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                aead.set_nonce(&nonce[..nonce_len])?;
+
+                let aad_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::AAD)
+                    .and_then(|aad| {
+                        aad.enter(|data| {
+                            if data.len() > buffer.len() {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data.copy_to_slice(&mut buffer[..data.len()]);
+                                Ok(data.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+                // The tag follows the text in the data buffer: it is written
+                // there when encrypting, and read from there when decrypting.
+                let data_len = operation
+                    .length
+                    .checked_add(AEAD_TAG_LENGTH)
+                    .ok_or(ErrorCode::SIZE)?;
+                let total = aad_len.checked_add(data_len).ok_or(ErrorCode::SIZE)?;
+                let input_len = if operation.encrypting {
+                    operation.length
+                } else {
+                    data_len
+                };
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::DATA)
+                    .and_then(|data_buf| {
+                        data_buf.enter(|data| {
+                            if total > buffer.len() || data_len > data.len() {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data[..input_len]
+                                    .copy_to_slice(&mut buffer[aad_len..aad_len + input_len]);
+                                Ok(aad_len)
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        let aad_len = match copied {
+            Ok(aad_len) => aad_len,
+            Err(e) => {
+                self.buffer.replace(buffer);
+                return Err(e);
+            }
+        };
+
+        self.current.set(Some((operation, aad_len)));
+        aead.crypt(buffer, 0, aad_len, operation.length, operation.encrypting)
+            .map_err(|(ecode, buffer)| {
+                self.current.set(None);
+                self.buffer.replace(buffer);
+                ecode
+            })
+    }
+
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            let appid = cntr.processid();
+            let operation = cntr.enter(|app, _| app.pending.take());
+            if let Some(operation) = operation {
+                self.current_app.set(appid);
+                if let Err(e) = self.start(operation, appid) {
+                    // Report the failure and move on to the next app.
+                    self.current_app.clear();
+                    let _ = self.apps.enter(appid, |_, kernel_data| {
+                        kernel_data
+                            .schedule_upcall(upcall::CRYPT_DONE, (into_statuscode(Err(e)), 0, 0))
+                            .ok();
+                    });
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, G: AES128GCM<'a>, C: ChaCha20Poly1305<'a>> AEADClient for AeadDriver<'a, G, C> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        if let (Some(appid), Some((operation, aad_len))) =
+            (self.current_app.take(), self.current.take())
+        {
+            let _ = self.apps.enter(appid, |_, kernel_data| {
+                if res.is_ok() && tag_is_valid {
+                    // Return the ciphertext and tag, or the plaintext.
+                    let output_len = if operation.encrypting {
+                        operation.length + AEAD_TAG_LENGTH
+                    } else {
+                        operation.length
+                    };
+                    let _ = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::DATA)
+                        .and_then(|data_buf| {
+                            data_buf.mut_enter(|data| {
+                                if output_len <= data.len() {
+                                    data[..output_len]
+                                        .copy_from_slice(&buf[aad_len..aad_len + output_len]);
+                                }
+                            })
+                        });
+                }
+                kernel_data
+                    .schedule_upcall(
+                        upcall::CRYPT_DONE,
+                        (
+                            into_statuscode(res),
+                            tag_is_valid as usize,
+                            operation.length,
+                        ),
+                    )
+                    .ok();
+            });
+        }
+
+        // Don't leave plaintext around in the kernel buffer.
+        buf.iter_mut().for_each(|b| *b = 0);
+        self.buffer.replace(buf);
+        self.check_queue();
+    }
+}
+
+/// Provide an interface for userland.
+impl<'a, G: AES128GCM<'a>, C: ChaCha20Poly1305<'a>> SyscallDriver for AeadDriver<'a, G, C> {
+    /// Command interface.
+    ///
+    /// The algorithm is selected by `data1`: `0` for AES-128-GCM and `1` for
+    /// ChaCha20-Poly1305.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Encrypt the first `data2` bytes of the data buffer. The
+    ///        ciphertext followed by the tag is written back to the data
+    ///        buffer, which must have room for the tag.
+    /// - `2`: Decrypt the first `data2` bytes of the data buffer, which must
+    ///        be followed by the tag. The plaintext is written back to the
+    ///        data buffer only if the tag is valid.
+    ///
+    /// Upcall 0 is called with the status, `1` if the tag is valid and the
+    /// length of the message when an operation is done.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let algorithm = match data1 {
+            0 => Some(Algorithm::Aes128Gcm),
+            1 => Some(Algorithm::ChaCha20Poly1305),
+            _ => None,
+        };
+
+        let res = match (command_num, algorithm) {
+            (0, _) => Ok(()),
+            (1, Some(algorithm)) | (2, Some(algorithm)) => self.enqueue(
+                Operation {
+                    algorithm,
+                    encrypting: command_num == 1,
+                    length: data2,
+                },
+                appid,
+            ),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
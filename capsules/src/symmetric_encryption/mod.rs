@@ -1 +1,2 @@
+pub mod aead;
 pub mod aes;
@@ -0,0 +1,257 @@
+//! Test an AEAD implementation, such as AES-GCM or ChaCha20-Poly1305.
+//!
+//! Each test vector is first encrypted and the ciphertext and tag are
+//! compared against the expected values, then the ciphertext is decrypted
+//! again. Finally, a copy of the ciphertext with a flipped bit is decrypted to
+//! check that the tag is rejected.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let buf = static_init!([u8; 256], [0; 256]);
+//! let test = static_init!(
+//!     capsules::test::aead::TestAead<'static, GCM>,
+//!     capsules::test::aead::TestAead::new(gcm, buf, &capsules::test::aead::GCM_TESTS)
+//! );
+//! gcm.set_client(test);
+//! test.run();
+//! ```
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::symmetric_encryption::{AEADClient, AEAD, AEAD_TAG_LENGTH};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// `(key, nonce, a_data, m_data, c_data)`, where `c_data` holds the
+/// ciphertext followed by the tag.
+pub type TestVector = (
+    &'static [u8],
+    &'static [u8],
+    &'static [u8],
+    &'static [u8],
+    &'static [u8],
+);
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Step {
+    Encrypt,
+    Decrypt,
+    DecryptTampered,
+}
+
+pub struct TestAead<'a, A: AEAD<'a>> {
+    aead: &'a A,
+    buf: TakeCell<'static, [u8]>,
+    tests: &'static [TestVector],
+    current_test: Cell<usize>,
+    step: Cell<Step>,
+}
+
+impl<'a, A: AEAD<'a>> TestAead<'a, A> {
+    pub fn new(aead: &'a A, buf: &'static mut [u8], tests: &'static [TestVector]) -> Self {
+        TestAead {
+            aead,
+            buf: TakeCell::new(buf),
+            tests,
+            current_test: Cell::new(0),
+            step: Cell::new(Step::Encrypt),
+        }
+    }
+
+    pub fn run(&self) {
+        debug!("AEAD encryption/decryption tests");
+        self.trigger_test();
+    }
+
+    fn next_test(&self) -> bool {
+        match self.step.get() {
+            Step::Encrypt => self.step.set(Step::Decrypt),
+            Step::Decrypt => self.step.set(Step::DecryptTampered),
+            Step::DecryptTampered => {
+                self.step.set(Step::Encrypt);
+                self.current_test.set(self.current_test.get() + 1);
+                if self.current_test.get() >= self.tests.len() {
+                    debug!("aead_test: all tests passed");
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn trigger_test(&self) {
+        let (key, nonce, a_data, m_data, c_data) = self.tests[self.current_test.get()];
+        let (a_off, m_off, m_len) = (0, a_data.len(), m_data.len());
+
+        let buf = match self.buf.take() {
+            None => panic!("aead_test failed: buffer is not present in trigger_test."),
+            Some(buf) => buf,
+        };
+        if buf.len() < m_off + m_len + AEAD_TAG_LENGTH {
+            panic!("aead_test failed: buffer is too small.");
+        }
+
+        buf[a_off..m_off].copy_from_slice(a_data);
+        match self.step.get() {
+            Step::Encrypt => buf[m_off..m_off + m_len].copy_from_slice(m_data),
+            Step::Decrypt => buf[m_off..m_off + m_len + AEAD_TAG_LENGTH].copy_from_slice(c_data),
+            Step::DecryptTampered => {
+                buf[m_off..m_off + m_len + AEAD_TAG_LENGTH].copy_from_slice(c_data);
+                buf[m_off] ^= 1;
+            }
+        }
+
+        if self.aead.set_key(key) != Ok(()) || self.aead.set_nonce(nonce) != Ok(()) {
+            panic!("aead_test failed: cannot set key or nonce.");
+        }
+
+        let encrypting = self.step.get() == Step::Encrypt;
+        let _ = self
+            .aead
+            .crypt(buf, a_off, m_off, m_len, encrypting)
+            .map_err(|(code, buf)| {
+                debug!("aead_test: failed to start test: {:?}", code);
+                self.buf.replace(buf);
+            });
+    }
+
+    fn check_test(&self, tag_is_valid: bool) {
+        let (_key, _nonce, a_data, m_data, c_data) = self.tests[self.current_test.get()];
+        let (a_off, m_off, m_len) = (0, a_data.len(), m_data.len());
+
+        let passed = self.buf.map_or(false, |buf| {
+            let a_matches = &buf[a_off..m_off] == a_data;
+            match self.step.get() {
+                Step::Encrypt => {
+                    a_matches
+                        && tag_is_valid
+                        && &buf[m_off..m_off + m_len + AEAD_TAG_LENGTH] == c_data
+                }
+                Step::Decrypt => a_matches && tag_is_valid && &buf[m_off..m_off + m_len] == m_data,
+                Step::DecryptTampered => a_matches && !tag_is_valid,
+            }
+        });
+
+        if passed {
+            debug!(
+                "aead_test passed: (current_test={}, step={:?}, tag_is_valid={})",
+                self.current_test.get(),
+                self.step.get(),
+                tag_is_valid
+            );
+        } else {
+            panic!(
+                "aead_test failed: (current_test={}, step={:?}, tag_is_valid={})",
+                self.current_test.get(),
+                self.step.get(),
+                tag_is_valid
+            );
+        }
+    }
+}
+
+impl<'a, A: AEAD<'a>> AEADClient for TestAead<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        self.buf.replace(buf);
+        if res != Ok(()) {
+            panic!("aead_test failed: crypt_done returned {:?}", res);
+        } else {
+            self.check_test(tag_is_valid);
+            if self.next_test() {
+                self.trigger_test()
+            }
+        }
+    }
+}
+
+pub static GCM_TESTS: [TestVector; 1] = [(
+    &GCM_KEY,
+    &GCM_NONCE,
+    &GCM_AAD,
+    &GCM_PLAINTEXT,
+    &GCM_CIPHERTEXT,
+)];
+
+pub static CHACHA20_POLY1305_TESTS: [TestVector; 1] = [(
+    &CHACHA20_POLY1305_KEY,
+    &CHACHA20_POLY1305_NONCE,
+    &CHACHA20_POLY1305_AAD,
+    &CHACHA20_POLY1305_PLAINTEXT,
+    &CHACHA20_POLY1305_CIPHERTEXT,
+)];
+
+// The Galois/Counter Mode of Operation (GCM), Test Case 4
+static GCM_KEY: [u8; 16] = [
+    0xFE, 0xFF, 0xE9, 0x92, 0x86, 0x65, 0x73, 0x1C, 0x6D, 0x6A, 0x8F, 0x94, 0x67, 0x30, 0x83, 0x08,
+];
+
+// The Galois/Counter Mode of Operation (GCM), Test Case 4
+static GCM_NONCE: [u8; 12] = [
+    0xCA, 0xFE, 0xBA, 0xBE, 0xFA, 0xCE, 0xDB, 0xAD, 0xDE, 0xCA, 0xF8, 0x88,
+];
+
+// The Galois/Counter Mode of Operation (GCM), Test Case 4
+static GCM_AAD: [u8; 20] = [
+    0xFE, 0xED, 0xFA, 0xCE, 0xDE, 0xAD, 0xBE, 0xEF, 0xFE, 0xED, 0xFA, 0xCE, 0xDE, 0xAD, 0xBE, 0xEF,
+    0xAB, 0xAD, 0xDA, 0xD2,
+];
+
+// The Galois/Counter Mode of Operation (GCM), Test Case 4
+static GCM_PLAINTEXT: [u8; 60] = [
+    0xD9, 0x31, 0x32, 0x25, 0xF8, 0x84, 0x06, 0xE5, 0xA5, 0x59, 0x09, 0xC5, 0xAF, 0xF5, 0x26, 0x9A,
+    0x86, 0xA7, 0xA9, 0x53, 0x15, 0x34, 0xF7, 0xDA, 0x2E, 0x4C, 0x30, 0x3D, 0x8A, 0x31, 0x8A, 0x72,
+    0x1C, 0x3C, 0x0C, 0x95, 0x95, 0x68, 0x09, 0x53, 0x2F, 0xCF, 0x0E, 0x24, 0x49, 0xA6, 0xB5, 0x25,
+    0xB1, 0x6A, 0xED, 0xF5, 0xAA, 0x0D, 0xE6, 0x57, 0xBA, 0x63, 0x7B, 0x39,
+];
+
+// The Galois/Counter Mode of Operation (GCM), Test Case 4, ciphertext followed by the tag
+static GCM_CIPHERTEXT: [u8; 76] = [
+    0x42, 0x83, 0x1E, 0xC2, 0x21, 0x77, 0x74, 0x24, 0x4B, 0x72, 0x21, 0xB7, 0x84, 0xD0, 0xD4, 0x9C,
+    0xE3, 0xAA, 0x21, 0x2F, 0x2C, 0x02, 0xA4, 0xE0, 0x35, 0xC1, 0x7E, 0x23, 0x29, 0xAC, 0xA1, 0x2E,
+    0x21, 0xD5, 0x14, 0xB2, 0x54, 0x66, 0x93, 0x1C, 0x7D, 0x8F, 0x6A, 0x5A, 0xAC, 0x84, 0xAA, 0x05,
+    0x1B, 0xA3, 0x0B, 0x39, 0x6A, 0x0A, 0xAC, 0x97, 0x3D, 0x58, 0xE0, 0x91, 0x5B, 0xC9, 0x4F, 0xBC,
+    0x32, 0x21, 0xA5, 0xDB, 0x94, 0xFA, 0xE9, 0x5A, 0xE7, 0x12, 0x1A, 0x47,
+];
+
+// RFC 8439, Section 2.8.2
+static CHACHA20_POLY1305_KEY: [u8; 32] = [
+    0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x8B, 0x8C, 0x8D, 0x8E, 0x8F,
+    0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0x9B, 0x9C, 0x9D, 0x9E, 0x9F,
+];
+
+// RFC 8439, Section 2.8.2
+static CHACHA20_POLY1305_NONCE: [u8; 12] = [
+    0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+];
+
+// RFC 8439, Section 2.8.2
+static CHACHA20_POLY1305_AAD: [u8; 12] = [
+    0x50, 0x51, 0x52, 0x53, 0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7,
+];
+
+// RFC 8439, Section 2.8.2
+static CHACHA20_POLY1305_PLAINTEXT: [u8; 114] = [
+    0x4C, 0x61, 0x64, 0x69, 0x65, 0x73, 0x20, 0x61, 0x6E, 0x64, 0x20, 0x47, 0x65, 0x6E, 0x74, 0x6C,
+    0x65, 0x6D, 0x65, 0x6E, 0x20, 0x6F, 0x66, 0x20, 0x74, 0x68, 0x65, 0x20, 0x63, 0x6C, 0x61, 0x73,
+    0x73, 0x20, 0x6F, 0x66, 0x20, 0x27, 0x39, 0x39, 0x3A, 0x20, 0x49, 0x66, 0x20, 0x49, 0x20, 0x63,
+    0x6F, 0x75, 0x6C, 0x64, 0x20, 0x6F, 0x66, 0x66, 0x65, 0x72, 0x20, 0x79, 0x6F, 0x75, 0x20, 0x6F,
+    0x6E, 0x6C, 0x79, 0x20, 0x6F, 0x6E, 0x65, 0x20, 0x74, 0x69, 0x70, 0x20, 0x66, 0x6F, 0x72, 0x20,
+    0x74, 0x68, 0x65, 0x20, 0x66, 0x75, 0x74, 0x75, 0x72, 0x65, 0x2C, 0x20, 0x73, 0x75, 0x6E, 0x73,
+    0x63, 0x72, 0x65, 0x65, 0x6E, 0x20, 0x77, 0x6F, 0x75, 0x6C, 0x64, 0x20, 0x62, 0x65, 0x20, 0x69,
+    0x74, 0x2E,
+];
+
+// RFC 8439, Section 2.8.2, ciphertext followed by the tag
+static CHACHA20_POLY1305_CIPHERTEXT: [u8; 130] = [
+    0xD3, 0x1A, 0x8D, 0x34, 0x64, 0x8E, 0x60, 0xDB, 0x7B, 0x86, 0xAF, 0xBC, 0x53, 0xEF, 0x7E, 0xC2,
+    0xA4, 0xAD, 0xED, 0x51, 0x29, 0x6E, 0x08, 0xFE, 0xA9, 0xE2, 0xB5, 0xA7, 0x36, 0xEE, 0x62, 0xD6,
+    0x3D, 0xBE, 0xA4, 0x5E, 0x8C, 0xA9, 0x67, 0x12, 0x82, 0xFA, 0xFB, 0x69, 0xDA, 0x92, 0x72, 0x8B,
+    0x1A, 0x71, 0xDE, 0x0A, 0x9E, 0x06, 0x0B, 0x29, 0x05, 0xD6, 0xA5, 0xB6, 0x7E, 0xCD, 0x3B, 0x36,
+    0x92, 0xDD, 0xBD, 0x7F, 0x2D, 0x77, 0x8B, 0x8C, 0x98, 0x03, 0xAE, 0xE3, 0x28, 0x09, 0x1B, 0x58,
+    0xFA, 0xB3, 0x24, 0xE4, 0xFA, 0xD6, 0x75, 0x94, 0x55, 0x85, 0x80, 0x8B, 0x48, 0x31, 0xD7, 0xBC,
+    0x3F, 0xF4, 0xDE, 0xF0, 0x8E, 0x4B, 0x7A, 0x9D, 0xE5, 0x76, 0xD2, 0x65, 0x86, 0xCE, 0xC6, 0x4B,
+    0x61, 0x16, 0x1A, 0xE1, 0x0B, 0x59, 0x4F, 0x09, 0xE2, 0x6A, 0x7E, 0x90, 0x2E, 0xCB, 0xD0, 0x60,
+    0x06, 0x91,
+];
@@ -0,0 +1,381 @@
+//! Test a digest engine against the NIST SHA-2 examples and the RFC 4231
+//! HMAC test cases.
+//!
+//! The test runs every test vector in turn: it selects the mode, adds the
+//! message with `add_data()`, runs the digest and compares the result with
+//! the expected value. It then checks that `verify()` accepts the expected
+//! value.
+//!
+//! Any implementation of all of the SHA-2 and HMAC modes of `hil::digest`
+//! can be tested, for example `capsules::sha_software::ShaSoftware`:
+//!
+//! ```rust
+//! let data = static_init!([u8; 128], [0; 128]);
+//! let digest = static_init!([u8; 64], [0; 64]);
+//! let test = static_init!(
+//!     capsules::test::digest::TestDigest<'static, ShaSoftware<'static, 64>, 64>,
+//!     capsules::test::digest::TestDigest::new(sha, data, digest)
+//! );
+//! sha.set_client(test);
+//! test.run();
+//! ```
+//!
+//! You should then see the following output
+//!
+//! ```text
+//! Digest test SHA-224 "abc": passed
+//! ...
+//! Digest test HMAC-SHA512 RFC 4231 case 2: passed
+//! Digest tests finished: 9 passed, 0 failed
+//! ```
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::digest::{
+    self, HMACSha256, HMACSha384, HMACSha512, Sha224, Sha256, Sha384, Sha512,
+};
+use kernel::utilities::cells::TakeCell;
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+struct TestVector {
+    name: &'static str,
+    mode: Mode,
+    key: &'static [u8],
+    message: &'static [u8],
+    expected: &'static [u8],
+}
+
+const MSG_ABC: &[u8] = b"abc";
+const MSG_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+const MSG_896: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+const RFC4231_KEY: &[u8] = b"Jefe";
+const RFC4231_DATA: &[u8] = b"what do ya want for nothing?";
+
+static TEST_VECTORS: [TestVector; 9] = [
+    TestVector {
+        name: "SHA-224 \"abc\"",
+        mode: Mode::Sha224,
+        key: &[],
+        message: MSG_ABC,
+        expected: &[
+            0x23, 0x09, 0x7d, 0x22, 0x34, 0x05, 0xd8, 0x22, 0x86, 0x42, 0xa4, 0x77, 0xbd, 0xa2,
+            0x55, 0xb3, 0x2a, 0xad, 0xbc, 0xe4, 0xbd, 0xa0, 0xb3, 0xf7, 0xe3, 0x6c, 0x9d, 0xa7,
+        ],
+    },
+    TestVector {
+        name: "SHA-256 \"abc\"",
+        mode: Mode::Sha256,
+        key: &[],
+        message: MSG_ABC,
+        expected: &[
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ],
+    },
+    TestVector {
+        name: "SHA-256 448 bit message",
+        mode: Mode::Sha256,
+        key: &[],
+        message: MSG_448,
+        expected: &[
+            0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e,
+            0x60, 0x39, 0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4,
+            0x19, 0xdb, 0x06, 0xc1,
+        ],
+    },
+    TestVector {
+        name: "SHA-384 \"abc\"",
+        mode: Mode::Sha384,
+        key: &[],
+        message: MSG_ABC,
+        expected: &[
+            0xcb, 0x00, 0x75, 0x3f, 0x45, 0xa3, 0x5e, 0x8b, 0xb5, 0xa0, 0x3d, 0x69, 0x9a, 0xc6,
+            0x50, 0x07, 0x27, 0x2c, 0x32, 0xab, 0x0e, 0xde, 0xd1, 0x63, 0x1a, 0x8b, 0x60, 0x5a,
+            0x43, 0xff, 0x5b, 0xed, 0x80, 0x86, 0x07, 0x2b, 0xa1, 0xe7, 0xcc, 0x23, 0x58, 0xba,
+            0xec, 0xa1, 0x34, 0xc8, 0x25, 0xa7,
+        ],
+    },
+    TestVector {
+        name: "SHA-512 \"abc\"",
+        mode: Mode::Sha512,
+        key: &[],
+        message: MSG_ABC,
+        expected: &[
+            0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20,
+            0x41, 0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6,
+            0x4b, 0x55, 0xd3, 0x9a, 0x21, 0x92, 0x99, 0x2a, 0x27, 0x4f, 0xc1, 0xa8, 0x36, 0xba,
+            0x3c, 0x23, 0xa3, 0xfe, 0xeb, 0xbd, 0x45, 0x4d, 0x44, 0x23, 0x64, 0x3c, 0xe8, 0x0e,
+            0x2a, 0x9a, 0xc9, 0x4f, 0xa5, 0x4c, 0xa4, 0x9f,
+        ],
+    },
+    TestVector {
+        name: "SHA-512 896 bit message",
+        mode: Mode::Sha512,
+        key: &[],
+        message: MSG_896,
+        expected: &[
+            0x8e, 0x95, 0x9b, 0x75, 0xda, 0xe3, 0x13, 0xda, 0x8c, 0xf4, 0xf7, 0x28, 0x14, 0xfc,
+            0x14, 0x3f, 0x8f, 0x77, 0x79, 0xc6, 0xeb, 0x9f, 0x7f, 0xa1, 0x72, 0x99, 0xae, 0xad,
+            0xb6, 0x88, 0x90, 0x18, 0x50, 0x1d, 0x28, 0x9e, 0x49, 0x00, 0xf7, 0xe4, 0x33, 0x1b,
+            0x99, 0xde, 0xc4, 0xb5, 0x43, 0x3a, 0xc7, 0xd3, 0x29, 0xee, 0xb6, 0xdd, 0x26, 0x54,
+            0x5e, 0x96, 0xe5, 0x5b, 0x87, 0x4b, 0xe9, 0x09,
+        ],
+    },
+    TestVector {
+        name: "HMAC-SHA256 RFC 4231 case 2",
+        mode: Mode::HmacSha256,
+        key: RFC4231_KEY,
+        message: RFC4231_DATA,
+        expected: &[
+            0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+            0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+            0x64, 0xec, 0x38, 0x43,
+        ],
+    },
+    TestVector {
+        name: "HMAC-SHA384 RFC 4231 case 2",
+        mode: Mode::HmacSha384,
+        key: RFC4231_KEY,
+        message: RFC4231_DATA,
+        expected: &[
+            0xaf, 0x45, 0xd2, 0xe3, 0x76, 0x48, 0x40, 0x31, 0x61, 0x7f, 0x78, 0xd2, 0xb5, 0x8a,
+            0x6b, 0x1b, 0x9c, 0x7e, 0xf4, 0x64, 0xf5, 0xa0, 0x1b, 0x47, 0xe4, 0x2e, 0xc3, 0x73,
+            0x63, 0x22, 0x44, 0x5e, 0x8e, 0x22, 0x40, 0xca, 0x5e, 0x69, 0xe2, 0xc7, 0x8b, 0x32,
+            0x39, 0xec, 0xfa, 0xb2, 0x16, 0x49,
+        ],
+    },
+    TestVector {
+        name: "HMAC-SHA512 RFC 4231 case 2",
+        mode: Mode::HmacSha512,
+        key: RFC4231_KEY,
+        message: RFC4231_DATA,
+        expected: &[
+            0x16, 0x4b, 0x7a, 0x7b, 0xfc, 0xf8, 0x19, 0xe2, 0xe3, 0x95, 0xfb, 0xe7, 0x3b, 0x56,
+            0xe0, 0xa3, 0x87, 0xbd, 0x64, 0x22, 0x2e, 0x83, 0x1f, 0xd6, 0x10, 0x27, 0x0c, 0xd7,
+            0xea, 0x25, 0x05, 0x54, 0x97, 0x58, 0xbf, 0x75, 0xc0, 0x5a, 0x99, 0x4a, 0x6d, 0x03,
+            0x4f, 0x65, 0xf8, 0xf0, 0xe6, 0xfd, 0xca, 0xea, 0xb1, 0xa3, 0x4d, 0x4a, 0x6b, 0x4b,
+            0x63, 0x6e, 0x07, 0x0a, 0x38, 0xbc, 0xe7, 0x37,
+        ],
+    },
+];
+
+pub struct TestDigest<'a, D: 'a, const L: usize> {
+    digest: &'a D,
+    data: TakeCell<'static, [u8]>,
+    output: TakeCell<'static, [u8; L]>,
+    index: Cell<usize>,
+    verifying: Cell<bool>,
+    passed: Cell<usize>,
+    failed: Cell<usize>,
+}
+
+impl<
+        'a,
+        D: digest::Digest<'a, L>
+            + Sha224
+            + Sha256
+            + Sha384
+            + Sha512
+            + HMACSha256
+            + HMACSha384
+            + HMACSha512,
+        const L: usize,
+    > TestDigest<'a, D, L>
+{
+    pub fn new(
+        digest: &'a D,
+        data: &'static mut [u8],
+        output: &'static mut [u8; L],
+    ) -> TestDigest<'a, D, L> {
+        TestDigest {
+            digest,
+            data: TakeCell::new(data),
+            output: TakeCell::new(output),
+            index: Cell::new(0),
+            verifying: Cell::new(false),
+            passed: Cell::new(0),
+            failed: Cell::new(0),
+        }
+    }
+
+    pub fn run(&self) {
+        self.index.set(0);
+        self.passed.set(0);
+        self.failed.set(0);
+        self.start();
+    }
+
+    fn start(&self) {
+        let vector = match TEST_VECTORS.get(self.index.get()) {
+            Some(vector) => vector,
+            None => {
+                debug!(
+                    "Digest tests finished: {} passed, {} failed",
+                    self.passed.get(),
+                    self.failed.get()
+                );
+                return;
+            }
+        };
+
+        let res = match vector.mode {
+            Mode::Sha224 => self.digest.set_mode_sha224(),
+            Mode::Sha256 => self.digest.set_mode_sha256(),
+            Mode::Sha384 => self.digest.set_mode_sha384(),
+            Mode::Sha512 => self.digest.set_mode_sha512(),
+            Mode::HmacSha256 => self.digest.set_mode_hmacsha256(vector.key),
+            Mode::HmacSha384 => self.digest.set_mode_hmacsha384(vector.key),
+            Mode::HmacSha512 => self.digest.set_mode_hmacsha512(vector.key),
+        };
+        if let Err(e) = res {
+            self.fail(vector, "set_mode", e);
+            return;
+        }
+
+        let data = self.data.take().unwrap();
+        if data.len() < vector.message.len() {
+            self.data.replace(data);
+            self.fail(vector, "data buffer too small", ErrorCode::SIZE);
+            return;
+        }
+        data[..vector.message.len()].copy_from_slice(vector.message);
+        let mut buffer = LeasableBuffer::new(data);
+        buffer.slice(0..vector.message.len());
+        if let Err((e, data)) = self.digest.add_data(buffer) {
+            self.data.replace(data);
+            self.fail(vector, "add_data", e);
+        }
+    }
+
+    fn fail(&self, vector: &TestVector, step: &str, error: ErrorCode) {
+        debug!("Digest test {}: FAILED at {} ({:?})", vector.name, step, error);
+        self.failed.set(self.failed.get() + 1);
+        self.next();
+    }
+
+    fn next(&self) {
+        self.digest.clear_data();
+        self.verifying.set(false);
+        self.index.set(self.index.get() + 1);
+        self.start();
+    }
+}
+
+impl<
+        'a,
+        D: digest::Digest<'a, L>
+            + Sha224
+            + Sha256
+            + Sha384
+            + Sha512
+            + HMACSha256
+            + HMACSha384
+            + HMACSha512,
+        const L: usize,
+    > digest::ClientData<'a, L> for TestDigest<'a, D, L>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data.replace(data);
+        let vector = &TEST_VECTORS[self.index.get()];
+        if let Err(e) = result {
+            self.fail(vector, "add_data_done", e);
+            return;
+        }
+
+        let output = self.output.take().unwrap();
+        if self.verifying.get() {
+            // The output buffer still holds the expected digest.
+            if let Err((e, output)) = self.digest.verify(output) {
+                self.output.replace(output);
+                self.fail(vector, "verify", e);
+            }
+        } else if let Err((e, output)) = self.digest.run(output) {
+            self.output.replace(output);
+            self.fail(vector, "run", e);
+        }
+    }
+}
+
+impl<
+        'a,
+        D: digest::Digest<'a, L>
+            + Sha224
+            + Sha256
+            + Sha384
+            + Sha512
+            + HMACSha256
+            + HMACSha384
+            + HMACSha512,
+        const L: usize,
+    > digest::ClientHash<'a, L> for TestDigest<'a, D, L>
+{
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, output: &'static mut [u8; L]) {
+        let vector = &TEST_VECTORS[self.index.get()];
+        if let Err(e) = result {
+            self.output.replace(output);
+            self.fail(vector, "hash_done", e);
+            return;
+        }
+
+        let len = vector.expected.len();
+        if len > L || output[..len] != *vector.expected {
+            self.output.replace(output);
+            self.fail(vector, "comparing the digest", ErrorCode::FAIL);
+            return;
+        }
+
+        // The digest matches, now check that verify() agrees. The engine is
+        // ready for a new message in the same mode after `run()`.
+        self.verifying.set(true);
+        let data = self.data.take().unwrap();
+        let mut buffer = LeasableBuffer::new(data);
+        buffer.slice(0..vector.message.len());
+        self.output.replace(output);
+        if let Err((e, data)) = self.digest.add_data(buffer) {
+            self.data.replace(data);
+            self.fail(vector, "add_data for verify", e);
+        }
+    }
+}
+
+impl<
+        'a,
+        D: digest::Digest<'a, L>
+            + Sha224
+            + Sha256
+            + Sha384
+            + Sha512
+            + HMACSha256
+            + HMACSha384
+            + HMACSha512,
+        const L: usize,
+    > digest::ClientVerify<'a, L> for TestDigest<'a, D, L>
+{
+    fn verification_done(&'a self, result: Result<bool, ErrorCode>, compare: &'static mut [u8; L]) {
+        self.output.replace(compare);
+        let vector = &TEST_VECTORS[self.index.get()];
+        match result {
+            Ok(true) => {
+                debug!("Digest test {}: passed", vector.name);
+                self.passed.set(self.passed.get() + 1);
+                self.next();
+            }
+            Ok(false) => self.fail(vector, "verify", ErrorCode::FAIL),
+            Err(e) => self.fail(vector, "verify", e),
+        }
+    }
+}
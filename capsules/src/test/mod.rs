@@ -1,13 +1,102 @@
+pub mod aead;
 pub mod aes;
 pub mod aes_ccm;
 pub mod alarm;
 pub mod alarm_edge_cases;
 pub mod crc;
+pub mod digest;
 pub mod double_grant_entry;
 pub mod kv_system;
 pub mod random_alarm;
 pub mod random_timer;
 pub mod rng;
+pub mod signature;
 pub mod udp;
+pub mod usb_host;
 pub mod virtual_rng;
 pub mod virtual_uart;
+
+/// Moves `value` to the heap for good, so host tests can hand out the
+/// `&'static` references that boards get from `static_init!`.
+#[cfg(test)]
+pub(crate) fn leak<T>(value: T) -> &'static mut T {
+    extern crate std;
+    std::boxed::Box::leak(std::boxed::Box::new(value))
+}
+
+/// An alarm for host tests, which only fires when the test calls `trigger`.
+/// Time stands still otherwise, at `set_now`.
+#[cfg(test)]
+pub(crate) struct FakeAlarm<'a> {
+    now: core::cell::Cell<kernel::hil::time::Ticks32>,
+    reference: core::cell::Cell<kernel::hil::time::Ticks32>,
+    dt: core::cell::Cell<kernel::hil::time::Ticks32>,
+    armed: core::cell::Cell<bool>,
+    client: kernel::utilities::cells::OptionalCell<&'a dyn kernel::hil::time::AlarmClient>,
+}
+
+#[cfg(test)]
+impl FakeAlarm<'_> {
+    pub(crate) fn new() -> Self {
+        Self {
+            now: core::cell::Cell::new(0u32.into()),
+            reference: core::cell::Cell::new(0u32.into()),
+            dt: core::cell::Cell::new(0u32.into()),
+            armed: core::cell::Cell::new(false),
+            client: kernel::utilities::cells::OptionalCell::empty(),
+        }
+    }
+
+    pub(crate) fn set_now(&self, now: u32) {
+        self.now.set(now.into());
+    }
+
+    /// Moves time to the alarm, and calls the client.
+    pub(crate) fn trigger(&self) {
+        use kernel::hil::time::Alarm;
+        self.now.set(self.get_alarm());
+        self.armed.set(false);
+        self.client.map(|client| client.alarm());
+    }
+}
+
+#[cfg(test)]
+impl kernel::hil::time::Time for FakeAlarm<'_> {
+    type Ticks = kernel::hil::time::Ticks32;
+    type Frequency = kernel::hil::time::Freq1MHz;
+
+    fn now(&self) -> Self::Ticks {
+        self.now.get()
+    }
+}
+
+#[cfg(test)]
+impl<'a> kernel::hil::time::Alarm<'a> for FakeAlarm<'a> {
+    fn set_alarm_client(&self, client: &'a dyn kernel::hil::time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        use kernel::hil::time::Ticks;
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> Result<(), kernel::ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        0u32.into()
+    }
+}
@@ -0,0 +1,237 @@
+//! Test a signature verifier against the RFC 6979 ECDSA P-256 example and
+//! the RFC 8032 Ed25519 test vectors.
+//!
+//! For every test vector the test imports the public key, checks that the
+//! signature is accepted, then flips a bit of the signature and checks that
+//! it is rejected. Finally the key is removed again.
+//!
+//! Any implementation of `hil::public_key_crypto::SignatureVerify` can be
+//! tested, for example
+//! `capsules::public_key_crypto::software::SignatureVerifySoftware`:
+//!
+//! ```rust
+//! let message = static_init!([u8; 32], [0; 32]);
+//! let signature = static_init!([u8; 64], [0; 64]);
+//! let test = static_init!(
+//!     capsules::test::signature::TestSignature<'static, SignatureVerifySoftware<'static, 4>>,
+//!     capsules::test::signature::TestSignature::new(verifier, message, signature)
+//! );
+//! verifier.set_verify_client(test);
+//! test.run();
+//! ```
+//!
+//! You should then see the following output
+//!
+//! ```text
+//! Signature test ECDSA P-256 RFC 6979 "sample": passed
+//! ...
+//! Signature tests finished: 3 passed, 0 failed
+//! ```
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::public_key_crypto::{
+    ClientVerify, KeyHandle, SignatureAlgorithm, SignatureVerify,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+struct TestVector {
+    name: &'static str,
+    algorithm: SignatureAlgorithm,
+    key: &'static [u8],
+    message: &'static [u8],
+    signature: &'static [u8; 64],
+}
+
+static TEST_VECTORS: [TestVector; 3] = [
+    TestVector {
+        name: "ECDSA P-256 RFC 6979 \"sample\"",
+        algorithm: SignatureAlgorithm::EcdsaP256,
+        key: &[
+            0x04, 0x60, 0xfe, 0xd4, 0xba, 0x25, 0x5a, 0x9d, 0x31, 0xc9, 0x61, 0xeb, 0x74, 0xc6,
+            0x35, 0x6d, 0x68, 0xc0, 0x49, 0xb8, 0x92, 0x3b, 0x61, 0xfa, 0x6c, 0xe6, 0x69, 0x62,
+            0x2e, 0x60, 0xf2, 0x9f, 0xb6, 0x79, 0x03, 0xfe, 0x10, 0x08, 0xb8, 0xbc, 0x99, 0xa4,
+            0x1a, 0xe9, 0xe9, 0x56, 0x28, 0xbc, 0x64, 0xf2, 0xf1, 0xb2, 0x0c, 0x2d, 0x7e, 0x9f,
+            0x51, 0x77, 0xa3, 0xc2, 0x94, 0xd4, 0x46, 0x22, 0x99,
+        ],
+        // SHA-256("sample")
+        message: &[
+            0xaf, 0x2b, 0xdb, 0xe1, 0xaa, 0x9b, 0x6e, 0xc1, 0xe2, 0xad, 0xe1, 0xd6, 0x94, 0xf4,
+            0x1f, 0xc7, 0x1a, 0x83, 0x1d, 0x02, 0x68, 0xe9, 0x89, 0x15, 0x62, 0x11, 0x3d, 0x8a,
+            0x62, 0xad, 0xd1, 0xbf,
+        ],
+        signature: &[
+            0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd, 0x9c, 0xd4, 0x5e,
+            0x81, 0xd6, 0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e, 0xa8,
+            0x4e, 0xaf, 0x37, 0x16, 0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41, 0xd4, 0x36,
+            0xc7, 0xa1, 0xb6, 0xe2, 0x9f, 0x65, 0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06,
+            0x4d, 0xc4, 0xab, 0x2f, 0x84, 0x3a, 0xcd, 0xa8,
+        ],
+    },
+    TestVector {
+        name: "Ed25519 RFC 8032 test 1",
+        algorithm: SignatureAlgorithm::Ed25519,
+        key: &[
+            0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64,
+            0x07, 0x3a, 0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68,
+            0xf7, 0x07, 0x51, 0x1a,
+        ],
+        message: &[],
+        signature: &[
+            0xe5, 0x56, 0x43, 0x00, 0xc3, 0x60, 0xac, 0x72, 0x90, 0x86, 0xe2, 0xcc, 0x80, 0x6e,
+            0x82, 0x8a, 0x84, 0x87, 0x7f, 0x1e, 0xb8, 0xe5, 0xd9, 0x74, 0xd8, 0x73, 0xe0, 0x65,
+            0x22, 0x49, 0x01, 0x55, 0x5f, 0xb8, 0x82, 0x15, 0x90, 0xa3, 0x3b, 0xac, 0xc6, 0x1e,
+            0x39, 0x70, 0x1c, 0xf9, 0xb4, 0x6b, 0xd2, 0x5b, 0xf5, 0xf0, 0x59, 0x5b, 0xbe, 0x24,
+            0x65, 0x51, 0x41, 0x43, 0x8e, 0x7a, 0x10, 0x0b,
+        ],
+    },
+    TestVector {
+        name: "Ed25519 RFC 8032 test 2",
+        algorithm: SignatureAlgorithm::Ed25519,
+        key: &[
+            0x3d, 0x40, 0x17, 0xc3, 0xe8, 0x43, 0x89, 0x5a, 0x92, 0xb7, 0x0a, 0xa7, 0x4d, 0x1b,
+            0x7e, 0xbc, 0x9c, 0x98, 0x2c, 0xcf, 0x2e, 0xc4, 0x96, 0x8c, 0xc0, 0xcd, 0x55, 0xf1,
+            0x2a, 0xf4, 0x66, 0x0c,
+        ],
+        message: &[0x72],
+        signature: &[
+            0x92, 0xa0, 0x09, 0xa9, 0xf0, 0xd4, 0xca, 0xb8, 0x72, 0x0e, 0x82, 0x0b, 0x5f, 0x64,
+            0x25, 0x40, 0xa2, 0xb2, 0x7b, 0x54, 0x16, 0x50, 0x3f, 0x8f, 0xb3, 0x76, 0x22, 0x23,
+            0xeb, 0xdb, 0x69, 0xda, 0x08, 0x5a, 0xc1, 0xe4, 0x3e, 0x15, 0x99, 0x6e, 0x45, 0x8f,
+            0x36, 0x13, 0xd0, 0xf1, 0x1d, 0x8c, 0x38, 0x7b, 0x2e, 0xae, 0xb4, 0x30, 0x2a, 0xee,
+            0xb0, 0x0d, 0x29, 0x16, 0x12, 0xbb, 0x0c, 0x00,
+        ],
+    },
+];
+
+pub struct TestSignature<'a, V: 'a> {
+    verifier: &'a V,
+    message: TakeCell<'static, [u8]>,
+    signature: TakeCell<'static, [u8]>,
+    key: OptionalCell<KeyHandle>,
+    index: Cell<usize>,
+    /// Whether the signature being verified was corrupted on purpose.
+    tampered: Cell<bool>,
+    passed: Cell<usize>,
+    failed: Cell<usize>,
+}
+
+impl<'a, V: SignatureVerify<'a>> TestSignature<'a, V> {
+    pub fn new(
+        verifier: &'a V,
+        message: &'static mut [u8],
+        signature: &'static mut [u8; 64],
+    ) -> TestSignature<'a, V> {
+        TestSignature {
+            verifier,
+            message: TakeCell::new(message),
+            signature: TakeCell::new(signature),
+            key: OptionalCell::empty(),
+            index: Cell::new(0),
+            tampered: Cell::new(false),
+            passed: Cell::new(0),
+            failed: Cell::new(0),
+        }
+    }
+
+    pub fn run(&self) {
+        self.index.set(0);
+        self.passed.set(0);
+        self.failed.set(0);
+        self.start();
+    }
+
+    fn start(&self) {
+        let vector = match TEST_VECTORS.get(self.index.get()) {
+            Some(vector) => vector,
+            None => {
+                debug!(
+                    "Signature tests finished: {} passed, {} failed",
+                    self.passed.get(),
+                    self.failed.get()
+                );
+                return;
+            }
+        };
+
+        match self.verifier.import_key(vector.algorithm, vector.key) {
+            Ok(key) => self.key.set(key),
+            Err(e) => {
+                self.fail(vector, "import_key", e);
+                return;
+            }
+        }
+        self.tampered.set(false);
+        self.verify(vector);
+    }
+
+    fn verify(&self, vector: &TestVector) {
+        let message = self.message.take().unwrap();
+        let signature = self.signature.take().unwrap();
+        if message.len() < vector.message.len() {
+            self.message.replace(message);
+            self.signature.replace(signature);
+            self.fail(vector, "message buffer too small", ErrorCode::SIZE);
+            return;
+        }
+        message[..vector.message.len()].copy_from_slice(vector.message);
+        signature.copy_from_slice(vector.signature);
+        if self.tampered.get() {
+            signature[10] ^= 0x01;
+        }
+
+        let mut buffer = LeasableBuffer::new(message);
+        buffer.slice(0..vector.message.len());
+        let key = self.key.unwrap_or(KeyHandle(0));
+        if let Err((e, message, signature)) = self.verifier.verify(key, buffer, signature) {
+            self.message.replace(message);
+            self.signature.replace(signature);
+            self.fail(vector, "verify", e);
+        }
+    }
+
+    fn fail(&self, vector: &TestVector, step: &str, error: ErrorCode) {
+        debug!(
+            "Signature test {}: FAILED at {} ({:?})",
+            vector.name, step, error
+        );
+        self.failed.set(self.failed.get() + 1);
+        self.next();
+    }
+
+    fn next(&self) {
+        self.key.take().map(|key| self.verifier.remove_key(key));
+        self.index.set(self.index.get() + 1);
+        self.start();
+    }
+}
+
+impl<'a, V: SignatureVerify<'a>> ClientVerify for TestSignature<'a, V> {
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) {
+        self.message.replace(message);
+        self.signature.replace(signature);
+        let vector = &TEST_VECTORS[self.index.get()];
+        match (result, self.tampered.get()) {
+            (Ok(true), false) => {
+                // Now check that a corrupted signature is rejected.
+                self.tampered.set(true);
+                self.verify(vector);
+            }
+            (Ok(false), true) => {
+                debug!("Signature test {}: passed", vector.name);
+                self.passed.set(self.passed.get() + 1);
+                self.next();
+            }
+            (Ok(_), false) => self.fail(vector, "verifying the signature", ErrorCode::FAIL),
+            (Ok(_), true) => self.fail(vector, "rejecting a bad signature", ErrorCode::FAIL),
+            (Err(e), _) => self.fail(vector, "verification_done", e),
+        }
+    }
+}
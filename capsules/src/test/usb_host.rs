@@ -0,0 +1,700 @@
+//! A software USB controller, which lets tests play the host of a USB device
+//! stack without hardware.
+//!
+//! `MockUsbController` implements `hil::usb::UsbController` for the class
+//! drivers (`CdcAcm`, `CtapHid`, the `usbc_client` behind
+//! `UsbSyscallDriver`, ...). The test scripts the host side: bus resets,
+//! control transfers with their setup, data and status stages, and IN and
+//! OUT transactions on the other endpoints. Each transaction completes
+//! before the call returns, with the handshake the device answered.
+//!
+//! Like the hardware, the controller NAKs the OUT transactions of an
+//! endpoint from the time its client returns `OutResult::Delay` until the
+//! client calls `endpoint_resume_out`. Resuming an endpoint that was not
+//! delayed is a bug that makes the nRF52 controller panic, so this one
+//! panics too.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let usb = MockUsbController::new();
+//! let ctap = CtapHid::new(&usb, 0x1337, 0x0dec, &STRINGS);
+//! usb.set_client(&ctap);
+//! ctap.enable();
+//! ctap.attach();
+//!
+//! usb.bus_reset();
+//! let mut device = [0; 18];
+//! assert_eq!(usb.get_descriptor(DESCRIPTOR_DEVICE, 0, 0, &mut device), Ok(18));
+//! assert_eq!(usb.set_address(5), Ok(()));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil;
+use kernel::hil::usb::{DeviceSpeed, TransferType};
+use kernel::utilities::cells::{OptionalCell, VolatileCell};
+
+pub const NUM_ENDPOINTS: usize = 8;
+
+// Standard requests
+const GET_DESCRIPTOR: u8 = 6;
+const SET_ADDRESS: u8 = 5;
+const SET_CONFIGURATION: u8 = 9;
+
+// Standard descriptor types
+pub const DESCRIPTOR_DEVICE: u8 = 1;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+pub const DESCRIPTOR_STRING: u8 = 3;
+pub const DESCRIPTOR_INTERFACE: u8 = 4;
+pub const DESCRIPTOR_ENDPOINT: u8 = 5;
+pub const DESCRIPTOR_DEVICE_QUALIFIER: u8 = 6;
+
+/// bmRequestType of a standard request to the device, from the host.
+const REQUEST_OUT: u8 = 0x00;
+/// bmRequestType of a standard request to the device, to the host.
+const REQUEST_IN: u8 = 0x80;
+
+/// The handshake the device answered a transaction with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Handshake {
+    Ack,
+    Nak,
+    Stall,
+}
+
+pub struct MockUsbController<'a> {
+    client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
+    ctrl_buffer: Cell<Option<&'a [VolatileCell<u8>]>>,
+    in_buffers: [Cell<Option<&'a [VolatileCell<u8>]>>; NUM_ENDPOINTS],
+    out_buffers: [Cell<Option<&'a [VolatileCell<u8>]>>; NUM_ENDPOINTS],
+    in_enabled: [Cell<Option<TransferType>>; NUM_ENDPOINTS],
+    out_enabled: [Cell<Option<TransferType>>; NUM_ENDPOINTS],
+    /// Whether the client has data to send, since the host last polled.
+    in_resumed: [Cell<bool>; NUM_ENDPOINTS],
+    /// Whether the endpoint NAKs until the client resumes it.
+    out_delayed: [Cell<bool>; NUM_ENDPOINTS],
+    speed: Cell<Option<DeviceSpeed>>,
+    attached: Cell<bool>,
+    address: Cell<u16>,
+    /// The address set by the client, used once the status stage completes.
+    pending_address: Cell<u16>,
+}
+
+impl<'a> MockUsbController<'a> {
+    pub fn new() -> MockUsbController<'a> {
+        MockUsbController {
+            client: OptionalCell::empty(),
+            ctrl_buffer: Cell::new(None),
+            in_buffers: Default::default(),
+            out_buffers: Default::default(),
+            in_enabled: Default::default(),
+            out_enabled: Default::default(),
+            in_resumed: Default::default(),
+            out_delayed: Default::default(),
+            speed: Cell::new(None),
+            attached: Cell::new(false),
+            address: Cell::new(0),
+            pending_address: Cell::new(0),
+        }
+    }
+
+    /// The speed the device was enabled with, if it was.
+    pub fn speed(&self) -> Option<DeviceSpeed> {
+        self.speed.get()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attached.get()
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address.get()
+    }
+
+    /// Whether the client resumed the IN endpoint since the host last polled
+    /// it.
+    pub fn in_resumed(&self, endpoint: usize) -> bool {
+        self.in_resumed[endpoint].get()
+    }
+
+    /// Whether the OUT endpoint NAKs until the client resumes it.
+    pub fn out_delayed(&self, endpoint: usize) -> bool {
+        self.out_delayed[endpoint].get()
+    }
+
+    pub fn bus_reset(&self) {
+        self.address.set(0);
+        self.pending_address.set(0);
+        self.in_resumed
+            .iter()
+            .for_each(|resumed| resumed.set(false));
+        self.out_delayed
+            .iter()
+            .for_each(|delayed| delayed.set(false));
+        self.client.map(|client| client.bus_reset());
+    }
+
+    /// A control transfer to the device, of `data.len()` bytes.
+    pub fn control_write(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<(), Handshake> {
+        let client = self.client.extract().expect("no client");
+        let buf = self.ctrl_buffer.get().expect("no control buffer");
+        self.setup(request_type, request, value, index, data.len())?;
+        for packet in data.chunks(buf.len()) {
+            packet
+                .iter()
+                .zip(buf.iter())
+                .for_each(|(byte, b)| b.set(*byte));
+            match client.ctrl_out(0, packet.len() as u32) {
+                hil::usb::CtrlOutResult::Ok => (),
+                hil::usb::CtrlOutResult::Delay => return Err(Handshake::Nak),
+                hil::usb::CtrlOutResult::Halted => return Err(Handshake::Stall),
+            }
+        }
+        self.status_stage();
+        Ok(())
+    }
+
+    /// A control transfer from the device, of up to `data.len()` bytes.
+    /// Returns the number of bytes received.
+    pub fn control_read(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &mut [u8],
+    ) -> Result<usize, Handshake> {
+        let client = self.client.extract().expect("no client");
+        let buf = self.ctrl_buffer.get().expect("no control buffer");
+        self.setup(request_type, request, value, index, data.len())?;
+        let mut received = 0;
+        while received < data.len() {
+            match client.ctrl_in(0) {
+                hil::usb::CtrlInResult::Packet(len, last) => {
+                    assert!(len <= buf.len(), "packet larger than the endpoint buffer");
+                    assert!(
+                        received + len <= data.len(),
+                        "the device sent more than requested"
+                    );
+                    data[received..received + len]
+                        .iter_mut()
+                        .zip(buf.iter())
+                        .for_each(|(byte, b)| *byte = b.get());
+                    received += len;
+                    if last {
+                        break;
+                    }
+                }
+                hil::usb::CtrlInResult::Delay => return Err(Handshake::Nak),
+                hil::usb::CtrlInResult::Error => return Err(Handshake::Stall),
+            }
+        }
+        self.status_stage();
+        Ok(received)
+    }
+
+    pub fn get_descriptor(
+        &self,
+        descriptor_type: u8,
+        index: u8,
+        lang_id: u16,
+        data: &mut [u8],
+    ) -> Result<usize, Handshake> {
+        let value = (descriptor_type as u16) << 8 | index as u16;
+        self.control_read(REQUEST_IN, GET_DESCRIPTOR, value, lang_id, data)
+    }
+
+    pub fn set_address(&self, address: u16) -> Result<(), Handshake> {
+        self.control_write(REQUEST_OUT, SET_ADDRESS, address, 0, &[])
+    }
+
+    pub fn set_configuration(&self, configuration: u8) -> Result<(), Handshake> {
+        self.control_write(REQUEST_OUT, SET_CONFIGURATION, configuration as u16, 0, &[])
+    }
+
+    /// An OUT transaction of `data` on a bulk or interrupt endpoint.
+    pub fn transfer_out(&self, endpoint: usize, data: &[u8]) -> Handshake {
+        let transfer_type = match self.out_enabled[endpoint].get() {
+            Some(transfer_type) => transfer_type,
+            None => return Handshake::Stall,
+        };
+        if self.out_delayed[endpoint].get() {
+            return Handshake::Nak;
+        }
+        let buf = self.out_buffers[endpoint].get().expect("no OUT buffer");
+        assert!(
+            data.len() <= buf.len(),
+            "packet larger than the endpoint buffer"
+        );
+        data.iter()
+            .zip(buf.iter())
+            .for_each(|(byte, b)| b.set(*byte));
+        let client = self.client.extract().expect("no client");
+        match client.packet_out(transfer_type, endpoint, data.len() as u32) {
+            hil::usb::OutResult::Ok => Handshake::Ack,
+            hil::usb::OutResult::Delay => {
+                self.out_delayed[endpoint].set(true);
+                Handshake::Nak
+            }
+            hil::usb::OutResult::Error => Handshake::Stall,
+        }
+    }
+
+    /// An IN transaction on a bulk or interrupt endpoint. Returns the number
+    /// of bytes received.
+    pub fn transfer_in(&self, endpoint: usize, data: &mut [u8]) -> Result<usize, Handshake> {
+        let transfer_type = self.in_enabled[endpoint].get().ok_or(Handshake::Stall)?;
+        self.in_resumed[endpoint].set(false);
+        let buf = self.in_buffers[endpoint].get().expect("no IN buffer");
+        let client = self.client.extract().expect("no client");
+        match client.packet_in(transfer_type, endpoint) {
+            hil::usb::InResult::Packet(len) => {
+                let len = cmp::min(len, data.len());
+                data[..len]
+                    .iter_mut()
+                    .zip(buf.iter())
+                    .for_each(|(byte, b)| *byte = b.get());
+                client.packet_transmitted(endpoint);
+                Ok(len)
+            }
+            hil::usb::InResult::Delay => Err(Handshake::Nak),
+            hil::usb::InResult::Error => Err(Handshake::Stall),
+        }
+    }
+
+    fn setup(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: usize,
+    ) -> Result<(), Handshake> {
+        let buf = self.ctrl_buffer.get().expect("no control buffer");
+        let value = value.to_le_bytes();
+        let index = index.to_le_bytes();
+        let length = (length as u16).to_le_bytes();
+        let setup = [
+            request_type,
+            request,
+            value[0],
+            value[1],
+            index[0],
+            index[1],
+            length[0],
+            length[1],
+        ];
+        setup
+            .iter()
+            .zip(buf.iter())
+            .for_each(|(byte, b)| b.set(*byte));
+        match self.client.map(|client| client.ctrl_setup(0)) {
+            Some(hil::usb::CtrlSetupResult::Ok) | Some(hil::usb::CtrlSetupResult::OkSetAddress) => {
+                Ok(())
+            }
+            _ => Err(Handshake::Stall),
+        }
+    }
+
+    fn status_stage(&self) {
+        self.client.map(|client| {
+            client.ctrl_status(0);
+            client.ctrl_status_complete(0);
+        });
+    }
+}
+
+impl<'a> hil::usb::UsbController<'a> for MockUsbController<'a> {
+    fn set_client(&self, client: &'a dyn hil::usb::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn endpoint_set_ctrl_buffer(&self, buf: &'a [VolatileCell<u8>]) {
+        self.ctrl_buffer.set(Some(buf));
+    }
+
+    fn endpoint_set_in_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.in_buffers[endpoint].set(Some(buf));
+    }
+
+    fn endpoint_set_out_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.out_buffers[endpoint].set(Some(buf));
+    }
+
+    fn enable_as_device(&self, speed: DeviceSpeed) {
+        self.speed.set(Some(speed));
+    }
+
+    fn attach(&self) {
+        self.attached.set(true);
+    }
+
+    fn detach(&self) {
+        self.attached.set(false);
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.pending_address.set(addr);
+    }
+
+    fn enable_address(&self) {
+        self.address.set(self.pending_address.get());
+    }
+
+    fn endpoint_in_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.in_enabled[endpoint].set(Some(transfer_type));
+    }
+
+    fn endpoint_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.out_enabled[endpoint].set(Some(transfer_type));
+    }
+
+    fn endpoint_in_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.endpoint_in_enable(transfer_type, endpoint);
+        self.endpoint_out_enable(transfer_type, endpoint);
+    }
+
+    fn endpoint_resume_in(&self, endpoint: usize) {
+        self.in_resumed[endpoint].set(true);
+    }
+
+    fn endpoint_resume_out(&self, endpoint: usize) {
+        assert!(
+            self.out_delayed[endpoint].take(),
+            "OUT endpoint {} resumed but not delayed",
+            endpoint
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{leak, FakeAlarm};
+    use crate::usb::cdc::CdcAcm;
+    use crate::usb::ctap::CtapHid;
+    use crate::usb::usbc_client;
+    use kernel::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+    use kernel::hil::uart::ControlLines;
+    use kernel::hil::usb::{Client, UsbController};
+    use kernel::hil::usb_hid::{self, UsbHid};
+    use kernel::utilities::cells::TakeCell;
+    use kernel::ErrorCode;
+
+    static STRINGS: &[&str; 3] = &["Tock", "Mock device", "0001"];
+
+    /// Check the configuration descriptor and the descriptors that follow
+    /// it, and return the number of interfaces.
+    fn check_configuration(config: &[u8]) -> usize {
+        assert_eq!(&config[..2], &[9, DESCRIPTOR_CONFIGURATION]);
+        assert_eq!(
+            u16::from_le_bytes([config[2], config[3]]) as usize,
+            config.len()
+        );
+
+        let mut interfaces = 0;
+        // The endpoints the last interface has, and has left to describe
+        let mut endpoints = 0;
+        let mut offset = config[0] as usize;
+        while offset < config.len() {
+            let len = config[offset] as usize;
+            assert!(len >= 2 && offset + len <= config.len());
+            match config[offset + 1] {
+                DESCRIPTOR_INTERFACE => {
+                    assert_eq!(len, 9);
+                    assert_eq!(endpoints, 0);
+                    assert_eq!(config[offset + 2] as usize, interfaces);
+                    interfaces += 1;
+                    endpoints = config[offset + 4];
+                }
+                DESCRIPTOR_ENDPOINT => {
+                    assert_eq!(len, 7);
+                    assert!(endpoints > 0);
+                    endpoints -= 1;
+                }
+                _ => (),
+            }
+            offset += len;
+        }
+        assert_eq!(endpoints, 0);
+        assert_eq!(config[4] as usize, interfaces);
+        interfaces
+    }
+
+    /// Check the descriptors the host reads during the enumeration, and
+    /// return the configuration descriptor.
+    fn enumerate(usb: &MockUsbController, vendor_id: u16, product_id: u16) -> [u8; 128] {
+        usb.bus_reset();
+
+        // Hosts first read the maximum packet size of the control endpoint.
+        let mut device = [0; 64];
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_DEVICE, 0, 0, &mut device),
+            Ok(18)
+        );
+        assert_eq!(&device[..2], &[18, DESCRIPTOR_DEVICE]);
+        assert_eq!(&device[2..4], &[0x00, 0x02]);
+        assert!([8, 16, 32, 64].contains(&device[7]));
+        assert_eq!(&device[8..10], &vendor_id.to_le_bytes());
+        assert_eq!(&device[10..12], &product_id.to_le_bytes());
+        assert_eq!(device[17], 1);
+
+        assert_eq!(usb.set_address(9), Ok(()));
+        assert_eq!(usb.address(), 9);
+
+        let mut config = [0; 128];
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_CONFIGURATION, 0, 0, &mut config[..9]),
+            Ok(9)
+        );
+        let total_len = u16::from_le_bytes([config[2], config[3]]) as usize;
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_CONFIGURATION, 0, 0, &mut config[..total_len]),
+            Ok(total_len)
+        );
+        check_configuration(&config[..total_len]);
+
+        let mut langs = [0; 255];
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_STRING, 0, 0, &mut langs),
+            Ok(4)
+        );
+        assert_eq!(&langs[..4], &[4, DESCRIPTOR_STRING, 0x09, 0x04]);
+
+        assert_eq!(usb.set_configuration(1), Ok(()));
+        config
+    }
+
+    #[test]
+    fn ctap_hid_enumerates() {
+        let usb = leak(MockUsbController::new());
+        let ctap = leak(CtapHid::new(usb, 0x1337, 0x0dec, STRINGS));
+        usb.set_client(ctap);
+        ctap.enable();
+        ctap.attach();
+        assert!(usb.is_attached());
+        assert!(matches!(usb.speed(), Some(DeviceSpeed::Full)));
+
+        let config = enumerate(usb, 0x1337, 0x0dec);
+        // The HID class descriptor follows the interface
+        assert_eq!(config[9 + 9 + 1], 0x21);
+
+        let mut string = [0; 255];
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_STRING, 2, 0x0409, &mut string),
+            Ok(2 + 2 * "Mock device".len())
+        );
+        assert_eq!(&string[..4], &[24, DESCRIPTOR_STRING, b'M', 0]);
+        // Unknown strings and languages are rejected
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_STRING, 4, 0x0409, &mut string),
+            Err(Handshake::Stall)
+        );
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_STRING, 1, 0x0407, &mut string),
+            Err(Handshake::Stall)
+        );
+        // A full speed device has no device qualifier
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_DEVICE_QUALIFIER, 0, 0, &mut string),
+            Err(Handshake::Stall)
+        );
+
+        // The report descriptor is requested from the interface
+        let mut report = [0; 128];
+        let len = usb.control_read(0x81, GET_DESCRIPTOR, 0x2200, 0, &mut report);
+        assert_eq!(len, Ok(34));
+        assert_eq!(&report[..3], &[0x06, 0xd0, 0xf1]);
+    }
+
+    #[test]
+    fn unexpected_data_stage_is_stalled() {
+        let usb = leak(MockUsbController::new());
+        let ctap = leak(CtapHid::new(usb, 0x1337, 0x0dec, STRINGS));
+        usb.set_client(ctap);
+        ctap.enable();
+
+        // SET_CONFIGURATION has no data stage
+        assert_eq!(
+            usb.control_write(REQUEST_OUT, SET_CONFIGURATION, 1, 0, &[0]),
+            Err(Handshake::Stall)
+        );
+        // The control endpoint is ready for the next request
+        let mut device = [0; 18];
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_DEVICE, 0, 0, &mut device),
+            Ok(18)
+        );
+    }
+
+    #[test]
+    fn cdc_acm_enumerates_and_sets_line_coding() {
+        let usb = leak(MockUsbController::new());
+        let client_states = leak([DynamicDeferredCallClientState::default()]);
+        let deferred_caller = leak(DynamicDeferredCall::new(client_states));
+        let cdc = leak(CdcAcm::new(
+            usb,
+            64,
+            0x1337,
+            0x0ace,
+            STRINGS,
+            leak(FakeAlarm::new()),
+            deferred_caller,
+            None,
+        ));
+        usb.set_client(cdc);
+        cdc.enable();
+        cdc.attach();
+
+        // The configuration is read in two packets
+        let config = enumerate(usb, 0x1337, 0x0ace);
+        assert!(u16::from_le_bytes([config[2], config[3]]) > 64);
+        assert_eq!(config[4], 2);
+
+        // SET_LINE_CODING to 9600 baud, 8N1, then read it back
+        let line_coding = [0x80, 0x25, 0, 0, 0, 0, 8];
+        assert_eq!(usb.control_write(0x21, 0x20, 0, 0, &line_coding), Ok(()));
+        assert_eq!(cdc.line_parameters().baud_rate, 9600);
+        let mut read = [0; 7];
+        assert_eq!(usb.control_read(0xa1, 0x21, 0, 0, &mut read), Ok(7));
+        assert_eq!(read, line_coding);
+        // A host may read only the baud rate
+        let mut baud_rate = [0; 4];
+        assert_eq!(usb.control_read(0xa1, 0x21, 0, 0, &mut baud_rate), Ok(4));
+        assert_eq!(baud_rate, [0x80, 0x25, 0, 0]);
+
+        // SET_CONTROL_LINE_STATE with DTR
+        assert_eq!(usb.control_write(0x21, 0x22, 0x01, 0, &[]), Ok(()));
+        let lines = cdc.control_line_state();
+        assert!(lines.dtr);
+        assert!(!lines.rts);
+    }
+
+    /// A HID client which gives its receive buffer back as soon as it
+    /// receives a report, if `rearm` is set.
+    struct Reports {
+        hid: &'static CtapHid<'static, MockUsbController<'static>>,
+        rearm: Cell<bool>,
+        received: Cell<Option<u8>>,
+        recv: TakeCell<'static, [u8; 64]>,
+        sent: TakeCell<'static, [u8; 64]>,
+    }
+
+    impl usb_hid::Client<'static, [u8; 64]> for Reports {
+        fn packet_received(
+            &'static self,
+            _result: Result<(), ErrorCode>,
+            buffer: &'static mut [u8; 64],
+            _endpoint: usize,
+        ) {
+            self.received.set(Some(buffer[0]));
+            if self.rearm.get() {
+                let _ = self.hid.receive_buffer(buffer);
+            } else {
+                self.recv.replace(buffer);
+            }
+        }
+
+        fn packet_transmitted(
+            &'static self,
+            _result: Result<(), ErrorCode>,
+            buffer: &'static mut [u8; 64],
+            _endpoint: usize,
+        ) {
+            self.sent.replace(buffer);
+        }
+
+        fn can_receive(&'static self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn ctap_hid_reports_round_trip() {
+        let usb = leak(MockUsbController::new());
+        let ctap = leak(CtapHid::new(usb, 0x1337, 0x0dec, STRINGS));
+        usb.set_client(ctap);
+        ctap.enable();
+        let reports = leak(Reports {
+            hid: ctap,
+            rearm: Cell::new(true),
+            received: Cell::new(None),
+            recv: TakeCell::empty(),
+            sent: TakeCell::empty(),
+        });
+        ctap.set_client(reports);
+        assert!(ctap.receive_buffer(leak([0; 64])).is_ok());
+
+        // A client that is ready for the next report keeps the endpoint open
+        assert_eq!(usb.transfer_out(1, &[1; 64]), Handshake::Ack);
+        assert_eq!(reports.received.get(), Some(1));
+        assert_eq!(usb.transfer_out(1, &[2; 64]), Handshake::Ack);
+        assert_eq!(reports.received.get(), Some(2));
+
+        // Otherwise the next report waits for a receive buffer
+        reports.rearm.set(false);
+        usb.transfer_out(1, &[3; 64]);
+        assert_eq!(reports.received.get(), Some(3));
+        assert!(usb.out_delayed(1));
+        assert_eq!(usb.transfer_out(1, &[4; 64]), Handshake::Nak);
+        reports.rearm.set(true);
+        assert!(ctap.receive_buffer(reports.recv.take().unwrap()).is_ok());
+        assert!(!usb.out_delayed(1));
+        assert_eq!(usb.transfer_out(1, &[4; 64]), Handshake::Ack);
+        assert_eq!(reports.received.get(), Some(4));
+
+        // Nothing to send until the client sends a report
+        let mut report = [0; 64];
+        assert_eq!(usb.transfer_in(1, &mut report), Err(Handshake::Nak));
+        assert!(ctap.send_buffer(leak([5; 64])).is_ok());
+        assert!(usb.in_resumed(1));
+        assert_eq!(usb.transfer_in(1, &mut report), Ok(64));
+        assert_eq!(report, [5; 64]);
+        assert!(reports.sent.is_some());
+    }
+
+    #[test]
+    fn usbc_client_echoes_bulk_data() {
+        let usb = leak(MockUsbController::new());
+        let client = leak(usbc_client::Client::new(usb, 64));
+        usb.set_client(client);
+        client.enable();
+        client.attach();
+
+        let mut device = [0; 18];
+        assert_eq!(
+            usb.get_descriptor(DESCRIPTOR_DEVICE, 0, 0, &mut device),
+            Ok(18)
+        );
+        let mut config = [0; 64];
+        let len = usb
+            .get_descriptor(DESCRIPTOR_CONFIGURATION, 0, 0, &mut config)
+            .unwrap();
+        assert_eq!(check_configuration(&config[..len]), 1);
+
+        assert_eq!(usb.transfer_out(2, &[1, 2, 3, 4, 5]), Handshake::Ack);
+        assert!(usb.in_resumed(1));
+        // The echo buffer is full until it is read back
+        assert_eq!(usb.transfer_out(2, &[6, 7, 8, 9]), Handshake::Nak);
+        assert_eq!(usb.transfer_out(2, &[6, 7, 8, 9]), Handshake::Nak);
+
+        let mut echo = [0; 8];
+        assert_eq!(usb.transfer_in(1, &mut echo), Ok(5));
+        assert_eq!(&echo[..5], &[1, 2, 3, 4, 5]);
+        assert!(!usb.out_delayed(2));
+        assert_eq!(usb.transfer_out(2, &[6, 7, 8, 9]), Handshake::Ack);
+        assert_eq!(usb.transfer_in(1, &mut echo), Ok(4));
+        assert_eq!(&echo[..4], &[6, 7, 8, 9]);
+        assert_eq!(usb.transfer_in(1, &mut echo), Err(Handshake::Nak));
+    }
+}
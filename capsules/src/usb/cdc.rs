@@ -1,6 +1,15 @@
 //! Communications Class Device for USB
 //!
 //! This capsule allows Tock to support a serial port over USB.
+//!
+//! The baud rate and the DTR and RTS lines set by the host do not affect the
+//! transfers, but they are passed to the client of the `uart::ControlLines`
+//! interface, e.g. to enter a bootloader when the host opens the port at
+//! 1200 baud.
+//!
+//! A device can have several serial ports: each `CdcAcm` is then the class
+//! client of a `composite::CompositeFunction`, rather than of the USB
+//! controller.
 
 use core::cell::Cell;
 use core::cmp;
@@ -84,6 +93,8 @@ enum CtrlState {
     Idle,
     /// Host has sent a SET_LINE_CODING configuration request.
     SetLineCoding,
+    /// Host has sent a GET_LINE_CODING request for up to this many bytes.
+    GetLineCoding(usize),
     /// Host has send a SET_CONTROL_LINE_STATE configuration request.
     SetControlLineState,
 }
@@ -92,6 +103,7 @@ enum CtrlState {
 enum CDCCntrlMessage {
     NotSupported,
     SetLineCoding = 0x20,
+    GetLineCoding = 0x21,
     SetControlLineState = 0x22,
     SendBreak = 0x23,
 }
@@ -100,6 +112,7 @@ impl From<u8> for CDCCntrlMessage {
     fn from(num: u8) -> Self {
         match num {
             0x20 => CDCCntrlMessage::SetLineCoding,
+            0x21 => CDCCntrlMessage::GetLineCoding,
             0x22 => CDCCntrlMessage::SetControlLineState,
             0x23 => CDCCntrlMessage::SendBreak,
             _ => CDCCntrlMessage::NotSupported,
@@ -107,6 +120,30 @@ impl From<u8> for CDCCntrlMessage {
     }
 }
 
+/// Converts a CDC line coding to UART parameters. The settings that UARTs do
+/// not support (1.5 stop bits, mark and space parity, and 5 or 16 data bits)
+/// are replaced by the closest one.
+fn line_parameters(line_coding: descriptors::CdcAcmSetLineCodingData) -> uart::Parameters {
+    uart::Parameters {
+        baud_rate: line_coding.baud_rate,
+        width: match line_coding.data_bits {
+            5 | 6 => uart::Width::Six,
+            7 => uart::Width::Seven,
+            _ => uart::Width::Eight,
+        },
+        parity: match line_coding.parity {
+            1 => uart::Parity::Odd,
+            2 => uart::Parity::Even,
+            _ => uart::Parity::None,
+        },
+        stop_bits: match line_coding.stop_bits {
+            0 => uart::StopBits::One,
+            _ => uart::StopBits::Two,
+        },
+        hw_flow_control: false,
+    }
+}
+
 /// Implementation of the Abstract Control Model (ACM) for the Communications
 /// Class Device (CDC) over USB.
 pub struct CdcAcm<'a, U: 'a, A: 'a + Alarm<'a>> {
@@ -124,6 +161,13 @@ pub struct CdcAcm<'a, U: 'a, A: 'a + Alarm<'a>> {
     /// request the host is currently sending us.
     ctrl_state: Cell<CtrlState>,
 
+    /// The line coding last set by the host, which it can read back.
+    line_coding: Cell<descriptors::CdcAcmSetLineCodingData>,
+    /// The DTR and RTS lines last set by the host.
+    control_line_state: Cell<uart::ControlLineState>,
+    /// The client to tell about changes of the line coding and control lines.
+    control_lines_client: OptionalCell<&'a dyn uart::ControlLinesClient>,
+
     /// A holder reference for the TX buffer we are transmitting from.
     tx_buffer: TakeCell<'static, [u8]>,
     /// The number of bytes the client has asked us to send. We track this so we
@@ -298,6 +342,14 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> CdcAcm<'a, U, A> {
             ],
             state: Cell::new(State::Disabled),
             ctrl_state: Cell::new(CtrlState::Idle),
+            line_coding: Cell::new(descriptors::CdcAcmSetLineCodingData {
+                baud_rate: 115200,
+                stop_bits: 0, // 1 stop bit
+                parity: 0,    // None
+                data_bits: 8,
+            }),
+            control_line_state: Cell::new(uart::ControlLineState::default()),
+            control_lines_client: OptionalCell::empty(),
             tx_buffer: TakeCell::empty(),
             tx_len: Cell::new(0),
             tx_offset: Cell::new(0),
@@ -413,6 +465,7 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
     /// CDC uses special values here, and we can use these to know when a CDC
     /// client is connected or not.
     fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.ctrl_state.set(CtrlState::Idle);
         descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf).map(|setup_data| {
             let b_request = setup_data.request_code;
 
@@ -420,6 +473,12 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
                 CDCCntrlMessage::SetLineCoding => {
                     self.ctrl_state.set(CtrlState::SetLineCoding);
                 }
+                CDCCntrlMessage::GetLineCoding => {
+                    // `ClientCtrl` does not answer class requests for data,
+                    // so we send the line coding ourselves.
+                    self.ctrl_state
+                        .set(CtrlState::GetLineCoding(setup_data.length as usize));
+                }
                 CDCCntrlMessage::SetControlLineState => {
                     // Bit 0 and 1 of the value (setup_data.value) can be set
                     // D0: Indicates to DCE if DTE is present or not.
@@ -429,10 +488,15 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
                     //     - 0 -> Deactivate carrier
                     //     - 1 -> Activate carrier
                     //
-                    // Currently we don't care about the value, just that this
-                    // event has occurred. If it has happened, update the flag
-                    // in `State::Connecting`.
+                    // The connection only depends on this event having
+                    // occurred. If it has happened, update the flag in
+                    // `State::Connecting`. The lines are passed to the
+                    // control lines client once the request completes.
                     self.set_connecting_state(false, true);
+                    self.control_line_state.set(uart::ControlLineState {
+                        dtr: setup_data.value & 0x01 != 0,
+                        rts: setup_data.value & 0x02 != 0,
+                    });
 
                     self.ctrl_state.set(CtrlState::SetControlLineState);
                 }
@@ -445,12 +509,28 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
             }
         });
 
+        if let CtrlState::GetLineCoding(_) = self.ctrl_state.get() {
+            return hil::usb::CtrlSetupResult::Ok;
+        }
         self.client_ctrl.ctrl_setup(endpoint)
     }
 
     /// Handle a Control In transaction
     fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
-        self.client_ctrl.ctrl_in(endpoint)
+        match self.ctrl_state.get() {
+            CtrlState::GetLineCoding(length) => {
+                let line_coding = self.line_coding.get();
+                let buf = &self.client_ctrl.ctrl_buffer.buf;
+                for (i, b) in line_coding.baud_rate.to_le_bytes().iter().enumerate() {
+                    buf[i].set(*b);
+                }
+                buf[4].set(line_coding.stop_bits);
+                buf[5].set(line_coding.parity);
+                buf[6].set(line_coding.data_bits);
+                hil::usb::CtrlInResult::Packet(cmp::min(7, length), true)
+            }
+            _ => self.client_ctrl.ctrl_in(endpoint),
+        }
     }
 
     /// Handle a Control Out transaction
@@ -462,6 +542,8 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
                 // We can parse the data we got.
                 descriptors::CdcAcmSetLineCodingData::get(&self.client_ctrl.ctrl_buffer.buf).map(
                     |line_coding| {
+                        self.line_coding.set(line_coding);
+
                         // If the device is configuring the baud rate to what we
                         // expect, we continue with the connecting process.
                         if line_coding.baud_rate == 115200 {
@@ -491,6 +573,19 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> hil::usb::Client<'a>
 
     /// Handle the completion of a Control transfer
     fn ctrl_status_complete(&'a self, endpoint: usize) {
+        match self.ctrl_state.get() {
+            CtrlState::SetLineCoding => {
+                let parameters = line_parameters(self.line_coding.get());
+                self.control_lines_client
+                    .map(|client| client.line_parameters_changed(parameters));
+            }
+            CtrlState::SetControlLineState => {
+                let state = self.control_line_state.get();
+                self.control_lines_client
+                    .map(|client| client.control_lines_changed(state));
+            }
+            _ => {}
+        }
         self.ctrl_state.set(CtrlState::Idle);
 
         // Here we check to see if we just got connected to a CDC client. If so,
@@ -665,6 +760,22 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> uart::Configure for
     }
 }
 
+impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> uart::ControlLines<'a>
+    for CdcAcm<'a, U, A>
+{
+    fn set_control_lines_client(&self, client: &'a dyn uart::ControlLinesClient) {
+        self.control_lines_client.set(client);
+    }
+
+    fn line_parameters(&self) -> uart::Parameters {
+        line_parameters(self.line_coding.get())
+    }
+
+    fn control_line_state(&self) -> uart::ControlLineState {
+        self.control_line_state.get()
+    }
+}
+
 impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> uart::Transmit<'a>
     for CdcAcm<'a, U, A>
 {
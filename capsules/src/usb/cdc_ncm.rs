@@ -0,0 +1,848 @@
+//! Network Control Model of the Communication Device Class for USB
+//!
+//! This capsule presents the device to the host as a USB network adapter
+//! (CDC-NCM), which Linux, macOS and Windows support without a driver. It
+//! implements the `EthernetAdapter` HIL, so the IPv6/UDP stack of
+//! `EthernetInterface` runs on it just as on a MAC:
+//!
+//! ```rust
+//! let ncm = components::cdc_ncm::CdcNcmComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     0x1209,
+//!     0x0003,
+//!     strings,
+//! )
+//! .finalize(components::usb_cdc_ncm_component_helper!(nrf52::usbd::Usbd));
+//!
+//! let (udp_send_mux, udp_recv_mux, port_table, ip_send_mux, icmp_recv_mux, eth, ip_receive) =
+//!     components::ethernet::EthernetComponent::new(
+//!         ncm,
+//!         EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]),
+//!         local_ip_ifaces,
+//!         mux_alarm,
+//!     )
+//!     .finalize(components::ethernet_component_helper!(Alarm));
+//!
+//! ncm.enable();
+//! ncm.attach();
+//! ```
+//!
+//! The fourth string is the MAC address of the network interface of the host,
+//! as 12 hexadecimal digits (e.g. "020000000002"). The device uses its own MAC
+//! address, given to `EthernetComponent`.
+//!
+//! Frames are carried in NCM Transfer Blocks (NTBs) with 16-bit headers. Each
+//! NTB the device sends holds one frame, while all frames of an NTB sent by the
+//! host are passed to the client. The link is up while the host selects the
+//! alternate setting of the data interface that has the bulk endpoints.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::CdcEthernetNetworkingDescriptor;
+use super::descriptors::CdcInterfaceDescriptor;
+use super::descriptors::CdcInterfaceDescriptorSubType;
+use super::descriptors::CdcNcmDescriptor;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::StandardRequest;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::ethernet::{EthernetAdapter, EthernetAdapterClient};
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Interrupt IN endpoint for notifications.
+const ENDPOINT_NOTIFY_NUM: usize = 1;
+/// Bulk IN endpoint for the NTBs sent to the host.
+const ENDPOINT_IN_NUM: usize = 2;
+/// Bulk OUT endpoint for the NTBs sent by the host.
+const ENDPOINT_OUT_NUM: usize = 3;
+
+/// The data interface, whose alternate setting 1 has the bulk endpoints.
+const DATA_INTERFACE: u16 = 1;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Max packet size of the control and of the bulk endpoints.
+pub const MAX_CTRL_PACKET_SIZE: u8 = 64;
+const MAX_PACKET_SIZE: usize = 64;
+
+/// The longest frame the device sends or receives: 1500 bytes of payload
+/// and the Ethernet header.
+pub const MAX_SEGMENT_SIZE: usize = 1514;
+
+/// The length of the NTB buffers, which is the smallest NTB size a host must
+/// support.
+pub const NTB_BUF_LEN: usize = 2048;
+
+/// Class specific requests of CDC-NCM.
+const SET_ETHERNET_PACKET_FILTER: u8 = 0x43;
+const GET_NTB_PARAMETERS: u8 = 0x80;
+const GET_NTB_FORMAT: u8 = 0x83;
+const SET_NTB_FORMAT: u8 = 0x84;
+const GET_NTB_INPUT_SIZE: u8 = 0x85;
+const SET_NTB_INPUT_SIZE: u8 = 0x86;
+
+/// Notifications on the interrupt endpoint.
+const NETWORK_CONNECTION: u8 = 0x00;
+const CONNECTION_SPEED_CHANGE: u8 = 0x2a;
+
+/// The bit rate reported to the host, that of a full speed device.
+const BIT_RATE: u32 = 12_000_000;
+
+/// Signatures of the NTB header and of the datagram pointer table.
+const NTH16_SIGNATURE: u32 = 0x484d_434e; // "NCMH"
+const NDP16_SIGNATURE: u32 = 0x304d_434e; // "NCM0", no CRC
+const NDP16_SIGNATURE_CRC: u32 = 0x314d_434e; // "NCM1"
+
+const NTH16_LEN: usize = 12;
+/// The datagram pointer table of the NTBs we send, with one entry and the
+/// terminating entry.
+const NDP16_LEN: usize = 16;
+/// Alignment of the datagram pointer tables and of the datagrams.
+const NTB_ALIGNMENT: u16 = 4;
+
+fn get_u16(buf: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]]) as usize
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+/// Calls `f` with each datagram of an NTB with 16-bit headers. Fails if the
+/// NTB is malformed, in which case some datagrams may have been passed
+/// already.
+fn parse_ntb<F: FnMut(&[u8])>(ntb: &[u8], mut f: F) -> Result<(), ErrorCode> {
+    if ntb.len() < NTH16_LEN || get_u32(ntb, 0) != NTH16_SIGNATURE || get_u16(ntb, 4) != NTH16_LEN {
+        return Err(ErrorCode::INVAL);
+    }
+    let block_len = get_u16(ntb, 8);
+    if block_len < NTH16_LEN {
+        return Err(ErrorCode::INVAL);
+    }
+    if block_len > ntb.len() {
+        return Err(ErrorCode::SIZE);
+    }
+    let ntb = &ntb[..block_len];
+
+    // Each table is at least 16 bytes, which bounds the length of a chain
+    // that loops.
+    let mut ndp = get_u16(ntb, 10);
+    for _ in 0..ntb.len() / 16 {
+        if ndp == 0 {
+            return Ok(());
+        }
+        if ndp < NTH16_LEN || ndp + 8 > ntb.len() {
+            return Err(ErrorCode::INVAL);
+        }
+        let signature = get_u32(ntb, ndp);
+        let ndp_len = get_u16(ntb, ndp + 4);
+        if (signature != NDP16_SIGNATURE && signature != NDP16_SIGNATURE_CRC)
+            || ndp_len < 16
+            || ndp + ndp_len > ntb.len()
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        for entry in (ndp + 8..ndp + ndp_len - 3).step_by(4) {
+            let index = get_u16(ntb, entry);
+            let len = get_u16(ntb, entry + 2);
+            if index == 0 || len == 0 {
+                break;
+            }
+            if index + len > ntb.len() {
+                return Err(ErrorCode::INVAL);
+            }
+            f(&ntb[index..index + len]);
+        }
+        ndp = get_u16(ntb, ndp + 6);
+    }
+    Err(ErrorCode::INVAL)
+}
+
+/// Writes an NTB with 16-bit headers that carries `datagram` into `ntb`, and
+/// returns its length. The NTB is padded so that its length is not a multiple
+/// of the packet size, and it ends with a short packet.
+fn write_ntb(ntb: &mut [u8], sequence: u16, datagram: &[u8]) -> Result<usize, ErrorCode> {
+    let index = NTH16_LEN + NDP16_LEN;
+    let mut block_len = index + datagram.len();
+    if block_len % MAX_PACKET_SIZE == 0 {
+        block_len += 1;
+    }
+    if block_len > ntb.len() {
+        return Err(ErrorCode::SIZE);
+    }
+
+    ntb[0..4].copy_from_slice(&NTH16_SIGNATURE.to_le_bytes());
+    ntb[4..6].copy_from_slice(&(NTH16_LEN as u16).to_le_bytes());
+    ntb[6..8].copy_from_slice(&sequence.to_le_bytes());
+    ntb[8..10].copy_from_slice(&(block_len as u16).to_le_bytes());
+    ntb[10..12].copy_from_slice(&(NTH16_LEN as u16).to_le_bytes());
+
+    let ndp = &mut ntb[NTH16_LEN..index];
+    ndp[0..4].copy_from_slice(&NDP16_SIGNATURE.to_le_bytes());
+    ndp[4..6].copy_from_slice(&(NDP16_LEN as u16).to_le_bytes());
+    ndp[6..8].copy_from_slice(&0u16.to_le_bytes()); // No next table
+    ndp[8..10].copy_from_slice(&(index as u16).to_le_bytes());
+    ndp[10..12].copy_from_slice(&(datagram.len() as u16).to_le_bytes());
+    ndp[12..16].copy_from_slice(&[0; 4]); // End of the table
+
+    ntb[index..index + datagram.len()].copy_from_slice(datagram);
+    for b in ntb[index + datagram.len()..block_len].iter_mut() {
+        *b = 0;
+    }
+    Ok(block_len)
+}
+
+/// The control transfer in progress that we answer ourselves.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum CtrlState {
+    Idle,
+    GetNtbParameters(usize),
+    GetNtbFormat,
+    GetNtbInputSize(usize),
+    SetNtbInputSize,
+    GetInterface(u8),
+}
+
+pub struct CdcNcm<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// Packet buffers of the endpoints.
+    notify_buffer: Buffer64,
+    in_buffer: Buffer64,
+    out_buffer: Buffer64,
+
+    client: OptionalCell<&'a dyn EthernetAdapterClient>,
+
+    ctrl_state: Cell<CtrlState>,
+    /// Whether the host selected the alternate setting with the endpoints.
+    connected: Cell<bool>,
+    /// The largest NTB the host accepts.
+    ntb_input_size: Cell<usize>,
+
+    /// The number of notifications left to send after a connection, and
+    /// whether one was handed to the controller.
+    notifications: Cell<usize>,
+    notification_in_flight: Cell<bool>,
+
+    /// The frame of the client being sent, and the NTB that carries it.
+    tx_frame: TakeCell<'static, [u8]>,
+    tx_ntb: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+    /// The length of the packet handed to the controller, if any.
+    tx_in_flight: Cell<usize>,
+    tx_sequence: Cell<u16>,
+
+    /// The NTB being received, and whether it is dropped because it does not
+    /// fit in the buffer.
+    rx_ntb: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_overflow: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CdcNcm<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 4],
+        tx_ntb: &'static mut [u8],
+        rx_ntb: &'static mut [u8],
+    ) -> Self {
+        // The functional descriptors go between the communication interface
+        // and its endpoint, and the data interface has two alternate
+        // settings, so the configuration is assembled here rather than by
+        // `create_descriptor_buffers()`.
+        let (device_descriptor_buffer, mut other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    class: 0x2, // Class: CDC
+                    max_packet_size_ep0: MAX_CTRL_PACKET_SIZE,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                &mut [],
+                &[],
+                None,
+                None,
+            );
+        other_descriptor_buffer.buf[4].set(2); // Number of interfaces
+
+        other_descriptor_buffer.append(&InterfaceDescriptor {
+            interface_number: 0,
+            num_endpoints: 1,
+            interface_class: 0x02,    // CDC communication
+            interface_subclass: 0x0d, // network control model (NCM)
+            interface_protocol: 0x00, // none
+            ..InterfaceDescriptor::default()
+        });
+        other_descriptor_buffer.append(&CdcInterfaceDescriptor {
+            subtype: CdcInterfaceDescriptorSubType::Header,
+            field1: 0x10, // CDC
+            field2: 0x01, // CDC 1.10
+        });
+        other_descriptor_buffer.append(&CdcInterfaceDescriptor {
+            subtype: CdcInterfaceDescriptorSubType::Union,
+            field1: 0x00, // Interface 0
+            field2: 0x01, // Interface 1
+        });
+        other_descriptor_buffer.append(&CdcEthernetNetworkingDescriptor {
+            mac_address_string: 4,
+            statistics: 0,
+            max_segment_size: MAX_SEGMENT_SIZE as u16,
+            num_multicast_filters: 0,
+            num_power_filters: 0,
+        });
+        other_descriptor_buffer.append(&CdcNcmDescriptor {
+            ncm_version: 0x0100,
+            network_capabilities: 0,
+        });
+        other_descriptor_buffer.append(&EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_NOTIFY_NUM,
+                TransferDirection::DeviceToHost,
+            ),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: 16,
+            interval: 16,
+        });
+
+        // Without endpoints, the link is down.
+        other_descriptor_buffer.append(&InterfaceDescriptor {
+            interface_number: 1,
+            alternate_setting: 0,
+            interface_class: 0x0a,    // CDC data
+            interface_subclass: 0x00, // none
+            interface_protocol: 0x01, // NTB
+            ..InterfaceDescriptor::default()
+        });
+        other_descriptor_buffer.append(&InterfaceDescriptor {
+            interface_number: 1,
+            alternate_setting: 1,
+            num_endpoints: 2,
+            interface_class: 0x0a,    // CDC data
+            interface_subclass: 0x00, // none
+            interface_protocol: 0x01, // NTB
+            ..InterfaceDescriptor::default()
+        });
+        other_descriptor_buffer.append(&EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_IN_NUM,
+                TransferDirection::DeviceToHost,
+            ),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: MAX_PACKET_SIZE as u16,
+            interval: 0,
+        });
+        other_descriptor_buffer.append(&EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_OUT_NUM,
+                TransferDirection::HostToDevice,
+            ),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: MAX_PACKET_SIZE as u16,
+            interval: 0,
+        });
+
+        CdcNcm {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            notify_buffer: Buffer64::default(),
+            in_buffer: Buffer64::default(),
+            out_buffer: Buffer64::default(),
+            client: OptionalCell::empty(),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            connected: Cell::new(false),
+            ntb_input_size: Cell::new(tx_ntb.len()),
+            notifications: Cell::new(0),
+            notification_in_flight: Cell::new(false),
+            tx_frame: TakeCell::empty(),
+            tx_ntb: TakeCell::new(tx_ntb),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+            tx_in_flight: Cell::new(0),
+            tx_sequence: Cell::new(0),
+            rx_ntb: TakeCell::new(rx_ntb),
+            rx_len: Cell::new(0),
+            rx_overflow: Cell::new(false),
+        }
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    /// Brings the link up, and tells the host about it.
+    fn connect(&self) {
+        self.connected.set(true);
+        self.notifications.set(2);
+        self.controller().endpoint_resume_in(ENDPOINT_NOTIFY_NUM);
+    }
+
+    /// Brings the link down, and fails the frame being sent.
+    fn disconnect(&self) {
+        self.connected.set(false);
+        self.notifications.set(0);
+        self.notification_in_flight.set(false);
+        self.rx_len.set(0);
+        self.rx_overflow.set(false);
+        self.tx_in_flight.set(0);
+        self.tx_frame.take().map(|frame| {
+            self.client
+                .map(move |client| client.tx_done(Err(ErrorCode::OFF), frame));
+        });
+    }
+
+    /// Writes the next notification into the packet buffer, and returns its
+    /// length.
+    fn write_notification(&self) -> usize {
+        let buf = &self.notify_buffer.buf;
+        // Request type: class request of an interface, to the host.
+        buf[0].set(0xa1);
+        buf[2].set(0);
+        buf[3].set(0);
+        buf[4].set(0); // Interface 0
+        buf[5].set(0);
+        if self.notifications.get() == 2 {
+            buf[1].set(CONNECTION_SPEED_CHANGE);
+            buf[6].set(8);
+            buf[7].set(0);
+            // The same bit rate downstream and upstream.
+            let rate = BIT_RATE.to_le_bytes();
+            for i in 0..8 {
+                buf[8 + i].set(rate[i % 4]);
+            }
+            16
+        } else {
+            buf[1].set(NETWORK_CONNECTION);
+            buf[2].set(1); // Connected
+            buf[6].set(0);
+            buf[7].set(0);
+            8
+        }
+    }
+
+    /// Handles SET_INTERFACE and GET_INTERFACE, which select the link state.
+    fn interface_request(
+        &'a self,
+        endpoint: usize,
+        setup_data: descriptors::SetupData,
+    ) -> hil::usb::CtrlSetupResult {
+        let interface = setup_data.index;
+        match setup_data.get_standard_request() {
+            Some(StandardRequest::SetInterface) => match (interface, setup_data.value) {
+                (DATA_INTERFACE, 0) => {
+                    self.disconnect();
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                (DATA_INTERFACE, 1) => {
+                    self.disconnect();
+                    self.connect();
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                (0, 0) => hil::usb::CtrlSetupResult::Ok,
+                _ => hil::usb::CtrlSetupResult::ErrGeneric,
+            },
+            Some(StandardRequest::GetInterface { .. }) => {
+                let alternate_setting = interface == DATA_INTERFACE && self.connected.get();
+                self.ctrl_state
+                    .set(CtrlState::GetInterface(alternate_setting as u8));
+                hil::usb::CtrlSetupResult::Ok
+            }
+            _ => self.client_ctrl.ctrl_setup(endpoint),
+        }
+    }
+
+    /// Handles a CDC-NCM class request.
+    fn class_request(
+        &'a self,
+        endpoint: usize,
+        setup_data: descriptors::SetupData,
+    ) -> hil::usb::CtrlSetupResult {
+        let length = setup_data.length as usize;
+        match setup_data.request_code {
+            GET_NTB_PARAMETERS => {
+                self.ctrl_state.set(CtrlState::GetNtbParameters(length));
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            GET_NTB_FORMAT => {
+                self.ctrl_state.set(CtrlState::GetNtbFormat);
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            GET_NTB_INPUT_SIZE => {
+                self.ctrl_state.set(CtrlState::GetNtbInputSize(length));
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            SET_NTB_INPUT_SIZE => {
+                if length < 4 || length > MAX_CTRL_PACKET_SIZE as usize {
+                    return hil::usb::CtrlSetupResult::ErrGeneric;
+                }
+                self.ctrl_state.set(CtrlState::SetNtbInputSize);
+            }
+            SET_NTB_FORMAT => {
+                // Only NTBs with 16-bit headers are supported.
+                if setup_data.value != 0 {
+                    return hil::usb::CtrlSetupResult::ErrGeneric;
+                }
+            }
+            SET_ETHERNET_PACKET_FILTER => {
+                // Received frames are filtered by the client.
+            }
+            _ => return hil::usb::CtrlSetupResult::ErrGeneric,
+        }
+        // Let `ClientCtrl` accept the request, and its data.
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CdcNcm<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for notifications and for the data transfers.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_NOTIFY_NUM, &self.notify_buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Interrupt, ENDPOINT_NOTIFY_NUM);
+
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, &self.in_buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, &self.out_buffer.buf);
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.disconnect();
+        self.ntb_input_size
+            .set(self.tx_ntb.map_or(NTB_BUF_LEN, |ntb| ntb.len()));
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// `ClientCtrl` answers class requests from the device with junk, and
+    /// rejects requests to select an alternate setting, so we answer these
+    /// ourselves.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = match descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return self.client_ctrl.ctrl_setup(endpoint),
+        };
+        match (
+            setup_data.request_type.request_type(),
+            setup_data.request_type.recipient(),
+        ) {
+            (RequestType::Class, Recipient::Interface) => self.class_request(endpoint, setup_data),
+            (RequestType::Standard, Recipient::Interface) => {
+                self.interface_request(endpoint, setup_data)
+            }
+            _ => self.client_ctrl.ctrl_setup(endpoint),
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        let buf = &self.client_ctrl.ctrl_buffer.buf;
+        match self.ctrl_state.get() {
+            CtrlState::GetNtbParameters(requested_length) => {
+                let out_max_size = self.rx_ntb.map_or(0, |ntb| ntb.len()) as u32;
+                let in_max_size = self.tx_ntb.map_or(0, |ntb| ntb.len()) as u32;
+                let mut parameters = [0; 28];
+                parameters[0..2].copy_from_slice(&28u16.to_le_bytes());
+                parameters[2..4].copy_from_slice(&1u16.to_le_bytes()); // 16-bit NTBs
+                parameters[4..8].copy_from_slice(&in_max_size.to_le_bytes());
+                parameters[8..10].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes()); // Divisor
+                parameters[12..14].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes());
+                parameters[16..20].copy_from_slice(&out_max_size.to_le_bytes());
+                parameters[20..22].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes()); // Divisor
+                parameters[24..26].copy_from_slice(&NTB_ALIGNMENT.to_le_bytes());
+                // Any number of datagrams per NTB.
+                let len = cmp::min(parameters.len(), requested_length);
+                for (i, b) in parameters[..len].iter().enumerate() {
+                    buf[i].set(*b);
+                }
+                hil::usb::CtrlInResult::Packet(len, true)
+            }
+            CtrlState::GetNtbFormat => {
+                buf[0].set(0); // 16-bit NTBs
+                buf[1].set(0);
+                hil::usb::CtrlInResult::Packet(2, true)
+            }
+            CtrlState::GetNtbInputSize(requested_length) => {
+                let size = (self.ntb_input_size.get() as u32).to_le_bytes();
+                let len = cmp::min(size.len(), requested_length);
+                for (i, b) in size[..len].iter().enumerate() {
+                    buf[i].set(*b);
+                }
+                hil::usb::CtrlInResult::Packet(len, true)
+            }
+            CtrlState::GetInterface(alternate_setting) => {
+                buf[0].set(alternate_setting);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            _ => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if self.ctrl_state.get() == CtrlState::SetNtbInputSize && packet_bytes >= 4 {
+            let buf = &self.client_ctrl.ctrl_buffer.buf;
+            let size = u32::from_le_bytes([buf[0].get(), buf[1].get(), buf[2].get(), buf[3].get()]);
+            self.ntb_input_size.set(size as usize);
+        }
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction.
+    fn packet_in(&'a self, transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Interrupt => {
+                if self.notification_in_flight.get() || self.notifications.get() == 0 {
+                    return hil::usb::InResult::Delay;
+                }
+                self.notification_in_flight.set(true);
+                hil::usb::InResult::Packet(self.write_notification())
+            }
+            TransferType::Bulk => {
+                if self.tx_in_flight.get() > 0 || self.tx_frame.is_none() {
+                    return hil::usb::InResult::Delay;
+                }
+                self.tx_ntb.map_or(hil::usb::InResult::Delay, |ntb| {
+                    let offset = self.tx_offset.get();
+                    let len = cmp::min(MAX_PACKET_SIZE, self.tx_len.get() - offset);
+                    for (i, b) in ntb[offset..offset + len].iter().enumerate() {
+                        self.in_buffer.buf[i].set(*b);
+                    }
+                    self.tx_in_flight.set(len);
+                    hil::usb::InResult::Packet(len)
+                })
+            }
+            TransferType::Control | TransferType::Isochronous => hil::usb::InResult::Error,
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction.
+    ///
+    /// An NTB ends with a short packet, or once its block length is
+    /// received.
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        _endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match transfer_type {
+            TransferType::Bulk => {
+                let packet_len = packet_bytes as usize;
+                self.rx_ntb.map(|ntb| {
+                    let start = self.rx_len.get();
+                    if self.rx_overflow.get() || start + packet_len > ntb.len() {
+                        self.rx_overflow.set(true);
+                    } else {
+                        for (b, cell) in ntb[start..start + packet_len]
+                            .iter_mut()
+                            .zip(self.out_buffer.buf.iter())
+                        {
+                            *b = cell.get();
+                        }
+                        self.rx_len.set(start + packet_len);
+                    }
+
+                    let len = self.rx_len.get();
+                    let complete = packet_len < MAX_PACKET_SIZE
+                        || (len >= NTH16_LEN && len >= get_u16(ntb, 8));
+                    if complete {
+                        if !self.rx_overflow.get() && len > 0 {
+                            self.client.map(|client| {
+                                // A malformed NTB is dropped.
+                                let _ = parse_ntb(&ntb[..len], |frame| client.rx_frame(frame));
+                            });
+                        }
+                        self.rx_len.set(0);
+                        self.rx_overflow.set(false);
+                    }
+                });
+                hil::usb::OutResult::Ok
+            }
+            TransferType::Control | TransferType::Isochronous | TransferType::Interrupt => {
+                hil::usb::OutResult::Error
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if endpoint == ENDPOINT_NOTIFY_NUM {
+            self.notification_in_flight.set(false);
+            let left = self.notifications.get().saturating_sub(1);
+            self.notifications.set(left);
+            if left > 0 {
+                self.controller().endpoint_resume_in(ENDPOINT_NOTIFY_NUM);
+            }
+            return;
+        }
+
+        let offset = self.tx_offset.get() + self.tx_in_flight.get();
+        self.tx_offset.set(offset);
+        self.tx_in_flight.set(0);
+        if offset < self.tx_len.get() {
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        } else {
+            self.tx_frame.take().map(|frame| {
+                self.client.map(move |client| client.tx_done(Ok(()), frame));
+            });
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> EthernetAdapter<'a> for CdcNcm<'a, U> {
+    fn set_client(&self, client: &'a dyn EthernetAdapterClient) {
+        self.client.set(client);
+    }
+
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.connected.get() {
+            return Err((ErrorCode::OFF, frame));
+        }
+        if self.tx_frame.is_some() {
+            return Err((ErrorCode::BUSY, frame));
+        }
+        if len > frame.len() || len > MAX_SEGMENT_SIZE {
+            return Err((ErrorCode::SIZE, frame));
+        }
+
+        let sequence = self.tx_sequence.get();
+        let ntb_len = self.tx_ntb.map_or(Err(ErrorCode::BUSY), |ntb| {
+            let max_len = cmp::min(ntb.len(), self.ntb_input_size.get());
+            write_ntb(&mut ntb[..max_len], sequence, &frame[..len])
+        });
+        match ntb_len {
+            Ok(ntb_len) => {
+                self.tx_sequence.set(sequence.wrapping_add(1));
+                self.tx_len.set(ntb_len);
+                self.tx_offset.set(0);
+                self.tx_frame.replace(frame);
+                self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                Ok(())
+            }
+            Err(e) => Err((e, frame)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ntbs_round_trip() {
+        let mut ntb = [0; 256];
+        let frame = [0x5a; 60];
+        let len = write_ntb(&mut ntb, 7, &frame).unwrap();
+        assert_eq!(len, NTH16_LEN + NDP16_LEN + frame.len());
+        let mut frames = 0;
+        assert_eq!(
+            parse_ntb(&ntb[..len], |datagram| {
+                assert_eq!(datagram, &frame[..]);
+                frames += 1;
+            }),
+            Ok(())
+        );
+        assert_eq!(frames, 1);
+
+        // An NTB of a whole number of packets is padded.
+        let len = write_ntb(&mut ntb, 8, &frame[..36]).unwrap();
+        assert_eq!(len, MAX_PACKET_SIZE + 1);
+        assert_eq!(get_u16(&ntb, 8), len);
+
+        // The frame does not fit.
+        assert_eq!(write_ntb(&mut ntb[..80], 9, &frame), Err(ErrorCode::SIZE));
+
+        // A chain of two tables, with two datagrams in the second one.
+        let mut ntb = [0; 128];
+        ntb[..NTH16_LEN].copy_from_slice(&[
+            0x4e, 0x43, 0x4d, 0x48, 12, 0, 0, 0, 128, 0, 12, 0, //
+        ]);
+        ntb[12..28].copy_from_slice(&[
+            0x4e, 0x43, 0x4d, 0x30, 16, 0, 28, 0, 64, 0, 4, 0, 0, 0, 0, 0, //
+        ]);
+        ntb[28..48].copy_from_slice(&[
+            0x4e, 0x43, 0x4d, 0x30, 20, 0, 0, 0, 68, 0, 2, 0, 72, 0, 3, 0, 0, 0, 0, 0, //
+        ]);
+        let mut lens = [0; 3];
+        let mut frames = 0;
+        assert_eq!(
+            parse_ntb(&ntb, |datagram| {
+                lens[frames] = datagram.len();
+                frames += 1;
+            }),
+            Ok(())
+        );
+        assert_eq!(lens, [4, 2, 3]);
+
+        // A table out of the block is rejected.
+        ntb[10] = 126;
+        assert_eq!(parse_ntb(&ntb, |_| {}), Err(ErrorCode::INVAL));
+
+        // A block shorter than its header is rejected.
+        ntb[8] = 5;
+        assert_eq!(parse_ntb(&ntb, |_| {}), Err(ErrorCode::INVAL));
+    }
+}
@@ -0,0 +1,820 @@
+//! Composite USB device combining several USB classes.
+//!
+//! A class client such as `CdcAcm` or `CtapHid` describes a whole USB device
+//! on its own, so a board with a single USB controller could only offer one
+//! of them. `CompositeDevice` is the client of the USB controller instead, and
+//! each class client is given a `CompositeFunction`, which it uses as its USB
+//! controller:
+//!
+//! ```text
+//!     CdcAcm     CtapHid    MassStorage
+//!        |          |            |
+//!   CompositeFunction (one for each class)
+//!                   |
+//!            CompositeDevice
+//!                   |
+//!             UsbController
+//! ```
+//!
+//! `CompositeDevice` answers the standard device requests itself. It builds
+//! the configuration from the descriptors of all functions, numbering their
+//! interfaces one after the other and giving each endpoint of a function its
+//! own endpoint of the controller. Functions with several interfaces are
+//! grouped by an Interface Association Descriptor, so that the host binds
+//! them to a single driver. Class, vendor and interface requests are
+//! forwarded to the function owning the interface or endpoint they are
+//! addressed to, and the packets of each endpoint go to its function.
+//!
+//! The class clients are unchanged and keep their own endpoint numbers, which
+//! are mapped to free endpoints of the controller in the order they are used.
+//! The configuration of all functions must fit in `CONFIGURATION_BUFLEN`
+//! bytes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let composite = static_init!(
+//!     CompositeDevice<'static, nrf52::usbd::Usbd>,
+//!     CompositeDevice::new(&nrf52::usbd::USBD, 64, 0x1915, 0x521f, STRINGS)
+//! );
+//! nrf52::usbd::USBD.set_client(composite);
+//! let cdc_function = static_init!(
+//!     CompositeFunction<'static, nrf52::usbd::Usbd>,
+//!     CompositeFunction::new(composite)
+//! );
+//! cdc_function.setup();
+//! // Create the class clients with their function as the USB controller.
+//! composite.enable();
+//! composite.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::ConfigurationDescriptor;
+use super::descriptors::Descriptor;
+use super::descriptors::DescriptorType;
+use super::descriptors::DeviceDescriptor;
+use super::descriptors::InterfaceAssociationDescriptor;
+use super::descriptors::LanguagesDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::SetupData;
+use super::descriptors::StandardRequest;
+use super::descriptors::StringDescriptor;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::VolatileCell;
+
+/// Space for the configuration descriptor with the descriptors of all
+/// functions.
+pub const CONFIGURATION_BUFLEN: usize = 256;
+
+/// Number of endpoints of the controller, including the control endpoint.
+const N_ENDPOINTS: usize = 8;
+
+/// Length of an Interface Association Descriptor.
+const IAD_LEN: usize = 8;
+
+/// Highest endpoint number a class client may use.
+const MAX_FUNCTION_ENDPOINT: usize = 15;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+const EMPTY: Cell<u8> = Cell::new(0);
+
+/// Standard request to get the configuration of a function, so that it can
+/// be made part of the composite configuration.
+const GET_CONFIGURATION_DESCRIPTOR: [u8; 8] = [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xff, 0xff];
+
+/// States of the control endpoint.
+#[derive(Copy, Clone)]
+enum State {
+    Init,
+
+    /// We are doing a Control In transfer of some data in
+    /// self.descriptor_storage, with the given extent remaining to send.
+    CtrlIn(usize, usize),
+
+    SetAddress,
+
+    /// The current transfer is handled by a function.
+    Function,
+}
+
+/// A USB device made of several class clients.
+pub struct CompositeDevice<'a, U: 'a> {
+    /// The USB hardware controller.
+    controller: &'a U,
+
+    /// State of the control endpoint.
+    state: Cell<State>,
+
+    /// A 64-byte buffer for the control endpoint to be passed to the USB
+    /// driver.
+    ctrl_buffer: Buffer64,
+
+    /// Storage for composing responses to device descriptor requests.
+    descriptor_storage: [Cell<u8>; CONFIGURATION_BUFLEN],
+
+    /// The functions of the device, in the order of their interfaces.
+    functions: List<'a, CompositeFunction<'a, U>>,
+
+    /// The function handling the current control transfer.
+    ctrl_function: OptionalCell<&'a CompositeFunction<'a, U>>,
+
+    /// Next endpoint of the controller to give to a function.
+    next_endpoint: Cell<usize>,
+
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+
+    /// USB strings to provide human readable descriptions of the device.
+    strings: &'static [&'static str; 3],
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CompositeDevice<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> Self {
+        Self {
+            controller,
+            state: Cell::new(State::Init),
+            ctrl_buffer: Buffer64::default(),
+            descriptor_storage: [EMPTY; CONFIGURATION_BUFLEN],
+            functions: List::new(),
+            ctrl_function: OptionalCell::empty(),
+            next_endpoint: Cell::new(1),
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+
+    #[inline]
+    pub fn controller(&self) -> &'a U {
+        self.controller
+    }
+
+    /// Gives the next free endpoint of the controller to a function.
+    fn allocate_endpoint(&self) -> Option<usize> {
+        let endpoint = self.next_endpoint.get();
+        if endpoint < N_ENDPOINTS {
+            self.next_endpoint.set(endpoint + 1);
+            Some(endpoint)
+        } else {
+            None
+        }
+    }
+
+    /// Finds the function using an endpoint of the controller, and the
+    /// endpoint number the function knows it by.
+    fn function_for_endpoint(
+        &self,
+        endpoint: usize,
+    ) -> Option<(&'a CompositeFunction<'a, U>, usize)> {
+        self.functions
+            .iter()
+            .find_map(|function| function.local_endpoint(endpoint).map(|ep| (function, ep)))
+    }
+
+    /// Writes the configuration descriptor of the composite device to the
+    /// descriptor storage, and returns its length.
+    fn write_configuration(&'a self) -> usize {
+        let buf = &self.descriptor_storage;
+        let mut len = ConfigurationDescriptor::default().size();
+        let mut interfaces = 0;
+
+        for function in self.functions.iter() {
+            function.first_interface.set(interfaces);
+            let fetched = function.fetch_configuration(&buf[len..]);
+            if fetched < 9 {
+                // No room for the descriptors of this function.
+                function.num_interfaces.set(0);
+                continue;
+            }
+            let count = buf[len + 4].get();
+            let body_len = fetched - 9;
+
+            // Replace the configuration descriptor of the function by an
+            // Interface Association Descriptor if it has several interfaces.
+            let header_len = if count > 1 { IAD_LEN } else { 0 };
+            for i in 0..body_len {
+                buf[len + header_len + i].set(buf[len + 9 + i].get());
+            }
+            let body = &buf[len + header_len..len + header_len + body_len];
+            relocate_descriptors(body, interfaces, |endpoint| {
+                function.controller_endpoint(endpoint).unwrap_or(0)
+            });
+            if count > 1 {
+                // The function takes the class of its first interface.
+                InterfaceAssociationDescriptor {
+                    first_interface: interfaces,
+                    interface_count: count,
+                    function_class: body[5].get(),
+                    function_subclass: body[6].get(),
+                    function_protocol: body[7].get(),
+                    string_index: 0,
+                }
+                .write_to(&buf[len..]);
+            }
+
+            len += header_len + body_len;
+            function.num_interfaces.set(count);
+            interfaces += count;
+        }
+
+        ConfigurationDescriptor {
+            num_interfaces: interfaces,
+            related_descriptor_length: len - 9,
+            ..ConfigurationDescriptor::default()
+        }
+        .write_to(buf);
+        len
+    }
+
+    fn handle_standard_device_request(
+        &'a self,
+        request: StandardRequest,
+    ) -> hil::usb::CtrlSetupResult {
+        match request {
+            StandardRequest::GetDescriptor {
+                descriptor_type,
+                descriptor_index,
+                lang_id,
+                requested_length,
+            } => {
+                let buf = &self.descriptor_storage;
+                let len = match descriptor_type {
+                    DescriptorType::Device => match descriptor_index {
+                        0 => DeviceDescriptor {
+                            vendor_id: self.vendor_id,
+                            product_id: self.product_id,
+                            manufacturer_string: 1,
+                            product_string: 2,
+                            serial_number_string: 3,
+                            // Functions are described by Interface
+                            // Association Descriptors
+                            class: 0xef,
+                            subclass: 0x02,
+                            protocol: 0x01,
+                            max_packet_size_ep0: self.max_ctrl_packet_size,
+                            ..DeviceDescriptor::default()
+                        }
+                        .write_to(buf),
+                        _ => return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex,
+                    },
+                    DescriptorType::Configuration => match descriptor_index {
+                        0 => self.write_configuration(),
+                        _ => return hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex,
+                    },
+                    DescriptorType::String => match descriptor_index {
+                        0 => LanguagesDescriptor { langs: LANGUAGES }.write_to(buf),
+                        i if i > 0
+                            && (i as usize) <= self.strings.len()
+                            && lang_id == LANGUAGES[0] =>
+                        {
+                            StringDescriptor {
+                                string: self.strings[i as usize - 1],
+                            }
+                            .write_to(buf)
+                        }
+                        _ => return hil::usb::CtrlSetupResult::ErrInvalidStringIndex,
+                    },
+                    DescriptorType::DeviceQualifier => {
+                        // We are full-speed only, so we must
+                        // respond with a request error
+                        return hil::usb::CtrlSetupResult::ErrNoDeviceQualifier;
+                    }
+                    _ => return hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType,
+                };
+                let end = min(len, requested_length as usize);
+                self.state.set(State::CtrlIn(0, end));
+                hil::usb::CtrlSetupResult::Ok
+            }
+            StandardRequest::SetAddress { device_address } => {
+                // Load the address we've been assigned ...
+                self.controller.set_address(device_address);
+
+                // ... and when this request gets to the Status stage we will actually enable the
+                // address.
+                self.state.set(State::SetAddress);
+                hil::usb::CtrlSetupResult::OkSetAddress
+            }
+            StandardRequest::SetConfiguration { .. } => {
+                // We have been assigned a particular configuration: fine!
+                hil::usb::CtrlSetupResult::Ok
+            }
+            _ => hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType,
+        }
+    }
+
+    /// Passes a request for an interface or an endpoint to the function
+    /// owning it.
+    fn forward_request(&'a self, setup_data: SetupData) -> hil::usb::CtrlSetupResult {
+        let index = setup_data.index;
+        let target = match setup_data.request_type.recipient() {
+            Recipient::Interface => self.functions.iter().find_map(|function| {
+                function
+                    .local_interface(index as u8)
+                    .map(|interface| (function, (index & 0xff00) | interface as u16))
+            }),
+            Recipient::Endpoint => self
+                .function_for_endpoint(index as usize & 0x0f)
+                .map(|(function, endpoint)| (function, (index & !0x0f) | endpoint as u16)),
+            _ => None,
+        };
+
+        target.map_or(
+            hil::usb::CtrlSetupResult::ErrGeneric,
+            |(function, local_index)| {
+                self.ctrl_function.set(function);
+                self.state.set(State::Function);
+                function.ctrl_setup(&self.ctrl_buffer.buf, local_index)
+            },
+        )
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CompositeDevice<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.controller
+            .endpoint_set_ctrl_buffer(&self.ctrl_buffer.buf);
+        self.controller
+            .enable_as_device(hil::usb::DeviceSpeed::Full); // must be Full for Bulk transfers
+        self.controller
+            .endpoint_out_enable(TransferType::Control, 0);
+
+        for function in self.functions.iter() {
+            function.client.map(|client| client.enable());
+        }
+
+        // Number the interfaces and give endpoints to the functions now, so
+        // that they don't change during enumeration.
+        self.write_configuration();
+    }
+
+    fn attach(&'a self) {
+        for function in self.functions.iter() {
+            function.client.map(|client| client.attach());
+        }
+        self.controller.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.state.set(State::Init);
+        self.ctrl_function.clear();
+        for function in self.functions.iter() {
+            function.client.map(|client| client.bus_reset());
+        }
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if endpoint != 0 {
+            // For now we only support the default Control endpoint
+            return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        self.state.set(State::Init);
+        self.ctrl_function.clear();
+
+        SetupData::get(&self.ctrl_buffer.buf).map_or(
+            hil::usb::CtrlSetupResult::ErrNoParse,
+            |setup_data| match (
+                setup_data.request_type.recipient(),
+                setup_data.get_standard_request(),
+            ) {
+                (Recipient::Device, Some(request)) => self.handle_standard_device_request(request),
+                _ => self.forward_request(setup_data),
+            },
+        )
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.state.get() {
+            State::CtrlIn(start, end) => {
+                let len = end.saturating_sub(start);
+                if len > 0 {
+                    let packet_bytes = min(self.ctrl_buffer.buf.len(), len);
+                    let packet = &self.descriptor_storage[start..start + packet_bytes];
+                    let buf = &self.ctrl_buffer.buf;
+
+                    // Copy a packet into the endpoint buffer
+                    for (i, b) in packet.iter().enumerate() {
+                        buf[i].set(b.get());
+                    }
+
+                    let start = start + packet_bytes;
+                    let len = end.saturating_sub(start);
+                    let transfer_complete = len == 0;
+
+                    self.state.set(State::CtrlIn(start, end));
+
+                    hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete)
+                } else {
+                    hil::usb::CtrlInResult::Packet(0, true)
+                }
+            }
+            State::Function => self
+                .ctrl_function
+                .map_or(hil::usb::CtrlInResult::Error, |f| {
+                    f.ctrl_in(&self.ctrl_buffer.buf, endpoint)
+                }),
+            _ => hil::usb::CtrlInResult::Error,
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        match self.state.get() {
+            State::Function => self
+                .ctrl_function
+                .map_or(hil::usb::CtrlOutResult::Halted, |f| {
+                    f.ctrl_out(&self.ctrl_buffer.buf, endpoint, packet_bytes)
+                }),
+            _ => {
+                // Bad state
+                hil::usb::CtrlOutResult::Halted
+            }
+        }
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.ctrl_function
+            .map(|f| f.client.map(|client| client.ctrl_status(endpoint)));
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        match self.state.get() {
+            State::SetAddress => {
+                self.controller.enable_address();
+            }
+            _ => {}
+        };
+        self.ctrl_function
+            .take()
+            .map(|f| f.client.map(|client| client.ctrl_status_complete(endpoint)));
+        self.state.set(State::Init);
+    }
+
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        self.function_for_endpoint(endpoint)
+            .and_then(|(function, endpoint)| {
+                function
+                    .client
+                    .map(|client| client.packet_in(transfer_type, endpoint))
+            })
+            .unwrap_or(hil::usb::InResult::Delay)
+    }
+
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        self.function_for_endpoint(endpoint)
+            .and_then(|(function, endpoint)| {
+                function
+                    .client
+                    .map(|client| client.packet_out(transfer_type, endpoint, packet_bytes))
+            })
+            .unwrap_or(hil::usb::OutResult::Error)
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        self.function_for_endpoint(endpoint)
+            .map(|(function, endpoint)| {
+                function
+                    .client
+                    .map(|client| client.packet_transmitted(endpoint))
+            });
+    }
+}
+
+/// One class client of a `CompositeDevice`, which it sees as its USB
+/// controller.
+pub struct CompositeFunction<'a, U: 'a> {
+    device: &'a CompositeDevice<'a, U>,
+    client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
+
+    /// The control endpoint buffer of the class client.
+    ctrl_buffer: OptionalCell<&'a [VolatileCell<u8>]>,
+
+    /// Endpoint of the controller for each endpoint number of the class
+    /// client, or 0 if it has none.
+    endpoints: [Cell<u8>; MAX_FUNCTION_ENDPOINT + 1],
+
+    /// Number of the first interface of the function in the composite device.
+    first_interface: Cell<u8>,
+    num_interfaces: Cell<u8>,
+
+    next: ListLink<'a, CompositeFunction<'a, U>>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CompositeFunction<'a, U> {
+    pub fn new(device: &'a CompositeDevice<'a, U>) -> Self {
+        Self {
+            device,
+            client: OptionalCell::empty(),
+            ctrl_buffer: OptionalCell::empty(),
+            endpoints: [EMPTY; MAX_FUNCTION_ENDPOINT + 1],
+            first_interface: Cell::new(0),
+            num_interfaces: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`. The interfaces of the
+    /// functions are numbered in the order of this call.
+    pub fn setup(&'a self) {
+        self.device.functions.push_tail(self);
+    }
+
+    /// Returns the endpoint of the controller for an endpoint of the class
+    /// client, giving it one if needed.
+    fn controller_endpoint(&self, endpoint: usize) -> Option<usize> {
+        let mapped = self.endpoints.get(endpoint)?;
+        if endpoint == 0 {
+            return None;
+        }
+        match mapped.get() {
+            0 => self.device.allocate_endpoint().map(|e| {
+                mapped.set(e as u8);
+                e
+            }),
+            e => Some(e as usize),
+        }
+    }
+
+    /// Returns the endpoint number of the class client for an endpoint of the
+    /// controller.
+    fn local_endpoint(&self, endpoint: usize) -> Option<usize> {
+        if endpoint == 0 {
+            return None;
+        }
+        self.endpoints
+            .iter()
+            .position(|mapped| mapped.get() as usize == endpoint)
+    }
+
+    /// Returns the interface number of the class client for an interface of
+    /// the composite device.
+    fn local_interface(&self, interface: u8) -> Option<u8> {
+        let local = interface.checked_sub(self.first_interface.get())?;
+        if local < self.num_interfaces.get() {
+            Some(local)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the configuration descriptor of the class client, with the
+    /// descriptors that follow it, into `buf`. Returns its length, or 0 if it
+    /// does not fit.
+    fn fetch_configuration(&'a self, buf: &[Cell<u8>]) -> usize {
+        let (client, ctrl_buffer) = match (self.client.extract(), self.ctrl_buffer.extract()) {
+            (Some(client), Some(ctrl_buffer)) => (client, ctrl_buffer),
+            _ => return 0,
+        };
+
+        for (i, b) in GET_CONFIGURATION_DESCRIPTOR.iter().enumerate() {
+            ctrl_buffer[i].set(*b);
+        }
+        let mut len = 0;
+        if let hil::usb::CtrlSetupResult::Ok = client.ctrl_setup(0) {
+            loop {
+                match client.ctrl_in(0) {
+                    hil::usb::CtrlInResult::Packet(packet_bytes, complete) => {
+                        if len + packet_bytes > buf.len() {
+                            len = 0;
+                            break;
+                        }
+                        for i in 0..packet_bytes {
+                            buf[len + i].set(ctrl_buffer[i].get());
+                        }
+                        len += packet_bytes;
+                        if complete {
+                            break;
+                        }
+                    }
+                    _ => {
+                        len = 0;
+                        break;
+                    }
+                }
+            }
+        }
+        // Return the class client to its idle state.
+        client.ctrl_status_complete(0);
+        len
+    }
+
+    /// Passes a setup packet to the class client, with the given index.
+    fn ctrl_setup(&'a self, setup: &[VolatileCell<u8>], index: u16) -> hil::usb::CtrlSetupResult {
+        match (self.client.extract(), self.ctrl_buffer.extract()) {
+            (Some(client), Some(ctrl_buffer)) => {
+                for i in 0..4 {
+                    ctrl_buffer[i].set(setup[i].get());
+                }
+                ctrl_buffer[4].set(index as u8);
+                ctrl_buffer[5].set((index >> 8) as u8);
+                for i in 6..8 {
+                    ctrl_buffer[i].set(setup[i].get());
+                }
+                client.ctrl_setup(0)
+            }
+            _ => hil::usb::CtrlSetupResult::ErrGeneric,
+        }
+    }
+
+    /// Gets the next data packet of a control transfer from the class client.
+    fn ctrl_in(&'a self, packet: &[VolatileCell<u8>], endpoint: usize) -> hil::usb::CtrlInResult {
+        match (self.client.extract(), self.ctrl_buffer.extract()) {
+            (Some(client), Some(ctrl_buffer)) => {
+                let result = client.ctrl_in(endpoint);
+                if let hil::usb::CtrlInResult::Packet(packet_bytes, _) = result {
+                    for i in 0..min(packet_bytes, packet.len()) {
+                        packet[i].set(ctrl_buffer[i].get());
+                    }
+                }
+                result
+            }
+            _ => hil::usb::CtrlInResult::Error,
+        }
+    }
+
+    /// Passes a data packet of a control transfer to the class client.
+    fn ctrl_out(
+        &'a self,
+        packet: &[VolatileCell<u8>],
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::CtrlOutResult {
+        match (self.client.extract(), self.ctrl_buffer.extract()) {
+            (Some(client), Some(ctrl_buffer)) => {
+                for i in 0..min(packet_bytes as usize, ctrl_buffer.len()) {
+                    ctrl_buffer[i].set(packet[i].get());
+                }
+                client.ctrl_out(endpoint, packet_bytes)
+            }
+            _ => hil::usb::CtrlOutResult::Halted,
+        }
+    }
+}
+
+impl<'a, U> ListNode<'a, CompositeFunction<'a, U>> for CompositeFunction<'a, U> {
+    fn next(&'a self) -> &'a ListLink<'a, CompositeFunction<'a, U>> {
+        &self.next
+    }
+}
+
+/// The composite device is the only user of the controller, so the requests of
+/// a class client for the device as a whole are dropped, and its endpoints are
+/// mapped to the ones it was given.
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::UsbController<'a> for CompositeFunction<'a, U> {
+    fn set_client(&self, client: &'a dyn hil::usb::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn endpoint_set_ctrl_buffer(&self, buf: &'a [VolatileCell<u8>]) {
+        self.ctrl_buffer.set(buf);
+    }
+
+    fn endpoint_set_in_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.controller_endpoint(endpoint)
+            .map(|e| self.device.controller().endpoint_set_in_buffer(e, buf));
+    }
+
+    fn endpoint_set_out_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.controller_endpoint(endpoint)
+            .map(|e| self.device.controller().endpoint_set_out_buffer(e, buf));
+    }
+
+    fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+
+    fn attach(&self) {}
+
+    fn detach(&self) {}
+
+    fn set_address(&self, _addr: u16) {}
+
+    fn enable_address(&self) {}
+
+    fn endpoint_in_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.controller_endpoint(endpoint).map(|e| {
+            self.device
+                .controller()
+                .endpoint_in_enable(transfer_type, e)
+        });
+    }
+
+    fn endpoint_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.controller_endpoint(endpoint).map(|e| {
+            self.device
+                .controller()
+                .endpoint_out_enable(transfer_type, e)
+        });
+    }
+
+    fn endpoint_in_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.controller_endpoint(endpoint).map(|e| {
+            self.device
+                .controller()
+                .endpoint_in_out_enable(transfer_type, e)
+        });
+    }
+
+    fn endpoint_resume_in(&self, endpoint: usize) {
+        self.controller_endpoint(endpoint)
+            .map(|e| self.device.controller().endpoint_resume_in(e));
+    }
+
+    fn endpoint_resume_out(&self, endpoint: usize) {
+        self.controller_endpoint(endpoint)
+            .map(|e| self.device.controller().endpoint_resume_out(e));
+    }
+}
+
+/// Renumbers the interfaces and endpoints in the descriptors of a function,
+/// which follow its configuration descriptor, for the composite device. The
+/// interfaces of the function start at `first_interface`, and `endpoint` maps
+/// the endpoint numbers of the function to the ones of the controller.
+fn relocate_descriptors<F: Fn(usize) -> usize>(buf: &[Cell<u8>], first_interface: u8, endpoint: F) {
+    let mut i = 0;
+    while i + 2 <= buf.len() {
+        let len = buf[i].get() as usize;
+        if len < 3 || i + len > buf.len() {
+            break;
+        }
+        let d = &buf[i..i + len];
+        let descriptor_type = d[1].get();
+        if descriptor_type == DescriptorType::Interface as u8 {
+            d[2].set(d[2].get() + first_interface);
+        } else if descriptor_type == DescriptorType::Endpoint as u8 {
+            let address = d[2].get();
+            d[2].set((address & 0x80) | endpoint(address as usize & 0x0f) as u8);
+        } else if descriptor_type == DescriptorType::CdcInterface as u8 {
+            // Functional descriptors that refer to interfaces
+            let subtype = d[2].get();
+            if subtype == descriptors::CdcInterfaceDescriptorSubType::CallManagement as u8 {
+                if len >= 5 {
+                    d[4].set(d[4].get() + first_interface);
+                }
+            } else if subtype == descriptors::CdcInterfaceDescriptorSubType::Union as u8 {
+                for b in &d[3..] {
+                    b.set(b.get() + first_interface);
+                }
+            }
+        }
+        i += len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn relocate_cdc_descriptors() {
+        #[rustfmt::skip]
+        let descriptors = [
+            9, 4, 0, 0, 1, 0x02, 0x02, 0x01, 0, // Interface 0
+            5, 0x24, 0x01, 0x00, 0x01,          // Call management, data interface 1
+            5, 0x24, 0x06, 0x00, 0x01,          // Union of interfaces 0 and 1
+            7, 5, 0x84, 3, 8, 0, 16,            // Endpoint 4 IN
+            9, 4, 1, 0, 2, 0x0a, 0, 0, 0,       // Interface 1
+            7, 5, 0x82, 2, 64, 0, 0,            // Endpoint 2 IN
+            7, 5, 0x03, 2, 64, 0, 0,            // Endpoint 3 OUT
+        ];
+        let buf = [EMPTY; 49];
+        for (b, d) in buf.iter().zip(descriptors.iter()) {
+            b.set(*d);
+        }
+
+        relocate_descriptors(&buf, 2, |endpoint| endpoint + 1);
+
+        let relocated = buf.iter().map(|b| b.get());
+        let expected = descriptors.iter().enumerate().map(|(i, d)| match i {
+            2 | 13 | 17 | 18 | 28 => d + 2,
+            21 | 37 | 44 => d + 1,
+            _ => *d,
+        });
+        assert!(relocated.eq(expected));
+    }
+}
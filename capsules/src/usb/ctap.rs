@@ -99,6 +99,8 @@ pub struct CtapHid<'a, U: 'a> {
     recv_offset: Cell<usize>,
 
     saved_endpoint: OptionalCell<usize>,
+    /// Whether the OUT endpoint is paused until a receive buffer is given.
+    delayed: Cell<bool>,
 }
 
 impl<'a, U: hil::usb::UsbController<'a>> CtapHid<'a, U> {
@@ -175,6 +177,7 @@ impl<'a, U: hil::usb::UsbController<'a>> CtapHid<'a, U> {
             recv_len: Cell::new(0),
             recv_offset: Cell::new(0),
             saved_endpoint: OptionalCell::empty(),
+            delayed: Cell::new(false),
         }
     }
 
@@ -231,7 +234,7 @@ impl<'a, U: hil::usb::UsbController<'a>> hil::usb_hid::UsbHid<'a, [u8; 64]> for
                 // Reset the offset
                 self.recv_offset.set(0);
             }
-        } else {
+        } else if self.delayed.take() {
             // If we have nothing to process, accept more data
             self.controller().endpoint_resume_out(ENDPOINT_NUM);
         }
@@ -373,14 +376,21 @@ impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CtapHid<'a, U>
                                 });
                                 // Reset the offset
                                 self.recv_offset.set(0);
-                                // Delay the next packet until we have finished
-                                // processing this packet
-                                hil::usb::OutResult::Delay
+                                if self.recv_buffer.is_some() {
+                                    // The client is ready for the next packet
+                                    hil::usb::OutResult::Ok
+                                } else {
+                                    // Delay the next packet until we have
+                                    // finished processing this packet
+                                    self.delayed.set(true);
+                                    hil::usb::OutResult::Delay
+                                }
                             } else {
                                 // We can't receive data. Record that we have data to send later
                                 // and apply back pressure to USB
                                 self.saved_endpoint.set(endpoint);
                                 self.recv_buffer.replace(buf);
+                                self.delayed.set(true);
                                 hil::usb::OutResult::Delay
                             }
                         } else {
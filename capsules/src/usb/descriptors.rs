@@ -157,6 +157,7 @@ pub enum DescriptorType {
     DeviceQualifier,
     OtherSpeedConfiguration,
     InterfacePower,
+    InterfaceAssociation = 0x0b,
     HID = 0x21,
     Report = 0x22,
     CdcInterface = 0x24,
@@ -172,6 +173,7 @@ fn get_descriptor_type(byte: u8) -> Option<DescriptorType> {
         6 => Some(DescriptorType::DeviceQualifier),
         7 => Some(DescriptorType::OtherSpeedConfiguration),
         8 => Some(DescriptorType::InterfacePower),
+        0x0b => Some(DescriptorType::InterfaceAssociation),
         0x21 => Some(DescriptorType::HID),
         0x22 => Some(DescriptorType::Report),
         0x24 => Some(DescriptorType::CdcInterface),
@@ -407,6 +409,14 @@ impl DescriptorBuffer {
         }
         self.len
     }
+
+    /// Appends a class-specific descriptor that `create_descriptor_buffers()`
+    /// has no parameter for, and updates the total length of the
+    /// configuration.
+    pub fn append(&mut self, descriptor: &dyn Descriptor) {
+        self.len += descriptor.write_to(&self.buf[self.len..]);
+        put_u16(&self.buf[2..4], self.len as u16);
+    }
 }
 
 /// Transform descriptor structs into descriptor buffers that can be
@@ -655,6 +665,60 @@ impl Descriptor for InterfaceDescriptor {
     }
 }
 
+/// Groups the interfaces of one function of a composite device, so that the
+/// host binds them to a single driver, e.g. the two interfaces of CDC-ACM.
+pub struct InterfaceAssociationDescriptor {
+    pub first_interface: u8,
+    pub interface_count: u8,
+    pub function_class: u8,
+    pub function_subclass: u8,
+    pub function_protocol: u8,
+    pub string_index: u8,
+}
+
+impl Descriptor for InterfaceAssociationDescriptor {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(8); // Size of descriptor
+        buf[1].set(DescriptorType::InterfaceAssociation as u8);
+        buf[2].set(self.first_interface);
+        buf[3].set(self.interface_count);
+        buf[4].set(self.function_class);
+        buf[5].set(self.function_subclass);
+        buf[6].set(self.function_protocol);
+        buf[7].set(self.string_index);
+        8
+    }
+}
+
+/// Describes the capabilities of a DFU interface. It follows the interface
+/// descriptors of the interface.
+pub struct DfuFunctionalDescriptor {
+    pub attributes: u8,
+    pub detach_timeout: u16,
+    pub transfer_size: u16,
+    pub dfu_version: u16,
+}
+
+impl Descriptor for DfuFunctionalDescriptor {
+    fn size(&self) -> usize {
+        9
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(9); // Size of descriptor
+        buf[1].set(0x21); // DFU functional, same value as HID
+        buf[2].set(self.attributes);
+        put_u16(&buf[3..5], self.detach_timeout);
+        put_u16(&buf[5..7], self.transfer_size);
+        put_u16(&buf[7..9], self.dfu_version);
+        9
+    }
+}
+
 pub struct EndpointAddress(u8);
 
 impl EndpointAddress {
@@ -860,6 +924,57 @@ impl Descriptor for CdcInterfaceDescriptor {
     }
 }
 
+/// Describes the Ethernet parameters of a CDC-ECM or CDC-NCM
+/// communication interface.
+pub struct CdcEthernetNetworkingDescriptor {
+    /// Index of the string with the MAC address of the host side, as 12
+    /// hexadecimal digits.
+    pub mac_address_string: u8,
+    pub statistics: u32,
+    pub max_segment_size: u16,
+    pub num_multicast_filters: u16,
+    pub num_power_filters: u8,
+}
+
+impl Descriptor for CdcEthernetNetworkingDescriptor {
+    fn size(&self) -> usize {
+        13
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(13); // Size of descriptor
+        buf[1].set(DescriptorType::CdcInterface as u8);
+        buf[2].set(CdcInterfaceDescriptorSubType::EthernetNetworking as u8);
+        buf[3].set(self.mac_address_string);
+        put_u32(&buf[4..8], self.statistics);
+        put_u16(&buf[8..10], self.max_segment_size);
+        put_u16(&buf[10..12], self.num_multicast_filters);
+        buf[12].set(self.num_power_filters);
+        13
+    }
+}
+
+/// Describes the optional features of a CDC-NCM function.
+pub struct CdcNcmDescriptor {
+    pub ncm_version: u16,
+    pub network_capabilities: u8,
+}
+
+impl Descriptor for CdcNcmDescriptor {
+    fn size(&self) -> usize {
+        6
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(6); // Size of descriptor
+        buf[1].set(DescriptorType::CdcInterface as u8);
+        buf[2].set(0x1a); // NCM functional descriptor
+        put_u16(&buf[3..5], self.ncm_version);
+        buf[5].set(self.network_capabilities);
+        6
+    }
+}
+
 /// The data structure sent in a CDC-ACM Set Line Coding message.
 #[derive(Debug, Copy, Clone)]
 pub struct CdcAcmSetLineCodingData {
@@ -949,3 +1064,9 @@ fn put_u16<'a>(buf: &'a [Cell<u8>], n: u16) {
     buf[0].set((n & 0xff) as u8);
     buf[1].set((n >> 8) as u8);
 }
+
+/// Write a `u32` to a buffer for transmission on the bus
+fn put_u32<'a>(buf: &'a [Cell<u8>], n: u32) {
+    put_u16(&buf[0..2], n as u16);
+    put_u16(&buf[2..4], (n >> 16) as u16);
+}
@@ -0,0 +1,886 @@
+//! Device Firmware Upgrade Class for USB
+//!
+//! This capsule implements the USB DFU 1.1 protocol, so that the apps of a
+//! board (and optionally a new kernel) can be updated with the stock
+//! `dfu-util` tool:
+//!
+//! ```text
+//! dfu-util -a 0 -D apps.tbf     # replace the apps
+//! dfu-util -a 0 -U backup.bin   # read the apps region back
+//! dfu-util -a 1 -D kernel.bin   # write the kernel staging area
+//! ```
+//!
+//! The device starts in runtime mode. On DFU_DETACH it waits for the host to
+//! reset the bus, and then enumerates again in DFU mode, where each target is
+//! an alternate setting of the DFU interface: alternate setting 0 is the apps
+//! region, and alternate setting 1 the kernel staging area, if there is one.
+//! Installing a kernel from the staging area is left to the bootloader.
+//!
+//! Downloads are only committed once they are complete and valid. The first
+//! block of an image is held in RAM while the rest is written, and the start
+//! of the target is cleared, so a partial image is never loaded. In the
+//! manifestation phase the headers of all apps of the image are checked with
+//! the TBF parser, and an end marker is written after the last app, before the
+//! first block is written and the new apps become visible to the kernel.
+//!
+//! The kernel only loads apps when it boots. If the board provides a reset
+//! function, the device asks the host to reset it after manifestation (e.g.
+//! with `dfu-util -R`) and calls the function then. Otherwise the device
+//! stays in DFU mode and the new apps run after the next reboot.
+//!
+//! The control endpoint only supports transfers of one packet for writes, so
+//! the transfer size is a single packet, and the data is collected in a larger
+//! buffer before it is written to the storage.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::DescriptorType;
+use super::descriptors::DfuFunctionalDescriptor;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::StandardRequest;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::TakeCell;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Max packet size of the control endpoint, which is also the DFU transfer
+/// size.
+pub const MAX_CTRL_PACKET_SIZE: u8 = 64;
+
+/// Interface class and subclass of DFU.
+const DFU_CLASS: u8 = 0xfe;
+const DFU_SUBCLASS: u8 = 0x01;
+/// Interface protocols of the runtime mode and of the DFU mode.
+const PROTOCOL_RUNTIME: u8 = 0x01;
+const PROTOCOL_DFU_MODE: u8 = 0x02;
+
+/// bmAttributes of the DFU functional descriptor.
+const ATTRIBUTE_CAN_DOWNLOAD: u8 = 1 << 0;
+const ATTRIBUTE_CAN_UPLOAD: u8 = 1 << 1;
+const ATTRIBUTE_MANIFESTATION_TOLERANT: u8 = 1 << 2;
+
+/// How long the host may take to reset the bus after DFU_DETACH, in ms. The
+/// device itself waits for the reset indefinitely.
+const DETACH_TIMEOUT: u16 = 1000;
+
+/// How long the host should wait before asking again while the device is
+/// busy, in ms.
+const POLL_TIMEOUT: u32 = 10;
+
+/// Class specific requests of DFU.
+const DFU_DETACH: u8 = 0;
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_GETSTATE: u8 = 5;
+const DFU_ABORT: u8 = 6;
+
+/// Length of the reply to DFU_GETSTATUS.
+const STATUS_LEN: usize = 6;
+
+/// Length of the marker written after the last app of an image. A TBF header
+/// of version 0 ends the list of apps.
+const END_MARKER_LEN: usize = 8;
+
+/// States of the DFU state machine.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum State {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DnBusy = 4,
+    DnloadIdle = 5,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+/// Status codes reported with DFU_GETSTATUS.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Status {
+    Ok = 0x00,
+    ErrTarget = 0x01,
+    ErrFile = 0x02,
+    ErrWrite = 0x03,
+    ErrVerify = 0x07,
+    ErrAddress = 0x08,
+    ErrNotDone = 0x09,
+    ErrStalledPkt = 0x0f,
+}
+
+/// The control transfer in progress that we answer ourselves.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum CtrlState {
+    Idle,
+    Download(usize),
+    /// The requested length, and the length sent.
+    Upload(usize, usize),
+    GetStatus,
+    GetState,
+    GetInterface,
+}
+
+/// The storage operation in progress.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Op {
+    Idle,
+    /// Reading the next data to upload.
+    Prefetch,
+    /// Writing the collected data of a download.
+    Flush,
+    /// Reading the app header at the given offset of the image.
+    Validate(usize),
+    /// Writing the marker after the last app.
+    EndMarker,
+    /// Writing the first block of the image.
+    Commit,
+}
+
+/// A region of storage that can be written and read with DFU.
+#[derive(Copy, Clone)]
+pub struct DfuTarget<'a> {
+    pub storage: &'a dyn NonvolatileStorage<'a>,
+    /// Address of the region in the storage.
+    pub start: usize,
+    /// Length of the region in bytes.
+    pub size: usize,
+}
+
+pub struct Dfu<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// The apps region, selected with alternate setting 0.
+    apps: DfuTarget<'a>,
+    /// The kernel staging area, selected with alternate setting 1.
+    staging: Option<DfuTarget<'a>>,
+
+    /// Called on the bus reset after a successful manifestation.
+    reset_function: Option<&'a (dyn Fn() + 'a)>,
+
+    /// Whether the device enumerates in DFU mode rather than runtime mode.
+    dfu_mode: Cell<bool>,
+    state: Cell<State>,
+    status: Cell<Status>,
+    alternate_setting: Cell<u8>,
+    ctrl_state: Cell<CtrlState>,
+    op: Cell<Op>,
+    /// Whether the data being prefetched is stale, e.g. because the target
+    /// changed.
+    refetch: Cell<bool>,
+
+    /// Collects the data of a download, or holds the data of an upload.
+    buffer: TakeCell<'a, [u8]>,
+    /// Offset in the target of the start of `buffer`.
+    offset: Cell<usize>,
+    /// Number of valid bytes in `buffer`.
+    fill: Cell<usize>,
+    /// Number of bytes of `buffer` already uploaded.
+    pos: Cell<usize>,
+
+    /// The first block of the image being downloaded, held back until the
+    /// image is complete.
+    first_block: TakeCell<'a, [u8]>,
+    first_len: Cell<usize>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> Dfu<'a, U> {
+    /// `strings` holds the manufacturer, product and serial number, and may
+    /// be followed by the names of the apps target and of the staging target.
+    /// `buffer` and `first_block` must have the same length, which should be
+    /// a multiple of `MAX_CTRL_PACKET_SIZE`.
+    pub fn new(
+        controller: &'a U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str],
+        apps: DfuTarget<'a>,
+        staging: Option<DfuTarget<'a>>,
+        reset_function: Option<&'a (dyn Fn() + 'a)>,
+        buffer: &'a mut [u8],
+        first_block: &'a mut [u8],
+    ) -> Self {
+        let num_targets = if staging.is_some() { 2 } else { 1 };
+        let string_index = |alternate_setting: u8| {
+            let index = 4 + alternate_setting as usize;
+            if strings.len() >= index {
+                index as u8
+            } else {
+                0
+            }
+        };
+        let interfaces: &mut [InterfaceDescriptor] = &mut [
+            InterfaceDescriptor {
+                interface_number: 0,
+                alternate_setting: 0,
+                interface_class: DFU_CLASS,
+                interface_subclass: DFU_SUBCLASS,
+                interface_protocol: PROTOCOL_RUNTIME,
+                string_index: string_index(0),
+                ..InterfaceDescriptor::default()
+            },
+            InterfaceDescriptor {
+                interface_number: 0,
+                alternate_setting: 1,
+                interface_class: DFU_CLASS,
+                interface_subclass: DFU_SUBCLASS,
+                interface_protocol: PROTOCOL_RUNTIME,
+                string_index: string_index(1),
+                ..InterfaceDescriptor::default()
+            },
+        ];
+
+        // DFU only uses the control endpoint.
+        let endpoints: &[&[EndpointDescriptor]] = &[&[], &[]];
+
+        let (device_descriptor_buffer, mut other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: MAX_CTRL_PACKET_SIZE,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                &mut interfaces[..num_targets],
+                &endpoints[..num_targets],
+                None,
+                None,
+            );
+        // All the alternate settings count as a single interface.
+        other_descriptor_buffer.buf[4].set(1);
+
+        let mut attributes = ATTRIBUTE_CAN_DOWNLOAD | ATTRIBUTE_CAN_UPLOAD;
+        if reset_function.is_none() {
+            attributes |= ATTRIBUTE_MANIFESTATION_TOLERANT;
+        }
+        other_descriptor_buffer.append(&DfuFunctionalDescriptor {
+            attributes,
+            detach_timeout: DETACH_TIMEOUT,
+            transfer_size: MAX_CTRL_PACKET_SIZE as u16,
+            dfu_version: 0x0110,
+        });
+
+        Dfu {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            apps,
+            staging,
+            reset_function,
+            dfu_mode: Cell::new(false),
+            state: Cell::new(State::AppIdle),
+            status: Cell::new(Status::Ok),
+            alternate_setting: Cell::new(0),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            op: Cell::new(Op::Idle),
+            refetch: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+            offset: Cell::new(0),
+            fill: Cell::new(0),
+            pos: Cell::new(0),
+            first_block: TakeCell::new(first_block),
+            first_len: Cell::new(0),
+        }
+    }
+
+    fn target(&self) -> DfuTarget<'a> {
+        match self.alternate_setting.get() {
+            1 => self.staging.unwrap_or(self.apps),
+            _ => self.apps,
+        }
+    }
+
+    /// Whether images for the current target are lists of TBF apps.
+    fn holds_apps(&self) -> bool {
+        self.alternate_setting.get() == 0
+    }
+
+    /// Switches the interface protocol in the descriptors, which the host
+    /// reads again after the bus reset.
+    fn set_protocol(&self, protocol: u8) {
+        let descriptors = self.client_ctrl.other_descriptor_buffer();
+        let buf = &descriptors.buf[..descriptors.len];
+        let mut i = 0;
+        while i + 8 < buf.len() {
+            let len = buf[i].get() as usize;
+            if len == 0 {
+                break;
+            }
+            if buf[i + 1].get() == DescriptorType::Interface as u8 {
+                buf[i + 7].set(protocol);
+            }
+            i += len;
+        }
+    }
+
+    fn fail(&self, status: Status) {
+        self.status.set(status);
+        self.state.set(State::Error);
+    }
+
+    /// Rejects a request. Errors in runtime mode leave the state unchanged.
+    fn stall(&self, status: Status) -> hil::usb::CtrlSetupResult {
+        if self.dfu_mode.get() {
+            self.fail(status);
+        }
+        hil::usb::CtrlSetupResult::ErrGeneric
+    }
+
+    /// Enters dfuIDLE, and prepares for an upload from the start of the
+    /// target.
+    fn idle(&self) {
+        self.state.set(State::DfuIdle);
+        self.offset.set(0);
+        self.prefetch();
+    }
+
+    /// Reads the next data to upload. As control reads cannot be delayed, the
+    /// data must be ready before the host asks for it.
+    fn prefetch(&self) {
+        self.fill.set(0);
+        self.pos.set(0);
+        let target = self.target();
+        let left = target.size.saturating_sub(self.offset.get());
+        if left == 0 {
+            return;
+        }
+        match self.buffer.take() {
+            Some(buffer) => {
+                let len = cmp::min(buffer.len(), left);
+                match target
+                    .storage
+                    .read(buffer, target.start + self.offset.get(), len)
+                {
+                    Ok(()) => self.op.set(Op::Prefetch),
+                    Err(_) => self.fail(Status::ErrVerify),
+                }
+            }
+            None => {
+                if self.op.get() == Op::Prefetch {
+                    self.refetch.set(true);
+                }
+            }
+        }
+    }
+
+    /// Writes the data collected in the buffer. The first block of an image
+    /// is kept back and cleared in the storage instead.
+    fn flush(&self) {
+        let target = self.target();
+        let len = self.fill.get();
+        self.buffer.take().map_or_else(
+            || self.fail(Status::ErrNotDone),
+            |buffer| {
+                if self.offset.get() == 0 {
+                    self.first_block.map(|first_block| {
+                        first_block[..len].copy_from_slice(&buffer[..len]);
+                    });
+                    self.first_len.set(len);
+                    for b in buffer[..len].iter_mut() {
+                        *b = 0;
+                    }
+                }
+                match target
+                    .storage
+                    .write(buffer, target.start + self.offset.get(), len)
+                {
+                    Ok(()) => {
+                        self.op.set(Op::Flush);
+                        if self.state.get() != State::Manifest {
+                            self.state.set(State::DnBusy);
+                        }
+                    }
+                    Err(_) => self.fail(Status::ErrWrite),
+                }
+            },
+        );
+    }
+
+    /// Runs the next step of the manifestation phase.
+    fn manifest(&self) {
+        if self.fill.get() > 0 {
+            self.flush();
+        } else if self.holds_apps() {
+            self.validate(0);
+        } else {
+            self.commit();
+        }
+    }
+
+    /// Checks the app header at `pos` of the downloaded image, and the ones
+    /// after it.
+    fn validate(&self, pos: usize) {
+        let image_len = self.offset.get();
+        if pos == image_len {
+            return self.write_end_marker();
+        }
+        if pos == 0 {
+            let first_len = self.first_len.get();
+            let result = self
+                .first_block
+                .map_or(Err(Status::ErrNotDone), |first_block| {
+                    check_tbf_header(&first_block[..first_len], image_len)
+                });
+            return match result {
+                Ok(len) => self.validate(len),
+                Err(status) => self.fail(status),
+            };
+        }
+        let target = self.target();
+        self.buffer.take().map_or_else(
+            || self.fail(Status::ErrNotDone),
+            |buffer| {
+                let len = cmp::min(buffer.len(), image_len - pos);
+                match target.storage.read(buffer, target.start + pos, len) {
+                    Ok(()) => self.op.set(Op::Validate(pos)),
+                    Err(_) => self.fail(Status::ErrVerify),
+                }
+            },
+        );
+    }
+
+    /// Ends the list of apps after the image, so that apps that were in the
+    /// region before are not loaded.
+    fn write_end_marker(&self) {
+        let target = self.target();
+        let image_len = self.offset.get();
+        if image_len + END_MARKER_LEN > target.size {
+            return self.commit();
+        }
+        self.buffer.take().map_or_else(
+            || self.fail(Status::ErrNotDone),
+            |buffer| {
+                for b in buffer[..END_MARKER_LEN].iter_mut() {
+                    *b = 0;
+                }
+                match target
+                    .storage
+                    .write(buffer, target.start + image_len, END_MARKER_LEN)
+                {
+                    Ok(()) => self.op.set(Op::EndMarker),
+                    Err(_) => self.fail(Status::ErrWrite),
+                }
+            },
+        );
+    }
+
+    /// Writes the first block, which makes the image visible.
+    fn commit(&self) {
+        let target = self.target();
+        self.first_block.take().map_or_else(
+            || self.fail(Status::ErrNotDone),
+            |first_block| match target.storage.write(
+                first_block,
+                target.start,
+                self.first_len.get(),
+            ) {
+                Ok(()) => self.op.set(Op::Commit),
+                Err(_) => self.fail(Status::ErrWrite),
+            },
+        );
+    }
+
+    fn manifestation_done(&self) {
+        if self.reset_function.is_some() {
+            self.state.set(State::ManifestWaitReset);
+        } else {
+            self.idle();
+        }
+    }
+
+    /// Handles a DFU class request.
+    fn dfu_request(
+        &'a self,
+        endpoint: usize,
+        request: u8,
+        length: usize,
+    ) -> hil::usb::CtrlSetupResult {
+        let state = self.state.get();
+        match request {
+            DFU_GETSTATUS => {
+                self.ctrl_state.set(CtrlState::GetStatus);
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            DFU_GETSTATE => {
+                self.ctrl_state.set(CtrlState::GetState);
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            DFU_DETACH if state == State::AppIdle => {
+                self.state.set(State::AppDetach);
+            }
+            DFU_DNLOAD if state == State::DfuIdle || state == State::DnloadIdle => {
+                if length > MAX_CTRL_PACKET_SIZE as usize {
+                    return self.stall(Status::ErrStalledPkt);
+                }
+                if length == 0 {
+                    if state == State::DfuIdle {
+                        return self.stall(Status::ErrStalledPkt);
+                    }
+                } else {
+                    if self.buffer.is_none() {
+                        return self.stall(Status::ErrNotDone);
+                    }
+                    if state == State::DfuIdle {
+                        self.offset.set(0);
+                        self.fill.set(0);
+                    }
+                    if self.offset.get() + self.fill.get() + length > self.target().size {
+                        return self.stall(Status::ErrAddress);
+                    }
+                }
+                self.ctrl_state.set(CtrlState::Download(length));
+            }
+            DFU_UPLOAD if state == State::DfuIdle || state == State::UploadIdle => {
+                if self.buffer.is_none() {
+                    return self.stall(Status::ErrNotDone);
+                }
+                let length = cmp::min(length, MAX_CTRL_PACKET_SIZE as usize);
+                self.ctrl_state.set(CtrlState::Upload(length, 0));
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            DFU_CLRSTATUS if state == State::Error => {
+                self.status.set(Status::Ok);
+                self.idle();
+            }
+            DFU_ABORT
+                if state == State::DfuIdle
+                    || state == State::DnloadIdle
+                    || state == State::UploadIdle =>
+            {
+                self.idle();
+            }
+            _ => return self.stall(Status::ErrStalledPkt),
+        }
+        // Let `ClientCtrl` accept the request, and the data of a download.
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Selects the target of the following requests.
+    fn set_interface(&'a self, alternate_setting: u16) -> hil::usb::CtrlSetupResult {
+        let num_targets = if self.staging.is_some() { 2 } else { 1 };
+        if alternate_setting >= num_targets {
+            return self.stall(Status::ErrTarget);
+        }
+        self.alternate_setting.set(alternate_setting as u8);
+        if self.dfu_mode.get() {
+            self.idle();
+        }
+        hil::usb::CtrlSetupResult::Ok
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Dfu<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    /// The host resets the bus after DFU_DETACH to enumerate the device in
+    /// DFU mode, and after manifestation to run the new firmware.
+    fn bus_reset(&'a self) {
+        match self.state.get() {
+            State::AppDetach => {
+                self.dfu_mode.set(true);
+                self.set_protocol(PROTOCOL_DFU_MODE);
+                self.status.set(Status::Ok);
+                self.idle();
+            }
+            State::ManifestWaitReset => {
+                if let Some(reset_function) = self.reset_function {
+                    reset_function();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// `ClientCtrl` does not know the requests of DFU, nor the alternate
+    /// settings of the interface, so we answer them ourselves.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = match descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return self.client_ctrl.ctrl_setup(endpoint),
+        };
+        match (
+            setup_data.request_type.request_type(),
+            setup_data.request_type.recipient(),
+        ) {
+            (RequestType::Class, Recipient::Interface) => self.dfu_request(
+                endpoint,
+                setup_data.request_code,
+                setup_data.length as usize,
+            ),
+            (RequestType::Standard, Recipient::Interface) => {
+                match setup_data.get_standard_request() {
+                    Some(StandardRequest::SetInterface) => self.set_interface(setup_data.value),
+                    Some(StandardRequest::GetInterface { .. }) => {
+                        self.ctrl_state.set(CtrlState::GetInterface);
+                        hil::usb::CtrlSetupResult::Ok
+                    }
+                    _ => self.client_ctrl.ctrl_setup(endpoint),
+                }
+            }
+            _ => self.client_ctrl.ctrl_setup(endpoint),
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        let buf = &self.client_ctrl.ctrl_buffer.buf;
+        match self.ctrl_state.get() {
+            CtrlState::GetStatus => {
+                let state = self.state.get();
+                let poll_timeout = match state {
+                    State::DnBusy | State::Manifest => POLL_TIMEOUT,
+                    _ => 0,
+                };
+                buf[0].set(self.status.get() as u8);
+                buf[1].set(poll_timeout as u8);
+                buf[2].set((poll_timeout >> 8) as u8);
+                buf[3].set((poll_timeout >> 16) as u8);
+                buf[4].set(state as u8);
+                buf[5].set(0); // No status description
+                hil::usb::CtrlInResult::Packet(STATUS_LEN, true)
+            }
+            CtrlState::GetState => {
+                buf[0].set(self.state.get() as u8);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            CtrlState::GetInterface => {
+                buf[0].set(self.alternate_setting.get());
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            CtrlState::Upload(length, _) => {
+                self.buffer.map_or(hil::usb::CtrlInResult::Error, |buffer| {
+                    let pos = self.pos.get();
+                    let len = cmp::min(length, self.fill.get() - pos);
+                    for (i, b) in buffer[pos..pos + len].iter().enumerate() {
+                        buf[i].set(*b);
+                    }
+                    self.ctrl_state.set(CtrlState::Upload(length, len));
+                    hil::usb::CtrlInResult::Packet(len, true)
+                })
+            }
+            _ => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if let CtrlState::Download(length) = self.ctrl_state.get() {
+            let len = cmp::min(packet_bytes as usize, length);
+            let fill = self.fill.get();
+            let copied = self.buffer.map(|buffer| {
+                for (i, b) in buffer[fill..fill + len].iter_mut().enumerate() {
+                    *b = self.client_ctrl.ctrl_buffer.buf[i].get();
+                }
+            });
+            if copied.is_none() {
+                return hil::usb::CtrlOutResult::Halted;
+            }
+            self.fill.set(fill + len);
+        }
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        match self.ctrl_state.get() {
+            CtrlState::Download(0) => {
+                self.state.set(State::Manifest);
+                self.manifest();
+            }
+            CtrlState::Download(_) => {
+                let full = self.buffer.map_or(false, |buffer| {
+                    buffer.len() - self.fill.get() < MAX_CTRL_PACKET_SIZE as usize
+                });
+                if full {
+                    self.flush();
+                } else {
+                    self.state.set(State::DnloadIdle);
+                }
+            }
+            CtrlState::Upload(length, len) => {
+                self.pos.set(self.pos.get() + len);
+                if len < length {
+                    // A short packet ends the upload.
+                    self.idle();
+                } else {
+                    self.state.set(State::UploadIdle);
+                    if self.pos.get() == self.fill.get() {
+                        self.offset.set(self.offset.get() + self.fill.get());
+                        self.prefetch();
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// DFU has no endpoints other than the control endpoint.
+    fn packet_in(&'a self, _transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        hil::usb::InResult::Error
+    }
+
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        hil::usb::OutResult::Error
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {}
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> NonvolatileStorageClient<'a> for Dfu<'a, U> {
+    fn read_done(&self, buffer: &'a mut [u8], length: usize) {
+        let op = self.op.replace(Op::Idle);
+        match op {
+            Op::Prefetch => {
+                self.buffer.replace(buffer);
+                if self.refetch.replace(false) {
+                    self.prefetch();
+                } else {
+                    self.fill.set(length);
+                }
+            }
+            Op::Validate(pos) => {
+                let result = check_tbf_header(&buffer[..length], self.offset.get() - pos);
+                self.buffer.replace(buffer);
+                match result {
+                    Ok(len) => self.validate(pos + len),
+                    Err(status) => self.fail(status),
+                }
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], length: usize) {
+        let op = self.op.replace(Op::Idle);
+        if op == Op::Commit {
+            self.first_block.replace(buffer);
+            return if length < self.first_len.get() {
+                self.fail(Status::ErrWrite)
+            } else {
+                self.manifestation_done()
+            };
+        }
+        self.buffer.replace(buffer);
+        match op {
+            Op::Flush => {
+                if length < self.fill.get() {
+                    return self.fail(Status::ErrWrite);
+                }
+                self.offset.set(self.offset.get() + length);
+                self.fill.set(0);
+                if self.state.get() == State::Manifest {
+                    self.manifest();
+                } else {
+                    self.state.set(State::DnloadIdle);
+                }
+            }
+            Op::EndMarker => {
+                if length < END_MARKER_LEN {
+                    return self.fail(Status::ErrWrite);
+                }
+                self.commit();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks the TBF header at the start of `data`, which holds the start of the
+/// rest of an image of `remaining` bytes, and returns the length of the app.
+fn check_tbf_header(data: &[u8], remaining: usize) -> Result<usize, Status> {
+    let lengths = data
+        .get(0..8)
+        .and_then(|lengths| lengths.try_into().ok())
+        .ok_or(Status::ErrFile)?;
+    let (version, header_len, total_len) =
+        tock_tbf::parse::parse_tbf_header_lengths(lengths).or(Err(Status::ErrFile))?;
+    let header = data.get(0..header_len as usize).ok_or(Status::ErrFile)?;
+    tock_tbf::parse::parse_tbf_header(header, version).or(Err(Status::ErrFile))?;
+    let total_len = total_len as usize;
+    if total_len > remaining {
+        return Err(Status::ErrFile);
+    }
+    Ok(total_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a TBF header without any TLVs for an app of `total_len` bytes.
+    fn tbf_header(total_len: u32) -> [u8; 16] {
+        let words = [2 | 16 << 16, total_len, 1, 0];
+        let checksum = words[0] ^ words[1] ^ words[2];
+        let mut header = [0; 16];
+        for (i, word) in [words[0], words[1], words[2], checksum].iter().enumerate() {
+            header[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        header
+    }
+
+    #[test]
+    fn tbf_headers_are_checked() {
+        let header = tbf_header(64);
+        assert_eq!(check_tbf_header(&header, 128), Ok(64));
+        assert_eq!(check_tbf_header(&header, 64), Ok(64));
+        // The app does not fit in the image.
+        assert_eq!(check_tbf_header(&header, 63), Err(Status::ErrFile));
+        // The header is cut off.
+        assert_eq!(check_tbf_header(&header[..12], 128), Err(Status::ErrFile));
+
+        let mut corrupted = header;
+        corrupted[8] ^= 0x10;
+        assert_eq!(check_tbf_header(&corrupted, 128), Err(Status::ErrFile));
+
+        // An erased or cleared start is not an app.
+        assert_eq!(check_tbf_header(&[0xff; 16], 128), Err(Status::ErrFile));
+        assert_eq!(check_tbf_header(&[0; 16], 128), Err(Status::ErrFile));
+    }
+}
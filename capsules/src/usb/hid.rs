@@ -0,0 +1,645 @@
+//! Human Interface Device Class for USB
+//!
+//! This capsule presents a keyboard, a mouse, or any other HID device
+//! described by a custom report descriptor. Input reports are sent on an
+//! interrupt IN endpoint, and output reports (e.g. the LED state of a
+//! keyboard) arrive with SET_REPORT on the control endpoint.
+//!
+//! The built-in report descriptors are the boot protocol descriptors of the
+//! HID specification, so the reports are the same in the boot and in the
+//! report protocol, and the device works in a BIOS. A keyboard report is
+//! 8 bytes (modifiers, a reserved byte and six key codes), and a mouse report
+//! is 3 bytes (buttons, X and Y).
+//!
+//! The idle rate set by the host is recorded and reported back, but reports
+//! are only sent when the client sends them.
+//!
+//! Changing the report descriptor detaches the device and attaches it again,
+//! so that the host enumerates it anew.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::DescriptorType;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::HIDCountryCode;
+use super::descriptors::HIDDescriptor;
+use super::descriptors::HIDSubordinateDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::StandardRequest;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Interrupt IN endpoint for input reports.
+const ENDPOINT_NUM: usize = 1;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Max packet size of the control and of the interrupt endpoint.
+pub const MAX_CTRL_PACKET_SIZE: u8 = 64;
+
+/// Maximum length of an input or output report.
+pub const MAX_REPORT_LEN: usize = 64;
+
+/// Maximum length of a custom report descriptor.
+pub const MAX_REPORT_DESCRIPTOR_LEN: usize = 256;
+
+/// Class specific requests of HID.
+const GET_REPORT: u8 = 0x01;
+const GET_IDLE: u8 = 0x02;
+const GET_PROTOCOL: u8 = 0x03;
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+
+/// Interface subclass of devices that support the boot protocol.
+const SUBCLASS_BOOT: u8 = 0x01;
+
+/// Boot keyboard report descriptor, from appendix E.6 of the HID
+/// specification.
+static KEYBOARD_REPORT_DESCRIPTOR: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xe0, //   Usage Minimum (224)
+    0x29, 0xe7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifiers
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LED report
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): LED report padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): key codes
+    0xc0, // End Collection
+];
+
+/// Boot mouse report descriptor, from appendix E.10 of the HID
+/// specification.
+static MOUSE_REPORT_DESCRIPTOR: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Buttons)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): buttons
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x01, //     Input (Constant): padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x02, //     Report Count (2)
+    0x81, 0x06, //     Input (Data, Variable, Relative): X and Y
+    0xc0, //   End Collection
+    0xc0, // End Collection
+];
+
+/// The devices with a built-in report descriptor. The values are the
+/// interface protocols of the boot devices.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BootDevice {
+    Keyboard = 1,
+    Mouse = 2,
+}
+
+impl BootDevice {
+    fn report_descriptor(self) -> &'static [u8] {
+        match self {
+            BootDevice::Keyboard => KEYBOARD_REPORT_DESCRIPTOR,
+            BootDevice::Mouse => MOUSE_REPORT_DESCRIPTOR,
+        }
+    }
+}
+
+/// The protocol selected by the host with SET_PROTOCOL.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Protocol {
+    Boot = 0,
+    Report = 1,
+}
+
+/// Implement this trait and use `set_client()` in order to receive callbacks.
+pub trait HidClient {
+    /// Called when the input report passed to `send_report()` was sent.
+    fn report_sent(&self, report: &'static mut [u8]);
+
+    /// Called when the host sets an output report, e.g. the LED state of a
+    /// keyboard.
+    fn output_report(&self, report: &[u8]);
+}
+
+/// The control transfer in progress that we answer ourselves.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum CtrlState {
+    Idle,
+    /// Sending the given range of the report descriptor.
+    ReportDescriptor(usize, usize),
+    HidDescriptor(usize),
+    GetReport(usize),
+    GetIdle,
+    GetProtocol,
+    SetReport,
+}
+
+pub struct Hid<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// Packet buffer of the interrupt IN endpoint.
+    buffer: Buffer64,
+
+    client: OptionalCell<&'a dyn HidClient>,
+
+    /// The report descriptor presented to the host.
+    report_descriptor: [Cell<u8>; MAX_REPORT_DESCRIPTOR_LEN],
+    report_descriptor_len: Cell<usize>,
+
+    /// The report being sent, and whether it was handed to the controller.
+    report: TakeCell<'static, [u8]>,
+    report_len: Cell<usize>,
+    in_flight: Cell<bool>,
+
+    /// The last input report, which the host can ask for with GET_REPORT.
+    last_report: [Cell<u8>; MAX_REPORT_LEN],
+    last_report_len: Cell<usize>,
+
+    /// The output report being received with SET_REPORT.
+    output_report: [Cell<u8>; MAX_REPORT_LEN],
+    output_report_len: Cell<usize>,
+
+    ctrl_state: Cell<CtrlState>,
+    idle_rate: Cell<u8>,
+    protocol: Cell<Protocol>,
+    attached: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> Hid<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        device: BootDevice,
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x03, // HID
+            interface_subclass: SUBCLASS_BOOT,
+            interface_protocol: device as u8,
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_NUM,
+                TransferDirection::DeviceToHost,
+            ),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: MAX_REPORT_LEN as u16,
+            interval: 10,
+        }]];
+
+        // The length of the report descriptor is patched in whenever the
+        // descriptor changes.
+        let hid_descriptor = HIDDescriptor {
+            hid_class: 0x0111,
+            country_code: HIDCountryCode::NotSupported,
+            sub_descriptors: &[HIDSubordinateDescriptor {
+                typ: DescriptorType::Report,
+                len: 0,
+            }],
+        };
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: MAX_CTRL_PACKET_SIZE,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                Some(&hid_descriptor),
+                None,
+            );
+
+        const EMPTY: Cell<u8> = Cell::new(0);
+        let hid = Hid {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // We answer requests for the HID descriptor
+                None, // and for the report descriptor ourselves
+                LANGUAGES,
+                strings,
+            ),
+            buffer: Buffer64::default(),
+            client: OptionalCell::empty(),
+            report_descriptor: [EMPTY; MAX_REPORT_DESCRIPTOR_LEN],
+            report_descriptor_len: Cell::new(0),
+            report: TakeCell::empty(),
+            report_len: Cell::new(0),
+            in_flight: Cell::new(false),
+            last_report: [EMPTY; MAX_REPORT_LEN],
+            last_report_len: Cell::new(0),
+            output_report: [EMPTY; MAX_REPORT_LEN],
+            output_report_len: Cell::new(0),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            idle_rate: Cell::new(0),
+            protocol: Cell::new(Protocol::Report),
+            attached: Cell::new(false),
+        };
+        hid.update_descriptors(device.report_descriptor(), SUBCLASS_BOOT, device as u8);
+        hid
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    pub fn set_client(&self, client: &'a dyn HidClient) {
+        self.client.set(client);
+    }
+
+    /// The protocol the host selected, which only changes for boot devices.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol.get()
+    }
+
+    /// Presents one of the built-in boot devices to the host.
+    pub fn set_boot_device(&self, device: BootDevice) {
+        self.update_descriptors(device.report_descriptor(), SUBCLASS_BOOT, device as u8);
+        self.reattach();
+    }
+
+    /// Presents a device with a custom report descriptor to the host. It does
+    /// not support the boot protocol.
+    pub fn set_report_descriptor(&self, report_descriptor: &[u8]) -> Result<(), ErrorCode> {
+        if report_descriptor.len() > MAX_REPORT_DESCRIPTOR_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.update_descriptors(report_descriptor, 0, 0);
+        self.reattach();
+        Ok(())
+    }
+
+    /// Sends an input report of `len` bytes.
+    pub fn send_report(
+        &self,
+        report: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.report.is_some() {
+            return Err((ErrorCode::BUSY, report));
+        }
+        if len > MAX_REPORT_LEN || len > report.len() {
+            return Err((ErrorCode::SIZE, report));
+        }
+        for (cell, b) in self.last_report.iter().zip(report[..len].iter()) {
+            cell.set(*b);
+        }
+        self.last_report_len.set(len);
+        self.report_len.set(len);
+        self.report.replace(report);
+        self.controller().endpoint_resume_in(ENDPOINT_NUM);
+        Ok(())
+    }
+
+    /// Stores the report descriptor, and updates the interface and HID
+    /// descriptors that refer to it.
+    fn update_descriptors(&self, report_descriptor: &[u8], subclass: u8, protocol: u8) {
+        for (cell, b) in self.report_descriptor.iter().zip(report_descriptor) {
+            cell.set(*b);
+        }
+        self.report_descriptor_len.set(report_descriptor.len());
+
+        let descriptors = self.client_ctrl.other_descriptor_buffer();
+        let buf = &descriptors.buf[..descriptors.len];
+        let mut i = 0;
+        while i + 1 < buf.len() {
+            let len = buf[i].get() as usize;
+            if len == 0 || i + len > buf.len() {
+                break;
+            }
+            let descriptor_type = buf[i + 1].get();
+            if descriptor_type == DescriptorType::Interface as u8 {
+                buf[i + 6].set(subclass);
+                buf[i + 7].set(protocol);
+            } else if descriptor_type == DescriptorType::HID as u8 {
+                let report_len = report_descriptor.len() as u16;
+                buf[i + 7].set(report_len as u8);
+                buf[i + 8].set((report_len >> 8) as u8);
+            }
+            i += len;
+        }
+    }
+
+    /// Lets the host enumerate the device again after a descriptor change.
+    fn reattach(&self) {
+        if self.attached.get() {
+            self.controller().detach();
+            self.controller().attach();
+        }
+    }
+
+    /// Finds the HID descriptor in the configuration.
+    fn hid_descriptor_offset(&self) -> Option<usize> {
+        let descriptors = self.client_ctrl.other_descriptor_buffer();
+        let buf = &descriptors.buf[..descriptors.len];
+        let mut i = 0;
+        while i + 1 < buf.len() {
+            let len = buf[i].get() as usize;
+            if len == 0 {
+                return None;
+            }
+            if buf[i + 1].get() == DescriptorType::HID as u8 {
+                return Some(i);
+            }
+            i += len;
+        }
+        None
+    }
+
+    /// Handles a standard request to the interface, if it is one we answer
+    /// ourselves.
+    fn interface_request(&self, request: StandardRequest) -> Option<hil::usb::CtrlSetupResult> {
+        let (descriptor_type, requested_length) = match request {
+            StandardRequest::GetDescriptor {
+                descriptor_type,
+                requested_length,
+                ..
+            } => (descriptor_type, requested_length as usize),
+            _ => return None,
+        };
+        match descriptor_type {
+            DescriptorType::HID => {
+                self.ctrl_state
+                    .set(CtrlState::HidDescriptor(requested_length));
+            }
+            DescriptorType::Report => {
+                let end = cmp::min(self.report_descriptor_len.get(), requested_length);
+                self.ctrl_state.set(CtrlState::ReportDescriptor(0, end));
+            }
+            _ => return None,
+        }
+        Some(hil::usb::CtrlSetupResult::Ok)
+    }
+
+    /// Handles a HID class request.
+    fn class_request(
+        &'a self,
+        endpoint: usize,
+        setup_data: descriptors::SetupData,
+    ) -> hil::usb::CtrlSetupResult {
+        match setup_data.request_code {
+            GET_REPORT => {
+                self.ctrl_state
+                    .set(CtrlState::GetReport(setup_data.length as usize));
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            GET_IDLE => {
+                self.ctrl_state.set(CtrlState::GetIdle);
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            GET_PROTOCOL => {
+                self.ctrl_state.set(CtrlState::GetProtocol);
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            SET_REPORT => {
+                if setup_data.length as usize > MAX_REPORT_LEN {
+                    return hil::usb::CtrlSetupResult::ErrGeneric;
+                }
+                self.output_report_len.set(0);
+                self.ctrl_state.set(CtrlState::SetReport);
+            }
+            SET_IDLE => {
+                self.idle_rate.set((setup_data.value >> 8) as u8);
+            }
+            SET_PROTOCOL => {
+                self.protocol.set(if setup_data.value == 0 {
+                    Protocol::Boot
+                } else {
+                    Protocol::Report
+                });
+            }
+            _ => return hil::usb::CtrlSetupResult::ErrGeneric,
+        }
+        // Let `ClientCtrl` accept the request, and the data of SET_REPORT.
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Hid<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffer for the input reports.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_NUM, &self.buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Interrupt, ENDPOINT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.attached.set(true);
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        // The report protocol is the default after a reset.
+        self.protocol.set(Protocol::Report);
+        self.idle_rate.set(0);
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The report descriptor can change at runtime, so we answer requests for
+    /// it, and for the HID descriptor with its length, rather than
+    /// `ClientCtrl`.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = match descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return self.client_ctrl.ctrl_setup(endpoint),
+        };
+        match (
+            setup_data.request_type.request_type(),
+            setup_data.request_type.recipient(),
+        ) {
+            (RequestType::Class, Recipient::Interface) => self.class_request(endpoint, setup_data),
+            (RequestType::Standard, Recipient::Interface) => setup_data
+                .get_standard_request()
+                .and_then(|request| self.interface_request(request))
+                .unwrap_or_else(|| self.client_ctrl.ctrl_setup(endpoint)),
+            _ => self.client_ctrl.ctrl_setup(endpoint),
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        let buf = &self.client_ctrl.ctrl_buffer.buf;
+        match self.ctrl_state.get() {
+            CtrlState::ReportDescriptor(start, end) => {
+                let len = cmp::min(end - start, buf.len());
+                for (i, b) in self.report_descriptor[start..start + len]
+                    .iter()
+                    .enumerate()
+                {
+                    buf[i].set(b.get());
+                }
+                self.ctrl_state
+                    .set(CtrlState::ReportDescriptor(start + len, end));
+                hil::usb::CtrlInResult::Packet(len, start + len == end)
+            }
+            CtrlState::HidDescriptor(requested_length) => {
+                self.hid_descriptor_offset()
+                    .map_or(hil::usb::CtrlInResult::Error, |offset| {
+                        let descriptors = self.client_ctrl.other_descriptor_buffer();
+                        let hid_descriptor = &descriptors.buf[offset..];
+                        let len = cmp::min(hid_descriptor[0].get() as usize, requested_length);
+                        for (i, b) in hid_descriptor[..len].iter().enumerate() {
+                            buf[i].set(b.get());
+                        }
+                        hil::usb::CtrlInResult::Packet(len, true)
+                    })
+            }
+            CtrlState::GetReport(requested_length) => {
+                let len = cmp::min(self.last_report_len.get(), requested_length);
+                for (i, b) in self.last_report[..len].iter().enumerate() {
+                    buf[i].set(b.get());
+                }
+                hil::usb::CtrlInResult::Packet(len, true)
+            }
+            CtrlState::GetIdle => {
+                buf[0].set(self.idle_rate.get());
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            CtrlState::GetProtocol => {
+                buf[0].set(self.protocol.get() as u8);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            _ => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if self.ctrl_state.get() == CtrlState::SetReport {
+            let start = self.output_report_len.get();
+            let len = cmp::min(packet_bytes as usize, MAX_REPORT_LEN - start);
+            for i in 0..len {
+                self.output_report[start + i].set(self.client_ctrl.ctrl_buffer.buf[i].get());
+            }
+            self.output_report_len.set(start + len);
+        }
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        if self.ctrl_state.get() == CtrlState::SetReport {
+            let mut report = [0; MAX_REPORT_LEN];
+            let len = self.output_report_len.get();
+            for (b, cell) in report.iter_mut().zip(self.output_report.iter()) {
+                *b = cell.get();
+            }
+            self.client
+                .map(|client| client.output_report(&report[..len]));
+        }
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction.
+    fn packet_in(&'a self, transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Interrupt => {
+                if self.in_flight.get() {
+                    return hil::usb::InResult::Delay;
+                }
+                self.report.map_or(hil::usb::InResult::Delay, |report| {
+                    let len = self.report_len.get();
+                    for (i, b) in report[..len].iter().enumerate() {
+                        self.buffer.buf[i].set(*b);
+                    }
+                    self.in_flight.set(true);
+                    hil::usb::InResult::Packet(len)
+                })
+            }
+            TransferType::Bulk | TransferType::Control | TransferType::Isochronous => {
+                hil::usb::InResult::Error
+            }
+        }
+    }
+
+    /// There is no OUT endpoint, output reports arrive with SET_REPORT.
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        hil::usb::OutResult::Error
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        self.in_flight.set(false);
+        self.report.take().map(|report| {
+            self.client.map(move |client| client.report_sent(report));
+        });
+    }
+}
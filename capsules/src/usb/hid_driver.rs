@@ -0,0 +1,252 @@
+//! Provides userspace with access to a USB HID device, e.g. to type keys or
+//! to move a pointer.
+//!
+//! The first app that uses the driver owns the device.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     board_kernel,
+//!     capsules::usb::hid_driver::DRIVER_NUM,
+//!     &nrf52::usbd::USBD,
+//!     0x1209,
+//!     0x0002,
+//!     strings,
+//!     capsules::usb::hid::BootDevice::Keyboard,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52::usbd::Usbd));
+//!
+//! hid.enable();
+//! hid.attach();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Read-only allow
+//!
+//! - `0`: The input report to send.
+//! - `1`: A custom report descriptor.
+//!
+//! ### Read-write allow
+//!
+//! - `0`: Receives the output reports set by the host, e.g. the LED state of
+//!   a keyboard.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Report events. The callback signature is
+//!   `fn(event: u32, len: u32)`, where `event` is `0` when the input report
+//!   was sent, and `1` when an output report of `len` bytes was received.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Send the first `data1` bytes of the input report buffer.
+//! - `2`: Present the device with the custom report descriptor.
+//! - `3`: Present a boot keyboard (`data1` = 1) or a boot mouse
+//!   (`data1` = 2).
+//! - `4`: Get the protocol selected by the host: 0 for the boot protocol and
+//!   1 for the report protocol.
+
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use super::hid::{BootDevice, Hid, HidClient, MAX_REPORT_DESCRIPTOR_LEN, MAX_REPORT_LEN};
+use crate::process_owner;
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::UsbHid as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const REPORT: usize = 0;
+    pub const REPORT_DESCRIPTOR: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const OUTPUT_REPORT: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Values of the first upcall argument.
+const EVENT_REPORT_SENT: usize = 0;
+const EVENT_OUTPUT_REPORT: usize = 1;
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct UsbHidDriver<'a, U: hil::usb::UsbController<'a>> {
+    hid: &'a Hid<'a, U>,
+    apps: Grant<
+        App,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    owner: OptionalCell<ProcessId>,
+    report: TakeCell<'static, [u8]>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> UsbHidDriver<'a, U> {
+    pub fn new(
+        hid: &'a Hid<'a, U>,
+        report: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> UsbHidDriver<'a, U> {
+        UsbHidDriver {
+            hid,
+            apps: grant,
+            owner: OptionalCell::empty(),
+            report: TakeCell::new(report),
+        }
+    }
+
+    fn send_report(&self, processid: ProcessId, len: usize) -> Result<(), ErrorCode> {
+        let report = self.report.take().ok_or(ErrorCode::BUSY)?;
+        let copied = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::REPORT)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            if len > data.len() || len > report.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data[..len].copy_to_slice(&mut report[..len]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        if let Err(e) = copied {
+            self.report.replace(report);
+            return Err(e);
+        }
+        self.hid.send_report(report, len).map_err(|(e, report)| {
+            self.report.replace(report);
+            e
+        })
+    }
+
+    fn set_report_descriptor(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let mut descriptor = [0; MAX_REPORT_DESCRIPTOR_LEN];
+        let len = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::REPORT_DESCRIPTOR)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            if data.len() > MAX_REPORT_DESCRIPTOR_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data.copy_to_slice(&mut descriptor[..data.len()]);
+                            Ok(data.len())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.hid.set_report_descriptor(&descriptor[..len])
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> HidClient for UsbHidDriver<'a, U> {
+    fn report_sent(&self, report: &'static mut [u8]) {
+        self.report.replace(report);
+        self.owner.map(|processid| {
+            let _ = self.apps.enter(*processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(0, (EVENT_REPORT_SENT, 0, 0))
+                    .ok();
+            });
+        });
+    }
+
+    fn output_report(&self, report: &[u8]) {
+        self.owner.map(|processid| {
+            let _ = self.apps.enter(*processid, |_, kernel_data| {
+                let len = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::OUTPUT_REPORT)
+                    .and_then(|dest| {
+                        dest.mut_enter(|dest| {
+                            let len = cmp::min(dest.len(), report.len());
+                            dest[..len].copy_from_slice(&report[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0);
+                kernel_data
+                    .schedule_upcall(0, (EVENT_OUTPUT_REPORT, len, 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> SyscallDriver for UsbHidDriver<'a, U> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+
+        // Reports from the host go to a single app
+        if let Err(e) = process_owner::claim(&self.owner, &self.apps, processid) {
+            return CommandReturn::failure(e);
+        }
+
+        match command_num {
+            1 => {
+                if data1 > MAX_REPORT_LEN {
+                    return CommandReturn::failure(ErrorCode::SIZE);
+                }
+                self.send_report(processid, data1).into()
+            }
+            2 => self.set_report_descriptor(processid).into(),
+            3 => match data1 {
+                1 => {
+                    self.hid.set_boot_device(BootDevice::Keyboard);
+                    CommandReturn::success()
+                }
+                2 => {
+                    self.hid.set_boot_device(BootDevice::Mouse);
+                    CommandReturn::success()
+                }
+                _ => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            4 => CommandReturn::success_u32(self.hid.protocol() as u32),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
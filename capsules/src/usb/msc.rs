@@ -0,0 +1,1008 @@
+//! Mass Storage Class Device for USB
+//!
+//! This capsule lets a host access a block storage device, e.g. an SD card or
+//! an external flash chip, as a USB disk. It implements the Bulk-Only
+//! Transport with the SCSI transparent command set, which every common
+//! operating system supports without drivers, so logs stored on a board can be
+//! copied off by plugging it in.
+//!
+//! The storage is accessed through the `NonvolatileStorage` HIL, one block of
+//! `BLOCK_SIZE` bytes at a time, and `SDCardStorage` adapts the SD card driver
+//! to it. The host sees a single logical unit of `num_blocks` blocks, where
+//! zero blocks means that no medium is present.
+//!
+//! The USB stack gives clients no way to stall a bulk endpoint and to clear
+//! the halt again. When the host expects more data than a command has, the
+//! data is therefore padded with zeros (or the extra data from the host is
+//! dropped), and the difference is reported as residue in the command status,
+//! as the Bulk-Only Transport allows.
+//!
+//! The USB serial number string must be at least 12 hexadecimal digits long
+//! for a Bulk-Only Transport device, and the first two strings are reported
+//! as the vendor and product of the disk.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+use crate::sdcard::{SDCard, SDCardClient};
+
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::time::Alarm;
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::VolatileCell;
+use kernel::ErrorCode;
+
+/// Identifying number for the endpoint when transferring data from us to the
+/// host.
+const ENDPOINT_IN_NUM: usize = 1;
+/// Identifying number for the endpoint when transferring data from the host to
+/// us.
+const ENDPOINT_OUT_NUM: usize = 2;
+
+const N_ENDPOINTS: usize = 2;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Size of the blocks of the disk, and of the buffer used to access them.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Length of a Command Block Wrapper.
+pub const CBW_LEN: usize = 31;
+/// Length of a Command Status Wrapper.
+pub const CSW_LEN: usize = 13;
+
+const CBW_SIGNATURE: u32 = 0x43425355; // "USBC"
+const CSW_SIGNATURE: u32 = 0x53425355; // "USBS"
+
+/// Class specific control requests of the Bulk-Only Transport.
+const REQUEST_MASS_STORAGE_RESET: u8 = 0xff;
+const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+
+/// Values of the status field of a Command Status Wrapper.
+const STATUS_PASSED: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+const STATUS_PHASE_ERROR: u8 = 2;
+
+/// Operation codes of the supported SCSI commands.
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5a;
+
+/// SCSI sense data: sense key, additional sense code and qualifier.
+type Sense = (u8, u8, u8);
+
+const SENSE_NONE: Sense = (0x00, 0x00, 0x00);
+const SENSE_NOT_READY: Sense = (0x02, 0x04, 0x01);
+const SENSE_MEDIUM_NOT_PRESENT: Sense = (0x02, 0x3a, 0x00);
+const SENSE_WRITE_FAULT: Sense = (0x03, 0x03, 0x00);
+const SENSE_READ_ERROR: Sense = (0x03, 0x11, 0x00);
+const SENSE_INVALID_COMMAND: Sense = (0x05, 0x20, 0x00);
+const SENSE_LBA_OUT_OF_RANGE: Sense = (0x05, 0x21, 0x00);
+const SENSE_INVALID_FIELD: Sense = (0x05, 0x24, 0x00);
+const SENSE_MEDIUM_CHANGED: Sense = (0x06, 0x28, 0x00);
+
+/// A Command Block Wrapper, which the host sends to start every command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CommandBlock {
+    /// Tag to echo in the status of the command.
+    pub tag: u32,
+    /// Number of bytes the host expects to transfer in the data phase.
+    pub data_len: u32,
+    /// Whether the data phase goes from the device to the host.
+    pub data_in: bool,
+    /// Logical unit the command is for.
+    pub lun: u8,
+    /// The SCSI command block, padded with zeros.
+    pub cb: [u8; 16],
+}
+
+impl CommandBlock {
+    /// Parses a Command Block Wrapper, which must be the whole packet.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != CBW_LEN || get_u32_le(&bytes[0..4]) != CBW_SIGNATURE {
+            return None;
+        }
+        let cb_len = bytes[14] as usize & 0x1f;
+        if cb_len == 0 || cb_len > 16 {
+            return None;
+        }
+        let mut cb = [0; 16];
+        cb[..cb_len].copy_from_slice(&bytes[15..15 + cb_len]);
+        Some(CommandBlock {
+            tag: get_u32_le(&bytes[4..8]),
+            data_len: get_u32_le(&bytes[8..12]),
+            data_in: bytes[12] & 0x80 != 0,
+            lun: bytes[13] & 0x0f,
+            cb,
+        })
+    }
+}
+
+/// Writes a Command Status Wrapper to `buf`, and returns its length.
+pub fn encode_status(buf: &mut [u8], tag: u32, residue: u32, status: u8) -> usize {
+    buf[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+    buf[4..8].copy_from_slice(&tag.to_le_bytes());
+    buf[8..12].copy_from_slice(&residue.to_le_bytes());
+    buf[12] = status;
+    CSW_LEN
+}
+
+fn get_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn get_u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Limits the number of blocks of a medium to those the storage can address:
+/// blocks are accessed at byte addresses, which overflow a `usize` beyond
+/// 4 GiB on 32-bit platforms.
+fn addressable_blocks(num_blocks: u64) -> u32 {
+    let max = cmp::min((usize::MAX / BLOCK_SIZE) as u64, u32::MAX as u64);
+    cmp::min(num_blocks, max) as u32
+}
+
+fn get_u16_be(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Copies `s` into `buf`, padded with spaces, as in SCSI INQUIRY data.
+fn copy_padded(buf: &mut [u8], s: &str) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = *s.as_bytes().get(i).unwrap_or(&b' ');
+    }
+}
+
+/// Notified when the medium of a `MassStorage` changes, e.g. when an SD card
+/// is inserted or removed.
+pub trait MediumClient {
+    /// The medium now has `num_blocks` blocks of `BLOCK_SIZE` bytes, or was
+    /// removed if `num_blocks` is zero.
+    fn medium_changed(&self, num_blocks: u32);
+}
+
+/// Phases of the Bulk-Only Transport.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Phase {
+    /// Waiting for a Command Block Wrapper from the host.
+    Command,
+    /// Sending a response of the given length, which is already in the IN
+    /// endpoint buffer.
+    Reply(usize),
+    /// Sending the block in the block buffer.
+    DataIn,
+    /// Receiving a block into the block buffer.
+    DataOut,
+    /// Waiting for the storage to read or write a block.
+    Storage,
+    /// Sending zeros until the host got all the data it expects.
+    PadIn,
+    /// Dropping data until the host sent all the data it announced.
+    DiscardOut,
+    /// Sending the Command Status Wrapper.
+    Status,
+}
+
+/// States of the Control Endpoint related to the Bulk-Only Transport.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CtrlState {
+    /// No ongoing ctrl transaction.
+    Idle,
+    /// Host has sent a GET_MAX_LUN request.
+    GetMaxLun,
+}
+
+/// Implementation of the Bulk-Only Transport of the Mass Storage Class over
+/// USB.
+pub struct MassStorage<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffers for each endpoint.
+    buffers: [Buffer64; N_ENDPOINTS],
+
+    /// Current state of the Control Endpoint.
+    ctrl_state: Cell<CtrlState>,
+
+    /// The block storage presented to the host.
+    storage: &'a dyn NonvolatileStorage<'a>,
+    /// Buffer of `BLOCK_SIZE` bytes for the block being transferred. It is
+    /// missing while the storage reads or writes a block.
+    block_buf: TakeCell<'a, [u8]>,
+    /// Number of blocks of the medium, or zero if there is none.
+    num_blocks: Cell<u32>,
+    /// Set when the medium changed and the host has not been told yet.
+    unit_attention: Cell<bool>,
+
+    /// Vendor and product reported in the INQUIRY data.
+    strings: &'static [&'static str; 3],
+
+    /// Current phase of the Bulk-Only Transport.
+    phase: Cell<Phase>,
+    /// Tag of the current command.
+    tag: Cell<u32>,
+    /// Number of bytes the host expects in the data phase of the current
+    /// command.
+    expected: Cell<u32>,
+    /// Whether the data phase of the current command is towards the host.
+    data_in: Cell<bool>,
+    /// Number of bytes moved in the data phase so far, including padding.
+    moved: Cell<u32>,
+    /// Number of bytes of actual data processed in the data phase so far.
+    processed: Cell<u32>,
+    /// Status to report when the current command finishes.
+    status: Cell<u8>,
+    /// Sense data of the last failed command, for REQUEST SENSE.
+    sense: Cell<Sense>,
+
+    /// Next block to read or write.
+    lba: Cell<u32>,
+    /// Number of blocks left to read or write after the current one.
+    blocks_left: Cell<u32>,
+    /// Where in the block buffer the current packet starts.
+    block_offset: Cell<usize>,
+
+    /// Whether an IN packet was handed to the controller and not transmitted
+    /// yet.
+    in_flight: Cell<bool>,
+    /// Whether we returned `Delay` for an OUT packet, and need to resume the
+    /// OUT endpoint before the host can send more.
+    out_delayed: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> MassStorage<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'a dyn NonvolatileStorage<'a>,
+        block_buf: &'a mut [u8],
+        num_blocks: u32,
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x08,    // Mass storage
+            interface_subclass: 0x06, // SCSI transparent command set
+            interface_protocol: 0x50, // Bulk-Only Transport
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_IN_NUM,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: 64,
+                interval: 0,
+            },
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_OUT_NUM,
+                    TransferDirection::HostToDevice,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: 64,
+                interval: 0,
+            },
+        ]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    class: 0x0, // Class: defined by the interface
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                None, // No HID descriptor
+                None, // No CDC descriptor
+            );
+
+        Self {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [Buffer64::default(), Buffer64::default()],
+            ctrl_state: Cell::new(CtrlState::Idle),
+            storage,
+            block_buf: TakeCell::new(block_buf),
+            num_blocks: Cell::new(addressable_blocks(num_blocks as u64)),
+            unit_attention: Cell::new(false),
+            strings,
+            phase: Cell::new(Phase::Command),
+            tag: Cell::new(0),
+            expected: Cell::new(0),
+            data_in: Cell::new(false),
+            moved: Cell::new(0),
+            processed: Cell::new(0),
+            status: Cell::new(STATUS_PASSED),
+            sense: Cell::new(SENSE_NONE),
+            lba: Cell::new(0),
+            blocks_left: Cell::new(0),
+            block_offset: Cell::new(0),
+            in_flight: Cell::new(false),
+            out_delayed: Cell::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    #[inline]
+    fn buffer(&'a self, i: usize) -> &'a [VolatileCell<u8>; 64] {
+        &self.buffers[i - 1].buf
+    }
+
+    /// Sets the number of blocks of the medium, zero meaning that there is
+    /// none. The host is told that the medium changed.
+    pub fn set_num_blocks(&self, num_blocks: u32) {
+        self.num_blocks.set(addressable_blocks(num_blocks as u64));
+        self.unit_attention.set(true);
+    }
+
+    /// Drops the current command, as after a reset of the transport.
+    fn reset(&self) {
+        self.phase.set(Phase::Command);
+        self.blocks_left.set(0);
+        self.in_flight.set(false);
+        self.resume_out();
+    }
+
+    /// Lets the host send OUT packets again if we delayed one.
+    fn resume_out(&self) {
+        if self.out_delayed.get() {
+            self.out_delayed.set(false);
+            self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+        }
+    }
+
+    /// Starts the command of a Command Block Wrapper.
+    fn command(&'a self, cbw: &CommandBlock) {
+        self.tag.set(cbw.tag);
+        self.expected.set(cbw.data_len);
+        self.data_in.set(cbw.data_in);
+        self.moved.set(0);
+        self.processed.set(0);
+        self.status.set(STATUS_PASSED);
+
+        let cb = &cbw.cb;
+        if cbw.lun != 0 {
+            return self.fail(SENSE_INVALID_FIELD);
+        }
+        // The host must learn about a changed medium before using it again.
+        if self.unit_attention.get() && cb[0] != INQUIRY && cb[0] != REQUEST_SENSE {
+            self.unit_attention.set(false);
+            return self.fail(SENSE_MEDIUM_CHANGED);
+        }
+
+        let num_blocks = self.num_blocks.get();
+        match cb[0] {
+            TEST_UNIT_READY => {
+                if num_blocks == 0 {
+                    self.fail(SENSE_MEDIUM_NOT_PRESENT)
+                } else {
+                    self.end_data()
+                }
+            }
+            REQUEST_SENSE => {
+                let (key, asc, ascq) = self.sense.replace(SENSE_NONE);
+                let mut data = [0; 18];
+                data[0] = 0x70; // Current error, fixed format
+                data[2] = key;
+                data[7] = 10; // Additional sense length
+                data[12] = asc;
+                data[13] = ascq;
+                self.reply(&data, cb[4] as usize)
+            }
+            INQUIRY => {
+                if cb[1] & 0x01 != 0 {
+                    // No vital product data pages
+                    return self.fail(SENSE_INVALID_FIELD);
+                }
+                let mut data = [0; 36];
+                data[1] = 0x80; // Removable medium
+                data[2] = 0x04; // SPC-2
+                data[3] = 0x02; // Response data format
+                data[4] = 31; // Additional length
+                copy_padded(&mut data[8..16], self.strings[0]);
+                copy_padded(&mut data[16..32], self.strings[1]);
+                copy_padded(&mut data[32..36], "1.0");
+                self.reply(&data, get_u16_be(&cb[3..5]) as usize)
+            }
+            MODE_SENSE_6 => {
+                // No mode pages, and not write protected
+                self.reply(&[3, 0, 0, 0], cb[4] as usize)
+            }
+            MODE_SENSE_10 => self.reply(&[0, 6, 0, 0, 0, 0, 0, 0], get_u16_be(&cb[7..9]) as usize),
+            START_STOP_UNIT | PREVENT_ALLOW_MEDIUM_REMOVAL | VERIFY_10 | SYNCHRONIZE_CACHE_10 => {
+                // Writes go straight to the storage, so there is nothing to do.
+                self.end_data()
+            }
+            READ_FORMAT_CAPACITIES => {
+                let mut data = [0; 12];
+                data[3] = 8; // Capacity list length
+                data[4..8].copy_from_slice(&num_blocks.to_be_bytes());
+                // Formatted media, or no media present
+                data[8] = if num_blocks == 0 { 0x03 } else { 0x02 };
+                data[9..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..4]);
+                self.reply(&data, get_u16_be(&cb[7..9]) as usize)
+            }
+            READ_CAPACITY_10 => {
+                if num_blocks == 0 {
+                    return self.fail(SENSE_MEDIUM_NOT_PRESENT);
+                }
+                let mut data = [0; 8];
+                data[0..4].copy_from_slice(&(num_blocks - 1).to_be_bytes());
+                data[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.reply(&data, data.len())
+            }
+            READ_10 | WRITE_10 => {
+                let lba = get_u32_be(&cb[2..6]);
+                let count = get_u16_be(&cb[7..9]) as u32;
+                let data_in = cb[0] == READ_10;
+                if num_blocks == 0 {
+                    self.fail(SENSE_MEDIUM_NOT_PRESENT)
+                } else if lba as u64 + count as u64 > num_blocks as u64 {
+                    self.fail(SENSE_LBA_OUT_OF_RANGE)
+                } else if count == 0 {
+                    self.end_data()
+                } else if cbw.data_in != data_in
+                    || (cbw.data_len as u64) < count as u64 * BLOCK_SIZE as u64
+                {
+                    // The host does not expect the data of the command.
+                    self.status.set(STATUS_PHASE_ERROR);
+                    self.end_data()
+                } else {
+                    self.lba.set(lba);
+                    self.blocks_left.set(count - 1);
+                    if data_in {
+                        self.read_block()
+                    } else {
+                        self.block_offset.set(0);
+                        self.phase.set(Phase::DataOut);
+                    }
+                }
+            }
+            _ => self.fail(SENSE_INVALID_COMMAND),
+        }
+    }
+
+    /// Sends up to `alloc_len` bytes of `data` in the data phase. The data
+    /// must fit in one packet.
+    fn reply(&'a self, data: &[u8], alloc_len: usize) {
+        if !self.data_in.get() && self.expected.get() > 0 {
+            self.status.set(STATUS_PHASE_ERROR);
+            return self.end_data();
+        }
+        let len = cmp::min(
+            cmp::min(data.len(), alloc_len),
+            self.expected.get() as usize,
+        );
+        if len == 0 {
+            return self.end_data();
+        }
+        let packet = self.buffer(ENDPOINT_IN_NUM);
+        for (i, b) in data[..len].iter().enumerate() {
+            packet[i].set(*b);
+        }
+        self.phase.set(Phase::Reply(len));
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    /// Fails the current command with the given sense data.
+    fn fail(&self, sense: Sense) {
+        self.sense.set(sense);
+        self.status.set(STATUS_FAILED);
+        self.end_data();
+    }
+
+    /// Finishes the data phase, after moving the rest of the data the host
+    /// expects, and sends the status.
+    fn end_data(&self) {
+        if self.moved.get() >= self.expected.get() {
+            self.phase.set(Phase::Status);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        } else if self.data_in.get() {
+            self.phase.set(Phase::PadIn);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        } else {
+            self.phase.set(Phase::DiscardOut);
+            self.resume_out();
+        }
+    }
+
+    /// Starts reading the next block from the storage.
+    fn read_block(&self) {
+        self.block_buf.take().map_or_else(
+            || self.fail(SENSE_NOT_READY),
+            |buf| {
+                let address = self.lba.get() as usize * BLOCK_SIZE;
+                match self.storage.read(buf, address, BLOCK_SIZE) {
+                    Ok(()) => self.phase.set(Phase::Storage),
+                    Err(_) => self.fail(SENSE_READ_ERROR),
+                }
+            },
+        );
+    }
+
+    /// Starts writing the block buffer to the storage.
+    fn write_block(&self) {
+        self.block_buf.take().map_or_else(
+            || self.fail(SENSE_NOT_READY),
+            |buf| {
+                let address = self.lba.get() as usize * BLOCK_SIZE;
+                match self.storage.write(buf, address, BLOCK_SIZE) {
+                    Ok(()) => self.phase.set(Phase::Storage),
+                    Err(_) => self.fail(SENSE_WRITE_FAULT),
+                }
+            },
+        );
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for MassStorage<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for IN and OUT data transfer.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, self.buffer(ENDPOINT_IN_NUM));
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, self.buffer(ENDPOINT_OUT_NUM));
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.reset();
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// GET_MAX_LUN asks for data that `ClientCtrl` does not know about, so we
+    /// answer it ourselves.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let request =
+            descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf).and_then(|setup_data| {
+                match setup_data.request_type.request_type() {
+                    descriptors::RequestType::Class => Some(setup_data.request_code),
+                    _ => None,
+                }
+            });
+
+        match request {
+            Some(REQUEST_GET_MAX_LUN) => {
+                self.ctrl_state.set(CtrlState::GetMaxLun);
+                return hil::usb::CtrlSetupResult::Ok;
+            }
+            Some(REQUEST_MASS_STORAGE_RESET) => self.reset(),
+            _ => {}
+        }
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.ctrl_state.get() {
+            CtrlState::GetMaxLun => {
+                // We only have logical unit 0.
+                self.client_ctrl.ctrl_buffer.buf[0].set(0);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            CtrlState::Idle => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction.
+    ///
+    /// This sends the next packet of the data or status phase. Moving on to
+    /// the next step of the transport happens in `packet_transmitted`.
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Bulk => {
+                if self.in_flight.get() {
+                    return hil::usb::InResult::Delay;
+                }
+                let packet = self.buffer(endpoint);
+                let len = match self.phase.get() {
+                    Phase::Reply(len) => {
+                        // The reply is already in the packet.
+                        self.processed.set(len as u32);
+                        len
+                    }
+                    Phase::DataIn => self.block_buf.map_or(0, |buf| {
+                        let offset = self.block_offset.get();
+                        let len = cmp::min(packet.len(), BLOCK_SIZE - offset);
+                        for i in 0..len {
+                            packet[i].set(buf[offset + i]);
+                        }
+                        self.block_offset.set(offset + len);
+                        self.processed.set(self.processed.get() + len as u32);
+                        len
+                    }),
+                    Phase::PadIn => {
+                        let remaining = self.expected.get() - self.moved.get();
+                        let len = cmp::min(packet.len(), remaining as usize);
+                        for i in 0..len {
+                            packet[i].set(0);
+                        }
+                        len
+                    }
+                    Phase::Status => {
+                        let mut csw = [0; CSW_LEN];
+                        let residue = self.expected.get() - self.processed.get();
+                        encode_status(&mut csw, self.tag.get(), residue, self.status.get());
+                        for i in 0..CSW_LEN {
+                            packet[i].set(csw[i]);
+                        }
+                        self.in_flight.set(true);
+                        return hil::usb::InResult::Packet(CSW_LEN);
+                    }
+                    _ => return hil::usb::InResult::Delay,
+                };
+                if len == 0 {
+                    return hil::usb::InResult::Delay;
+                }
+                self.moved.set(self.moved.get() + len as u32);
+                self.in_flight.set(true);
+                hil::usb::InResult::Packet(len)
+            }
+            TransferType::Control | TransferType::Isochronous | TransferType::Interrupt => {
+                // Nothing to do for the Bulk-Only Transport.
+                hil::usb::InResult::Delay
+            }
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    ///
+    /// We take every packet, and return `Delay` after the ones the host must
+    /// not follow up on yet, e.g. while a block is being written. The OUT
+    /// endpoint is resumed once we can take the next packet.
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match transfer_type {
+            TransferType::Bulk => {
+                let packet = self.buffer(endpoint);
+                let len = cmp::min(packet_bytes as usize, packet.len());
+                match self.phase.get() {
+                    Phase::Command => {
+                        let mut bytes = [0; CBW_LEN];
+                        if len == CBW_LEN {
+                            for i in 0..CBW_LEN {
+                                bytes[i] = packet[i].get();
+                            }
+                        }
+                        // Invalid wrappers should stall both endpoints, which
+                        // we can't do, so we ignore them.
+                        CommandBlock::parse(&bytes[..len]).map(|cbw| self.command(&cbw));
+                    }
+                    Phase::DataOut => {
+                        self.block_buf.map(|buf| {
+                            let offset = self.block_offset.get();
+                            let copy = cmp::min(len, BLOCK_SIZE - offset);
+                            for i in 0..copy {
+                                buf[offset + i] = packet[i].get();
+                            }
+                            self.block_offset.set(offset + copy);
+                        });
+                        self.moved.set(self.moved.get() + len as u32);
+                        if self.block_offset.get() == BLOCK_SIZE {
+                            self.write_block();
+                        }
+                    }
+                    Phase::DiscardOut => {
+                        let moved = self.moved.get() + len as u32;
+                        self.moved.set(cmp::min(moved, self.expected.get()));
+                        self.end_data();
+                    }
+                    _ => {}
+                }
+
+                match self.phase.get() {
+                    Phase::Command | Phase::DataOut | Phase::DiscardOut => hil::usb::OutResult::Ok,
+                    _ => {
+                        // Hold off the host until this command is done.
+                        self.out_delayed.set(true);
+                        hil::usb::OutResult::Delay
+                    }
+                }
+            }
+            TransferType::Control | TransferType::Isochronous | TransferType::Interrupt => {
+                // Nothing to do for the Bulk-Only Transport.
+                hil::usb::OutResult::Ok
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        self.in_flight.set(false);
+        match self.phase.get() {
+            Phase::Reply(_) => self.end_data(),
+            Phase::DataIn => {
+                if self.block_offset.get() < BLOCK_SIZE {
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else if self.blocks_left.get() > 0 {
+                    self.blocks_left.set(self.blocks_left.get() - 1);
+                    self.read_block();
+                } else {
+                    self.end_data();
+                }
+            }
+            Phase::PadIn => self.end_data(),
+            Phase::Status => {
+                // The command is done, wait for the next one.
+                self.phase.set(Phase::Command);
+                self.resume_out();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> NonvolatileStorageClient<'a> for MassStorage<'a, U> {
+    fn read_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.block_buf.replace(buffer);
+        // The command may have been dropped by a reset in the meantime.
+        if self.phase.get() != Phase::Storage {
+            return;
+        }
+        if length < BLOCK_SIZE {
+            return self.fail(SENSE_READ_ERROR);
+        }
+        self.lba.set(self.lba.get() + 1);
+        self.block_offset.set(0);
+        self.phase.set(Phase::DataIn);
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], length: usize) {
+        self.block_buf.replace(buffer);
+        if self.phase.get() != Phase::Storage {
+            return;
+        }
+        if length < BLOCK_SIZE {
+            return self.fail(SENSE_WRITE_FAULT);
+        }
+        self.processed.set(self.processed.get() + BLOCK_SIZE as u32);
+        self.lba.set(self.lba.get() + 1);
+        if self.blocks_left.get() > 0 {
+            self.blocks_left.set(self.blocks_left.get() - 1);
+            self.block_offset.set(0);
+            self.phase.set(Phase::DataOut);
+            self.resume_out();
+        } else {
+            self.end_data();
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> MediumClient for MassStorage<'a, U> {
+    fn medium_changed(&self, num_blocks: u32) {
+        self.set_num_blocks(num_blocks);
+        // A removed medium won't finish the block it was accessing.
+        if num_blocks == 0 && self.phase.get() == Phase::Storage {
+            self.fail(SENSE_MEDIUM_NOT_PRESENT);
+        }
+    }
+}
+
+/// Presents an SD card as `NonvolatileStorage` for `MassStorage`.
+///
+/// Accesses must be single, aligned blocks of `BLOCK_SIZE` bytes. The medium
+/// client is told when the card is initialized or removed, and the card is
+/// reported as removed after an error, as the SD card driver keeps the buffer
+/// of a failed access.
+pub struct SDCardStorage<'a, A: Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient<'a>>,
+    medium_client: OptionalCell<&'a dyn MediumClient>,
+}
+
+impl<'a, A: Alarm<'a>> SDCardStorage<'a, A> {
+    pub fn new(sdcard: &'a SDCard<'a, A>) -> Self {
+        Self {
+            sdcard,
+            client: OptionalCell::empty(),
+            medium_client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_medium_client(&self, client: &'a dyn MediumClient) {
+        self.medium_client.set(client);
+    }
+
+    /// Initializes the card if it is installed, and watches for it being
+    /// inserted or removed.
+    pub fn start(&self) {
+        self.sdcard.detect_changes();
+        if self.sdcard.is_installed() {
+            let _ = self.sdcard.initialize();
+        }
+    }
+
+    fn sector(address: usize, length: usize) -> Result<u32, ErrorCode> {
+        if address % BLOCK_SIZE != 0 || length != BLOCK_SIZE {
+            Err(ErrorCode::INVAL)
+        } else {
+            Ok((address / BLOCK_SIZE) as u32)
+        }
+    }
+}
+
+impl<A: Alarm<'static>> NonvolatileStorage<'static> for SDCardStorage<'static, A> {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient<'static>) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let sector = Self::sector(address, length)?;
+        self.sdcard.read_blocks(buffer, sector, 1)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let sector = Self::sector(address, length)?;
+        self.sdcard.write_blocks(buffer, sector, 1)
+    }
+}
+
+impl<'a, A: Alarm<'a>> SDCardClient for SDCardStorage<'a, A> {
+    fn card_detection_changed(&self, installed: bool) {
+        if installed {
+            let _ = self.sdcard.initialize();
+        } else {
+            self.medium_client.map(|client| client.medium_changed(0));
+        }
+    }
+
+    fn init_done(&self, _block_size: u32, total_size: u64) {
+        let num_blocks = addressable_blocks(total_size / BLOCK_SIZE as u64);
+        self.medium_client
+            .map(|client| client.medium_changed(num_blocks));
+    }
+
+    fn read_done(&self, data: &'static mut [u8], len: usize) {
+        self.client.map(move |client| client.read_done(data, len));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        let len = buffer.len();
+        self.client
+            .map(move |client| client.write_done(buffer, len));
+    }
+
+    fn error(&self, _error: u32) {
+        self.medium_client.map(|client| client.medium_changed(0));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn media_are_limited_to_addressable_blocks() {
+        assert_eq!(addressable_blocks(0), 0);
+        assert_eq!(addressable_blocks(1 << 20), 1 << 20);
+        let max = cmp::min(usize::MAX / BLOCK_SIZE, u32::MAX as usize);
+        assert_eq!(addressable_blocks(u64::MAX) as usize, max);
+        // The last block can be addressed.
+        assert!((max - 1).checked_mul(BLOCK_SIZE).is_some());
+    }
+
+    #[test]
+    fn command_and_status_wrappers() {
+        // READ(10) of 2 blocks at LBA 0x1234
+        let mut cbw = [0; CBW_LEN];
+        cbw[0..4].copy_from_slice(b"USBC");
+        cbw[4..8].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+        cbw[8..12].copy_from_slice(&1024u32.to_le_bytes());
+        cbw[12] = 0x80;
+        cbw[14] = 10;
+        cbw[15..25].copy_from_slice(&[READ_10, 0, 0, 0, 0x12, 0x34, 0, 0, 2, 0]);
+
+        let cb = CommandBlock::parse(&cbw).unwrap();
+        assert_eq!(cb.tag, 0xdeadbeef);
+        assert_eq!(cb.data_len, 1024);
+        assert!(cb.data_in);
+        assert_eq!(cb.lun, 0);
+        assert_eq!(get_u32_be(&cb.cb[2..6]), 0x1234);
+        assert_eq!(get_u16_be(&cb.cb[7..9]), 2);
+
+        assert_eq!(CommandBlock::parse(&cbw[..CBW_LEN - 1]), None);
+        cbw[0] = b'X';
+        assert_eq!(CommandBlock::parse(&cbw), None);
+
+        let mut csw = [0; CSW_LEN];
+        assert_eq!(
+            encode_status(&mut csw, 0xdeadbeef, 512, STATUS_FAILED),
+            CSW_LEN
+        );
+        assert_eq!(
+            csw,
+            [b'U', b'S', b'B', b'S', 0xef, 0xbe, 0xad, 0xde, 0, 2, 0, 0, 1]
+        );
+    }
+}
@@ -167,6 +167,13 @@ impl<'a, 'b, U: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, U> {
         self.controller
     }
 
+    /// The configuration descriptor and the descriptors that follow it. The
+    /// buffer can be changed in place, e.g. by a class that switches modes,
+    /// before the host reads it again.
+    pub fn other_descriptor_buffer(&self) -> &DescriptorBuffer {
+        &self.other_descriptor_buffer
+    }
+
     #[inline]
     fn descriptor_buf(&'a self) -> &'a [Cell<u8>] {
         &self.descriptor_storage
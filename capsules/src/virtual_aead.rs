@@ -0,0 +1,246 @@
+//! Virtualizes an AEAD implementation (`hil::symmetric_encryption::AEAD`),
+//! such as `capsules::aes_gcm::Aes128Gcm` or
+//! `capsules::chacha20_poly1305::ChaCha20Poly1305Software`, so that several
+//! users can share it.
+//!
+//! Each `VirtualAEAD` keeps its own key and nonce. Requests are queued and
+//! executed one after the other: before running a request, the key and nonce
+//! of the requesting client are loaded into the underlying implementation.
+//! Since the underlying implementation only checks keys and nonces at that
+//! point, a key or nonce of the wrong length is reported as an error in
+//! `crypt_done()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! type GCM = capsules::aes_gcm::Aes128Gcm<'static, sam4l::aes::Aes<'static>>;
+//! let gcm_mux = static_init!(
+//!     capsules::virtual_aead::MuxAEAD<'static, GCM>,
+//!     capsules::virtual_aead::MuxAEAD::new(gcm, dynamic_deferred_caller)
+//! );
+//! gcm.set_client(gcm_mux);
+//! gcm_mux.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(gcm_mux).unwrap(), // Unwrap fail = no deferred call slot available for the AEAD mux
+//! );
+//!
+//! let gcm_client = static_init!(
+//!     capsules::virtual_aead::VirtualAEAD<'static, GCM>,
+//!     capsules::virtual_aead::VirtualAEAD::new(gcm_mux)
+//! );
+//! gcm_client.setup();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::symmetric_encryption::{AEADClient, ChaCha20Poly1305, AEAD, AES128GCM};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// The longest key a `VirtualAEAD` can store.
+const MAX_KEY_LENGTH: usize = 32;
+/// The longest nonce a `VirtualAEAD` can store.
+const MAX_NONCE_LENGTH: usize = 16;
+
+// to cache up the function parameters of the crypt() function
+struct CryptFunctionParameters {
+    buf: &'static mut [u8],
+    a_off: usize,
+    m_off: usize,
+    m_len: usize,
+    encrypting: bool,
+}
+
+pub struct MuxAEAD<'a, A: AEAD<'a>> {
+    aead: &'a A,
+    clients: List<'a, VirtualAEAD<'a, A>>,
+    inflight: OptionalCell<&'a VirtualAEAD<'a, A>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, A: AEAD<'a>> MuxAEAD<'a, A> {
+    pub fn new(aead: &'a A, deferred_caller: &'a DynamicDeferredCall) -> MuxAEAD<'a, A> {
+        MuxAEAD {
+            aead,
+            clients: List::new(),
+            inflight: OptionalCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn do_next_op_async(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let node = match self.clients.iter().find(|node| node.queued_up.is_some()) {
+                Some(node) => node,
+                None => return,
+            };
+            let parameters = match node.queued_up.take() {
+                Some(parameters) => parameters,
+                None => return,
+            };
+
+            self.inflight.set(node);
+            if let Err((ecode, buf)) = node.start(parameters) {
+                // Report the failure and move on to the next request.
+                self.inflight.clear();
+                node.client.map(move |client| {
+                    client.crypt_done(buf, Err(ecode), false);
+                });
+            }
+        }
+    }
+}
+
+impl<'a, A: AEAD<'a>> DynamicDeferredCallClient for MuxAEAD<'a, A> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.do_next_op();
+    }
+}
+
+impl<'a, A: AEAD<'a>> AEADClient for MuxAEAD<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        if let Some(node) = self.inflight.take() {
+            node.client.map(move |client| {
+                client.crypt_done(buf, res, tag_is_valid);
+            });
+        }
+        self.do_next_op();
+    }
+}
+
+pub struct VirtualAEAD<'a, A: AEAD<'a>> {
+    mux: &'a MuxAEAD<'a, A>,
+    next: ListLink<'a, VirtualAEAD<'a, A>>,
+    client: OptionalCell<&'a dyn AEADClient>,
+
+    key: Cell<[u8; MAX_KEY_LENGTH]>,
+    key_len: Cell<usize>,
+    nonce: Cell<[u8; MAX_NONCE_LENGTH]>,
+    nonce_len: Cell<usize>,
+    queued_up: OptionalCell<CryptFunctionParameters>,
+}
+
+impl<'a, A: AEAD<'a>> VirtualAEAD<'a, A> {
+    pub fn new(mux: &'a MuxAEAD<'a, A>) -> VirtualAEAD<'a, A> {
+        VirtualAEAD {
+            mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            key: Cell::new([0; MAX_KEY_LENGTH]),
+            key_len: Cell::new(0),
+            nonce: Cell::new([0; MAX_NONCE_LENGTH]),
+            nonce_len: Cell::new(0),
+            queued_up: OptionalCell::empty(),
+        }
+    }
+
+    /// bind itself to self.mux, should be called after static_init!
+    pub fn setup(&'a self) {
+        self.mux.clients.push_head(self);
+    }
+
+    fn start(
+        &self,
+        parameters: CryptFunctionParameters,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let aead = self.mux.aead;
+        let res = aead
+            .set_key(&self.key.get()[..self.key_len.get()])
+            .and_then(|()| aead.set_nonce(&self.nonce.get()[..self.nonce_len.get()]));
+        if let Err(e) = res {
+            return Err((e, parameters.buf));
+        }
+        aead.crypt(
+            parameters.buf,
+            parameters.a_off,
+            parameters.m_off,
+            parameters.m_len,
+            parameters.encrypting,
+        )
+    }
+}
+
+impl<'a, A: AEAD<'a>> AEAD<'a> for VirtualAEAD<'a, A> {
+    fn set_client(&'a self, client: &'a dyn AEADClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() > MAX_KEY_LENGTH {
+            Err(ErrorCode::INVAL)
+        } else {
+            let mut new_key = [0u8; MAX_KEY_LENGTH];
+            new_key[..key.len()].copy_from_slice(key);
+            self.key.set(new_key);
+            self.key_len.set(key.len());
+            Ok(())
+        }
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        if nonce.len() > MAX_NONCE_LENGTH {
+            Err(ErrorCode::INVAL)
+        } else {
+            let mut new_nonce = [0u8; MAX_NONCE_LENGTH];
+            new_nonce[..nonce.len()].copy_from_slice(nonce);
+            self.nonce.set(new_nonce);
+            self.nonce_len.set(nonce.len());
+            Ok(())
+        }
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.queued_up.is_some()
+            || self
+                .mux
+                .inflight
+                .map_or(false, |node| core::ptr::eq(*node, self))
+        {
+            return Err((ErrorCode::BUSY, buf));
+        }
+
+        self.queued_up.set(CryptFunctionParameters {
+            buf,
+            a_off,
+            m_off,
+            m_len,
+            encrypting,
+        });
+        self.mux.do_next_op_async();
+        Ok(())
+    }
+}
+
+impl<'a, A: AES128GCM<'a>> AES128GCM<'a> for VirtualAEAD<'a, A> {}
+
+impl<'a, A: ChaCha20Poly1305<'a>> ChaCha20Poly1305<'a> for VirtualAEAD<'a, A> {}
+
+// Fit in the linked list
+impl<'a, A: AEAD<'a>> ListNode<'a, VirtualAEAD<'a, A>> for VirtualAEAD<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualAEAD<'a, A>> {
+        &self.next
+    }
+}
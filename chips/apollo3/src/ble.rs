@@ -464,10 +464,30 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Ble<'a> {
         }
     }
 
+    fn transmit_scannable_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        _response_len: usize,
+        channel: RadioChannel,
+    ) {
+        // Scan requests are not answered
+        self.transmit_advertisement(buf, len, channel);
+    }
+
     fn receive_advertisement(&self, _channel: RadioChannel) {
         unimplemented!();
     }
 
+    fn receive_advertisement_active(
+        &self,
+        _channel: RadioChannel,
+        _scanner_address: &[u8; 6],
+    ) -> Result<(), ErrorCode> {
+        // Scan requests are not sent
+        Err(ErrorCode::NOSUPPORT)
+    }
+
     fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
         self.rx_client.set(client);
     }
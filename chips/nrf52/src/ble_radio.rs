@@ -527,15 +527,63 @@ register_bitfields! [u32,
     ]
 ];
 
-static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
-    [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
+// S0 and Length, followed by the longest payload allowed by MAXLEN
+const BLE_PDU_MAX_LEN: usize = 2 + nrf5x::constants::RADIO_PAYLOAD_LENGTH;
+
+static mut PAYLOAD: [u8; BLE_PDU_MAX_LEN] = [0x00; BLE_PDU_MAX_LEN];
+
+// Transmit buffer of the connection exchanges, so that the response can be
+// prepared while the request is being received into `PAYLOAD`.
+static mut CONN_PAYLOAD: [u8; BLE_PDU_MAX_LEN] = [0x00; BLE_PDU_MAX_LEN];
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.1 Inter Frame Space
+const BLE_T_IFS_US: u32 = 150;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3
+const ADV_IND: u8 = 0b0000;
+const SCAN_REQ: u8 = 0b0011;
+const ADV_SCAN_IND: u8 = 0b0110;
+const ADV_HEADER_PDU_TYPE_MASK: u8 = 0x0f;
+const ADV_HEADER_TXADD_OFFSET: u8 = 6;
+const ADV_HEADER_RXADD_OFFSET: u8 = 7;
+const SCAN_REQ_PAYLOAD_LEN: u8 = 12;
+
+/// Step of an exchange started through `BleConnectionDriver`.
+#[derive(Copy, Clone, PartialEq)]
+enum ExchangeState {
+    Idle,
+    /// Transmitting a connectable advertisement, the radio switches to RX
+    /// after it.
+    Advertising,
+    /// Waiting for a packet, the radio switches to TX after it.
+    Listening,
+    /// Transmitting the response.
+    Responding,
+    /// Transmitting a scannable advertisement, the radio switches to RX
+    /// after it.
+    ScannableAdvertising,
+    /// Waiting for a scan request, the radio switches to TX after it.
+    AwaitingScanRequest,
+    /// Transmitting the scan response.
+    ScanResponding,
+    /// Waiting for an advertisement, the radio switches to TX after it.
+    ActiveScanning,
+    /// Transmitting a scan request, the radio switches to RX after it.
+    ScanRequesting,
+    /// Waiting for the scan response.
+    AwaitingScanResponse,
+}
 
 pub struct Radio<'a> {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
+    connection_client: OptionalCell<&'a dyn ble_advertising::ConnectionClient>,
     buffer: TakeCell<'static, [u8]>,
+    exchange: Cell<ExchangeState>,
+    receiving: Cell<bool>,
+    scanner_address: Cell<[u8; 6]>,
 }
 
 impl<'a> Radio<'a> {
@@ -545,7 +593,11 @@ impl<'a> Radio<'a> {
             tx_power: Cell::new(TxPower::ZerodBm),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            connection_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            exchange: Cell::new(ExchangeState::Idle),
+            receiving: Cell::new(false),
+            scanner_address: Cell::new([0; 6]),
         }
     }
 
@@ -599,6 +651,11 @@ impl<'a> Radio<'a> {
     pub fn handle_interrupt(&self) {
         self.disable_all_interrupts();
 
+        if self.exchange.get() != ExchangeState::Idle {
+            self.handle_exchange_interrupt();
+            return;
+        }
+
         if self.registers.event_ready.is_set(Event::READY) {
             self.registers.event_ready.write(Event::READY::CLEAR);
             self.registers.event_end.write(Event::READY::CLEAR);
@@ -636,14 +693,7 @@ impl<'a> Radio<'a> {
                 | nrf5x::constants::RADIO_STATE_RXDISABLE
                 | nrf5x::constants::RADIO_STATE_RX => {
                     self.radio_off();
-                    unsafe {
-                        self.rx_client.map(|client| {
-                            // Length is: S0 (1 Byte) + Length (1 Byte) + S1 (0 Bytes) + Payload
-                            // And because the length field is directly read from the packet
-                            // We need to add 2 to length to get the total length
-                            client.receive_event(&mut PAYLOAD, PAYLOAD[1] + 2, result)
-                        });
-                    }
+                    self.deliver_received(result);
                 }
                 // Radio state - Disabled
                 _ => (),
@@ -652,6 +702,183 @@ impl<'a> Radio<'a> {
         self.enable_interrupts();
     }
 
+    // Connection exchanges chain TX and RX with shortcuts, so that the radio
+    // keeps T_IFS between packets. The shortcuts for the next packet are set
+    // while the current one is on air.
+    fn handle_exchange_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.event_ready.is_set(Event::READY) {
+            regs.event_ready.write(Event::READY::CLEAR);
+            match self.exchange.get() {
+                ExchangeState::Responding | ExchangeState::ScanResponding => regs
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET),
+                ExchangeState::ScanRequesting => regs.shorts.write(
+                    Shortcut::READY_START::SET
+                        + Shortcut::END_DISABLE::SET
+                        + Shortcut::DISABLED_RXEN::SET,
+                ),
+                _ => (),
+            }
+        }
+
+        if regs.event_address.is_set(Event::READY) {
+            regs.event_address.write(Event::READY::CLEAR);
+            match self.exchange.get() {
+                ExchangeState::Listening
+                | ExchangeState::AwaitingScanRequest
+                | ExchangeState::ActiveScanning => {
+                    self.receiving.set(true);
+                    regs.shorts.write(
+                        Shortcut::READY_START::SET
+                            + Shortcut::END_DISABLE::SET
+                            + Shortcut::DISABLED_TXEN::SET,
+                    );
+                    unsafe {
+                        regs.packetptr.set(CONN_PAYLOAD.as_ptr() as u32);
+                    }
+                }
+                // The scan response is received into `PAYLOAD`
+                ExchangeState::ScanRequesting => self.set_dma_ptr(),
+                _ => (),
+            }
+        }
+
+        if regs.event_end.is_set(Event::READY) {
+            regs.event_end.write(Event::READY::CLEAR);
+            match self.exchange.get() {
+                ExchangeState::Advertising => {
+                    // The radio is switching to RX
+                    self.exchange.set(ExchangeState::Listening);
+                    self.set_dma_ptr();
+                }
+                ExchangeState::Listening => {
+                    self.receiving.set(false);
+                    let crc_ok = regs.crcstatus.is_set(Event::READY);
+                    let response = self.connection_client.map_or(None, |client| unsafe {
+                        let len = PAYLOAD[1] as usize + 2;
+                        client.packet_received(&PAYLOAD[..len], crc_ok, &mut CONN_PAYLOAD)
+                    });
+                    if response.is_some() {
+                        self.exchange.set(ExchangeState::Responding);
+                    } else {
+                        // Abort the ramp-up of the transmitter
+                        self.end_exchange();
+                    }
+                }
+                ExchangeState::Responding => self.end_exchange(),
+                ExchangeState::ScannableAdvertising => {
+                    // The radio is switching to RX, and the advertisement
+                    // buffer is no longer needed.
+                    self.exchange.set(ExchangeState::AwaitingScanRequest);
+                    self.set_dma_ptr();
+                    self.tx_client.map(|client| {
+                        self.buffer
+                            .take()
+                            .map(|buf| client.transmit_event(buf, Ok(())))
+                    });
+                }
+                ExchangeState::AwaitingScanRequest => {
+                    self.receiving.set(false);
+                    if regs.crcstatus.is_set(Event::READY) && self.is_scan_request_for_us() {
+                        self.exchange.set(ExchangeState::ScanResponding);
+                    } else {
+                        self.stop_exchange();
+                    }
+                }
+                ExchangeState::ScanResponding => self.stop_exchange(),
+                ExchangeState::ActiveScanning => {
+                    self.receiving.set(false);
+                    let crc_ok = regs.crcstatus.is_set(Event::READY);
+                    if crc_ok && self.prepare_scan_request() {
+                        self.exchange.set(ExchangeState::ScanRequesting);
+                    } else {
+                        self.stop_exchange();
+                    }
+                    self.deliver_received(if crc_ok { Ok(()) } else { Err(ErrorCode::FAIL) });
+                }
+                ExchangeState::ScanRequesting => {
+                    // The radio is switching to RX
+                    self.exchange.set(ExchangeState::AwaitingScanResponse);
+                }
+                ExchangeState::AwaitingScanResponse => {
+                    let crc_ok = regs.crcstatus.is_set(Event::READY);
+                    self.stop_exchange();
+                    self.deliver_received(if crc_ok { Ok(()) } else { Err(ErrorCode::FAIL) });
+                }
+                ExchangeState::Idle => (),
+            }
+        }
+
+        if self.exchange.get() != ExchangeState::Idle {
+            self.enable_exchange_interrupts();
+        }
+    }
+
+    fn end_exchange(&self) {
+        self.stop_exchange();
+        self.connection_client
+            .map(|client| client.exchange_done(Ok(())));
+    }
+
+    // Also aborts the ramp-up of the transmitter after a packet that is not
+    // answered.
+    fn stop_exchange(&self) {
+        self.registers.shorts.set(0);
+        self.registers.task_disable.write(Task::ENABLE::SET);
+        self.exchange.set(ExchangeState::Idle);
+        self.radio_off();
+    }
+
+    // Pass the packet in `PAYLOAD` to the receive client.
+    fn deliver_received(&self, result: Result<(), ErrorCode>) {
+        unsafe {
+            self.rx_client.map(|client| {
+                // Length is: S0 (1 Byte) + Length (1 Byte) + S1 (0 Bytes) + Payload
+                // And because the length field is directly read from the packet
+                // We need to add 2 to length to get the total length
+                client.receive_event(&mut PAYLOAD, PAYLOAD[1].saturating_add(2), result)
+            });
+        }
+    }
+
+    // Whether the packet in `PAYLOAD` is a scan request for the advertiser
+    // of the scan response in `CONN_PAYLOAD`.
+    fn is_scan_request_for_us(&self) -> bool {
+        unsafe {
+            let adv_a_random = (CONN_PAYLOAD[0] >> ADV_HEADER_TXADD_OFFSET) & 1;
+            PAYLOAD[0] & ADV_HEADER_PDU_TYPE_MASK == SCAN_REQ
+                && PAYLOAD[1] == SCAN_REQ_PAYLOAD_LEN
+                && (PAYLOAD[0] >> ADV_HEADER_RXADD_OFFSET) & 1 == adv_a_random
+                && PAYLOAD[8..14] == CONN_PAYLOAD[2..8]
+        }
+    }
+
+    // Write a scan request to `CONN_PAYLOAD` if the advertisement in
+    // `PAYLOAD` is scannable, and return whether it is.
+    fn prepare_scan_request(&self) -> bool {
+        unsafe {
+            let pdu_type = PAYLOAD[0] & ADV_HEADER_PDU_TYPE_MASK;
+            if (pdu_type != ADV_IND && pdu_type != ADV_SCAN_IND) || (PAYLOAD[1] as usize) < 6 {
+                return false;
+            }
+            let adv_a_random = (PAYLOAD[0] >> ADV_HEADER_TXADD_OFFSET) & 1;
+            CONN_PAYLOAD[0] =
+                SCAN_REQ | 1 << ADV_HEADER_TXADD_OFFSET | adv_a_random << ADV_HEADER_RXADD_OFFSET;
+            CONN_PAYLOAD[1] = SCAN_REQ_PAYLOAD_LEN;
+            CONN_PAYLOAD[2..8].copy_from_slice(&self.scanner_address.get());
+            CONN_PAYLOAD[8..14].copy_from_slice(&PAYLOAD[2..8]);
+            true
+        }
+    }
+
+    fn enable_exchange_interrupts(&self) {
+        self.registers
+            .intenset
+            .write(Interrupt::READY::SET + Interrupt::ADDRESS::SET + Interrupt::END::SET);
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.intenset.write(
             Interrupt::READY::SET
@@ -674,9 +901,9 @@ impl<'a> Radio<'a> {
         self.registers.intenclr.set(0xffffffff);
     }
 
-    fn replace_radio_buffer(&self, buf: &'static mut [u8]) -> &'static mut [u8] {
+    fn replace_radio_buffer(&self, buf: &'static mut [u8], len: usize) -> &'static mut [u8] {
         // set payload
-        for (i, c) in buf.as_ref().iter().enumerate() {
+        for (i, c) in buf.as_ref().iter().take(len).enumerate() {
             unsafe {
                 PAYLOAD[i] = *c;
             }
@@ -687,6 +914,9 @@ impl<'a> Radio<'a> {
     fn ble_initialize(&self, channel: RadioChannel) {
         self.radio_on();
 
+        self.registers.shorts.set(0);
+        self.exchange.set(ExchangeState::Idle);
+
         self.ble_set_tx_power();
 
         self.ble_set_channel_rate();
@@ -725,6 +955,15 @@ impl<'a> Radio<'a> {
         self.registers.base0.set(0x89bed600);
     }
 
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
+    // The access address and CRC initialization value of a connection are
+    // chosen by the master in its CONNECT_IND.
+    fn ble_set_connection_access_address(&self, access_address: u32, crc_init: u32) {
+        self.registers.prefix0.set(access_address >> 24);
+        self.registers.base0.set(access_address << 8);
+        self.registers.crcinit.set(crc_init & 0xffffff);
+    }
+
     // Packet configuration
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1 Packet Format
     //
@@ -788,20 +1027,62 @@ impl<'a> Radio<'a> {
 }
 
 impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
-    fn transmit_advertisement(&self, buf: &'static mut [u8], _len: usize, channel: RadioChannel) {
-        let res = self.replace_radio_buffer(buf);
+    fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel) {
+        let res = self.replace_radio_buffer(buf, len);
         self.buffer.replace(res);
         self.ble_initialize(channel);
         self.tx();
         self.enable_interrupts();
     }
 
+    fn transmit_scannable_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        response_len: usize,
+        channel: RadioChannel,
+    ) {
+        if let Some(response) = buf.get(len..len + response_len) {
+            unsafe {
+                let response_len = core::cmp::min(response_len, CONN_PAYLOAD.len());
+                CONN_PAYLOAD[..response_len].copy_from_slice(&response[..response_len]);
+            }
+        }
+        let res = self.replace_radio_buffer(buf, len);
+        self.buffer.replace(res);
+        self.ble_initialize(channel);
+        self.registers.tifs.set(BLE_T_IFS_US);
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_RXEN::SET,
+        );
+        self.exchange.set(ExchangeState::ScannableAdvertising);
+        self.tx();
+        self.enable_exchange_interrupts();
+    }
+
     fn receive_advertisement(&self, channel: RadioChannel) {
         self.ble_initialize(channel);
         self.rx();
         self.enable_interrupts();
     }
 
+    fn receive_advertisement_active(
+        &self,
+        channel: RadioChannel,
+        scanner_address: &[u8; 6],
+    ) -> Result<(), ErrorCode> {
+        self.ble_initialize(channel);
+        self.scanner_address.set(*scanner_address);
+        self.registers.tifs.set(BLE_T_IFS_US);
+        self.registers
+            .shorts
+            .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+        self.exchange.set(ExchangeState::ActiveScanning);
+        self.rx();
+        self.enable_exchange_interrupts();
+        Ok(())
+    }
+
     fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
         self.rx_client.set(client);
     }
@@ -811,6 +1092,55 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     }
 }
 
+impl<'a> ble_advertising::BleConnectionDriver<'a> for Radio<'a> {
+    fn set_connection_client(&self, client: &'a dyn ble_advertising::ConnectionClient) {
+        self.connection_client.set(client);
+    }
+
+    fn advertise_connectable(&self, pdu: &[u8], channel: RadioChannel) {
+        self.ble_initialize(channel);
+        unsafe {
+            let len = core::cmp::min(pdu.len(), CONN_PAYLOAD.len());
+            CONN_PAYLOAD[..len].copy_from_slice(&pdu[..len]);
+            self.registers.packetptr.set(CONN_PAYLOAD.as_ptr() as u32);
+        }
+        self.registers.tifs.set(BLE_T_IFS_US);
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_RXEN::SET,
+        );
+        self.exchange.set(ExchangeState::Advertising);
+        self.tx();
+        self.enable_exchange_interrupts();
+    }
+
+    fn listen_connection(&self, channel: RadioChannel, access_address: u32, crc_init: u32) {
+        self.ble_initialize(channel);
+        self.ble_set_connection_access_address(access_address, crc_init);
+        self.registers.tifs.set(BLE_T_IFS_US);
+        self.registers
+            .shorts
+            .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+        self.exchange.set(ExchangeState::Listening);
+        self.rx();
+        self.enable_exchange_interrupts();
+    }
+
+    fn stop_listening(&self) -> Result<(), ErrorCode> {
+        if self.exchange.get() == ExchangeState::Idle {
+            return Ok(());
+        }
+        if self.exchange.get() != ExchangeState::Listening || self.receiving.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.disable_all_interrupts();
+        self.registers.shorts.set(0);
+        self.registers.task_disable.write(Task::ENABLE::SET);
+        self.exchange.set(ExchangeState::Idle);
+        self.radio_off();
+        Ok(())
+    }
+}
+
 impl ble_advertising::BleConfig for Radio<'_> {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
@@ -43,6 +43,10 @@ pub const PREBUF_LEN_BYTES: usize = 2;
 // to return the frame buffer.
 const MIMIC_PSDU_OFFSET: u32 = 1;
 
+// The LQI reported by the radio ranges from 0 to 63, and is scaled to the
+// 0 to 255 range of IEEE 802.15.4
+const IEEE802154_LQI_FACTOR: u16 = 4;
+
 // IEEEStd 802.15.4-2011 Section 8.1.2.2
 // Frequency is 2405 + 5 * (k - 11) MHz, where k = 11, 12, ... , 26.
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -674,6 +678,9 @@ pub struct Radio<'p> {
     channel: Cell<RadioChannel>,
     transmitting: Cell<bool>,
     timer0: OptionalCell<&'p crate::timer::TimerAlarm<'p>>,
+    rx_rssi: Cell<i8>,
+    rx_lqi: Cell<u8>,
+    single_attempt: Cell<bool>,
 }
 
 impl<'a> AlarmClient for Radio<'a> {
@@ -700,6 +707,9 @@ impl<'p> Radio<'p> {
             channel: Cell::new(RadioChannel::DataChannel26),
             transmitting: Cell::new(false),
             timer0: OptionalCell::empty(),
+            rx_rssi: Cell::new(0),
+            rx_lqi: Cell::new(0),
+            single_attempt: Cell::new(false),
         }
     }
 
@@ -801,7 +811,9 @@ impl<'p> Radio<'p> {
             //in the IEEE 802.15.4 standard (see Figure 69 in
             //section 7.5.1.4 The CSMA-CA algorithm of the
             //standard).
-            if self.cca_count.get() < IEEE802154_MAX_POLLING_ATTEMPTS {
+            if !self.single_attempt.get()
+                && self.cca_count.get() < IEEE802154_MAX_POLLING_ATTEMPTS
+            {
                 self.cca_count.set(self.cca_count.get() + 1);
                 self.cca_be.set(self.cca_be.get() + 1);
                 let backoff_periods = self.random_nonce() & ((1 << self.cca_be.get()) - 1);
@@ -864,6 +876,14 @@ impl<'p> Radio<'p> {
                         // And because the length field is directly read from the packet
                         // We need to add 2 to length to get the total length
 
+                        // The RSSI is sampled at the start of the frame, and the
+                        // radio writes the LQI in place of the first FCS byte
+                        self.rx_rssi
+                            .set(-(self.registers.rssisample.read(RssiSample::RSSISAMPLE) as i8));
+                        let lqi = rbuf[MIMIC_PSDU_OFFSET as usize + 1 + frame_len];
+                        self.rx_lqi
+                            .set(core::cmp::min(lqi as u16 * IEEE802154_LQI_FACTOR, 255) as u8);
+
                         client.receive(rbuf, frame_len, self.registers.crcstatus.get() == 1, result)
                     });
                 }
@@ -926,6 +946,11 @@ impl<'p> Radio<'p> {
         self.set_tx_address();
         self.set_rx_address();
 
+        // Sample the RSSI of every received frame
+        self.registers
+            .shorts
+            .write(Shortcut::ADDRESS_RSSISTART::SET + Shortcut::DISABLED_RSSISTOP::SET);
+
         // First step in transmitting or receiving is entering rx mode
         self.rx();
     }
@@ -1162,3 +1187,21 @@ impl<'p> kernel::hil::radio::RadioData for Radio<'p> {
         Ok(())
     }
 }
+
+impl<'p> kernel::hil::radio::RadioChannelAccess for Radio<'p> {
+    fn set_single_attempt(&self, enable: bool) {
+        self.single_attempt.set(enable);
+    }
+}
+
+impl<'p> kernel::hil::radio::RadioMonitor for Radio<'p> {
+    fn set_promiscuous_mode(&self, _enable: bool) -> Result<(), ErrorCode> {
+        // The radio neither filters frames by address nor sends
+        // acknowledgements, so every frame is always received
+        Ok(())
+    }
+
+    fn rx_signal_quality(&self) -> (i8, u8) {
+        (self.rx_rssi.get(), self.rx_lqi.get())
+    }
+}
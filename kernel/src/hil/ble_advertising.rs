@@ -49,9 +49,52 @@
 
 use crate::ErrorCode;
 
+/// How long the radio may take to answer a scan request, or to receive a scan
+/// response, after a scannable advertisement: from the end of the
+/// advertisement, T_IFS, the longest `SCAN_REQ`, T_IFS and the longest
+/// `SCAN_RSP`, rounded up.
+pub const SCAN_RESPONSE_WINDOW_US: u32 = 1500;
+
 pub trait BleAdvertisementDriver<'a> {
     fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel);
+
+    /// Transmit the scannable advertisement in `buf[..len]`, and answer a
+    /// `SCAN_REQ` for its AdvA with the `SCAN_RSP` in
+    /// `buf[len..len + response_len]`.
+    ///
+    /// The scan request comes T_IFS after the advertisement and must be
+    /// answered T_IFS after it, so the radio does it on its own:
+    /// `TxClient::transmit_event` is called once the advertisement is sent,
+    /// and the radio keeps answering until the next operation, which should
+    /// start `SCAN_RESPONSE_WINDOW_US` later at the earliest.
+    fn transmit_scannable_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        response_len: usize,
+        channel: RadioChannel,
+    );
+
     fn receive_advertisement(&self, channel: RadioChannel);
+
+    /// Like `receive_advertisement`, but a scannable advertisement
+    /// (`ADV_IND` or `ADV_SCAN_IND`) is answered T_IFS later with a `SCAN_REQ`
+    /// from the random address `scanner_address`.
+    ///
+    /// The advertisement is passed to `RxClient::receive_event` as usual, and
+    /// the radio then receives the next packet, normally the `SCAN_RSP`, which
+    /// is passed to `receive_event` too. The next operation should start
+    /// `SCAN_RESPONSE_WINDOW_US` after a scannable advertisement at the
+    /// earliest.
+    ///
+    /// Returns `Err(ErrorCode::NOSUPPORT)`, and receives nothing, if the radio
+    /// cannot send scan requests.
+    fn receive_advertisement_active(
+        &self,
+        channel: RadioChannel,
+        scanner_address: &[u8; 6],
+    ) -> Result<(), ErrorCode>;
+
     fn set_receive_client(&self, client: &'a dyn RxClient);
     fn set_transmit_client(&self, client: &'a dyn TxClient);
 }
@@ -60,6 +103,50 @@ pub trait BleConfig {
     fn set_tx_power(&self, power: u8) -> Result<(), ErrorCode>;
 }
 
+/// Radio primitives for the connection state of a link layer in the
+/// peripheral (slave) role.
+///
+/// A connection is a series of exchanges, in each of which the peer sends a
+/// PDU and the link layer answers it exactly T_IFS (150 µs) later. This is too
+/// soon to go through a deferred call, so the radio asks the client for the
+/// response from the interrupt handler in which the packet was received
+/// (`ConnectionClient::packet_received`), and reports the end of the exchange
+/// with `ConnectionClient::exchange_done`.
+pub trait BleConnectionDriver<'a> {
+    fn set_connection_client(&self, client: &'a dyn ConnectionClient);
+
+    /// Transmit the connectable advertising PDU `pdu` (header included) on
+    /// `channel`, then listen for a request (`SCAN_REQ` or `CONNECT_IND`)
+    /// T_IFS after it, with the advertising access address.
+    fn advertise_connectable(&self, pdu: &[u8], channel: RadioChannel);
+
+    /// Listen for a data channel PDU on `channel`, with the access address and
+    /// CRC initialization value of a connection, and answer it.
+    ///
+    /// The radio listens until a packet is received or `stop_listening` is
+    /// called; the link layer is in charge of the receive window.
+    fn listen_connection(&self, channel: RadioChannel, access_address: u32, crc_init: u32);
+
+    /// Stop listening, e.g. at the end of a receive window. This fails with
+    /// `BUSY` if a packet is being received, in which case the exchange ends
+    /// normally. On success, no `exchange_done` follows.
+    fn stop_listening(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait ConnectionClient {
+    /// A packet was received, with `pdu` the PDU header and payload. The
+    /// client writes the PDU to send back (if any) to `response` and returns
+    /// its length, or `None` to end the exchange without answering.
+    ///
+    /// This is called in the interrupt handler of the radio and must return
+    /// within a few tens of microseconds.
+    fn packet_received(&self, pdu: &[u8], crc_ok: bool, response: &mut [u8]) -> Option<usize>;
+
+    /// The exchange is over: the response was sent or no response was
+    /// needed.
+    fn exchange_done(&self, result: Result<(), ErrorCode>);
+}
+
 pub trait RxClient {
     fn receive_event(&self, buf: &'static mut [u8], len: u8, result: Result<(), ErrorCode>);
 }
@@ -114,6 +201,50 @@ pub enum RadioChannel {
 }
 
 impl RadioChannel {
+    /// The data channel with index `index` (0 to 36).
+    pub fn data_channel(index: u8) -> Option<RadioChannel> {
+        const DATA_CHANNELS: [RadioChannel; 37] = [
+            RadioChannel::DataChannel0,
+            RadioChannel::DataChannel1,
+            RadioChannel::DataChannel2,
+            RadioChannel::DataChannel3,
+            RadioChannel::DataChannel4,
+            RadioChannel::DataChannel5,
+            RadioChannel::DataChannel6,
+            RadioChannel::DataChannel7,
+            RadioChannel::DataChannel8,
+            RadioChannel::DataChannel9,
+            RadioChannel::DataChannel10,
+            RadioChannel::DataChannel11,
+            RadioChannel::DataChannel12,
+            RadioChannel::DataChannel13,
+            RadioChannel::DataChannel14,
+            RadioChannel::DataChannel15,
+            RadioChannel::DataChannel16,
+            RadioChannel::DataChannel17,
+            RadioChannel::DataChannel18,
+            RadioChannel::DataChannel19,
+            RadioChannel::DataChannel20,
+            RadioChannel::DataChannel21,
+            RadioChannel::DataChannel22,
+            RadioChannel::DataChannel23,
+            RadioChannel::DataChannel24,
+            RadioChannel::DataChannel25,
+            RadioChannel::DataChannel26,
+            RadioChannel::DataChannel27,
+            RadioChannel::DataChannel28,
+            RadioChannel::DataChannel29,
+            RadioChannel::DataChannel30,
+            RadioChannel::DataChannel31,
+            RadioChannel::DataChannel32,
+            RadioChannel::DataChannel33,
+            RadioChannel::DataChannel34,
+            RadioChannel::DataChannel35,
+            RadioChannel::DataChannel36,
+        ];
+        DATA_CHANNELS.get(index as usize).copied()
+    }
+
     pub fn get_channel_index(&self) -> u32 {
         match *self {
             RadioChannel::DataChannel0 => 0,
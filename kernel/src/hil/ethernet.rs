@@ -0,0 +1,46 @@
+//! Interface for Ethernet MAC adapters.
+//!
+//! An `EthernetAdapter` sends and receives whole Ethernet II frames, from the
+//! destination MAC address up to the end of the payload. The preamble, start
+//! frame delimiter and frame check sequence are handled by the adapter.
+//! Filtering of received frames by destination address is left to the
+//! client.
+
+use crate::ErrorCode;
+
+/// The length of the Ethernet header: destination and source MAC addresses
+/// and EtherType.
+pub const HEADER_LEN: usize = 14;
+
+/// The maximum length of an Ethernet frame carrying a payload of 1500 bytes
+/// and an 802.1Q tag, excluding the frame check sequence.
+pub const MAX_FRAME_LEN: usize = 1518;
+
+pub trait EthernetAdapter<'a> {
+    fn set_client(&self, client: &'a dyn EthernetAdapterClient);
+
+    /// Sends the first `len` bytes of `frame`. On success, `tx_done` is
+    /// called once the frame has been sent. Only one frame can be sent at a
+    /// time.
+    ///
+    /// Return values:
+    /// - `Ok(())`: the frame is being sent.
+    /// - `BUSY`: another frame is being sent.
+    /// - `SIZE`: `len` is longer than `frame`, or than the frames the
+    ///   adapter can send.
+    /// - `OFF`: the adapter is not initialized.
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+pub trait EthernetAdapterClient {
+    /// A frame passed to `transmit` has been sent, or failed to be sent.
+    fn tx_done(&self, result: Result<(), ErrorCode>, frame: &'static mut [u8]);
+
+    /// A frame has been received. `frame` is only valid for the duration of
+    /// the call.
+    fn rx_frame(&self, frame: &[u8]);
+}
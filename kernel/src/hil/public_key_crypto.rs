@@ -0,0 +1,103 @@
+//! Interface for public key cryptography.
+//!
+//! This HIL provides asymmetric signature verification. Public keys are first
+//! imported into the implementation with `PublicKeyManager::import_key()`,
+//! which returns a `KeyHandle`. Signatures are then verified against the key
+//! a handle refers to with `SignatureVerify::verify()`.
+//!
+//! The supported algorithms and the encodings of keys, messages and
+//! signatures are described by `SignatureAlgorithm`.
+
+use crate::utilities::leasable_buffer::LeasableBuffer;
+use crate::ErrorCode;
+
+/// Signature algorithms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    /// ECDSA over the NIST P-256 curve.
+    ///
+    /// - Keys are uncompressed SEC1 points, either 65 bytes (`0x04 || X ||
+    ///   Y`) or 64 bytes (`X || Y`), with big-endian coordinates.
+    /// - The message is the hash of the signed data, usually its 32 byte
+    ///   SHA-256 digest. Hashes longer than 32 bytes are truncated as
+    ///   described in FIPS 186-4.
+    /// - Signatures are 64 bytes, `r || s`, both big-endian.
+    EcdsaP256,
+    /// Ed25519 as described in RFC 8032.
+    ///
+    /// - Keys are 32 byte encoded points.
+    /// - The message is the signed data itself.
+    /// - Signatures are 64 bytes, `R || S`.
+    Ed25519,
+}
+
+impl SignatureAlgorithm {
+    /// Length of a signature in bytes.
+    pub fn signature_len(&self) -> usize {
+        match self {
+            SignatureAlgorithm::EcdsaP256 => 64,
+            SignatureAlgorithm::Ed25519 => 64,
+        }
+    }
+}
+
+/// Refers to a public key imported into a `PublicKeyManager`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyHandle(pub usize);
+
+/// Stores public keys and hands out handles to them.
+pub trait PublicKeyManager {
+    /// Import a public key for `algorithm`. The key is checked and copied,
+    /// so `key` can be reused once this returns.
+    ///
+    /// Returns the handle of the key on success, `INVAL` if the key is not
+    /// valid for the algorithm, `NOSUPPORT` if the algorithm isn't supported
+    /// and `NOMEM` if there is no room for another key.
+    fn import_key(&self, algorithm: SignatureAlgorithm, key: &[u8])
+        -> Result<KeyHandle, ErrorCode>;
+
+    /// Remove a key. The handle must not be used after this. Returns `INVAL`
+    /// if the handle doesn't refer to a key and `BUSY` if the key is in use
+    /// by an ongoing verification.
+    fn remove_key(&self, handle: KeyHandle) -> Result<(), ErrorCode>;
+
+    /// Return the algorithm of the key `handle` refers to.
+    fn key_algorithm(&self, handle: KeyHandle) -> Result<SignatureAlgorithm, ErrorCode>;
+}
+
+/// Implement this trait and use `set_verify_client()` in order to receive
+/// callbacks.
+pub trait ClientVerify {
+    /// Called when a verification is complete.
+    ///
+    /// `result` is `Ok(true)` if the signature is valid, `Ok(false)` if it
+    /// isn't and an `ErrorCode` if the verification could not be done. The
+    /// buffers passed to `verify()` are returned.
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    );
+}
+
+/// Verifies signatures.
+pub trait SignatureVerify<'a>: PublicKeyManager {
+    /// Set the client instance which will receive the `verification_done()`
+    /// callback.
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify);
+
+    /// Verify that `signature` is a valid signature of `message` by the key
+    /// `key` refers to. The active part of `message` is used.
+    ///
+    /// On error the buffers are returned, otherwise they are returned in the
+    /// `verification_done()` callback. Returns `BUSY` if a verification is
+    /// already in progress, `INVAL` if `key` isn't a valid handle and `SIZE`
+    /// if the signature has the wrong length for the algorithm of the key.
+    fn verify(
+        &self,
+        key: KeyHandle,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8], &'static mut [u8])>;
+}
@@ -10,6 +10,11 @@
 use crate::ErrorCode;
 
 pub trait TxClient {
+    /// Returns the frame buffer when a transmission completes. `acked` is
+    /// true if the frame requested an acknowledgement and the radio received
+    /// it. `result` is `Err(ErrorCode::BUSY)` if the frame was not sent
+    /// because the channel was busy (a channel access failure), and another
+    /// error if the transmission failed for any other reason.
     fn send_done(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>);
 }
 
@@ -103,6 +108,27 @@ pub trait RadioConfig {
     fn set_channel(&self, chan: u8) -> Result<(), ErrorCode>;
 }
 
+/// Optional features for monitoring the channel, e.g. by a packet sniffer.
+pub trait RadioMonitor {
+    /// Enables or disables promiscuous mode, which takes effect after
+    /// `config_commit`. In promiscuous mode, frames are received regardless
+    /// of their destination address and PAN ID, and are not acknowledged.
+    fn set_promiscuous_mode(&self, enable: bool) -> Result<(), ErrorCode>;
+
+    /// Returns the RSSI (in dBm) and the link quality indicator (0 to 255)
+    /// of the frame being received. Only valid during `RxClient::receive`.
+    fn rx_signal_quality(&self) -> (i8, u8);
+}
+
+/// Optional control over channel access, for MAC layers that implement
+/// CSMA-CA and retransmissions themselves.
+pub trait RadioChannelAccess {
+    /// When enabled, `transmit` performs a single clear channel assessment
+    /// and never retransmits the frame. If the channel is busy, the
+    /// transmission completes with `Err(ErrorCode::BUSY)`.
+    fn set_single_attempt(&self, enable: bool);
+}
+
 pub trait RadioData {
     fn set_transmit_client(&self, client: &'static dyn TxClient);
     fn set_receive_client(&self, client: &'static dyn RxClient, receive_buffer: &'static mut [u8]);
@@ -139,3 +139,67 @@ pub trait AES128CCM<'a> {
         encrypting: bool,
     ) -> Result<(), (ErrorCode, &'static mut [u8])>;
 }
+
+pub const GCM_NONCE_LENGTH: usize = 12;
+pub const CHACHA20_POLY1305_KEY_SIZE: usize = 32;
+pub const CHACHA20_POLY1305_NONCE_LENGTH: usize = 12;
+
+/// The length of the authentication tag appended by the AEAD algorithms
+/// below.
+pub const AEAD_TAG_LENGTH: usize = 16;
+
+pub trait AEADClient {
+    /// `res` is Ok(()) if the encryption/decryption process succeeded. This
+    /// does not mean that the message has been verified in the case of
+    /// decryption.
+    /// If we are encrypting: `tag_is_valid` is `true` iff `res` is Ok(()).
+    /// If we are decrypting: `tag_is_valid` is `true` iff `res` is Ok(()) and the
+    /// message authentication tag is valid.
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool);
+}
+
+/// Authenticated encryption with associated data.
+///
+/// The key and nonce lengths depend on the algorithm, see the traits
+/// `AES128GCM` and `ChaCha20Poly1305`. The authentication tag is always
+/// `AEAD_TAG_LENGTH` bytes long.
+pub trait AEAD<'a> {
+    /// Set the client instance which will receive `crypt_done()` callbacks
+    fn set_client(&'a self, client: &'a dyn AEADClient);
+
+    /// Set the key to be used for encryption.
+    /// Returns `INVAL` if the key has the wrong length for the algorithm.
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Set the nonce to be used for encryption.
+    /// Returns `INVAL` if the nonce has the wrong length for the algorithm.
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Try to begin the encryption/decryption process.
+    ///
+    /// The associated data is `buf[a_off..m_off]` and the message is
+    /// `buf[m_off..m_off + m_len]`, which is encrypted or decrypted in place.
+    /// The tag follows the message: when encrypting it is written to
+    /// `buf[m_off + m_len..m_off + m_len + AEAD_TAG_LENGTH]`, when
+    /// decrypting it is read from there and checked.
+    ///
+    /// Returns `INVAL` if the offsets don't fit in `buf` and `BUSY` if an
+    /// operation is already in progress.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+/// AES-128 in Galois/Counter Mode (NIST SP 800-38D). Keys are
+/// `AES128_KEY_SIZE` bytes and nonces are `GCM_NONCE_LENGTH` bytes.
+pub trait AES128GCM<'a>: AEAD<'a> {}
+
+/// ChaCha20-Poly1305 as described in RFC 8439. Keys are
+/// `CHACHA20_POLY1305_KEY_SIZE` bytes and nonces are
+/// `CHACHA20_POLY1305_NONCE_LENGTH` bytes.
+pub trait ChaCha20Poly1305<'a>: AEAD<'a> {}
@@ -325,3 +325,41 @@ pub trait ReceiveAdvanced<'a>: Receive<'a> {
         interbyte_timeout: u8,
     ) -> Result<(), (ErrorCode, &'static mut [u8])>;
 }
+
+/// The modem control lines of a serial port that are driven by the other end
+/// of the line.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ControlLineState {
+    /// Data Terminal Ready: the other end has opened the port.
+    pub dtr: bool,
+    /// Request To Send.
+    pub rts: bool,
+}
+
+/// Trait for serial ports whose line parameters and control lines are set by
+/// the other end, such as a USB CDC-ACM port, whose host picks the baud rate
+/// and raises DTR when an application opens the port.
+///
+/// The parameters do not change how the data is transferred, but clients can
+/// act on them, e.g. enter the bootloader when the host opens the port at
+/// 1200 baud and closes it again.
+pub trait ControlLines<'a> {
+    /// Set the client, which is called when the other end changes the line
+    /// parameters or the control lines.
+    fn set_control_lines_client(&self, client: &'a dyn ControlLinesClient);
+
+    /// Returns the line parameters last set by the other end.
+    fn line_parameters(&self) -> Parameters;
+
+    /// Returns the state of the control lines last set by the other end.
+    fn control_line_state(&self) -> ControlLineState;
+}
+
+pub trait ControlLinesClient {
+    /// The other end set the line parameters, e.g. the baud rate. It may set
+    /// the same parameters again.
+    fn line_parameters_changed(&self, parameters: Parameters);
+
+    /// The other end set the control lines.
+    fn control_lines_changed(&self, state: ControlLineState);
+}
@@ -166,7 +166,7 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     flash: &'static [u8],
 
     /// Collection of pointers to the TBF header in flash.
-    header: tock_tbf::types::TbfHeader,
+    header: tock_tbf::types::TbfHeader<'static>,
 
     /// State saved on behalf of the process each time the app switches to the
     /// kernel.
@@ -29,7 +29,7 @@ macro_rules! align4 {
 /// we can skip over it and check for the next app.
 /// - Err(InitialTbfParseError::InvalidHeader(app_length))
 pub fn parse_tbf_header_lengths(
-    app: &'static [u8; 8],
+    app: &[u8; 8],
 ) -> Result<(u16, u16, u32), types::InitialTbfParseError> {
     // Version is the first 16 bits of the app TBF contents. We need this to
     // correctly parse the other lengths.
@@ -73,10 +73,10 @@ pub fn parse_tbf_header_lengths(
 /// The `header` must be a slice that only contains the TBF header. The caller
 /// should use the `parse_tbf_header_lengths()` function to determine this
 /// length to create the correct sized slice.
-pub fn parse_tbf_header(
-    header: &'static [u8],
+pub fn parse_tbf_header<'a>(
+    header: &'a [u8],
     version: u16,
-) -> Result<types::TbfHeader, types::TbfParseError> {
+) -> Result<types::TbfHeader<'a>, types::TbfParseError> {
     match version {
         2 => {
             // Get the required base. This will succeed because we parsed the
@@ -0,0 +1,242 @@
+#!/usr/bin/env python3
+
+# Exposes the serial IPv6 tunnel of a Tock board as a TUN device.
+#
+# Usage: sudo serial-tun.py [options] SERIAL_PORT
+#
+# Author: Tock Project Developers
+
+'''
+Bridges IPv6 packets between a Tock board running a `SerialTunnel` network
+interface (capsules/src/net/tunnel) and a Linux TUN device, so that the board
+can be reached from the host with the usual networking tools (ping, nc, ...).
+
+Packets are framed on the serial line with SLIP (RFC 1055) or HDLC-like
+framing with a 16 bit FCS (RFC 1662). Both ends must use the same framing.
+
+Creating a TUN device requires root (or CAP_NET_ADMIN).
+
+Usage: serial-tun.py [options] SERIAL_PORT
+Options:
+  -b, --baud=RATE       Baud rate of the serial port. Default: 115200
+  -f, --framing=NAME    slip or hdlc. Default: slip
+  -i, --interface=NAME  Name of the TUN device. Default: tock0
+  -a, --address=ADDR    IPv6 address and prefix length to assign to the TUN
+                        device, e.g. fd00::1/64. By default, the device is
+                        only brought up.
+  -v, --verbose         Print every packet.
+
+Example:
+  sudo tools/serial-tun.py -a fd00::1/64 /dev/ttyACM0
+  ping -6 fd00::2
+'''
+
+import fcntl
+import getopt
+import os
+import select
+import struct
+import subprocess
+import sys
+import termios
+import tty
+
+TUNSETIFF = 0x400454ca
+IFF_TUN = 0x0001
+IFF_NO_PI = 0x1000
+
+MTU = 1280
+
+SLIP_END = 0xc0
+SLIP_ESC = 0xdb
+SLIP_ESC_END = 0xdc
+SLIP_ESC_ESC = 0xdd
+
+HDLC_FLAG = 0x7e
+HDLC_ESC = 0x7d
+HDLC_XOR = 0x20
+HDLC_ESCAPED = (HDLC_FLAG, HDLC_ESC, 0x11, 0x13)
+FCS_INIT = 0xffff
+FCS_GOOD = 0xf0b8
+
+BAUD_RATES = {
+    9600: termios.B9600,
+    19200: termios.B19200,
+    38400: termios.B38400,
+    57600: termios.B57600,
+    115200: termios.B115200,
+    230400: termios.B230400,
+    460800: termios.B460800,
+    921600: termios.B921600,
+    1000000: termios.B1000000,
+}
+
+
+def fcs16(data, fcs=FCS_INIT):
+    '''CRC-16/X.25 frame check sequence, as used by HDLC.'''
+    for byte in data:
+        fcs ^= byte
+        for _ in range(8):
+            fcs = (fcs >> 1) ^ 0x8408 if fcs & 1 else fcs >> 1
+    return fcs
+
+
+def encode(framing, packet):
+    '''Returns the frame of an IPv6 packet.'''
+    out = bytearray()
+    if framing == 'slip':
+        out.append(SLIP_END)
+        for byte in packet:
+            if byte == SLIP_END:
+                out += bytes([SLIP_ESC, SLIP_ESC_END])
+            elif byte == SLIP_ESC:
+                out += bytes([SLIP_ESC, SLIP_ESC_ESC])
+            else:
+                out.append(byte)
+        out.append(SLIP_END)
+    else:
+        fcs = fcs16(packet) ^ 0xffff
+        out.append(HDLC_FLAG)
+        for byte in bytes(packet) + struct.pack('<H', fcs):
+            if byte in HDLC_ESCAPED:
+                out += bytes([HDLC_ESC, byte ^ HDLC_XOR])
+            else:
+                out.append(byte)
+        out.append(HDLC_FLAG)
+    return bytes(out)
+
+
+class Decoder:
+    '''Reassembles packets from bytes received on the serial line.'''
+
+    def __init__(self, framing):
+        self.framing = framing
+        self.delimiter = SLIP_END if framing == 'slip' else HDLC_FLAG
+        self.esc = SLIP_ESC if framing == 'slip' else HDLC_ESC
+        self.reset()
+
+    def reset(self):
+        self.frame = bytearray()
+        self.escaped = False
+        self.discard = False
+
+    def end(self):
+        frame, valid = bytes(self.frame), not (self.discard or self.escaped)
+        self.reset()
+        if not valid:
+            return None
+        if self.framing == 'slip':
+            return frame or None
+        if len(frame) > 2 and fcs16(frame) == FCS_GOOD:
+            return frame[:-2]
+        return None
+
+    def push(self, data):
+        '''Processes received bytes, and returns the packets they complete.'''
+        packets = []
+        for byte in data:
+            if byte == self.delimiter:
+                packet = self.end()
+                if packet is not None:
+                    packets.append(packet)
+            elif self.discard:
+                continue
+            elif self.escaped:
+                self.escaped = False
+                if self.framing == 'hdlc':
+                    self.frame.append(byte ^ HDLC_XOR)
+                elif byte == SLIP_ESC_END:
+                    self.frame.append(SLIP_END)
+                elif byte == SLIP_ESC_ESC:
+                    self.frame.append(SLIP_ESC)
+                else:
+                    self.discard = True
+            elif byte == self.esc:
+                self.escaped = True
+            else:
+                self.frame.append(byte)
+            if len(self.frame) > MTU + 2:
+                self.discard = True
+        return packets
+
+
+def open_serial(port, baud):
+    fd = os.open(port, os.O_RDWR | os.O_NOCTTY)
+    tty.setraw(fd)
+    attrs = termios.tcgetattr(fd)
+    attrs[4] = attrs[5] = BAUD_RATES[baud]
+    termios.tcsetattr(fd, termios.TCSANOW, attrs)
+    termios.tcflush(fd, termios.TCIOFLUSH)
+    return fd
+
+
+def open_tun(name, address):
+    fd = os.open('/dev/net/tun', os.O_RDWR)
+    ifr = struct.pack('16sH', name.encode(), IFF_TUN | IFF_NO_PI)
+    fcntl.ioctl(fd, TUNSETIFF, ifr)
+    subprocess.check_call(['ip', 'link', 'set', 'dev', name, 'mtu', str(MTU), 'up'])
+    if address:
+        subprocess.check_call(['ip', '-6', 'addr', 'add', address, 'dev', name])
+    return fd
+
+
+def usage(message=None):
+    if message:
+        print(message, file=sys.stderr)
+    print(__doc__, file=sys.stderr)
+    sys.exit(1)
+
+
+def main():
+    try:
+        opts, args = getopt.getopt(sys.argv[1:], 'b:f:i:a:vh',
+                                   ['baud=', 'framing=', 'interface=', 'address=',
+                                    'verbose', 'help'])
+    except getopt.GetoptError as err:
+        usage(str(err))
+
+    baud, framing, name, address, verbose = 115200, 'slip', 'tock0', None, False
+    for opt, val in opts:
+        if opt in ('-b', '--baud'):
+            baud = int(val)
+            if baud not in BAUD_RATES:
+                usage('Unsupported baud rate: {}'.format(baud))
+        elif opt in ('-f', '--framing'):
+            framing = val.lower()
+            if framing not in ('slip', 'hdlc'):
+                usage('Unknown framing: {}'.format(val))
+        elif opt in ('-i', '--interface'):
+            name = val
+        elif opt in ('-a', '--address'):
+            address = val
+        elif opt in ('-v', '--verbose'):
+            verbose = True
+        else:
+            usage()
+    if len(args) != 1:
+        usage()
+
+    serial = open_serial(args[0], baud)
+    tun = open_tun(name, address)
+    decoder = Decoder(framing)
+    print('Bridging {} ({}, {} baud) to {}'.format(args[0], framing, baud, name))
+
+    while True:
+        readable, _, _ = select.select([serial, tun], [], [])
+        if tun in readable:
+            packet = os.read(tun, MTU)
+            if verbose:
+                print('host -> board: {} bytes'.format(len(packet)))
+            os.write(serial, encode(framing, packet))
+        if serial in readable:
+            for packet in decoder.push(os.read(serial, 4096)):
+                if verbose:
+                    print('board -> host: {} bytes'.format(len(packet)))
+                os.write(tun, packet)
+
+
+if __name__ == '__main__':
+    try:
+        main()
+    except KeyboardInterrupt:
+        pass
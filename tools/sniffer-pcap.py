@@ -0,0 +1,179 @@
+#!/usr/bin/env python3
+
+# Saves the capture of a Tock 802.15.4 sniffer as a PCAP file.
+#
+# Usage: sniffer-pcap.py [options] SERIAL_PORT
+#
+# Author: Tock Project Developers
+
+'''
+Reads the PCAP stream of a Tock board running the 802.15.4 `Sniffer` capsule
+(capsules/src/ieee802154/sniffer.rs) from a serial port, and writes it to a
+file or to stdout, e.g. to watch the capture live in Wireshark.
+
+The stream is only written once its PCAP header is found, so the board may be
+started or reset after this tool. Records that do not look valid (e.g. because
+of bytes lost on the serial line) are skipped until the stream resynchronizes.
+
+Usage: sniffer-pcap.py [options] SERIAL_PORT
+Options:
+  -b, --baud=RATE      Baud rate of the serial port. Default: 115200
+  -o, --output=FILE    File to write the capture to, or - for stdout.
+                       Default: -
+  -w, --wall-clock     Rebase the timestamps of the board, which start at
+                       zero, on the time of the host.
+  -v, --verbose        Print a line for every frame to stderr.
+
+Examples:
+  tools/sniffer-pcap.py -o capture.pcap /dev/ttyACM1
+  tools/sniffer-pcap.py -w /dev/ttyACM1 | wireshark -k -i -
+'''
+
+import getopt
+import os
+import struct
+import sys
+import termios
+import time
+import tty
+
+PCAP_HEADER = struct.pack('<IHHiIII', 0xa1b2c3d4, 2, 4, 0, 0, 256, 283)
+RECORD_HEADER_LEN = 16
+TAP_HEADER_LEN = 36
+MAX_FRAME_LEN = 127
+
+BAUD_RATES = {
+    9600: termios.B9600,
+    19200: termios.B19200,
+    38400: termios.B38400,
+    57600: termios.B57600,
+    115200: termios.B115200,
+    230400: termios.B230400,
+    460800: termios.B460800,
+    921600: termios.B921600,
+    1000000: termios.B1000000,
+}
+
+
+def open_serial(port, baud):
+    fd = os.open(port, os.O_RDWR | os.O_NOCTTY)
+    tty.setraw(fd)
+    attrs = termios.tcgetattr(fd)
+    attrs[4] = attrs[5] = BAUD_RATES[baud]
+    termios.tcsetattr(fd, termios.TCSANOW, attrs)
+    termios.tcflush(fd, termios.TCIOFLUSH)
+    return fd
+
+
+def valid_record(data):
+    '''Checks the headers of the record at the start of data.'''
+    _, usec, incl_len, orig_len = struct.unpack_from('<IIII', data)
+    version, _, tap_len = struct.unpack_from('<BBH', data, RECORD_HEADER_LEN)
+    return (usec < 1000000 and incl_len == orig_len and version == 0
+            and tap_len == TAP_HEADER_LEN
+            and TAP_HEADER_LEN <= incl_len <= TAP_HEADER_LEN + MAX_FRAME_LEN)
+
+
+class Stream:
+    '''Extracts PCAP records from the bytes received on the serial line.'''
+
+    def __init__(self):
+        self.data = bytearray()
+        self.synced = False
+
+    def push(self, data):
+        '''Processes received bytes, and returns the complete records.'''
+        self.data += data
+        records = []
+        while True:
+            if not self.synced:
+                start = self.data.find(PCAP_HEADER)
+                if start < 0:
+                    # Keep a possible partial header
+                    del self.data[:max(0, len(self.data) - len(PCAP_HEADER))]
+                    return records
+                del self.data[:start + len(PCAP_HEADER)]
+                self.synced = True
+            if len(self.data) < RECORD_HEADER_LEN + 4:
+                return records
+            if PCAP_HEADER.startswith(self.data[:len(PCAP_HEADER)]):
+                if len(self.data) < len(PCAP_HEADER):
+                    return records
+                # The board restarted the capture
+                self.synced = False
+                continue
+            if not valid_record(self.data):
+                # Skip a byte and look for the next record
+                del self.data[0]
+                continue
+            length = RECORD_HEADER_LEN + struct.unpack_from('<I', self.data, 8)[0]
+            if len(self.data) < length:
+                return records
+            records.append(bytes(self.data[:length]))
+            del self.data[:length]
+
+
+def usage(message=None):
+    if message:
+        print(message, file=sys.stderr)
+    print(__doc__, file=sys.stderr)
+    sys.exit(1)
+
+
+def main():
+    try:
+        opts, args = getopt.getopt(sys.argv[1:], 'b:o:wvh',
+                                   ['baud=', 'output=', 'wall-clock', 'verbose', 'help'])
+    except getopt.GetoptError as err:
+        usage(str(err))
+
+    baud, output, wall_clock, verbose = 115200, '-', False, False
+    for opt, val in opts:
+        if opt in ('-b', '--baud'):
+            baud = int(val)
+            if baud not in BAUD_RATES:
+                usage('Unsupported baud rate: {}'.format(baud))
+        elif opt in ('-o', '--output'):
+            output = val
+        elif opt in ('-w', '--wall-clock'):
+            wall_clock = True
+        elif opt in ('-v', '--verbose'):
+            verbose = True
+        else:
+            usage()
+    if len(args) != 1:
+        usage()
+
+    serial = open_serial(args[0], baud)
+    out = sys.stdout.buffer if output == '-' else open(output, 'wb')
+    out.write(PCAP_HEADER)
+    out.flush()
+    stream = Stream()
+    start_us = int(time.time() * 1000000) if wall_clock else 0
+    count = 0
+    print('Capturing from {} ({} baud)'.format(args[0], baud), file=sys.stderr)
+
+    while True:
+        for record in stream.push(os.read(serial, 4096)):
+            sec, usec, incl_len, _ = struct.unpack_from('<IIII', record)
+            if wall_clock:
+                timestamp = start_us + sec * 1000000 + usec
+                record = struct.pack('<II', timestamp // 1000000,
+                                     timestamp % 1000000) + record[8:]
+            if verbose:
+                rssi, = struct.unpack_from('<f', record, RECORD_HEADER_LEN + 16)
+                channel, = struct.unpack_from('<H', record, RECORD_HEADER_LEN + 24)
+                lqi = record[RECORD_HEADER_LEN + 32]
+                count += 1
+                print('{:5} {}.{:06} ch {} len {} rssi {:.0f} lqi {}'.format(
+                    count, sec, usec, channel, incl_len - TAP_HEADER_LEN, rssi, lqi),
+                    file=sys.stderr)
+            out.write(record)
+            out.flush()
+
+
+if __name__ == '__main__':
+    try:
+        main()
+    except (KeyboardInterrupt, BrokenPipeError):
+        pass